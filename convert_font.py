@@ -0,0 +1,155 @@
+"""
+TrueType to PSF font converter for HyzeOS
+
+Rasterizes the CP437 character set into an 8x16 PSF1 font using cairo,
+so no Python packages beyond the standard library are needed.
+"""
+
+import argparse
+import ctypes
+import ctypes.util
+import unicodedata
+
+WIDTH = 8
+HEIGHT = 16
+
+PSF1_MAGIC = b"\x36\x04"
+
+# CP437 code points 0x00-0xFF as Unicode
+CP437 = (
+    "\u0000☺☻♥♦♣♠•◘○◙♂♀♪♫☼"
+    "►◄↕‼¶§▬↨↑↓→←∟↔▲▼"
+    + "".join(chr(c) for c in range(0x20, 0x7F)) + "⌂"
+    "ÇüéâäàåçêëèïîìÄÅ"
+    "ÉæÆôöòûùÿÖÜ¢£¥₧ƒ"
+    "áíóúñÑªº¿⌐¬½¼¡«»"
+    "░▒▓│┤╡╢╖╕╣║╗╝╜╛┐"
+    "└┴┬├─┼╞╟╚╔╩╦╠═╬╧"
+    "╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀"
+    "αßΓπΣσµτΦΘΩδ∞φε∩"
+    "≡±≥≤⌠⌡÷≈°∙·√ⁿ²■ "
+)
+
+CAIRO_FORMAT_A8 = 2
+
+
+def block_glyph(ch):
+    """Box/shade characters must touch the cell edges to tile, so draw them directly"""
+    full = [0xFF] * HEIGHT
+    shades = {"░": (0x88, 0x22), "▒": (0xAA, 0x55), "▓": (0xEE, 0xBB)}
+    if ch in shades:
+        a, b = shades[ch]
+        return [a if y % 2 == 0 else b for y in range(HEIGHT)]
+    if ch == "█":
+        return full
+    if ch == "▄":
+        return [0] * (HEIGHT // 2) + [0xFF] * (HEIGHT // 2)
+    if ch == "▀":
+        return [0xFF] * (HEIGHT // 2) + [0] * (HEIGHT // 2)
+    if ch == "▌":
+        return [0xF0] * HEIGHT
+    if ch == "▐":
+        return [0x0F] * HEIGHT
+    if 0x2500 <= ord(ch) <= 0x257F:
+        return box_glyph(ch)
+    return None
+
+
+def box_glyph(ch):
+    """Line-drawing characters built from their arm weights (1 single, 2 double)"""
+    arms = {"up": 0, "down": 0, "left": 0, "right": 0}
+    weight = 1
+    for word in unicodedata.name(ch).replace("BOX DRAWINGS ", "").split():
+        if word in ("LIGHT", "SINGLE", "HEAVY"):
+            weight = 1
+        elif word == "DOUBLE":
+            weight = 2
+        elif word == "HORIZONTAL":
+            arms["left"] = arms["right"] = weight
+        elif word == "VERTICAL":
+            arms["up"] = arms["down"] = weight
+        elif word.lower() in arms:
+            arms[word.lower()] = weight
+
+    # Strokes: column masks for vertical arms, row offsets for horizontal arms
+    columns = {1: 0x18, 2: 0x66}
+    row_offsets = {1: (0,), 2: (-1, 1)}
+    mid = HEIGHT // 2 - 1
+
+    rows = [0] * HEIGHT
+    for arm, bits in (("left", 0xF8), ("right", 0x1F)):
+        for dy in row_offsets.get(arms[arm], ()):
+            rows[mid + dy] |= bits
+    for arm, ys in (("up", range(0, mid + 2)), ("down", range(mid - 1, HEIGHT))):
+        if arms[arm]:
+            for y in ys:
+                rows[y] |= columns[arms[arm]]
+    return rows
+
+
+def render(cairo, font, size, ch, baseline):
+    surface = cairo.cairo_image_surface_create(CAIRO_FORMAT_A8, WIDTH, HEIGHT)
+    cr = cairo.cairo_create(surface)
+    cairo.cairo_select_font_face(cr, font.encode(), 0, 0)
+    cairo.cairo_set_font_size(cr, size)
+    cairo.cairo_move_to(cr, 0, baseline)
+    cairo.cairo_show_text(cr, ch.encode("utf-8"))
+    cairo.cairo_surface_flush(surface)
+
+    stride = cairo.cairo_image_surface_get_stride(surface)
+    data = ctypes.cast(cairo.cairo_image_surface_get_data(surface), ctypes.POINTER(ctypes.c_ubyte))
+    rows = []
+    for y in range(HEIGHT):
+        bits = 0
+        for x in range(WIDTH):
+            if data[y * stride + x] >= 100:
+                bits |= 0x80 >> x
+        rows.append(bits)
+
+    cairo.cairo_destroy(cr)
+    cairo.cairo_surface_destroy(surface)
+    return rows
+
+
+def main():
+    parser = argparse.ArgumentParser(description="Convert a TrueType font to an 8x16 PSF1 font for HyzeOS")
+    parser.add_argument("--font", default="DejaVu Sans Mono", help="Font family (looked up by fontconfig)")
+    parser.add_argument("--size", type=float, default=14.0, help="Font size in pixels")
+    parser.add_argument("--baseline", type=float, default=12.0, help="Baseline row")
+    parser.add_argument("--output", default="fonts/default8x16.psf", help="Output PSF file")
+    args = parser.parse_args()
+
+    cairo = ctypes.CDLL(ctypes.util.find_library("cairo"))
+    ptr = ctypes.c_void_p
+    cairo.cairo_image_surface_create.restype = ptr
+    cairo.cairo_image_surface_create.argtypes = [ctypes.c_int, ctypes.c_int, ctypes.c_int]
+    cairo.cairo_create.restype = ptr
+    cairo.cairo_create.argtypes = [ptr]
+    cairo.cairo_select_font_face.argtypes = [ptr, ctypes.c_char_p, ctypes.c_int, ctypes.c_int]
+    cairo.cairo_set_font_size.argtypes = [ptr, ctypes.c_double]
+    cairo.cairo_move_to.argtypes = [ptr, ctypes.c_double, ctypes.c_double]
+    cairo.cairo_show_text.argtypes = [ptr, ctypes.c_char_p]
+    cairo.cairo_image_surface_get_data.restype = ptr
+    for fn in ("cairo_surface_flush", "cairo_image_surface_get_stride", "cairo_image_surface_get_data",
+               "cairo_destroy", "cairo_surface_destroy"):
+        getattr(cairo, fn).argtypes = [ptr]
+
+    glyphs = bytearray()
+    for ch in CP437:
+        rows = block_glyph(ch)
+        if rows is None:
+            if ch in ("\u0000", " ", " "):
+                rows = [0] * HEIGHT
+            else:
+                rows = render(cairo, args.font, args.size, ch, args.baseline)
+        glyphs.extend(rows)
+
+    with open(args.output, "wb") as f:
+        # PSF1 header: magic, mode 0 (256 glyphs, no unicode table), charsize
+        f.write(PSF1_MAGIC + bytes([0, HEIGHT]) + bytes(glyphs))
+
+    print(f"Wrote {len(CP437)} glyphs to {args.output}")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,12 @@
+# Example HexShell script: `run /scripts/example.sh`
+# Copy it to /scripts/boot.sh to have it run at every boot.
+set NAME notes
+set DIR /
+
+cd $DIR
+if cat $NAME
+    echo Found $NAME
+else
+    write $NAME Created by example.sh
+    echo Made a new $NAME
+fi
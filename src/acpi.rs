@@ -0,0 +1,413 @@
+use core::ptr::read_unaligned;
+
+pub const MAX_CPUS: usize = 16;
+const MAX_IOAPICS: usize = 4;
+const MAX_OVERRIDES: usize = 16;
+
+const BIOS_AREA_START: usize = 0xE0000;
+const BIOS_AREA_END: usize = 0x100000;
+const EBDA_SEGMENT_PTR: usize = 0x40E;
+
+const SDT_HEADER_LEN: usize = 36;
+
+#[derive(Clone, Copy)]
+pub struct Processor {
+    pub acpi_id: u8,
+    pub apic_id: u8,
+    pub enabled: bool,
+}
+
+#[derive(Clone, Copy)]
+pub struct IoApic {
+    pub id: u8,
+    pub address: u32,
+    pub gsi_base: u32,
+}
+
+/// ISA IRQ that is wired to a different global system interrupt, or with
+/// non-default polarity/trigger (MPS INTI flags)
+#[derive(Clone, Copy)]
+pub struct InterruptOverride {
+    pub source: u8,
+    pub gsi: u32,
+    pub flags: u16,
+}
+
+#[derive(Clone, Copy)]
+pub struct Fadt {
+    /// Physical address of the FACS
+    pub firmware_ctrl: u32,
+    pub dsdt: u32,
+    pub sci_int: u16,
+    pub smi_cmd: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub pm1a_evt_blk: u32,
+    pub pm1b_evt_blk: u32,
+    pub pm1a_cnt_blk: u32,
+    pub pm1b_cnt_blk: u32,
+    pub pm1_evt_len: u8,
+    pub flags: u32,
+    pub reset_port: u16,
+    pub reset_value: u8,
+}
+
+impl Fadt {
+    const fn empty() -> Self {
+        Self {
+            firmware_ctrl: 0,
+            dsdt: 0,
+            sci_int: 0,
+            smi_cmd: 0,
+            acpi_enable: 0,
+            acpi_disable: 0,
+            pm1a_evt_blk: 0,
+            pm1b_evt_blk: 0,
+            pm1a_cnt_blk: 0,
+            pm1b_cnt_blk: 0,
+            pm1_evt_len: 0,
+            flags: 0,
+            reset_port: 0,
+            reset_value: 0,
+        }
+    }
+}
+
+pub struct AcpiInfo {
+    pub present: bool,
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+    pub local_apic_address: u32,
+    pub processors: [Processor; MAX_CPUS],
+    pub processor_count: usize,
+    pub io_apics: [IoApic; MAX_IOAPICS],
+    pub io_apic_count: usize,
+    pub overrides: [InterruptOverride; MAX_OVERRIDES],
+    pub override_count: usize,
+    pub fadt: Option<Fadt>,
+}
+
+impl AcpiInfo {
+    const fn empty() -> Self {
+        Self {
+            present: false,
+            revision: 0,
+            oem_id: [0; 6],
+            local_apic_address: 0,
+            processors: [Processor { acpi_id: 0, apic_id: 0, enabled: false }; MAX_CPUS],
+            processor_count: 0,
+            io_apics: [IoApic { id: 0, address: 0, gsi_base: 0 }; MAX_IOAPICS],
+            io_apic_count: 0,
+            overrides: [InterruptOverride { source: 0, gsi: 0, flags: 0 }; MAX_OVERRIDES],
+            override_count: 0,
+            fadt: None,
+        }
+    }
+
+    /// Number of processors the firmware reports as usable
+    pub fn cpu_count(&self) -> usize {
+        let mut count = 0;
+        for cpu in self.processors[..self.processor_count].iter() {
+            if cpu.enabled {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Override for an ISA IRQ, if the MADT lists one
+    pub fn irq_override(&self, irq: u8) -> Option<InterruptOverride> {
+        self.overrides[..self.override_count].iter().find(|o| o.source == irq).copied()
+    }
+}
+
+static mut ACPI_INFO: AcpiInfo = AcpiInfo::empty();
+
+pub fn get_info() -> &'static AcpiInfo {
+    unsafe { &ACPI_INFO }
+}
+
+/// Locate the RSDP, walk the RSDT and parse the tables we care about
+pub fn init() -> Result<(), &'static str> {
+    let rsdp = find_rsdp().ok_or("RSDP not found")?;
+    let info = unsafe { &mut ACPI_INFO };
+
+    unsafe {
+        for i in 0..6 {
+            info.oem_id[i] = *((rsdp + 9 + i) as *const u8);
+        }
+        info.revision = *((rsdp + 15) as *const u8);
+    }
+
+    let rsdt = unsafe { read_unaligned((rsdp + 16) as *const u32) } as usize;
+    if !table_valid(rsdt, b"RSDT") {
+        return Err("Invalid RSDT");
+    }
+
+    let length = table_length(rsdt);
+    let entries = (length - SDT_HEADER_LEN) / 4;
+    for i in 0..entries {
+        let table = unsafe { read_unaligned((rsdt + SDT_HEADER_LEN + i * 4) as *const u32) } as usize;
+        if table_valid(table, b"APIC") {
+            parse_madt(info, table);
+        } else if table_valid(table, b"FACP") {
+            info.fadt = Some(parse_fadt(table));
+        }
+    }
+
+    info.present = true;
+    Ok(())
+}
+
+/// Find a table by signature, returning its physical address
+pub fn find_table(signature: &[u8; 4]) -> Option<usize> {
+    if signature == b"DSDT" {
+        if !get_info().present {
+            return None;
+        }
+        let dsdt = get_info().fadt?.dsdt as usize;
+        return if table_valid(dsdt, b"DSDT") { Some(dsdt) } else { None };
+    }
+    find_nth_table(signature, 0)
+}
+
+/// Find the `n`th table with this signature, for tables that may repeat
+/// such as SSDTs
+pub fn find_nth_table(signature: &[u8; 4], n: usize) -> Option<usize> {
+    if !get_info().present {
+        return None;
+    }
+
+    let rsdp = find_rsdp()?;
+    let rsdt = unsafe { read_unaligned((rsdp + 16) as *const u32) } as usize;
+    let entries = (table_length(rsdt) - SDT_HEADER_LEN) / 4;
+    let mut seen = 0;
+    for i in 0..entries {
+        let table = unsafe { read_unaligned((rsdt + SDT_HEADER_LEN + i * 4) as *const u32) } as usize;
+        if table_valid(table, signature) {
+            if seen == n {
+                return Some(table);
+            }
+            seen += 1;
+        }
+    }
+    None
+}
+
+/// Signature of every table the RSDT lists, in order
+pub fn table_signatures() -> impl Iterator<Item = [u8; 4]> {
+    let rsdt = if get_info().present {
+        find_rsdp().map(|rsdp| unsafe { read_unaligned((rsdp + 16) as *const u32) } as usize)
+    } else {
+        None
+    };
+    let entries = rsdt.map_or(0, |rsdt| (table_length(rsdt) - SDT_HEADER_LEN) / 4);
+    (0..entries).map(move |i| {
+        let rsdt = rsdt.unwrap_or(0);
+        unsafe {
+            let table = read_unaligned((rsdt + SDT_HEADER_LEN + i * 4) as *const u32) as usize;
+            read_unaligned(table as *const [u8; 4])
+        }
+    })
+}
+
+/// Length of an SDT including its header
+pub fn table_length(table: usize) -> usize {
+    unsafe { read_unaligned((table + 4) as *const u32) as usize }
+}
+
+/// Contents of an SDT after the header, e.g. the AML of a DSDT
+pub fn table_body(table: usize) -> &'static [u8] {
+    let length = table_length(table);
+    unsafe { core::slice::from_raw_parts((table + SDT_HEADER_LEN) as *const u8, length - SDT_HEADER_LEN) }
+}
+
+fn find_rsdp() -> Option<usize> {
+    let ebda = unsafe { read_unaligned(EBDA_SEGMENT_PTR as *const u16) } as usize * 16;
+    if ebda != 0 {
+        if let Some(addr) = scan_for_rsdp(ebda, ebda + 1024) {
+            return Some(addr);
+        }
+    }
+    scan_for_rsdp(BIOS_AREA_START, BIOS_AREA_END)
+}
+
+fn scan_for_rsdp(start: usize, end: usize) -> Option<usize> {
+    let mut addr = start & !0xF;
+    while addr + 20 <= end {
+        let sig = unsafe { core::slice::from_raw_parts(addr as *const u8, 8) };
+        if sig == b"RSD PTR " && checksum(addr, 20) {
+            return Some(addr);
+        }
+        addr += 16;
+    }
+    None
+}
+
+fn checksum(addr: usize, len: usize) -> bool {
+    let mut sum: u8 = 0;
+    for i in 0..len {
+        sum = sum.wrapping_add(unsafe { *((addr + i) as *const u8) });
+    }
+    sum == 0
+}
+
+fn table_valid(table: usize, signature: &[u8; 4]) -> bool {
+    if table == 0 {
+        return false;
+    }
+    let sig = unsafe { core::slice::from_raw_parts(table as *const u8, 4) };
+    if sig != signature {
+        return false;
+    }
+    let length = table_length(table);
+    length >= SDT_HEADER_LEN && checksum(table, length)
+}
+
+fn parse_madt(info: &mut AcpiInfo, madt: usize) {
+    let length = table_length(madt);
+    info.local_apic_address = unsafe { read_unaligned((madt + SDT_HEADER_LEN) as *const u32) };
+
+    let mut offset = SDT_HEADER_LEN + 8;
+    while offset + 2 <= length {
+        let entry = madt + offset;
+        let (kind, entry_len) = unsafe { (*(entry as *const u8), *((entry + 1) as *const u8) as usize) };
+        if entry_len < 2 || offset + entry_len > length {
+            break;
+        }
+
+        match kind {
+            0 if entry_len >= 8 && info.processor_count < MAX_CPUS => unsafe {
+                let flags = read_unaligned((entry + 4) as *const u32);
+                info.processors[info.processor_count] = Processor {
+                    acpi_id: *((entry + 2) as *const u8),
+                    apic_id: *((entry + 3) as *const u8),
+                    // Bit 0: enabled. Bit 1 alone, online capable, means it
+                    // is not present yet and must not be started
+                    enabled: flags & 1 != 0,
+                };
+                info.processor_count += 1;
+            },
+            1 if entry_len >= 12 && info.io_apic_count < MAX_IOAPICS => unsafe {
+                info.io_apics[info.io_apic_count] = IoApic {
+                    id: *((entry + 2) as *const u8),
+                    address: read_unaligned((entry + 4) as *const u32),
+                    gsi_base: read_unaligned((entry + 8) as *const u32),
+                };
+                info.io_apic_count += 1;
+            },
+            2 if entry_len >= 10 && info.override_count < MAX_OVERRIDES => unsafe {
+                info.overrides[info.override_count] = InterruptOverride {
+                    source: *((entry + 3) as *const u8),
+                    gsi: read_unaligned((entry + 4) as *const u32),
+                    flags: read_unaligned((entry + 8) as *const u16),
+                };
+                info.override_count += 1;
+            },
+            _ => {}
+        }
+
+        offset += entry_len;
+    }
+}
+
+fn parse_fadt(fadt: usize) -> Fadt {
+    let length = table_length(fadt);
+    let mut parsed = Fadt::empty();
+
+    unsafe {
+        parsed.firmware_ctrl = read_unaligned((fadt + 36) as *const u32);
+        parsed.dsdt = read_unaligned((fadt + 40) as *const u32);
+        parsed.sci_int = read_unaligned((fadt + 46) as *const u16);
+        parsed.smi_cmd = read_unaligned((fadt + 48) as *const u32);
+        parsed.acpi_enable = *((fadt + 52) as *const u8);
+        parsed.acpi_disable = *((fadt + 53) as *const u8);
+        parsed.pm1a_evt_blk = read_unaligned((fadt + 56) as *const u32);
+        parsed.pm1b_evt_blk = read_unaligned((fadt + 60) as *const u32);
+        parsed.pm1a_cnt_blk = read_unaligned((fadt + 64) as *const u32);
+        parsed.pm1b_cnt_blk = read_unaligned((fadt + 68) as *const u32);
+        parsed.pm1_evt_len = *((fadt + 88) as *const u8);
+
+        // ACPI 2.0+ FADTs carry flags and a reset register
+        if length >= 129 {
+            parsed.flags = read_unaligned((fadt + 112) as *const u32);
+            // Reset register must be supported and live in system I/O space
+            if parsed.flags & (1 << 10) != 0 && *((fadt + 116) as *const u8) == 1 {
+                parsed.reset_port = read_unaligned((fadt + 120) as *const u32) as u16;
+                parsed.reset_value = *((fadt + 128) as *const u8);
+            }
+        }
+    }
+
+    parsed
+}
+
+/// Extract SLP_TYPa/SLP_TYPb for the S5 (soft-off) state from the DSDT
+pub fn s5_sleep_type() -> Option<(u16, u16)> {
+    sleep_type(b"_S5_")
+}
+
+/// Extract SLP_TYPa/SLP_TYPb for S3 (suspend to RAM) from the DSDT
+pub fn s3_sleep_type() -> Option<(u16, u16)> {
+    sleep_type(b"_S3_")
+}
+
+fn sleep_type(object: &[u8; 4]) -> Option<(u16, u16)> {
+    let dsdt = find_table(b"DSDT")?;
+    let length = table_length(dsdt);
+    let body = unsafe { core::slice::from_raw_parts(dsdt as *const u8, length) };
+
+    let mut i = SDT_HEADER_LEN;
+    while i + 4 < length {
+        if &body[i..i + 4] == object {
+            // Expect `Name(_Sx_, Package() { ... })`, optionally with a root prefix
+            let is_name = body[i - 1] == 0x08 || (body[i - 2] == 0x08 && body[i - 1] == b'\\');
+            if is_name && body[i + 4] == 0x12 {
+                let mut p = i + 5;
+                // Skip PkgLength (lead byte encodes extra length bytes) and NumElements
+                p += (*body.get(p)? >> 6) as usize + 2;
+                let (typ_a, next) = read_package_byte(body, p)?;
+                let (typ_b, _) = read_package_byte(body, next)?;
+                return Some((typ_a as u16, typ_b as u16));
+            }
+        }
+        i += 1;
+    }
+    None
+}
+
+fn read_package_byte(body: &[u8], p: usize) -> Option<(u8, usize)> {
+    match *body.get(p)? {
+        // BytePrefix
+        0x0A => Some((*body.get(p + 1)?, p + 2)),
+        // ZeroOp / OneOp
+        0x00 => Some((0, p + 1)),
+        0x01 => Some((1, p + 1)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn madt_entries_are_bounded_and_only_enabled_cpus_count() {
+        let mut table = [0u8; SDT_HEADER_LEN + 8 + 8 + 8 + 4];
+        let length = table.len() as u32;
+        table[4..8].copy_from_slice(&length.to_le_bytes());
+        let entries = SDT_HEADER_LEN + 8;
+        // Enabled, then online capable only
+        table[entries..entries + 8].copy_from_slice(&[0, 8, 1, 1, 1, 0, 0, 0]);
+        table[entries + 8..entries + 16].copy_from_slice(&[0, 8, 2, 2, 2, 0, 0, 0]);
+        // An I/O APIC entry claiming more than the table has left
+        table[entries + 16..entries + 20].copy_from_slice(&[1, 12, 0, 0]);
+
+        let mut info = AcpiInfo::empty();
+        parse_madt(&mut info, table.as_ptr() as usize);
+        assert_eq!(info.processor_count, 2);
+        assert!(info.processors[0].enabled);
+        assert!(!info.processors[1].enabled);
+        assert_eq!(info.io_apic_count, 0);
+    }
+}
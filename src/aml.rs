@@ -0,0 +1,583 @@
+use crate::acpi;
+
+// Just enough AML to find devices in the DSDT and SSDTs.
+//
+// The namespace is walked statically: scopes, devices and `Name` objects
+// are followed, and methods are only looked into when their body is a lone
+// `Return` of a constant or of a sibling `Name`. Anything the walker can't
+// size is skipped to the end of the enclosing scope, which every scope and
+// device carries as its package length.
+
+pub const MAX_DEVICES: usize = 48;
+const MAX_DEPTH: usize = 6;
+const MAX_RESOURCES: usize = 6;
+
+const ZERO_OP: u8 = 0x00;
+const ONE_OP: u8 = 0x01;
+const ALIAS_OP: u8 = 0x06;
+const NAME_OP: u8 = 0x08;
+const BYTE_PREFIX: u8 = 0x0A;
+const WORD_PREFIX: u8 = 0x0B;
+const DWORD_PREFIX: u8 = 0x0C;
+const STRING_PREFIX: u8 = 0x0D;
+const QWORD_PREFIX: u8 = 0x0E;
+const SCOPE_OP: u8 = 0x10;
+const BUFFER_OP: u8 = 0x11;
+const PACKAGE_OP: u8 = 0x12;
+const VAR_PACKAGE_OP: u8 = 0x13;
+const METHOD_OP: u8 = 0x14;
+const EXTERNAL_OP: u8 = 0x15;
+const DUAL_NAME_PREFIX: u8 = 0x2E;
+const MULTI_NAME_PREFIX: u8 = 0x2F;
+const EXT_OP_PREFIX: u8 = 0x5B;
+const ROOT_CHAR: u8 = b'\\';
+const PARENT_PREFIX: u8 = b'^';
+const IF_OP: u8 = 0xA0;
+const ELSE_OP: u8 = 0xA1;
+const WHILE_OP: u8 = 0xA2;
+const RETURN_OP: u8 = 0xA4;
+const ONES_OP: u8 = 0xFF;
+
+// Second byte after EXT_OP_PREFIX
+const MUTEX_OP: u8 = 0x01;
+const EVENT_OP: u8 = 0x02;
+const REVISION_OP: u8 = 0x30;
+const OP_REGION_OP: u8 = 0x80;
+const FIELD_OP: u8 = 0x81;
+const DEVICE_OP: u8 = 0x82;
+const PROCESSOR_OP: u8 = 0x83;
+const POWER_RES_OP: u8 = 0x84;
+const THERMAL_ZONE_OP: u8 = 0x85;
+const INDEX_FIELD_OP: u8 = 0x86;
+const BANK_FIELD_OP: u8 = 0x87;
+
+/// FADT flag: set when the power button is a control method device
+/// rather than the fixed-feature button
+const FADT_PWR_BUTTON: u32 = 1 << 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    Io { base: u16, len: u16 },
+    Irq(u32),
+    Memory { base: u32, len: u32 },
+}
+
+#[derive(Clone, Copy)]
+pub struct Device {
+    path: [[u8; 4]; MAX_DEPTH],
+    depth: usize,
+    hid: [u8; 8],
+    hid_len: usize,
+    pub uid: Option<u32>,
+    resources: [Resource; MAX_RESOURCES],
+    resource_count: usize,
+}
+
+const NO_DEVICE: Device = Device {
+    path: [[0; 4]; MAX_DEPTH],
+    depth: 0,
+    hid: [0; 8],
+    hid_len: 0,
+    uid: None,
+    resources: [Resource::Irq(0); MAX_RESOURCES],
+    resource_count: 0,
+};
+
+impl Device {
+    /// Name segments from the root, e.g. `_SB_`, `PCI0`, `KBD_`
+    pub fn path(&self) -> &[[u8; 4]] {
+        &self.path[..self.depth]
+    }
+
+    /// Hardware ID, e.g. `PNP0303`; empty if the device has none
+    pub fn hid(&self) -> &[u8] {
+        &self.hid[..self.hid_len]
+    }
+
+    pub fn resources(&self) -> &[Resource] {
+        &self.resources[..self.resource_count]
+    }
+
+    /// First I/O port range, which is the register block for most ISA devices
+    pub fn io_base(&self) -> Option<u16> {
+        self.resources().iter().find_map(|r| match *r {
+            Resource::Io { base, .. } => Some(base),
+            _ => None,
+        })
+    }
+
+    pub fn irq(&self) -> Option<u32> {
+        self.resources().iter().find_map(|r| match *r {
+            Resource::Irq(irq) => Some(irq),
+            _ => None,
+        })
+    }
+
+    fn set_hid(&mut self, value: Value) {
+        match value {
+            Value::Integer(id) => {
+                self.hid = eisa_id(id as u32);
+                self.hid_len = 7;
+            }
+            Value::String(s) => {
+                self.hid_len = s.len().min(self.hid.len());
+                self.hid[..self.hid_len].copy_from_slice(&s[..self.hid_len]);
+            }
+            Value::Buffer(_) => {}
+        }
+    }
+
+    fn set_resources(&mut self, template: &[u8]) {
+        self.resource_count = 0;
+        for resource in (ResourceIter { data: template }) {
+            if self.resource_count == MAX_RESOURCES {
+                break;
+            }
+            self.resources[self.resource_count] = resource;
+            self.resource_count += 1;
+        }
+    }
+}
+
+pub struct Namespace {
+    devices: [Device; MAX_DEVICES],
+    count: usize,
+}
+
+impl Namespace {
+    pub const fn new() -> Self {
+        Self { devices: [NO_DEVICE; MAX_DEVICES], count: 0 }
+    }
+
+    pub fn devices(&self) -> &[Device] {
+        &self.devices[..self.count]
+    }
+
+    /// Walk the definition block `aml` (a table body, header excluded)
+    pub fn load(&mut self, aml: &[u8]) {
+        self.walk(aml, 0, aml.len(), &Scope::root(), None);
+    }
+
+    pub fn find(&self, hid: &[u8]) -> Option<&Device> {
+        self.devices().iter().find(|d| d.hid() == hid)
+    }
+
+    fn walk(&mut self, aml: &[u8], start: usize, end: usize, scope: &Scope, device: Option<usize>) {
+        let mut p = start;
+        while p < end {
+            p = match self.term(aml, p, end, scope, device) {
+                Some(next) => next,
+                // Can't tell how long this term is; give up on the scope
+                None => return,
+            };
+        }
+    }
+
+    /// Handle the term at `p`, returning where the next one starts
+    fn term(&mut self, aml: &[u8], p: usize, end: usize, scope: &Scope, device: Option<usize>) -> Option<usize> {
+        match *aml.get(p)? {
+            SCOPE_OP => {
+                let (pkg_end, p) = pkg_length(aml, p + 1)?;
+                let (name, p) = name_string(aml, p)?;
+                let inner = scope.join(&name)?;
+                self.walk(aml, p, pkg_end.min(end), &inner, None);
+                Some(pkg_end)
+            }
+            NAME_OP => {
+                let (name, p) = name_string(aml, p + 1)?;
+                let (value, next) = data_object(aml, p)?;
+                if let (Some(index), Some(seg)) = (device, name.single()) {
+                    self.apply(index, seg, value);
+                }
+                Some(next)
+            }
+            METHOD_OP => {
+                let (pkg_end, p) = pkg_length(aml, p + 1)?;
+                let (name, p) = name_string(aml, p)?;
+                if let (Some(index), Some(seg)) = (device, name.single()) {
+                    // Skip the flags byte; look for `Return (...)` as the whole body
+                    if aml.get(p + 1) == Some(&RETURN_OP) {
+                        if let Some(value) = self.returned(aml, p + 2, scope) {
+                            self.apply(index, seg, value);
+                        }
+                    }
+                }
+                Some(pkg_end)
+            }
+            ALIAS_OP => {
+                let (_, p) = name_string(aml, p + 1)?;
+                let (_, p) = name_string(aml, p)?;
+                Some(p)
+            }
+            EXTERNAL_OP => {
+                let (_, p) = name_string(aml, p + 1)?;
+                // ObjectType and ArgumentCount
+                Some(p + 2)
+            }
+            IF_OP | ELSE_OP | WHILE_OP => pkg_length(aml, p + 1).map(|(pkg_end, _)| pkg_end),
+            EXT_OP_PREFIX => self.ext_term(aml, p + 2, end, scope, *aml.get(p + 1)?),
+            _ => None,
+        }
+    }
+
+    fn ext_term(&mut self, aml: &[u8], p: usize, end: usize, scope: &Scope, op: u8) -> Option<usize> {
+        match op {
+            DEVICE_OP => {
+                let (pkg_end, p) = pkg_length(aml, p)?;
+                let (name, p) = name_string(aml, p)?;
+                let mut inner = scope.join(&name)?;
+                inner.body = Some((p, pkg_end.min(end)));
+                let index = if self.count < MAX_DEVICES {
+                    let mut device = NO_DEVICE;
+                    device.path = inner.path;
+                    device.depth = inner.depth;
+                    self.devices[self.count] = device;
+                    self.count += 1;
+                    Some(self.count - 1)
+                } else {
+                    None
+                };
+                self.walk(aml, p, pkg_end.min(end), &inner, index);
+                Some(pkg_end)
+            }
+            OP_REGION_OP => {
+                let (_, p) = name_string(aml, p)?;
+                // RegionSpace, then offset and length; only constants are sized
+                let (_, p) = data_object(aml, p + 1)?;
+                let (_, p) = data_object(aml, p)?;
+                Some(p)
+            }
+            MUTEX_OP => name_string(aml, p).map(|(_, p)| p + 1),
+            EVENT_OP => name_string(aml, p).map(|(_, p)| p),
+            FIELD_OP | INDEX_FIELD_OP | BANK_FIELD_OP | PROCESSOR_OP | POWER_RES_OP | THERMAL_ZONE_OP => {
+                pkg_length(aml, p).map(|(pkg_end, _)| pkg_end)
+            }
+            _ => None,
+        }
+    }
+
+    /// Value of `Return (x)` where `x` is a constant or a `Name` in `scope`
+    fn returned<'a>(&self, aml: &'a [u8], p: usize, scope: &Scope) -> Option<Value<'a>> {
+        if let Some((value, _)) = data_object(aml, p) {
+            return Some(value);
+        }
+        let (name, _) = name_string(aml, p)?;
+        let target = name.single()?;
+        let (start, end) = scope.body?;
+        find_name(aml, start, end, target)
+    }
+
+    fn apply(&mut self, index: usize, name: [u8; 4], value: Value) {
+        let device = &mut self.devices[index];
+        match &name {
+            b"_HID" => device.set_hid(value),
+            b"_UID" => {
+                if let Value::Integer(uid) = value {
+                    device.uid = Some(uid as u32);
+                }
+            }
+            b"_CRS" => {
+                if let Value::Buffer(template) = value {
+                    device.set_resources(template);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Value<'a> {
+    Integer(u64),
+    String(&'a [u8]),
+    Buffer(&'a [u8]),
+}
+
+/// A parsed NameString: how many levels to climb (or from the root), then segments
+struct Name {
+    root: bool,
+    parents: usize,
+    segs: [[u8; 4]; MAX_DEPTH],
+    count: usize,
+}
+
+impl Name {
+    /// The segment of a plain one-segment relative name
+    fn single(&self) -> Option<[u8; 4]> {
+        if !self.root && self.parents == 0 && self.count == 1 {
+            Some(self.segs[0])
+        } else {
+            None
+        }
+    }
+}
+
+/// Where in the namespace the walker is
+struct Scope {
+    path: [[u8; 4]; MAX_DEPTH],
+    depth: usize,
+    /// Byte range of the device body, for resolving names inside it
+    body: Option<(usize, usize)>,
+}
+
+impl Scope {
+    fn root() -> Self {
+        Self { path: [[0; 4]; MAX_DEPTH], depth: 0, body: None }
+    }
+
+    fn join(&self, name: &Name) -> Option<Scope> {
+        let mut scope = Scope { path: self.path, depth: self.depth, body: None };
+        if name.root {
+            scope.depth = 0;
+        }
+        scope.depth = scope.depth.checked_sub(name.parents)?;
+        for seg in &name.segs[..name.count] {
+            if scope.depth == MAX_DEPTH {
+                return None;
+            }
+            scope.path[scope.depth] = *seg;
+            scope.depth += 1;
+        }
+        Some(scope)
+    }
+}
+
+/// Decode a PkgLength at `p`, returning the end of the package it measures
+/// and where the data after it starts
+fn pkg_length(aml: &[u8], p: usize) -> Option<(usize, usize)> {
+    let lead = *aml.get(p)?;
+    let extra = (lead >> 6) as usize;
+    let mut length = if extra == 0 { (lead & 0x3F) as usize } else { (lead & 0x0F) as usize };
+    for i in 0..extra {
+        length |= (*aml.get(p + 1 + i)? as usize) << (4 + 8 * i);
+    }
+    Some((p + length, p + 1 + extra))
+}
+
+fn name_seg(aml: &[u8], p: usize) -> Option<[u8; 4]> {
+    let bytes = aml.get(p..p + 4)?;
+    let valid = bytes.iter().enumerate().all(|(i, &c)| c == b'_' || c.is_ascii_uppercase() || (i > 0 && c.is_ascii_digit()));
+    if valid {
+        Some([bytes[0], bytes[1], bytes[2], bytes[3]])
+    } else {
+        None
+    }
+}
+
+fn name_string(aml: &[u8], mut p: usize) -> Option<(Name, usize)> {
+    let mut name = Name { root: false, parents: 0, segs: [[0; 4]; MAX_DEPTH], count: 0 };
+    if aml.get(p) == Some(&ROOT_CHAR) {
+        name.root = true;
+        p += 1;
+    } else {
+        while aml.get(p) == Some(&PARENT_PREFIX) {
+            name.parents += 1;
+            p += 1;
+        }
+    }
+
+    let count = match *aml.get(p)? {
+        ZERO_OP => return Some((name, p + 1)),
+        DUAL_NAME_PREFIX => {
+            p += 1;
+            2
+        }
+        MULTI_NAME_PREFIX => {
+            p += 2;
+            *aml.get(p - 1)? as usize
+        }
+        _ => 1,
+    };
+    if count > MAX_DEPTH {
+        return None;
+    }
+    for i in 0..count {
+        name.segs[i] = name_seg(aml, p)?;
+        p += 4;
+    }
+    name.count = count;
+    Some((name, p))
+}
+
+/// A constant data object (integer, string, buffer or package) at `p`
+fn data_object(aml: &[u8], p: usize) -> Option<(Value<'_>, usize)> {
+    let int = |len: usize| -> Option<(Value, usize)> {
+        let bytes = aml.get(p + 1..p + 1 + len)?;
+        let value = bytes.iter().rev().fold(0u64, |acc, &b| acc << 8 | b as u64);
+        Some((Value::Integer(value), p + 1 + len))
+    };
+
+    match *aml.get(p)? {
+        ZERO_OP => Some((Value::Integer(0), p + 1)),
+        ONE_OP => Some((Value::Integer(1), p + 1)),
+        ONES_OP => Some((Value::Integer(u64::MAX), p + 1)),
+        BYTE_PREFIX => int(1),
+        WORD_PREFIX => int(2),
+        DWORD_PREFIX => int(4),
+        QWORD_PREFIX => int(8),
+        STRING_PREFIX => {
+            let len = aml.get(p + 1..)?.iter().position(|&c| c == 0)?;
+            Some((Value::String(&aml[p + 1..p + 1 + len]), p + len + 2))
+        }
+        BUFFER_OP => {
+            let (pkg_end, q) = pkg_length(aml, p + 1)?;
+            // BufferSize is normally a constant; the bytes run to the package end
+            let (_, q) = data_object(aml, q)?;
+            Some((Value::Buffer(aml.get(q..pkg_end)?), pkg_end))
+        }
+        PACKAGE_OP | VAR_PACKAGE_OP => {
+            let (pkg_end, _) = pkg_length(aml, p + 1)?;
+            Some((Value::Buffer(&[]), pkg_end))
+        }
+        EXT_OP_PREFIX if aml.get(p + 1) == Some(&REVISION_OP) => Some((Value::Integer(0), p + 2)),
+        _ => None,
+    }
+}
+
+/// Find `Name (target, ...)` directly in the term list between `start` and `end`
+fn find_name(aml: &[u8], start: usize, end: usize, target: [u8; 4]) -> Option<Value<'_>> {
+    let mut p = start;
+    while p + 5 < end {
+        if aml[p] == NAME_OP && aml[p + 1..p + 5] == target {
+            return data_object(aml, p + 5).map(|(value, _)| value);
+        }
+        p += 1;
+    }
+    None
+}
+
+/// Expand a compressed EISA ID such as 0x0303D041 to `PNP0303`
+fn eisa_id(id: u32) -> [u8; 8] {
+    let id = id.swap_bytes();
+    let hex = b"0123456789ABCDEF";
+    let mut out = [0u8; 8];
+    out[0] = b'@' + ((id >> 26) & 0x1F) as u8;
+    out[1] = b'@' + ((id >> 21) & 0x1F) as u8;
+    out[2] = b'@' + ((id >> 16) & 0x1F) as u8;
+    for i in 0..4 {
+        out[3 + i] = hex[((id >> (12 - 4 * i)) & 0xF) as usize];
+    }
+    out
+}
+
+/// Walks the descriptors of a resource template (a `_CRS` buffer)
+struct ResourceIter<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for ResourceIter<'a> {
+    type Item = Resource;
+
+    fn next(&mut self) -> Option<Resource> {
+        loop {
+            let tag = *self.data.first()?;
+            let (kind, body, len) = if tag & 0x80 == 0 {
+                ((tag >> 3) & 0x0F, 1, (tag & 0x07) as usize)
+            } else {
+                let len = *self.data.get(1)? as usize | (*self.data.get(2)? as usize) << 8;
+                (tag, 3, len)
+            };
+            let d = self.data.get(body..body + len)?;
+            self.data = &self.data[body + len..];
+            let word = |i: usize| d[i] as u16 | (d[i + 1] as u16) << 8;
+            let dword = |i: usize| word(i) as u32 | (word(i + 2) as u32) << 16;
+
+            let resource = match (kind, len) {
+                // End tag
+                (0x0F, _) => return None,
+                // IRQ: 16-bit mask, only the lowest line is kept
+                (0x04, 2..=3) if word(0) != 0 => Resource::Irq(word(0).trailing_zeros()),
+                // I/O port: decode info, minimum, maximum, alignment, length
+                (0x08, 7) => Resource::Io { base: word(1), len: d[6] as u16 },
+                // Fixed I/O port
+                (0x09, 3) => Resource::Io { base: word(0) & 0x3FF, len: d[2] as u16 },
+                // 32-bit fixed memory
+                (0x86, 9) => Resource::Memory { base: dword(1), len: dword(5) },
+                // Extended interrupt: flags, count, then the first interrupt
+                (0x89, 6..=usize::MAX) if d[1] > 0 => Resource::Irq(dword(2)),
+                _ => continue,
+            };
+            return Some(resource);
+        }
+    }
+}
+
+static mut NAMESPACE: Namespace = Namespace::new();
+
+/// Walk the DSDT and every SSDT, returning how many devices were found
+pub fn init() -> Result<usize, &'static str> {
+    let namespace = unsafe { &mut NAMESPACE };
+    let dsdt = acpi::find_table(b"DSDT").ok_or("No DSDT")?;
+    namespace.load(acpi::table_body(dsdt));
+
+    let mut index = 0;
+    while let Some(ssdt) = acpi::find_nth_table(b"SSDT", index) {
+        namespace.load(acpi::table_body(ssdt));
+        index += 1;
+    }
+    Ok(namespace.count)
+}
+
+pub fn devices() -> &'static [Device] {
+    unsafe { NAMESPACE.devices() }
+}
+
+/// The 8042 keyboard controller, found through its keyboard device
+pub fn ps2_controller() -> Option<&'static Device> {
+    let namespace = unsafe { &NAMESPACE };
+    namespace.find(b"PNP0303").or_else(|| namespace.find(b"PNP030B"))
+}
+
+/// 16550-compatible serial ports
+pub fn serial_ports() -> impl Iterator<Item = &'static Device> {
+    devices().iter().filter(|d| d.hid() == b"PNP0501" || d.hid() == b"PNP0500")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerButton {
+    /// Reported through the PM1 event block
+    FixedFeature,
+    /// A `PNP0C0C` device, reported through a GPE and `Notify`
+    Device,
+}
+
+pub fn power_button() -> Option<PowerButton> {
+    let fadt = acpi::get_info().fadt?;
+    if fadt.flags & FADT_PWR_BUTTON == 0 {
+        Some(PowerButton::FixedFeature)
+    } else if unsafe { NAMESPACE.find(b"PNP0C0C") }.is_some() {
+        Some(PowerButton::Device)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn finds_serial_port_resources() {
+        #[rustfmt::skip]
+        let aml = [
+            // Scope (\_SB)
+            SCOPE_OP, 0x2D, b'\\', b'_', b'S', b'B', b'_',
+            // Device (COM1)
+            EXT_OP_PREFIX, DEVICE_OP, 0x25, b'C', b'O', b'M', b'1',
+            // Name (_HID, EisaId ("PNP0501"))
+            NAME_OP, b'_', b'H', b'I', b'D', DWORD_PREFIX, 0x41, 0xD0, 0x05, 0x01,
+            // Name (_CRS, ResourceTemplate () { IO (Decode16, 0x3F8, 0x3F8, 0, 8) IRQNoFlags () {4} })
+            NAME_OP, b'_', b'C', b'R', b'S', BUFFER_OP, 0x10, BYTE_PREFIX, 0x0D,
+            0x47, 0x01, 0xF8, 0x03, 0xF8, 0x03, 0x00, 0x08,
+            0x22, 0x10, 0x00,
+            0x79, 0x00,
+        ];
+
+        let mut namespace = Namespace::new();
+        namespace.load(&aml);
+        let device = namespace.find(b"PNP0501").expect("serial port not found");
+        assert_eq!(device.path(), &[*b"_SB_", *b"COM1"]);
+        assert_eq!(device.io_base(), Some(0x3F8));
+        assert_eq!(device.irq(), Some(4));
+    }
+}
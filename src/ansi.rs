@@ -0,0 +1,273 @@
+// ANSI escape codes, as in ANSI art files and from serial terminals. The
+// parser turns bytes into `Action`s for `Writer::write_ansi` to carry out
+// and keeps the SGR colors itself, so it needs no screen to test. Bytes
+// that aren't control codes are CP437 characters, the VGA text font's own.
+//
+// Understood: CR, LF, backspace and tab; CSI cursor movement (A B C D H f),
+// erasing (J K), saving and restoring the cursor (s u) and SGR (m) with
+// bold, blink, reverse and the 8 colors, their bright versions (90-97,
+// 100-107) and defaults. Bold brightens the foreground and blink the
+// background, as ANSI art expects. Anything else, including `ESC [ ?`
+// private modes and 256-color SGR, is read and dropped.
+
+const ESC: u8 = 0x1B;
+/// Ends the text of an ANSI art file; a SAUCE record of metadata follows
+const SUB: u8 = 0x1A;
+const MAX_PARAMS: usize = 8;
+/// VGA color numbers for ANSI's black, red, green, yellow, blue, magenta,
+/// cyan and white
+const VGA_COLORS: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+const DEFAULT_FOREGROUND: u8 = 7;
+const DEFAULT_BACKGROUND: u8 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    /// A CP437 character to draw
+    Print(u8),
+    Newline,
+    CarriageReturn,
+    Backspace,
+    Tab,
+    Up(usize),
+    Down(usize),
+    Forward(usize),
+    Back(usize),
+    /// Row and column, counted from 0
+    MoveTo(usize, usize),
+    /// 0 from the cursor on, 1 up to the cursor, 2 everything
+    EraseDisplay(u8),
+    /// Like `EraseDisplay` within the cursor's line
+    EraseLine(u8),
+    /// The attribute byte SGR left behind
+    Color(u8),
+    SaveCursor,
+    RestoreCursor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Ground,
+    /// After ESC
+    Escape,
+    /// After ESC [
+    Csi,
+}
+
+pub struct Parser {
+    state: State,
+    params: [u16; MAX_PARAMS],
+    count: usize,
+    /// `ESC [ ?`
+    private: bool,
+    /// ANSI color numbers, 8-15 the bright ones
+    foreground: u8,
+    background: u8,
+    bold: bool,
+    blink: bool,
+    reverse: bool,
+    /// Column and row `ESC [ s` saved
+    saved: (usize, usize),
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Ground,
+            params: [0; MAX_PARAMS],
+            count: 0,
+            private: false,
+            foreground: DEFAULT_FOREGROUND,
+            background: DEFAULT_BACKGROUND,
+            bold: false,
+            blink: false,
+            reverse: false,
+            saved: (0, 0),
+        }
+    }
+
+    /// Take one byte; returns what it completes, if anything
+    pub fn feed(&mut self, byte: u8) -> Option<Action> {
+        match self.state {
+            State::Ground => match byte {
+                ESC => {
+                    self.state = State::Escape;
+                    None
+                }
+                b'\n' => Some(Action::Newline),
+                b'\r' => Some(Action::CarriageReturn),
+                0x08 => Some(Action::Backspace),
+                b'\t' => Some(Action::Tab),
+                // NUL and the bell
+                0x00 | 0x07 => None,
+                _ => Some(Action::Print(byte)),
+            },
+            State::Escape => {
+                self.state = if byte == b'[' { State::Csi } else { State::Ground };
+                self.params = [0; MAX_PARAMS];
+                self.count = 0;
+                self.private = false;
+                None
+            }
+            State::Csi => match byte {
+                b'0'..=b'9' => {
+                    self.count = self.count.max(1);
+                    let param = &mut self.params[self.count - 1];
+                    *param = param.saturating_mul(10).saturating_add((byte - b'0') as u16);
+                    None
+                }
+                b';' => {
+                    self.count = (self.count.max(1) + 1).min(MAX_PARAMS);
+                    None
+                }
+                b'?' => {
+                    self.private = true;
+                    None
+                }
+                0x40..=0x7E => {
+                    self.state = State::Ground;
+                    if self.private { None } else { self.finish(byte) }
+                }
+                // Intermediate bytes
+                _ => None,
+            },
+        }
+    }
+
+    /// Parameter `i`, or `default` if it was left out or 0
+    fn param(&self, i: usize, default: usize) -> usize {
+        match self.params[i] {
+            0 => default,
+            value => value as usize,
+        }
+    }
+
+    fn finish(&mut self, command: u8) -> Option<Action> {
+        let n = self.param(0, 1);
+        Some(match command {
+            b'A' => Action::Up(n),
+            b'B' => Action::Down(n),
+            b'C' => Action::Forward(n),
+            b'D' => Action::Back(n),
+            b'H' | b'f' => Action::MoveTo(n - 1, self.param(1, 1) - 1),
+            b'J' => Action::EraseDisplay(self.params[0].min(2) as u8),
+            b'K' => Action::EraseLine(self.params[0].min(2) as u8),
+            b's' => Action::SaveCursor,
+            b'u' => Action::RestoreCursor,
+            b'm' => {
+                self.select_graphics();
+                Action::Color(self.attribute())
+            }
+            _ => return None,
+        })
+    }
+
+    fn select_graphics(&mut self) {
+        // `ESC [ m` is a reset like `ESC [ 0 m`
+        let params = self.params;
+        let params = &params[..self.count.max(1)];
+        let mut i = 0;
+        while i < params.len() {
+            match params[i] {
+                0 => {
+                    self.foreground = DEFAULT_FOREGROUND;
+                    self.background = DEFAULT_BACKGROUND;
+                    self.bold = false;
+                    self.blink = false;
+                    self.reverse = false;
+                }
+                1 => self.bold = true,
+                5 => self.blink = true,
+                7 => self.reverse = true,
+                22 => self.bold = false,
+                25 => self.blink = false,
+                27 => self.reverse = false,
+                n @ 30..=37 => self.foreground = (n - 30) as u8,
+                39 => self.foreground = DEFAULT_FOREGROUND,
+                n @ 40..=47 => self.background = (n - 40) as u8,
+                49 => self.background = DEFAULT_BACKGROUND,
+                n @ 90..=97 => self.foreground = (n - 90) as u8 + 8,
+                n @ 100..=107 => self.background = (n - 100) as u8 + 8,
+                // 256 colors (`38;5;n`) and true color (`38;2;r;g;b`) have
+                // nothing to map to, so their numbers are skipped
+                38 | 48 => i += if params.get(i + 1) == Some(&2) { 4 } else { 2 },
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+
+    /// The VGA attribute byte for the colors selected so far
+    pub fn attribute(&self) -> u8 {
+        let (foreground, background) =
+            if self.reverse { (self.background, self.foreground) } else { (self.foreground, self.background) };
+        let foreground = if self.bold { foreground | 8 } else { foreground };
+        let background = if self.blink { background | 8 } else { background };
+        vga_color(background) << 4 | vga_color(foreground)
+    }
+
+    pub fn save_cursor(&mut self, col: usize, row: usize) {
+        self.saved = (col, row);
+    }
+
+    /// Column and row last saved
+    pub fn saved_cursor(&self) -> (usize, usize) {
+        self.saved
+    }
+}
+
+fn vga_color(ansi: u8) -> u8 {
+    VGA_COLORS[(ansi & 7) as usize] | (ansi & 8)
+}
+
+/// The art of an ANSI art file, without the end-of-file mark and SAUCE
+/// record that may follow it
+pub fn art(file: &[u8]) -> &[u8] {
+    file.split(|&b| b == SUB).next().unwrap_or(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn actions(parser: &mut Parser, bytes: &[u8]) -> ([Option<Action>; 8], usize) {
+        let mut found = [None; 8];
+        let mut count = 0;
+        for &byte in bytes {
+            if let Some(action) = parser.feed(byte) {
+                found[count] = Some(action);
+                count += 1;
+            }
+        }
+        (found, count)
+    }
+
+    #[test_case]
+    fn sequences_become_actions_and_colors() {
+        let mut parser = Parser::new();
+        let (found, count) = actions(&mut parser, b"\x1b[5;10Ha\x1b[2J\x1b[A\x1b[3D\r\n\x1b[?25l\x1b[K");
+        assert_eq!(count, 8);
+        assert_eq!(found[0], Some(Action::MoveTo(4, 9)));
+        assert_eq!(found[1], Some(Action::Print(b'a')));
+        assert_eq!(found[2], Some(Action::EraseDisplay(2)));
+        assert_eq!(found[3], Some(Action::Up(1)));
+        assert_eq!(found[4], Some(Action::Back(3)));
+        assert_eq!(found[5], Some(Action::CarriageReturn));
+        assert_eq!(found[6], Some(Action::Newline));
+        assert_eq!(found[7], Some(Action::EraseLine(0)));
+
+        // Bold red on blue, then reversed, then bright green from 256 colors skipped
+        let (found, _) = actions(&mut parser, b"\x1b[1;31;44m\x1b[7m\x1b[0;38;5;1;92m\x1b[m");
+        assert_eq!(found[0], Some(Action::Color(0x1C)));
+        assert_eq!(found[1], Some(Action::Color(0x49)));
+        assert_eq!(found[2], Some(Action::Color(0x0A)));
+        assert_eq!(found[3], Some(Action::Color(0x07)));
+
+        // A sequence split across calls picks up where it left off
+        assert_eq!(parser.feed(0x1B), None);
+        assert_eq!(parser.feed(b'['), None);
+        assert_eq!(parser.feed(b'2'), None);
+        assert_eq!(parser.feed(b'C'), Some(Action::Forward(2)));
+        assert_eq!(parser.feed(0xDB), Some(Action::Print(0xDB)));
+        assert_eq!(art(b"art\x1aSAUCE00"), b"art");
+    }
+}
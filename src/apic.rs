@@ -0,0 +1,253 @@
+use core::arch::{asm, naked_asm};
+use core::ptr::{read_volatile, write_volatile};
+
+use crate::acpi;
+use crate::cpu;
+use crate::idt;
+use crate::io::outb;
+
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const CPUID_APIC: u32 = 1 << 9;
+
+// Local APIC register offsets
+const REG_ID: usize = 0x20;
+const REG_EOI: usize = 0xB0;
+const REG_SPURIOUS: usize = 0xF0;
+const REG_ICR_LOW: usize = 0x300;
+const REG_ICR_HIGH: usize = 0x310;
+const REG_LVT_TIMER: usize = 0x320;
+const REG_TIMER_INITIAL: usize = 0x380;
+const REG_TIMER_CURRENT: usize = 0x390;
+const REG_TIMER_DIVIDE: usize = 0x3E0;
+
+const SPURIOUS_VECTOR: u8 = 0xFF;
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const TIMER_PERIODIC: u32 = 1 << 17;
+/// Divide configuration value for a divisor of 16
+const TIMER_DIVIDE_16: u32 = 0x3;
+
+// Interrupt command register
+const ICR_INIT: u32 = 0x5 << 8;
+const ICR_STARTUP: u32 = 0x6 << 8;
+const ICR_PENDING: u32 = 1 << 12;
+const ICR_ASSERT: u32 = 1 << 14;
+
+// I/O APIC registers, reached through the select/window pair
+const IOREGSEL: usize = 0x00;
+const IOWIN: usize = 0x10;
+const IOAPIC_VERSION: u32 = 0x01;
+const IOAPIC_REDIRECTION: u32 = 0x10;
+
+const REDIRECT_ACTIVE_LOW: u32 = 1 << 13;
+const REDIRECT_LEVEL: u32 = 1 << 15;
+const REDIRECT_MASKED: u32 = 1 << 16;
+
+/// Legacy IRQs keep the vectors they had behind the PIC
+const IRQ_BASE_VECTOR: u8 = 0x20;
+const ROUTED_IRQS: [u8; 3] = [0, 1, 12];
+
+/// PIT ticks to measure the APIC timer over
+const CALIBRATION_TICKS: u32 = 10;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TickSource {
+    Pit,
+    ApicTimer,
+}
+
+static mut LOCAL_APIC: usize = 0;
+static mut ENABLED: bool = false;
+static mut TICK_SOURCE: TickSource = TickSource::Pit;
+/// APIC timer counts per 10 ms tick, at divisor 16
+static mut TIMER_COUNT: u32 = 0;
+
+fn read(reg: usize) -> u32 {
+    unsafe { read_volatile((LOCAL_APIC + reg) as *const u32) }
+}
+
+fn write(reg: usize, value: u32) {
+    unsafe { write_volatile((LOCAL_APIC + reg) as *mut u32, value) }
+}
+
+fn ioapic_read(base: usize, reg: u32) -> u32 {
+    unsafe {
+        write_volatile((base + IOREGSEL) as *mut u32, reg);
+        read_volatile((base + IOWIN) as *const u32)
+    }
+}
+
+fn ioapic_write(base: usize, reg: u32, value: u32) {
+    unsafe {
+        write_volatile((base + IOREGSEL) as *mut u32, reg);
+        write_volatile((base + IOWIN) as *mut u32, value);
+    }
+}
+
+pub fn is_supported() -> bool {
+    let (_, _, _, edx) = cpu::cpuid(1);
+    edx & CPUID_APIC != 0
+}
+
+pub fn is_enabled() -> bool {
+    unsafe { ENABLED }
+}
+
+pub fn tick_source() -> TickSource {
+    unsafe { TICK_SOURCE }
+}
+
+pub fn local_id() -> u8 {
+    (read(REG_ID) >> 24) as u8
+}
+
+pub fn end_of_interrupt() {
+    write(REG_EOI, 0);
+}
+
+/// Switch interrupt delivery from the 8259 PICs to the local APIC and the
+/// first I/O APIC. Runs after `idt::init`, since the PIT ticks are used to
+/// calibrate the APIC timer when it becomes the tick source.
+pub fn init(tick_source: TickSource) -> Result<(), &'static str> {
+    if !is_supported() {
+        return Err("CPU has no local APIC");
+    }
+    let info = acpi::get_info();
+    if !info.present || info.local_apic_address == 0 {
+        return Err("No MADT");
+    }
+    if info.io_apic_count == 0 {
+        return Err("No I/O APIC in the MADT");
+    }
+
+    unsafe {
+        LOCAL_APIC = info.local_apic_address as usize;
+    }
+    idt::set_gate(SPURIOUS_VECTOR, spurious_interrupt_handler as u32, false);
+    enable_local();
+
+    if tick_source == TickSource::ApicTimer {
+        calibrate_timer();
+    }
+
+    unsafe {
+        asm!("cli", options(nostack));
+        // Mask every line on both PICs; they stay remapped to 0x20-0x2F so a
+        // stray spurious IRQ still lands on a harmless vector
+        outb(0x21, 0xFF);
+        outb(0xA1, 0xFF);
+
+        for &irq in ROUTED_IRQS.iter() {
+            let masked = irq == 0 && tick_source == TickSource::ApicTimer;
+            route_irq(info, irq, 0, masked);
+        }
+
+        ENABLED = true;
+        TICK_SOURCE = tick_source;
+        if tick_source == TickSource::ApicTimer {
+            write(REG_TIMER_DIVIDE, TIMER_DIVIDE_16);
+            write(REG_LVT_TIMER, TIMER_PERIODIC | IRQ_BASE_VECTOR as u32);
+            write(REG_TIMER_INITIAL, TIMER_COUNT);
+        }
+        asm!("sti", options(nostack));
+    }
+    Ok(())
+}
+
+/// Enable the calling CPU's local APIC with its timer masked. Every CPU
+/// runs this for itself; `init` covers the bootstrap processor.
+pub fn enable_local() {
+    cpu::write_msr(IA32_APIC_BASE, cpu::read_msr(IA32_APIC_BASE) | APIC_BASE_ENABLE);
+    write(REG_SPURIOUS, SOFTWARE_ENABLE | SPURIOUS_VECTOR as u32);
+    write(REG_LVT_TIMER, LVT_MASKED);
+}
+
+fn send_ipi(apic_id: u8, command: u32) {
+    write(REG_ICR_HIGH, (apic_id as u32) << 24);
+    write(REG_ICR_LOW, command);
+    while read(REG_ICR_LOW) & ICR_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+/// Reset another processor into its wait-for-startup state
+pub fn send_init(apic_id: u8) {
+    send_ipi(apic_id, ICR_INIT | ICR_ASSERT);
+}
+
+/// Start a processor waiting after INIT at real-mode address `page << 12`
+pub fn send_startup(apic_id: u8, page: u8) {
+    send_ipi(apic_id, ICR_STARTUP | ICR_ASSERT | page as u32);
+}
+
+/// Count APIC timer decrements across a few PIT ticks
+fn calibrate_timer() {
+    write(REG_TIMER_DIVIDE, TIMER_DIVIDE_16);
+
+    // Start on a tick boundary
+    let start = idt::get_ticks();
+    while idt::get_ticks() == start {
+        idt::wait_for_interrupt();
+    }
+    write(REG_TIMER_INITIAL, u32::MAX);
+    let start = idt::get_ticks();
+    while idt::get_ticks().wrapping_sub(start) < CALIBRATION_TICKS {
+        idt::wait_for_interrupt();
+    }
+    let elapsed = u32::MAX - read(REG_TIMER_CURRENT);
+    write(REG_TIMER_INITIAL, 0);
+
+    unsafe {
+        TIMER_COUNT = (elapsed / CALIBRATION_TICKS).max(1);
+    }
+}
+
+/// Route and unmask an ISA IRQ after `init`. `default_flags` are the MPS
+/// INTI flags to use when the MADT has no override for it.
+pub fn enable_irq(irq: u8, default_flags: u16) {
+    route_irq(acpi::get_info(), irq, default_flags, false);
+}
+
+/// Point an ISA IRQ at its usual vector on this CPU, honouring any MADT
+/// interrupt source override
+fn route_irq(info: &acpi::AcpiInfo, irq: u8, default_flags: u16, masked: bool) {
+    let (gsi, flags) = match info.irq_override(irq) {
+        Some(o) => (o.gsi, o.flags),
+        // Identity mapped; ISA lines are edge triggered and active high
+        None => (irq as u32, default_flags),
+    };
+
+    for ioapic in info.io_apics[..info.io_apic_count].iter() {
+        let base = ioapic.address as usize;
+        let entries = (ioapic_read(base, IOAPIC_VERSION) >> 16 & 0xFF) + 1;
+        if gsi < ioapic.gsi_base || gsi >= ioapic.gsi_base + entries {
+            continue;
+        }
+
+        let mut low = (IRQ_BASE_VECTOR + irq) as u32;
+        if flags & 0x3 == 0x3 {
+            low |= REDIRECT_ACTIVE_LOW;
+        }
+        if (flags >> 2) & 0x3 == 0x3 {
+            low |= REDIRECT_LEVEL;
+        }
+        if masked {
+            low |= REDIRECT_MASKED;
+        }
+        let reg = IOAPIC_REDIRECTION + (gsi - ioapic.gsi_base) * 2;
+        ioapic_write(base, reg + 1, (local_id() as u32) << 24);
+        ioapic_write(base, reg, low);
+        return;
+    }
+    log_warn!("APIC: no I/O APIC handles GSI {}", gsi);
+}
+
+/// Spurious interrupts must not be acknowledged
+#[unsafe(naked)]
+#[no_mangle]
+pub unsafe extern "C" fn spurious_interrupt_handler() {
+    naked_asm!(
+        "iretd",
+    );
+}
@@ -0,0 +1,88 @@
+use crate::clock;
+
+// Commands to run later, from `at <duration> <command>`. The shell checks
+// for due jobs whenever it is waiting for a key and runs them as if typed,
+// so a job waits while another command is running.
+
+const MAX_JOBS: usize = 8;
+pub const MAX_COMMAND_LEN: usize = 80;
+
+#[derive(Clone, Copy)]
+pub struct Job {
+    pub id: u32,
+    /// Uptime at which it runs
+    pub due_ms: u64,
+    command: [u8; MAX_COMMAND_LEN],
+    len: usize,
+}
+
+impl Job {
+    pub fn command(&self) -> &[u8] {
+        &self.command[..self.len]
+    }
+}
+
+static mut JOBS: [Option<Job>; MAX_JOBS] = [None; MAX_JOBS];
+static mut NEXT_ID: u32 = 1;
+
+/// Run `command` in `delay_ms`; returns the job's number
+pub fn schedule(delay_ms: u64, command: &[u8]) -> Result<u32, &'static str> {
+    if command.len() > MAX_COMMAND_LEN {
+        return Err("Command too long");
+    }
+    unsafe {
+        let slot = JOBS.iter_mut().find(|job| job.is_none()).ok_or("Too many jobs")?;
+        let id = NEXT_ID;
+        NEXT_ID += 1;
+        let mut job = Job { id, due_ms: clock::uptime_ms() + delay_ms, command: [0; MAX_COMMAND_LEN], len: command.len() };
+        job.command[..command.len()].copy_from_slice(command);
+        *slot = Some(job);
+        Ok(id)
+    }
+}
+
+pub fn cancel(id: u32) -> Result<(), &'static str> {
+    unsafe {
+        let slot = JOBS.iter_mut().find(|job| job.map_or(false, |job| job.id == id)).ok_or("No such job")?;
+        *slot = None;
+    }
+    Ok(())
+}
+
+/// Pending jobs, soonest first
+pub fn jobs() -> impl Iterator<Item = Job> {
+    let mut sorted = unsafe { JOBS };
+    sorted.sort_unstable_by_key(|job| job.map_or(u64::MAX, |job| job.due_ms));
+    IntoIterator::into_iter(sorted).flatten()
+}
+
+/// Remove and return the earliest job that is due
+pub fn take_due() -> Option<Job> {
+    let now = clock::uptime_ms();
+    unsafe {
+        let slot = JOBS.iter_mut()
+            .filter(|job| job.map_or(false, |job| job.due_ms <= now))
+            .min_by_key(|job| job.map_or(u64::MAX, |job| job.due_ms))?;
+        slot.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn jobs_run_when_due_and_can_be_cancelled() {
+        let now = schedule(0, b"echo now").unwrap();
+        let later = schedule(3_600_000, b"echo later").unwrap();
+        assert_eq!(jobs().next().map(|job| job.id), Some(now));
+
+        let due = take_due().unwrap();
+        assert_eq!((due.id, due.command()), (now, &b"echo now"[..]));
+        assert!(take_due().is_none());
+
+        assert!(cancel(later).is_ok());
+        assert!(cancel(later).is_err());
+        assert!(schedule(0, &[b'x'; MAX_COMMAND_LEN + 1]).is_err());
+    }
+}
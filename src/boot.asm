@@ -12,6 +12,8 @@
 
 .section .bss
 .align 16
+.global stack_bottom
+.global stack_top
 stack_bottom:
 .skip 16384
 stack_top:
@@ -21,15 +23,19 @@ stack_top:
 .type _start, @function
 _start:
 	mov $stack_top, %esp
+	xor %ebp, %ebp
 
 	pushl $0
 	popf
 
-	mov %cr0, %eax
-	and $0xFFFFFFFB, %eax
-	or $0x2, %eax
-	mov %eax, %cr0
+	mov %cr0, %ecx
+	and $0xFFFFFFFB, %ecx
+	or $0x2, %ecx
+	mov %ecx, %cr0
 
+	# Multiboot magic and info pointer as kernel_main(magic, info)
+	push %ebx
+	push %eax
 	call kernel_main
 
 	cli
@@ -37,4 +43,62 @@ _start:
 	jmp 1b
 .size _start, . - _start
 
+# Application processor startup. The kernel copies this block to
+# AP_TRAMPOLINE, a page below 1 MB, and the startup IPI enters it there in
+# real mode, so every address is taken relative to that copy. The kernel
+# fills in the data block before each start. Firmware jumps here too when
+# waking from S3, as the FACS waking vector points at the same copy.
+.set AP_TRAMPOLINE, 0x8000
+
+.section .rodata
+.global ap_trampoline_start
+.global ap_trampoline_data
+.global ap_trampoline_end
+.code16
+ap_trampoline_start:
+	cli
+	cld
+	xor %ax, %ax
+	mov %ax, %ds
+	lgdtl ap_gdt_ptr - ap_trampoline_start + AP_TRAMPOLINE
+
+	mov %cr0, %eax
+	or $0x1, %eax
+	mov %eax, %cr0
+	ljmpl $0x08, $(ap_protected_mode - ap_trampoline_start + AP_TRAMPOLINE)
+
+.code32
+ap_protected_mode:
+	mov $0x10, %ax
+	mov %ax, %ds
+	mov %ax, %es
+	mov %ax, %fs
+	mov %ax, %gs
+	mov %ax, %ss
+	mov ap_stack - ap_trampoline_start + AP_TRAMPOLINE, %esp
+	xor %ebp, %ebp
+
+	# entry(cpu), which does not return
+	pushl ap_cpu - ap_trampoline_start + AP_TRAMPOLINE
+	mov ap_entry - ap_trampoline_start + AP_TRAMPOLINE, %eax
+	call *%eax
+
+	cli
+2:	hlt
+	jmp 2b
+
+.align 4
+ap_trampoline_data:
+	.word 0
+ap_gdt_ptr:
+	.word 0
+	.long 0
+ap_stack:
+	.long 0
+ap_entry:
+	.long 0
+ap_cpu:
+	.long 0
+ap_trampoline_end:
+
 .section .note.GNU-stack, "", @progbits
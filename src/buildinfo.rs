@@ -0,0 +1,83 @@
+// What this kernel was built from. The Makefile passes the commit, build
+// time, compiler and build options to rustc as environment variables; a
+// build without them says "unknown". The same facts are kept as text in the
+// `.buildinfo` section, so `readelf -p .buildinfo bin/myos.bin` tells which
+// build an image is without booting it.
+
+pub const VERSION: &str = "0.1.0";
+/// Short commit hash, with `-dirty` for uncommitted changes
+pub const GIT_HASH: &str = or_unknown(option_env!("HYZEOS_GIT_HASH"));
+/// UTC, as `YYYY-MM-DDTHH:MM:SSZ`
+pub const BUILD_TIME: &str = or_unknown(option_env!("HYZEOS_BUILD_TIME"));
+pub const RUSTC_VERSION: &str = or_unknown(option_env!("HYZEOS_RUSTC_VERSION"));
+/// Build options turned on, e.g. `debug symbols`
+pub const FEATURES: &str = match option_env!("HYZEOS_FEATURES") {
+    Some(features) if !features.is_empty() => features,
+    _ if cfg!(debug_assertions) => "debug",
+    _ => "none",
+};
+
+const fn or_unknown(value: Option<&'static str>) -> &'static str {
+    match value {
+        Some(value) if !value.is_empty() => value,
+        _ => "unknown",
+    }
+}
+
+const RECORD_LEN: usize = 256;
+const RECORD_MAGIC: &str = "HyzeOS build info";
+
+#[used]
+#[link_section = ".buildinfo"]
+static RECORD: [u8; RECORD_LEN] = record();
+
+/// `RECORD_MAGIC` and one `key=value` per field, each NUL-terminated, cut
+/// short if they don't fit
+const fn record() -> [u8; RECORD_LEN] {
+    let fields: [(&str, &str); 6] = [
+        ("", RECORD_MAGIC),
+        ("version=", VERSION),
+        ("git=", GIT_HASH),
+        ("built=", BUILD_TIME),
+        ("rustc=", RUSTC_VERSION),
+        ("features=", FEATURES),
+    ];
+    let mut out = [0u8; RECORD_LEN];
+    let mut len = 0;
+    let mut i = 0;
+    while i < fields.len() {
+        let mut part = 0;
+        while part < 2 {
+            let bytes = if part == 0 { fields[i].0.as_bytes() } else { fields[i].1.as_bytes() };
+            let mut j = 0;
+            // Keep the last byte for a terminating NUL
+            while j < bytes.len() && len < RECORD_LEN - 1 {
+                out[len] = bytes[j];
+                len += 1;
+                j += 1;
+            }
+            part += 1;
+        }
+        if len < RECORD_LEN - 1 {
+            len += 1;
+        }
+        i += 1;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn record_holds_every_field() {
+        let mut fields = RECORD.split(|&b| b == 0);
+        assert_eq!(fields.next(), Some(RECORD_MAGIC.as_bytes()));
+        assert_eq!(fields.next(), Some(&b"version=0.1.0"[..]));
+        assert!(fields.next().unwrap().starts_with(b"git="));
+        assert!(fields.next().unwrap().starts_with(b"built="));
+        assert!(fields.next().unwrap().starts_with(b"rustc="));
+        assert!(fields.next().unwrap().starts_with(b"features="));
+    }
+}
@@ -0,0 +1,97 @@
+// Capabilities: what a user program may ask the kernel for. The shell starts
+// with all of them; `caps drop` takes some away for good, and `run -c`
+// starts a program or script with fewer. A program can never get more than
+// the shell that ran it. System calls check the running program's set on
+// entry and fail with EPERM when a capability is missing.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Caps(u32);
+
+const NAMES: [(&str, Caps); 3] = [("files", Caps::FILES), ("net", Caps::NET), ("rawio", Caps::RAW_IO)];
+
+impl Caps {
+    pub const NONE: Caps = Caps(0);
+    /// Open files
+    pub const FILES: Caps = Caps(1 << 0);
+    /// Sockets, once there is a network stack
+    pub const NET: Caps = Caps(1 << 1);
+    /// Port I/O and physical memory, once programs can ask for them
+    pub const RAW_IO: Caps = Caps(1 << 2);
+    pub const ALL: Caps = Caps(Caps::FILES.0 | Caps::NET.0 | Caps::RAW_IO.0);
+
+    pub fn contains(self, other: Caps) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The capabilities in both sets
+    pub fn intersect(self, other: Caps) -> Caps {
+        Caps(self.0 & other.0)
+    }
+
+    pub fn without(self, other: Caps) -> Caps {
+        Caps(self.0 & !other.0)
+    }
+
+    /// A comma-separated list like `files,net`, or `all` or `none`
+    pub fn parse(list: &[u8]) -> Result<Caps, &'static str> {
+        match list {
+            b"all" => return Ok(Caps::ALL),
+            b"none" => return Ok(Caps::NONE),
+            _ => {}
+        }
+        list.split(|&b| b == b',').try_fold(Caps::NONE, |caps, name| {
+            NAMES.iter()
+                .find(|(known, _)| known.as_bytes() == name)
+                .map(|&(_, cap)| Caps(caps.0 | cap.0))
+                .ok_or("Unknown capability (files, net, rawio, all or none)")
+        })
+    }
+
+    /// Name of each capability and whether this set has it
+    pub fn iter(self) -> impl Iterator<Item = (&'static str, bool)> {
+        NAMES.iter().map(move |&(name, cap)| (name, self.contains(cap)))
+    }
+}
+
+static mut SHELL: Caps = Caps::ALL;
+
+/// What the shell, and so anything it runs, may use
+pub fn shell() -> Caps {
+    unsafe { SHELL }
+}
+
+/// Narrow the shell's set; there is no way to widen it again
+pub fn restrict_shell(caps: Caps) {
+    unsafe {
+        SHELL = SHELL.intersect(caps);
+    }
+}
+
+/// Run `f`, e.g. a script, with the shell's set narrowed to `caps`, then put
+/// the set back as it was
+pub fn with_shell<R>(caps: Caps, f: impl FnOnce() -> R) -> R {
+    let saved = shell();
+    restrict_shell(caps);
+    let result = f();
+    unsafe {
+        SHELL = saved;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn parse_and_reduce() {
+        assert_eq!(Caps::parse(b"files,rawio"), Ok(Caps(Caps::FILES.0 | Caps::RAW_IO.0)));
+        assert_eq!(Caps::parse(b"none"), Ok(Caps::NONE));
+        assert!(Caps::parse(b"files,root").is_err());
+
+        let reduced = Caps::ALL.without(Caps::NET).intersect(Caps::parse(b"net,files").unwrap());
+        assert_eq!(reduced, Caps::FILES);
+        assert!(!reduced.contains(Caps::NET));
+        assert_eq!(reduced.iter().filter(|&(_, has)| has).count(), 1);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::vga_colors::Color;
+use crate::sink::Sink;
+use crate::writer::Writer;
+
+const FULL_BLOCK: u8 = 0xDB;
+const LEFT_HALF_BLOCK: u8 = 0xDD;
+const LOWER_HALF_BLOCK: u8 = 0xDC;
+const LIGHT_SHADE: u8 = 0xB0;
+
+/// Sparkline levels from empty to full, using CP437 glyphs
+const SPARK_LEVELS: [u8; 4] = [b' ', b'_', LOWER_HALF_BLOCK, FULL_BLOCK];
+
+/// `value` as a percentage of `max`, clamped to 100
+pub fn percent(value: u64, max: u64) -> u32 {
+    if max == 0 {
+        return 0;
+    }
+    (value.min(max) * 100 / max) as u32
+}
+
+/// Horizontal bar `width` cells wide, filled in half-cell steps, with the
+/// unfilled part shaded
+pub fn bar(writer: &mut dyn Sink, value: u64, max: u64, width: usize, color: Color) {
+    let halves = if max == 0 { 0 } else { (value.min(max) * width as u64 * 2 / max) as usize };
+
+    writer.set_color(color, Color::Black);
+    for _ in 0..halves / 2 {
+        writer.write_byte(FULL_BLOCK);
+    }
+    if halves % 2 == 1 {
+        writer.write_byte(LEFT_HALF_BLOCK);
+    }
+
+    writer.set_color(Color::DarkGray, Color::Black);
+    for _ in (halves + 1) / 2..width {
+        writer.write_byte(LIGHT_SHADE);
+    }
+    writer.set_color(Color::White, Color::Black);
+}
+
+/// Pick a bar color from how full it is
+pub fn level_color(percent: u32) -> Color {
+    match percent {
+        0..=59 => Color::LightGreen,
+        60..=84 => Color::Yellow,
+        _ => Color::LightRed,
+    }
+}
+
+/// One-line sparkline with one cell per value; `max` of 0 scales to the
+/// largest value
+pub fn sparkline(writer: &mut dyn Sink, values: &[u32], max: u32, color: Color) {
+    let max = if max == 0 { values.iter().copied().max().unwrap_or(0) } else { max };
+    writer.set_color(color, Color::Black);
+    for &value in values {
+        let level = if max == 0 {
+            0
+        } else {
+            (value.min(max) as usize * (SPARK_LEVELS.len() - 1) + max as usize / 2) / max as usize
+        };
+        writer.write_byte(SPARK_LEVELS[level]);
+    }
+    writer.set_color(Color::White, Color::Black);
+}
+
+/// Multi-row column chart with its top-left corner at (col, row); each
+/// row holds two steps using half blocks
+pub fn column_chart(writer: &mut Writer, col: usize, row: usize, height: usize, values: &[u32], max: u32, color: Color) {
+    let max = if max == 0 { values.iter().copied().max().unwrap_or(1).max(1) } else { max };
+    writer.set_color(color, Color::Black);
+    for line in 0..height {
+        writer.set_position(col, row + line);
+        // Half-steps from the bottom of the chart to the bottom of this line
+        let floor = (height - 1 - line) * 2;
+        for &value in values {
+            let halves = value.min(max) as usize * height * 2 / max as usize;
+            let glyph = match halves.saturating_sub(floor) {
+                0 => b' ',
+                1 => LOWER_HALF_BLOCK,
+                _ => FULL_BLOCK,
+            };
+            writer.write_byte(glyph);
+        }
+    }
+    writer.set_color(Color::White, Color::Black);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn percent_clamps_and_handles_zero_max() {
+        assert_eq!(percent(1, 4), 25);
+        assert_eq!(percent(9, 4), 100);
+        assert_eq!(percent(3, 0), 0);
+    }
+}
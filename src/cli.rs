@@ -1,57 +1,194 @@
+use core::cell::Cell;
+use core::fmt::Display;
+use core::time::Duration as CoreDuration;
+
 use crate::writer::Writer;
-use crate::keyboard::Keyboard;
+use crate::sink::{Sink, Capture};
+use crate::keyboard::{self, Keyboard};
 use crate::vga_colors::Color;
 use crate::idt;
 use crate::snake::SnakeGame;
+use crate::tetris::TetrisGame;
+use crate::life::LifeGame;
+use crate::matrix::MatrixRain;
+use crate::digital_clock::DigitalClock;
+use crate::kbtest::KeyboardTest;
+use crate::serial_terminal::SerialTerminal;
+use crate::monitor::Monitor;
+use crate::mandelbrot::Mandelbrot;
 use crate::video_player::VideoPlayer;
 use crate::bad_apple_data::{FRAME_COUNT as BAD_APPLE_FRAME_COUNT, FRAME_WIDTH as BAD_APPLE_FRAME_WIDTH, FRAME_HEIGHT as BAD_APPLE_FRAME_HEIGHT, TARGET_FPS as BAD_APPLE_TARGET_FPS, FRAMES as BAD_APPLE_FRAMES};
 use crate::RAHH_data::{FRAME_COUNT as RAHH_FRAME_COUNT, FRAME_WIDTH as RAHH_FRAME_WIDTH, FRAME_HEIGHT as RAHH_FRAME_HEIGHT, TARGET_FPS as RAHH_TARGET_FPS, FRAMES as RAHH_FRAMES};
-use crate::filesystem::{get_filesystem, FileEntry};
+use crate::filesystem::{get_filesystem, FileEntry, FileSystem, MAX_FILE_SIZE};
 use crate::editor::Editor;
+use crate::pager::Pager;
+use crate::file_manager::FileManager;
 
 
-use crate::hex_fetch::HexFetch;
+use crate::hex_fetch::{HexFetch, Layout};
 
 use crate::graphics::graphics;
+use crate::power;
+use crate::sci;
+use crate::suspend;
+use crate::driver::{self, Failure};
+use crate::speaker;
+use crate::framebuffer;
+use crate::font;
+use crate::buildinfo;
+use crate::at;
+use crate::watchdog;
+use crate::clock;
+use crate::fmt::{Duration as FmtDuration, Size, Thousands};
+use crate::tsc::Instant;
+use crate::config::{self, Saver};
+use crate::hwprobe;
+use crate::dhcp;
+use crate::http;
+use crate::lock;
+use crate::signal;
+use crate::httpd;
+use crate::net::{self, Ipv4Addr, UdpSocket};
+use crate::netdev;
+use crate::multiboot;
+use crate::image::Image;
+use crate::ansi;
+use crate::elf::ElfFile;
+use crate::usermode::{self, Exit};
+use crate::chart;
+use crate::screenshot;
+use crate::recorder;
+use crate::latency;
+use crate::crash;
+use crate::heap::{self, Record};
+use crate::metrics::{self, Counter};
+use crate::idle::{self, PerfHint, PerfControl};
+use crate::faulttest;
+use crate::aml::{self, Resource, PowerButton};
+use crate::theme;
+use crate::script::{Line, Blocks};
+use crate::env;
+use crate::glob;
+use crate::text::{self, Counts, SedScript, SortOptions};
+use crate::prompt;
+use crate::history;
+use crate::users;
+use crate::rng;
+use crate::caps::{self, Caps};
 
 const MAX_COMMAND_LEN: usize = 80;
+/// A command continued over several lines, joined
+const MAX_INPUT_LEN: usize = env::MAX_LINE_LEN;
+/// Shown before each continued line unless `PS2` says otherwise
+const DEFAULT_PS2: &[u8] = b"> ";
+const SCREEN_WIDTH: usize = 80;
+const MAX_HEXDUMP_LEN: usize = 512;
+const MAX_PIPELINE: usize = 4;
+/// Most pipelines chained with `&&` and `||` on one line
+const MAX_LIST: usize = 8;
+const MAX_SCRIPT_DEPTH: usize = 4;
+const BOOT_SCRIPT: &[u8] = b"/scripts/boot.sh";
+const PROFILE_SCRIPT: &[u8] = b"/etc/profile.hsh";
+/// In the `HOME` directory
+const RC_SCRIPT: &[u8] = b"/.hshrc";
+
+/// Commands that draw on the screen or take it over; their output can't be
+/// piped or redirected, but they can end a pipeline to read its output
+const SCREEN_COMMANDS: [&[u8]; 28] = [
+    b"clear", b"monitor", b"play", b"snake", b"tetris", b"life", b"matrix", b"mandel", b"view", b"ansi",
+    b"run", b"screenshot", b"record", b"replay", b"edit", b"hfm", b"theme", b"fbcon", b"faulttest",
+    b"less", b"more", b"lock", b"login", b"useradd", b"passwd", b"clock", b"kbtest", b"term",
+];
+
+/// Output of the previous pipeline stage and the one being captured. A
+/// redirect can't save more than a file holds anyway.
+static mut PIPE_BUFFERS: [[u8; MAX_FILE_SIZE]; 2] = [[0; MAX_FILE_SIZE]; 2];
+/// A whole HTTP response for `wget`: a file's worth of body and its headers
+static mut WGET_BUFFER: [u8; MAX_FILE_SIZE + 1024] = [0; MAX_FILE_SIZE + 1024];
+/// The new contents of a file `sed -i` is editing
+static mut SED_BUFFER: [u8; MAX_FILE_SIZE] = [0; MAX_FILE_SIZE];
 
 pub struct CLI {
-    buffer: [u8; MAX_COMMAND_LEN],
+    buffer: [u8; MAX_INPUT_LEN],
     buffer_len: usize,
+    /// Where the line being typed starts, after any it continues
+    line_start: usize,
     shift_pressed: bool,
+    ctrl_pressed: bool,
+    /// What the last Ctrl+R search looked for, for Ctrl+R on an empty one
+    last_search: [u8; MAX_COMMAND_LEN],
+    last_search_len: usize,
+    /// Scripts running inside scripts
+    script_depth: Cell<usize>,
 }
 
 impl CLI {
     pub const fn new() -> Self {
         Self {
-            buffer: [0; MAX_COMMAND_LEN],
+            buffer: [0; MAX_INPUT_LEN],
             buffer_len: 0,
+            line_start: 0,
             shift_pressed: false,
+            ctrl_pressed: false,
+            last_search: [0; MAX_COMMAND_LEN],
+            last_search_len: 0,
+            script_depth: Cell::new(0),
         }
     }
 
     pub fn show_prompt(&self, writer: &mut Writer) {
-        writer.set_color(Color::LightGreen, Color::Black);
-        writer.write_str("HyzeOS> ");
-        writer.set_color(Color::White, Color::Black);
+        prompt::render(env::get().get(b"PROMPT").unwrap_or(env::DEFAULT_PROMPT), writer);
     }
 
     pub fn run(&mut self, writer: &mut Writer) -> ! {
         self.show_prompt(writer);
+        let mut last_key_ms = clock::uptime_ms();
 
         loop {
-            let scancode = match idt::get_scancode() {
+            watchdog::feed();
+            if let Some(event) = sci::take_event() {
+                self.handle_acpi_event(event, writer);
+            }
+            net::poll();
+            dhcp::poll();
+            httpd::poll();
+            if let Some(job) = at::take_due() {
+                self.run_job(&job, writer);
+            }
+            if signal::take_interrupt() {
+                // Ctrl+C at the prompt drops what was typed, continued
+                // lines and all
+                writer.write_str("^C\n");
+                self.buffer_len = 0;
+                self.line_start = 0;
+                env::get().set_status(ShellError::Interrupted.status());
+                self.show_prompt(writer);
+            }
+            if signal::take_suspend() {
+                writer.write_str("^Z\nNo job control yet, so nothing to suspend\n");
+                self.redraw_input(writer);
+            }
+
+            let mut scancode = match idt::get_scancode() {
                 Some(sc) => sc,
                 None => {
+                    let timeout_secs = config::get().screensaver_secs;
+                    if timeout_secs > 0 && clock::uptime_ms() - last_key_ms >= timeout_secs as u64 * 1000 {
+                        lock::lock(writer, config::get().saver);
+                        last_key_ms = clock::uptime_ms();
+                        continue;
+                    }
                     idt::wait_for_interrupt();
                     continue;
                 }
             };
+            last_key_ms = clock::uptime_ms();
 
             if scancode & 0x80 != 0 {
                 if scancode == 0xAA || scancode == 0xB6 {
                     self.shift_pressed = false;
+                } else if scancode == 0x9D {
+                    self.ctrl_pressed = false;
                 }
                 continue;
             }
@@ -61,68 +198,569 @@ impl CLI {
                 continue;
             }
 
+            if scancode == 0x1D {
+                self.ctrl_pressed = true;
+                continue;
+            }
+
+            // Ctrl+R, only on a command's first line as it brings back
+            // whole commands
+            if self.ctrl_pressed && scancode == 0x13 && self.line_start == 0 {
+                if !self.search_history(writer) {
+                    continue;
+                }
+                // Enter on a match runs it like Enter on a typed line
+                scancode = 0x1C;
+            }
+
             if scancode == 0x1C {
                 writer.write_byte(b'\n');
+                if self.continue_line() {
+                    self.redraw_input(writer);
+                    continue;
+                }
                 self.execute_command(writer);
                 self.buffer_len = 0;
+                self.line_start = 0;
+                // The command may have taken the key up along with the Ctrl+C
+                self.ctrl_pressed = false;
+                self.show_prompt(writer);
+                continue;
+            }
+
+            // Esc gives up on a command left unfinished
+            if scancode == 0x01 && self.line_start > 0 {
+                writer.write_byte(b'\n');
+                self.buffer_len = 0;
+                self.line_start = 0;
                 self.show_prompt(writer);
                 continue;
             }
 
             if scancode == 0x0E {
-                if self.buffer_len > 0 {
+                if self.buffer_len > self.line_start {
                     self.buffer_len -= 1;
                     self.delete_char(writer);
                 }
                 continue;
             }
 
-            if let Some(c) = Keyboard::scancode_to_char(scancode, self.shift_pressed) {
-                if self.buffer_len < MAX_COMMAND_LEN {
-                    self.buffer[self.buffer_len] = c as u8;
+            for c in Keyboard::scancode_to_bytes(scancode, self.shift_pressed) {
+                if self.buffer_len - self.line_start < MAX_COMMAND_LEN && self.buffer_len < MAX_INPUT_LEN {
+                    self.buffer[self.buffer_len] = c;
                     self.buffer_len += 1;
-                    writer.write_byte(c as u8);
+                    writer.write_byte(c);
                 }
             }
         }
     }
 
-    fn delete_char(&self, writer: &mut Writer) {
-        unsafe {
-            let vga = 0xb8000 as *mut u8;
-            if writer.get_col() > 0 {
-                let col = writer.get_col() - 1;
-                let row = writer.get_row();
-                let offset = (row * 80 + col) * 2;
-                *vga.add(offset) = b' ';
-                writer.set_position(col, row);
+    /// Put back the prompt and whatever was being typed on this line
+    fn redraw_input(&self, writer: &mut Writer) {
+        if self.line_start > 0 {
+            prompt::render(env::get().get(b"PS2").unwrap_or(DEFAULT_PS2), writer);
+        } else {
+            self.show_prompt(writer);
+        }
+        writer.write_bytes(&self.buffer[self.line_start..self.buffer_len]);
+    }
+
+    /// If the command typed so far goes on, get ready for its next line:
+    /// a trailing `\` is dropped and the lines are joined as they are, while
+    /// an open quote or bracket joins them with a space
+    fn continue_line(&mut self) -> bool {
+        let line = &self.buffer[..self.buffer_len];
+        if line.last() == Some(&b'\\') {
+            self.buffer_len -= 1;
+        } else if is_unfinished(line) && self.buffer_len < MAX_INPUT_LEN {
+            self.buffer[self.buffer_len] = b' ';
+            self.buffer_len += 1;
+        } else {
+            return false;
+        }
+        self.line_start = self.buffer_len;
+        true
+    }
+
+    /// Ctrl+R: look back through the history for the newest command that
+    /// contains what is typed, showing it in place of the line. Ctrl+R
+    /// again looks further back, or with nothing typed yet repeats the
+    /// last search. Enter leaves the match in the buffer and returns true
+    /// to run it; Tab or an arrow key leaves it there to edit, and Esc or
+    /// Ctrl+G puts back the line as it was.
+    fn search_history(&mut self, writer: &mut Writer) -> bool {
+        let history = history::get();
+        // Screen cells counted from the top left: where the line starts
+        // and how far the last thing drawn went
+        let mut origin = (writer.get_row() * SCREEN_WIDTH + writer.get_col()).saturating_sub(self.buffer_len);
+        let mut end = origin + self.buffer_len;
+        let mut pattern = [0u8; MAX_COMMAND_LEN];
+        let mut pattern_len = 0;
+        let mut found: Option<(usize, &[u8])> = None;
+        let mut failed = false;
+        let mut extended = false;
+
+        loop {
+            let label: &[u8] = if failed { b"(failed reverse-i-search)`" } else { b"(reverse-i-search)`" };
+            let shown = found.map_or(&self.buffer[..self.buffer_len], |(_, line)| line);
+            origin = redraw_at(writer, origin, &mut end, &[label, &pattern[..pattern_len], b"': ", shown]);
+
+            let scancode = loop {
+                match idt::get_scancode() {
+                    Some(scancode) => break scancode,
+                    // Ctrl+C gives up like Esc, then the prompt drops the line
+                    None if signal::interrupted() => break 0x01,
+                    None => idt::wait_for_interrupt(),
+                }
+            };
+            match scancode {
+                0xAA | 0xB6 => self.shift_pressed = false,
+                0x9D => self.ctrl_pressed = false,
+                0x2A | 0x36 => self.shift_pressed = true,
+                0x1D => self.ctrl_pressed = true,
+                0xE0 => extended = true,
+                _ if scancode & 0x80 != 0 => extended = false,
+                // Ctrl+R: the next older match
+                0x13 if self.ctrl_pressed => {
+                    if pattern_len == 0 {
+                        pattern_len = self.last_search_len;
+                        pattern[..pattern_len].copy_from_slice(&self.last_search[..pattern_len]);
+                    }
+                    let before = found.map_or(usize::MAX, |(number, _)| number);
+                    match history.search(&pattern[..pattern_len], before) {
+                        Some(older) => found = Some(older),
+                        None => failed = true,
+                    }
+                }
+                // Esc or Ctrl+G
+                0x01 | 0x22 if scancode == 0x01 || self.ctrl_pressed => {
+                    redraw_at(writer, origin, &mut end, &[&self.buffer[..self.buffer_len]]);
+                    return false;
+                }
+                // Backspace: search again for what is left, from the newest
+                0x0E => {
+                    pattern_len = pattern_len.saturating_sub(1);
+                    found = history.search(&pattern[..pattern_len], usize::MAX);
+                    failed = pattern_len > 0 && found.is_none();
+                }
+                _ => {
+                    let typed = if self.ctrl_pressed || extended {
+                        None
+                    } else {
+                        Keyboard::scancode_to_byte(scancode, self.shift_pressed)
+                    };
+                    match typed {
+                        Some(c) => {
+                            if pattern_len < MAX_COMMAND_LEN {
+                                pattern[pattern_len] = c;
+                                pattern_len += 1;
+                            }
+                            // The match stays while it still matches
+                            let before = found.map_or(usize::MAX, |(number, _)| number + 1);
+                            match history.search(&pattern[..pattern_len], before) {
+                                Some(line) => found = Some(line),
+                                None => failed = true,
+                            }
+                        }
+                        // Enter, Tab, an arrow or any other key takes the match
+                        _ => {
+                            if pattern_len > 0 {
+                                self.last_search[..pattern_len].copy_from_slice(&pattern[..pattern_len]);
+                                self.last_search_len = pattern_len;
+                            }
+                            if let Some((_, line)) = found {
+                                self.buffer[..line.len()].copy_from_slice(line);
+                                self.buffer_len = line.len();
+                            }
+                            redraw_at(writer, origin, &mut end, &[&self.buffer[..self.buffer_len]]);
+                            return scancode == 0x1C;
+                        }
+                    }
+                }
+            }
+            if scancode & 0x80 == 0 && scancode != 0xE0 {
+                extended = false;
             }
         }
     }
 
+    fn delete_char(&self, writer: &mut Writer) {
+        if writer.get_col() > 0 {
+            let col = writer.get_col() - 1;
+            let row = writer.get_row();
+            let attribute = Writer::cell(col, row) & 0xFF00;
+            Writer::set_cell(col, row, attribute | b' ' as u16);
+            writer.set_position(col, row);
+        }
+    }
+
+    /// Run the typed line, or the history entry `!n` or `!!` names, and add
+    /// it to the history
     fn execute_command(&self, writer: &mut Writer) {
         if self.buffer_len == 0 {
             return;
         }
+        let mut recalled = [0u8; MAX_INPUT_LEN];
+        let mut line = &self.buffer[..self.buffer_len];
+        if line[0] == b'!' {
+            match history::get().recall(&line[1..]) {
+                Ok(command) => {
+                    recalled[..command.len()].copy_from_slice(command);
+                    line = &recalled[..command.len()];
+                    writer.write_bytes(line);
+                    writer.write_byte(b'\n');
+                }
+                Err(e) => {
+                    self.error(writer, e);
+                    return;
+                }
+            }
+        }
+
+        history::get().push(line, history::limit());
+        if let Err(e) = history::save() {
+            log_debug!("History: {}", e);
+        }
+        signal::clear();
+        // Its status is kept in `$?`, which the prompt shows
+        let _ = self.execute_line(line, writer);
+    }
+
+    /// Run a command line and keep its status for `$?`
+    fn execute_line(&self, line: &[u8], writer: &mut Writer) -> CommandResult {
+        // Their command lines, `&&` and `||` included, are the rest of the
+        // line and are expanded each time they run
+        let result = if line == b"time" || line.starts_with(b"time ") {
+            self.cmd_time(trim(&line[4..]), writer)
+        } else if line == b"watch" || line.starts_with(b"watch ") {
+            self.cmd_watch(trim(&line[5..]), writer)
+        } else if line == b"at" || line.starts_with(b"at ") {
+            self.cmd_at(trim(&line[2..]), writer)
+        } else {
+            match List::parse(line) {
+                Ok(list) => return self.run_list(&list, writer),
+                Err(e) => {
+                    self.error(writer, e);
+                    Err(ShellError::Syntax)
+                }
+            }
+        };
+        let result = if signal::interrupted() { Err(ShellError::Interrupted) } else { result };
+        env::get().set_status(exit_status(result));
+        result
+    }
+
+    /// Run each pipeline of `list` whose connector lets it, expanding it
+    /// only then so `$?` is the status of the one before. Ctrl+C stops the
+    /// whole list.
+    fn run_list(&self, list: &List, writer: &mut Writer) -> CommandResult {
+        let mut result = Ok(ExitCode::SUCCESS);
+        for &(connector, pipeline) in list.items() {
+            let succeeded = exit_status(result) == 0;
+            let runs = match connector {
+                Connector::First => true,
+                Connector::And => succeeded,
+                Connector::Or => !succeeded,
+            };
+            if runs {
+                result = self.run_pipeline(pipeline, writer);
+                if signal::interrupted() {
+                    result = Err(ShellError::Interrupted);
+                }
+                env::get().set_status(exit_status(result));
+            }
+            if result == Err(ShellError::Interrupted) {
+                break;
+            }
+        }
+        result
+    }
+
+    /// Run one pipeline, with its variables, pipes and redirect. Its status
+    /// is the last command's.
+    fn run_pipeline(&self, line: &[u8], writer: &mut Writer) -> CommandResult {
+        let mut expanded = [0u8; env::MAX_LINE_LEN];
+        let line = match env::get().expand(line, &mut expanded) {
+            Ok(len) => &expanded[..len],
+            Err(e) => {
+                self.error(writer, e);
+                return Err(ShellError::Syntax);
+            }
+        };
+        let mut globbed = [0u8; env::MAX_LINE_LEN];
+        let line = match glob::expand(line, &mut globbed) {
+            Ok(len) => &globbed[..len],
+            Err(e) => {
+                self.error(writer, e);
+                return Err(ShellError::Syntax);
+            }
+        };
+        let pipeline = match Pipeline::parse(line) {
+            Ok(pipeline) => pipeline,
+            Err(e) => {
+                self.error(writer, e);
+                return Err(ShellError::Syntax);
+            }
+        };
+        let stages = pipeline.stages();
+
+        if stages.len() == 1 && pipeline.redirect.is_none() {
+            return if is_screen_command(stages[0]) {
+                self.run_screen_command(stages[0], &[], writer)
+            } else {
+                self.run_command(stages[0], &[], writer)
+            };
+        }
+        let last = stages.len() - 1;
+        let piped = if pipeline.redirect.is_none() { &stages[..last] } else { stages };
+        if let Some(&cmd) = piped.iter().find(|&&cmd| is_screen_command(cmd)) {
+            writer.set_color(Color::Red, Color::Black);
+            writer.write_str("Error: ");
+            writer.write_bytes(next_arg(cmd).0);
+            writer.write_str(" uses the whole screen, its output can't be piped\n");
+            writer.set_color(Color::White, Color::Black);
+            return Err(ShellError::Syntax);
+        }
+
+        // Each stage writes into one buffer while reading the other
+        let buffers = unsafe { &mut PIPE_BUFFERS };
+        let mut output_len = 0;
+        let mut result = Ok(ExitCode::SUCCESS);
+        for (i, &cmd) in stages.iter().enumerate() {
+            let (even, odd) = buffers.split_at_mut(1);
+            let (input, output) = if i % 2 == 0 { (&odd[0], &mut even[0]) } else { (&even[0], &mut odd[0]) };
+            let input = &input[..output_len];
+
+            if i == last && pipeline.redirect.is_none() {
+                return if is_screen_command(cmd) {
+                    self.run_screen_command(cmd, input, writer)
+                } else {
+                    self.run_command(cmd, input, writer)
+                };
+            }
+            let mut capture = Capture::new(output);
+            result = self.run_command(cmd, input, &mut capture);
+            if capture.overflowed() {
+                writer.set_color(Color::Yellow, Color::Black);
+                writer.write_str("Warning: output of ");
+                writer.write_bytes(next_arg(cmd).0);
+                writer.write_str(" cut short\n");
+                writer.set_color(Color::White, Color::Black);
+            }
+            output_len = capture.as_bytes().len();
+        }
 
-        let cmd = &self.buffer[..self.buffer_len];
+        let output = &buffers[(stages.len() - 1) % 2][..output_len];
+        let fs = get_filesystem();
+        let written = match pipeline.redirect {
+            Redirect::Append(name) if fs.read_file(name).is_some() => fs.append_file(name, output),
+            Redirect::Append(name) | Redirect::Truncate(name) => fs.write_file(name, output),
+            Redirect::None => Ok(()),
+        };
+        if let Err(e) = written {
+            return Err(self.error(writer, e));
+        }
+        result
+    }
+
+    /// Commands in `SCREEN_COMMANDS`, which draw on the screen directly. Only
+    /// the pager reads piped `input`.
+    fn run_screen_command(&self, cmd: &[u8], input: &[u8], writer: &mut Writer) -> CommandResult {
+        if cmd == b"clear" {
+            writer.clear();
+        } else if cmd == b"monitor" {
+            Monitor::new().run(writer);
+        } else if cmd.starts_with(b"play ") {
+            let video_name = &cmd[5..];
+            if video_name == b"badapple" {
+                let mut player = VideoPlayer::new(BAD_APPLE_FRAMES, BAD_APPLE_FRAME_COUNT, BAD_APPLE_FRAME_WIDTH, BAD_APPLE_FRAME_HEIGHT, BAD_APPLE_TARGET_FPS);
+                player.run();
+                writer.clear();
+                writer.write_str("Video finished!\n");
+            } else if video_name == b"RAHH" {
+                let mut player = VideoPlayer::new(RAHH_FRAMES, RAHH_FRAME_COUNT, RAHH_FRAME_WIDTH, RAHH_FRAME_HEIGHT, RAHH_TARGET_FPS);
+                player.run();
+                writer.clear();
+                writer.write_str("Video finished!\n");
+            } else {
+                writer.set_color(Color::Red, Color::Black);
+                writer.write_str("Unknown video: ");
+                writer.write_bytes(video_name);
+                writer.write_str("\nAvailable videos: badapple\n");
+                writer.set_color(Color::White, Color::Black);
+                return Err(ShellError::Failed);
+            }
+        } else if cmd == b"snake" {
+            let mut game = SnakeGame::new();
+            game.run(writer);
+            writer.clear();
+            writer.write_str("Thanks for playing!\n");
+        } else if cmd == b"tetris" {
+            let mut game = TetrisGame::new();
+            game.run(writer);
+            writer.clear();
+            writer.write_str("Thanks for playing!\n");
+        } else if cmd == b"life" || cmd.starts_with(b"life ") {
+            return self.cmd_life(&cmd[4..], writer);
+        } else if cmd == b"lock" {
+            lock::lock(writer, config::get().saver);
+        } else if cmd.starts_with(b"lock ") {
+            match next_arg(&cmd[5..]) {
+                (b"--life", rest) if trim(rest).is_empty() => lock::lock(writer, Saver::Life),
+                (b"--hash", password) if !trim(password).is_empty() => {
+                    writer.write_str("lockpass=");
+                    writer.write_bytes(&lock::hash_hex(unquote(trim(password))));
+                    writer.write_byte(b'\n');
+                }
+                _ => return Err(self.error(writer, "Usage: lock [--life|--hash <password>]")),
+            }
+        } else if cmd == b"login" {
+            users::login(writer);
+            self.run_profile(writer);
+        } else if cmd.starts_with(b"useradd ") {
+            return self.cmd_useradd(trim(&cmd[8..]), writer);
+        } else if cmd == b"passwd" || cmd.starts_with(b"passwd ") {
+            return self.cmd_passwd(trim(&cmd[6..]), writer);
+        } else if cmd == b"matrix" {
+            if let Err(e) = MatrixRain::new().run(writer) {
+                return Err(self.error(writer, e));
+            }
+        } else if cmd == b"clock" || cmd.starts_with(b"clock ") {
+            match trim(&cmd[5..]) {
+                b"" => return self.show_clock(false, writer),
+                b"-d" => return self.show_clock(true, writer),
+                _ => return Err(self.error(writer, "Usage: clock [-d]")),
+            }
+        } else if cmd == b"kbtest" {
+            KeyboardTest::new().run(writer);
+        } else if cmd == b"term" || cmd.starts_with(b"term ") {
+            return self.cmd_term(&cmd[4..], writer);
+        } else if cmd == b"mandel" {
+            if let Err(e) = Mandelbrot::new().run() {
+                return Err(self.error(writer, e));
+            }
+        } else if cmd.starts_with(b"view ") {
+            return self.cmd_view(&cmd[5..], writer);
+        } else if cmd == b"ansi" || cmd.starts_with(b"ansi ") {
+            return self.cmd_ansi(trim(&cmd[4..]), writer);
+        } else if cmd.starts_with(b"run ") {
+            return self.cmd_run(&cmd[4..], writer);
+        } else if cmd == b"screenshot" || cmd.starts_with(b"screenshot ") {
+            return self.cmd_screenshot(&cmd[10..], writer);
+        } else if cmd == b"record" || cmd.starts_with(b"record ") {
+            return self.cmd_record(&cmd[6..], writer);
+        } else if cmd.starts_with(b"replay ") {
+            return self.cmd_replay(&cmd[7..], writer);
+        } else if cmd.starts_with(b"edit ") {
+            return self.cmd_edit(&cmd[5..], writer);
+        } else if cmd == b"hfm" {
+            let mut manager = FileManager::new();
+            manager.run(writer);
+            writer.set_color(Color::White, Color::Black);
+            writer.clear();
+        } else if cmd == b"theme" || cmd.starts_with(b"theme ") {
+            return self.cmd_theme(&cmd[5..], writer);
+        } else if cmd == b"faulttest" || cmd.starts_with(b"faulttest ") {
+            return self.cmd_faulttest(&cmd[9..], writer);
+        } else if cmd == b"fbcon" || cmd.starts_with(b"fbcon ") {
+            return self.cmd_fbcon(&cmd[5..], writer);
+        } else if cmd == b"less" || cmd.starts_with(b"less ") || cmd == b"more" || cmd.starts_with(b"more ") {
+            return self.cmd_less(trim(&cmd[4..]), input, writer);
+        }
+        Ok(ExitCode::SUCCESS)
+    }
 
+    /// Run one command, reading piped `input` if it takes any
+    fn run_command(&self, cmd: &[u8], input: &[u8], writer: &mut dyn Sink) -> CommandResult {
         if cmd == b"help" {
             writer.set_color(Color::LightCyan, Color::Black);
             writer.write_str("Available commands:\n");
             writer.set_color(Color::White, Color::Black);
             writer.write_str("  help          - Show this help message\n");
             writer.write_str("  clear         - Clear the screen\n");
+            writer.write_str("  theme [name]  - List or switch color themes\n");
+            writer.write_str("  loadkeys [layout] - List or switch keyboard layouts\n");
             writer.write_str("  hello         - Print a greeting\n");
             writer.write_str("  info          - Display system information\n");
+            writer.write_str("  version       - Show the kernel version, commit and build details\n");
+            writer.write_str("  time <command> - Run a command line and show how long it took\n");
+            writer.write_str("  sleep <time>  - Wait, e.g. 500ms, 2s, 1h30m (any key stops)\n");
+            writer.write_str("  watch [-n <time>] <command> - Rerun a command every 2s or <time>\n");
+            writer.write_str("  at [<time> <command> | -r <n>] - Run a command later, or list/remove jobs\n");
+            writer.write_str("  hexfetch      - System summary (--show f,.. --logo name)\n");
             writer.write_str("  echo <text>   - Echo back the text\n");
+            writer.write_str("  random [max]  - Print a random number, below max if given\n");
+            writer.write_str("  uuid          - Print a random UUID\n");
+            writer.write_str("  cmdline       - Show the boot command line\n");
+            writer.write_str("  bootinfo      - Show bootloader memory map and modules\n");
+            writer.write_str("  devices       - List ACPI devices and their resources\n");
+            writer.write_str("  hwprobe [--json] - Report CPU, memory, ACPI, PCI and legacy hardware (JSON to COM1)\n");
+            writer.write_str("  ifconfig [if] - Show network interfaces and their traffic\n");
+            writer.write_str("  arp           - Show the ARP cache\n");
+            writer.write_str("  dhcp [status] - Get an address with DHCP, or show the lease\n");
+            writer.write_str("  ping [-c n] <ip> - Send ICMP echo requests, Ctrl+C stops\n");
+            writer.write_str("  udp send <ip> <port> <text> | udp listen <port> - Exchange UDP datagrams\n");
+            writer.write_str("  wget <url> [file] - Save a page fetched over HTTP, e.g. wget http://10.0.2.2:8000/a.txt\n");
+            writer.write_str("  httpd [start [port]|stop] - Serve status pages over HTTP, or show the server\n");
+            writer.write_str("  free          - Show memory and file system usage\n");
+            writer.write_str("  stats         - Show kernel counters: interrupts, context switches, output\n");
+            writer.write_str("  heap [stats|dump|mark] - Kernel heap usage, live allocations, leak mark\n");
+            writer.write_str("  monitor       - Live CPU, memory and interrupt graphs\n");
             writer.write_str("  snake         - Play the snake game\n");
+            writer.write_str("  tetris        - Play Tetris\n");
+            writer.write_str("  life [file]   - Conway's Game of Life\n");
+            writer.write_str("  matrix        - Matrix digital rain\n");
+            writer.write_str("  clock [-d]    - Full-screen digital clock, with the date for -d\n");
+            writer.write_str("  kbtest        - Show key presses: scancodes, key codes, modifiers, characters\n");
+            writer.write_str("  lock [--life|--hash <password>] - Lock the screen, or print a lockpass= digest\n");
+            writer.write_str("  mandel        - Mandelbrot set explorer\n");
             writer.write_str("  play <video>  - Play a video (badapple)\n");
+            writer.write_str("  term <port> <baud> - Serial terminal on com1-com4, Ctrl+] hangs up\n");
+            writer.write_str("  beep [hz] [ms] - Play a tone on the PC speaker\n");
+            writer.write_str("  fbcon [on WxH|off] - Graphics-mode console\n");
+            writer.write_str("  font [load <file>|default|scale <n>] - Console font\n");
+            writer.write_str("  suspend       - Sleep in S3 until a wake event\n");
+            writer.write_str("  shutdown      - Power off the machine\n");
+            writer.write_str("  reboot        - Restart the machine\n");
+            writer.set_color(Color::LightCyan, Color::Black);
+            writer.write_str("Shell:\n");
+            writer.set_color(Color::White, Color::Black);
+            writer.write_str("  cmd > file    - Save a command's output to a file\n");
+            writer.write_str("  cmd >> file   - Append a command's output to a file\n");
+            writer.write_str("  cmd1 | cmd2   - Feed output to cat or write <file>\n");
+            writer.write_str("  cmd | xargs [-n max] [command] - Run a command with the piped words as arguments\n");
+            writer.write_str("  cmd1 && cmd2  - Run cmd2 only if cmd1 succeeds\n");
+            writer.write_str("  cmd1 || cmd2  - Run cmd2 only if cmd1 fails\n");
+            writer.write_str("  echo $?       - Show the last command's exit status\n");
+            writer.write_str("  Ctrl+C        - Stop the running command, or drop the typed line\n");
+            writer.write_str("  set [NAME value] - List variables (* exported) or set one\n");
+            writer.write_str("  export [NAME[=value]] - Set a variable and mark it exported\n");
+            writer.write_str("  unset <NAME>  - Remove a variable\n");
+            writer.write_str("  $NAME         - Replaced by the variable's value\n");
+            writer.write_str("  history       - List earlier commands (!n runs one, !! the last)\n");
+            writer.write_str("  whoami        - Print the user logged in\n");
+            writer.write_str("  login         - Log in as another user\n");
+            writer.write_str("  useradd <name> - Add a user, with a home in /home (root only)\n");
+            writer.write_str("  passwd [user] - Change your password, or as root anyone's\n");
             writer.set_color(Color::LightCyan, Color::Black);
             writer.write_str("File System:\n");
             writer.set_color(Color::White, Color::Black);
             writer.write_str("  ls            - List files and directories\n");
-            writer.write_str("  cat <file>    - Display file contents\n");
+            writer.write_str("  cat [file]    - Display file contents, or piped input\n");
+            writer.write_str("  wc [-l|-w|-c] [file...] - Count lines, words and bytes\n");
+            writer.write_str("  head [-n N] [file] - First 10 (or N) lines; tail for the last\n");
+            writer.write_str("  sort [-r] [-n] [-u] [file] - Sort lines (reverse, numeric, unique)\n");
+            writer.write_str("  uniq [-c] [-d] [file] - Merge repeated lines (counts, repeated only)\n");
+            writer.write_str("  sed [-i] <script> [file] - Replace text (s/old/new/[g]) or delete lines ([n[,m]]d)\n");
+            writer.write_str("  diff <old> [new] - Show the lines that changed, new being piped input if not given\n");
+            writer.write_str("  view <file>   - Show a BMP or PPM image\n");
+            writer.write_str("  ansi <file>   - Show an ANSI art (.ans) file\n");
+            writer.write_str("  run [-c caps] <file> - Run an ELF program in user mode, or a script\n");
+            writer.write_str("  caps [drop <caps>] - Show or drop what programs may do\n");
+            writer.write_str("  screenshot    - Save the screen to a file (show <file> redraws)\n");
+            writer.write_str("  record <file> - Record console output (record stop saves it)\n");
+            writer.write_str("  replay <file> [speed] - Play a recording back, ESC stops\n");
+            writer.write_str("  less [file]   - Page a file or piped output (also more)\n");
             writer.write_str("  edit <file>   - Edit a file\n");
             writer.write_str("  touch <file>  - Create an empty file\n");
             writer.write_str("  write <file>  - Write text to file\n");
@@ -131,8 +769,18 @@ impl CLI {
             writer.write_str("  rmdir <dir>   - Remove a directory\n");
             writer.write_str("  cd <dir>      - Change directory\n");
             writer.write_str("  pwd           - Print working directory\n");
-        } else if cmd == b"clear" {
-            writer.clear();
+            writer.write_str("  hfm           - Two-pane file manager\n");
+            writer.set_color(Color::LightCyan, Color::Black);
+            writer.write_str("Debugging:\n");
+            writer.set_color(Color::White, Color::Black);
+            writer.write_str("  hexdump <addr> <len>        - Hex+ASCII memory dump\n");
+            writer.write_str("  peek <addr> [b|w|d]         - Read memory\n");
+            writer.write_str("  poke <addr> <val> [b|w|d]   - Write memory\n");
+            writer.write_str("  irqlat [seconds]            - Timer interrupt latency histogram\n");
+            writer.write_str("  idleinfo [hint <mode>]      - Idle state residency, P-state hint\n");
+            writer.write_str("  faulttest [crash de|ud|gp|df] - Check exception handling\n");
+            writer.write_str("  drvtest suspend-resume      - Cycle every driver, check it comes back\n");
+            writer.write_str("  watchdog [<time>|off]       - Panic if the kernel hangs this long\n");
         } else if cmd == b"hello" {
             writer.set_color(Color::Yellow, Color::Black);
             writer.write_str("Hello from HyzeOS!\n");
@@ -142,66 +790,152 @@ impl CLI {
             writer.write_str("=== HyzeOS ===\n");
             writer.set_color(Color::White, Color::Black);
             writer.write_str("A simple operating system written in Rust\n");
-            writer.write_str("Version: 0.1.0\n");
+            write!(writer, "Version: {} ({})\n", buildinfo::VERSION, buildinfo::GIT_HASH);
+        } else if cmd == b"sleep" || cmd.starts_with(b"sleep ") {
+            return self.cmd_sleep(trim(&cmd[5..]), writer);
+        } else if cmd == b"version" {
+            return self.cmd_version(writer);
+        } else if cmd == b"cmdline" {
+            writer.write_bytes(config::get().command_line());
+            writer.write_byte(b'\n');
+        } else if cmd == b"devices" {
+            return self.cmd_devices(writer);
+        } else if cmd == b"hwprobe" || cmd.starts_with(b"hwprobe ") {
+            return self.cmd_hwprobe(trim(&cmd[7..]), writer);
+        } else if cmd == b"ifconfig" || cmd.starts_with(b"ifconfig ") {
+            return self.cmd_ifconfig(trim(&cmd[8..]), writer);
+        } else if cmd.starts_with(b"ping ") {
+            return self.cmd_ping(&cmd[5..], writer);
+        } else if cmd == b"dhcp" || cmd.starts_with(b"dhcp ") {
+            return self.cmd_dhcp(trim(&cmd[4..]), writer);
+        } else if cmd == b"arp" {
+            return self.cmd_arp(writer);
+        } else if cmd.starts_with(b"udp ") {
+            return self.cmd_udp(&cmd[4..], writer);
+        } else if cmd.starts_with(b"wget ") {
+            return self.cmd_wget(&cmd[5..], writer);
+        } else if cmd == b"httpd" || cmd.starts_with(b"httpd ") {
+            return self.cmd_httpd(&cmd[5..], writer);
+        } else if cmd == b"bootinfo" {
+            return self.cmd_bootinfo(writer);
+        } else if cmd == b"heap" || cmd.starts_with(b"heap ") {
+            return self.cmd_heap(trim(&cmd[4..]), writer);
+        } else if cmd == b"stats" {
+            return self.cmd_stats(writer);
+        } else if cmd == b"free" {
+            return self.cmd_free(writer);
+        } else if cmd == b"random" || cmd.starts_with(b"random ") {
+            return self.cmd_random(trim(&cmd[6..]), writer);
+        } else if cmd == b"uuid" {
+            writer.write_bytes(&rng::uuid());
+            writer.write_byte(b'\n');
         } else if cmd.starts_with(b"echo ") {
             writer.write_bytes(&cmd[5..]);
             writer.write_byte(b'\n');
-        } else if cmd.starts_with(b"play ") {
-            let video_name = &cmd[5..];
-            if video_name == b"badapple" {
-                let mut player = VideoPlayer::new(BAD_APPLE_FRAMES, BAD_APPLE_FRAME_COUNT, BAD_APPLE_FRAME_WIDTH, BAD_APPLE_FRAME_HEIGHT, BAD_APPLE_TARGET_FPS);
-                player.run();
-                writer.clear();
-                writer.write_str("Video finished!\n");
-            } else if video_name == b"RAHH" {
-                let mut player = VideoPlayer::new(RAHH_FRAMES, RAHH_FRAME_COUNT, RAHH_FRAME_WIDTH, RAHH_FRAME_HEIGHT, RAHH_TARGET_FPS);
-                player.run();
-                writer.clear();
-                writer.write_str("Video finished!\n");
-            } else {
-                writer.set_color(Color::Red, Color::Black);
-                writer.write_str("Unknown video: ");
-                writer.write_bytes(video_name);
-                writer.write_str("\nAvailable videos: badapple\n");
-                writer.set_color(Color::White, Color::Black);
-            }
-        } else if cmd == b"snake" {
-            let mut game = SnakeGame::new();
-            game.run(writer);
-            writer.clear();
-            writer.write_str("Thanks for playing!\n");
         } else if cmd == b"ls" {
-            self.cmd_ls(writer);
+            return self.cmd_ls(writer);
+        } else if cmd == b"cat" {
+            writer.write_bytes(input);
         } else if cmd.starts_with(b"cat ") {
-            self.cmd_cat(&cmd[4..], writer);
-        } else if cmd.starts_with(b"edit ") {
-            self.cmd_edit(&cmd[5..], writer);
+            return self.cmd_cat(&cmd[4..], writer);
+        } else if cmd == b"wc" || cmd.starts_with(b"wc ") {
+            return self.cmd_wc(&cmd[2..], input, writer);
+        } else if cmd == b"head" || cmd.starts_with(b"head ") {
+            return self.cmd_head_tail(&cmd[4..], input, false, writer);
+        } else if cmd == b"tail" || cmd.starts_with(b"tail ") {
+            return self.cmd_head_tail(&cmd[4..], input, true, writer);
+        } else if cmd == b"sort" || cmd.starts_with(b"sort ") {
+            return self.cmd_sort(&cmd[4..], input, writer);
+        } else if cmd == b"uniq" || cmd.starts_with(b"uniq ") {
+            return self.cmd_uniq(&cmd[4..], input, writer);
+        } else if cmd == b"sed" || cmd.starts_with(b"sed ") {
+            return self.cmd_sed(&cmd[3..], input, writer);
+        } else if cmd == b"diff" || cmd.starts_with(b"diff ") {
+            return self.cmd_diff(&cmd[4..], input, writer);
+        } else if cmd == b"xargs" || cmd.starts_with(b"xargs ") {
+            return self.cmd_xargs(trim(&cmd[5..]), input, writer);
         } else if cmd.starts_with(b"touch ") {
-            self.cmd_touch(&cmd[6..], writer);
+            return self.cmd_touch(&cmd[6..], writer);
         } else if cmd.starts_with(b"write ") {
-            self.cmd_write(&cmd[6..], writer);
+            return self.cmd_write(&cmd[6..], input, writer);
         } else if cmd.starts_with(b"rm ") {
-            self.cmd_rm(&cmd[3..], writer);
+            return self.cmd_rm(&cmd[3..], writer);
         } else if cmd.starts_with(b"mkdir ") {
-            self.cmd_mkdir(&cmd[6..], writer);
+            return self.cmd_mkdir(&cmd[6..], writer);
         } else if cmd.starts_with(b"rmdir ") {
-            self.cmd_rmdir(&cmd[6..], writer);
+            return self.cmd_rmdir(&cmd[6..], writer);
         } else if cmd.starts_with(b"cd ") {
-            self.cmd_cd(&cmd[3..], writer);
+            return self.cmd_cd(&cmd[3..], writer);
         } else if cmd == b"pwd" {
-            self.cmd_pwd(writer);
-        } else if cmd == b"hexfetch" {
-           HexFetch::fetch(writer);
+            return self.cmd_pwd(writer);
+        } else if cmd == b"whoami" {
+            writer.write_bytes(users::current());
+            writer.write_byte(b'\n');
+        } else if cmd == b"caps" || cmd.starts_with(b"caps ") {
+            return self.cmd_caps(&cmd[4..], writer);
+        } else if cmd == b"history" {
+            for (number, line) in history::get().iter() {
+                write!(writer, "{:>5}  ", number);
+                writer.write_bytes(line);
+                writer.write_byte(b'\n');
+            }
+        } else if cmd == b"set" || cmd.starts_with(b"set ") {
+            return self.cmd_set(&cmd[3..], false, writer);
+        } else if cmd == b"export" || cmd.starts_with(b"export ") {
+            return self.cmd_set(&cmd[6..], true, writer);
+        } else if cmd.starts_with(b"unset ") {
+            let (name, _) = next_arg(&cmd[6..]);
+            if let Err(e) = env::get().unset(name) {
+                return Err(self.error(writer, e));
+            }
+        } else if cmd == b"loadkeys" || cmd.starts_with(b"loadkeys ") {
+            return self.cmd_loadkeys(&cmd[8..], writer);
+        } else if cmd == b"hexfetch" || cmd.starts_with(b"hexfetch ") {
+            match Layout::parse(&cmd[8..]) {
+                Ok(layout) => HexFetch::fetch(&layout, writer),
+                Err(e) => return Err(self.error(writer, e)),
+            }
+        } else if cmd.starts_with(b"hexdump ") {
+            return self.cmd_hexdump(&cmd[8..], writer);
+        } else if cmd.starts_with(b"peek ") {
+            return self.cmd_peek(&cmd[5..], writer);
+        } else if cmd.starts_with(b"poke ") {
+            return self.cmd_poke(&cmd[5..], writer);
+        } else if cmd == b"irqlat" || cmd.starts_with(b"irqlat ") {
+            return self.cmd_irqlat(&cmd[6..], writer);
+        } else if cmd == b"idleinfo" || cmd.starts_with(b"idleinfo ") {
+            return self.cmd_idleinfo(&cmd[8..], writer);
+        } else if cmd == b"watchdog" || cmd.starts_with(b"watchdog ") {
+            return self.cmd_watchdog(trim(&cmd[8..]), writer);
+        } else if cmd == b"drvtest" || cmd.starts_with(b"drvtest ") {
+            return self.cmd_drvtest(&cmd[7..], writer);
+        } else if cmd == b"beep" || cmd.starts_with(b"beep ") {
+            return self.cmd_beep(&cmd[4..], writer);
+        } else if cmd == b"font" || cmd.starts_with(b"font ") {
+            return self.cmd_font(&cmd[4..], writer);
+        } else if cmd == b"suspend" {
+            return self.cmd_suspend(writer);
+        } else if cmd == b"shutdown" {
+            writer.set_color(Color::Yellow, Color::Black);
+            writer.write_str("Shutting down...\n");
+            power::shutdown();
+        } else if cmd == b"reboot" {
+            writer.set_color(Color::Yellow, Color::Black);
+            writer.write_str("Rebooting...\n");
+            power::reboot();
         } else {
+            speaker::error_beep();
             writer.set_color(Color::Red, Color::Black);
             writer.write_str("Unknown command: ");
             writer.write_bytes(cmd);
             writer.write_str("\nType 'help' for available commands.\n");
             writer.set_color(Color::White, Color::Black);
+            return Err(ShellError::NotFound);
         }
+        Ok(ExitCode::SUCCESS)
     }
 
-    fn cmd_ls(&self, writer: &mut Writer) {
+    fn cmd_ls(&self, writer: &mut dyn Sink) -> CommandResult {
         let fs = get_filesystem();
         let mut has_entries = false;
         
@@ -228,211 +962,2542 @@ impl CLI {
             writer.write_str("(empty directory)\n");
         }
         writer.set_color(Color::White, Color::Black);
+        Ok(ExitCode::SUCCESS)
     }
 
-    fn cmd_cat(&self, filename: &[u8], writer: &mut Writer) {
+    fn cmd_cat(&self, args: &[u8], writer: &mut dyn Sink) -> CommandResult {
         let fs = get_filesystem();
-        match fs.read_file(filename) {
-            Some(content) => {
-                writer.write_bytes(content);
-                if !content.is_empty() && content[content.len() - 1] != b'\n' {
+        let mut result = Ok(ExitCode::SUCCESS);
+        let mut rest = args;
+        loop {
+            let (filename, after) = next_word(rest);
+            if filename.is_empty() {
+                break;
+            }
+            rest = after;
+            match fs.read_path(filename) {
+                Some(content) => {
+                    writer.write_bytes(content);
+                    if !content.is_empty() && content[content.len() - 1] != b'\n' {
+                        writer.write_byte(b'\n');
+                    }
+                }
+                None => {
+                    writer.set_color(Color::Red, Color::Black);
+                    writer.write_str("File not found: ");
+                    writer.write_bytes(filename);
                     writer.write_byte(b'\n');
+                    writer.set_color(Color::White, Color::Black);
+                    result = Err(ShellError::Failed);
                 }
             }
-            None => {
-                writer.set_color(Color::Red, Color::Black);
-                writer.write_str("File not found: ");
-                writer.write_bytes(filename);
-                writer.write_byte(b'\n');
-                writer.set_color(Color::White, Color::Black);
-            }
         }
+        result
     }
 
-    fn cmd_edit(&self, filename: &[u8], writer: &mut Writer) {
-        let mut editor = Editor::new();
-        match editor.open(filename) {
-            Ok(()) => {
-                editor.run(writer);
-                writer.clear();
-            }
-            Err(e) => {
-                writer.set_color(Color::Red, Color::Black);
-                writer.write_str("Error: ");
-                writer.write_str(e);
-                writer.write_byte(b'\n');
-                writer.set_color(Color::White, Color::Black);
-            }
+    /// The file `args` names, or what was piped in if it names none
+    fn text_input<'a>(&self, args: &[u8], input: &'a [u8], writer: &mut dyn Sink) -> Result<&'a [u8], ShellError> {
+        let (file, rest) = next_word(args);
+        if file.is_empty() {
+            return Ok(input);
+        }
+        if !trim(rest).is_empty() {
+            return Err(self.error(writer, "Only one file at a time"));
         }
+        get_filesystem().read_path(file).ok_or_else(|| self.error(writer, "File not found"))
     }
 
-    fn cmd_touch(&self, filename: &[u8], writer: &mut Writer) {
-        let fs = get_filesystem();
-        if fs.file_exists(filename) {
-            writer.write_str("File already exists\n");
-            return;
+    fn cmd_wc(&self, args: &[u8], input: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        let mut rest = args;
+        let (mut lines, mut words, mut bytes) = (false, false, false);
+        loop {
+            match next_arg(rest).0 {
+                b"-l" => lines = true,
+                b"-w" => words = true,
+                b"-c" => bytes = true,
+                _ => break,
+            }
+            rest = next_arg(rest).1;
         }
-        match fs.create_file(filename, b"") {
-            Ok(()) => {
-                writer.set_color(Color::Green, Color::Black);
-                writer.write_str("Created: ");
-                writer.write_bytes(filename);
-                writer.write_byte(b'\n');
-                writer.set_color(Color::White, Color::Black);
+        if !lines && !words && !bytes {
+            lines = true;
+            words = true;
+            bytes = true;
+        }
+        let show = |writer: &mut dyn Sink, counts: Counts, name: &[u8]| {
+            let mut first = true;
+            for &(shown, value) in [(lines, counts.lines), (words, counts.words), (bytes, counts.bytes)].iter() {
+                if shown {
+                    write!(writer, "{}{:>7}", if first { "" } else { " " }, value);
+                    first = false;
+                }
             }
-            Err(e) => {
-                writer.set_color(Color::Red, Color::Black);
-                writer.write_str("Error: ");
-                writer.write_str(e);
-                writer.write_byte(b'\n');
-                writer.set_color(Color::White, Color::Black);
+            if !name.is_empty() {
+                writer.write_byte(b' ');
+                writer.write_bytes(name);
             }
-        }
-    }
+            writer.write_byte(b'\n');
+        };
 
-    fn cmd_write(&self, args: &[u8], writer: &mut Writer) {
-        let mut space_idx = None;
-        for (i, &b) in args.iter().enumerate() {
-            if b == b' ' {
-                space_idx = Some(i);
+        if trim(rest).is_empty() {
+            show(writer, text::count(input), b"");
+            return Ok(ExitCode::SUCCESS);
+        }
+        let mut result = Ok(ExitCode::SUCCESS);
+        let mut total = Counts::default();
+        let mut files = 0;
+        loop {
+            let (file, after) = next_word(rest);
+            if file.is_empty() {
                 break;
             }
+            rest = after;
+            match get_filesystem().read_path(file) {
+                Some(content) => {
+                    let counts = text::count(content);
+                    total.add(counts);
+                    files += 1;
+                    show(writer, counts, file);
+                }
+                None => result = Err(self.error(writer, "File not found")),
+            }
         }
+        if files > 1 {
+            show(writer, total, b"total");
+        }
+        result
+    }
 
-        match space_idx {
-            Some(idx) => {
-                let filename = &args[..idx];
-                let content = &args[idx + 1..];
-                
-                let fs = get_filesystem();
-                match fs.write_file(filename, content) {
-                    Ok(()) => {
-                        writer.set_color(Color::Green, Color::Black);
-                        writer.write_str("Written to: ");
-                        writer.write_bytes(filename);
-                        writer.write_byte(b'\n');
-                        writer.set_color(Color::White, Color::Black);
-                    }
-                    Err(e) => {
-                        writer.set_color(Color::Red, Color::Black);
-                        writer.write_str("Error: ");
-                        writer.write_str(e);
-                        writer.write_byte(b'\n');
-                        writer.set_color(Color::White, Color::Black);
-                    }
+    fn cmd_head_tail(&self, args: &[u8], input: &[u8], tail: bool, writer: &mut dyn Sink) -> CommandResult {
+        let (lines, rest) = match next_arg(args) {
+            (b"-n", rest) => {
+                let (count, rest) = next_arg(rest);
+                match parse_number(count) {
+                    Some(count) => (count as usize, rest),
+                    None => return Err(self.error(writer, "Usage: head|tail [-n lines] [file]")),
                 }
             }
-            None => {
-                writer.set_color(Color::Yellow, Color::Black);
-                writer.write_str("Usage: write <filename> <content>\n");
-                writer.set_color(Color::White, Color::Black);
+            _ => (10, args),
+        };
+        let content = self.text_input(rest, input, writer)?;
+        let part = if tail { text::tail(content, lines) } else { text::head(content, lines) };
+        writer.write_bytes(part);
+        if !part.is_empty() && part[part.len() - 1] != b'\n' {
+            writer.write_byte(b'\n');
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_sort(&self, args: &[u8], input: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        let mut options = SortOptions::default();
+        let mut rest = args;
+        loop {
+            match next_arg(rest).0 {
+                b"-r" => options.reverse = true,
+                b"-n" => options.numeric = true,
+                b"-u" => options.unique = true,
+                _ => break,
             }
+            rest = next_arg(rest).1;
+        }
+        let content = self.text_input(rest, input, writer)?;
+        if let Err(e) = text::sort(content, options, writer) {
+            return Err(self.error(writer, e));
         }
+        Ok(ExitCode::SUCCESS)
     }
 
-    fn cmd_rm(&self, filename: &[u8], writer: &mut Writer) {
+    fn cmd_uniq(&self, args: &[u8], input: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        let (mut count, mut repeated) = (false, false);
+        let mut rest = args;
+        loop {
+            match next_arg(rest).0 {
+                b"-c" => count = true,
+                b"-d" => repeated = true,
+                _ => break,
+            }
+            rest = next_arg(rest).1;
+        }
+        let content = self.text_input(rest, input, writer)?;
+        text::uniq(content, count, repeated, writer);
+        Ok(ExitCode::SUCCESS)
+    }
+
+    /// Run `command`, `echo` if there is none, with the words of `input`
+    /// added on: as many at a time as fit on a command line, or `-n` at most
+    /// `sed [-i] <script> [file]`; `-i` writes the result back to the file
+    fn cmd_sed(&self, args: &[u8], input: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        let (in_place, args) = match next_arg(args) {
+            (b"-i", rest) => (true, rest),
+            _ => (false, args),
+        };
+        let (script, rest) = next_word(args);
+        if script.is_empty() {
+            return Err(self.error(writer, "Usage: sed [-i] <script> [file]"));
+        }
+        let script = match SedScript::parse(script) {
+            Ok(script) => script,
+            Err(e) => return Err(self.error(writer, e)),
+        };
+        if !in_place {
+            let text = self.text_input(rest, input, writer)?;
+            text::sed(text, &script, writer);
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let (file, extra) = next_word(rest);
+        if file.is_empty() || !trim(extra).is_empty() {
+            return Err(self.error(writer, "sed -i needs one file"));
+        }
+        let fs = get_filesystem();
+        let text = match fs.read_path(file) {
+            Some(text) => text,
+            None => return Err(self.error(writer, "File not found")),
+        };
+        let mut edited = Capture::new(unsafe { &mut SED_BUFFER });
+        text::sed(text, &script, &mut edited);
+        if edited.overflowed() {
+            return Err(self.error(writer, "Result is larger than a file can be"));
+        }
+        if let Err(e) = fs.write_path(file, edited.as_bytes()) {
+            return Err(self.error(writer, e));
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    /// `diff <old> <new>`, or `cmd | diff <old>` to compare with the output
+    fn cmd_diff(&self, args: &[u8], input: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        let (old_name, rest) = next_word(args);
+        let (new_name, rest) = next_word(rest);
+        if old_name.is_empty() || !trim(rest).is_empty() {
+            return Err(self.error(writer, "Usage: diff <old> [new]"));
+        }
+        let fs = get_filesystem();
+        let (old, new) = match (fs.read_path(old_name), new_name) {
+            (Some(old), b"") => (old, input),
+            (Some(old), _) => match fs.read_path(new_name) {
+                Some(new) => (old, new),
+                None => return Err(self.error(writer, "File not found")),
+            },
+            (None, _) => return Err(self.error(writer, "File not found")),
+        };
+        let new_name = if new_name.is_empty() { &b"-"[..] } else { new_name };
+        match text::diff(old_name, old, new_name, new, writer) {
+            // A difference is status 1 without an error, as in other shells,
+            // so `diff a b && echo same` works
+            Ok(differ) => Ok(ExitCode(differ as u8)),
+            Err(e) => Err(self.error(writer, e)),
+        }
+    }
+
+    fn cmd_xargs(&self, args: &[u8], input: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        let (max_items, command) = match next_arg(args) {
+            (b"-n", rest) => {
+                let (count, rest) = next_arg(rest);
+                match parse_number(count) {
+                    Some(count) if count > 0 => (count as usize, trim(rest)),
+                    _ => return Err(self.error(writer, "Usage: xargs [-n max] [command]")),
+                }
+            }
+            _ => (usize::MAX, args),
+        };
+        let command = if command.is_empty() { &b"echo"[..] } else { command };
+        if is_screen_command(command) {
+            return Err(self.error(writer, "xargs can't run commands that use the whole screen"));
+        }
+
+        let mut line = [0u8; MAX_INPUT_LEN];
+        let mut items = input;
+        let mut all_succeeded = true;
+        while items.iter().any(|b| !b.is_ascii_whitespace()) && !signal::interrupted() {
+            let (len, rest) = match xargs_batch(command, items, max_items, &mut line) {
+                Ok(batch) => batch,
+                Err(e) => return Err(self.error(writer, e)),
+            };
+            all_succeeded &= exit_status(self.run_command(&line[..len], &[], writer)) == 0;
+            items = rest;
+        }
+        if all_succeeded {
+            Ok(ExitCode::SUCCESS)
+        } else {
+            Err(ShellError::Failed)
+        }
+    }
+
+    fn cmd_term(&self, args: &[u8], writer: &mut Writer) -> CommandResult {
+        let (port, rest) = next_arg(args);
+        let (baud, rest) = next_arg(rest);
+        let baud = match parse_number(baud) {
+            Some(baud) if !port.is_empty() && trim(rest).is_empty() => baud,
+            _ => return Err(self.error(writer, "Usage: term <port> <baud>, e.g. term com2 115200")),
+        };
+        if let Err(e) = SerialTerminal::open(port, baud).and_then(|mut terminal| terminal.run(writer)) {
+            return Err(self.error(writer, e));
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn show_clock(&self, show_date: bool, writer: &mut Writer) -> CommandResult {
+        if let Err(e) = DigitalClock::new(show_date).run(writer) {
+            return Err(self.error(writer, e));
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_view(&self, filename: &[u8], writer: &mut Writer) -> CommandResult {
+        let result = get_filesystem()
+            .read_file(filename)
+            .ok_or("File not found")
+            .and_then(Image::decode)
+            .and_then(|image| {
+                let (fb, saved) = framebuffer::begin_fullscreen(640, 480)?;
+                image.draw_fitted(&fb);
+                idt::flush_buffer();
+                self.wait_for_key();
+                framebuffer::end_fullscreen(saved);
+                Ok(())
+            });
+
+        if let Err(e) = result {
+            return Err(self.error(writer, e));
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    /// Draw an ANSI art file, CP437 characters and escape codes, until a
+    /// key is pressed
+    fn cmd_ansi(&self, filename: &[u8], writer: &mut Writer) -> CommandResult {
+        let filename = unquote(filename);
+        if filename.is_empty() {
+            return Err(self.error(writer, "Usage: ansi <file>"));
+        }
+        let file = match get_filesystem().read_path(filename) {
+            Some(file) => file,
+            None => return Err(self.error(writer, "File not found")),
+        };
+        writer.set_color(Color::LightGray, Color::Black);
+        writer.clear();
+        writer.disable_cursor();
+        writer.write_ansi(&mut ansi::Parser::new(), ansi::art(file));
+        idt::flush_buffer();
+        self.wait_for_key();
+        writer.set_color(Color::White, Color::Black);
+        writer.clear();
+        writer.enable_cursor();
+        Ok(ExitCode::SUCCESS)
+    }
+
+    /// `run [-c caps] <file>`: the program or script gets the shell's
+    /// capabilities, narrowed to `caps` if given
+    fn cmd_run(&self, args: &[u8], writer: &mut Writer) -> CommandResult {
+        let (first, rest) = next_arg(args);
+        let (caps, filename) = if first == b"-c" {
+            let (list, rest) = next_arg(rest);
+            match Caps::parse(list) {
+                Ok(caps) => (caps::shell().intersect(caps), next_arg(rest).0),
+                Err(e) => return Err(self.error(writer, e)),
+            }
+        } else {
+            (caps::shell(), first)
+        };
+        let data = match find_program(filename) {
+            Some(data) => data,
+            None => {
+                self.error(writer, "File not found");
+                return Err(ShellError::NotFound);
+            }
+        };
+        if !ElfFile::is_elf(data) {
+            return caps::with_shell(caps, || self.run_script(data, writer));
+        }
+
+        let result = ElfFile::parse(data)
+            .and_then(|elf| elf.load().map(|()| elf.entry()));
+
+        match result {
+            Ok(entry) => match usermode::run(entry, caps, writer) {
+                Exit::Code(code) => {
+                    if code != 0 {
+                        writer.set_color(Color::Yellow, Color::Black);
+                        writer.write_str("Exited with code ");
+                        if code < 0 {
+                            writer.write_byte(b'-');
+                        }
+                        self.write_number(writer, code.unsigned_abs() as usize);
+                        writer.write_byte(b'\n');
+                        writer.set_color(Color::White, Color::Black);
+                    }
+                    // Only the low byte makes it to `$?`, as elsewhere
+                    Ok(ExitCode(code as u8))
+                }
+                Exit::Fault { vector, eip } => {
+                    writer.set_color(Color::Red, Color::Black);
+                    writer.write_str("Killed: ");
+                    writer.write_str(usermode::exception_name(vector));
+                    writer.write_str(" at 0x");
+                    self.write_hex(writer, eip, 8);
+                    writer.write_byte(b'\n');
+                    writer.set_color(Color::White, Color::Black);
+                    Err(ShellError::Killed(vector as u8))
+                }
+            },
+            Err(e) => Err(self.error(writer, e)),
+        }
+    }
+
+    /// Run each line of a script like a typed command. Its status is the
+    /// last command's.
+    fn run_script(&self, text: &[u8], writer: &mut Writer) -> CommandResult {
+        if self.script_depth.get() == MAX_SCRIPT_DEPTH {
+            return Err(self.error(writer, "Scripts nested too deeply"));
+        }
+        self.script_depth.set(self.script_depth.get() + 1);
+
+        let mut blocks = Blocks::new();
+        let mut result = Ok(());
+        let mut status = Ok(ExitCode::SUCCESS);
+        let mut number = 0;
+        for line in text.split(|&b| b == b'\n') {
+            number += 1;
+            result = match Line::parse(line) {
+                Line::Empty => Ok(()),
+                Line::If(cmd) => {
+                    let succeeded = blocks.active() && exit_status(self.execute_line(cmd, writer)) == 0;
+                    blocks.begin_if(succeeded)
+                }
+                Line::Else => blocks.begin_else(),
+                Line::Fi => blocks.end(),
+                _ if !blocks.active() => Ok(()),
+                Line::Command(cmd) => {
+                    status = self.execute_line(cmd, writer);
+                    Ok(())
+                }
+            };
+            if result.is_err() {
+                break;
+            }
+            if signal::interrupted() {
+                status = Err(ShellError::Interrupted);
+                break;
+            }
+        }
+        if result.is_ok() && blocks.is_open() && status != Err(ShellError::Interrupted) {
+            result = Err("Missing fi");
+        }
+
+        self.script_depth.set(self.script_depth.get() - 1);
+        if let Err(e) = result {
+            writer.set_color(Color::Red, Color::Black);
+            write!(writer, "Error: line {}: {}\n", number, e);
+            writer.set_color(Color::White, Color::Black);
+            return Err(ShellError::Syntax);
+        }
+        status
+    }
+
+    /// Run `/scripts/boot.sh` if there is one, before the first prompt
+    pub fn run_boot_script(&self, writer: &mut Writer) {
+        signal::clear();
+        if let Some(script) = get_filesystem().read_path(BOOT_SCRIPT) {
+            let _ = self.run_script(script, writer);
+        }
+    }
+
+    /// Run `/etc/profile.hsh` and then `$HOME/.hshrc`, each if it exists,
+    /// so the shell starts with the variables, prompt and theme they set
+    pub fn run_profile(&self, writer: &mut Writer) {
+        signal::clear();
+        if let Some(script) = get_filesystem().read_path(PROFILE_SCRIPT) {
+            let _ = self.run_script(script, writer);
+        }
+
+        let home = env::get().get(b"HOME").unwrap_or(env::DEFAULT_HOME);
+        let mut path = [0u8; env::MAX_LINE_LEN];
+        let len = home.len() + RC_SCRIPT.len();
+        if len > path.len() {
+            return;
+        }
+        path[..home.len()].copy_from_slice(home);
+        path[home.len()..len].copy_from_slice(RC_SCRIPT);
+        if let Some(script) = get_filesystem().read_path(&path[..len]) {
+            let _ = self.run_script(script, writer);
+        }
+    }
+
+    fn cmd_useradd(&self, name: &[u8], writer: &mut Writer) -> CommandResult {
+        if users::current() != b"root" {
+            return Err(self.error(writer, "Only root can add users"));
+        }
+        if !users::valid_name(name) {
+            return Err(self.error(writer, "Usage: useradd <name>, letters, digits, _ and - only"));
+        }
+        if users::find(name).is_some() {
+            return Err(self.error(writer, "User already exists"));
+        }
+        let mut password = [0u8; users::MAX_PASSWORD_LEN];
+        let result = users::ask_new_password(writer, &mut password)
+            .and_then(|len| users::add(name, &password[..len]));
+        match result {
+            Ok(()) => {
+                writer.write_str("Added ");
+                writer.write_bytes(name);
+                writer.write_str(", home /home/");
+                writer.write_bytes(name);
+                writer.write_byte(b'\n');
+            }
+            Err(e) => return Err(self.error(writer, e)),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    /// Change a password. Anyone but root has to give the old one first,
+    /// and only root can change someone else's.
+    fn cmd_passwd(&self, name: &[u8], writer: &mut Writer) -> CommandResult {
+        let me = users::current();
+        let name = if name.is_empty() { me } else { name };
+        let account = match users::find(name) {
+            Some(account) => account,
+            None => return Err(self.error(writer, "No such user")),
+        };
+        if me != b"root" {
+            if name != me {
+                return Err(self.error(writer, "Only root can change another user's password"));
+            }
+            if account.has_password() {
+                let mut old = [0u8; users::MAX_PASSWORD_LEN];
+                writer.write_str("Current password: ");
+                let len = users::read_line(writer, true, &mut old);
+                writer.write_byte(b'\n');
+                if !len.map_or(false, |len| account.check_password(&old[..len])) {
+                    return Err(self.error(writer, "Wrong password"));
+                }
+            }
+        }
+        let mut password = [0u8; users::MAX_PASSWORD_LEN];
+        let result = users::ask_new_password(writer, &mut password)
+            .and_then(|len| users::set_password(name, &password[..len]));
+        match result {
+            Ok(()) => writer.write_str("Password changed\n"),
+            Err(e) => return Err(self.error(writer, e)),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_screenshot(&self, args: &[u8], writer: &mut Writer) -> CommandResult {
+        let (first, rest) = next_arg(args);
+        let (file, _) = next_arg(rest);
+
+        if first == b"show" {
+            let result = get_filesystem()
+                .read_file(file)
+                .ok_or("File not found")
+                .and_then(screenshot::show);
+            match result {
+                Ok(()) => {
+                    idt::flush_buffer();
+                    self.wait_for_key();
+                    writer.set_color(Color::White, Color::Black);
+                    writer.clear();
+                }
+                Err(e) => return Err(self.error(writer, e)),
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let mut generated = [0u8; screenshot::MAX_BASE_LEN];
+        let base = if first.is_empty() {
+            let len = screenshot::next_free_name(&mut generated);
+            &generated[..len]
+        } else {
+            first
+        };
+        match screenshot::save(base) {
+            Ok(()) => {
+                writer.write_str("Saved ");
+                writer.write_bytes(base);
+                writer.write_str(".txt and ");
+                writer.write_bytes(base);
+                writer.write_str(".scr\n");
+            }
+            Err(e) => return Err(self.error(writer, e)),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_record(&self, args: &[u8], writer: &mut Writer) -> CommandResult {
+        let (first, _) = next_arg(args);
+
+        if first.is_empty() {
+            writer.write_str(if recorder::is_recording() { "Recording\n" } else { "Not recording\n" });
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let result = if first == b"stop" {
+            recorder::stop().map(|(name, truncated)| {
+                writer.write_str("Saved ");
+                writer.write_bytes(name);
+                writer.write_byte(b'\n');
+                if truncated {
+                    writer.set_color(Color::Yellow, Color::Black);
+                    writer.write_str("Recording was cut short at the 4 KB file size limit\n");
+                    writer.set_color(Color::White, Color::Black);
+                }
+            })
+        } else {
+            // Start from a blank screen so playback matches what was seen
+            recorder::start(first, writer.get_color()).map(|()| writer.clear())
+        };
+
+        if let Err(e) = result {
+            return Err(self.error(writer, e));
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_replay(&self, args: &[u8], writer: &mut Writer) -> CommandResult {
+        let (file, rest) = next_arg(args);
+        let (speed, _) = next_arg(rest);
+        let speed = if speed.is_empty() { Some(1) } else { parse_number(speed) };
+
+        let result = match (get_filesystem().read_file(file), speed) {
+            (None, _) => Err("File not found"),
+            (_, None) | (_, Some(0)) => Err("Speed must be a positive number"),
+            (Some(data), Some(speed)) => recorder::replay(data, speed, writer),
+        };
+        match result {
+            Ok(finished) => {
+                writer.set_color(Color::LightGray, Color::Black);
+                writer.write_str(if finished { "\n[replay finished]\n" } else { "\n[replay stopped]\n" });
+                writer.set_color(Color::White, Color::Black);
+            }
+            Err(e) => return Err(self.error(writer, e)),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_edit(&self, filename: &[u8], writer: &mut Writer) -> CommandResult {
+        let mut editor = Editor::new();
+        match editor.open(filename) {
+            Ok(()) => {
+                editor.run(writer);
+                writer.clear();
+            }
+            Err(e) => return Err(self.error(writer, e)),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    /// Page a file, or the output piped in when no file is named
+    fn cmd_less(&self, filename: &[u8], input: &[u8], writer: &mut Writer) -> CommandResult {
+        let filename = unquote(filename);
+        let (text, name) = if !filename.is_empty() {
+            match get_filesystem().read_path(filename) {
+                Some(content) => (content, filename),
+                None => return Err(self.error(writer, "File not found")),
+            }
+        } else if !input.is_empty() {
+            (input, &b"(pipe)"[..])
+        } else {
+            return Err(self.error(writer, "Usage: less <file>, or pipe output into it"));
+        };
+        match Pager::new(text, name) {
+            Ok(mut pager) => {
+                pager.run(writer);
+                writer.set_color(Color::White, Color::Black);
+                writer.clear();
+            }
+            Err(e) => return Err(self.error(writer, e)),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    /// Run a whole command line, pipes included, and say how long it took
+    fn cmd_time(&self, line: &[u8], writer: &mut Writer) -> CommandResult {
+        if line.is_empty() {
+            return Err(self.error(writer, "Usage: time <command>"));
+        }
+        let start = Instant::now();
+        let idle_start = idt::idle_ticks();
+        let result = self.execute_line(line, writer);
+        let elapsed = start.elapsed();
+        // Busy is whatever wasn't spent halted, to the timer tick
+        let idle_ticks = idt::idle_ticks().wrapping_sub(idle_start) as u64;
+        let busy = elapsed.saturating_sub(CoreDuration::from_nanos(idle_ticks * clock::tick_period_ns() as u64));
+        let percent = busy.as_micros() * 100 / elapsed.as_micros().max(1);
+        writer.set_color(Color::LightCyan, Color::Black);
+        write!(writer, "real {}.{:06}s\n", elapsed.as_secs(), elapsed.subsec_micros());
+        write!(writer, "cpu  {}.{:06}s ({}%)\n", busy.as_secs(), busy.subsec_micros(), percent);
+        writer.set_color(Color::White, Color::Black);
+        result
+    }
+
+    fn cmd_sleep(&self, arg: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        let ms = match clock::parse_duration(arg) {
+            Ok(ms) => ms,
+            Err(e) => return Err(self.error(writer, e)),
+        };
+        self.wait_or_key(ms);
+        Ok(ExitCode::SUCCESS)
+    }
+
+    /// Wait `ms` milliseconds; true if a key or Ctrl+C cut it short
+    fn wait_or_key(&self, ms: u64) -> bool {
+        let deadline = clock::uptime_ms().saturating_add(ms);
+        while clock::uptime_ms() < deadline {
+            if let Some(sc) = idt::get_scancode() {
+                // Not Ctrl, Shift or Alt, so Ctrl+C isn't taken for any key
+                if sc & 0x80 == 0 && !matches!(sc, 0x1D | 0x2A | 0x36 | 0x38) {
+                    return true;
+                }
+            }
+            if signal::interrupted() {
+                return true;
+            }
+            idt::wait_for_interrupt();
+        }
+        false
+    }
+
+    fn cmd_watch(&self, args: &[u8], writer: &mut Writer) -> CommandResult {
+        const USAGE: &str = "Usage: watch [-n <time>] <command>";
+        let (mut interval_ms, mut interval, mut command) = (2000, &b"2s"[..], args);
+        if next_arg(args).0 == b"-n" {
+            let (text, rest) = next_arg(next_arg(args).1);
+            interval = text;
+            interval_ms = match clock::parse_duration(text) {
+                Ok(ms) if ms > 0 => ms,
+                Ok(_) => return Err(self.error(writer, "Interval must be more than zero")),
+                Err(e) => return Err(self.error(writer, e)),
+            };
+            command = trim(rest);
+        }
+        if command.is_empty() {
+            return Err(self.error(writer, USAGE));
+        }
+        loop {
+            writer.clear();
+            writer.set_color(Color::LightCyan, Color::Black);
+            writer.write_str("Every ");
+            writer.write_bytes(interval);
+            writer.write_str(": ");
+            writer.write_bytes(command);
+            writer.write_str("  (any key stops)\n\n");
+            writer.set_color(Color::White, Color::Black);
+            let _ = self.execute_line(command, writer);
+            if self.wait_or_key(interval_ms) {
+                break;
+            }
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_at(&self, args: &[u8], writer: &mut Writer) -> CommandResult {
+        const USAGE: &str = "Usage: at [<time> <command> | -r <n>]";
+        let (first, rest) = next_arg(args);
+        match first {
+            b"" => {
+                let now = clock::uptime_ms();
+                let mut shown = 0;
+                for job in at::jobs() {
+                    write!(writer, "{:>3}  in {}", job.id, FmtDuration((job.due_ms.saturating_sub(now) + 999) / 1000));
+                    writer.pad_to(24);
+                    writer.write_bytes(job.command());
+                    writer.write_byte(b'\n');
+                    shown += 1;
+                }
+                if shown == 0 {
+                    writer.write_str("No jobs\n");
+                }
+            }
+            b"-r" => match parse_number(trim(rest)) {
+                Some(id) => {
+                    if let Err(e) = at::cancel(id) {
+                        return Err(self.error(writer, e));
+                    }
+                }
+                None => return Err(self.error(writer, USAGE)),
+            },
+            _ => {
+                let command = trim(rest);
+                if command.is_empty() {
+                    return Err(self.error(writer, USAGE));
+                }
+                match clock::parse_duration(first).and_then(|ms| at::schedule(ms, command)) {
+                    Ok(id) => write!(writer, "Job {} scheduled\n", id),
+                    Err(e) => return Err(self.error(writer, e)),
+                }
+            }
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    /// Run a job from `at` as if typed, then put back whatever was being
+    /// typed at the prompt
+    fn run_job(&self, job: &at::Job, writer: &mut Writer) {
+        writer.write_byte(b'\n');
+        writer.set_color(Color::Yellow, Color::Black);
+        write!(writer, "[at {}] ", job.id);
+        writer.write_bytes(job.command());
+        writer.write_byte(b'\n');
+        writer.set_color(Color::White, Color::Black);
+        signal::clear();
+        let _ = self.execute_line(job.command(), writer);
+        self.redraw_input(writer);
+    }
+
+    fn cmd_version(&self, writer: &mut dyn Sink) -> CommandResult {
+        writer.set_color(Color::LightCyan, Color::Black);
+        write!(writer, "HyzeOS {}\n", buildinfo::VERSION);
+        writer.set_color(Color::White, Color::Black);
+        let fields = [
+            ("Commit", buildinfo::GIT_HASH),
+            ("Built", buildinfo::BUILD_TIME),
+            ("Compiler", buildinfo::RUSTC_VERSION),
+            ("Features", buildinfo::FEATURES),
+        ];
+        for &(name, value) in fields.iter() {
+            writer.write_str("  ");
+            writer.write_padded(name, 10);
+            writer.write_str(value);
+            writer.write_byte(b'\n');
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_touch(&self, filename: &[u8], writer: &mut dyn Sink) -> CommandResult {
         let fs = get_filesystem();
-        match fs.delete_file(filename) {
+        if fs.file_exists(filename) {
+            writer.write_str("File already exists\n");
+            return Ok(ExitCode::SUCCESS);
+        }
+        match fs.create_file(filename, b"") {
             Ok(()) => {
                 writer.set_color(Color::Green, Color::Black);
-                writer.write_str("Deleted: ");
+                writer.write_str("Created: ");
                 writer.write_bytes(filename);
                 writer.write_byte(b'\n');
                 writer.set_color(Color::White, Color::Black);
             }
-            Err(e) => {
-                writer.set_color(Color::Red, Color::Black);
-                writer.write_str("Error: ");
-                writer.write_str(e);
-                writer.write_byte(b'\n');
+            Err(e) => return Err(self.error(writer, e)),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_write(&self, args: &[u8], input: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        let mut space_idx = None;
+        for (i, &b) in args.iter().enumerate() {
+            if b == b' ' {
+                space_idx = Some(i);
+                break;
+            }
+        }
+
+        let (filename, content) = match space_idx {
+            Some(idx) => (&args[..idx], &args[idx + 1..]),
+            // `cmd | write <file>` saves what cmd printed
+            None if !input.is_empty() => (args, input),
+            None => {
+                writer.set_color(Color::Yellow, Color::Black);
+                writer.write_str("Usage: write <filename> <content>\n");
+                writer.set_color(Color::White, Color::Black);
+                return Ok(ExitCode::SUCCESS);
+            }
+        };
+
+        let fs = get_filesystem();
+        match fs.write_file(filename, content) {
+            Ok(()) => {
+                writer.set_color(Color::Green, Color::Black);
+                writer.write_str("Written to: ");
+                writer.write_bytes(filename);
+                writer.write_byte(b'\n');
+                writer.set_color(Color::White, Color::Black);
+            }
+            Err(e) => return Err(self.error(writer, e)),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_rm(&self, args: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        let fs = get_filesystem();
+        let mut result = Ok(ExitCode::SUCCESS);
+        let mut rest = args;
+        loop {
+            let (filename, after) = next_word(rest);
+            if filename.is_empty() {
+                break;
+            }
+            rest = after;
+            match fs.delete_path(filename) {
+                Ok(()) => {
+                    writer.set_color(Color::Green, Color::Black);
+                    writer.write_str("Deleted: ");
+                    writer.write_bytes(filename);
+                    writer.write_byte(b'\n');
+                    writer.set_color(Color::White, Color::Black);
+                }
+                Err(e) => result = Err(self.error(writer, e)),
+            }
+        }
+        result
+    }
+
+    fn cmd_mkdir(&self, dirname: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        let fs = get_filesystem();
+        match fs.create_directory(dirname) {
+            Ok(()) => {
+                writer.set_color(Color::Green, Color::Black);
+                writer.write_str("Created directory: ");
+                writer.write_bytes(dirname);
+                writer.write_byte(b'\n');
+                writer.set_color(Color::White, Color::Black);
+            }
+            Err(e) => return Err(self.error(writer, e)),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_rmdir(&self, dirname: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        let fs = get_filesystem();
+        match fs.remove_directory(dirname) {
+            Ok(()) => {
+                writer.set_color(Color::Green, Color::Black);
+                writer.write_str("Removed directory: ");
+                writer.write_bytes(dirname);
+                writer.write_byte(b'\n');
+                writer.set_color(Color::White, Color::Black);
+            }
+            Err(e) => return Err(self.error(writer, e)),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_cd(&self, dirname: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        let fs = get_filesystem();
+        match fs.change_directory(dirname) {
+            Ok(()) => {
+                // Success
+            }
+            Err(e) => return Err(self.error(writer, e)),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_pwd(&self, writer: &mut dyn Sink) -> CommandResult {
+        let fs = get_filesystem();
+        let mut path_buffer = [0u8; 128];
+        let len = fs.get_current_path(&mut path_buffer);
+        writer.set_color(Color::LightCyan, Color::Black);
+        writer.write_bytes(&path_buffer[..len]);
+        writer.write_byte(b'\n');
+        writer.set_color(Color::White, Color::Black);
+        Ok(ExitCode::SUCCESS)
+    }
+
+    /// With no arguments, list the variables (only the exported ones for
+    /// `export`). Otherwise set `NAME value` or `NAME=value`; a value may be
+    /// double quoted to keep spaces, `|` or `>`.
+    fn cmd_set(&self, args: &[u8], export: bool, writer: &mut dyn Sink) -> CommandResult {
+        let args = trim(args);
+        if args.is_empty() {
+            for (name, value, exported) in env::get().iter().filter(|&(_, _, exported)| exported || !export) {
+                writer.write_str(if export { "export " } else if exported { "* " } else { "  " });
+                writer.write_bytes(name);
+                writer.write_byte(b'=');
+                writer.write_bytes(value);
+                writer.write_byte(b'\n');
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let split = args.iter().position(|&b| b == b'=' || b == b' ').unwrap_or(args.len());
+        let name = &args[..split];
+        let result = if export && split == args.len() {
+            env::get().export(name)
+        } else {
+            let value = unquote(trim(args.get(split + 1..).unwrap_or(b"")));
+            env::set(name, value).and_then(|()| if export { env::get().export(name) } else { Ok(()) })
+        };
+        if let Err(e) = result {
+            return Err(self.error(writer, e));
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    /// List the shell's capabilities, or `drop` some for good
+    fn cmd_caps(&self, args: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        let (first, rest) = next_arg(args);
+        match first {
+            b"" => {
+                for (name, has) in caps::shell().iter() {
+                    writer.write_str(if has { "  + " } else { "  - " });
+                    writer.write_str(name);
+                    writer.write_byte(b'\n');
+                }
+            }
+            b"drop" => match Caps::parse(next_arg(rest).0) {
+                Ok(dropped) => caps::restrict_shell(Caps::ALL.without(dropped)),
+                Err(e) => return Err(self.error(writer, e)),
+            },
+            _ => return Err(self.error(writer, "Usage: caps [drop <files,net,rawio>]")),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_random(&self, arg: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        if arg.is_empty() {
+            write!(writer, "{}\n", rng::random_u32());
+            return Ok(ExitCode::SUCCESS);
+        }
+        match parse_number(arg) {
+            Some(max) if max > 0 => write!(writer, "{}\n", rng::below(max)),
+            _ => return Err(self.error(writer, "Usage: random [max], max above 0")),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_free(&self, writer: &mut dyn Sink) -> CommandResult {
+        let kernel = crate::kernel_image_size() as u64;
+        let total = multiboot::total_memory_kb().unwrap_or(0) as u64 * 1024;
+        let usage = get_filesystem().usage();
+
+        self.usage_row(writer, "Kernel", kernel, total, Size);
+        self.usage_row(writer, "Files", usage.files as u64, usage.max_files as u64, Thousands);
+        self.usage_row(writer, "Storage", usage.bytes as u64, usage.capacity as u64, Size);
+        self.usage_row(writer, "Dirs", usage.directories as u64, usage.max_directories as u64, Thousands);
+        let heap = heap::stats();
+        self.usage_row(writer, "Heap", (heap.size - heap.free_bytes) as u64, heap.size as u64, Size);
+        Ok(ExitCode::SUCCESS)
+    }
+
+    /// Kernel counters from `metrics`
+    fn cmd_stats(&self, writer: &mut dyn Sink) -> CommandResult {
+        self.stats_row(writer, "Interrupts", Thousands(metrics::total_interrupts()));
+        for (vector, count) in metrics::active_vectors() {
+            write!(writer, "  {:#04x}  ", vector);
+            match vector {
+                0x00..=0x1F => writer.write_str(usermode::exception_name(vector as u32)),
+                0x20..=0x2F => write!(writer, "IRQ {}", vector - 0x20),
+                0x80 => writer.write_str("system call"),
+                _ => writer.write_str("other"),
+            }
+            writer.pad_to(40);
+            write!(writer, "{}\n", Thousands(count as u64));
+        }
+        self.stats_row(writer, "Context switches", Thousands(metrics::get(Counter::ContextSwitches) as u64));
+        self.stats_row(writer, "Console output", Size(metrics::get(Counter::ConsoleBytes) as u64));
+        self.stats_row(writer, "Serial output", Size(metrics::get(Counter::SerialBytes) as u64));
+        let heap = heap::stats();
+        self.stats_row(writer, "Heap allocations", Thousands(heap.allocations));
+        self.stats_row(writer, "Heap frees", Thousands(heap.frees));
+        Ok(ExitCode::SUCCESS)
+    }
+
+    /// `heap [stats|dump|mark]`: the kernel heap's totals, its live
+    /// allocations with those made since the last mark flagged, or a new mark
+    fn cmd_heap(&self, args: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        match args {
+            b"" | b"stats" => {
+                let stats = heap::stats();
+                self.usage_row(writer, "Heap", (stats.size - stats.free_bytes) as u64, stats.size as u64, Size);
+                self.stats_row(writer, "Live", format_args!("{} allocation(s), {}", Thousands(stats.live_count as u64), Size(stats.live_bytes as u64)));
+                self.stats_row(writer, "Peak", Size(stats.peak_bytes as u64));
+                self.stats_row(writer, "Allocations", Thousands(stats.allocations));
+                self.stats_row(writer, "Frees", Thousands(stats.frees));
+                self.stats_row(writer, "Failures", Thousands(stats.failures));
+            }
+            b"dump" => {
+                let mut records = [Record::default(); heap::MAX_TRACKED];
+                let count = heap::records(&mut records);
+                writer.set_color(Color::Yellow, Color::Black);
+                writer.write_str("  Address     Size   Age  Called from\n");
+                writer.set_color(Color::White, Color::Black);
+                let now = idt::get_ticks();
+                let mut new = 0;
+                for record in &records[..count] {
+                    let is_new = heap::is_new(record);
+                    if is_new {
+                        new += 1;
+                    }
+                    let age_secs = now.wrapping_sub(record.tick) as u64 * clock::tick_period_ns() as u64 / 1_000_000_000;
+                    write!(writer, "{} {:#010x} {:>6} {:>5}s", if is_new { '*' } else { ' ' }, record.addr, record.size, age_secs);
+                    for &caller in record.callers.iter().filter(|&&caller| caller != 0) {
+                        match crash::resolve_symbol(caller) {
+                            Some((name, offset)) => {
+                                writer.write_str("  ");
+                                writer.write_bytes(&name[..name.len().min(24)]);
+                                write!(writer, "+{:#x}", offset);
+                            }
+                            None => write!(writer, "  {:#010x}", caller),
+                        }
+                    }
+                    writer.write_byte(b'\n');
+                }
+                let untracked = heap::stats().untracked;
+                if untracked > 0 {
+                    write!(writer, "  ...and {} more not tracked\n", untracked);
+                }
+                write!(writer, "{} live, {} made since the last `heap mark` (*)\n", count + untracked, new);
+            }
+            b"mark" => {
+                heap::mark();
+                writer.write_str("Marked; allocations from now on show as new in `heap dump`\n");
+            }
+            _ => return Err(self.error(writer, "Usage: heap [stats|dump|mark]")),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn stats_row<T: Display>(&self, writer: &mut dyn Sink, label: &str, value: T) {
+        writer.set_color(Color::Yellow, Color::Black);
+        writer.write_str(label);
+        writer.set_color(Color::White, Color::Black);
+        writer.pad_to(20);
+        write!(writer, "{}\n", value);
+    }
+
+    /// One `free` row: label, bar, then "used / total", each shown through
+    /// `unit`, e.g. `Size`
+    fn usage_row<T: Display>(&self, writer: &mut dyn Sink, label: &str, used: u64, total: u64, unit: fn(u64) -> T) {
+        const BAR_WIDTH: usize = 30;
+
+        writer.set_color(Color::Yellow, Color::Black);
+        writer.write_str(label);
+        for _ in label.len()..10 {
+            writer.write_byte(b' ');
+        }
+        let percent = chart::percent(used, total);
+        chart::bar(writer, used, total, BAR_WIDTH, chart::level_color(percent));
+        write!(writer, "  {} / {} ({}%)\n", unit(used), unit(total), percent);
+    }
+
+    fn handle_acpi_event(&self, event: sci::Event, writer: &mut Writer) {
+        writer.write_byte(b'\n');
+        writer.set_color(Color::Yellow, Color::Black);
+        match event {
+            sci::Event::PowerButton => {
+                writer.write_str("Power button pressed, shutting down...\n");
+                power::shutdown();
+            }
+            sci::Event::SleepButton => {
+                writer.write_str("Sleep button pressed, suspending...\n");
+                writer.set_color(Color::White, Color::Black);
+                let _ = self.cmd_suspend(writer);
+            }
+        }
+        writer.set_color(Color::White, Color::Black);
+        self.redraw_input(writer);
+    }
+
+    fn cmd_suspend(&self, writer: &mut dyn Sink) -> CommandResult {
+        match suspend::suspend() {
+            Ok(()) => {
+                writer.set_color(Color::Green, Color::Black);
+                writer.write_str("Resumed\n");
+                writer.set_color(Color::White, Color::Black);
+            }
+            Err(e) => return Err(self.error(writer, e)),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_drvtest(&self, args: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        let (test, _) = next_arg(args);
+        if test != b"suspend-resume" {
+            writer.set_color(Color::Yellow, Color::Black);
+            writer.write_str("Usage: drvtest suspend-resume\n");
+            writer.set_color(Color::White, Color::Black);
+            writer.write_str("Drivers:");
+            for driver in driver::drivers().iter().flatten() {
+                write!(writer, " {}", driver.name);
+            }
+            writer.write_byte(b'\n');
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let mut passed = 0;
+        let mut total = 0;
+        for driver in driver::drivers().iter().flatten() {
+            let result = driver::cycle(driver);
+
+            total += 1;
+            writer.write_str("  ");
+            writer.write_padded(driver.name, 14);
+            match result {
+                Ok(()) => {
+                    passed += 1;
+                    writer.set_color(Color::Green, Color::Black);
+                    writer.write_str("ok\n");
+                }
+                Err(Failure::Suspend(e)) => {
+                    writer.set_color(Color::Red, Color::Black);
+                    write!(writer, "suspend failed: {}\n", e);
+                }
+                Err(Failure::Resume(e)) => {
+                    writer.set_color(Color::Red, Color::Black);
+                    write!(writer, "resume failed: {}\n", e);
+                }
+            }
+            writer.set_color(Color::White, Color::Black);
+        }
+        writer.set_color(if passed == total { Color::Green } else { Color::Red }, Color::Black);
+        write!(writer, "{}/{} drivers came back\n", passed, total);
+        writer.set_color(Color::White, Color::Black);
+        if passed == total {
+            Ok(ExitCode::SUCCESS)
+        } else {
+            Err(ShellError::Failed)
+        }
+    }
+
+    fn cmd_devices(&self, writer: &mut dyn Sink) -> CommandResult {
+        for device in aml::devices() {
+            // Bus slots and other ID-less nodes only add noise
+            if device.hid().is_empty() {
+                continue;
+            }
+            writer.set_color(Color::Yellow, Color::Black);
+            for seg in device.path() {
+                writer.write_byte(b'\\');
+                writer.write_bytes(seg);
+            }
+            writer.set_color(Color::White, Color::Black);
+            writer.write_str("  ");
+            writer.write_bytes(device.hid());
+            for resource in device.resources() {
+                match *resource {
+                    Resource::Io { base, len } => {
+                        writer.write_str("  io 0x");
+                        self.write_hex(writer, base as u32, 4);
+                        if len > 1 {
+                            writer.write_str("-0x");
+                            self.write_hex(writer, (base + len - 1) as u32, 4);
+                        }
+                    }
+                    Resource::Irq(irq) => {
+                        writer.write_str("  irq ");
+                        self.write_number(writer, irq as usize);
+                    }
+                    Resource::Memory { base, len } => {
+                        writer.write_str("  mem 0x");
+                        self.write_hex(writer, base, 8);
+                        writer.write_str(" ");
+                        self.write_number(writer, (len / 1024) as usize);
+                        writer.write_str(" KB");
+                    }
+                }
+            }
+            writer.write_byte(b'\n');
+        }
+
+        writer.set_color(Color::Yellow, Color::Black);
+        writer.write_str("Power button: ");
+        writer.set_color(Color::White, Color::Black);
+        writer.write_str(match aml::power_button() {
+            Some(PowerButton::FixedFeature) => "fixed feature\n",
+            Some(PowerButton::Device) => "control method device\n",
+            None => "none\n",
+        });
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_hwprobe(&self, args: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        match args {
+            b"" => hwprobe::write_text(writer),
+            b"--json" => {
+                writer.write_str("Probing...\n");
+                let bytes = hwprobe::send_json();
+                write!(writer, "Sent a {} byte JSON report to COM1\n", bytes);
+            }
+            _ => return Err(self.error(writer, "Usage: hwprobe [--json]")),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_ifconfig(&self, name: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        if !name.is_empty() && netdev::find(name).is_none() {
+            return Err(self.error(writer, "No such interface"));
+        }
+        let mut shown = 0;
+        for device in netdev::devices().filter(|d| name.is_empty() || d.name().as_bytes() == name) {
+            let stats = device.stats();
+            writer.set_color(Color::Yellow, Color::Black);
+            writer.write_str(device.name());
+            writer.set_color(Color::White, Color::Black);
+            writer.pad_to(8);
+            write!(writer, "{}  HWaddr {}  link {}\n", device.driver(), device.mac(), if device.link_up() { "up" } else { "down" });
+            let config = net::config();
+            write!(writer, "        inet {}  netmask {}  gateway {}  dns {}\n", config.address, config.netmask, config.gateway, config.dns);
+            write!(writer, "        RX packets {}  bytes {}  dropped {}  errors {}\n",
+                stats.rx_packets, stats.rx_bytes, stats.rx_dropped, stats.rx_errors);
+            write!(writer, "        TX packets {}  bytes {}  errors {}\n", stats.tx_packets, stats.tx_bytes, stats.tx_errors);
+            shown += 1;
+        }
+        if shown == 0 {
+            writer.write_str("No network interfaces\n");
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_arp(&self, writer: &mut dyn Sink) -> CommandResult {
+        writer.set_color(Color::Yellow, Color::Black);
+        writer.write_str("Address          HWaddress          Age\n");
+        writer.set_color(Color::White, Color::Black);
+        let mut shown = 0;
+        for (ip, mac, age_ms) in net::arp_entries() {
+            write!(writer, "{}", ip);
+            writer.pad_to(17);
+            write!(writer, "{}  {}s\n", mac, age_ms / 1000);
+            shown += 1;
+        }
+        if shown == 0 {
+            writer.write_str("(empty)\n");
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_dhcp(&self, args: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        match args {
+            b"" => {
+                writer.write_str("Asking for an address...\n");
+                if let Err(e) = dhcp::acquire() {
+                    return Err(self.error(writer, e));
+                }
+            }
+            b"status" => {}
+            _ => return Err(self.error(writer, "Usage: dhcp [status]")),
+        }
+        write!(writer, "State: {}\n", dhcp::state().name());
+        if let Some(lease) = dhcp::lease() {
+            let now = clock::uptime_ms();
+            let left = |at_ms: u64| FmtDuration(at_ms.saturating_sub(now) / 1000);
+            write!(writer, "Address {}  netmask {}  gateway {}  dns {}\n", lease.address, lease.netmask, lease.gateway, lease.dns);
+            write!(writer, "Server {}  lease {}\n", lease.server, FmtDuration(lease.duration_secs as u64));
+            write!(writer, "Renew in {}, rebind in {}, expires in {}\n",
+                left(lease.renew_at_ms()), left(lease.rebind_at_ms()), left(lease.expires_ms()));
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_ping(&self, args: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        const USAGE: &str = "Usage: ping [-c count] <ip>";
+        const DATA_LEN: usize = 56;
+        const INTERVAL_MS: u64 = 1000;
+
+        let (mut first, mut rest) = next_arg(args);
+        let mut count = None;
+        if first == b"-c" {
+            let (n, after) = next_arg(rest);
+            count = match parse_number(n) {
+                Some(n) if n > 0 => Some(n),
+                _ => return Err(self.error(writer, USAGE)),
+            };
+            let next = next_arg(after);
+            first = next.0;
+            rest = next.1;
+        }
+        let target = match Ipv4Addr::parse(first) {
+            Some(ip) if trim(rest).is_empty() => ip,
+            _ => return Err(self.error(writer, USAGE)),
+        };
+
+        write!(writer, "PING {}: {} data bytes\n", target, DATA_LEN);
+        let id = clock::uptime_ms() as u16 ^ 0x485A;
+        let mut data = [0u8; DATA_LEN];
+        for (i, b) in data.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let (mut sent, mut received) = (0u32, 0u32);
+        let (mut min_us, mut max_us, mut total_us) = (u64::MAX, 0u64, 0u64);
+        let mut result = Ok(ExitCode::SUCCESS);
+
+        'pinging: while count.map_or(true, |count| sent < count) {
+            let sequence = sent as u16 + 1;
+            let start = Instant::now();
+            if let Err(e) = net::send_echo_request(target, id, sequence, &data) {
+                result = Err(self.error(writer, e));
+                if sent == 0 {
+                    return result;
+                }
+                break;
+            }
+            sent += 1;
+
+            let mut answered = false;
+            while start.elapsed().as_millis() < INTERVAL_MS as u128 {
+                if ctrl_c_pressed() {
+                    break 'pinging;
+                }
+                net::poll();
+                match net::take_echo_reply() {
+                    Some(reply) if !answered && reply.from == target && reply.id == id && reply.sequence == sequence => {
+                        let us = start.elapsed().as_micros() as u64;
+                        answered = true;
+                        received += 1;
+                        min_us = min_us.min(us);
+                        max_us = max_us.max(us);
+                        total_us += us;
+                        write!(writer, "{} bytes from {}: icmp_seq={} ttl={} time={}.{:03} ms\n",
+                            reply.len + 8, reply.from, reply.sequence, reply.ttl, us / 1000, us % 1000);
+                    }
+                    _ => idt::wait_for_interrupt(),
+                }
+            }
+            if !answered {
+                write!(writer, "Request timeout for icmp_seq {}\n", sequence);
+            }
+        }
+
+        writer.set_color(Color::LightCyan, Color::Black);
+        write!(writer, "--- {} ping statistics ---\n", target);
+        writer.set_color(Color::White, Color::Black);
+        let loss = if sent == 0 { 0 } else { (sent - received) * 100 / sent };
+        write!(writer, "{} packets transmitted, {} received, {}% packet loss\n", sent, received, loss);
+        if received > 0 {
+            let avg_us = total_us / received as u64;
+            write!(writer, "rtt min/avg/max = {}.{:03}/{}.{:03}/{}.{:03} ms\n",
+                min_us / 1000, min_us % 1000, avg_us / 1000, avg_us % 1000, max_us / 1000, max_us % 1000);
+        }
+        result
+    }
+
+    /// Fetch a URL over HTTP and save its body, by default under the last
+    /// part of its path
+    fn cmd_wget(&self, args: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        let (target, rest) = next_arg(args);
+        let (file, rest) = next_arg(rest);
+        if target.is_empty() || !rest.is_empty() {
+            return Err(self.error(writer, "Usage: wget <url> [file]"));
+        }
+        let url = match http::Url::parse(target) {
+            Ok(url) => url,
+            Err(e) => return Err(self.error(writer, e)),
+        };
+        let file = if file.is_empty() { url.file_name() } else { file };
+
+        write!(writer, "Connecting to {}:{}...\n", url.address, url.port);
+        let buf = unsafe { &mut WGET_BUFFER };
+        let response = match http::get(&url, buf, &mut || ctrl_c_pressed()) {
+            Ok(response) => response,
+            Err(e) => return Err(self.error(writer, e)),
+        };
+        if !(200..300).contains(&response.status) {
+            writer.set_color(Color::Red, Color::Black);
+            write!(writer, "HTTP {} ", response.status);
+            writer.write_bytes(response.reason);
+            writer.write_byte(b'\n');
+            writer.set_color(Color::White, Color::Black);
+            return Err(ShellError::Failed);
+        }
+        if let Err(e) = get_filesystem().write_path(file, response.body) {
+            return Err(self.error(writer, e));
+        }
+        writer.set_color(Color::Green, Color::Black);
+        write!(writer, "HTTP {}, {} saved to ", response.status, Size(response.body.len() as u64));
+        writer.write_bytes(file);
+        writer.write_byte(b'\n');
+        writer.set_color(Color::White, Color::Black);
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_httpd(&self, args: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        const USAGE: &str = "Usage: httpd [start [port]|stop]";
+        let (action, rest) = next_arg(args);
+        let (port, rest) = next_arg(rest);
+        if !rest.is_empty() {
+            return Err(self.error(writer, USAGE));
+        }
+        let result = match action {
+            b"" => Ok(()),
+            b"start" => match port {
+                b"" => httpd::start(httpd::DEFAULT_PORT),
+                _ => match parse_number(port).filter(|&port| port > 0 && port <= u16::MAX as u32) {
+                    Some(port) => httpd::start(port as u16),
+                    None => Err("Invalid port"),
+                },
+            },
+            b"stop" if port.is_empty() => httpd::stop(),
+            _ => Err(USAGE),
+        };
+        if let Err(e) = result {
+            return Err(self.error(writer, e));
+        }
+        match httpd::running() {
+            Some((port, served)) => write!(writer, "Serving status pages on port {}, {} requests so far\n", port, served),
+            None => writer.write_str("Not running\n"),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    /// `watchdog [<time>|off]`: show the watchdog, or set or clear its limit
+    fn cmd_watchdog(&self, args: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        let result = match args {
+            b"" => Ok(()),
+            b"off" => watchdog::set_timeout_ms(0),
+            _ => match clock::parse_duration(args) {
+                Ok(ms) if ms > 0 && ms <= u32::MAX as u64 => watchdog::set_timeout_ms(ms as u32),
+                Ok(_) => Err("Usage: watchdog [<time>|off]"),
+                Err(e) => Err(e),
+            },
+        };
+        if let Err(e) = result {
+            return Err(self.error(writer, e));
+        }
+        match watchdog::timeout_ms() {
+            0 => writer.write_str("Watchdog off\n"),
+            ms => write!(writer, "Watchdog panics after {} ms without progress, last fed {} ms ago\n", ms, watchdog::since_fed_ms()),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_udp(&self, args: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        let (action, rest) = next_arg(args);
+        match action {
+            b"send" => {
+                let (ip, rest) = next_arg(rest);
+                let (port, text) = next_arg(rest);
+                let (ip, port) = match (Ipv4Addr::parse(ip), parse_number(port)) {
+                    (Some(ip), Some(port)) if port > 0 && port <= 0xFFFF => (ip, port as u16),
+                    _ => return Err(self.error(writer, "Usage: udp send <ip> <port> <text>")),
+                };
+                let result = UdpSocket::bind(0).and_then(|socket| socket.send_to(trim(text), ip, port));
+                if let Err(e) = result {
+                    return Err(self.error(writer, e));
+                }
+            }
+            b"listen" => {
+                let port = match parse_number(trim(rest)) {
+                    Some(port) if port > 0 && port <= 0xFFFF => port as u16,
+                    _ => return Err(self.error(writer, "Usage: udp listen <port>")),
+                };
+                let socket = match UdpSocket::bind(port) {
+                    Ok(socket) => socket,
+                    Err(e) => return Err(self.error(writer, e)),
+                };
+                write!(writer, "Listening on UDP port {}, press any key to stop\n", port);
+                let mut buf = [0u8; net::MAX_UDP_PAYLOAD];
+                loop {
+                    if let Some((len, from, from_port)) = socket.recv_from(&mut buf) {
+                        writer.set_color(Color::LightCyan, Color::Black);
+                        write!(writer, "{}:{}: ", from, from_port);
+                        writer.set_color(Color::White, Color::Black);
+                        writer.write_bytes(&buf[..len]);
+                        writer.write_byte(b'\n');
+                        continue;
+                    }
+                    if let Some(sc) = idt::get_scancode() {
+                        if sc & 0x80 == 0 {
+                            break;
+                        }
+                    }
+                    idt::wait_for_interrupt();
+                }
+            }
+            _ => return Err(self.error(writer, "Usage: udp send <ip> <port> <text> | udp listen <port>")),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_bootinfo(&self, writer: &mut dyn Sink) -> CommandResult {
+        writer.set_color(Color::Yellow, Color::Black);
+        writer.write_str("Bootloader: ");
+        writer.set_color(Color::White, Color::Black);
+        writer.write_bytes(multiboot::bootloader_name().unwrap_or(b"unknown"));
+        writer.write_byte(b'\n');
+
+        if let Some(device) = multiboot::boot_device() {
+            writer.set_color(Color::Yellow, Color::Black);
+            writer.write_str("Boot device: ");
+            writer.set_color(Color::White, Color::Black);
+            writer.write_str("drive 0x");
+            self.write_hex(writer, device.drive as u32, 2);
+            if device.partition != 0xFF {
+                writer.write_str(", partition ");
+                self.write_number(writer, device.partition as usize);
+            }
+            writer.write_byte(b'\n');
+        }
+
+        writer.set_color(Color::Yellow, Color::Black);
+        writer.write_str("Memory map:\n");
+        writer.set_color(Color::White, Color::Black);
+        for region in multiboot::memory_map() {
+            writer.write_str("  ");
+            self.write_hex(writer, (region.base >> 32) as u32, 8);
+            self.write_hex(writer, region.base as u32, 8);
+            writer.write_str("  ");
+            self.write_number(writer, (region.length / 1024) as usize);
+            writer.write_str(" KB  ");
+            writer.write_str(match region.kind {
+                multiboot::MEMORY_AVAILABLE => "available",
+                3 => "ACPI reclaimable",
+                4 => "ACPI NVS",
+                5 => "bad",
+                _ => "reserved",
+            });
+            writer.write_byte(b'\n');
+        }
+        writer.write_str("  Usable: ");
+        self.write_number(writer, multiboot::total_memory_kb().unwrap_or(0) as usize);
+        writer.write_str(" KB\n");
+
+        writer.set_color(Color::Yellow, Color::Black);
+        writer.write_str("Modules:\n");
+        writer.set_color(Color::White, Color::Black);
+        for module in multiboot::modules() {
+            writer.write_str("  0x");
+            self.write_hex(writer, module.start, 8);
+            writer.write_str("  ");
+            self.write_number(writer, (module.end - module.start) as usize);
+            writer.write_str(" bytes  ");
+            writer.write_bytes(module.cmdline);
+            writer.write_byte(b'\n');
+        }
+
+        if let Some(fb) = multiboot::framebuffer() {
+            writer.set_color(Color::Yellow, Color::Black);
+            writer.write_str("Framebuffer: ");
+            writer.set_color(Color::White, Color::Black);
+            self.write_number(writer, fb.width as usize);
+            writer.write_byte(b'x');
+            self.write_number(writer, fb.height as usize);
+            writer.write_byte(b'x');
+            self.write_number(writer, fb.bpp as usize);
+            writer.write_str(" at 0x");
+            self.write_hex(writer, fb.address as u32, 8);
+            writer.write_byte(b'\n');
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_life(&self, args: &[u8], writer: &mut Writer) -> CommandResult {
+        let (pattern, _) = next_arg(args);
+        let mut game = LifeGame::new();
+        if pattern.is_empty() {
+            game.randomize();
+        } else if let Err(e) = game.load_pattern(pattern) {
+            return Err(self.error(writer, e));
+        }
+        game.run(writer);
+        writer.set_color(Color::White, Color::Black);
+        writer.clear();
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_beep(&self, args: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        let (freq_arg, rest) = next_arg(args);
+        let (ms_arg, _) = next_arg(rest);
+        let frequency = if freq_arg.is_empty() { Some(880) } else { parse_number(freq_arg) };
+        let duration = if ms_arg.is_empty() { Some(200) } else { parse_number(ms_arg) };
+
+        match (frequency, duration) {
+            (Some(frequency), Some(duration)) if (20..=20000).contains(&frequency) => {
+                speaker::beep(frequency, duration.min(5000));
+            }
+            _ => {
+                writer.set_color(Color::Yellow, Color::Black);
+                writer.write_str("Usage: beep [20-20000 hz] [ms]\n");
+                writer.set_color(Color::White, Color::Black);
+            }
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_fbcon(&self, args: &[u8], writer: &mut Writer) -> CommandResult {
+        let (action, rest) = next_arg(args);
+        let (mode, _) = next_arg(rest);
+
+        if action == b"off" {
+            framebuffer::restore_text_mode();
+            writer.set_position(writer.get_col(), writer.get_row());
+            return Ok(ExitCode::SUCCESS);
+        }
+        if action != b"on" && !action.is_empty() {
+            writer.set_color(Color::Yellow, Color::Black);
+            writer.write_str("Usage: fbcon [on WxH|off]\n");
+            writer.set_color(Color::White, Color::Black);
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let (width, height) = if mode.is_empty() {
+            (Some(800), Some(600))
+        } else {
+            let split = mode.iter().position(|&b| b == b'x').unwrap_or(mode.len());
+            (parse_number(&mode[..split]), mode.get(split + 1..).and_then(parse_number))
+        };
+        let (width, height) = match (width, height) {
+            (Some(w), Some(h)) => (w as usize, h as usize),
+            _ => return Err(self.error(writer, "Mode must look like 800x600")),
+        };
+
+        match framebuffer::set_mode(width, height) {
+            Ok(_) => {
+                framebuffer::enable_console();
+                writer.set_position(writer.get_col(), writer.get_row());
+            }
+            Err(e) => return Err(self.error(writer, e)),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_font(&self, args: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        let (action, rest) = next_arg(args);
+        let (value, _) = next_arg(rest);
+
+        let result = match action {
+            b"" => Ok(()),
+            b"default" => {
+                font::reset();
+                Ok(())
+            }
+            b"load" if !value.is_empty() => font::load(value).map(|_| ()),
+            b"scale" => match parse_number(value) {
+                Some(scale) if (1..=4).contains(&scale) => {
+                    framebuffer::set_console_scale(scale as usize);
+                    Ok(())
+                }
+                _ => Err("Scale must be 1-4"),
+            },
+            _ => Err("Usage: font [load <file>|default|scale <n>]"),
+        };
+
+        if let Err(e) = result {
+            return Err(self.error(writer, e));
+        }
+        if action == b"default" || action == b"load" {
+            framebuffer::font_changed();
+        }
+
+        let current = font::current();
+        writer.write_str("Font: ");
+        self.write_number(writer, current.width());
+        writer.write_byte(b'x');
+        self.write_number(writer, current.height());
+        writer.write_str(", ");
+        self.write_number(writer, current.glyph_count());
+        writer.write_str(" glyphs, scale ");
+        self.write_number(writer, framebuffer::console_scale());
+        writer.write_byte(b'\n');
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_hexdump(&self, args: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        let (addr_arg, rest) = next_arg(args);
+        let (len_arg, _) = next_arg(rest);
+        let (addr, len) = match (parse_number(addr_arg), parse_number(len_arg)) {
+            (Some(addr), Some(len)) => (addr as usize, len as usize),
+            _ => {
+                writer.set_color(Color::Yellow, Color::Black);
+                writer.write_str("Usage: hexdump <addr> <len>\n");
+                writer.set_color(Color::White, Color::Black);
+                return Ok(ExitCode::SUCCESS);
+            }
+        };
+        let len = len.min(MAX_HEXDUMP_LEN);
+        // Past the top of the address space `addr + offset` would wrap
+        if len > 0 && addr.checked_add(len - 1).is_none() {
+            return Err(self.error(writer, "Address out of range"));
+        }
+
+        let mut offset = 0;
+        while offset < len {
+            let line_len = (len - offset).min(16);
+            let line = unsafe { core::slice::from_raw_parts((addr + offset) as *const u8, line_len) };
+
+            writer.set_color(Color::LightCyan, Color::Black);
+            self.write_hex(writer, (addr + offset) as u32, 8);
+            writer.set_color(Color::White, Color::Black);
+            writer.write_str("  ");
+            for i in 0..16 {
+                if i < line_len {
+                    self.write_hex(writer, line[i] as u32, 2);
+                    writer.write_byte(b' ');
+                } else {
+                    writer.write_str("   ");
+                }
+            }
+            writer.write_str(" |");
+            for &byte in line {
+                writer.write_byte(if (0x20..0x7f).contains(&byte) { byte } else { b'.' });
+            }
+            writer.write_str("|\n");
+            offset += 16;
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_peek(&self, args: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        let (addr_arg, rest) = next_arg(args);
+        let (size_arg, _) = next_arg(rest);
+        let addr = match parse_number(addr_arg) {
+            Some(addr) => addr as usize,
+            None => {
+                writer.set_color(Color::Yellow, Color::Black);
+                writer.write_str("Usage: peek <addr> [b|w|d]\n");
+                writer.set_color(Color::White, Color::Black);
+                return Ok(ExitCode::SUCCESS);
+            }
+        };
+        let size = self.access_size(size_arg, addr, writer)?;
+
+        let value = unsafe {
+            match size {
+                1 => core::ptr::read_volatile(addr as *const u8) as u32,
+                2 => core::ptr::read_volatile(addr as *const u16) as u32,
+                _ => core::ptr::read_volatile(addr as *const u32),
+            }
+        };
+
+        writer.set_color(Color::LightCyan, Color::Black);
+        self.write_hex(writer, addr as u32, 8);
+        writer.set_color(Color::White, Color::Black);
+        writer.write_str(": 0x");
+        self.write_hex(writer, value, size * 2);
+        writer.write_str(" (");
+        self.write_number(writer, value as usize);
+        writer.write_str(")\n");
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_irqlat(&self, args: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        const BAR_WIDTH: usize = 30;
+
+        let (seconds_arg, _) = next_arg(args);
+        let seconds = if seconds_arg.is_empty() { Some(5) } else { parse_number(seconds_arg) };
+        let seconds = match seconds {
+            Some(s) if s >= 1 && s <= 60 => s,
+            _ => {
+                writer.set_color(Color::Yellow, Color::Black);
+                writer.write_str("Usage: irqlat [seconds 1-60]\n");
                 writer.set_color(Color::White, Color::Black);
+                return Ok(ExitCode::SUCCESS);
+            }
+        };
+
+        if let Err(e) = latency::start() {
+            return Err(self.error(writer, e));
+        }
+        writer.write_str("Measuring timer interrupt latency for ");
+        self.write_number(writer, seconds as usize);
+        writer.write_str(" s...\n");
+        clock::sleep_ms(seconds * 1000);
+        let stats = latency::stop();
+
+        writer.set_color(Color::Yellow, Color::Black);
+        writer.write_str("Samples: ");
+        writer.set_color(Color::White, Color::Black);
+        self.write_number(writer, stats.samples as usize);
+        writer.write_byte(b'\n');
+        if stats.samples == 0 {
+            return Ok(ExitCode::SUCCESS);
+        }
+        for &(label, ns) in [("Min: ", stats.min_ns), ("  Avg: ", stats.average_ns()), ("  Max: ", stats.max_ns)].iter() {
+            writer.set_color(Color::Yellow, Color::Black);
+            writer.write_str(label);
+            writer.set_color(Color::White, Color::Black);
+            self.write_micros(writer, ns);
+        }
+        writer.write_byte(b'\n');
+
+        let most = stats.buckets.iter().copied().max().unwrap_or(0);
+        for (i, &count) in stats.buckets.iter().enumerate() {
+            let (prefix, limit) = match latency::BUCKET_LIMITS_US.get(i) {
+                Some(&limit) => ("  <", limit),
+                None => (" >=", latency::BUCKET_LIMITS_US[i - 1]),
+            };
+            writer.set_color(Color::Yellow, Color::Black);
+            writer.write_str(prefix);
+            if limit < 100 {
+                writer.write_byte(b' ');
+            }
+            if limit < 10 {
+                writer.write_byte(b' ');
             }
+            self.write_number(writer, limit as usize);
+            writer.write_str(" us ");
+            chart::bar(writer, count as u64, most as u64, BAR_WIDTH, Color::LightCyan);
+            writer.set_color(Color::White, Color::Black);
+            writer.write_str(" ");
+            self.write_number(writer, count as usize);
+            writer.write_byte(b'\n');
         }
+        Ok(ExitCode::SUCCESS)
     }
 
-    fn cmd_mkdir(&self, dirname: &[u8], writer: &mut Writer) {
-        let fs = get_filesystem();
-        match fs.create_directory(dirname) {
-            Ok(()) => {
-                writer.set_color(Color::Green, Color::Black);
-                writer.write_str("Created directory: ");
-                writer.write_bytes(dirname);
+    fn cmd_loadkeys(&self, args: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        let (name, _) = next_arg(args);
+
+        if name.is_empty() {
+            let current = keyboard::layout().name;
+            for layout in keyboard::LAYOUTS.iter() {
+                writer.write_str(if layout.name == current { "* " } else { "  " });
+                writer.write_str(layout.name);
                 writer.write_byte(b'\n');
-                writer.set_color(Color::White, Color::Black);
             }
-            Err(e) => {
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        match keyboard::find_layout(name) {
+            Some(layout) => {
+                keyboard::set_layout(layout);
+                writer.write_str("Keyboard layout: ");
+                writer.write_str(layout.name);
+                writer.write_byte(b'\n');
+            }
+            None => {
                 writer.set_color(Color::Red, Color::Black);
                 writer.write_str("Error: ");
-                writer.write_str(e);
-                writer.write_byte(b'\n');
+                writer.write_str("Unknown layout (us, uk, de or fr)\n");
                 writer.set_color(Color::White, Color::Black);
+                return Err(ShellError::Failed);
             }
         }
+        Ok(ExitCode::SUCCESS)
     }
 
-    fn cmd_rmdir(&self, dirname: &[u8], writer: &mut Writer) {
-        let fs = get_filesystem();
-        match fs.remove_directory(dirname) {
+    fn cmd_theme(&self, args: &[u8], writer: &mut Writer) -> CommandResult {
+        let (name, _) = next_arg(args);
+
+        if name.is_empty() {
+            let current = theme::current();
+            for &t in theme::THEMES.iter() {
+                writer.write_str(if t == current { "* " } else { "  " });
+                writer.write_str(t.name());
+                writer.write_byte(b'\n');
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        match env::set(b"THEME", name) {
             Ok(()) => {
-                writer.set_color(Color::Green, Color::Black);
-                writer.write_str("Removed directory: ");
-                writer.write_bytes(dirname);
+                let t = theme::current();
+                let palette = t.palette();
+                writer.set_color(palette.text, palette.background);
+                writer.clear();
+                writer.set_color(palette.heading, palette.background);
+                writer.write_str("Theme: ");
+                writer.set_color(palette.value, palette.background);
+                writer.write_str(t.name());
                 writer.write_byte(b'\n');
-                writer.set_color(Color::White, Color::Black);
+                writer.set_color(palette.text, palette.background);
             }
             Err(e) => {
-                writer.set_color(Color::Red, Color::Black);
+                let palette = theme::palette();
+                writer.set_color(palette.error, palette.background);
                 writer.write_str("Error: ");
                 writer.write_str(e);
                 writer.write_byte(b'\n');
+                writer.set_color(palette.text, palette.background);
+                return Err(ShellError::Failed);
+            }
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_idleinfo(&self, args: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        let (first, rest) = next_arg(args);
+        if first == b"hint" {
+            let (mode, _) = next_arg(rest);
+            let result = PerfHint::from_name(mode)
+                .ok_or("Usage: idleinfo hint performance|balanced|powersave")
+                .and_then(idle::set_perf_hint);
+            if let Err(e) = result {
+                return Err(self.error(writer, e));
+            }
+            return Ok(ExitCode::SUCCESS);
+        } else if !first.is_empty() {
+            writer.write_str("Usage: idleinfo [hint performance|balanced|powersave]\n");
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let uptime = idt::get_ticks().max(1);
+        writer.set_color(Color::Yellow, Color::Black);
+        writer.write_str("State        Entries     Ticks  Residency\n");
+        writer.set_color(Color::White, Color::Black);
+        for state in idle::states() {
+            writer.write_padded(state.name, 11);
+            write!(writer, "{:>9} {:>9} {:>9}%\n", state.entries, state.ticks, state.ticks as u64 * 100 / uptime as u64);
+        }
+
+        writer.set_color(Color::Yellow, Color::Black);
+        writer.write_str("P-states: ");
+        writer.set_color(Color::White, Color::Black);
+        match idle::perf_control() {
+            Some(control) => {
+                writer.write_str(match control {
+                    PerfControl::Hwp => "HWP",
+                    PerfControl::AmdPstate => "AMD P-state MSRs",
+                });
+                writer.write_str(", hint ");
+                writer.write_str(idle::perf_hint().map_or("not set", |hint| hint.name()));
+                writer.write_byte(b'\n');
+            }
+            None => writer.write_str("no control\n"),
+        }
+        Ok(ExitCode::SUCCESS)
+    }
+
+    fn cmd_faulttest(&self, args: &[u8], writer: &mut Writer) -> CommandResult {
+        let (first, rest) = next_arg(args);
+        let (class, _) = next_arg(rest);
+
+        if first == b"crash" {
+            writer.set_color(Color::Yellow, Color::Black);
+            writer.write_str("This stops the kernel on the crash screen. Continue? (y/n) ");
+            writer.set_color(Color::White, Color::Black);
+            let confirmed = keyboard::is_yes(self.wait_for_key());
+            writer.write_str(if confirmed { "y\n" } else { "n\n" });
+            if !confirmed {
+                writer.write_str("Aborted\n");
+                return Ok(ExitCode::SUCCESS);
+            }
+            if let Err(e) = faulttest::crash(class) {
+                return Err(self.error(writer, e));
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        writer.write_str("Provoking exceptions in user mode...\n");
+        match faulttest::run(writer) {
+            Ok((passed, total)) => {
+                writer.set_color(if passed == total { Color::Green } else { Color::Red }, Color::Black);
+                self.write_number(writer, passed);
+                writer.write_str("/");
+                self.write_number(writer, total);
+                writer.write_str(" passed\n");
                 writer.set_color(Color::White, Color::Black);
             }
+            Err(e) => return Err(self.error(writer, e)),
         }
+        Ok(ExitCode::SUCCESS)
     }
 
-    fn cmd_cd(&self, dirname: &[u8], writer: &mut Writer) {
-        let fs = get_filesystem();
-        match fs.change_directory(dirname) {
-            Ok(()) => {
-                // Success
+    /// Nanoseconds as microseconds with one decimal
+    fn write_micros(&self, writer: &mut dyn Sink, ns: u32) {
+        self.write_number(writer, (ns / 1000) as usize);
+        writer.write_byte(b'.');
+        self.write_number(writer, (ns % 1000 / 100) as usize);
+        writer.write_str(" us");
+    }
+
+    fn cmd_poke(&self, args: &[u8], writer: &mut dyn Sink) -> CommandResult {
+        let (addr_arg, rest) = next_arg(args);
+        let (value_arg, rest) = next_arg(rest);
+        let (size_arg, _) = next_arg(rest);
+        let (addr, value) = match (parse_number(addr_arg), parse_number(value_arg)) {
+            (Some(addr), Some(value)) => (addr as usize, value),
+            _ => {
+                writer.set_color(Color::Yellow, Color::Black);
+                writer.write_str("Usage: poke <addr> <value> [b|w|d]\n");
+                writer.set_color(Color::White, Color::Black);
+                return Ok(ExitCode::SUCCESS);
             }
-            Err(e) => {
+        };
+        let size = self.access_size(size_arg, addr, writer)?;
+        if size < 4 && value >> (size * 8) != 0 {
+            writer.set_color(Color::Red, Color::Black);
+            writer.write_str("Value does not fit in the access size\n");
+            writer.set_color(Color::White, Color::Black);
+            return Err(ShellError::Failed);
+        }
+
+        writer.set_color(Color::Yellow, Color::Black);
+        writer.write_str("Write 0x");
+        self.write_hex(writer, value, size * 2);
+        writer.write_str(" to 0x");
+        self.write_hex(writer, addr as u32, 8);
+        writer.write_str("? This can crash the system (y/n) ");
+        writer.set_color(Color::White, Color::Black);
+
+        let confirmed = keyboard::is_yes(self.wait_for_key());
+        writer.write_str(if confirmed { "y\n" } else { "n\n" });
+        if !confirmed {
+            writer.write_str("Aborted\n");
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        unsafe {
+            match size {
+                1 => core::ptr::write_volatile(addr as *mut u8, value as u8),
+                2 => core::ptr::write_volatile(addr as *mut u16, value as u16),
+                _ => core::ptr::write_volatile(addr as *mut u32, value),
+            }
+        }
+        writer.set_color(Color::Green, Color::Black);
+        writer.write_str("Done\n");
+        writer.set_color(Color::White, Color::Black);
+        Ok(ExitCode::SUCCESS)
+    }
+
+    /// Map a b/w/d suffix to a byte count, checking the address alignment
+    /// and that the access doesn't run past the top of the address space
+    fn access_size(&self, arg: &[u8], addr: usize, writer: &mut dyn Sink) -> Result<usize, ShellError> {
+        let size = match arg {
+            b"" | b"b" => 1,
+            b"w" => 2,
+            b"d" => 4,
+            _ => {
                 writer.set_color(Color::Red, Color::Black);
-                writer.write_str("Error: ");
-                writer.write_str(e);
-                writer.write_byte(b'\n');
+                writer.write_str("Size must be b, w or d\n");
                 writer.set_color(Color::White, Color::Black);
+                return Err(ShellError::Failed);
             }
+        };
+        if addr % size != 0 {
+            writer.set_color(Color::Red, Color::Black);
+            writer.write_str("Address must be ");
+            self.write_number(writer, size);
+            writer.write_str("-byte aligned\n");
+            writer.set_color(Color::White, Color::Black);
+            return Err(ShellError::Failed);
         }
+        if addr.checked_add(size - 1).is_none() {
+            return Err(self.error(writer, "Address out of range"));
+        }
+        Ok(size)
     }
 
-    fn cmd_pwd(&self, writer: &mut Writer) {
-        let fs = get_filesystem();
-        let mut path_buffer = [0u8; 128];
-        let len = fs.get_current_path(&mut path_buffer);
-        writer.set_color(Color::LightCyan, Color::Black);
-        writer.write_bytes(&path_buffer[..len]);
+    /// Print `e` as an error; returns the error a command failing with it
+    /// ends with
+    fn error(&self, writer: &mut dyn Sink, e: &str) -> ShellError {
+        writer.set_color(Color::Red, Color::Black);
+        writer.write_str("Error: ");
+        writer.write_str(e);
         writer.write_byte(b'\n');
         writer.set_color(Color::White, Color::Black);
+        ShellError::Failed
     }
 
-    fn write_number(&self, writer: &mut Writer, mut num: usize) {
-        if num == 0 {
-            writer.write_byte(b'0');
-            return;
+    fn wait_for_key(&self) -> u8 {
+        loop {
+            if let Some(sc) = idt::get_scancode() {
+                if sc & 0x80 == 0 {
+                    return sc;
+                }
+            }
+            idt::wait_for_interrupt();
         }
-        
-        let mut digits = [0u8; 20];
-        let mut i = 0;
-        while num > 0 {
-            digits[i] = (num % 10) as u8 + b'0';
-            num /= 10;
-            i += 1;
+    }
+
+    fn write_hex(&self, writer: &mut dyn Sink, value: u32, digits: usize) {
+        for i in (0..digits).rev() {
+            let nibble = ((value >> (i * 4)) & 0xF) as u8;
+            writer.write_byte(if nibble < 10 { b'0' + nibble } else { b'A' + nibble - 10 });
         }
-        
-        while i > 0 {
-            i -= 1;
-            writer.write_byte(digits[i]);
+    }
+
+    fn write_number(&self, writer: &mut dyn Sink, num: usize) {
+        write!(writer, "{}", num);
+    }
+}
+
+/// Read a program by path, or look a bare name up in each `PATH` directory
+/// when it isn't in the current one
+fn find_program(name: &[u8]) -> Option<&'static [u8]> {
+    let fs: &'static FileSystem = get_filesystem();
+    if let Some(data) = fs.read_path(name) {
+        return Some(data);
+    }
+    if name.contains(&b'/') {
+        return None;
+    }
+    let mut path = [0u8; env::MAX_LINE_LEN];
+    env::get().get(b"PATH")?.split(|&b| b == b':').filter(|dir| !dir.is_empty()).find_map(|dir| {
+        let len = dir.len() + 1 + name.len();
+        let path = path.get_mut(..len)?;
+        path[..dir.len()].copy_from_slice(dir);
+        path[dir.len()] = b'/';
+        path[dir.len() + 1..].copy_from_slice(name);
+        fs.read_path(path)
+    })
+}
+
+fn is_screen_command(cmd: &[u8]) -> bool {
+    SCREEN_COMMANDS.contains(&next_arg(cmd).0)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Redirect<'a> {
+    None,
+    /// `> file`
+    Truncate(&'a [u8]),
+    /// `>> file`
+    Append(&'a [u8]),
+}
+
+impl Redirect<'_> {
+    fn is_none(&self) -> bool {
+        *self == Redirect::None
+    }
+}
+
+/// A command line split into `cmd | cmd | ...` stages, with the last one's
+/// output optionally sent to a file. `|` and `>` inside double quotes are
+/// left alone.
+struct Pipeline<'a> {
+    stages: [&'a [u8]; MAX_PIPELINE],
+    count: usize,
+    redirect: Redirect<'a>,
+}
+
+impl<'a> Pipeline<'a> {
+    fn parse(line: &'a [u8]) -> Result<Pipeline<'a>, &'static str> {
+        let (commands, redirect) = match find_unquoted(line, b'>') {
+            Some(pos) => {
+                let (append, rest) = match line.get(pos + 1) {
+                    Some(b'>') => (true, &line[pos + 2..]),
+                    _ => (false, &line[pos + 1..]),
+                };
+                let (name, extra) = next_arg(rest);
+                if name.is_empty() || !trim(extra).is_empty() || name.iter().any(|&b| b == b'>' || b == b'|') {
+                    return Err("Redirect needs one file name");
+                }
+                (&line[..pos], if append { Redirect::Append(name) } else { Redirect::Truncate(name) })
+            }
+            None => (line, Redirect::None),
+        };
+
+        let mut pipeline = Pipeline { stages: [&[]; MAX_PIPELINE], count: 0, redirect };
+        let mut rest = commands;
+        loop {
+            let end = find_unquoted(rest, b'|').unwrap_or(rest.len());
+            let stage = trim(&rest[..end]);
+            if stage.is_empty() {
+                return Err("Empty command in pipeline");
+            }
+            if pipeline.count == MAX_PIPELINE {
+                return Err("Too many commands in pipeline");
+            }
+            pipeline.stages[pipeline.count] = stage;
+            pipeline.count += 1;
+            if end == rest.len() {
+                return Ok(pipeline);
+            }
+            rest = &rest[end + 1..];
+        }
+    }
+
+    fn stages(&self) -> &[&'a [u8]] {
+        &self.stages[..self.count]
+    }
+}
+
+/// How a pipeline in a list follows on from the one before
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Connector {
+    First,
+    /// `&&`: runs if the status so far is 0
+    And,
+    /// `||`: runs if it isn't
+    Or,
+}
+
+/// A command line split into pipelines chained with `&&` and `||`. They
+/// are split before expansion, and not inside double quotes.
+struct List<'a> {
+    items: [(Connector, &'a [u8]); MAX_LIST],
+    count: usize,
+}
+
+impl<'a> List<'a> {
+    fn parse(line: &'a [u8]) -> Result<List<'a>, &'static str> {
+        let mut list = List { items: [(Connector::First, &[]); MAX_LIST], count: 0 };
+        let mut connector = Connector::First;
+        let mut rest = line;
+        loop {
+            let (end, next) = match find_connector(rest) {
+                Some((pos, next)) => (pos, Some(next)),
+                None => (rest.len(), None),
+            };
+            let item = trim(&rest[..end]);
+            if item.is_empty() && (next.is_some() || connector != Connector::First) {
+                return Err("Missing command next to && or ||");
+            }
+            if list.count == MAX_LIST {
+                return Err("Too many commands chained with && and ||");
+            }
+            list.items[list.count] = (connector, item);
+            list.count += 1;
+            match next {
+                Some(next) => {
+                    connector = next;
+                    rest = &rest[end + 2..];
+                }
+                None => return Ok(list),
+            }
+        }
+    }
+
+    fn items(&self) -> &[(Connector, &'a [u8])] {
+        &self.items[..self.count]
+    }
+}
+
+/// How a command finished, as a status for `$?`. Programs pick their own;
+/// built-in commands are `SUCCESS` or a `ShellError`, bar `diff`'s 1 for
+/// files that differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ExitCode(u8);
+
+impl ExitCode {
+    const SUCCESS: ExitCode = ExitCode(0);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ShellError {
+    /// The command printed an error
+    Failed,
+    /// No such command or program
+    NotFound,
+    /// The line didn't expand or parse
+    Syntax,
+    /// A program was killed by this exception vector
+    Killed(u8),
+    /// Stopped by Ctrl+C
+    Interrupted,
+}
+
+impl ShellError {
+    /// The status other shells give the same failure
+    fn status(self) -> u8 {
+        match self {
+            ShellError::Failed => 1,
+            ShellError::Syntax => 2,
+            ShellError::NotFound => 127,
+            ShellError::Killed(vector) => 128u8.saturating_add(vector),
+            // 128 plus SIGINT
+            ShellError::Interrupted => 130,
+        }
+    }
+}
+
+type CommandResult = Result<ExitCode, ShellError>;
+
+fn exit_status(result: CommandResult) -> u8 {
+    match result {
+        Ok(ExitCode(status)) => status,
+        Err(e) => e.status(),
+    }
+}
+
+/// Write `parts` one after another from `origin`, a screen cell counted
+/// from the top left, and blank what is left of the text that ended at
+/// `end`. Returns where the text starts now, higher up if it scrolled the
+/// screen.
+fn redraw_at(writer: &mut Writer, origin: usize, end: &mut usize, parts: &[&[u8]]) -> usize {
+    writer.set_position(origin % SCREEN_WIDTH, origin / SCREEN_WIDTH);
+    let len: usize = parts.iter().map(|part| part.len()).sum();
+    for part in parts {
+        writer.write_bytes(part);
+    }
+    let at = writer.get_row() * SCREEN_WIDTH + writer.get_col();
+    let scrolled = (origin + len).saturating_sub(at);
+    let blank = (writer.get_color() as u16) << 8 | b' ' as u16;
+    for cell in at..end.saturating_sub(scrolled) {
+        Writer::set_cell(cell % SCREEN_WIDTH, cell / SCREEN_WIDTH, blank);
+    }
+    *end = at;
+    at.saturating_sub(len)
+}
+
+/// The next command line for `xargs`: `command` and as many of the words of
+/// `items` as fit in `out`, up to `max_items`. Returns its length and the
+/// words left over.
+fn xargs_batch<'a>(command: &[u8], mut items: &'a [u8], max_items: usize, out: &mut [u8]) -> Result<(usize, &'a [u8]), &'static str> {
+    out.get_mut(..command.len()).ok_or("Command too long")?.copy_from_slice(command);
+    let mut len = command.len();
+    let mut count = 0;
+    loop {
+        let start = items.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(items.len());
+        items = &items[start..];
+        if items.is_empty() || count == max_items {
+            return Ok((len, items));
+        }
+        let end = items.iter().position(|b| b.is_ascii_whitespace()).unwrap_or(items.len());
+        match out.get_mut(len..len + 1 + end) {
+            Some(slot) => {
+                slot[0] = b' ';
+                slot[1..].copy_from_slice(&items[..end]);
+            }
+            None if count == 0 => return Err("Argument too long for a command line"),
+            None => return Ok((len, items)),
+        }
+        len += 1 + end;
+        count += 1;
+        items = &items[end..];
+    }
+}
+
+/// Position of the first `byte` outside double quotes
+fn find_unquoted(line: &[u8], byte: u8) -> Option<usize> {
+    let mut quoted = false;
+    line.iter().position(|&b| {
+        if b == b'"' {
+            quoted = !quoted;
+        }
+        b == byte && !quoted
+    })
+}
+
+/// Position of the first `&&` or `||` outside double quotes, and which
+fn find_connector(line: &[u8]) -> Option<(usize, Connector)> {
+    let mut quoted = false;
+    for (i, pair) in line.windows(2).enumerate() {
+        if pair[0] == b'"' {
+            quoted = !quoted;
+        } else if !quoted && pair == b"&&" {
+            return Some((i, Connector::And));
+        } else if !quoted && pair == b"||" {
+            return Some((i, Connector::Or));
+        }
+    }
+    None
+}
+
+/// Strip one pair of double quotes around `bytes`
+fn unquote(bytes: &[u8]) -> &[u8] {
+    match bytes {
+        [b'"', inner @ .., b'"'] => inner,
+        _ => bytes,
+    }
+}
+
+fn trim(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|&b| b != b' ').unwrap_or(bytes.len());
+    let end = bytes.iter().rposition(|&b| b != b' ').map_or(start, |i| i + 1);
+    &bytes[start..end]
+}
+
+/// Whether a line leaves a double quote or a bracket open
+fn is_unfinished(line: &[u8]) -> bool {
+    let mut quoted = false;
+    let mut depth = 0i32;
+    for &b in line {
+        match b {
+            b'"' => quoted = !quoted,
+            b'(' | b'[' | b'{' if !quoted => depth += 1,
+            b')' | b']' | b'}' if !quoted => depth -= 1,
+            _ => {}
+        }
+    }
+    quoted || depth > 0
+}
+
+/// Split off the first argument like `next_arg`, keeping one in double
+/// quotes together and taking the quotes off
+fn next_word(args: &[u8]) -> (&[u8], &[u8]) {
+    let args = &args[args.iter().position(|&b| b != b' ').unwrap_or(args.len())..];
+    let end = find_unquoted(args, b' ').unwrap_or(args.len());
+    (unquote(&args[..end]), &args[end..])
+}
+
+/// Split off the first space-separated argument, returning it and the rest
+fn next_arg(args: &[u8]) -> (&[u8], &[u8]) {
+    let mut start = 0;
+    while start < args.len() && args[start] == b' ' {
+        start += 1;
+    }
+    let mut end = start;
+    while end < args.len() && args[end] != b' ' {
+        end += 1;
+    }
+    (&args[start..end], &args[end..])
+}
+
+/// Go through the keys pressed so far; true on Ctrl+C or Esc
+fn ctrl_c_pressed() -> bool {
+    while let Some(scancode) = idt::get_scancode() {
+        if scancode == 0x01 {
+            return true;
         }
     }
+    signal::interrupted()
+}
+
+/// Parse a decimal or 0x-prefixed hexadecimal number
+fn parse_number(arg: &[u8]) -> Option<u32> {
+    if arg.is_empty() {
+        return None;
+    }
+    let (digits, radix) = if arg.len() > 2 && (&arg[..2] == b"0x" || &arg[..2] == b"0X") {
+        (&arg[2..], 16)
+    } else {
+        (arg, 10)
+    };
+
+    let mut value: u32 = 0;
+    for &d in digits {
+        let digit = match d {
+            b'0'..=b'9' => (d - b'0') as u32,
+            b'a'..=b'f' if radix == 16 => (d - b'a' + 10) as u32,
+            b'A'..=b'F' if radix == 16 => (d - b'A' + 10) as u32,
+            _ => return None,
+        };
+        value = value.checked_mul(radix)?.checked_add(digit)?;
+    }
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn pipeline_splits_stages_and_redirect() {
+        let pipeline = Pipeline::parse(b"ls | cat  >> out.txt").unwrap();
+        assert_eq!(pipeline.stages(), &[&b"ls"[..], &b"cat"[..]]);
+        assert_eq!(pipeline.redirect, Redirect::Append(b"out.txt"));
+
+        let pipeline = Pipeline::parse(b"echo hi>f").unwrap();
+        assert_eq!(pipeline.stages(), &[&b"echo hi"[..]]);
+        assert_eq!(pipeline.redirect, Redirect::Truncate(b"f"));
+
+        let pipeline = Pipeline::parse(b"set PROMPT \"a|b> \" | cat").unwrap();
+        assert_eq!(pipeline.stages(), &[&b"set PROMPT \"a|b> \""[..], &b"cat"[..]]);
+        assert_eq!(unquote(b"\"a|b> \""), b"a|b> ");
+        assert!(pipeline.redirect.is_none());
+
+        assert!(Pipeline::parse(b"ls |").is_err());
+        assert!(Pipeline::parse(b"ls >").is_err());
+        assert!(Pipeline::parse(b"ls > a b").is_err());
+    }
+
+    #[test_case]
+    fn lists_split_at_and_and_or() {
+        let list = List::parse(b"cd logs && cat \"a||b\" || echo none").unwrap();
+        assert_eq!(
+            list.items(),
+            &[(Connector::First, &b"cd logs"[..]), (Connector::And, &b"cat \"a||b\""[..]), (Connector::Or, &b"echo none"[..])]
+        );
+        assert_eq!(List::parse(b"ls | cat").unwrap().items(), &[(Connector::First, &b"ls | cat"[..])]);
+        assert!(List::parse(b"ls &&").is_err());
+        assert!(List::parse(b"|| ls").is_err());
+
+        assert_eq!(exit_status(Err(ShellError::NotFound)), 127);
+        assert_eq!(exit_status(Err(ShellError::Killed(14))), 142);
+        assert_eq!(exit_status(Err(ShellError::Interrupted)), 130);
+        assert_eq!(exit_status(Ok(ExitCode(3))), 3);
+    }
+
+    #[test_case]
+    fn open_quotes_and_brackets_continue_the_line() {
+        assert!(is_unfinished(b"echo \"two"));
+        assert!(is_unfinished(b"ls [ab"));
+        assert!(!is_unfinished(b"echo \"(\" [ab]"));
+        assert!(!is_unfinished(b"echo )"));
+    }
+
+    #[test_case]
+    fn xargs_fills_command_lines() {
+        let mut out = [0u8; 16];
+        let (len, rest) = xargs_batch(b"rm", b"a.log\n  b.log\nc.log\n", 2, &mut out).unwrap();
+        assert_eq!((&out[..len], rest), (&b"rm a.log b.log"[..], &b"c.log\n"[..]));
+        let (len, rest) = xargs_batch(b"rm", rest, 2, &mut out).unwrap();
+        assert_eq!((&out[..len], rest), (&b"rm c.log"[..], &b""[..]));
+
+        // Batches end where the line is full
+        let (len, rest) = xargs_batch(b"echo", b"12345 67890 x", usize::MAX, &mut out).unwrap();
+        assert_eq!((&out[..len], rest), (&b"echo 12345 67890"[..], &b"x"[..]));
+        assert!(xargs_batch(b"echo", b"0123456789abcdef", usize::MAX, &mut out).is_err());
+    }
 }
@@ -0,0 +1,144 @@
+use core::ptr;
+
+use crate::apic::{self, TickSource};
+use crate::idt;
+
+// Monotonic time since boot in nanoseconds. Each timer tick adds the tick
+// period, so reprogramming the timer only changes how much a tick is worth,
+// not what time it is. Between ticks the PIT's count fills in, while the
+// PIT is the tick source.
+
+const NS_PER_MS: u64 = 1_000_000;
+const NS_PER_SEC: u64 = 1_000_000_000;
+
+/// Nanoseconds per timer tick, set by whoever programs the timer
+static mut TICK_NS: u32 = 10_000_000;
+/// Time at the last tick
+static mut TICK_TIME_NS: u64 = 0;
+/// Latest time handed out, so interpolation never goes backwards
+static mut LAST_NS: u64 = 0;
+
+pub fn set_tick_period(ns: u32) {
+    unsafe {
+        TICK_NS = ns.max(1);
+    }
+}
+
+pub fn tick_period_ns() -> u32 {
+    unsafe { TICK_NS }
+}
+
+/// Called from the timer interrupt
+pub fn tick() {
+    unsafe {
+        TICK_TIME_NS += TICK_NS as u64;
+    }
+}
+
+pub fn now_ns() -> u64 {
+    unsafe {
+        // The tick can land between the reads; go again if it did
+        let now = loop {
+            let base = ptr::read_volatile(&TICK_TIME_NS);
+            let offset = since_tick_ns();
+            if ptr::read_volatile(&TICK_TIME_NS) == base {
+                break base + offset;
+            }
+        };
+        LAST_NS = LAST_NS.max(now);
+        LAST_NS
+    }
+}
+
+/// How far the PIT has counted towards the next tick, short of a whole
+/// tick in case that one is pending with interrupts off
+fn since_tick_ns() -> u64 {
+    if apic::is_enabled() && apic::tick_source() == TickSource::ApicTimer {
+        return 0;
+    }
+    let elapsed = idt::pit_divisor().saturating_sub(idt::pit_count()) as u64;
+    (elapsed * idt::PIT_PERIOD_PS as u64 / 1000).min(tick_period_ns() as u64 - 1)
+}
+
+pub fn uptime_ms() -> u64 {
+    now_ns() / NS_PER_MS
+}
+
+pub fn uptime_seconds() -> u32 {
+    (now_ns() / NS_PER_SEC) as u32
+}
+
+/// Wait at least `ms` milliseconds, halting between timer interrupts
+pub fn sleep_ms(ms: u32) {
+    let deadline = now_ns() + ms as u64 * NS_PER_MS;
+    while now_ns() < deadline {
+        idt::wait_for_interrupt();
+    }
+}
+
+/// A duration such as `500ms`, `2s`, `5m` or `1h30m` in milliseconds. Each
+/// part is a whole number and a unit (`ms`, `s`, `m`, `h` or `d`); a bare
+/// number is seconds.
+pub fn parse_duration(text: &[u8]) -> Result<u64, &'static str> {
+    const INVALID: &str = "Invalid duration (e.g. 500ms, 2s, 5m, 1h30m)";
+    if text.is_empty() {
+        return Err(INVALID);
+    }
+    if text.iter().all(u8::is_ascii_digit) {
+        return parse_count(text).and_then(|n| n.checked_mul(1000)).ok_or(INVALID);
+    }
+    let mut total: u64 = 0;
+    let mut rest = text;
+    while !rest.is_empty() {
+        let digits = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+        let unit_len = rest[digits..].iter().take_while(|b| b.is_ascii_alphabetic()).count();
+        let ms_per_unit = match &rest[digits..digits + unit_len] {
+            b"ms" => 1,
+            b"s" => 1000,
+            b"m" => 60_000,
+            b"h" => 3_600_000,
+            b"d" => 86_400_000,
+            _ => return Err(INVALID),
+        };
+        let part = parse_count(&rest[..digits]).and_then(|n| n.checked_mul(ms_per_unit)).ok_or(INVALID)?;
+        total = total.checked_add(part).ok_or(INVALID)?;
+        rest = &rest[digits + unit_len..];
+    }
+    Ok(total)
+}
+
+fn parse_count(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() {
+        return None;
+    }
+    digits.iter().try_fold(0u64, |n, &d| n.checked_mul(10)?.checked_add((d - b'0') as u64))
+}
+
+/// Nanoseconds per tick for a PIT divisor
+pub fn pit_tick_ns(divisor: u32) -> u32 {
+    (divisor as u64 * idt::PIT_PERIOD_PS as u64 / 1000) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn time_moves_forward_and_durations_parse() {
+        let before = now_ns();
+        assert!(now_ns() >= before);
+        assert_eq!(pit_tick_ns(11932) / 1000, 10_000);
+        assert_eq!(pit_tick_ns(1193) / 1000, 999);
+
+        assert_eq!(parse_duration(b"500ms"), Ok(500));
+        assert_eq!(parse_duration(b"2s"), Ok(2000));
+        assert_eq!(parse_duration(b"5m"), Ok(300_000));
+        assert_eq!(parse_duration(b"1h30m"), Ok(5_400_000));
+        assert_eq!(parse_duration(b"3"), Ok(3000));
+        assert!(parse_duration(b"").is_err());
+        assert!(parse_duration(b"1x").is_err());
+        assert!(parse_duration(b"h").is_err());
+        assert!(parse_duration(b"1h30").is_err());
+        assert!(parse_duration(b"99999999999999999999s").is_err());
+    }
+}
@@ -0,0 +1,173 @@
+use crate::sha256::{self, DIGEST_LEN};
+use crate::sync::Once;
+use crate::theme::Theme;
+
+const MAX_CMDLINE_LEN: usize = 256;
+const DEFAULT_SCREENSAVER_SECS: u32 = 600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Console {
+    Vga,
+    /// VGA plus a copy of all console output on COM1
+    Serial,
+}
+
+/// What the lock screen shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Saver {
+    /// Blank but for the uptime, moving every few seconds
+    Uptime,
+    /// The Game of Life from a random start
+    Life,
+}
+
+/// Settings taken from the boot command line, e.g.
+/// `loglevel=debug console=serial theme=matrix chime=off apic=on timer=pit
+/// screensaver=300 saver=life lockpass=<sha256 hex> watchdog=10`
+pub struct BootConfig {
+    pub log_level: LogLevel,
+    pub console: Console,
+    pub theme: Theme,
+    pub chime: bool,
+    /// Route interrupts through the local and I/O APICs when present
+    pub apic: bool,
+    /// Tick from the local APIC timer instead of the PIT (`timer=apic`)
+    pub apic_timer: bool,
+    /// Ask for an address with DHCP at boot (`dhcp=off` keeps the default)
+    pub dhcp: bool,
+    /// Lock the screen after this long at the prompt without a key; zero
+    /// never does
+    pub screensaver_secs: u32,
+    pub saver: Saver,
+    /// SHA-256 of the password that unlocks the screen, if it needs one
+    pub lock_hash: Option<[u8; DIGEST_LEN]>,
+    /// Panic if the kernel goes this long without getting back to the
+    /// shell or waiting; zero is off
+    pub watchdog_secs: u32,
+    cmdline: [u8; MAX_CMDLINE_LEN],
+    cmdline_len: usize,
+}
+
+impl BootConfig {
+    const fn new() -> Self {
+        Self {
+            log_level: LogLevel::Info,
+            console: Console::Vga,
+            theme: Theme::Default,
+            chime: true,
+            apic: true,
+            apic_timer: false,
+            dhcp: true,
+            screensaver_secs: DEFAULT_SCREENSAVER_SECS,
+            saver: Saver::Uptime,
+            lock_hash: None,
+            watchdog_secs: 0,
+            cmdline: [0; MAX_CMDLINE_LEN],
+            cmdline_len: 0,
+        }
+    }
+
+    pub fn parse(cmdline: &[u8]) -> Self {
+        let mut config = Self::new();
+        let len = cmdline.len().min(MAX_CMDLINE_LEN);
+        config.cmdline[..len].copy_from_slice(&cmdline[..len]);
+        config.cmdline_len = len;
+
+        for option in cmdline.split(|&b| b == b' ') {
+            let split = match option.iter().position(|&b| b == b'=') {
+                Some(split) => split,
+                // Bare words, like the kernel path GRUB prepends
+                None => continue,
+            };
+            let (key, value) = (&option[..split], &option[split + 1..]);
+            match key {
+                b"loglevel" => config.log_level = match value {
+                    b"error" => LogLevel::Error,
+                    b"warn" => LogLevel::Warn,
+                    b"debug" => LogLevel::Debug,
+                    _ => LogLevel::Info,
+                },
+                b"console" => config.console = match value {
+                    b"serial" => Console::Serial,
+                    _ => Console::Vga,
+                },
+                b"theme" => config.theme = Theme::from_name(value).unwrap_or(Theme::Default),
+                b"chime" => config.chime = !matches!(value, b"off" | b"0" | b"no"),
+                b"apic" => config.apic = !matches!(value, b"off" | b"0" | b"no"),
+                b"timer" => config.apic_timer = value == b"apic",
+                b"dhcp" => config.dhcp = !matches!(value, b"off" | b"0" | b"no"),
+                b"screensaver" => config.screensaver_secs = match value {
+                    b"off" => 0,
+                    _ => core::str::from_utf8(value).ok().and_then(|secs| secs.parse().ok()).unwrap_or(DEFAULT_SCREENSAVER_SECS),
+                },
+                b"saver" => config.saver = if value == b"life" { Saver::Life } else { Saver::Uptime },
+                b"lockpass" => config.lock_hash = sha256::parse_hex(value),
+                b"watchdog" => config.watchdog_secs = core::str::from_utf8(value).ok().and_then(|secs| secs.parse().ok()).unwrap_or(0),
+                _ => {}
+            }
+        }
+        config
+    }
+
+    pub fn command_line(&self) -> &[u8] {
+        &self.cmdline[..self.cmdline_len]
+    }
+
+    pub fn serial_console(&self) -> bool {
+        self.console == Console::Serial
+    }
+}
+
+static BOOT_CONFIG: Once<BootConfig> = Once::new();
+/// What `get` returns before `init`
+static DEFAULT_CONFIG: BootConfig = BootConfig::new();
+
+pub fn init(cmdline: &[u8]) {
+    BOOT_CONFIG.call_once(|| BootConfig::parse(cmdline));
+}
+
+pub fn get() -> &'static BootConfig {
+    BOOT_CONFIG.get().unwrap_or(&DEFAULT_CONFIG)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn parses_known_options() {
+        let config = BootConfig::parse(b"/boot/myos.bin loglevel=debug console=serial theme=light chime=off apic=off timer=apic dhcp=off screensaver=off saver=life watchdog=10");
+        assert_eq!(config.log_level, LogLevel::Debug);
+        assert_eq!(config.console, Console::Serial);
+        assert_eq!(config.theme, Theme::Light);
+        assert!(!config.chime);
+        assert!(!config.apic);
+        assert!(config.apic_timer);
+        assert!(!config.dhcp);
+        assert_eq!(config.screensaver_secs, 0);
+        assert_eq!(config.saver, Saver::Life);
+        assert_eq!(config.watchdog_secs, 10);
+    }
+
+    #[test_case]
+    fn unknown_values_fall_back_to_defaults() {
+        let config = BootConfig::parse(b"loglevel=loud bogus=1 theme");
+        assert_eq!(config.log_level, LogLevel::Info);
+        assert_eq!(config.console, Console::Vga);
+        assert!(config.chime);
+        assert!(config.apic);
+        assert!(!config.apic_timer);
+        assert_eq!(config.screensaver_secs, DEFAULT_SCREENSAVER_SECS);
+        assert_eq!(config.saver, Saver::Uptime);
+        assert_eq!(config.lock_hash, None);
+        assert_eq!(config.watchdog_secs, 0);
+    }
+}
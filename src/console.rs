@@ -0,0 +1,110 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::smp;
+use crate::sync::{self, SpinLock};
+use crate::vga_colors::{Color, color_code};
+use crate::writer::Writer;
+
+const NO_OWNER: usize = usize::MAX;
+
+/// The screen console shared by the shell and the drivers' suspend and
+/// resume. The lock is reentrant per CPU, so code running under the shell's
+/// guard, or an interrupt handler that interrupted it, can still take it.
+struct Console {
+    lock: SpinLock,
+    /// Index of the CPU holding `lock`
+    owner: AtomicUsize,
+    writer: UnsafeCell<Writer>,
+}
+
+unsafe impl Sync for Console {}
+
+static CONSOLE: Console = Console {
+    lock: SpinLock::new(),
+    owner: AtomicUsize::new(NO_OWNER),
+    writer: UnsafeCell::new(Writer::new(color_code(Color::White, Color::Black))),
+};
+
+pub struct ConsoleGuard {
+    /// Taken on a CPU that already held the console; releases nothing
+    nested: bool,
+}
+
+impl Deref for ConsoleGuard {
+    type Target = Writer;
+
+    fn deref(&self) -> &Writer {
+        unsafe { &*CONSOLE.writer.get() }
+    }
+}
+
+impl DerefMut for ConsoleGuard {
+    fn deref_mut(&mut self) -> &mut Writer {
+        unsafe { &mut *CONSOLE.writer.get() }
+    }
+}
+
+impl Drop for ConsoleGuard {
+    fn drop(&mut self) {
+        if !self.nested {
+            // An interrupt between these two would find the lock held by
+            // no one it recognises and spin on it forever
+            let interrupts = sync::disable_interrupts();
+            CONSOLE.owner.store(NO_OWNER, Ordering::Release);
+            CONSOLE.lock.unlock();
+            sync::restore_interrupts(interrupts);
+        }
+    }
+}
+
+/// Lock the console. This is also the reentrancy escape hatch: on a CPU
+/// that already holds it the guard is handed out at once instead of
+/// deadlocking, so output from an interrupt handler may land in the middle
+/// of the line it interrupted.
+pub fn lock() -> ConsoleGuard {
+    let cpu = smp::current_index();
+    if CONSOLE.owner.load(Ordering::Acquire) == cpu {
+        return ConsoleGuard { nested: true };
+    }
+    // Interrupts stay off until the owner is recorded, so a handler that
+    // prints never sees the lock taken but unowned on its own CPU
+    let interrupts = sync::disable_interrupts();
+    CONSOLE.lock.lock();
+    CONSOLE.owner.store(cpu, Ordering::Release);
+    sync::restore_interrupts(interrupts);
+    ConsoleGuard { nested: false }
+}
+
+const SCREEN_COLS: usize = 80;
+const SCREEN_ROWS: usize = 25;
+
+/// Text screen contents kept in RAM while video memory loses power
+static mut SAVED_SCREEN: [u16; SCREEN_COLS * SCREEN_ROWS] = [0; SCREEN_COLS * SCREEN_ROWS];
+
+pub fn suspend() -> Result<(), &'static str> {
+    for row in 0..SCREEN_ROWS {
+        for col in 0..SCREEN_COLS {
+            unsafe {
+                SAVED_SCREEN[row * SCREEN_COLS + col] = Writer::cell(col, row);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Put the saved screen back and check video memory kept it
+pub fn resume() -> Result<(), &'static str> {
+    for row in 0..SCREEN_ROWS {
+        for col in 0..SCREEN_COLS {
+            let cell = unsafe { SAVED_SCREEN[row * SCREEN_COLS + col] };
+            Writer::set_cell(col, row, cell);
+            if Writer::cell(col, row) != cell {
+                return Err("Video memory did not keep the screen");
+            }
+        }
+    }
+    lock().enable_cursor();
+    Ok(())
+}
@@ -0,0 +1,39 @@
+use core::arch::asm;
+
+pub fn cpuid(function: u32) -> (u32, u32, u32, u32) {
+    cpuid_count(function, 0)
+}
+
+pub fn cpuid_count(function: u32, subleaf: u32) -> (u32, u32, u32, u32) {
+    let eax: u32;
+    let ebx: u32;
+    let ecx: u32;
+    let edx: u32;
+
+    unsafe {
+        asm!(
+            "cpuid",
+            inout("eax") function => eax,
+            out("ebx") ebx,
+            inout("ecx") subleaf => ecx,
+            out("edx") edx,
+            options(nostack, preserves_flags)
+        );
+    }
+
+    (eax, ebx, ecx, edx)
+}
+
+pub fn read_msr(msr: u32) -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        asm!("rdmsr", in("ecx") msr, out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+    }
+    (high as u64) << 32 | low as u64
+}
+
+pub fn write_msr(msr: u32, value: u64) {
+    unsafe {
+        asm!("wrmsr", in("ecx") msr, in("eax") value as u32, in("edx") (value >> 32) as u32, options(nostack, preserves_flags));
+    }
+}
@@ -0,0 +1,407 @@
+use core::arch::asm;
+use core::fmt;
+use core::panic::PanicInfo;
+
+use crate::stackguard;
+use crate::vga_colors::{Color, color_code};
+use crate::writer::Writer;
+
+const MAX_FRAMES: usize = 16;
+
+extern "C" {
+    static stack_bottom: u8;
+    static stack_top: u8;
+    static __ksyms_start: u8;
+    static __ksyms_end: u8;
+}
+
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+pub struct Registers {
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+    pub esi: u32,
+    pub edi: u32,
+    pub ebp: u32,
+    pub esp: u32,
+    pub eip: u32,
+    pub eflags: u32,
+    pub cr0: u32,
+    pub cr2: u32,
+    pub cr3: u32,
+}
+
+impl Registers {
+    /// Snapshot the current CPU state (general registers are only
+    /// meaningful as of this call, and EIP is where it was inlined)
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let mut regs = Registers::default();
+        let ptr = &mut regs as *mut Registers;
+        unsafe {
+            asm!(
+                "mov [{p}], eax",
+                "mov [{p} + 4], ebx",
+                "mov [{p} + 8], ecx",
+                "mov [{p} + 12], edx",
+                "mov [{p} + 16], esi",
+                "mov [{p} + 20], edi",
+                "mov [{p} + 24], ebp",
+                "mov [{p} + 28], esp",
+                // The address of the next instruction, as near to the
+                // caller as an inlined capture gets
+                "call 2f",
+                "2:",
+                "pop {t}",
+                "mov [{p} + 32], {t}",
+                "pushfd",
+                "pop dword ptr [{p} + 36]",
+                "mov {t}, cr0",
+                "mov [{p} + 40], {t}",
+                "mov {t}, cr2",
+                "mov [{p} + 44], {t}",
+                "mov {t}, cr3",
+                "mov [{p} + 48], {t}",
+                p = in(reg) ptr,
+                t = out(reg) _,
+            );
+        }
+        regs
+    }
+}
+
+/// Adapter so `core::fmt` machinery can render straight to the screen
+struct FmtWriter<'a>(&'a mut Writer);
+
+impl<'a> fmt::Write for FmtWriter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_str(s);
+        Ok(())
+    }
+}
+
+/// Red screen of death for Rust panics
+pub fn panic(info: &PanicInfo) -> ! {
+    unsafe {
+        asm!("cli", options(nostack));
+    }
+    let regs = Registers::capture();
+
+    let mut writer = begin_report();
+    writer.write_str("KERNEL PANIC\n\n");
+    {
+        let mut out = FmtWriter(&mut writer);
+        let _ = fmt::write(&mut out, format_args!("{}\n", info.message()));
+        if let Some(location) = info.location() {
+            let _ = fmt::write(&mut out, format_args!("  at {}:{}:{}\n", location.file(), location.line(), location.column()));
+        }
+    }
+
+    dump_registers(&mut writer, &regs);
+    dump_stack_trace(&mut writer, regs.ebp);
+    halt();
+}
+
+/// Red screen of death for CPU exceptions raised by kernel code
+pub fn exception(name: &str, error_code: u32, eip: u32, ebp: u32) -> ! {
+    unsafe {
+        asm!("cli", options(nostack));
+    }
+    let mut regs = Registers::capture();
+    regs.eip = eip;
+    regs.ebp = ebp;
+
+    let mut writer = begin_report();
+    writer.write_str("CPU EXCEPTION: ");
+    writer.write_str(name);
+    writer.write_str("\n\n  error code ");
+    write_hex(&mut writer, error_code);
+    if let Some((symbol, offset)) = resolve_symbol(eip) {
+        writer.write_str("\n  in ");
+        write_truncated(&mut writer, symbol, 50);
+        writer.write_str("+0x");
+        write_hex_short(&mut writer, offset);
+    }
+    // An overflow is the likely cause of whatever fault followed it
+    if let Some(stack) = stackguard::overflowed_stack() {
+        writer.write_str("\n  after the ");
+        writer.write_str(stack);
+        writer.write_str(" stack overflowed");
+    }
+    writer.write_byte(b'\n');
+
+    dump_registers(&mut writer, &regs);
+    dump_stack_trace(&mut writer, ebp);
+    halt();
+}
+
+/// Red screen of death for a double fault, reported from its own task with
+/// `regs` as the faulting code left them
+pub fn double_fault(regs: &Registers) -> ! {
+    let mut writer = begin_report();
+    writer.write_str("CPU EXCEPTION: double fault\n\n");
+    writer.set_color(Color::White, Color::Red);
+    writer.write_str("  a fault while starting the handler for another, from a bad IDT entry\n");
+    writer.write_str("  or a broken kernel stack\n");
+    if let Some((symbol, offset)) = resolve_symbol(regs.eip) {
+        writer.write_str("  in ");
+        write_truncated(&mut writer, symbol, 50);
+        writer.write_str("+0x");
+        write_hex_short(&mut writer, offset);
+        writer.write_byte(b'\n');
+    }
+    writer.write_str("  stack pointer ");
+    match stackguard::stack_containing(regs.esp) {
+        Some(stack) => {
+            writer.write_str("in the ");
+            writer.write_str(stack);
+            writer.write_str(" stack");
+        }
+        None => writer.write_str("outside every kernel stack"),
+    }
+    if let Some(stack) = stackguard::overflowed_stack() {
+        writer.write_str(", and the ");
+        writer.write_str(stack);
+        writer.write_str(" stack has overflowed");
+    }
+    writer.write_byte(b'\n');
+
+    dump_registers(&mut writer, regs);
+    dump_stack_trace(&mut writer, regs.ebp);
+    halt();
+}
+
+/// Red screen of death for a stack canary found overwritten on the way out
+/// of the function containing `eip`. The frames past it may be garbage.
+pub fn stack_smashed(eip: u32, ebp: u32) -> ! {
+    unsafe {
+        asm!("cli", options(nostack));
+    }
+    let mut regs = Registers::capture();
+    regs.eip = eip;
+    regs.ebp = ebp;
+
+    let mut writer = begin_report();
+    writer.write_str("STACK SMASHING DETECTED
+
+");
+    writer.set_color(Color::White, Color::Red);
+    writer.write_str("  canary overwritten in ");
+    match resolve_symbol(eip) {
+        Some((symbol, offset)) => {
+            write_truncated(&mut writer, symbol, 50);
+            writer.write_str("+0x");
+            write_hex_short(&mut writer, offset);
+        }
+        None => {
+            write_hex(&mut writer, eip);
+            writer.write_str(" (build with SYMBOLS=1 for names)");
+        }
+    }
+    writer.write_byte(b'\n');
+
+    dump_registers(&mut writer, &regs);
+    dump_stack_trace(&mut writer, ebp);
+    halt();
+}
+
+/// Red screen of death for a stack whose floor was found written over by
+/// `stackguard`. The memory under that stack has been overwritten too.
+pub fn stack_overflow(name: &str) -> ! {
+    unsafe {
+        asm!("cli", options(nostack));
+    }
+    let regs = Registers::capture();
+
+    let mut writer = begin_report();
+    writer.write_str("STACK OVERFLOW\n\n");
+    writer.set_color(Color::White, Color::Red);
+    writer.write_str("  the ");
+    writer.write_str(name);
+    writer.write_str(" stack ran past its bottom\n");
+
+    dump_registers(&mut writer, &regs);
+    dump_stack_trace(&mut writer, regs.ebp);
+    halt();
+}
+
+/// Clear the screen to the crash colors and return a writer for it
+pub fn begin_report() -> Writer {
+    let mut writer = Writer::new(color_code(Color::White, Color::Red));
+    writer.clear();
+    writer.set_color(Color::Yellow, Color::Red);
+    writer
+}
+
+pub fn dump_registers(writer: &mut Writer, regs: &Registers) {
+    writer.set_color(Color::Yellow, Color::Red);
+    writer.write_str("\nRegisters:\n");
+    writer.set_color(Color::White, Color::Red);
+
+    let named = [
+        ("EAX", regs.eax), ("EBX", regs.ebx), ("ECX", regs.ecx), ("EDX", regs.edx),
+        ("ESI", regs.esi), ("EDI", regs.edi), ("EBP", regs.ebp), ("ESP", regs.esp),
+        ("EIP", regs.eip), ("EFL", regs.eflags), ("CR0", regs.cr0), ("CR2", regs.cr2),
+        ("CR3", regs.cr3),
+    ];
+    for (i, &(name, value)) in named.iter().enumerate() {
+        writer.write_str("  ");
+        writer.write_str(name);
+        writer.write_str("=");
+        write_hex(writer, value);
+        if i % 4 == 3 {
+            writer.write_byte(b'\n');
+        }
+    }
+    writer.write_byte(b'\n');
+}
+
+/// Walk the saved EBP chain and print each return address
+pub fn dump_stack_trace(writer: &mut Writer, ebp: u32) {
+    let mut frames = [0u32; MAX_FRAMES];
+    let count = stack_trace(ebp, &mut frames);
+    write_frames(writer, "\nCall stack:\n", &frames[..count]);
+}
+
+/// Fill `frames` with return addresses along the saved EBP chain, innermost
+/// first, and return how many were found
+pub fn stack_trace(mut ebp: u32, frames: &mut [u32]) -> usize {
+    let (low, high) = unsafe {
+        (&stack_bottom as *const u8 as u32, &stack_top as *const u8 as u32)
+    };
+
+    let mut count = 0;
+    while count < frames.len() {
+        if ebp == 0 || ebp < low || ebp + 8 > high || ebp & 3 != 0 {
+            break;
+        }
+        let (next, ret) = unsafe {
+            (*(ebp as *const u32), *((ebp + 4) as *const u32))
+        };
+        if ret == 0 {
+            break;
+        }
+        frames[count] = ret;
+        count += 1;
+
+        // Frames must move up the stack, otherwise the chain is corrupt
+        if next <= ebp {
+            break;
+        }
+        ebp = next;
+    }
+    count
+}
+
+fn write_frames(writer: &mut Writer, title: &str, frames: &[u32]) {
+    writer.set_color(Color::Yellow, Color::Red);
+    writer.write_str(title);
+    writer.set_color(Color::White, Color::Red);
+
+    for &ret in frames {
+        writer.write_str("  ");
+        write_hex(writer, ret);
+        if let Some((name, offset)) = resolve_symbol(ret) {
+            writer.write_str("  ");
+            write_truncated(writer, name, 50);
+            writer.write_str("+0x");
+            write_hex_short(writer, offset);
+        }
+        writer.write_byte(b'\n');
+    }
+}
+
+/// Report a lock misuse that would hang: where this CPU is now, and the
+/// stack recorded when the conflicting lock was taken
+pub fn deadlock(message: &str, lock: usize, other: &[u32]) -> ! {
+    unsafe {
+        asm!("cli", options(nostack));
+    }
+    let regs = Registers::capture();
+
+    let mut writer = begin_report();
+    writer.write_str("DEADLOCK\n\n");
+    writer.set_color(Color::White, Color::Red);
+    writer.write_str(message);
+    writer.write_str(" (lock at 0x");
+    write_hex(&mut writer, lock as u32);
+    writer.write_str(")\n");
+
+    let mut frames = [0u32; MAX_FRAMES];
+    let count = stack_trace(regs.ebp, &mut frames);
+    write_frames(&mut writer, "\nThis CPU, acquiring:\n", &frames[..count]);
+    write_frames(&mut writer, "\nConflicting acquisition:\n", other);
+    halt();
+}
+
+/// Look up `addr` in the embedded symbol table (built with `make SYMBOLS=1`).
+/// The table is `nm -n` output: one "<hex addr> <type> <name>" line per symbol.
+pub fn resolve_symbol(addr: u32) -> Option<(&'static [u8], u32)> {
+    let table = unsafe {
+        let start = &__ksyms_start as *const u8;
+        let end = &__ksyms_end as *const u8;
+        core::slice::from_raw_parts(start, end as usize - start as usize)
+    };
+
+    let mut best: Option<(&'static [u8], u32)> = None;
+    for line in table.split(|&b| b == b'\n') {
+        if line.len() < 12 {
+            continue;
+        }
+        let sym_addr = match parse_hex(&line[..8]) {
+            Some(a) => a,
+            None => continue,
+        };
+        if sym_addr > addr {
+            break;
+        }
+        best = Some((&line[11..], addr - sym_addr));
+    }
+    best
+}
+
+fn parse_hex(digits: &[u8]) -> Option<u32> {
+    let mut value: u32 = 0;
+    for &d in digits {
+        let nibble = match d {
+            b'0'..=b'9' => d - b'0',
+            b'a'..=b'f' => d - b'a' + 10,
+            b'A'..=b'F' => d - b'A' + 10,
+            _ => return None,
+        };
+        value = (value << 4) | nibble as u32;
+    }
+    Some(value)
+}
+
+pub fn halt() -> ! {
+    loop {
+        unsafe {
+            asm!("cli; hlt", options(nostack));
+        }
+    }
+}
+
+fn write_hex(writer: &mut Writer, value: u32) {
+    for i in (0..8).rev() {
+        let nibble = ((value >> (i * 4)) & 0xF) as u8;
+        writer.write_byte(if nibble < 10 { b'0' + nibble } else { b'A' + nibble - 10 });
+    }
+}
+
+fn write_hex_short(writer: &mut Writer, value: u32) {
+    let mut started = false;
+    for i in (0..8).rev() {
+        let nibble = ((value >> (i * 4)) & 0xF) as u8;
+        if nibble != 0 || started || i == 0 {
+            started = true;
+            writer.write_byte(if nibble < 10 { b'0' + nibble } else { b'a' + nibble - 10 });
+        }
+    }
+}
+
+fn write_truncated(writer: &mut Writer, s: &[u8], max_len: usize) {
+    writer.write_bytes(&s[..s.len().min(max_len)]);
+}
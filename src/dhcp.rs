@@ -0,0 +1,432 @@
+use crate::clock;
+use crate::idt;
+use crate::net::{self, Config, Ipv4Addr, UdpSocket};
+use crate::netdev::{self, MacAddress};
+use crate::tsc;
+
+// DHCP client: asks the network for an address, netmask, gateway and DNS
+// server and keeps the lease. `acquire` goes through DISCOVER, OFFER,
+// REQUEST and ACK while the caller waits. After that `poll`, called from
+// the shell's idle loop, renews with the server that gave the lease half
+// way through it, asks any server from seven eighths on and gives the
+// address up if the lease runs out.
+
+const SERVER_PORT: u16 = 67;
+const CLIENT_PORT: u16 = 68;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const OPTIONS_OFFSET: usize = 240;
+/// BOOTP's minimum message size
+const MESSAGE_LEN: usize = 300;
+/// Ask the server to broadcast its answers, as there is no address yet
+const FLAG_BROADCAST: u16 = 0x8000;
+
+const DISCOVER: u8 = 1;
+const OFFER: u8 = 2;
+const REQUEST: u8 = 3;
+const ACK: u8 = 5;
+const NAK: u8 = 6;
+
+const OPTION_PAD: u8 = 0;
+const OPTION_NETMASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS: u8 = 6;
+const OPTION_REQUESTED_IP: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_PARAMETERS: u8 = 55;
+const OPTION_RENEWAL_TIME: u8 = 58;
+const OPTION_REBINDING_TIME: u8 = 59;
+const OPTION_END: u8 = 255;
+
+const TRIES: u32 = 3;
+const REPLY_TIMEOUT_MS: u64 = 2000;
+/// Between requests while renewing or rebinding
+const RETRY_MS: u64 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// No lease
+    Init,
+    Selecting,
+    Requesting,
+    Bound,
+    /// Asking the server that gave the lease for more time
+    Renewing,
+    /// Asking any server, as the first one didn't answer
+    Rebinding,
+}
+
+impl State {
+    pub fn name(self) -> &'static str {
+        match self {
+            State::Init => "no lease",
+            State::Selecting => "selecting",
+            State::Requesting => "requesting",
+            State::Bound => "bound",
+            State::Renewing => "renewing",
+            State::Rebinding => "rebinding",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lease {
+    pub address: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+    pub dns: Ipv4Addr,
+    pub server: Ipv4Addr,
+    pub duration_secs: u32,
+    renewal_secs: u32,
+    rebinding_secs: u32,
+    /// Uptime when it was granted
+    pub obtained_ms: u64,
+}
+
+impl Lease {
+    pub fn renew_at_ms(&self) -> u64 {
+        self.obtained_ms + self.renewal_secs as u64 * 1000
+    }
+
+    pub fn rebind_at_ms(&self) -> u64 {
+        self.obtained_ms + self.rebinding_secs as u64 * 1000
+    }
+
+    pub fn expires_ms(&self) -> u64 {
+        self.obtained_ms + self.duration_secs as u64 * 1000
+    }
+}
+
+/// The parts of a server's message the client uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Reply {
+    kind: u8,
+    address: Ipv4Addr,
+    server: Ipv4Addr,
+    netmask: Ipv4Addr,
+    gateway: Ipv4Addr,
+    dns: Ipv4Addr,
+    lease_secs: u32,
+    renewal_secs: u32,
+    rebinding_secs: u32,
+}
+
+impl Reply {
+    fn lease(&self) -> Lease {
+        let duration = self.lease_secs.max(1);
+        let or = |value: u32, default: u32| if value != 0 && value < duration { value } else { default };
+        Lease {
+            address: self.address,
+            netmask: self.netmask,
+            gateway: self.gateway,
+            dns: self.dns,
+            server: self.server,
+            duration_secs: duration,
+            renewal_secs: or(self.renewal_secs, duration / 2),
+            rebinding_secs: or(self.rebinding_secs, duration / 8 * 7),
+            obtained_ms: clock::uptime_ms(),
+        }
+    }
+}
+
+static mut STATE: State = State::Init;
+static mut LEASE: Option<Lease> = None;
+/// Bound to port 68 while there is a lease to look after
+static mut SOCKET: Option<UdpSocket> = None;
+static mut XID: u32 = 0;
+static mut NEXT_RETRY_MS: u64 = 0;
+
+pub fn state() -> State {
+    unsafe { STATE }
+}
+
+pub fn lease() -> Option<Lease> {
+    unsafe { LEASE }
+}
+
+/// Get a lease from scratch and configure the network with it. On failure
+/// the configuration from before stays.
+pub fn acquire() -> Result<Lease, &'static str> {
+    let mac = netdev::devices().next().ok_or("No network interface")?.mac();
+    let previous = net::config();
+    unsafe {
+        LEASE = None;
+        SOCKET = None;
+    }
+    let socket = UdpSocket::bind(CLIENT_PORT)?;
+    // Until there is an address, send from 0.0.0.0 and hear broadcasts
+    net::configure(Config { address: Ipv4Addr::UNSPECIFIED, netmask: Ipv4Addr::UNSPECIFIED, gateway: Ipv4Addr::UNSPECIFIED, dns: previous.dns });
+
+    let result = negotiate(&socket, mac);
+    match result {
+        Ok(lease) => unsafe {
+            apply(&lease);
+            LEASE = Some(lease);
+            SOCKET = Some(socket);
+            STATE = State::Bound;
+        },
+        Err(_) => {
+            net::configure(previous);
+            unsafe {
+                STATE = State::Init;
+            }
+        }
+    }
+    result
+}
+
+fn negotiate(socket: &UdpSocket, mac: MacAddress) -> Result<Lease, &'static str> {
+    let xid = new_xid();
+    for _ in 0..TRIES {
+        unsafe {
+            STATE = State::Selecting;
+        }
+        send(socket, DISCOVER, xid, mac, Ipv4Addr::UNSPECIFIED, None, Ipv4Addr::BROADCAST)?;
+        let offer = match wait_reply(socket, xid, REPLY_TIMEOUT_MS) {
+            Some(reply) if reply.kind == OFFER => reply,
+            _ => continue,
+        };
+
+        unsafe {
+            STATE = State::Requesting;
+        }
+        let server = Some(offer.server);
+        send(socket, REQUEST, xid, mac, Ipv4Addr::UNSPECIFIED, Some((offer.address, server)), Ipv4Addr::BROADCAST)?;
+        match wait_reply(socket, xid, REPLY_TIMEOUT_MS) {
+            Some(reply) if reply.kind == ACK => return Ok(reply.lease()),
+            Some(reply) if reply.kind == NAK => return Err("Server refused the address"),
+            _ => continue,
+        }
+    }
+    Err("No DHCP server answered")
+}
+
+/// Look after the lease: renew, rebind or give it up when it is time
+pub fn poll() {
+    let lease = match lease() {
+        Some(lease) => lease,
+        None => return,
+    };
+    let socket = match unsafe { SOCKET.as_ref() } {
+        Some(socket) => socket,
+        None => return,
+    };
+    let now = clock::uptime_ms();
+
+    // Read everything, so strays can't fill the queue before the ACK
+    let mut buf = [0u8; net::MAX_UDP_PAYLOAD];
+    while let Some((len, _, _)) = socket.recv_from(&mut buf) {
+        if state() != State::Bound {
+            match parse_reply(&buf[..len], unsafe { XID }) {
+                Some(reply) if reply.kind == ACK => unsafe {
+                    let renewed = reply.lease();
+                    apply(&renewed);
+                    LEASE = Some(renewed);
+                    STATE = State::Bound;
+                    return;
+                },
+                Some(reply) if reply.kind == NAK => {
+                    give_up();
+                    return;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if now >= lease.expires_ms() {
+        give_up();
+        return;
+    }
+    let (next, server) = if now >= lease.rebind_at_ms() {
+        (State::Rebinding, Ipv4Addr::BROADCAST)
+    } else if now >= lease.renew_at_ms() {
+        (State::Renewing, lease.server)
+    } else {
+        return;
+    };
+    unsafe {
+        if STATE == next && now < NEXT_RETRY_MS {
+            return;
+        }
+        if STATE == State::Bound {
+            XID = new_xid();
+        }
+        STATE = next;
+        NEXT_RETRY_MS = now + RETRY_MS;
+        if let Some(device) = netdev::devices().next() {
+            let _ = send(socket, REQUEST, XID, device.mac(), lease.address, None, server);
+        }
+    }
+}
+
+/// The lease ran out or was refused: stop using its address
+fn give_up() {
+    let dns = net::config().dns;
+    net::configure(Config { address: Ipv4Addr::UNSPECIFIED, netmask: Ipv4Addr::UNSPECIFIED, gateway: Ipv4Addr::UNSPECIFIED, dns });
+    unsafe {
+        LEASE = None;
+        SOCKET = None;
+        STATE = State::Init;
+    }
+}
+
+fn apply(lease: &Lease) {
+    net::configure(Config { address: lease.address, netmask: lease.netmask, gateway: lease.gateway, dns: lease.dns });
+}
+
+fn new_xid() -> u32 {
+    let xid = tsc::read() as u32 ^ (clock::now_ns() >> 10) as u32;
+    unsafe {
+        XID = xid;
+    }
+    xid
+}
+
+fn wait_reply(socket: &UdpSocket, xid: u32, timeout_ms: u64) -> Option<Reply> {
+    let deadline = clock::uptime_ms() + timeout_ms;
+    let mut buf = [0u8; net::MAX_UDP_PAYLOAD];
+    while clock::uptime_ms() < deadline {
+        match socket.recv_from(&mut buf) {
+            Some((len, _, _)) => {
+                if let Some(reply) = parse_reply(&buf[..len], xid) {
+                    return Some(reply);
+                }
+            }
+            None => idt::wait_for_interrupt(),
+        }
+    }
+    None
+}
+
+/// `requested` is the offered address and the server that offered it,
+/// while selecting; renewing fills in `ciaddr` instead
+fn send(socket: &UdpSocket, kind: u8, xid: u32, mac: MacAddress, ciaddr: Ipv4Addr, requested: Option<(Ipv4Addr, Option<Ipv4Addr>)>, to: Ipv4Addr) -> Result<(), &'static str> {
+    let mut message = [0u8; MESSAGE_LEN];
+    build(&mut message, kind, xid, mac, ciaddr, requested);
+    socket.send_to(&message, to, SERVER_PORT)
+}
+
+fn build(message: &mut [u8; MESSAGE_LEN], kind: u8, xid: u32, mac: MacAddress, ciaddr: Ipv4Addr, requested: Option<(Ipv4Addr, Option<Ipv4Addr>)>) {
+    message[0] = 1;
+    message[1] = 1;
+    message[2] = 6;
+    message[4..8].copy_from_slice(&xid.to_be_bytes());
+    if ciaddr == Ipv4Addr::UNSPECIFIED {
+        message[10..12].copy_from_slice(&FLAG_BROADCAST.to_be_bytes());
+    }
+    message[12..16].copy_from_slice(&ciaddr.0);
+    message[28..34].copy_from_slice(&mac.0);
+    message[236..240].copy_from_slice(&MAGIC_COOKIE);
+
+    let mut options = OptionWriter { buf: &mut message[OPTIONS_OFFSET..], len: 0 };
+    options.put(OPTION_MESSAGE_TYPE, &[kind]);
+    if let Some((address, server)) = requested {
+        options.put(OPTION_REQUESTED_IP, &address.0);
+        if let Some(server) = server {
+            options.put(OPTION_SERVER_ID, &server.0);
+        }
+    }
+    options.put(OPTION_PARAMETERS, &[OPTION_NETMASK, OPTION_ROUTER, OPTION_DNS, OPTION_LEASE_TIME, OPTION_RENEWAL_TIME, OPTION_REBINDING_TIME]);
+    options.buf[options.len] = OPTION_END;
+}
+
+struct OptionWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl OptionWriter<'_> {
+    fn put(&mut self, code: u8, value: &[u8]) {
+        self.buf[self.len] = code;
+        self.buf[self.len + 1] = value.len() as u8;
+        self.buf[self.len + 2..self.len + 2 + value.len()].copy_from_slice(value);
+        self.len += 2 + value.len();
+    }
+}
+
+/// A server's answer to transaction `xid`, if that is what `message` is
+fn parse_reply(message: &[u8], xid: u32) -> Option<Reply> {
+    if message.len() < OPTIONS_OFFSET || message[0] != 2 || message[4..8] != xid.to_be_bytes() || message[236..240] != MAGIC_COOKIE {
+        return None;
+    }
+    let address = |bytes: &[u8]| Ipv4Addr([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let mut reply = Reply {
+        kind: 0,
+        address: address(&message[16..20]),
+        server: Ipv4Addr::UNSPECIFIED,
+        netmask: Ipv4Addr([255, 255, 255, 0]),
+        gateway: Ipv4Addr::UNSPECIFIED,
+        dns: Ipv4Addr::UNSPECIFIED,
+        lease_secs: 0,
+        renewal_secs: 0,
+        rebinding_secs: 0,
+    };
+
+    let mut options = &message[OPTIONS_OFFSET..];
+    loop {
+        let code = match options.first() {
+            None | Some(&OPTION_END) => break,
+            Some(&OPTION_PAD) => {
+                options = &options[1..];
+                continue;
+            }
+            Some(&code) => code,
+        };
+        let len = *options.get(1)? as usize;
+        let value = options.get(2..2 + len)?;
+        let secs = || if len >= 4 { u32::from_be_bytes([value[0], value[1], value[2], value[3]]) } else { 0 };
+        match code {
+            OPTION_MESSAGE_TYPE if len >= 1 => reply.kind = value[0],
+            // Routers and DNS servers are lists; the first one will do
+            OPTION_NETMASK if len >= 4 => reply.netmask = address(value),
+            OPTION_ROUTER if len >= 4 => reply.gateway = address(value),
+            OPTION_DNS if len >= 4 => reply.dns = address(value),
+            OPTION_SERVER_ID if len >= 4 => reply.server = address(value),
+            OPTION_LEASE_TIME => reply.lease_secs = secs(),
+            OPTION_RENEWAL_TIME => reply.renewal_secs = secs(),
+            OPTION_REBINDING_TIME => reply.rebinding_secs = secs(),
+            _ => {}
+        }
+        options = &options[2 + len..];
+    }
+    if reply.kind == 0 {
+        return None;
+    }
+    Some(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn replies_parse_and_leases_get_timers() {
+        let mac = MacAddress([0x52, 0x54, 0, 0x12, 0x34, 0x56]);
+        let mut message = [0u8; MESSAGE_LEN];
+        build(&mut message, REQUEST, 0x1234_5678, mac, Ipv4Addr::UNSPECIFIED, Some((Ipv4Addr([10, 0, 2, 15]), Some(Ipv4Addr([10, 0, 2, 2])))));
+        assert_eq!(&message[OPTIONS_OFFSET..OPTIONS_OFFSET + 3], &[OPTION_MESSAGE_TYPE, 1, REQUEST]);
+        // Our own request isn't a reply
+        assert_eq!(parse_reply(&message, 0x1234_5678), None);
+
+        // Turn it into the server's ACK
+        message[0] = 2;
+        message[16..20].copy_from_slice(&[10, 0, 2, 15]);
+        let mut options = OptionWriter { buf: &mut message[OPTIONS_OFFSET..], len: 0 };
+        options.put(OPTION_MESSAGE_TYPE, &[ACK]);
+        options.put(OPTION_SERVER_ID, &[10, 0, 2, 2]);
+        options.put(OPTION_ROUTER, &[10, 0, 2, 2]);
+        options.put(OPTION_DNS, &[10, 0, 2, 3]);
+        options.put(OPTION_LEASE_TIME, &86400u32.to_be_bytes());
+        options.buf[options.len] = OPTION_END;
+
+        assert_eq!(parse_reply(&message, 0x8765_4321), None);
+        let reply = parse_reply(&message, 0x1234_5678).unwrap();
+        assert_eq!((reply.kind, reply.address, reply.dns), (ACK, Ipv4Addr([10, 0, 2, 15]), Ipv4Addr([10, 0, 2, 3])));
+        let lease = reply.lease();
+        assert_eq!(lease.renew_at_ms() - lease.obtained_ms, 43_200_000);
+        assert_eq!(lease.rebind_at_ms() - lease.obtained_ms, 75_600_000);
+        assert_eq!(lease.expires_ms() - lease.obtained_ms, 86_400_000);
+    }
+}
@@ -0,0 +1,165 @@
+use crate::clock;
+use crate::idt;
+use crate::rtc::{self, DateTime};
+use crate::sink::{Capture, Sink};
+use crate::theme;
+use crate::vga_colors::{color_code, Color};
+use crate::writer::Writer;
+
+// `clock`: the time of day from the RTC in big block digits, centered on
+// the screen, with the date under it if asked for. A timer callback marks
+// when to look at the RTC again; the digits are redrawn when it has moved
+// on a second.
+
+const WIDTH: usize = 80;
+const HEIGHT: usize = 25;
+/// Screen cells per font pixel; two columns make it about square
+const PIXEL_WIDTH: usize = 2;
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+const DIGIT_GAP: usize = 2;
+/// HH:MM:SS with a gap on either side of each colon
+const CLOCK_WIDTH: usize = 6 * GLYPH_WIDTH * PIXEL_WIDTH + 3 * DIGIT_GAP + 2 * (PIXEL_WIDTH + 2 * DIGIT_GAP);
+/// Checked more often than once a second so the display doesn't lag the RTC
+const CHECK_INTERVAL_MS: u64 = 250;
+const BLOCK: u8 = 0xDB;
+
+/// 5x7 digits, one row per byte, the leftmost pixel in bit 4
+const DIGITS: [[u8; GLYPH_HEIGHT]; 10] = [
+    [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110],
+    [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+    [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111],
+    [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110],
+    [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010],
+    [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110],
+    [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110],
+    [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000],
+    [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110],
+    [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100],
+];
+/// Rows of the colon's single column
+const COLON: [u8; GLYPH_HEIGHT] = [0, 0, 1, 0, 1, 0, 0];
+
+static mut CHECK_DUE: bool = false;
+static mut NEXT_CHECK_MS: u64 = 0;
+
+fn on_tick(_ticks: u32) {
+    unsafe {
+        let now = clock::uptime_ms();
+        if now >= NEXT_CHECK_MS {
+            NEXT_CHECK_MS = now + CHECK_INTERVAL_MS;
+            CHECK_DUE = true;
+        }
+    }
+}
+
+pub struct DigitalClock {
+    show_date: bool,
+    top: usize,
+    attribute: u8,
+}
+
+impl DigitalClock {
+    pub fn new(show_date: bool) -> Self {
+        let height = if show_date { GLYPH_HEIGHT + 2 } else { GLYPH_HEIGHT };
+        Self {
+            show_date,
+            top: (HEIGHT - height) / 2,
+            attribute: color_code(theme::palette().heading, Color::Black),
+        }
+    }
+
+    /// Show the time until a key is pressed
+    pub fn run(&mut self, writer: &mut Writer) -> Result<(), &'static str> {
+        unsafe {
+            NEXT_CHECK_MS = 0;
+            CHECK_DUE = true;
+        }
+        let callback = idt::add_timer_callback(on_tick)?;
+
+        idt::flush_buffer();
+        writer.set_color(Color::Black, Color::Black);
+        writer.clear();
+        writer.disable_cursor();
+
+        let mut shown: Option<DateTime> = None;
+        loop {
+            if let Some(scancode) = idt::get_scancode() {
+                if scancode & 0x80 == 0 {
+                    break;
+                }
+            }
+            if unsafe { core::mem::replace(&mut CHECK_DUE, false) } {
+                let now = rtc::now();
+                if shown != Some(now) {
+                    self.draw(writer, &now);
+                    shown = Some(now);
+                }
+            }
+            idt::wait_for_interrupt();
+        }
+
+        idt::remove_timer_callback(callback);
+        writer.enable_cursor();
+        writer.set_color(Color::White, Color::Black);
+        writer.clear();
+        Ok(())
+    }
+
+    fn draw(&self, writer: &mut Writer, now: &DateTime) {
+        let digits = [now.hour / 10, now.hour % 10, now.minute / 10, now.minute % 10, now.second / 10, now.second % 10];
+        let mut col = (WIDTH - CLOCK_WIDTH) / 2;
+        for (i, &digit) in digits.iter().enumerate() {
+            col = self.draw_glyph(col, &DIGITS[digit as usize], GLYPH_WIDTH);
+            if i % 2 == 0 {
+                col += DIGIT_GAP;
+            } else if i < 5 {
+                col = self.draw_glyph(col + DIGIT_GAP, &COLON, 1) + DIGIT_GAP;
+            }
+        }
+
+        if self.show_date {
+            // Dates differ in length, so the whole line is redrawn
+            let mut line = [0; WIDTH];
+            let len = format_date(now, &mut line);
+            writer.set_color_code(self.attribute);
+            writer.set_position(0, self.top + GLYPH_HEIGHT + 1);
+            writer.pad_to((WIDTH - len) / 2);
+            writer.write_bytes(&line[..len]);
+            writer.pad_to(WIDTH - 1);
+        }
+    }
+
+    /// Draw a glyph `width` pixels wide at `col`; returns the column after it
+    fn draw_glyph(&self, col: usize, rows: &[u8; GLYPH_HEIGHT], width: usize) -> usize {
+        for (y, &bits) in rows.iter().enumerate() {
+            for x in 0..width * PIXEL_WIDTH {
+                let lit = bits >> (width - 1 - x / PIXEL_WIDTH) & 1 != 0;
+                let cell = if lit { BLOCK } else { b' ' };
+                Writer::set_cell(col + x, self.top + y, (self.attribute as u16) << 8 | cell as u16);
+            }
+        }
+        col + width * PIXEL_WIDTH
+    }
+}
+
+/// `Friday, 16 October 2026` into `out`; returns its length
+fn format_date(date: &DateTime, out: &mut [u8]) -> usize {
+    let mut text = Capture::new(out);
+    write!(text, "{}, {} {} {}", date.weekday_name(), date.day, date.month_name(), date.year);
+    text.as_bytes().len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn clock_fits_the_screen_and_dates_read_naturally() {
+        assert!(CLOCK_WIDTH <= WIDTH);
+        let date = DateTime { year: 2026, month: 10, day: 16, hour: 9, minute: 5, second: 0 };
+        let mut out = [0u8; WIDTH];
+        let len = format_date(&date, &mut out);
+        assert_eq!(&out[..len], b"Friday, 16 October 2026");
+    }
+}
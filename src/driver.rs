@@ -0,0 +1,116 @@
+use core::arch::asm;
+
+use crate::console;
+use crate::mouse;
+use crate::serial;
+
+// Registry of device drivers. For now a driver is a name and the callbacks
+// that quiesce its hardware and bring it back, used around S3 and by
+// `drvtest`.
+
+const MAX_DRIVERS: usize = 8;
+
+#[derive(Clone, Copy)]
+pub struct Driver {
+    pub name: &'static str,
+    /// Quiesce the device before it loses power; an error aborts a suspend
+    pub suspend: fn() -> Result<(), &'static str>,
+    /// Reinitialize the device and check it responds
+    pub resume: fn() -> Result<(), &'static str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    Suspend(&'static str),
+    Resume(&'static str),
+}
+
+static mut DRIVERS: [Option<Driver>; MAX_DRIVERS] = [None; MAX_DRIVERS];
+
+/// Register the drivers for devices set up during early boot. Suspended in
+/// this order and resumed in reverse, so the mouse cursor is off the
+/// screen while the console saves it.
+pub fn init() -> Result<(), &'static str> {
+    register(Driver { name: "serial", suspend: serial::suspend, resume: serial::resume })?;
+    register(Driver { name: "mouse", suspend: mouse::suspend, resume: mouse::resume })?;
+    register(Driver { name: "console", suspend: console::suspend, resume: console::resume })
+}
+
+pub fn register(driver: Driver) -> Result<(), &'static str> {
+    unsafe {
+        let slot = DRIVERS.iter_mut().find(|slot| slot.is_none()).ok_or("No free driver slots")?;
+        *slot = Some(driver);
+    }
+    Ok(())
+}
+
+/// Registered drivers in registration order. Slots fill in order and are
+/// never freed.
+pub fn drivers() -> &'static [Option<Driver>] {
+    unsafe {
+        let count = DRIVERS.iter().position(|slot| slot.is_none()).unwrap_or(MAX_DRIVERS);
+        &DRIVERS[..count]
+    }
+}
+
+/// Suspend every driver. If one refuses, the ones before it are resumed
+/// and the refusing driver is returned with its reason.
+pub fn suspend_all() -> Result<(), (&'static str, &'static str)> {
+    for (i, driver) in drivers().iter().flatten().enumerate() {
+        if let Err(e) = (driver.suspend)() {
+            resume_first(i);
+            return Err((driver.name, e));
+        }
+    }
+    Ok(())
+}
+
+/// Resume every driver, last registered first. Failures are logged and
+/// counted, and the rest still get resumed.
+pub fn resume_all() -> usize {
+    resume_first(drivers().len())
+}
+
+fn resume_first(count: usize) -> usize {
+    let mut failed = 0;
+    for driver in drivers()[..count].iter().rev().flatten() {
+        if let Err(e) = (driver.resume)() {
+            log_warn!("{}: resume failed: {}", driver.name, e);
+            failed += 1;
+        }
+    }
+    failed
+}
+
+/// Suspend and resume one driver with interrupts off, as they are around
+/// S3, to check it can bring its hardware back
+pub fn cycle(driver: &Driver) -> Result<(), Failure> {
+    unsafe {
+        asm!("cli", options(nostack));
+    }
+    let result = (driver.suspend)().map_err(Failure::Suspend).and_then(|()| (driver.resume)().map_err(Failure::Resume));
+    unsafe {
+        asm!("sti", options(nostack));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ok() -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn dead() -> Result<(), &'static str> {
+        Err("no response")
+    }
+
+    #[test_case]
+    fn cycle_reports_which_step_failed() {
+        assert_eq!(cycle(&Driver { name: "good", suspend: ok, resume: ok }), Ok(()));
+        assert_eq!(cycle(&Driver { name: "stuck", suspend: dead, resume: ok }), Err(Failure::Suspend("no response")));
+        assert_eq!(cycle(&Driver { name: "gone", suspend: ok, resume: dead }), Err(Failure::Resume("no response")));
+    }
+}
@@ -0,0 +1,429 @@
+use core::arch::naked_asm;
+use core::ptr::{read_volatile, write_volatile};
+
+use crate::apic;
+use crate::driver::{self, Driver};
+use crate::gdt;
+use crate::idt;
+use crate::netdev::{self, FrameQueue, MacAddress, NetDevice, Stats, MAX_FRAME_LEN};
+use crate::pci::{self, PciDevice};
+use crate::stackguard;
+use crate::sync::Mutex;
+
+// Intel 8254x (e1000), the card QEMU emulates by default. The card reads
+// and writes descriptor rings and packet buffers in memory on its own;
+// without paging their addresses are the physical addresses it needs.
+// The interrupt handler moves received frames into a queue, so nothing is
+// lost while the shell is busy, and hands each descriptor straight back.
+
+const VENDOR_INTEL: u16 = 0x8086;
+/// 82540EM (QEMU's `e1000`) and 82545EM
+const DEVICE_IDS: [u16; 2] = [0x100E, 0x100F];
+
+const CTRL: usize = 0x0000;
+const STATUS: usize = 0x0008;
+const EERD: usize = 0x0014;
+const ICR: usize = 0x00C0;
+const IMS: usize = 0x00D0;
+const IMC: usize = 0x00D8;
+const RCTL: usize = 0x0100;
+const TCTL: usize = 0x0400;
+const TIPG: usize = 0x0410;
+const RDBAL: usize = 0x2800;
+const RDBAH: usize = 0x2804;
+const RDLEN: usize = 0x2808;
+const RDH: usize = 0x2810;
+const RDT: usize = 0x2818;
+const TDBAL: usize = 0x3800;
+const TDBAH: usize = 0x3804;
+const TDLEN: usize = 0x3808;
+const TDH: usize = 0x3810;
+const TDT: usize = 0x3818;
+/// Multicast table, 128 registers
+const MTA: usize = 0x5200;
+const RAL0: usize = 0x5400;
+const RAH0: usize = 0x5404;
+
+const CTRL_ASDE: u32 = 1 << 5;
+const CTRL_SLU: u32 = 1 << 6;
+const CTRL_RST: u32 = 1 << 26;
+const STATUS_LU: u32 = 1 << 1;
+const EERD_START: u32 = 1 << 0;
+const EERD_DONE: u32 = 1 << 4;
+/// Receive address valid
+const RAH_AV: u32 = 1 << 31;
+
+const RCTL_EN: u32 = 1 << 1;
+/// Accept broadcasts
+const RCTL_BAM: u32 = 1 << 15;
+/// Strip the FCS
+const RCTL_SECRC: u32 = 1 << 26;
+const TCTL_EN: u32 = 1 << 1;
+/// Pad short packets
+const TCTL_PSP: u32 = 1 << 3;
+const TCTL_CT: u32 = 0x10 << 4;
+const TCTL_COLD: u32 = 0x40 << 12;
+/// Inter-packet gap recommended for copper
+const TIPG_DEFAULT: u32 = 0x0060_200A;
+
+/// Interrupt causes: link change, receive ring low, overrun, frame received
+const INT_LSC: u32 = 1 << 2;
+const INT_RXDMT0: u32 = 1 << 4;
+const INT_RXO: u32 = 1 << 6;
+const INT_RXT0: u32 = 1 << 7;
+const INT_RECEIVE: u32 = INT_RXDMT0 | INT_RXO | INT_RXT0;
+
+const DESC_DD: u8 = 1 << 0;
+const DESC_EOP: u8 = 1 << 1;
+const TX_CMD_EOP: u8 = 1 << 0;
+const TX_CMD_IFCS: u8 = 1 << 1;
+const TX_CMD_RS: u8 = 1 << 3;
+
+const RX_COUNT: usize = 32;
+const TX_COUNT: usize = 8;
+/// RCTL's default buffer size
+const BUFFER_SIZE: usize = 2048;
+const RESET_SPINS: u32 = 1_000_000;
+
+/// PCI interrupts are level triggered and active low
+const PCI_IRQ_FLAGS: u16 = 0x0F;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RxDescriptor {
+    addr: u64,
+    length: u16,
+    checksum: u16,
+    status: u8,
+    errors: u8,
+    special: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct TxDescriptor {
+    addr: u64,
+    length: u16,
+    cso: u8,
+    cmd: u8,
+    status: u8,
+    css: u8,
+    special: u16,
+}
+
+/// Ring lengths must be multiples of 128 bytes
+#[repr(C, align(128))]
+struct Rings {
+    rx: [RxDescriptor; RX_COUNT],
+    tx: [TxDescriptor; TX_COUNT],
+}
+
+#[repr(C, align(16))]
+struct Buffers {
+    rx: [[u8; BUFFER_SIZE]; RX_COUNT],
+    tx: [[u8; BUFFER_SIZE]; TX_COUNT],
+}
+
+const EMPTY_RX: RxDescriptor = RxDescriptor { addr: 0, length: 0, checksum: 0, status: 0, errors: 0, special: 0 };
+const EMPTY_TX: TxDescriptor = TxDescriptor { addr: 0, length: 0, cso: 0, cmd: 0, status: 0, css: 0, special: 0 };
+
+static mut RINGS: Rings = Rings { rx: [EMPTY_RX; RX_COUNT], tx: [EMPTY_TX; TX_COUNT] };
+static mut BUFFERS: Buffers = Buffers { rx: [[0; BUFFER_SIZE]; RX_COUNT], tx: [[0; BUFFER_SIZE]; TX_COUNT] };
+
+struct Nic {
+    mmio: usize,
+    mac: MacAddress,
+    /// Next receive descriptor the card will fill
+    rx_next: usize,
+    /// Next transmit descriptor to hand to the card
+    tx_next: usize,
+    stats: Stats,
+}
+
+impl Nic {
+    fn read(&self, reg: usize) -> u32 {
+        unsafe { read_volatile((self.mmio + reg) as *const u32) }
+    }
+
+    fn write(&self, reg: usize, value: u32) {
+        unsafe { write_volatile((self.mmio + reg) as *mut u32, value) }
+    }
+}
+
+static NIC: Mutex<Option<Nic>> = Mutex::new(None);
+static RX_QUEUE: Mutex<FrameQueue> = Mutex::new(FrameQueue::new());
+static mut NIC_IRQ: u8 = 0;
+
+/// Find the card, set up its rings and start receiving. Runs after
+/// `apic::init`, like `sci::init`. Returns the card's IRQ.
+pub fn init() -> Result<u8, &'static str> {
+    let device = find().ok_or("No e1000 card")?;
+    let bar0 = device.read(0x10);
+    if bar0 & 1 != 0 {
+        return Err("BAR0 is not memory mapped");
+    }
+    let irq = device.interrupt_line();
+    if irq >= 16 {
+        return Err("No legacy IRQ assigned");
+    }
+    device.enable_bus_master();
+
+    let mut nic = Nic { mmio: device.bar(0) as usize, mac: MacAddress([0; 6]), rx_next: 0, tx_next: 0, stats: Stats::default() };
+    reset(&mut nic)?;
+    *NIC.lock() = Some(nic);
+    unsafe {
+        NIC_IRQ = irq;
+    }
+
+    netdev::register(&DEVICE)?;
+    driver::register(Driver { name: "e1000", suspend, resume })?;
+
+    idt::set_gate(0x20 + irq, e1000_interrupt_handler as u32, false);
+    if apic::is_enabled() {
+        apic::enable_irq(irq, PCI_IRQ_FLAGS);
+    } else {
+        idt::unmask_pic_irq(irq);
+    }
+    Ok(irq)
+}
+
+fn find() -> Option<PciDevice> {
+    DEVICE_IDS.iter().filter_map(|&id| pci::find_device(VENDOR_INTEL, id)).next()
+}
+
+/// Reset the card and program it from scratch: after boot and after a
+/// resume, which loses its state
+fn reset(nic: &mut Nic) -> Result<(), &'static str> {
+    nic.write(IMC, 0xFFFF_FFFF);
+    nic.write(CTRL, nic.read(CTRL) | CTRL_RST);
+    let mut spins = 0;
+    while nic.read(CTRL) & CTRL_RST != 0 {
+        spins += 1;
+        if spins == RESET_SPINS {
+            return Err("Reset did not complete");
+        }
+    }
+    nic.write(IMC, 0xFFFF_FFFF);
+    nic.read(ICR);
+    nic.write(CTRL, nic.read(CTRL) | CTRL_SLU | CTRL_ASDE);
+
+    nic.mac = read_mac(nic);
+    let mac = nic.mac.0;
+    nic.write(RAL0, u32::from_le_bytes([mac[0], mac[1], mac[2], mac[3]]));
+    nic.write(RAH0, u16::from_le_bytes([mac[4], mac[5]]) as u32 | RAH_AV);
+    for i in 0..128 {
+        nic.write(MTA + i * 4, 0);
+    }
+
+    unsafe {
+        for i in 0..RX_COUNT {
+            RINGS.rx[i] = EMPTY_RX;
+            RINGS.rx[i].addr = BUFFERS.rx[i].as_ptr() as u64;
+        }
+        // Every transmit slot starts out done, i.e. free
+        for i in 0..TX_COUNT {
+            RINGS.tx[i] = EMPTY_TX;
+            RINGS.tx[i].status = DESC_DD;
+        }
+        nic.write(RDBAL, RINGS.rx.as_ptr() as u32);
+        nic.write(TDBAL, RINGS.tx.as_ptr() as u32);
+    }
+    nic.write(RDBAH, 0);
+    nic.write(RDLEN, (RX_COUNT * 16) as u32);
+    nic.write(RDH, 0);
+    nic.write(RDT, RX_COUNT as u32 - 1);
+    nic.write(TDBAH, 0);
+    nic.write(TDLEN, (TX_COUNT * 16) as u32);
+    nic.write(TDH, 0);
+    nic.write(TDT, 0);
+    nic.rx_next = 0;
+    nic.tx_next = 0;
+
+    nic.write(RCTL, RCTL_EN | RCTL_BAM | RCTL_SECRC);
+    nic.write(TCTL, TCTL_EN | TCTL_PSP | TCTL_CT | TCTL_COLD);
+    nic.write(TIPG, TIPG_DEFAULT);
+    nic.write(IMS, INT_RECEIVE | INT_LSC);
+    Ok(())
+}
+
+/// The address the firmware left in the receive filter, else the EEPROM's
+fn read_mac(nic: &Nic) -> MacAddress {
+    let high = nic.read(RAH0);
+    if high & RAH_AV != 0 {
+        let low = nic.read(RAL0).to_le_bytes();
+        let high = high.to_le_bytes();
+        return MacAddress([low[0], low[1], low[2], low[3], high[0], high[1]]);
+    }
+    let mut mac = [0u8; 6];
+    for word in 0..3 {
+        let value = read_eeprom(nic, word as u8).to_le_bytes();
+        mac[word * 2] = value[0];
+        mac[word * 2 + 1] = value[1];
+    }
+    MacAddress(mac)
+}
+
+fn read_eeprom(nic: &Nic, word: u8) -> u16 {
+    nic.write(EERD, (word as u32) << 8 | EERD_START);
+    for _ in 0..RESET_SPINS {
+        let value = nic.read(EERD);
+        if value & EERD_DONE != 0 {
+            return (value >> 16) as u16;
+        }
+    }
+    0
+}
+
+/// Hand every filled receive descriptor's frame to the queue and the
+/// descriptor back to the card
+fn drain_rx(nic: &mut Nic) {
+    let mut queue = RX_QUEUE.lock();
+    loop {
+        let i = nic.rx_next;
+        unsafe {
+            let desc = &mut RINGS.rx[i];
+            if read_volatile(&desc.status) & DESC_DD == 0 {
+                break;
+            }
+            let len = read_volatile(&desc.length) as usize;
+            if read_volatile(&desc.status) & DESC_EOP == 0 || read_volatile(&desc.errors) != 0 || len > MAX_FRAME_LEN {
+                nic.stats.rx_errors += 1;
+            } else if queue.push(&BUFFERS.rx[i][..len]) {
+                nic.stats.rx_packets += 1;
+                nic.stats.rx_bytes += len as u64;
+            } else {
+                nic.stats.rx_dropped += 1;
+            }
+            write_volatile(&mut desc.status, 0);
+        }
+        nic.write(RDT, i as u32);
+        nic.rx_next = (i + 1) % RX_COUNT;
+    }
+}
+
+fn suspend() -> Result<(), &'static str> {
+    let guard = NIC.lock();
+    let nic = guard.as_ref().ok_or("Not initialised")?;
+    nic.write(IMC, 0xFFFF_FFFF);
+    nic.write(RCTL, 0);
+    nic.write(TCTL, 0);
+    Ok(())
+}
+
+fn resume() -> Result<(), &'static str> {
+    let device = find().ok_or("Card is gone")?;
+    device.enable_bus_master();
+    let mut guard = NIC.lock();
+    let nic = guard.as_mut().ok_or("Not initialised")?;
+    RX_QUEUE.lock().clear();
+    reset(nic)
+}
+
+struct E1000;
+
+static DEVICE: E1000 = E1000;
+
+impl NetDevice for E1000 {
+    fn name(&self) -> &'static str {
+        "eth0"
+    }
+
+    fn driver(&self) -> &'static str {
+        "e1000"
+    }
+
+    fn mac(&self) -> MacAddress {
+        NIC.lock().as_ref().map_or(MacAddress([0; 6]), |nic| nic.mac)
+    }
+
+    fn link_up(&self) -> bool {
+        NIC.lock().as_ref().map_or(false, |nic| nic.read(STATUS) & STATUS_LU != 0)
+    }
+
+    fn send(&self, frame: &[u8]) -> Result<(), &'static str> {
+        if frame.len() > MAX_FRAME_LEN {
+            return Err("Frame too long");
+        }
+        let mut guard = NIC.lock();
+        let nic = guard.as_mut().ok_or("Not initialised")?;
+        let i = nic.tx_next;
+        unsafe {
+            let desc = &mut RINGS.tx[i];
+            if read_volatile(&desc.status) & DESC_DD == 0 {
+                nic.stats.tx_errors += 1;
+                return Err("Transmit ring full");
+            }
+            BUFFERS.tx[i][..frame.len()].copy_from_slice(frame);
+            write_volatile(desc, TxDescriptor {
+                addr: BUFFERS.tx[i].as_ptr() as u64,
+                length: frame.len() as u16,
+                cmd: TX_CMD_EOP | TX_CMD_IFCS | TX_CMD_RS,
+                ..EMPTY_TX
+            });
+        }
+        nic.tx_next = (i + 1) % TX_COUNT;
+        nic.write(TDT, nic.tx_next as u32);
+        nic.stats.tx_packets += 1;
+        nic.stats.tx_bytes += frame.len() as u64;
+        Ok(())
+    }
+
+    fn receive(&self, buf: &mut [u8]) -> Option<usize> {
+        RX_QUEUE.lock().pop(buf)
+    }
+
+    fn stats(&self) -> Stats {
+        NIC.lock().as_ref().map_or(Stats::default(), |nic| nic.stats)
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn e1000_handler_inner() {
+    let _canary = stackguard::Canary::new();
+    let irq = unsafe { NIC_IRQ };
+    idt::count_irq(irq as usize);
+
+    if let Some(nic) = NIC.lock().as_mut() {
+        // Reading the cause clears it; link changes need nothing, as
+        // `link_up` asks the card each time
+        if nic.read(ICR) & INT_RECEIVE != 0 {
+            drain_rx(nic);
+        }
+    }
+
+    idt::end_of_interrupt(irq as usize);
+}
+
+#[unsafe(naked)]
+#[no_mangle]
+pub unsafe extern "C" fn e1000_interrupt_handler() {
+    naked_asm!(
+        "pusha",
+        "push ds",
+        "push es",
+        "mov ax, {kernel_data}",
+        "mov ds, ax",
+        "mov es, ax",
+        "call e1000_handler_inner",
+        "pop es",
+        "pop ds",
+        "popa",
+        "iretd",
+        kernel_data = const gdt::KERNEL_DATA as u32,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::mem::size_of;
+
+    #[test_case]
+    fn descriptors_match_the_hardware_layout() {
+        assert_eq!(size_of::<RxDescriptor>(), 16);
+        assert_eq!(size_of::<TxDescriptor>(), 16);
+        assert_eq!(size_of::<[RxDescriptor; RX_COUNT]>() % 128, 0);
+        assert_eq!(size_of::<[TxDescriptor; TX_COUNT]>() % 128, 0);
+        assert!(BUFFER_SIZE >= MAX_FRAME_LEN);
+    }
+}
@@ -8,6 +8,11 @@ const EDITOR_WIDTH: usize = 80;
 const EDITOR_HEIGHT: usize = 23;
 const MAX_LINES: usize = 100;
 const MAX_LINE_LEN: usize = 80;
+/// Paths like `/scripts/boot.sh`; longer ones would run into the status bar
+const MAX_FILENAME_LEN: usize = 48;
+const MAX_QUERY_LEN: usize = 40;
+/// Row of the key help, and of the search prompt while it's open
+const PROMPT_ROW: usize = 24;
 
 pub struct Editor {
     lines: [[u8; MAX_LINE_LEN]; MAX_LINES],
@@ -17,8 +22,11 @@ pub struct Editor {
     cursor_y: usize,
     scroll_offset: usize,
     modified: bool,
-    filename: [u8; 32],
+    filename: [u8; MAX_FILENAME_LEN],
     filename_len: usize,
+    /// Last search, repeated by F3
+    query: [u8; MAX_QUERY_LEN],
+    query_len: usize,
 }
 
 impl Editor {
@@ -31,13 +39,17 @@ impl Editor {
             cursor_y: 0,
             scroll_offset: 0,
             modified: false,
-            filename: [0; 32],
+            filename: [0; MAX_FILENAME_LEN],
             filename_len: 0,
+            query: [0; MAX_QUERY_LEN],
+            query_len: 0,
         }
     }
 
+    /// Load `filename`, a path absolute or relative to the current
+    /// directory; a file that doesn't exist yet is created on save
     pub fn open(&mut self, filename: &[u8]) -> Result<(), &'static str> {
-        if filename.len() > 32 {
+        if filename.len() > MAX_FILENAME_LEN {
             return Err("Filename too long");
         }
 
@@ -45,7 +57,7 @@ impl Editor {
         self.filename_len = filename.len();
 
         let fs = get_filesystem();
-        if let Some(content) = fs.read_file(filename) {
+        if let Some(content) = fs.read_path(filename) {
             self.load_content(content);
         } else {
             self.num_lines = 1;
@@ -131,6 +143,22 @@ impl Editor {
                 continue;
             }
 
+            if ctrl_pressed && scancode == 0x21 {
+                // The prompt reads the Ctrl release itself
+                ctrl_pressed = false;
+                if self.read_query(writer) {
+                    self.find_next(writer);
+                }
+                self.draw(writer);
+                continue;
+            }
+
+            if scancode == 0x3D {
+                self.find_next(writer);
+                self.draw(writer);
+                continue;
+            }
+
             if ctrl_pressed && scancode == 0x10 {
                 if self.modified {
                     self.show_message(writer, "Unsaved changes! Press Ctrl+Q again to quit", Color::Yellow);
@@ -188,9 +216,15 @@ impl Editor {
                 continue;
             }
 
-            if let Some(c) = Keyboard::scancode_to_char(scancode, shift_pressed) {
+            if scancode == 0x53 {
+                self.delete();
+                self.draw(writer);
+                continue;
+            }
+
+            for c in Keyboard::scancode_to_bytes(scancode, shift_pressed) {
                 if !ctrl_pressed {
-                    self.insert_char(c as u8);
+                    self.insert_char(c);
                     self.draw(writer);
                 }
             }
@@ -282,14 +316,19 @@ impl Editor {
         writer.write_byte(b'\n');
 
         writer.set_color(Color::White, Color::Black);
-        writer.write_str("Ctrl+S: Save | Ctrl+Q or ESC: Quit | Arrows: Navigate");
+        let help = "Ctrl+S: Save | Ctrl+F: Find, F3: Next | Ctrl+Q or ESC: Quit";
+        writer.write_str(help);
+        // Clear what a message or the search prompt left
+        for _ in help.len()..EDITOR_WIDTH - 1 {
+            writer.write_byte(b' ');
+        }
 
         let screen_y = self.cursor_y.saturating_sub(self.scroll_offset);
         writer.set_position(self.cursor_x, screen_y);
     }
 
     fn show_message(&self, writer: &mut Writer, msg: &str, color: Color) {
-        writer.set_position(0, 24);
+        writer.set_position(0, PROMPT_ROW);
         writer.set_color(color, Color::Black);
         writer.write_str(msg);
         
@@ -309,6 +348,69 @@ impl Editor {
         }
     }
 
+    /// Edit the search text on the bottom row, starting from the last one.
+    /// Returns false if ESC cancelled it or it was left empty.
+    fn read_query(&mut self, writer: &mut Writer) -> bool {
+        let mut shift_pressed = false;
+        loop {
+            writer.set_position(0, PROMPT_ROW);
+            writer.set_color(Color::Yellow, Color::Black);
+            writer.write_str("Find: ");
+            writer.set_color(Color::White, Color::Black);
+            writer.write_bytes(&self.query[..self.query_len]);
+            // Stop short of the last column so the screen doesn't scroll
+            for _ in 6 + self.query_len..EDITOR_WIDTH - 1 {
+                writer.write_byte(b' ');
+            }
+            writer.set_position(6 + self.query_len, PROMPT_ROW);
+
+            let scancode = match idt::get_scancode() {
+                Some(sc) => sc,
+                None => {
+                    idt::wait_for_interrupt();
+                    continue;
+                }
+            };
+            match scancode {
+                0x2A | 0x36 => shift_pressed = true,
+                0xAA | 0xB6 => shift_pressed = false,
+                0x01 => return false,
+                0x1C => return self.query_len > 0,
+                0x0E => self.query_len = self.query_len.saturating_sub(1),
+                _ => {
+                    for c in Keyboard::scancode_to_bytes(scancode, shift_pressed) {
+                        if self.query_len < MAX_QUERY_LEN {
+                            self.query[self.query_len] = c;
+                            self.query_len += 1;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Move to the next match of the search after the cursor, wrapping
+    /// around the end of the file
+    fn find_next(&mut self, writer: &mut Writer) {
+        let query = &self.query[..self.query_len];
+        if query.is_empty() {
+            return;
+        }
+        for i in 0..=self.num_lines {
+            let y = (self.cursor_y + i) % self.num_lines;
+            let from = if i == 0 { self.cursor_x + 1 } else { 0 };
+            if let Some(x) = find_in_line(&self.lines[y][..self.line_lengths[y]], query, from) {
+                self.cursor_y = y;
+                self.cursor_x = x;
+                if y < self.scroll_offset || y >= self.scroll_offset + EDITOR_HEIGHT {
+                    self.scroll_offset = y.saturating_sub(EDITOR_HEIGHT / 2);
+                }
+                return;
+            }
+        }
+        self.show_message(writer, "Not found", Color::Yellow);
+    }
+
     fn insert_char(&mut self, c: u8) {
         if self.cursor_y >= MAX_LINES {
             return;
@@ -399,6 +501,22 @@ impl Editor {
         }
     }
 
+    /// Delete the character under the cursor, or join the next line on if
+    /// the cursor is at the end of the line and the two fit on one
+    fn delete(&mut self) {
+        let line_len = self.line_lengths[self.cursor_y];
+        if self.cursor_x < line_len {
+            self.lines[self.cursor_y].copy_within(self.cursor_x + 1..line_len, self.cursor_x);
+            self.line_lengths[self.cursor_y] -= 1;
+            self.modified = true;
+        } else if self.cursor_y + 1 < self.num_lines
+            && line_len + self.line_lengths[self.cursor_y + 1] <= MAX_LINE_LEN
+        {
+            self.move_right();
+            self.backspace();
+        }
+    }
+
     fn move_up(&mut self) {
         if self.cursor_y > 0 {
             self.cursor_y -= 1;
@@ -490,9 +608,31 @@ impl Editor {
         }
 
         let fs = get_filesystem();
-        fs.write_file(&self.filename[..self.filename_len], &content[..pos])?;
+        fs.write_path(&self.filename[..self.filename_len], &content[..pos])?;
         self.modified = false;
 
         Ok(())
     }
 }
+
+/// Column of the first match of `query` in `line` at or after `from`
+fn find_in_line(line: &[u8], query: &[u8], from: usize) -> Option<usize> {
+    if query.is_empty() || from > line.len() {
+        return None;
+    }
+    line[from..].windows(query.len()).position(|window| window == query).map(|i| i + from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn find_in_line_starts_at_from() {
+        assert_eq!(find_in_line(b"echo hi; echo", b"echo", 0), Some(0));
+        assert_eq!(find_in_line(b"echo hi; echo", b"echo", 1), Some(9));
+        assert_eq!(find_in_line(b"echo", b"echo", 1), None);
+        assert_eq!(find_in_line(b"ab", b"abc", 0), None);
+        assert_eq!(find_in_line(b"ab", b"b", 5), None);
+    }
+}
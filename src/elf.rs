@@ -0,0 +1,163 @@
+use crate::usermode;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const CLASS_32: u8 = 1;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+const TYPE_EXEC: u16 = 2;
+const MACHINE_386: u16 = 3;
+
+const PT_LOAD: u32 = 1;
+const PROGRAM_HEADER_SIZE: usize = 32;
+
+/// Segment permission bits
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+/// A loadable segment: `data` is copied to `vaddr` and the rest of
+/// `mem_size` is zeroed
+pub struct Segment<'a> {
+    pub vaddr: u32,
+    pub mem_size: u32,
+    pub flags: u32,
+    pub data: &'a [u8],
+}
+
+/// A validated 32-bit x86 ELF executable
+pub struct ElfFile<'a> {
+    data: &'a [u8],
+    entry: u32,
+    ph_offset: usize,
+    ph_count: usize,
+    ph_size: usize,
+}
+
+fn u16_at(data: &[u8], i: usize) -> u16 {
+    u16::from_le_bytes([data[i], data[i + 1]])
+}
+
+fn u32_at(data: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]])
+}
+
+impl<'a> ElfFile<'a> {
+    pub fn is_elf(data: &[u8]) -> bool {
+        data.starts_with(ELF_MAGIC)
+    }
+
+    pub fn parse(data: &'a [u8]) -> Result<Self, &'static str> {
+        if !Self::is_elf(data) || data.len() < 52 {
+            return Err("Not an ELF file");
+        }
+        if data[4] != CLASS_32 || data[5] != DATA_LITTLE_ENDIAN {
+            return Err("Not a 32-bit little-endian ELF");
+        }
+        if u16_at(data, 16) != TYPE_EXEC {
+            return Err("Not an ELF executable");
+        }
+        if u16_at(data, 18) != MACHINE_386 {
+            return Err("Not an x86 ELF");
+        }
+
+        let elf = Self {
+            data,
+            entry: u32_at(data, 24),
+            ph_offset: u32_at(data, 28) as usize,
+            ph_size: u16_at(data, 42) as usize,
+            ph_count: u16_at(data, 44) as usize,
+        };
+        let ph_end = elf.ph_size.checked_mul(elf.ph_count).and_then(|len| len.checked_add(elf.ph_offset));
+        if elf.ph_size < PROGRAM_HEADER_SIZE || ph_end.map_or(true, |end| end > data.len()) {
+            return Err("Invalid ELF program headers");
+        }
+        // Catch bad offsets here so `segments` can just slice
+        for i in 0..elf.ph_count {
+            let header = elf.ph_offset + i * elf.ph_size;
+            let (offset, file_size) = (u32_at(data, header + 4) as usize, u32_at(data, header + 16) as usize);
+            if offset.checked_add(file_size).map_or(true, |end| end > data.len()) {
+                return Err("ELF segment is truncated");
+            }
+            if file_size > u32_at(data, header + 20) as usize {
+                return Err("ELF segment is larger on disk than in memory");
+            }
+        }
+        Ok(elf)
+    }
+
+    pub fn entry(&self) -> u32 {
+        self.entry
+    }
+
+    /// The PT_LOAD segments, in file order
+    pub fn segments(&self) -> impl Iterator<Item = Segment<'a>> + '_ {
+        (0..self.ph_count)
+            .map(move |i| self.ph_offset + i * self.ph_size)
+            .filter(move |&header| u32_at(self.data, header) == PT_LOAD)
+            .map(move |header| {
+                let offset = u32_at(self.data, header + 4) as usize;
+                let file_size = u32_at(self.data, header + 16) as usize;
+                Segment {
+                    vaddr: u32_at(self.data, header + 8),
+                    mem_size: u32_at(self.data, header + 20),
+                    flags: u32_at(self.data, header + 24),
+                    data: &self.data[offset..offset + file_size],
+                }
+            })
+    }
+
+    /// Copy every segment into program memory. Nothing is written unless all
+    /// of them fit.
+    pub fn load(&self) -> Result<(), &'static str> {
+        usermode::check_window()?;
+        if self.segments().any(|s| !usermode::contains(s.vaddr, s.mem_size)) {
+            return Err("ELF segment lies outside program memory");
+        }
+        if !self.segments().any(|s| s.flags & PF_X != 0 && s.vaddr <= self.entry && self.entry - s.vaddr < s.mem_size) {
+            return Err("ELF entry point is not in an executable segment");
+        }
+
+        for segment in self.segments() {
+            unsafe {
+                let dest = usermode::physical(segment.vaddr);
+                core::ptr::copy_nonoverlapping(segment.data.as_ptr(), dest, segment.data.len());
+                core::ptr::write_bytes(dest.add(segment.data.len()), 0, (segment.mem_size as usize) - segment.data.len());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn parses_header_and_load_segment() {
+        let mut data = [0u8; 84 + 4];
+        data[..4].copy_from_slice(ELF_MAGIC);
+        data[4] = CLASS_32;
+        data[5] = DATA_LITTLE_ENDIAN;
+        data[16] = TYPE_EXEC as u8;
+        data[18] = MACHINE_386 as u8;
+        data[24..28].copy_from_slice(&0x1000u32.to_le_bytes());
+        data[28] = 52;
+        data[42] = PROGRAM_HEADER_SIZE as u8;
+        data[44] = 1;
+        // One PT_LOAD of 4 file bytes at offset 84, 16 bytes in memory
+        data[52] = PT_LOAD as u8;
+        data[56] = 84;
+        data[60..64].copy_from_slice(&0x1000u32.to_le_bytes());
+        data[68] = 4;
+        data[72] = 16;
+        data[76] = (PF_R | PF_X) as u8;
+
+        let elf = ElfFile::parse(&data).unwrap();
+        assert_eq!(elf.entry(), 0x1000);
+        let segment = elf.segments().next().unwrap();
+        assert_eq!((segment.vaddr, segment.mem_size, segment.data.len()), (0x1000, 16, 4));
+        assert!(ElfFile::parse(&data[..80]).is_err());
+        // A header table that wraps the address space
+        data[28..32].copy_from_slice(&0xFFFF_FFF0u32.to_le_bytes());
+        assert_eq!(ElfFile::parse(&data).err(), Some("Invalid ELF program headers"));
+    }
+}
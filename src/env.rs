@@ -0,0 +1,206 @@
+use crate::theme::{self, Theme};
+
+// The shell environment: named string variables, expanded as `$NAME` in
+// command lines. A few names mean something to the shell itself:
+// `PATH` (directories `run` searches, `:` separated), `PROMPT` (see
+// `prompt`), `USER`, `HOME` (where the shell looks for `.hshrc`) and
+// `THEME`. `$?` is the exit status of the last command, which is kept
+// apart from the variables so it can't be set or unset.
+
+const MAX_VARS: usize = 24;
+const MAX_NAME_LEN: usize = 32;
+const MAX_VALUE_LEN: usize = 128;
+/// Longest line after `$NAME` expansion
+pub const MAX_LINE_LEN: usize = 256;
+
+pub const DEFAULT_PATH: &[u8] = b"/bin";
+pub const DEFAULT_PROMPT: &[u8] = b"HyzeOS:\\w> ";
+pub const DEFAULT_HOME: &[u8] = b"/home";
+
+#[derive(Clone, Copy)]
+struct Variable {
+    name: [u8; MAX_NAME_LEN],
+    name_len: usize,
+    value: [u8; MAX_VALUE_LEN],
+    value_len: usize,
+    /// Marked with `export`; what a program would inherit
+    exported: bool,
+}
+
+impl Variable {
+    const fn empty() -> Self {
+        Self { name: [0; MAX_NAME_LEN], name_len: 0, value: [0; MAX_VALUE_LEN], value_len: 0, exported: false }
+    }
+
+    pub fn name(&self) -> &[u8] {
+        &self.name[..self.name_len]
+    }
+
+    pub fn value(&self) -> &[u8] {
+        &self.value[..self.value_len]
+    }
+}
+
+pub struct Environment {
+    vars: [Variable; MAX_VARS],
+    count: usize,
+    /// Exit status of the last command, for `$?`
+    status: u8,
+}
+
+impl Environment {
+    pub const fn new() -> Self {
+        Self { vars: [Variable::empty(); MAX_VARS], count: 0, status: 0 }
+    }
+
+    fn index(&self, name: &[u8]) -> Option<usize> {
+        self.vars[..self.count].iter().position(|var| var.name() == name)
+    }
+
+    pub fn get(&self, name: &[u8]) -> Option<&[u8]> {
+        self.index(name).map(|i| self.vars[i].value())
+    }
+
+    pub fn set(&mut self, name: &[u8], value: &[u8]) -> Result<(), &'static str> {
+        if name.is_empty() || !name.iter().all(|&b| is_name_byte(b)) {
+            return Err("Variable names are letters, digits and _");
+        }
+        if name.len() > MAX_NAME_LEN {
+            return Err("Variable name too long");
+        }
+        if value.len() > MAX_VALUE_LEN {
+            return Err("Variable value too long");
+        }
+        let i = match self.index(name) {
+            Some(i) => i,
+            None if self.count == MAX_VARS => return Err("Too many variables"),
+            None => {
+                self.count += 1;
+                self.vars[self.count - 1] = Variable::empty();
+                self.count - 1
+            }
+        };
+        let var = &mut self.vars[i];
+        var.name[..name.len()].copy_from_slice(name);
+        var.name_len = name.len();
+        var.value[..value.len()].copy_from_slice(value);
+        var.value_len = value.len();
+        Ok(())
+    }
+
+    pub fn export(&mut self, name: &[u8]) -> Result<(), &'static str> {
+        let i = self.index(name).ok_or("No such variable")?;
+        self.vars[i].exported = true;
+        Ok(())
+    }
+
+    pub fn unset(&mut self, name: &[u8]) -> Result<(), &'static str> {
+        let i = self.index(name).ok_or("No such variable")?;
+        self.vars.copy_within(i + 1..self.count, i);
+        self.count -= 1;
+        Ok(())
+    }
+
+    /// Name, value and export flag of each variable, oldest first
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8], bool)> {
+        self.vars[..self.count].iter().map(|var| (var.name(), var.value(), var.exported))
+    }
+
+    pub fn status(&self) -> u8 {
+        self.status
+    }
+
+    pub fn set_status(&mut self, status: u8) {
+        self.status = status;
+    }
+
+    /// Copy `line` into `out` with every `$NAME` replaced by its value and
+    /// `$?` by the last exit status. Unset variables expand to nothing and
+    /// a `$` not followed by a name is kept. Returns the expanded length.
+    pub fn expand(&self, line: &[u8], out: &mut [u8]) -> Result<usize, &'static str> {
+        let mut status = [0u8; 3];
+        let mut len = 0;
+        let mut i = 0;
+        while i < line.len() {
+            let name_len = line[i + 1..].iter().take_while(|&&b| is_name_byte(b)).count();
+            let piece = if line[i] == b'$' && line.get(i + 1) == Some(&b'?') {
+                i += 2;
+                format_status(self.status, &mut status)
+            } else if line[i] == b'$' && name_len > 0 {
+                let name = &line[i + 1..i + 1 + name_len];
+                i += 1 + name_len;
+                self.get(name).unwrap_or(b"")
+            } else {
+                i += 1;
+                &line[i - 1..i]
+            };
+            out.get_mut(len..len + piece.len()).ok_or("Line too long after expansion")?.copy_from_slice(piece);
+            len += piece.len();
+        }
+        Ok(len)
+    }
+}
+
+fn format_status(status: u8, buf: &mut [u8; 3]) -> &[u8] {
+    let digits = if status >= 100 { 3 } else if status >= 10 { 2 } else { 1 };
+    let mut value = status;
+    for digit in buf[..digits].iter_mut().rev() {
+        *digit = b'0' + value % 10;
+        value /= 10;
+    }
+    &buf[..digits]
+}
+
+fn is_name_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+static mut ENV: Environment = Environment::new();
+
+/// Set the variables the shell looks at to their defaults
+pub fn init() {
+    let env = get();
+    let _ = env.set(b"PATH", DEFAULT_PATH);
+    let _ = env.set(b"PROMPT", DEFAULT_PROMPT);
+    let _ = env.set(b"USER", b"root");
+    let _ = env.set(b"HOME", DEFAULT_HOME);
+    let _ = env.set(b"THEME", theme::current().name().as_bytes());
+    for name in [&b"PATH"[..], b"PROMPT", b"USER", b"HOME", b"THEME"].iter() {
+        let _ = env.export(name);
+    }
+}
+
+pub fn get() -> &'static mut Environment {
+    unsafe { &mut ENV }
+}
+
+/// Set a variable, applying it when the shell acts on it
+pub fn set(name: &[u8], value: &[u8]) -> Result<(), &'static str> {
+    if name == b"THEME" {
+        theme::set(Theme::from_name(value).ok_or("Unknown theme")?);
+    }
+    get().set(name, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn expand_and_unset() {
+        let mut env = Environment::new();
+        env.set(b"DIR", b"logs").unwrap();
+        env.set(b"X", b"1").unwrap();
+        let mut out = [0u8; MAX_LINE_LEN];
+        let len = env.expand(b"cd $DIR; echo $NOPE$ ok", &mut out).unwrap();
+        assert_eq!(&out[..len], b"cd logs; echo $ ok");
+        env.set_status(127);
+        let len = env.expand(b"echo $? $?x", &mut out).unwrap();
+        assert_eq!(&out[..len], b"echo 127 127x");
+
+        env.unset(b"DIR").unwrap();
+        assert_eq!(env.get(b"DIR"), None);
+        assert_eq!(env.get(b"X"), Some(&b"1"[..]));
+        assert!(env.set(b"BAD-NAME", b"").is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use core::arch::asm;
+
+use crate::usermode::{self, Exit};
+use crate::caps;
+use crate::syscall;
+use crate::vga_colors::Color;
+use crate::writer::Writer;
+
+const VECTOR_DIVIDE: u32 = 0x00;
+const VECTOR_INVALID_OPCODE: u32 = 0x06;
+const VECTOR_GENERAL_PROTECTION: u32 = 0x0D;
+
+/// One exception class, provoked by a few bytes of ring 3 code
+struct Case {
+    name: &'static str,
+    code: &'static [u8],
+    expected: Expected,
+}
+
+#[derive(Clone, Copy)]
+enum Expected {
+    Fault(u32),
+    Code(i32),
+}
+
+const CASES: &[Case] = &[
+    Case {
+        name: "divide by zero",
+        // xor ecx, ecx; div ecx
+        code: &[0x31, 0xC9, 0xF7, 0xF1],
+        expected: Expected::Fault(VECTOR_DIVIDE),
+    },
+    Case {
+        name: "invalid opcode",
+        // ud2
+        code: &[0x0F, 0x0B],
+        expected: Expected::Fault(VECTOR_INVALID_OPCODE),
+    },
+    Case {
+        name: "privileged instruction",
+        // cli
+        code: &[0xFA],
+        expected: Expected::Fault(VECTOR_GENERAL_PROTECTION),
+    },
+    Case {
+        name: "port I/O",
+        // in al, 0x60
+        code: &[0xE4, 0x60],
+        expected: Expected::Fault(VECTOR_GENERAL_PROTECTION),
+    },
+    Case {
+        name: "memory outside the program",
+        // mov eax, [0xFFFFF000]
+        code: &[0xA1, 0x00, 0xF0, 0xFF, 0xFF],
+        expected: Expected::Fault(VECTOR_GENERAL_PROTECTION),
+    },
+    Case {
+        name: "kernel-only gate",
+        // int 0x21
+        code: &[0xCD, 0x21],
+        expected: Expected::Fault(VECTOR_GENERAL_PROTECTION),
+    },
+    Case {
+        name: "unknown system call",
+        // mov eax, 999; int 0x80; mov ebx, eax; mov eax, 1; int 0x80
+        code: &[
+            0xB8, 0xE7, 0x03, 0x00, 0x00, 0xCD, 0x80, 0x89, 0xC3,
+            0xB8, 0x01, 0x00, 0x00, 0x00, 0xCD, 0x80,
+        ],
+        expected: Expected::Code(-syscall::ENOSYS),
+    },
+];
+
+/// Run every case in ring 3 and check the kernel survives each one with
+/// the right verdict. Returns how many passed out of how many ran.
+pub fn run(writer: &mut Writer) -> Result<(usize, usize), &'static str> {
+    usermode::check_window()?;
+
+    let mut passed = 0;
+    for case in CASES {
+        let entry = 0;
+        unsafe {
+            core::ptr::copy_nonoverlapping(case.code.as_ptr(), usermode::physical(entry), case.code.len());
+        }
+        let exit = usermode::run(entry, caps::shell(), writer);
+        let ok = match (case.expected, exit) {
+            (Expected::Fault(want), Exit::Fault { vector, .. }) => vector == want,
+            (Expected::Code(want), Exit::Code(code)) => code == want,
+            _ => false,
+        };
+
+        writer.set_color(if ok { Color::Green } else { Color::Red }, Color::Black);
+        writer.write_str(if ok { "  PASS  " } else { "  FAIL  " });
+        writer.set_color(Color::White, Color::Black);
+        writer.write_str(case.name);
+        writer.write_str(": ");
+        match exit {
+            Exit::Fault { vector, .. } => writer.write_str(usermode::exception_name(vector)),
+            Exit::Code(_) => writer.write_str("exited"),
+        }
+        writer.write_byte(b'\n');
+        if ok {
+            passed += 1;
+        }
+    }
+    Ok((passed, CASES.len()))
+}
+
+/// Raise an exception in kernel mode, which should end on the crash screen
+pub fn crash(class: &[u8]) -> Result<(), &'static str> {
+    unsafe {
+        match class {
+            b"de" => asm!("xor ecx, ecx", "div ecx", out("eax") _, out("ecx") _, out("edx") _),
+            b"ud" => asm!("ud2"),
+            // Selector past the end of the GDT
+            b"gp" => asm!("mov ax, 0x38", "mov ds, ax", out("eax") _),
+            // The breakpoint gate isn't present, and neither is the one
+            // for the segment-not-present fault that raises
+            b"df" => asm!("int3"),
+            _ => return Err("Unknown exception class (de, ud, gp or df)"),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,402 @@
+use crate::writer::Writer;
+use crate::vga_colors::Color;
+use crate::idt;
+use crate::keyboard;
+use crate::filesystem::{get_filesystem, FileEntry};
+use crate::editor::Editor;
+
+const SCREEN_WIDTH: usize = 80;
+const PANE_WIDTH: usize = 40;
+const LIST_TOP: usize = 2;
+const LIST_HEIGHT: usize = 21;
+const STATUS_ROW: usize = 23;
+const KEYS_ROW: usize = 24;
+const NAME_WIDTH: usize = 26;
+
+#[derive(Clone, Copy)]
+struct Pane {
+    dir: usize,
+    selected: usize,
+    scroll: usize,
+}
+
+/// One row of a pane listing
+#[derive(Clone, Copy)]
+struct Entry {
+    name: [u8; 32],
+    name_len: usize,
+    size: usize,
+    is_dir: bool,
+    is_parent: bool,
+}
+
+impl Entry {
+    fn name(&self) -> &[u8] {
+        &self.name[..self.name_len]
+    }
+}
+
+/// Two-pane file manager in the style of Norton Commander
+pub struct FileManager {
+    panes: [Pane; 2],
+    active: usize,
+    message: &'static str,
+}
+
+impl FileManager {
+    pub fn new() -> Self {
+        let dir = get_filesystem().current_dir();
+        let pane = Pane { dir, selected: 0, scroll: 0 };
+        Self {
+            panes: [pane, Pane { dir: 0, selected: 0, scroll: 0 }],
+            active: 0,
+            message: "",
+        }
+    }
+
+    pub fn run(&mut self, writer: &mut Writer) {
+        let original_dir = get_filesystem().current_dir();
+        idt::flush_buffer();
+        writer.clear();
+        self.draw(writer);
+
+        loop {
+            let scancode = self.wait_for_key();
+            self.message = "";
+
+            match scancode {
+                0x01 | 0x44 => break,                    // ESC / F10
+                0x0F => self.active = 1 - self.active,   // Tab
+                0x48 => self.move_selection(-1),         // Up
+                0x50 => self.move_selection(1),          // Down
+                0x49 => self.move_selection(-(LIST_HEIGHT as isize)),
+                0x51 => self.move_selection(LIST_HEIGHT as isize),
+                0x1C => self.open_selected(writer),      // Enter
+                0x3D => self.view_selected(writer),      // F3
+                0x3E => self.edit_selected(writer),      // F4
+                0x3F => self.transfer_selected(false),   // F5
+                0x40 => self.transfer_selected(true),    // F6
+                0x42 => self.delete_selected(writer),    // F8
+                _ => continue,
+            }
+
+            self.draw(writer);
+        }
+
+        get_filesystem().set_current_dir(original_dir);
+    }
+
+    fn wait_for_key(&self) -> u8 {
+        loop {
+            if let Some(sc) = idt::get_scancode() {
+                if sc & 0x80 == 0 {
+                    return sc;
+                }
+            }
+            idt::wait_for_interrupt();
+        }
+    }
+
+    /// Fetch the `index`-th row of a pane, with ".." first outside the root
+    fn entry(&self, pane: usize, index: usize) -> Option<Entry> {
+        let fs = get_filesystem();
+        let dir = self.panes[pane].dir;
+        let mut entry = Entry { name: [0; 32], name_len: 0, size: 0, is_dir: false, is_parent: false };
+
+        let mut index = index;
+        if dir != 0 {
+            if index == 0 {
+                entry.name[..2].copy_from_slice(b"..");
+                entry.name_len = 2;
+                entry.is_dir = true;
+                entry.is_parent = true;
+                return Some(entry);
+            }
+            index -= 1;
+        }
+
+        let saved = fs.current_dir();
+        fs.set_current_dir(dir);
+        let found = fs.list_files().nth(index).map(|item| {
+            let (name, size, is_dir) = match item {
+                FileEntry::Directory(name) => (name, 0, true),
+                FileEntry::File(name, size) => (name, size, false),
+            };
+            entry.name[..name.len()].copy_from_slice(name);
+            entry.name_len = name.len();
+            entry.size = size;
+            entry.is_dir = is_dir;
+            entry
+        });
+        fs.set_current_dir(saved);
+        found
+    }
+
+    fn entry_count(&self, pane: usize) -> usize {
+        let fs = get_filesystem();
+        let dir = self.panes[pane].dir;
+        let saved = fs.current_dir();
+        fs.set_current_dir(dir);
+        let count = fs.list_files().count();
+        fs.set_current_dir(saved);
+        if dir != 0 { count + 1 } else { count }
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        let count = self.entry_count(self.active);
+        let pane = &mut self.panes[self.active];
+        if count == 0 {
+            pane.selected = 0;
+            return;
+        }
+        let target = pane.selected as isize + delta;
+        pane.selected = target.max(0).min(count as isize - 1) as usize;
+
+        if pane.selected < pane.scroll {
+            pane.scroll = pane.selected;
+        } else if pane.selected >= pane.scroll + LIST_HEIGHT {
+            pane.scroll = pane.selected + 1 - LIST_HEIGHT;
+        }
+    }
+
+    /// Run `f` with the filesystem's current directory set to the active pane
+    fn in_active_dir<T>(&self, f: impl FnOnce() -> T) -> T {
+        let fs = get_filesystem();
+        let saved = fs.current_dir();
+        fs.set_current_dir(self.panes[self.active].dir);
+        let result = f();
+        fs.set_current_dir(saved);
+        result
+    }
+
+    fn open_selected(&mut self, writer: &mut Writer) {
+        let entry = match self.entry(self.active, self.panes[self.active].selected) {
+            Some(entry) => entry,
+            None => return,
+        };
+        if !entry.is_dir {
+            self.view_selected(writer);
+            return;
+        }
+
+        let fs = get_filesystem();
+        let saved = fs.current_dir();
+        fs.set_current_dir(self.panes[self.active].dir);
+        if fs.change_directory(entry.name()).is_ok() {
+            let pane = &mut self.panes[self.active];
+            pane.dir = fs.current_dir();
+            pane.selected = 0;
+            pane.scroll = 0;
+        }
+        fs.set_current_dir(saved);
+    }
+
+    fn selected_file(&mut self) -> Option<Entry> {
+        match self.entry(self.active, self.panes[self.active].selected) {
+            Some(entry) if !entry.is_dir => Some(entry),
+            Some(_) => {
+                self.message = "Not a file";
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn view_selected(&mut self, writer: &mut Writer) {
+        let entry = match self.selected_file() {
+            Some(entry) => entry,
+            None => return,
+        };
+
+        writer.set_color(Color::White, Color::Black);
+        writer.clear();
+        self.in_active_dir(|| {
+            if let Some(content) = get_filesystem().read_file(entry.name()) {
+                writer.write_bytes(content);
+            }
+        });
+        writer.set_position(0, KEYS_ROW);
+        writer.set_color(Color::Black, Color::LightGray);
+        writer.write_str(" Press any key to return ");
+        self.wait_for_key();
+        writer.set_color(Color::White, Color::Black);
+        writer.clear();
+    }
+
+    fn edit_selected(&mut self, writer: &mut Writer) {
+        let entry = match self.selected_file() {
+            Some(entry) => entry,
+            None => return,
+        };
+
+        self.in_active_dir(|| {
+            let mut editor = Editor::new();
+            if editor.open(entry.name()).is_ok() {
+                editor.run(writer);
+            }
+        });
+        writer.set_color(Color::White, Color::Black);
+        writer.clear();
+    }
+
+    /// Copy or move the selected file into the other pane's directory
+    fn transfer_selected(&mut self, is_move: bool) {
+        let entry = match self.selected_file() {
+            Some(entry) => entry,
+            None => return,
+        };
+        let dest = self.panes[1 - self.active].dir;
+        if dest == self.panes[self.active].dir {
+            self.message = "Source and destination are the same";
+            return;
+        }
+
+        let result = self.in_active_dir(|| {
+            let fs = get_filesystem();
+            if is_move {
+                fs.move_file_to(entry.name(), dest)
+            } else {
+                fs.copy_file_to(entry.name(), dest)
+            }
+        });
+        self.message = match result {
+            Ok(()) if is_move => "Moved",
+            Ok(()) => "Copied",
+            Err(e) => e,
+        };
+        if is_move {
+            self.move_selection(0);
+        }
+    }
+
+    fn delete_selected(&mut self, writer: &mut Writer) {
+        let entry = match self.entry(self.active, self.panes[self.active].selected) {
+            Some(entry) if !entry.is_parent => entry,
+            _ => return,
+        };
+
+        self.draw_status(writer, "Delete selected entry? (y/n)", Color::Yellow);
+        if !keyboard::is_yes(self.wait_for_key()) {
+            self.message = "Cancelled";
+            return;
+        }
+
+        let result = self.in_active_dir(|| {
+            let fs = get_filesystem();
+            if entry.is_dir {
+                fs.remove_directory(entry.name())
+            } else {
+                fs.delete_file(entry.name())
+            }
+        });
+        self.message = match result {
+            Ok(()) => "Deleted",
+            Err(e) => e,
+        };
+        self.move_selection(0);
+    }
+
+    fn draw(&self, writer: &mut Writer) {
+        writer.set_position(0, 0);
+        writer.set_color(Color::Black, Color::LightGray);
+        write_padded(writer, b" HyzeOS File Manager", SCREEN_WIDTH);
+
+        for pane in 0..2 {
+            self.draw_pane(writer, pane);
+        }
+
+        self.draw_status(writer, self.message, Color::White);
+
+        writer.set_position(0, KEYS_ROW);
+        writer.set_color(Color::Black, Color::Cyan);
+        write_padded(writer, b" F3 View  F4 Edit  F5 Copy  F6 Move  F8 Delete  F10 Quit  Tab Switch", SCREEN_WIDTH - 1);
+        writer.set_position(SCREEN_WIDTH - 1, STATUS_ROW);
+    }
+
+    fn draw_pane(&self, writer: &mut Writer, pane: usize) {
+        let left = pane * PANE_WIDTH;
+        let state = self.panes[pane];
+
+        let fs = get_filesystem();
+        let saved = fs.current_dir();
+        fs.set_current_dir(state.dir);
+        let mut path = [0u8; 128];
+        let path_len = fs.get_current_path(&mut path);
+        fs.set_current_dir(saved);
+
+        writer.set_position(left, 1);
+        if pane == self.active {
+            writer.set_color(Color::Black, Color::Cyan);
+        } else {
+            writer.set_color(Color::White, Color::Blue);
+        }
+        writer.write_byte(b' ');
+        write_padded(writer, &path[..path_len], PANE_WIDTH - 1);
+
+        for row in 0..LIST_HEIGHT {
+            let index = state.scroll + row;
+            writer.set_position(left, LIST_TOP + row);
+
+            let entry = match self.entry(pane, index) {
+                Some(entry) => entry,
+                None => {
+                    writer.set_color(Color::LightCyan, Color::Blue);
+                    write_padded(writer, b"", PANE_WIDTH - 1);
+                    writer.write_byte(b'|');
+                    continue;
+                }
+            };
+
+            if pane == self.active && index == state.selected {
+                writer.set_color(Color::Black, Color::Cyan);
+            } else if entry.is_dir {
+                writer.set_color(Color::White, Color::Blue);
+            } else {
+                writer.set_color(Color::LightCyan, Color::Blue);
+            }
+
+            writer.write_byte(b' ');
+            let name = entry.name();
+            write_padded(writer, &name[..name.len().min(NAME_WIDTH)], NAME_WIDTH);
+            let mut size_buf = [b' '; 11];
+            if entry.is_dir {
+                size_buf[6..].copy_from_slice(b"<DIR>");
+            } else {
+                format_right(entry.size, &mut size_buf);
+            }
+            writer.write_bytes(&size_buf);
+            writer.write_byte(b' ');
+
+            writer.set_color(Color::LightCyan, Color::Blue);
+            writer.write_byte(b'|');
+        }
+    }
+
+    fn draw_status(&self, writer: &mut Writer, message: &str, color: Color) {
+        writer.set_position(0, STATUS_ROW);
+        writer.set_color(color, Color::Black);
+        writer.write_byte(b' ');
+        write_padded(writer, message.as_bytes(), SCREEN_WIDTH - 1);
+    }
+}
+
+fn write_padded(writer: &mut Writer, text: &[u8], width: usize) {
+    let len = text.len().min(width);
+    writer.write_bytes(&text[..len]);
+    for _ in len..width {
+        writer.write_byte(b' ');
+    }
+}
+
+/// Right-align a number in `buf`
+fn format_right(mut n: usize, buf: &mut [u8]) {
+    let mut i = buf.len();
+    loop {
+        i -= 1;
+        buf[i] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 || i == 0 {
+            break;
+        }
+    }
+}
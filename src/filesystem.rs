@@ -1,9 +1,14 @@
+use crate::integrity::{Checksum, Seal};
+
 const MAX_FILES: usize = 32;
 const MAX_FILENAME_LEN: usize = 32;
-const MAX_FILE_SIZE: usize = 4096;
-const MAX_PATH_LEN: usize = 128;
+pub const MAX_FILE_SIZE: usize = 4096;
+pub const MAX_PATH_LEN: usize = 128;
 const MAX_DIRS: usize = 16;
 
+const FILE_MAGIC: u32 = 0xF11E_F11E;
+const DIRECTORY_MAGIC: u32 = 0xD1D1_D1D1;
+
 #[derive(Clone, Copy)]
 pub struct File {
     pub name: [u8; MAX_FILENAME_LEN],
@@ -12,17 +17,39 @@ pub struct File {
     pub size: usize,
     pub is_used: bool,
     pub parent_dir: usize,
+    /// Covers everything but the contents
+    seal: Seal,
 }
 
 impl File {
     pub const fn empty() -> Self {
-        Self {
+        let mut file = Self {
             name: [0; MAX_FILENAME_LEN],
             name_len: 0,
             data: [0; MAX_FILE_SIZE],
             size: 0,
             is_used: false,
             parent_dir: 0,
+            seal: Seal::new(FILE_MAGIC, 0),
+        };
+        file.reseal();
+        file
+    }
+
+    const fn checksum(&self) -> u32 {
+        Checksum::new()
+            .bytes(&self.name)
+            .word(self.name_len)
+            .word(self.size)
+            .word(self.is_used as usize)
+            .word(self.parent_dir)
+            .finish()
+    }
+
+    /// Call after changing any field
+    const fn reseal(&mut self) {
+        if cfg!(debug_assertions) {
+            self.seal.update(self.checksum());
         }
     }
 }
@@ -33,19 +60,49 @@ pub struct Directory {
     pub name_len: usize,
     pub is_used: bool,
     pub parent_dir: usize,
+    seal: Seal,
 }
 
 impl Directory {
     pub const fn empty() -> Self {
-        Self {
+        let mut dir = Self {
             name: [0; MAX_FILENAME_LEN],
             name_len: 0,
             is_used: false,
             parent_dir: 0,
+            seal: Seal::new(DIRECTORY_MAGIC, 0),
+        };
+        dir.reseal();
+        dir
+    }
+
+    const fn checksum(&self) -> u32 {
+        Checksum::new()
+            .bytes(&self.name)
+            .word(self.name_len)
+            .word(self.is_used as usize)
+            .word(self.parent_dir)
+            .finish()
+    }
+
+    /// Call after changing any field
+    const fn reseal(&mut self) {
+        if cfg!(debug_assertions) {
+            self.seal.update(self.checksum());
         }
     }
 }
 
+/// Slot and byte usage of the file system
+pub struct Usage {
+    pub files: usize,
+    pub max_files: usize,
+    pub directories: usize,
+    pub max_directories: usize,
+    pub bytes: usize,
+    pub capacity: usize,
+}
+
 pub struct FileSystem {
     files: [File; MAX_FILES],
     directories: [Directory; MAX_DIRS],
@@ -64,6 +121,7 @@ impl FileSystem {
         fs.directories[0].name[0] = b'/';
         fs.directories[0].name_len = 1;
         fs.directories[0].parent_dir = 0;
+        fs.directories[0].reseal();
         
         fs
     }
@@ -74,6 +132,7 @@ impl FileSystem {
     }
 
     pub fn create_file(&mut self, name: &[u8], content: &[u8]) -> Result<(), &'static str> {
+        self.check();
         if name.len() > MAX_FILENAME_LEN {
             return Err("Filename too long");
         }
@@ -98,6 +157,7 @@ impl FileSystem {
                 file.size = content.len();
                 file.is_used = true;
                 file.parent_dir = self.current_dir;
+                file.reseal();
                 return Ok(());
             }
         }
@@ -106,6 +166,7 @@ impl FileSystem {
     }
 
     pub fn read_file(&self, name: &[u8]) -> Option<&[u8]> {
+        self.check();
         for file in self.files.iter() {
             if file.is_used && file.parent_dir == self.current_dir
                 && file.name_len == name.len() 
@@ -117,7 +178,64 @@ impl FileSystem {
         None
     }
 
+    /// Read a file by path, absolute or relative to the current directory,
+    /// e.g. `/scripts/boot.sh` or `../notes`
+    pub fn read_path(&self, path: &[u8]) -> Option<&[u8]> {
+        self.check();
+        let (dir, name) = self.resolve(path)?;
+        self.files.iter()
+            .find(|file| file.is_used && file.parent_dir == dir && &file.name[..file.name_len] == name)
+            .map(|file| &file.data[..file.size])
+    }
+
+    /// Write a file by path like `read_path`, creating it if needed. The
+    /// directories on the way must already exist.
+    pub fn write_path(&mut self, path: &[u8], content: &[u8]) -> Result<(), &'static str> {
+        let (dir, name) = self.resolve(path).ok_or("Directory not found")?;
+        let saved = self.current_dir;
+        self.current_dir = dir;
+        let result = self.write_file(name, content);
+        self.current_dir = saved;
+        result
+    }
+
+    /// Panic if an entry was changed other than through these methods. Only
+    /// debug builds check.
+    fn check(&self) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        for (i, file) in self.files.iter().enumerate() {
+            file.seal.verify(FILE_MAGIC, file.checksum(), "file", i);
+        }
+        for (i, dir) in self.directories.iter().enumerate() {
+            dir.seal.verify(DIRECTORY_MAGIC, dir.checksum(), "directory", i);
+        }
+    }
+
+    /// Directory holding the last component of `path`, and that component
+    fn resolve<'a>(&self, path: &'a [u8]) -> Option<(usize, &'a [u8])> {
+        let mut dir = if path.first() == Some(&b'/') { 0 } else { self.current_dir };
+        let (dirs, name) = match path.iter().rposition(|&b| b == b'/') {
+            Some(split) => (&path[..split], &path[split + 1..]),
+            None => (&b""[..], path),
+        };
+        for part in dirs.split(|&b| b == b'/') {
+            dir = match part {
+                b"" | b"." => dir,
+                b".." => self.directories[dir].parent_dir,
+                _ => (0..MAX_DIRS).find(|&i| {
+                    let candidate = &self.directories[i];
+                    candidate.is_used && i != 0 && candidate.parent_dir == dir
+                        && &candidate.name[..candidate.name_len] == part
+                })?,
+            };
+        }
+        Some((dir, name))
+    }
+
     pub fn write_file(&mut self, name: &[u8], content: &[u8]) -> Result<(), &'static str> {
+        self.check();
         if content.len() > MAX_FILE_SIZE {
             return Err("Content too large");
         }
@@ -129,6 +247,7 @@ impl FileSystem {
             {
                 file.data[..content.len()].copy_from_slice(content);
                 file.size = content.len();
+                file.reseal();
                 return Ok(());
             }
         }
@@ -137,6 +256,7 @@ impl FileSystem {
     }
 
     pub fn append_file(&mut self, name: &[u8], content: &[u8]) -> Result<(), &'static str> {
+        self.check();
         for file in self.files.iter_mut() {
             if file.is_used && file.parent_dir == self.current_dir
                 && file.name_len == name.len() 
@@ -147,6 +267,7 @@ impl FileSystem {
                 }
                 file.data[file.size..file.size + content.len()].copy_from_slice(content);
                 file.size += content.len();
+                file.reseal();
                 return Ok(());
             }
         }
@@ -155,6 +276,7 @@ impl FileSystem {
     }
 
     pub fn delete_file(&mut self, name: &[u8]) -> Result<(), &'static str> {
+        self.check();
         for file in self.files.iter_mut() {
             if file.is_used && file.parent_dir == self.current_dir
                 && file.name_len == name.len() 
@@ -163,13 +285,48 @@ impl FileSystem {
                 file.is_used = false;
                 file.size = 0;
                 file.name_len = 0;
+                file.reseal();
                 return Ok(());
             }
         }
         Err("File not found")
     }
 
+    /// Delete a file by path like `read_path`
+    pub fn delete_path(&mut self, path: &[u8]) -> Result<(), &'static str> {
+        let (dir, name) = self.resolve(path).ok_or("Directory not found")?;
+        let saved = self.current_dir;
+        self.current_dir = dir;
+        let result = self.delete_file(name);
+        self.current_dir = saved;
+        result
+    }
+
+    /// List a directory by path, absolute or relative, like `list_files`
+    /// does the current one
+    pub fn list_directory(&self, path: &[u8]) -> Option<FileIterator> {
+        self.check();
+        let (parent, name) = self.resolve(path)?;
+        let dir = match name {
+            b"" | b"." => parent,
+            b".." => self.directories[parent].parent_dir,
+            _ => (1..MAX_DIRS).find(|&i| {
+                let candidate = &self.directories[i];
+                candidate.is_used && candidate.parent_dir == parent && &candidate.name[..candidate.name_len] == name
+            })?,
+        };
+        Some(FileIterator {
+            files: &self.files,
+            directories: &self.directories,
+            current_dir: dir,
+            file_index: 0,
+            dir_index: 0,
+            phase: ListPhase::Directories,
+        })
+    }
+
     pub fn list_files(&self) -> FileIterator {
+        self.check();
         FileIterator {
             files: &self.files,
             directories: &self.directories,
@@ -181,6 +338,7 @@ impl FileSystem {
     }
 
     pub fn file_exists(&self, name: &[u8]) -> bool {
+        self.check();
         for file in self.files.iter() {
             if file.is_used && file.parent_dir == self.current_dir
                 && file.name_len == name.len() 
@@ -193,6 +351,7 @@ impl FileSystem {
     }
 
     pub fn create_directory(&mut self, name: &[u8]) -> Result<(), &'static str> {
+        self.check();
         if name.len() > MAX_FILENAME_LEN {
             return Err("Directory name too long");
         }
@@ -212,6 +371,7 @@ impl FileSystem {
                 self.directories[i].name_len = name.len();
                 self.directories[i].is_used = true;
                 self.directories[i].parent_dir = self.current_dir;
+                self.directories[i].reseal();
                 return Ok(());
             }
         }
@@ -220,6 +380,7 @@ impl FileSystem {
     }
 
     pub fn change_directory(&mut self, name: &[u8]) -> Result<(), &'static str> {
+        self.check();
         if name == b".." {
             if self.current_dir == 0 {
                 return Ok(()); // Already at root
@@ -247,6 +408,7 @@ impl FileSystem {
     }
 
     pub fn get_current_path(&self, buffer: &mut [u8; MAX_PATH_LEN]) -> usize {
+        self.check();
         if self.current_dir == 0 {
             buffer[0] = b'/';
             return 1;
@@ -276,7 +438,82 @@ impl FileSystem {
         pos
     }
 
+    pub fn usage(&self) -> Usage {
+        self.check();
+        let used = self.files.iter().filter(|f| f.is_used);
+        Usage {
+            files: used.clone().count(),
+            max_files: MAX_FILES,
+            directories: self.directories.iter().filter(|d| d.is_used).count(),
+            max_directories: MAX_DIRS,
+            bytes: used.map(|f| f.size).sum(),
+            capacity: MAX_FILES * MAX_FILE_SIZE,
+        }
+    }
+
+    pub fn current_dir(&self) -> usize {
+        self.current_dir
+    }
+
+    pub fn set_current_dir(&mut self, dir: usize) {
+        if dir < MAX_DIRS && self.directories[dir].is_used {
+            self.current_dir = dir;
+        }
+    }
+
+    /// Copy a file from the current directory into `dest_dir`
+    pub fn copy_file_to(&mut self, name: &[u8], dest_dir: usize) -> Result<(), &'static str> {
+        self.check();
+        let src = self.find_file(name).ok_or("File not found")?;
+        if self.find_file_in(name, dest_dir).is_some() {
+            return Err("File already exists");
+        }
+        let dst = self.files.iter().position(|f| !f.is_used).ok_or("No space for new file")?;
+
+        let size = self.files[src].size;
+        let (from, to) = if src < dst {
+            let (a, b) = self.files.split_at_mut(dst);
+            (&a[src], &mut b[0])
+        } else {
+            let (a, b) = self.files.split_at_mut(src);
+            (&b[0], &mut a[dst])
+        };
+        to.name = from.name;
+        to.name_len = from.name_len;
+        to.data[..size].copy_from_slice(&from.data[..size]);
+        to.size = size;
+        to.is_used = true;
+        to.parent_dir = dest_dir;
+        to.reseal();
+        Ok(())
+    }
+
+    /// Move a file from the current directory into `dest_dir`
+    pub fn move_file_to(&mut self, name: &[u8], dest_dir: usize) -> Result<(), &'static str> {
+        self.check();
+        let src = self.find_file(name).ok_or("File not found")?;
+        if self.find_file_in(name, dest_dir).is_some() {
+            return Err("File already exists");
+        }
+        self.files[src].parent_dir = dest_dir;
+        self.files[src].reseal();
+        Ok(())
+    }
+
+    fn find_file(&self, name: &[u8]) -> Option<usize> {
+        self.find_file_in(name, self.current_dir)
+    }
+
+    fn find_file_in(&self, name: &[u8], dir: usize) -> Option<usize> {
+        self.files.iter().position(|file| {
+            file.is_used && file.parent_dir == dir
+                && file.name_len == name.len()
+                && &file.name[..file.name_len] == name
+        })
+    }
+
     pub fn remove_directory(&mut self, name: &[u8]) -> Result<(), &'static str> {
+        self.check();
         let mut dir_index = None;
         for i in 1..MAX_DIRS {
             if self.directories[i].is_used && self.directories[i].parent_dir == self.current_dir
@@ -303,6 +540,7 @@ impl FileSystem {
 
         self.directories[idx].is_used = false;
         self.directories[idx].name_len = 0;
+        self.directories[idx].reseal();
         Ok(())
     }
 }
@@ -369,3 +607,64 @@ static mut FILE_SYSTEM: FileSystem = FileSystem::new();
 pub fn get_filesystem() -> &'static mut FileSystem {
     unsafe { &mut FILE_SYSTEM }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn create_read_delete_file() {
+        let fs = get_filesystem();
+        fs.create_file(b"test_rw", b"hello").unwrap();
+        assert_eq!(fs.read_file(b"test_rw"), Some(&b"hello"[..]));
+        assert!(fs.create_file(b"test_rw", b"again").is_err());
+        fs.delete_file(b"test_rw").unwrap();
+        assert!(!fs.file_exists(b"test_rw"));
+    }
+
+    #[test_case]
+    fn append_respects_max_size() {
+        let fs = get_filesystem();
+        fs.write_file(b"test_append", b"ab").unwrap();
+        fs.append_file(b"test_append", b"cd").unwrap();
+        assert_eq!(fs.read_file(b"test_append"), Some(&b"abcd"[..]));
+        assert!(fs.append_file(b"test_append", &[0; MAX_FILE_SIZE]).is_err());
+        fs.delete_file(b"test_append").unwrap();
+    }
+
+    #[test_case]
+    fn directories_scope_files() {
+        let fs = get_filesystem();
+        fs.create_directory(b"test_dir").unwrap();
+        fs.change_directory(b"test_dir").unwrap();
+        fs.create_file(b"inner", b"x").unwrap();
+        fs.change_directory(b"..").unwrap();
+        assert!(!fs.file_exists(b"inner"));
+        assert_eq!(fs.remove_directory(b"test_dir"), Err("Directory not empty"));
+
+        fs.change_directory(b"test_dir").unwrap();
+        fs.delete_file(b"inner").unwrap();
+        fs.change_directory(b"..").unwrap();
+        fs.remove_directory(b"test_dir").unwrap();
+    }
+
+    #[test_case]
+    fn read_path_walks_directories() {
+        let fs = get_filesystem();
+        fs.create_directory(b"test_path").unwrap();
+        fs.change_directory(b"test_path").unwrap();
+        fs.create_file(b"leaf", b"found").unwrap();
+        assert_eq!(fs.read_path(b"../test_path/./leaf"), Some(&b"found"[..]));
+        fs.change_directory(b"/").unwrap();
+        assert_eq!(fs.read_path(b"/test_path/leaf"), Some(&b"found"[..]));
+        assert_eq!(fs.read_path(b"test_path/missing"), None);
+        fs.write_path(b"test_path/leaf", b"again").unwrap();
+        assert_eq!(fs.read_path(b"/test_path/leaf"), Some(&b"again"[..]));
+        assert!(fs.write_path(b"/nowhere/leaf", b"").is_err());
+
+        fs.change_directory(b"test_path").unwrap();
+        fs.delete_file(b"leaf").unwrap();
+        fs.change_directory(b"..").unwrap();
+        fs.remove_directory(b"test_path").unwrap();
+    }
+}
@@ -0,0 +1,154 @@
+use core::fmt::{self as core_fmt, Display, Formatter};
+
+use crate::env;
+
+// Numbers for people: byte sizes in KiB/MiB/GiB, counts with thousands
+// separators and durations in days, hours, minutes and seconds. The
+// separators follow `LC_NUMERIC`, else `LANG`, so `set LANG de_DE` shows
+// 1.234.567 and 1,5 MiB. Each is a `Display` wrapper, used as
+// `write!(out, "{}", Size(bytes))`.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Locale {
+    /// Between groups of three digits, `None` for no grouping
+    pub thousands: Option<char>,
+    pub decimal: char,
+}
+
+impl Locale {
+    pub const ENGLISH: Locale = Locale { thousands: Some(','), decimal: '.' };
+    /// `C` and `POSIX`: no grouping
+    pub const PLAIN: Locale = Locale { thousands: None, decimal: '.' };
+
+    /// The conventions for a locale name such as `de_DE.UTF-8`, going by
+    /// its language; unknown languages get English ones
+    pub fn from_name(name: &[u8]) -> Locale {
+        let language = name.split(|&b| b == b'_' || b == b'.' || b == b'-').next().unwrap_or(b"");
+        match language {
+            b"C" | b"POSIX" => Locale::PLAIN,
+            b"de" | b"es" | b"it" | b"nl" | b"pt" | b"da" | b"id" | b"tr" => Locale { thousands: Some('.'), decimal: ',' },
+            b"fr" | b"ru" | b"pl" | b"cs" | b"sv" | b"fi" | b"nb" | b"uk" => Locale { thousands: Some(' '), decimal: ',' },
+            _ => Locale::ENGLISH,
+        }
+    }
+
+    pub fn current() -> Locale {
+        let env = env::get();
+        match env.get(b"LC_NUMERIC").or_else(|| env.get(b"LANG")) {
+            Some(name) if !name.is_empty() => Locale::from_name(name),
+            _ => Locale::ENGLISH,
+        }
+    }
+}
+
+/// A count with thousands separators
+#[derive(Debug, Clone, Copy)]
+pub struct Thousands(pub u64);
+
+impl Display for Thousands {
+    fn fmt(&self, f: &mut Formatter) -> core_fmt::Result {
+        write_grouped(f, self.0, Locale::current())
+    }
+}
+
+fn write_grouped(out: &mut dyn core_fmt::Write, n: u64, locale: Locale) -> core_fmt::Result {
+    let separator = match locale.thousands {
+        Some(separator) if n >= 1000 => separator,
+        _ => return write!(out, "{}", n),
+    };
+    let mut groups = [0u16; 7];
+    let mut count = 0;
+    let mut rest = n;
+    while rest > 0 {
+        groups[count] = (rest % 1000) as u16;
+        rest /= 1000;
+        count += 1;
+    }
+    write!(out, "{}", groups[count - 1])?;
+    for &group in groups[..count - 1].iter().rev() {
+        write!(out, "{}{:03}", separator, group)?;
+    }
+    Ok(())
+}
+
+/// A size in bytes, in the largest binary unit it reaches, to a tenth
+#[derive(Debug, Clone, Copy)]
+pub struct Size(pub u64);
+
+const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+impl Display for Size {
+    fn fmt(&self, f: &mut Formatter) -> core_fmt::Result {
+        write_size(f, self.0, Locale::current())
+    }
+}
+
+fn write_size(out: &mut dyn core_fmt::Write, bytes: u64, locale: Locale) -> core_fmt::Result {
+    if bytes < 1024 {
+        return write!(out, "{} B", bytes);
+    }
+    let mut unit = 0;
+    let mut scale = 1024u64;
+    while unit + 1 < UNITS.len() && bytes >= scale * 1024 {
+        unit += 1;
+        scale *= 1024;
+    }
+    // Rounded to the nearest tenth
+    let tenths = bytes / scale * 10 + (bytes % scale * 10 + scale / 2) / scale;
+    write_grouped(out, tenths / 10, locale)?;
+    write!(out, "{}{} {}", locale.decimal, tenths % 10, UNITS[unit])
+}
+
+/// Seconds as `1d 02h 03m 04s`, leaving out leading zero units
+#[derive(Debug, Clone, Copy)]
+pub struct Duration(pub u64);
+
+impl Display for Duration {
+    fn fmt(&self, f: &mut Formatter) -> core_fmt::Result {
+        let secs = self.0;
+        let (days, hours, minutes, seconds) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+        if days > 0 {
+            write!(f, "{}d {:02}h {:02}m {:02}s", days, hours, minutes, seconds)
+        } else if hours > 0 {
+            write!(f, "{}h {:02}m {:02}s", hours, minutes, seconds)
+        } else if minutes > 0 {
+            write!(f, "{}m {:02}s", minutes, seconds)
+        } else {
+            write!(f, "{}s", seconds)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+
+    struct Text {
+        buf: [u8; 128],
+        len: usize,
+    }
+
+    impl Write for Text {
+        fn write_str(&mut self, s: &str) -> core_fmt::Result {
+            self.buf[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+            self.len += s.len();
+            Ok(())
+        }
+    }
+
+    #[test_case]
+    fn sizes_counts_and_durations() {
+        let mut out = Text { buf: [0; 128], len: 0 };
+        let german = Locale::from_name(b"de_DE.UTF-8");
+        write_grouped(&mut out, 1234567, Locale::ENGLISH).unwrap();
+        write_grouped(&mut out, 1234567, german).unwrap();
+        write_grouped(&mut out, 1234567, Locale::from_name(b"C")).unwrap();
+        write_grouped(&mut out, 999, Locale::ENGLISH).unwrap();
+        write_size(&mut out, 512, Locale::ENGLISH).unwrap();
+        write_size(&mut out, 1536, german).unwrap();
+        write_size(&mut out, 5 << 30, Locale::ENGLISH).unwrap();
+        write!(out, "{} {}", Duration(59), Duration(90061)).unwrap();
+        assert_eq!(&out.buf[..out.len], &b"1,234,5671.234.5671234567999512 B1,5 KiB5.0 GiB59s 1d 01h 01m 01s"[..]);
+    }
+}
@@ -0,0 +1,206 @@
+use crate::filesystem::get_filesystem;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xB5, 0x4A, 0x86];
+const PSF1_MODE_512: u8 = 0x01;
+
+pub const MAX_GLYPH_WIDTH: usize = 16;
+pub const MAX_GLYPH_HEIGHT: usize = 32;
+
+const MAX_FONT_FILE: usize = 4096;
+const CACHE_SLOTS: usize = 64;
+
+/// Generated from DejaVu Sans Mono by convert_font.py
+static DEFAULT_FONT: &[u8] = include_bytes!("../fonts/default8x16.psf");
+
+/// A parsed PSF1/PSF2 bitmap font
+#[derive(Clone, Copy)]
+pub struct Font {
+    glyphs: &'static [u8],
+    glyph_count: usize,
+    width: usize,
+    height: usize,
+    bytes_per_row: usize,
+}
+
+impl Font {
+    pub fn parse(bytes: &'static [u8]) -> Result<Self, &'static str> {
+        if bytes.len() >= 4 && bytes[..2] == PSF1_MAGIC {
+            let height = bytes[3] as usize;
+            let glyph_count = if bytes[2] & PSF1_MODE_512 != 0 { 512 } else { 256 };
+            return Self::new(&bytes[4..], glyph_count, 8, height);
+        }
+
+        if bytes.len() >= 32 && bytes[..4] == PSF2_MAGIC {
+            let field = |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]) as usize;
+            let header_size = field(8);
+            let glyph_count = field(16);
+            let bytes_per_glyph = field(20);
+            let height = field(24);
+            let width = field(28);
+            if header_size > bytes.len() || bytes_per_glyph != (width + 7) / 8 * height {
+                return Err("Corrupt PSF2 header");
+            }
+            return Self::new(&bytes[header_size..], glyph_count, width, height);
+        }
+
+        Err("Not a PSF font")
+    }
+
+    fn new(glyphs: &'static [u8], glyph_count: usize, width: usize, height: usize) -> Result<Self, &'static str> {
+        if width == 0 || height == 0 || width > MAX_GLYPH_WIDTH || height > MAX_GLYPH_HEIGHT {
+            return Err("Unsupported glyph size");
+        }
+        let bytes_per_row = (width + 7) / 8;
+        // Files may be cut short by the ramfs size limit; keep the glyphs that are there
+        let glyph_count = glyph_count.min(glyphs.len() / (bytes_per_row * height));
+        if glyph_count == 0 {
+            return Err("Font has no glyphs");
+        }
+        Ok(Self { glyphs, glyph_count, width, height, bytes_per_row })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn glyph_count(&self) -> usize {
+        self.glyph_count
+    }
+
+    /// Whether pixel (x, y) of `ch` is set; missing glyphs render as '?'
+    pub fn pixel(&self, ch: u8, x: usize, y: usize) -> bool {
+        let index = if (ch as usize) < self.glyph_count {
+            ch as usize
+        } else if self.glyph_count > b'?' as usize {
+            b'?' as usize
+        } else {
+            0
+        };
+        let glyph_size = self.bytes_per_row * self.height;
+        let row = &self.glyphs[index * glyph_size + y * self.bytes_per_row..];
+        row[x / 8] & (0x80 >> (x % 8)) != 0
+    }
+}
+
+static mut CURRENT: Option<Font> = None;
+static mut LOADED: [u8; MAX_FONT_FILE] = [0; MAX_FONT_FILE];
+
+/// The font used for graphics-mode text
+pub fn current() -> Font {
+    unsafe {
+        match CURRENT {
+            Some(font) => font,
+            None => {
+                let font = Font::parse(DEFAULT_FONT).expect("embedded font is valid");
+                CURRENT = Some(font);
+                font
+            }
+        }
+    }
+}
+
+/// Replace the current font with a PSF file from the ramfs
+pub fn load(filename: &[u8]) -> Result<Font, &'static str> {
+    let content = get_filesystem().read_file(filename).ok_or("File not found")?;
+    // Validate before overwriting the buffer the current font may live in
+    Font::parse(content)?;
+    let font = unsafe {
+        LOADED[..content.len()].copy_from_slice(content);
+        Font::parse(&LOADED[..content.len()])?
+    };
+    set_current(font);
+    Ok(font)
+}
+
+pub fn reset() {
+    set_current(Font::parse(DEFAULT_FONT).expect("embedded font is valid"));
+}
+
+fn set_current(font: Font) {
+    unsafe {
+        CURRENT = Some(font);
+        for slot in CACHE.iter_mut() {
+            slot.valid = false;
+        }
+    }
+}
+
+/// A glyph expanded to 32bpp pixels for one color pair
+struct CachedGlyph {
+    valid: bool,
+    ch: u8,
+    fg: u32,
+    bg: u32,
+    pixels: [u32; MAX_GLYPH_WIDTH * MAX_GLYPH_HEIGHT],
+}
+
+const EMPTY_SLOT: CachedGlyph = CachedGlyph {
+    valid: false,
+    ch: 0,
+    fg: 0,
+    bg: 0,
+    pixels: [0; MAX_GLYPH_WIDTH * MAX_GLYPH_HEIGHT],
+};
+
+static mut CACHE: [CachedGlyph; CACHE_SLOTS] = [EMPTY_SLOT; CACHE_SLOTS];
+
+/// Pixels of `ch` in the current font (width * height, row-major), from a
+/// direct-mapped cache keyed on character and colors
+pub fn render(ch: u8, fg: u32, bg: u32) -> &'static [u32] {
+    let font = current();
+    let hash = (ch as u32) ^ fg.wrapping_mul(31) ^ bg.wrapping_mul(17);
+    let slot = unsafe { &mut CACHE[hash as usize % CACHE_SLOTS] };
+
+    if !(slot.valid && slot.ch == ch && slot.fg == fg && slot.bg == bg) {
+        for y in 0..font.height {
+            for x in 0..font.width {
+                slot.pixels[y * font.width + x] = if font.pixel(ch, x, y) { fg } else { bg };
+            }
+        }
+        slot.valid = true;
+        slot.ch = ch;
+        slot.fg = fg;
+        slot.bg = bg;
+    }
+    &slot.pixels[..font.width * font.height]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn embedded_font_is_8x16() {
+        let font = Font::parse(DEFAULT_FONT).unwrap();
+        assert_eq!((font.width(), font.height(), font.glyph_count()), (8, 16, 256));
+        // Full block fills the cell, space is empty
+        assert!(font.pixel(0xDB, 0, 0) && font.pixel(0xDB, 7, 15));
+        assert!(!(0..16).any(|y| (0..8).any(|x| font.pixel(b' ', x, y))));
+    }
+
+    #[test_case]
+    fn truncated_psf2_keeps_complete_glyphs() {
+        static FONT: [u8; 32 + 3 * 8] = {
+            let mut data = [0u8; 32 + 3 * 8];
+            let header = [0x72, 0xB5, 0x4A, 0x86, 0, 0, 0, 0, 32, 0, 0, 0, 0, 0, 0, 0,
+                          128, 0, 0, 0, 8, 0, 0, 0, 8, 0, 0, 0, 8, 0, 0, 0];
+            let mut i = 0;
+            while i < 32 {
+                data[i] = header[i];
+                i += 1;
+            }
+            data[32 + 8] = 0x80;
+            data
+        };
+        let font = Font::parse(&FONT).unwrap();
+        assert_eq!((font.width(), font.height(), font.glyph_count()), (8, 8, 3));
+        assert!(font.pixel(1, 0, 0));
+        // Out-of-range characters fall back to glyph 0
+        assert!(!font.pixel(b'A', 0, 0));
+    }
+}
@@ -0,0 +1,476 @@
+use crate::io::{inb, outb, outw, inw};
+use crate::pci;
+use crate::font;
+
+// Bochs/QEMU "DISPI" interface (BGA), also implemented by VirtualBox
+const VBE_INDEX_PORT: u16 = 0x01CE;
+const VBE_DATA_PORT: u16 = 0x01CF;
+
+const VBE_INDEX_ID: u16 = 0;
+const VBE_INDEX_XRES: u16 = 1;
+const VBE_INDEX_YRES: u16 = 2;
+const VBE_INDEX_BPP: u16 = 3;
+const VBE_INDEX_ENABLE: u16 = 4;
+
+const VBE_ID_MIN: u16 = 0xB0C0;
+const VBE_ENABLED: u16 = 0x01;
+const VBE_LFB_ENABLED: u16 = 0x40;
+
+/// LFB address of the ISA Bochs adapter when no PCI device is found
+const DEFAULT_LFB_ADDRESS: usize = 0xE000_0000;
+
+const MAX_WIDTH: usize = 1280;
+const MAX_HEIGHT: usize = 1024;
+
+const TEXT_BUFFER: usize = 0xb8000;
+const TEXT_COLS: usize = 80;
+const TEXT_ROWS: usize = 25;
+
+const SAVED_GLYPH_HEIGHT: usize = 16;
+
+/// The 16 text-mode colors as 0xRRGGBB
+const PALETTE: [u32; 16] = [
+    0x000000, 0x0000AA, 0x00AA00, 0x00AAAA, 0xAA0000, 0xAA00AA, 0xAA5500, 0xAAAAAA,
+    0x555555, 0x5555FF, 0x55FF55, 0x55FFFF, 0xFF5555, 0xFF55FF, 0xFFFF55, 0xFFFFFF,
+];
+
+/// A 32bpp linear framebuffer
+#[derive(Clone, Copy)]
+pub struct Framebuffer {
+    address: usize,
+    width: usize,
+    height: usize,
+    pitch: usize,
+}
+
+impl Framebuffer {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn put_pixel(&self, x: usize, y: usize, color: u32) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        unsafe {
+            *((self.address + y * self.pitch + x * 4) as *mut u32) = color;
+        }
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> u32 {
+        if x >= self.width || y >= self.height {
+            return 0;
+        }
+        unsafe { *((self.address + y * self.pitch + x * 4) as *const u32) }
+    }
+
+    /// Fill a rectangle, clipped to the screen
+    pub fn fill_rect(&self, x: usize, y: usize, w: usize, h: usize, color: u32) {
+        let x_end = (x + w).min(self.width);
+        let y_end = (y + h).min(self.height);
+        for row in y..y_end {
+            let line = (self.address + row * self.pitch) as *mut u32;
+            for col in x..x_end {
+                unsafe {
+                    *line.add(col) = color;
+                }
+            }
+        }
+    }
+
+    pub fn clear(&self, color: u32) {
+        self.fill_rect(0, 0, self.width, self.height, color);
+    }
+
+    /// Copy a `w` x `h` block of 0xRRGGBB pixels (row-major) to the screen
+    pub fn blit(&self, x: usize, y: usize, w: usize, h: usize, pixels: &[u32]) {
+        if w == 0 || pixels.len() < w * h || x >= self.width {
+            return;
+        }
+        let visible = w.min(self.width - x);
+        for row in 0..h.min(self.height.saturating_sub(y)) {
+            let src = &pixels[row * w..row * w + visible];
+            let dst = (self.address + (y + row) * self.pitch + x * 4) as *mut u32;
+            unsafe {
+                core::ptr::copy_nonoverlapping(src.as_ptr(), dst, visible);
+            }
+        }
+    }
+
+    /// Draw one character of the current font with an opaque background
+    pub fn draw_char(&self, x: usize, y: usize, ch: u8, fg: u32, bg: u32) {
+        let font = font::current();
+        self.blit(x, y, font.width(), font.height(), font::render(ch, fg, bg));
+    }
+
+    /// Draw a character with each font pixel enlarged to `scale` x `scale`
+    pub fn draw_char_scaled(&self, x: usize, y: usize, ch: u8, fg: u32, bg: u32, scale: usize) {
+        if scale <= 1 {
+            self.draw_char(x, y, ch, fg, bg);
+            return;
+        }
+        let font = font::current();
+        let pixels = font::render(ch, fg, bg);
+        for gy in 0..font.height() {
+            for gx in 0..font.width() {
+                let color = pixels[gy * font.width() + gx];
+                self.fill_rect(x + gx * scale, y + gy * scale, scale, scale, color);
+            }
+        }
+    }
+
+    pub fn draw_text(&self, x: usize, y: usize, text: &[u8], fg: u32, bg: u32, scale: usize) {
+        let advance = font::current().width() * scale.max(1);
+        for (i, &ch) in text.iter().enumerate() {
+            self.draw_char_scaled(x + i * advance, y, ch, fg, bg, scale);
+        }
+    }
+}
+
+static mut ACTIVE: Option<Framebuffer> = None;
+static mut CONSOLE: bool = false;
+static mut CURSOR: usize = 0;
+static mut CONSOLE_SCALE: usize = 1;
+
+/// The text-mode font, saved from VGA plane 2 since the LFB overwrites it
+static mut SAVED_FONT: [u8; 256 * SAVED_GLYPH_HEIGHT] = [0; 256 * SAVED_GLYPH_HEIGHT];
+
+/// Text cells while the console is drawn in graphics mode; VRAM is shared
+/// with the linear framebuffer, so 0xB8000 cannot be used meanwhile
+static mut SHADOW: [u8; TEXT_COLS * TEXT_ROWS * 2] = [0; TEXT_COLS * TEXT_ROWS * 2];
+
+static mut SAVED_VGA: VgaState = VgaState::empty();
+
+pub fn is_available() -> bool {
+    unsafe { vbe_read(VBE_INDEX_ID) >= VBE_ID_MIN }
+}
+
+pub fn active() -> Option<&'static Framebuffer> {
+    unsafe { ACTIVE.as_ref() }
+}
+
+/// Switch to a `width` x `height` 32bpp linear framebuffer mode
+pub fn set_mode(width: usize, height: usize) -> Result<&'static Framebuffer, &'static str> {
+    if !is_available() {
+        return Err("No Bochs/QEMU VBE adapter found");
+    }
+    if width == 0 || height == 0 || width > MAX_WIDTH || height > MAX_HEIGHT {
+        return Err("Unsupported resolution");
+    }
+
+    unsafe {
+        if ACTIVE.is_none() {
+            SAVED_VGA = VgaState::save();
+            read_font();
+            core::ptr::copy_nonoverlapping(TEXT_BUFFER as *const u8, SHADOW.as_mut_ptr(), SHADOW.len());
+        }
+
+        vbe_write(VBE_INDEX_ENABLE, 0);
+        vbe_write(VBE_INDEX_XRES, width as u16);
+        vbe_write(VBE_INDEX_YRES, height as u16);
+        vbe_write(VBE_INDEX_BPP, 32);
+        vbe_write(VBE_INDEX_ENABLE, VBE_ENABLED | VBE_LFB_ENABLED);
+
+        // The adapter may round or refuse the request
+        let width = vbe_read(VBE_INDEX_XRES) as usize;
+        let height = vbe_read(VBE_INDEX_YRES) as usize;
+
+        let fb = Framebuffer { address: lfb_address(), width, height, pitch: width * 4 };
+        fb.clear(0);
+        ACTIVE = Some(fb);
+        Ok(ACTIVE.as_ref().unwrap())
+    }
+}
+
+/// Return to 80x25 text mode, restoring the font and screen contents
+pub fn restore_text_mode() {
+    unsafe {
+        if ACTIVE.is_none() {
+            return;
+        }
+        vbe_write(VBE_INDEX_ENABLE, 0);
+        SAVED_VGA.restore();
+        write_font();
+        core::ptr::copy_nonoverlapping(SHADOW.as_ptr(), TEXT_BUFFER as *mut u8, SHADOW.len());
+        ACTIVE = None;
+        CONSOLE = false;
+    }
+}
+
+/// What to return to after a full-screen graphics program
+pub struct SavedMode {
+    console: Option<(usize, usize)>,
+}
+
+/// Switch to `width` x `height` for a full-screen program, remembering
+/// whether the graphics console was active
+pub fn begin_fullscreen(width: usize, height: usize) -> Result<(Framebuffer, SavedMode), &'static str> {
+    let console = match active() {
+        Some(fb) if console_enabled() => Some((fb.width, fb.height)),
+        _ => None,
+    };
+    let fb = *set_mode(width, height)?;
+    Ok((fb, SavedMode { console }))
+}
+
+/// Go back to text mode or the graphics console, redrawing the text screen
+pub fn end_fullscreen(saved: SavedMode) {
+    if let Some((width, height)) = saved.console {
+        if set_mode(width, height).is_ok() {
+            enable_console();
+            return;
+        }
+    }
+    restore_text_mode();
+}
+
+/// Render the text console into the framebuffer from now on
+pub fn enable_console() {
+    unsafe {
+        if ACTIVE.is_some() {
+            CONSOLE = true;
+            redraw_text();
+        }
+    }
+}
+
+/// Enlarge console glyphs by an integer factor
+pub fn set_console_scale(scale: usize) {
+    unsafe {
+        CONSOLE_SCALE = scale.max(1);
+    }
+    font_changed();
+}
+
+pub fn console_scale() -> usize {
+    unsafe { CONSOLE_SCALE }
+}
+
+/// Lay the console out again after the cell size changed
+pub fn font_changed() {
+    if !console_enabled() {
+        return;
+    }
+    if let Some(fb) = active() {
+        fb.clear(0);
+    }
+    redraw_text();
+}
+
+pub fn console_enabled() -> bool {
+    unsafe { CONSOLE }
+}
+
+/// Where `Writer` should store text cells
+pub fn text_buffer() -> *mut u8 {
+    unsafe {
+        if CONSOLE { SHADOW.as_mut_ptr() } else { TEXT_BUFFER as *mut u8 }
+    }
+}
+
+/// Pixel size of one console cell
+fn cell_size() -> (usize, usize) {
+    let font = font::current();
+    let scale = unsafe { CONSOLE_SCALE };
+    (font.width() * scale, font.height() * scale)
+}
+
+/// Top-left pixel of the centered 80x25 text grid
+fn text_origin(fb: &Framebuffer) -> (usize, usize) {
+    let (cell_width, cell_height) = cell_size();
+    (
+        fb.width.saturating_sub(TEXT_COLS * cell_width) / 2,
+        fb.height.saturating_sub(TEXT_ROWS * cell_height) / 2,
+    )
+}
+
+/// Redraw one text cell after `Writer` changed it
+pub fn text_cell_changed(col: usize, row: usize) {
+    if !console_enabled() || col >= TEXT_COLS || row >= TEXT_ROWS {
+        return;
+    }
+    let fb = match active() {
+        Some(fb) => fb,
+        None => return,
+    };
+
+    let offset = (row * TEXT_COLS + col) * 2;
+    let (ch, attribute) = unsafe { (SHADOW[offset], SHADOW[offset + 1]) };
+    let fg = PALETTE[(attribute & 0x0F) as usize];
+    let bg = PALETTE[(attribute >> 4) as usize];
+    let (cell_width, cell_height) = cell_size();
+    let (x0, y0) = text_origin(fb);
+    let (x, y) = (x0 + col * cell_width, y0 + row * cell_height);
+    fb.draw_char_scaled(x, y, ch, fg, bg, unsafe { CONSOLE_SCALE });
+
+    // Underline cursor, like the hardware one in text mode
+    if unsafe { CURSOR } == row * TEXT_COLS + col {
+        let thickness = cell_height / 8;
+        fb.fill_rect(x, y + cell_height - thickness, cell_width, thickness, fg);
+    }
+}
+
+/// Redraw the whole text grid (after scrolling or clearing)
+pub fn redraw_text() {
+    if !console_enabled() {
+        return;
+    }
+    for row in 0..TEXT_ROWS {
+        for col in 0..TEXT_COLS {
+            text_cell_changed(col, row);
+        }
+    }
+}
+
+pub fn move_cursor(col: usize, row: usize) {
+    let old = unsafe { CURSOR };
+    unsafe {
+        CURSOR = row * TEXT_COLS + col;
+    }
+    text_cell_changed(old % TEXT_COLS, old / TEXT_COLS);
+    text_cell_changed(col, row);
+}
+
+fn lfb_address() -> usize {
+    pci::find_device(0x1234, 0x1111)
+        .or_else(|| pci::find_device(0x80EE, 0xBEEF))
+        .map(|dev| dev.bar(0) as usize)
+        .unwrap_or(DEFAULT_LFB_ADDRESS)
+}
+
+unsafe fn vbe_write(index: u16, value: u16) {
+    outw(VBE_INDEX_PORT, index);
+    outw(VBE_DATA_PORT, value);
+}
+
+unsafe fn vbe_read(index: u16) -> u16 {
+    outw(VBE_INDEX_PORT, index);
+    inw(VBE_DATA_PORT)
+}
+
+const SEQ_INDEX: u16 = 0x3C4;
+const GC_INDEX: u16 = 0x3CE;
+const CRTC_INDEX: u16 = 0x3D4;
+const ATTR_PORT: u16 = 0x3C0;
+const ATTR_READ: u16 = 0x3C1;
+const INPUT_STATUS: u16 = 0x3DA;
+const MISC_READ: u16 = 0x3CC;
+const MISC_WRITE: u16 = 0x3C2;
+
+/// Legacy VGA registers; enabling the DISPI mode rewrites some of them
+struct VgaState {
+    misc: u8,
+    seq: [u8; 5],
+    crtc: [u8; 25],
+    gc: [u8; 9],
+    attr: [u8; 21],
+}
+
+impl VgaState {
+    const fn empty() -> Self {
+        Self { misc: 0, seq: [0; 5], crtc: [0; 25], gc: [0; 9], attr: [0; 21] }
+    }
+
+    unsafe fn save() -> Self {
+        let mut state = Self::empty();
+        state.misc = inb(MISC_READ);
+        for i in 0..state.seq.len() {
+            state.seq[i] = read_indexed(SEQ_INDEX, i as u8);
+        }
+        for i in 0..state.crtc.len() {
+            state.crtc[i] = read_indexed(CRTC_INDEX, i as u8);
+        }
+        for i in 0..state.gc.len() {
+            state.gc[i] = read_indexed(GC_INDEX, i as u8);
+        }
+        for i in 0..state.attr.len() {
+            inb(INPUT_STATUS);
+            outb(ATTR_PORT, i as u8);
+            state.attr[i] = inb(ATTR_READ);
+        }
+        inb(INPUT_STATUS);
+        outb(ATTR_PORT, 0x20);
+        state
+    }
+
+    unsafe fn restore(&self) {
+        outb(MISC_WRITE, self.misc);
+        for (i, &value) in self.seq.iter().enumerate() {
+            write_indexed(SEQ_INDEX, i as u8, value);
+        }
+
+        // Unlock CRTC registers 0-7 before rewriting them
+        write_indexed(CRTC_INDEX, 0x11, read_indexed(CRTC_INDEX, 0x11) & !0x80);
+        for (i, &value) in self.crtc.iter().enumerate() {
+            write_indexed(CRTC_INDEX, i as u8, value);
+        }
+        for (i, &value) in self.gc.iter().enumerate() {
+            write_indexed(GC_INDEX, i as u8, value);
+        }
+
+        inb(INPUT_STATUS);
+        for (i, &value) in self.attr.iter().enumerate() {
+            outb(ATTR_PORT, i as u8);
+            outb(ATTR_PORT, value);
+        }
+        outb(ATTR_PORT, 0x20);
+    }
+}
+
+unsafe fn read_indexed(index_port: u16, index: u8) -> u8 {
+    outb(index_port, index);
+    inb(index_port + 1)
+}
+
+unsafe fn write_indexed(index_port: u16, index: u8, value: u8) {
+    outb(index_port, index);
+    outb(index_port + 1, value);
+}
+
+/// Map plane 2 (the font plane) linearly at 0xA0000 while `f` runs
+unsafe fn with_font_plane<F: FnOnce(*mut u8)>(f: F) {
+    let seq2 = read_indexed(SEQ_INDEX, 2);
+    let seq4 = read_indexed(SEQ_INDEX, 4);
+    let gc4 = read_indexed(GC_INDEX, 4);
+    let gc5 = read_indexed(GC_INDEX, 5);
+    let gc6 = read_indexed(GC_INDEX, 6);
+
+    write_indexed(SEQ_INDEX, 2, 0x04);
+    write_indexed(SEQ_INDEX, 4, 0x07);
+    write_indexed(GC_INDEX, 4, 0x02);
+    write_indexed(GC_INDEX, 5, 0x00);
+    write_indexed(GC_INDEX, 6, 0x04);
+
+    f(0xA0000 as *mut u8);
+
+    write_indexed(SEQ_INDEX, 2, seq2);
+    write_indexed(SEQ_INDEX, 4, seq4);
+    write_indexed(GC_INDEX, 4, gc4);
+    write_indexed(GC_INDEX, 5, gc5);
+    write_indexed(GC_INDEX, 6, gc6);
+}
+
+/// Glyphs occupy the first 16 of 32 bytes per character in plane 2
+unsafe fn read_font() {
+    with_font_plane(|plane| {
+        for ch in 0..256 {
+            for line in 0..SAVED_GLYPH_HEIGHT {
+                SAVED_FONT[ch * SAVED_GLYPH_HEIGHT + line] = *plane.add(ch * 32 + line);
+            }
+        }
+    });
+}
+
+unsafe fn write_font() {
+    with_font_plane(|plane| {
+        for ch in 0..256 {
+            for line in 0..SAVED_GLYPH_HEIGHT {
+                *plane.add(ch * 32 + line) = SAVED_FONT[ch * SAVED_GLYPH_HEIGHT + line];
+            }
+        }
+    });
+}
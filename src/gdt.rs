@@ -0,0 +1,224 @@
+use core::arch::{asm, naked_asm};
+
+use crate::crash;
+use crate::stackguard;
+use crate::usermode;
+
+pub const KERNEL_CODE: u16 = 0x08;
+pub const KERNEL_DATA: u16 = 0x10;
+/// User selectors carry RPL 3. Their segments cover only the program
+/// window, so addresses in ring 3 start at 0 there.
+pub const USER_CODE: u16 = 0x18 | 3;
+pub const USER_DATA: u16 = 0x20 | 3;
+pub const TSS_SELECTOR: u16 = 0x28;
+/// Task the CPU switches to on a double fault
+pub const DOUBLE_FAULT_TSS_SELECTOR: u16 = 0x30;
+
+const GDT_SIZE: usize = 7;
+
+/// Kernel stack for interrupts and system calls taken in ring 3
+const KERNEL_STACK_SIZE: usize = 16 * 1024;
+/// The double fault handler runs on its own stack, so a fault caused by
+/// a broken kernel stack can still be reported
+const DOUBLE_FAULT_STACK_SIZE: usize = 8 * 1024;
+
+/// 32-bit task state segment. The main TSS only supplies `esp0`/`ss0` for
+/// ring transitions and receives the CPU state on a double fault; the
+/// double fault TSS describes the task that reports it.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct TaskStateSegment {
+    pub link: u32,
+    pub esp0: u32,
+    pub ss0: u32,
+    pub esp1: u32,
+    pub ss1: u32,
+    pub esp2: u32,
+    pub ss2: u32,
+    pub cr3: u32,
+    pub eip: u32,
+    pub eflags: u32,
+    pub eax: u32,
+    pub ecx: u32,
+    pub edx: u32,
+    pub ebx: u32,
+    pub esp: u32,
+    pub ebp: u32,
+    pub esi: u32,
+    pub edi: u32,
+    pub es: u32,
+    pub cs: u32,
+    pub ss: u32,
+    pub ds: u32,
+    pub fs: u32,
+    pub gs: u32,
+    pub ldt: u32,
+    pub trap: u16,
+    pub iomap_base: u16,
+}
+
+impl TaskStateSegment {
+    const fn new() -> Self {
+        Self {
+            link: 0, esp0: 0, ss0: KERNEL_DATA as u32, esp1: 0, ss1: 0, esp2: 0, ss2: 0,
+            cr3: 0, eip: 0, eflags: 0, eax: 0, ecx: 0, edx: 0, ebx: 0, esp: 0, ebp: 0,
+            esi: 0, edi: 0, es: 0, cs: 0, ss: 0, ds: 0, fs: 0, gs: 0, ldt: 0, trap: 0,
+            // Pointing past the end means no I/O bitmap, so ring 3 gets no ports
+            iomap_base: core::mem::size_of::<TaskStateSegment>() as u16,
+        }
+    }
+}
+
+#[repr(C, packed)]
+struct GdtPointer {
+    limit: u16,
+    base: u32,
+}
+
+#[repr(C, align(16))]
+struct Stack<const N: usize>([u8; N]);
+
+impl<const N: usize> Stack<N> {
+    fn top(&self) -> u32 {
+        self.0.as_ptr() as u32 + N as u32
+    }
+
+    fn bottom(&self) -> u32 {
+        self.0.as_ptr() as u32
+    }
+}
+
+static mut GDT: [u64; GDT_SIZE] = [0; GDT_SIZE];
+static mut GDT_PTR: GdtPointer = GdtPointer { limit: 0, base: 0 };
+
+pub static mut TSS: TaskStateSegment = TaskStateSegment::new();
+static mut DOUBLE_FAULT_TSS: TaskStateSegment = TaskStateSegment::new();
+
+static mut KERNEL_STACK: Stack<KERNEL_STACK_SIZE> = Stack([0; KERNEL_STACK_SIZE]);
+static mut DOUBLE_FAULT_STACK: Stack<DOUBLE_FAULT_STACK_SIZE> = Stack([0; DOUBLE_FAULT_STACK_SIZE]);
+
+const fn descriptor(base: u32, limit: u32, access: u8, flags: u8) -> u64 {
+    (limit as u64 & 0xFFFF)
+        | (base as u64 & 0xFF_FFFF) << 16
+        | (access as u64) << 40
+        | ((limit as u64 >> 16) & 0xF) << 48
+        | (flags as u64 & 0xF) << 52
+        | (base as u64 >> 24) << 56
+}
+
+fn tss_descriptor(tss: &TaskStateSegment) -> u64 {
+    let base = tss as *const TaskStateSegment as u32;
+    descriptor(base, core::mem::size_of::<TaskStateSegment>() as u32 - 1, 0x89, 0)
+}
+
+/// Replace the bootloader's GDT with flat kernel segments, user segments
+/// over the program window and the two TSSs, and reload every segment
+/// register
+pub fn init() {
+    unsafe {
+        TSS.esp0 = KERNEL_STACK.top();
+        stackguard::watch_stack("ring 0", KERNEL_STACK.bottom(), KERNEL_STACK.top());
+        stackguard::watch_stack("double fault", DOUBLE_FAULT_STACK.bottom(), DOUBLE_FAULT_STACK.top());
+
+        let cr3: usize;
+        asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack));
+        DOUBLE_FAULT_TSS.cr3 = cr3 as u32;
+        DOUBLE_FAULT_TSS.eip = double_fault_task as u32;
+        DOUBLE_FAULT_TSS.esp = DOUBLE_FAULT_STACK.top();
+        // Reserved bit 1 set, interrupts off
+        DOUBLE_FAULT_TSS.eflags = 0x2;
+        DOUBLE_FAULT_TSS.cs = KERNEL_CODE as u32;
+        DOUBLE_FAULT_TSS.ss = KERNEL_DATA as u32;
+        DOUBLE_FAULT_TSS.ds = KERNEL_DATA as u32;
+        DOUBLE_FAULT_TSS.es = KERNEL_DATA as u32;
+        DOUBLE_FAULT_TSS.fs = KERNEL_DATA as u32;
+        DOUBLE_FAULT_TSS.gs = KERNEL_DATA as u32;
+
+        GDT[0] = 0;
+        GDT[1] = descriptor(0, 0xFFFFF, 0x9A, 0xC);
+        GDT[2] = descriptor(0, 0xFFFFF, 0x92, 0xC);
+        // The limit counts 4 KB pages
+        let user_limit = usermode::USER_SIZE / 4096 - 1;
+        GDT[3] = descriptor(usermode::USER_BASE, user_limit, 0xFA, 0xC);
+        GDT[4] = descriptor(usermode::USER_BASE, user_limit, 0xF2, 0xC);
+        GDT[5] = tss_descriptor(&TSS);
+        GDT[6] = tss_descriptor(&DOUBLE_FAULT_TSS);
+
+        GDT_PTR.limit = (core::mem::size_of::<[u64; GDT_SIZE]>() - 1) as u16;
+        GDT_PTR.base = GDT.as_ptr() as u32;
+
+        asm!(
+            "lgdt [{ptr}]",
+            // Far return to reload CS
+            "push {code}",
+            "lea {tmp}, [3f]",
+            "push {tmp}",
+            "retf",
+            "3:",
+            "mov {tmp:x}, {data}",
+            "mov ds, {tmp:x}",
+            "mov es, {tmp:x}",
+            "mov fs, {tmp:x}",
+            "mov gs, {tmp:x}",
+            "mov ss, {tmp:x}",
+            ptr = in(reg) &GDT_PTR,
+            code = const KERNEL_CODE as u32,
+            data = const KERNEL_DATA,
+            tmp = out(reg) _,
+        );
+        asm!("ltr {0:x}", in(reg) TSS_SELECTOR, options(nostack));
+    }
+}
+
+/// Reload the task register after waking from S3. The trampoline already
+/// loaded the GDT, where the TSS descriptor is still marked busy.
+pub fn resume() {
+    unsafe {
+        GDT[5] = tss_descriptor(&TSS);
+        asm!("ltr {0:x}", in(reg) TSS_SELECTOR, options(nostack));
+    }
+}
+
+/// Limit and base of the kernel GDT, for processors that load it themselves
+pub fn pointer() -> (u16, u32) {
+    unsafe { (GDT_PTR.limit, GDT_PTR.base) }
+}
+
+/// CPU state at the time of the double fault, saved by the task switch
+pub fn faulting_state() -> TaskStateSegment {
+    unsafe { TSS }
+}
+
+/// Entered through the double fault task gate with the error code (always
+/// 0) on the fresh stack
+#[unsafe(naked)]
+#[no_mangle]
+pub unsafe extern "C" fn double_fault_task() {
+    naked_asm!(
+        "call double_fault_inner",
+    );
+}
+
+#[no_mangle]
+pub extern "C" fn double_fault_inner(_error_code: u32) -> ! {
+    crash::double_fault(&faulting_registers())
+}
+
+/// Registers of the task that double faulted, from the state the switch
+/// saved; the control registers are read now, as they haven't changed
+fn faulting_registers() -> crash::Registers {
+    let state = faulting_state();
+    let mut regs = crash::Registers::capture();
+    regs.eax = state.eax;
+    regs.ebx = state.ebx;
+    regs.ecx = state.ecx;
+    regs.edx = state.edx;
+    regs.esi = state.esi;
+    regs.edi = state.edi;
+    regs.ebp = state.ebp;
+    regs.esp = state.esp;
+    regs.eip = state.eip;
+    regs.eflags = state.eflags;
+    regs.cr3 = state.cr3;
+    regs
+}
@@ -0,0 +1,203 @@
+use crate::filesystem::{get_filesystem, FileEntry};
+
+// Wildcards in shell words: `*` matches any run of characters, `?` any one
+// and `[...]` one of a set, with ranges like `[a-z]` and `[!...]` for
+// anything but the set. Only the last part of a path may hold wildcards,
+// as in `logs/??.txt`. A word with double quotes in it is left alone, and
+// so is a pattern that matches nothing.
+
+/// Room for every file and directory in one directory
+const MAX_MATCHES: usize = 48;
+
+pub fn has_wildcards(word: &[u8]) -> bool {
+    word.iter().any(|&b| b == b'*' || b == b'?' || b == b'[')
+}
+
+/// Whether all of `name` matches `pattern`
+pub fn matches(pattern: &[u8], name: &[u8]) -> bool {
+    // Where to pick up after the last `*`: its pattern position and how
+    // much of the name it has swallowed so far
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, n));
+                p += 1;
+                continue;
+            }
+            Some(b'?') => {
+                p += 1;
+                n += 1;
+                continue;
+            }
+            Some(b'[') => {
+                if let Some((matched, len)) = match_set(&pattern[p..], name[n]) {
+                    if matched {
+                        p += len;
+                        n += 1;
+                        continue;
+                    }
+                } else if name[n] == b'[' {
+                    // No closing bracket: a plain `[`
+                    p += 1;
+                    n += 1;
+                    continue;
+                }
+            }
+            Some(&b) if b == name[n] => {
+                p += 1;
+                n += 1;
+                continue;
+            }
+            _ => {}
+        }
+        // Mismatch: let the last `*` take one more character
+        match star {
+            Some((star_p, star_n)) => {
+                star = Some((star_p, star_n + 1));
+                p = star_p + 1;
+                n = star_n + 1;
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+/// Match `byte` against the set at the start of `pattern`, giving whether
+/// it is in the set and the set's length, or None if the `[` isn't closed
+fn match_set(pattern: &[u8], byte: u8) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negate = matches!(pattern.get(i), Some(b'!') | Some(b'^'));
+    if negate {
+        i += 1;
+    }
+    let mut found = false;
+    let mut first = true;
+    loop {
+        let b = *pattern.get(i)?;
+        // A `]` straight after the `[` is part of the set
+        if b == b']' && !first {
+            return Some((found != negate, i + 1));
+        }
+        first = false;
+        if pattern.get(i + 1) == Some(&b'-') && pattern.get(i + 2).map_or(false, |&end| end != b']') {
+            found |= b <= byte && byte <= pattern[i + 2];
+            i += 3;
+        } else {
+            found |= b == byte;
+            i += 1;
+        }
+    }
+}
+
+/// Copy `line` into `out`, replacing each word with wildcards by the
+/// sorted paths it matches. Returns the expanded length.
+pub fn expand(line: &[u8], out: &mut [u8]) -> Result<usize, &'static str> {
+    let mut len = 0;
+    let mut rest = line;
+    while !rest.is_empty() {
+        let word_len = word_end(rest);
+        let (word, after) = rest.split_at(word_len);
+        let piece_len = if has_wildcards(word) && !word.iter().any(|&b| b == b'"' || b == b'|' || b == b'>') {
+            expand_word(word, &mut out[len..])?
+        } else {
+            0
+        };
+        if piece_len == 0 {
+            out.get_mut(len..len + word.len()).ok_or("Line too long after expansion")?.copy_from_slice(word);
+            len += word.len();
+        } else {
+            len += piece_len;
+        }
+        // Separators go through as they are
+        let gap = after.iter().take_while(|&&b| b == b' ').count();
+        out.get_mut(len..len + gap).ok_or("Line too long after expansion")?.copy_from_slice(&after[..gap]);
+        len += gap;
+        rest = &after[gap..];
+    }
+    Ok(len)
+}
+
+/// Length of the word at the start of `text`: up to a space outside
+/// double quotes
+fn word_end(text: &[u8]) -> usize {
+    let mut quoted = false;
+    text.iter().position(|&b| {
+        if b == b'"' {
+            quoted = !quoted;
+        }
+        b == b' ' && !quoted
+    }).unwrap_or(text.len())
+}
+
+/// Write the matches of one pattern, space-separated; 0 if none
+fn expand_word(word: &[u8], out: &mut [u8]) -> Result<usize, &'static str> {
+    let (dir, pattern) = match word.iter().rposition(|&b| b == b'/') {
+        Some(split) => (&word[..split + 1], &word[split + 1..]),
+        None => (&b""[..], word),
+    };
+    if has_wildcards(dir) {
+        return Ok(0);
+    }
+    let listing = match get_filesystem().list_directory(if dir.is_empty() { b"." } else { dir }) {
+        Some(listing) => listing,
+        None => return Ok(0),
+    };
+
+    let mut names: [&[u8]; MAX_MATCHES] = [&[]; MAX_MATCHES];
+    let mut count = 0;
+    for entry in listing {
+        let name = match entry {
+            FileEntry::Directory(name) => name,
+            FileEntry::File(name, _) => name,
+        };
+        // Hidden names only match a pattern that asks for the dot
+        if name.first() == Some(&b'.') && pattern.first() != Some(&b'.') {
+            continue;
+        }
+        if count < MAX_MATCHES && matches(pattern, name) {
+            names[count] = name;
+            count += 1;
+        }
+    }
+    names[..count].sort_unstable();
+
+    let mut len = 0;
+    for (i, name) in names[..count].iter().enumerate() {
+        let sep = if i == 0 { 0 } else { 1 };
+        let piece = out.get_mut(len..len + sep + dir.len() + name.len()).ok_or("Line too long after expansion")?;
+        if sep == 1 {
+            piece[0] = b' ';
+        }
+        piece[sep..sep + dir.len()].copy_from_slice(dir);
+        piece[sep + dir.len()..].copy_from_slice(name);
+        len += piece.len();
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn patterns_match_and_quotes_stop_expansion() {
+        assert!(matches(b"*.tmp", b"a.tmp"));
+        assert!(matches(b"*.tmp", b".tmp"));
+        assert!(!matches(b"*.tmp", b"a.tmp.bak"));
+        assert!(matches(b"??.txt", b"01.txt"));
+        assert!(!matches(b"??.txt", b"1.txt"));
+        assert!(matches(b"[a-c]*", b"beta"));
+        assert!(!matches(b"[!a-c]*", b"beta"));
+        assert!(matches(b"[]x]", b"]"));
+        assert!(matches(b"a[b", b"a[b"));
+        assert!(matches(b"*a*b*", b"xxaxxbxx"));
+        assert!(matches(b"*", b""));
+
+        let mut out = [0u8; 64];
+        let len = expand(b"echo \"*.tmp\"  nothing*here", &mut out).unwrap();
+        assert_eq!(&out[..len], &b"echo \"*.tmp\"  nothing*here"[..]);
+    }
+}
@@ -0,0 +1,172 @@
+use crate::syscall::{EBADF, EMFILE, ENFILE};
+
+// Kernel objects that user programs reach through handles. A handle names a
+// slot in the handle table and the slot's generation, so one kept after
+// `close` stops working instead of reaching whatever reuses the slot.
+// Several handles can share an object, which goes away with its last
+// handle. Each program starts with the console on handles 0, 1 and 2.
+
+const MAX_OBJECTS: usize = 16;
+const MAX_HANDLES: usize = 16;
+
+#[derive(Clone, Copy)]
+pub enum Object {
+    /// Keyboard input, read a line at a time
+    ConsoleIn,
+    ConsoleOut,
+    /// A file opened for reading; the ramfs never frees file data
+    File { data: &'static [u8], offset: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handle {
+    index: u16,
+    generation: u16,
+}
+
+impl Handle {
+    /// The handle a program passes as a file descriptor
+    pub fn from_raw(raw: u32) -> Handle {
+        Handle { index: raw as u16, generation: (raw >> 16) as u16 }
+    }
+
+    pub fn raw(self) -> u32 {
+        (self.generation as u32) << 16 | self.index as u32
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ObjectSlot {
+    object: Option<Object>,
+    refs: usize,
+}
+
+#[derive(Clone, Copy)]
+struct HandleSlot {
+    /// Index into the object slots
+    object: Option<usize>,
+    generation: u16,
+}
+
+pub struct HandleTable {
+    objects: [ObjectSlot; MAX_OBJECTS],
+    handles: [HandleSlot; MAX_HANDLES],
+}
+
+impl HandleTable {
+    pub const fn new() -> Self {
+        Self {
+            objects: [ObjectSlot { object: None, refs: 0 }; MAX_OBJECTS],
+            handles: [HandleSlot { object: None, generation: 0 }; MAX_HANDLES],
+        }
+    }
+
+    /// Add an object and return the first handle to it. Errors are negative
+    /// errno values, like system call results.
+    pub fn open(&mut self, object: Object) -> Result<Handle, i32> {
+        let id = self.objects.iter().position(|slot| slot.object.is_none()).ok_or(-ENFILE)?;
+        self.objects[id] = ObjectSlot { object: Some(object), refs: 0 };
+        self.attach(id).map_err(|e| {
+            self.objects[id].object = None;
+            e
+        })
+    }
+
+    /// Another handle to the same object
+    pub fn dup(&mut self, handle: Handle) -> Result<Handle, i32> {
+        let id = self.object_id(handle)?;
+        self.attach(id)
+    }
+
+    fn attach(&mut self, id: usize) -> Result<Handle, i32> {
+        let index = self.handles.iter().position(|slot| slot.object.is_none()).ok_or(-EMFILE)?;
+        let slot = &mut self.handles[index];
+        slot.object = Some(id);
+        self.objects[id].refs += 1;
+        Ok(Handle { index: index as u16, generation: slot.generation })
+    }
+
+    fn object_id(&self, handle: Handle) -> Result<usize, i32> {
+        match self.handles.get(handle.index as usize) {
+            Some(&HandleSlot { object: Some(id), generation }) if generation == handle.generation => Ok(id),
+            _ => Err(-EBADF),
+        }
+    }
+
+    pub fn get(&mut self, handle: Handle) -> Result<&mut Object, i32> {
+        let id = self.object_id(handle)?;
+        self.objects[id].object.as_mut().ok_or(-EBADF)
+    }
+
+    /// Drop a handle, and its object if no other handle shares it
+    pub fn close(&mut self, handle: Handle) -> Result<(), i32> {
+        let id = self.object_id(handle)?;
+        let slot = &mut self.handles[handle.index as usize];
+        slot.object = None;
+        slot.generation = slot.generation.wrapping_add(1);
+        let object = &mut self.objects[id];
+        object.refs -= 1;
+        if object.refs == 0 {
+            object.object = None;
+        }
+        Ok(())
+    }
+
+    /// Handles still open, e.g. at program exit
+    pub fn open_handles(&self) -> impl Iterator<Item = Handle> + '_ {
+        self.handles.iter().enumerate().filter(|(_, slot)| slot.object.is_some())
+            .map(|(index, slot)| Handle { index: index as u16, generation: slot.generation })
+    }
+}
+
+static mut TABLE: HandleTable = HandleTable::new();
+
+pub fn table() -> &'static mut HandleTable {
+    unsafe { &mut TABLE }
+}
+
+/// Start a program's table over with stdin, stdout and stderr, the last two
+/// sharing the console output object
+pub fn begin_process() -> Result<(), i32> {
+    let table = table();
+    *table = HandleTable::new();
+    table.open(Object::ConsoleIn)?;
+    let stdout = table.open(Object::ConsoleOut)?;
+    table.dup(stdout)?;
+    Ok(())
+}
+
+/// Close whatever the program left open
+pub fn end_process() {
+    let table = table();
+    loop {
+        let handle = match table.open_handles().next() {
+            Some(handle) => handle,
+            None => break,
+        };
+        let _ = table.close(handle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn stale_handles_fail_and_shared_objects_outlive_one_close() {
+        let mut table = HandleTable::new();
+        let first = table.open(Object::File { data: b"abc", offset: 0 }).unwrap();
+        let second = table.dup(first).unwrap();
+        assert_eq!((first.raw(), second.raw()), (0, 1));
+
+        table.close(first).unwrap();
+        assert!(matches!(table.get(second), Ok(Object::File { .. })));
+        assert_eq!(table.get(first).err(), Some(-EBADF));
+
+        let reused = table.open(Object::ConsoleOut).unwrap();
+        assert_eq!(reused.raw(), 1 << 16);
+        assert_eq!(table.close(first), Err(-EBADF));
+        assert_eq!(Handle::from_raw(reused.raw()), reused);
+        assert_eq!(table.open_handles().count(), 2);
+    }
+}
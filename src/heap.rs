@@ -0,0 +1,340 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+
+use crate::crash;
+use crate::idt;
+use crate::sync::Mutex;
+
+// The kernel heap behind `alloc`: a fixed region in .bss carved into blocks
+// by a first-fit allocator. Each block starts with an 8-byte header holding
+// its size, with the low bit set while it's free; free neighbours are
+// merged as allocation walks past them. Blocks handed out for alignments
+// over 8 are padded, and the word before the pointer says how far back the
+// block's payload starts.
+//
+// Every allocation is counted, and the live ones are tracked with their
+// size, age and the return addresses they were made from, for `heap dump`.
+// `heap mark` starts a new generation so allocations still live from after
+// it stand out as possible leaks. A warning is logged when free space drops
+// below an eighth of the heap.
+
+const HEAP_SIZE: usize = 512 * 1024;
+const HEADER: usize = 8;
+const MIN_BLOCK: usize = 16;
+const FREE: u32 = 1;
+/// Live allocations tracked for `heap dump`; more still count in the stats
+pub const MAX_TRACKED: usize = 128;
+/// Return addresses kept per allocation, innermost first
+pub const CALLERS: usize = 3;
+
+#[repr(align(4096))]
+struct Region([u8; HEAP_SIZE]);
+
+static mut REGION: Region = Region([0; HEAP_SIZE]);
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    /// Allocations made since boot, and frees
+    pub allocations: u64,
+    pub frees: u64,
+    pub failures: u64,
+    pub live_count: usize,
+    /// Bytes asked for by live allocations
+    pub live_bytes: usize,
+    pub peak_bytes: usize,
+    /// Bytes not in any block in use, headers and padding counted as used
+    pub free_bytes: usize,
+    pub size: usize,
+    /// Live allocations that didn't fit in the tracking table
+    pub untracked: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Record {
+    pub addr: usize,
+    pub size: usize,
+    /// Counts up with each allocation; compared against `heap mark`
+    pub generation: u32,
+    pub tick: u32,
+    pub callers: [u32; CALLERS],
+}
+
+const NO_RECORD: Option<Record> = None;
+
+struct Heap {
+    base: *mut u8,
+    stats: Stats,
+    records: [Option<Record>; MAX_TRACKED],
+    next_generation: u32,
+    mark: u32,
+    /// Set once the low free space warning is given, until space comes back
+    low: bool,
+}
+
+// The region is only reached through the heap's lock
+unsafe impl Send for Heap {}
+
+impl Heap {
+    const fn empty() -> Self {
+        Self {
+            base: ptr::null_mut(),
+            stats: Stats {
+                allocations: 0, frees: 0, failures: 0, live_count: 0, live_bytes: 0,
+                peak_bytes: 0, free_bytes: 0, size: 0, untracked: 0,
+            },
+            records: [NO_RECORD; MAX_TRACKED],
+            next_generation: 0,
+            mark: 0,
+            low: false,
+        }
+    }
+
+    /// Take over `size` bytes at `base`, 8-byte aligned, as one free block
+    unsafe fn init(&mut self, base: *mut u8, size: usize) {
+        let size = size & !(HEADER - 1);
+        self.base = base;
+        self.stats.size = size;
+        self.stats.free_bytes = size;
+        self.set_header(0, size, true);
+    }
+
+    unsafe fn header(&self, offset: usize) -> (usize, bool) {
+        let word = *(self.base.add(offset) as *const u32);
+        ((word & !FREE) as usize, word & FREE != 0)
+    }
+
+    unsafe fn set_header(&mut self, offset: usize, size: usize, free: bool) {
+        *(self.base.add(offset) as *mut u32) = size as u32 | if free { FREE } else { 0 };
+    }
+
+    /// Set up on first use, on the region in .bss
+    fn ensure_init(&mut self) {
+        if self.base.is_null() {
+            unsafe {
+                self.init(ptr::addr_of_mut!(REGION.0) as *mut u8, HEAP_SIZE);
+            }
+        }
+    }
+
+    /// Find room for `layout`; null if there is none
+    unsafe fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        self.ensure_init();
+        let padding = layout.align().saturating_sub(HEADER);
+        let needed = (HEADER + layout.size().max(1) + padding + HEADER - 1) & !(HEADER - 1);
+        let needed = needed.max(MIN_BLOCK);
+
+        let mut offset = 0;
+        while offset < self.stats.size {
+            let (mut size, free) = self.header(offset);
+            if free {
+                // Merge the free blocks after this one
+                while offset + size < self.stats.size {
+                    let (next_size, next_free) = self.header(offset + size);
+                    if !next_free {
+                        break;
+                    }
+                    size += next_size;
+                }
+                self.set_header(offset, size, true);
+                if size >= needed {
+                    return self.take(offset, size, needed, layout);
+                }
+            }
+            offset += size;
+        }
+        self.stats.failures += 1;
+        ptr::null_mut()
+    }
+
+    /// Use the free block at `offset` for `layout`, splitting off what
+    /// isn't `needed`
+    unsafe fn take(&mut self, offset: usize, size: usize, needed: usize, layout: Layout) -> *mut u8 {
+        let size = if size - needed >= MIN_BLOCK {
+            self.set_header(offset + needed, size - needed, true);
+            needed
+        } else {
+            size
+        };
+        self.set_header(offset, size, false);
+        self.stats.free_bytes -= size;
+
+        let payload = self.base.add(offset + HEADER);
+        let addr = (payload as usize + layout.align() - 1) & !(layout.align() - 1);
+        // How far back the payload starts; for unpadded blocks this is the
+        // header's second word
+        *((addr - 4) as *mut u32) = (addr - payload as usize) as u32;
+        addr as *mut u8
+    }
+
+    /// Give back what `allocate` returned
+    unsafe fn release(&mut self, addr: *mut u8) {
+        let back = *(addr.sub(4) as *const u32) as usize;
+        let offset = addr as usize - back - HEADER - self.base as usize;
+        let (size, _) = self.header(offset);
+        self.set_header(offset, size, true);
+        self.stats.free_bytes += size;
+    }
+
+    fn track(&mut self, addr: usize, size: usize, callers: [u32; CALLERS]) {
+        self.stats.allocations += 1;
+        self.stats.live_count += 1;
+        self.stats.live_bytes += size;
+        self.stats.peak_bytes = self.stats.peak_bytes.max(self.stats.live_bytes);
+        let generation = self.next_generation;
+        self.next_generation = self.next_generation.wrapping_add(1);
+        match self.records.iter_mut().find(|record| record.is_none()) {
+            Some(slot) => *slot = Some(Record { addr, size, generation, tick: idt::get_ticks(), callers }),
+            None => self.stats.untracked += 1,
+        }
+    }
+
+    fn untrack(&mut self, addr: usize, size: usize) {
+        self.stats.frees += 1;
+        self.stats.live_count -= 1;
+        self.stats.live_bytes -= size;
+        match self.records.iter_mut().find(|record| record.map_or(false, |record| record.addr == addr)) {
+            Some(slot) => *slot = None,
+            None => self.stats.untracked = self.stats.untracked.saturating_sub(1),
+        }
+    }
+
+    /// Note whether free space is below an eighth of the heap; true only
+    /// when it has just fallen there
+    fn check_low(&mut self) -> bool {
+        let low = self.stats.free_bytes < self.stats.size / 8;
+        let warn = low && !self.low;
+        self.low = low;
+        warn
+    }
+}
+
+static HEAP: Mutex<Heap> = Mutex::new(Heap::empty());
+
+pub struct KernelAllocator;
+
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let callers = callers();
+        let (addr, free, warn) = {
+            let mut heap = HEAP.lock();
+            let addr = heap.allocate(layout);
+            if !addr.is_null() {
+                heap.track(addr as usize, layout.size(), callers);
+            }
+            let warn = heap.check_low();
+            (addr, heap.stats.free_bytes, warn)
+        };
+        if warn {
+            log_warn!("Heap: only {} bytes free", free);
+        }
+        addr
+    }
+
+    unsafe fn dealloc(&self, addr: *mut u8, layout: Layout) {
+        let mut heap = HEAP.lock();
+        heap.release(addr);
+        heap.untrack(addr as usize, layout.size());
+        heap.check_low();
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: KernelAllocator = KernelAllocator;
+
+/// Return addresses of the allocation's callers, from the frame pointers
+fn callers() -> [u32; CALLERS] {
+    let mut callers = [0; CALLERS];
+    crash::stack_trace(crash::Registers::capture().ebp, &mut callers);
+    callers
+}
+
+pub fn stats() -> Stats {
+    let mut heap = HEAP.lock();
+    heap.ensure_init();
+    heap.stats
+}
+
+/// Start a new generation: allocations made from now on count as new
+pub fn mark() {
+    let mut heap = HEAP.lock();
+    heap.mark = heap.next_generation;
+}
+
+/// Whether `record` was made since the last `mark`
+pub fn is_new(record: &Record) -> bool {
+    let heap = HEAP.lock();
+    record.generation.wrapping_sub(heap.mark) < heap.next_generation.wrapping_sub(heap.mark)
+}
+
+/// The live allocations tracked, oldest first, into `out`; returns how many
+pub fn records(out: &mut [Record]) -> usize {
+    let heap = HEAP.lock();
+    let mut count = 0;
+    for record in heap.records.iter().flatten() {
+        if count == out.len() {
+            break;
+        }
+        out[count] = *record;
+        count += 1;
+    }
+    out[..count].sort_unstable_by_key(|record| record.generation);
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+
+    #[test_case]
+    fn blocks_are_split_merged_and_aligned() {
+        let mut memory = [0u64; 512];
+        let mut heap = Heap::empty();
+        unsafe {
+            heap.init(memory.as_mut_ptr() as *mut u8, 4096);
+            let a = heap.allocate(Layout::from_size_align(100, 8).unwrap());
+            let b = heap.allocate(Layout::from_size_align(200, 8).unwrap());
+            let c = heap.allocate(Layout::from_size_align(30, 64).unwrap());
+            assert!(!a.is_null() && !b.is_null() && !c.is_null());
+            assert_eq!(c as usize % 64, 0);
+            assert!(b as usize >= a as usize + 100);
+
+            // The freed middle block is reused, and freeing all gives the
+            // whole region back in one piece
+            heap.release(b);
+            assert_eq!(heap.allocate(Layout::from_size_align(150, 8).unwrap()), b);
+            heap.release(b);
+            heap.release(a);
+            heap.release(c);
+            assert_eq!(heap.stats.free_bytes, 4096);
+            assert!(!heap.allocate(Layout::from_size_align(4000, 8).unwrap()).is_null());
+            assert!(heap.allocate(Layout::from_size_align(4000, 8).unwrap()).is_null());
+        }
+    }
+
+    #[test_case]
+    fn allocations_are_counted_and_tracked() {
+        let before = stats();
+        mark();
+        let boxed = Box::new([7u8; 48]);
+        let mut list = Vec::new();
+        list.extend_from_slice(&[1u32, 2, 3]);
+        let during = stats();
+        assert_eq!(during.live_count, before.live_count + 2);
+        assert!(during.live_bytes >= before.live_bytes + 48 + 12);
+
+        let mut found = [Record::default(); MAX_TRACKED];
+        let count = records(&mut found);
+        let record = found[..count].iter().find(|record| record.addr == boxed.as_ptr() as usize).unwrap();
+        assert_eq!(record.size, 48);
+        assert!(is_new(record));
+
+        drop(boxed);
+        drop(list);
+        let after = stats();
+        assert_eq!(after.live_count, before.live_count);
+        assert_eq!(after.live_bytes, before.live_bytes);
+        assert_eq!(after.allocations, after.frees + after.live_count as u64);
+    }
+}
@@ -1,6 +1,17 @@
 use crate::vga_colors::Color;
-use crate::writer::Writer;
-use core::arch::asm;
+use crate::acpi;
+use crate::buildinfo;
+use crate::clock;
+use crate::fmt::{Duration, Size, Thousands};
+use crate::metrics;
+use crate::report::Report;
+use crate::sink::{Capture, Sink};
+use crate::smp;
+use crate::multiboot;
+use crate::framebuffer;
+use crate::filesystem::get_filesystem;
+use crate::theme;
+use crate::cpu::{cpuid, cpuid_count};
 
 pub struct HexFetch {}
 
@@ -8,6 +19,15 @@ struct CpuInfo {
     vendor: [u8; 12],
     brand: [u8; 48],
     has_brand: bool,
+    /// Cache sizes in KB, zero when unknown
+    l1d_kb: u32,
+    l1i_kb: u32,
+    l2_kb: u32,
+    l3_kb: u32,
+    cores: u32,
+    threads: u32,
+    /// Hypervisor vendor signature from leaf 0x40000000
+    hypervisor: Option<[u8; 12]>,
 }
 
 impl CpuInfo {
@@ -16,6 +36,13 @@ impl CpuInfo {
             vendor: [0; 12],
             brand: [0; 48],
             has_brand: false,
+            l1d_kb: 0,
+            l1i_kb: 0,
+            l2_kb: 0,
+            l3_kb: 0,
+            cores: 1,
+            threads: 1,
+            hypervisor: None,
         };
 
         let (max_func, ebx, ecx, edx) = cpuid(0);
@@ -36,9 +63,130 @@ impl CpuInfo {
             }
         }
 
+        if max_func >= 1 {
+            let (_, ebx, ecx, edx) = cpuid(1);
+            // HTT flag: EBX[23:16] holds the logical processor count
+            if edx & (1 << 28) != 0 {
+                info.threads = ((ebx >> 16) & 0xFF).max(1);
+            }
+            // ECX bit 31 is reserved for hypervisors to announce themselves
+            if ecx & (1 << 31) != 0 {
+                let (_, ebx, ecx, edx) = cpuid(0x40000000);
+                let mut signature = [0u8; 12];
+                signature[0..4].copy_from_slice(&ebx.to_le_bytes());
+                signature[4..8].copy_from_slice(&ecx.to_le_bytes());
+                signature[8..12].copy_from_slice(&edx.to_le_bytes());
+                info.hypervisor = Some(signature);
+            }
+        }
+
+        if &info.vendor == b"AuthenticAMD" {
+            info.detect_amd_cache(max_ext);
+        } else if max_func >= 4 {
+            info.detect_deterministic_cache();
+        } else if max_func >= 2 {
+            info.detect_descriptor_cache();
+        }
+
+        if info.threads < info.cores {
+            info.threads = info.cores;
+        }
+
         info
     }
 
+    /// Intel leaf 4: one sub-leaf per cache until the type field is zero
+    fn detect_deterministic_cache(&mut self) {
+        for subleaf in 0..16 {
+            let (eax, ebx, ecx, _) = cpuid_count(4, subleaf);
+            let cache_type = eax & 0x1F;
+            if cache_type == 0 {
+                break;
+            }
+            if subleaf == 0 {
+                self.cores = (eax >> 26) + 1;
+            }
+
+            let ways = (ebx >> 22) + 1;
+            let partitions = ((ebx >> 12) & 0x3FF) + 1;
+            let line_size = (ebx & 0xFFF) + 1;
+            let sets = ecx + 1;
+            let size_kb = ways * partitions * line_size * sets / 1024;
+
+            match ((eax >> 5) & 0x7, cache_type) {
+                (1, 1) => self.l1d_kb = size_kb,
+                (1, 2) => self.l1i_kb = size_kb,
+                (2, _) => self.l2_kb = size_kb,
+                (3, _) => self.l3_kb = size_kb,
+                _ => {}
+            }
+        }
+    }
+
+    /// Intel leaf 2: one-byte descriptors, only the common ones are decoded
+    fn detect_descriptor_cache(&mut self) {
+        let (eax, ebx, ecx, edx) = cpuid(2);
+        for reg in [eax & 0xFFFFFF00, ebx, ecx, edx].iter() {
+            // Bit 31 set means the register holds no valid descriptors
+            if reg & 0x8000_0000 != 0 {
+                continue;
+            }
+            for byte in reg.to_le_bytes().iter() {
+                match *byte {
+                    0x06 => self.l1i_kb = 8,
+                    0x08 => self.l1i_kb = 16,
+                    0x30 => self.l1i_kb = 32,
+                    0x0A | 0x66 => self.l1d_kb = 8,
+                    0x0C | 0x67 => self.l1d_kb = 16,
+                    0x2C | 0x68 => self.l1d_kb = 32,
+                    0x41 | 0x79 => self.l2_kb = 128,
+                    0x42 | 0x7A | 0x82 => self.l2_kb = 256,
+                    0x43 | 0x7B | 0x83 => self.l2_kb = 512,
+                    0x44 | 0x7C | 0x84 => self.l2_kb = 1024,
+                    0x45 | 0x7D | 0x85 => self.l2_kb = 2048,
+                    0x22 => self.l3_kb = 512,
+                    0x23 => self.l3_kb = 1024,
+                    0x25 => self.l3_kb = 2048,
+                    0x29 => self.l3_kb = 4096,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// AMD extended leaves 0x80000005/6, plus core count from 0x80000008
+    fn detect_amd_cache(&mut self, max_ext: u32) {
+        if max_ext >= 0x80000005 {
+            let (_, _, ecx, edx) = cpuid(0x80000005);
+            self.l1d_kb = ecx >> 24;
+            self.l1i_kb = edx >> 24;
+        }
+        if max_ext >= 0x80000006 {
+            let (_, _, ecx, edx) = cpuid(0x80000006);
+            self.l2_kb = ecx >> 16;
+            self.l3_kb = (edx >> 18) * 512;
+        }
+        if max_ext >= 0x80000008 {
+            let (_, _, ecx, _) = cpuid(0x80000008);
+            self.cores = (ecx & 0xFF) + 1;
+        }
+    }
+
+    fn hypervisor_str(&self) -> Option<&str> {
+        let signature = self.hypervisor?;
+        Some(match &signature {
+            b"KVMKVMKVM\0\0\0" => "QEMU/KVM",
+            b"TCGTCGTCGTCG" => "QEMU (TCG)",
+            b"VBoxVBoxVBox" => "VirtualBox",
+            b"VMwareVMware" => "VMware",
+            b"Microsoft Hv" => "Hyper-V",
+            b"XenVMMXenVMM" => "Xen",
+            b"bhyve bhyve " => "bhyve",
+            b" lrpepyh  vr" => "Parallels",
+            _ => "Unknown hypervisor",
+        })
+    }
+
     fn vendor_str(&self) -> &str {
         core::str::from_utf8(&self.vendor).unwrap_or("Unknown")
     }
@@ -53,113 +201,239 @@ impl CpuInfo {
     }
 }
 
-fn cpuid(function: u32) -> (u32, u32, u32, u32) {
-    let eax: u32;
-    let ebx: u32;
-    let ecx: u32;
-    let edx: u32;
+fn detect_memory_kb() -> u32 {
+    multiboot::total_memory_kb().unwrap_or(0)
+}
+
+/// One line of hexfetch output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Os,
+    Kernel,
+    Uptime,
+    Shell,
+    Cpu,
+    Cores,
+    Cpus,
+    Cache,
+    Memory,
+    Arch,
+    Host,
+    Resolution,
+    Files,
+    Interrupts,
+}
+
+const FIELDS: [Field; 14] = [
+    Field::Os,
+    Field::Kernel,
+    Field::Uptime,
+    Field::Shell,
+    Field::Cpu,
+    Field::Cores,
+    Field::Cpus,
+    Field::Cache,
+    Field::Memory,
+    Field::Arch,
+    Field::Host,
+    Field::Resolution,
+    Field::Files,
+    Field::Interrupts,
+];
 
-    unsafe {
-        asm!(
-            "cpuid",
-            inout("eax") function => eax,
-            out("ebx") ebx,
-            out("ecx") ecx,
-            out("edx") edx,
-            options(nostack, preserves_flags)
-        );
+impl Field {
+    pub fn from_name(name: &[u8]) -> Option<Field> {
+        FIELDS.iter().copied().find(|field| field.name().as_bytes() == name)
     }
 
-    (eax, ebx, ecx, edx)
+    /// Name used by `--show`
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Os => "os",
+            Field::Kernel => "kernel",
+            Field::Uptime => "uptime",
+            Field::Shell => "shell",
+            Field::Cpu => "cpu",
+            Field::Cores => "cores",
+            Field::Cpus => "cpus",
+            Field::Cache => "cache",
+            Field::Memory => "mem",
+            Field::Arch => "arch",
+            Field::Host => "host",
+            Field::Resolution => "resolution",
+            Field::Files => "files",
+            Field::Interrupts => "irqs",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Field::Os => "OS: ",
+            Field::Kernel => "Kernel: ",
+            Field::Uptime => "Uptime: ",
+            Field::Shell => "Shell: ",
+            Field::Cpu => "CPU: ",
+            Field::Cores => "Cores: ",
+            Field::Cpus => "CPUs: ",
+            Field::Cache => "Cache: ",
+            Field::Memory => "Memory: ",
+            Field::Arch => "Arch: ",
+            Field::Host => "Host: ",
+            Field::Resolution => "Resolution: ",
+            Field::Files => "Files: ",
+            Field::Interrupts => "Interrupts: ",
+        }
+    }
 }
 
-fn detect_memory_kb() -> u32 {
-    let base_mem: u16 = unsafe { *(0x413 as *const u16) };
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Logo {
+    Hyze,
+    Small,
+    Cube,
+    None,
+}
 
-    let extended_kb: u32 = 128 * 1024;
+const LOGOS: [Logo; 4] = [Logo::Hyze, Logo::Small, Logo::Cube, Logo::None];
 
-    (base_mem as u32) + extended_kb
+impl Logo {
+    pub fn from_name(name: &[u8]) -> Option<Logo> {
+        LOGOS.iter().copied().find(|logo| logo.name().as_bytes() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Logo::Hyze => "hyze",
+            Logo::Small => "small",
+            Logo::Cube => "cube",
+            Logo::None => "none",
+        }
+    }
+
+    /// Art lines, all padded to the same width
+    fn lines(self) -> &'static [&'static str] {
+        match self {
+            Logo::Hyze => &[
+                "    __  __          _            ",
+                "   / / / /__  _  __(_)_  ______ _",
+                "  / /_/ / _ \\| |/_/ / / / / __ `/",
+                " / __  /  __/>  </ / /_/ / /_/ / ",
+                "/_/ /_/\\___/_/|_/_/\\__,_/\\__,_/  ",
+            ],
+            Logo::Small => &[
+                " _  _    ",
+                "| || |   ",
+                "| __ |   ",
+                "|_||_|   ",
+            ],
+            Logo::Cube => &[
+                "   +--------+   ",
+                "  /        /|   ",
+                " +--------+ |   ",
+                " |  HYZE  | +   ",
+                " |   OS   |/    ",
+                " +--------+     ",
+            ],
+            Logo::None => &[],
+        }
+    }
+
+    fn width(self) -> usize {
+        self.lines().first().map_or(0, |line| line.chars().count())
+    }
 }
 
-fn get_uptime_seconds() -> u32 {
-    let ticks: u32 = unsafe { *(0x46C as *const u32) };
-    ticks / 18
+/// Which lines hexfetch shows, in order, and next to which logo
+pub struct Layout {
+    fields: [Field; FIELDS.len()],
+    count: usize,
+    pub logo: Logo,
+}
+
+impl Layout {
+    pub fn fields(&self) -> &[Field] {
+        &self.fields[..self.count]
+    }
+
+    /// Read `--show a,b,c` and `--logo name`; anything not given keeps the default
+    pub fn parse(mut args: &[u8]) -> Result<Layout, &'static str> {
+        let mut layout = Layout::default();
+        loop {
+            let (option, rest) = next_word(args);
+            if option.is_empty() {
+                return Ok(layout);
+            }
+            let (value, rest) = next_word(rest);
+            args = rest;
+            match option {
+                b"--show" => {
+                    layout.count = 0;
+                    for name in value.split(|&c| c == b',').filter(|name| !name.is_empty()) {
+                        let field = Field::from_name(name).ok_or("Unknown field")?;
+                        if layout.count == layout.fields.len() || layout.fields().contains(&field) {
+                            return Err("Field listed twice");
+                        }
+                        layout.fields[layout.count] = field;
+                        layout.count += 1;
+                    }
+                    if layout.count == 0 {
+                        return Err("--show needs a list of fields");
+                    }
+                }
+                b"--logo" => layout.logo = Logo::from_name(value).ok_or("Unknown logo")?,
+                _ => return Err("Usage: hexfetch [--show field,...] [--logo name]"),
+            }
+        }
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout { fields: FIELDS, count: FIELDS.len(), logo: Logo::Hyze }
+    }
+}
+
+fn next_word(args: &[u8]) -> (&[u8], &[u8]) {
+    let args = &args[args.iter().position(|&c| c != b' ').unwrap_or(args.len())..];
+    let end = args.iter().position(|&c| c == b' ').unwrap_or(args.len());
+    (&args[..end], &args[end..])
 }
 
 impl HexFetch {
-    pub fn fetch(writer: &mut Writer) {
+    pub fn fetch(layout: &Layout, out: &mut dyn Sink) {
         let cpu = CpuInfo::detect();
-        let memory_kb = detect_memory_kb();
-        let memory_mb = memory_kb / 1024;
-        let uptime = get_uptime_seconds();
-
-        let hours = uptime / 3600;
-        let minutes = (uptime % 3600) / 60;
-        let seconds = uptime % 60;
-
-        // Smaller ASCII art (30 chars wide) + info on right
-        // Line 1
-        writer.set_color(Color::LightCyan, Color::Black);
-        writer.write_str("    __  __          _            ");
-        writer.set_color(Color::Yellow, Color::Black);
-        writer.write_str("OS: ");
-        writer.set_color(Color::White, Color::Black);
-        writer.write_str("HyzeOS\n");
-
-        // Line 2
-        writer.set_color(Color::LightCyan, Color::Black);
-        writer.write_str("   / / / /__  _  __(_)_  ______ _");
-        writer.set_color(Color::Yellow, Color::Black);
-        writer.write_str("Kernel: ");
-        writer.set_color(Color::White, Color::Black);
-        writer.write_str("0.1.0\n");
-
-        // Line 3
-        writer.set_color(Color::LightCyan, Color::Black);
-        writer.write_str("  / /_/ / _ \\| |/_/ / / / / __ `/");
-        writer.set_color(Color::Yellow, Color::Black);
-        writer.write_str("Uptime: ");
-        writer.set_color(Color::White, Color::Black);
-        write_uptime(writer, hours, minutes, seconds);
-        writer.write_str("\n");
-
-        // Line 4
-        writer.set_color(Color::LightCyan, Color::Black);
-        writer.write_str(" / __  /  __/>  </ / /_/ / /_/ / ");
-        writer.set_color(Color::Yellow, Color::Black);
-        writer.write_str("Shell: ");
-        writer.set_color(Color::White, Color::Black);
-        writer.write_str("HexShell\n");
-
-        // Line 5
-        writer.set_color(Color::LightCyan, Color::Black);
-        writer.write_str("/_/ /_/\\___/_/|_/_/\\__,_/\\__,_/  ");
-        writer.set_color(Color::Yellow, Color::Black);
-        writer.write_str("CPU: ");
-        writer.set_color(Color::White, Color::Black);
-        // Truncate CPU name to fit
-        write_truncated(writer, cpu.brand_str(), 25);
-        writer.write_str("\n");
-
-        // Line 6 - Memory
-        writer.set_color(Color::LightCyan, Color::Black);
-        writer.write_str("                                 ");
-        writer.set_color(Color::Yellow, Color::Black);
-        writer.write_str("Memory: ");
-        writer.set_color(Color::White, Color::Black);
-        write_number(writer, memory_mb);
-        writer.write_str(" MB\n");
-
-        // Line 7 - Arch
-        writer.set_color(Color::LightCyan, Color::Black);
-        writer.write_str("                                 ");
-        writer.set_color(Color::Yellow, Color::Black);
-        writer.write_str("Arch: ");
-        writer.set_color(Color::White, Color::Black);
-        writer.write_str("i386\n");
+        let palette = theme::palette();
+        let (logo, label, value, background) = (palette.logo, palette.label, palette.value, palette.background);
+
+        // Fields with nothing to say are dropped so the logo stays compact
+        let mut shown = [Field::Os; FIELDS.len()];
+        let mut count = 0;
+        for &field in layout.fields() {
+            if has_value(field, &cpu) {
+                shown[count] = field;
+                count += 1;
+            }
+        }
+
+        let art = layout.logo.lines();
+        let width = layout.logo.width();
+        for line in 0..art.len().max(count) {
+            out.set_color(logo, background);
+            match art.get(line) {
+                Some(text) => out.write_str(text),
+                None => out.pad_to(width),
+            }
+            if let Some(&field) = shown[..count].get(line) {
+                out.set_color(label, background);
+                out.write_str(field.label());
+                out.set_color(value, background);
+                write_value(out, field, &cpu);
+            }
+            out.write_byte(b'\n');
+        }
 
         // Color palette display
-        writer.write_str("\n    ");
+        out.write_str("\n    ");
         for i in 0..8 {
             let color = match i {
                 0 => Color::Black,
@@ -172,11 +446,11 @@ impl HexFetch {
                 7 => Color::LightGray,
                 _ => Color::Black,
             };
-            writer.set_color(color, color);
-            writer.write_str("  ");
+            out.set_color(color, color);
+            out.write_str("  ");
         }
-        writer.set_color(Color::White, Color::Black);
-        writer.write_str("\n    ");
+        out.set_color(Color::White, Color::Black);
+        out.write_str("\n    ");
         for i in 0..8 {
             let color = match i {
                 0 => Color::DarkGray,
@@ -189,54 +463,113 @@ impl HexFetch {
                 7 => Color::White,
                 _ => Color::Black,
             };
-            writer.set_color(color, color);
-            writer.write_str("  ");
+            out.set_color(color, color);
+            out.write_str("  ");
         }
-        writer.set_color(Color::White, Color::Black);
-        writer.write_str("\n");
+        out.set_color(palette.text, background);
+        out.write_byte(b'\n');
     }
 }
 
-/// Write a string truncated to max_len characters
-fn write_truncated(writer: &mut Writer, s: &str, max_len: usize) {
-    let bytes = s.as_bytes();
-    if bytes.len() <= max_len {
-        writer.write_str(s);
-    } else {
-        for i in 0..max_len {
-            writer.write_byte(bytes[i]);
-        }
+/// The same fields as a report section, named as `--show` names them
+pub fn report(report: &mut dyn Report) {
+    let cpu = CpuInfo::detect();
+    report.section("hexfetch");
+    for &field in FIELDS.iter().filter(|&&field| has_value(field, &cpu)) {
+        let mut buffer = [0u8; 64];
+        let mut value = Capture::new(&mut buffer);
+        write_value(&mut value, field, &cpu);
+        report.value(field.name(), format_args!("{}", core::str::from_utf8(value.as_bytes()).unwrap_or("?")));
     }
 }
 
-fn write_number(writer: &mut Writer, mut n: u32) {
-    if n == 0 {
-        writer.write_str("0");
-        return;
+fn has_value(field: Field, cpu: &CpuInfo) -> bool {
+    match field {
+        Field::Cache => cpu.l1d_kb + cpu.l1i_kb + cpu.l2_kb + cpu.l3_kb > 0,
+        Field::Host => cpu.hypervisor.is_some(),
+        _ => true,
     }
+}
 
-    let mut buf = [0u8; 10];
-    let mut i = 0;
+fn write_value(out: &mut dyn Sink, field: Field, cpu: &CpuInfo) {
+    match field {
+        Field::Os => out.write_str("HyzeOS"),
+        Field::Kernel => write!(out, "{} ({})", buildinfo::VERSION, buildinfo::GIT_HASH),
+        Field::Uptime => write!(out, "{}", Duration(clock::uptime_seconds() as u64)),
+        Field::Shell => out.write_str("HexShell"),
+        Field::Cpu => {
+            // Truncate CPU name to fit
+            let brand = cpu.brand_str();
+            out.write_str(brand.get(..25).unwrap_or(brand));
+            let cpu_count = acpi::get_info().cpu_count();
+            if cpu_count > 0 {
+                write!(out, " ({})", cpu_count);
+            }
+        }
+        Field::Cores => {
+            write!(out, "{}", cpu.cores);
+            if cpu.threads > cpu.cores {
+                write!(out, " ({} threads)", cpu.threads);
+            }
+        }
+        Field::Cpus => write!(out, "{} online", smp::online_count()),
+        Field::Cache => write_cache_line(out, cpu),
+        Field::Memory => write!(out, "{}", Size(detect_memory_kb() as u64 * 1024)),
+        Field::Arch => out.write_str("i386"),
+        Field::Host => out.write_str(cpu.hypervisor_str().unwrap_or("")),
+        Field::Resolution => match framebuffer::active() {
+            Some(fb) => write!(out, "{}x{} framebuffer", fb.width(), fb.height()),
+            None => out.write_str("80x25 text"),
+        },
+        Field::Files => {
+            let usage = get_filesystem().usage();
+            write!(out, "{} in {} directories", usage.files, usage.directories);
+        }
+        Field::Interrupts => write!(out, "{} handled", Thousands(metrics::total_interrupts())),
+    }
+}
 
-    while n > 0 {
-        buf[i] = b'0' + (n % 10) as u8;
-        n /= 10;
-        i += 1;
+fn write_cache_line(out: &mut dyn Sink, cpu: &CpuInfo) {
+    let mut first = true;
+    if cpu.l1d_kb + cpu.l1i_kb > 0 {
+        out.write_str("L1 ");
+        write_cache_size(out, cpu.l1d_kb);
+        out.write_str("+");
+        write_cache_size(out, cpu.l1i_kb);
+        first = false;
+    }
+    for &(name, size) in [("L2 ", cpu.l2_kb), ("L3 ", cpu.l3_kb)].iter() {
+        if size == 0 {
+            continue;
+        }
+        if !first {
+            out.write_str(", ");
+        }
+        out.write_str(name);
+        write_cache_size(out, size);
+        first = false;
     }
+}
 
-    while i > 0 {
-        i -= 1;
-        writer.write_byte(buf[i]);
+fn write_cache_size(out: &mut dyn Sink, kb: u32) {
+    if kb >= 1024 && kb % 1024 == 0 {
+        write!(out, "{}M", kb / 1024);
+    } else {
+        write!(out, "{}K", kb);
     }
 }
 
-fn write_uptime(writer: &mut Writer, hours: u32, minutes: u32, seconds: u32) {
-    if hours > 0 {
-        write_number(writer, hours);
-        writer.write_str("h ");
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn layout_keeps_requested_order() {
+        let layout = Layout::parse(b" --show cpu,mem,os --logo small").unwrap();
+        assert_eq!(layout.fields(), &[Field::Cpu, Field::Memory, Field::Os]);
+        assert_eq!(layout.logo, Logo::Small);
+        assert_eq!(Layout::parse(b"").unwrap().fields().len(), FIELDS.len());
+        assert!(Layout::parse(b" --show cpu,gpu").is_err());
+        assert!(Layout::parse(b" --show cpu,cpu").is_err());
     }
-    write_number(writer, minutes);
-    writer.write_str("m ");
-    write_number(writer, seconds);
-    writer.write_str("s");
 }
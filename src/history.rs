@@ -0,0 +1,167 @@
+use crate::env;
+use crate::filesystem::{get_filesystem, MAX_FILE_SIZE};
+
+// Shell command history, numbered from 1 like `!n` expects. It is saved to
+// `HISTORY_FILE` after every command and loaded at startup, so an initrd can
+// bring one along. `HISTSIZE` sets how many commands are kept.
+
+pub const HISTORY_FILE: &[u8] = b"/home/.hexsh_history";
+const HISTORY_DIR: &[u8] = b"home";
+/// Most commands kept, whatever `HISTSIZE` says
+const MAX_ENTRIES: usize = 64;
+const DEFAULT_SIZE: usize = 32;
+/// Same as the shell's longest command, continued lines and all
+const MAX_ENTRY_LEN: usize = 256;
+
+pub struct History {
+    entries: [[u8; MAX_ENTRY_LEN]; MAX_ENTRIES],
+    lens: [usize; MAX_ENTRIES],
+    /// Slot of the oldest entry
+    start: usize,
+    count: usize,
+    /// Number of the oldest entry; numbers keep counting as old ones drop
+    first: usize,
+}
+
+impl History {
+    pub const fn new() -> Self {
+        Self { entries: [[0; MAX_ENTRY_LEN]; MAX_ENTRIES], lens: [0; MAX_ENTRIES], start: 0, count: 0, first: 1 }
+    }
+
+    /// Add a command, dropping the oldest ones beyond `limit`
+    pub fn push(&mut self, line: &[u8], limit: usize) {
+        let line = &line[..line.len().min(MAX_ENTRY_LEN)];
+        if self.count == MAX_ENTRIES {
+            self.drop_oldest();
+        }
+        let slot = (self.start + self.count) % MAX_ENTRIES;
+        self.entries[slot][..line.len()].copy_from_slice(line);
+        self.lens[slot] = line.len();
+        self.count += 1;
+        while self.count > limit.min(MAX_ENTRIES) {
+            self.drop_oldest();
+        }
+    }
+
+    fn drop_oldest(&mut self) {
+        self.start = (self.start + 1) % MAX_ENTRIES;
+        self.count -= 1;
+        self.first += 1;
+    }
+
+    pub fn get(&self, number: usize) -> Option<&[u8]> {
+        let index = number.checked_sub(self.first).filter(|&i| i < self.count)?;
+        let slot = (self.start + index) % MAX_ENTRIES;
+        Some(&self.entries[slot][..self.lens[slot]])
+    }
+
+    /// Number and text of each command, oldest first
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &[u8])> {
+        (self.first..self.first + self.count).filter_map(move |number| self.get(number).map(|line| (number, line)))
+    }
+
+    /// The newest command numbered below `before` that contains `pattern`,
+    /// for Ctrl+R
+    pub fn search(&self, pattern: &[u8], before: usize) -> Option<(usize, &[u8])> {
+        if pattern.is_empty() {
+            return None;
+        }
+        (self.first..(self.first + self.count).min(before))
+            .rev()
+            .filter_map(|number| self.get(number).map(|line| (number, line)))
+            .find(|&(_, line)| line.windows(pattern.len()).any(|window| window == pattern))
+    }
+
+    /// The command `!spec` refers to: `!!` the last one, `!n` number n
+    pub fn recall(&self, spec: &[u8]) -> Result<&[u8], &'static str> {
+        let number = match spec {
+            b"!" => (self.first + self.count).checked_sub(1),
+            _ => core::str::from_utf8(spec).ok().and_then(|s| s.parse().ok()),
+        };
+        number.and_then(|number| self.get(number)).ok_or("No such command in history")
+    }
+}
+
+static mut HISTORY: History = History::new();
+static mut FILE_BUFFER: [u8; MAX_FILE_SIZE] = [0; MAX_FILE_SIZE];
+
+pub fn get() -> &'static mut History {
+    unsafe { &mut HISTORY }
+}
+
+/// Commands to keep, from `HISTSIZE`
+pub fn limit() -> usize {
+    env::get()
+        .get(b"HISTSIZE")
+        .and_then(|value| core::str::from_utf8(value).ok()?.parse().ok())
+        .unwrap_or(DEFAULT_SIZE)
+}
+
+/// Read the saved history, if there is one. Returns the commands kept.
+pub fn load() -> usize {
+    let history = get();
+    if let Some(text) = get_filesystem().read_path(HISTORY_FILE) {
+        for line in text.split(|&b| b == b'\n').filter(|line| !line.is_empty()) {
+            history.push(line, limit());
+        }
+    }
+    history.count
+}
+
+/// Write the history out, one command per line, making `/home` if needed.
+/// The oldest commands are left out if they don't all fit in a file.
+pub fn save() -> Result<(), &'static str> {
+    let history = get();
+    let mut from = history.first + history.count;
+    let mut size = 0;
+    while let Some(line) = history.get(from - 1) {
+        if size + line.len() + 1 > MAX_FILE_SIZE {
+            break;
+        }
+        size += line.len() + 1;
+        from -= 1;
+    }
+
+    let buffer = unsafe { &mut FILE_BUFFER };
+    let mut len = 0;
+    for (_, line) in history.iter().filter(|&(number, _)| number >= from) {
+        buffer[len..len + line.len()].copy_from_slice(line);
+        buffer[len + line.len()] = b'\n';
+        len += line.len() + 1;
+    }
+
+    let fs = get_filesystem();
+    let saved = fs.current_dir();
+    fs.set_current_dir(0);
+    let made = match fs.change_directory(HISTORY_DIR) {
+        Ok(()) => Ok(()),
+        Err(_) => fs.create_directory(HISTORY_DIR),
+    };
+    fs.set_current_dir(saved);
+    made?;
+    fs.write_path(HISTORY_FILE, &buffer[..len])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn numbers_survive_dropping_old_commands() {
+        let mut history = History::new();
+        for line in [&b"ls"[..], b"pwd", b"cd /", b"cat notes"].iter() {
+            history.push(line, 3);
+        }
+        assert_eq!(history.get(1), None);
+        assert_eq!(history.get(2), Some(&b"pwd"[..]));
+        assert_eq!(history.recall(b"!"), Ok(&b"cat notes"[..]));
+        assert_eq!(history.recall(b"3"), Ok(&b"cd /"[..]));
+        assert!(history.recall(b"9").is_err());
+        assert_eq!(history.iter().map(|(number, _)| number).sum::<usize>(), 2 + 3 + 4);
+
+        assert_eq!(history.search(b"c", usize::MAX), Some((4, &b"cat notes"[..])));
+        assert_eq!(history.search(b"c", 4), Some((3, &b"cd /"[..])));
+        assert_eq!(history.search(b"c", 3), None);
+        assert_eq!(history.search(b"", usize::MAX), None);
+    }
+}
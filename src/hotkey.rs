@@ -0,0 +1,176 @@
+use core::arch::asm;
+
+use crate::idt;
+use crate::keyboard;
+use crate::power;
+use crate::vga_colors::{Color, color_code};
+use crate::writer::Writer;
+
+pub const CTRL: u8 = 1 << 0;
+pub const ALT: u8 = 1 << 1;
+pub const SHIFT: u8 = 1 << 2;
+
+/// Keys sent after an 0xE0 prefix carry this bit
+pub const EXTENDED: u16 = 0xE000;
+
+pub const KEY_F1: u16 = 0x3B;
+pub const KEY_KEYPAD_DELETE: u16 = 0x53;
+pub const KEY_DELETE: u16 = EXTENDED | 0x53;
+pub const KEY_PRINT_SCREEN: u16 = EXTENDED | 0x37;
+
+const MAX_HOTKEYS: usize = 16;
+
+#[derive(Clone, Copy)]
+struct Hotkey {
+    modifiers: u8,
+    key: u16,
+    action: fn(),
+}
+
+static mut HOTKEYS: [Option<Hotkey>; MAX_HOTKEYS] = [None; MAX_HOTKEYS];
+/// Modifier keys currently held, tracked from the keyboard interrupt
+static mut MODIFIERS: u8 = 0;
+static mut EXTENDED_PREFIX: bool = false;
+/// One bit per hotkey slot whose action has yet to run
+static mut PENDING: u32 = 0;
+static mut DISPATCHING: bool = false;
+
+pub fn init() {
+    let _ = register(CTRL | ALT, KEY_DELETE, reboot_prompt);
+    let _ = register(CTRL | ALT, KEY_KEYPAD_DELETE, reboot_prompt);
+}
+
+/// Run `action` whenever `key` is pressed with exactly `modifiers` held.
+/// Actions run outside interrupt context, the next time anything polls the
+/// keyboard buffer, and the key itself is not delivered.
+pub fn register(modifiers: u8, key: u16, action: fn()) -> Result<usize, &'static str> {
+    unsafe {
+        asm!("cli", options(nostack));
+        let taken = HOTKEYS.iter().flatten().any(|h| h.modifiers == modifiers && h.key == key);
+        let slot = HOTKEYS.iter().position(|h| h.is_none());
+        if let (false, Some(id)) = (taken, slot) {
+            HOTKEYS[id] = Some(Hotkey { modifiers, key, action });
+        }
+        asm!("sti", options(nostack));
+        if taken {
+            return Err("Hotkey is already registered");
+        }
+        slot.ok_or("No free hotkey slots")
+    }
+}
+
+pub fn unregister(id: usize) {
+    unsafe {
+        asm!("cli", options(nostack));
+        if id < MAX_HOTKEYS {
+            HOTKEYS[id] = None;
+            PENDING &= !(1 << id);
+        }
+        asm!("sti", options(nostack));
+    }
+}
+
+/// Feed one scancode from the keyboard interrupt. Returns true when it
+/// completed a hotkey and should be dropped.
+pub fn filter(scancode: u8) -> bool {
+    unsafe {
+        if scancode == 0xE0 {
+            EXTENDED_PREFIX = true;
+            return false;
+        }
+        let key = if EXTENDED_PREFIX { EXTENDED } else { 0 } | (scancode & 0x7F) as u16;
+        let released = scancode & 0x80 != 0;
+        EXTENDED_PREFIX = false;
+
+        // Extended 0x2A/0x36 are fake shifts inside PrtSc and similar sequences
+        let modifier = match key {
+            0x1D | 0xE01D => CTRL,
+            0x38 | 0xE038 => ALT,
+            0x2A | 0x36 => SHIFT,
+            _ => 0,
+        };
+        if modifier != 0 {
+            if released {
+                MODIFIERS &= !modifier;
+            } else {
+                MODIFIERS |= modifier;
+            }
+            return false;
+        }
+        if released {
+            return false;
+        }
+
+        for (id, hotkey) in HOTKEYS.iter().enumerate() {
+            if let Some(hotkey) = hotkey {
+                if hotkey.key == key && hotkey.modifiers == MODIFIERS {
+                    PENDING |= 1 << id;
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+/// Modifier keys held right now, as `CTRL`, `ALT` and `SHIFT` bits
+pub fn modifiers() -> u8 {
+    unsafe { MODIFIERS }
+}
+
+/// Run the actions of hotkeys pressed since the last call. Actions may poll
+/// the keyboard themselves; they won't be re-entered.
+pub fn run_pending() {
+    unsafe {
+        if DISPATCHING || PENDING == 0 {
+            return;
+        }
+        DISPATCHING = true;
+        asm!("cli", options(nostack));
+        let pending = core::mem::replace(&mut PENDING, 0);
+        asm!("sti", options(nostack));
+
+        for id in 0..MAX_HOTKEYS {
+            if pending & (1 << id) != 0 {
+                if let Some(hotkey) = HOTKEYS[id] {
+                    (hotkey.action)();
+                }
+            }
+        }
+        DISPATCHING = false;
+    }
+}
+
+/// Ctrl+Alt+Del: ask before rebooting, drawn over whatever is on screen
+fn reboot_prompt() {
+    const TEXT: &[u8] = b" Reboot the machine? (Y/N) ";
+    const ROW: usize = 12;
+    let col = (80 - TEXT.len()) / 2;
+    let attribute = color_code(Color::White, Color::Red) as u16;
+
+    let mut saved = [0u16; TEXT.len()];
+    for (i, &ch) in TEXT.iter().enumerate() {
+        saved[i] = Writer::cell(col + i, ROW);
+        Writer::set_cell(col + i, ROW, ch as u16 | attribute << 8);
+    }
+
+    let confirmed = loop {
+        match idt::get_scancode() {
+            Some(0x01) => break false,
+            Some(scancode) if scancode & 0x80 == 0 => match keyboard::answer(scancode) {
+                Some(b'y') | Some(b'Y') => break true,
+                Some(b'n') | Some(b'N') => break false,
+                _ => {}
+            },
+            Some(_) => {}
+            None => idt::wait_for_interrupt(),
+        }
+    };
+    if confirmed {
+        power::reboot();
+    }
+
+    for (i, &cell) in saved.iter().enumerate() {
+        Writer::set_cell(col + i, ROW, cell);
+    }
+}
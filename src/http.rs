@@ -0,0 +1,171 @@
+use crate::clock;
+use crate::idt;
+use crate::net::Ipv4Addr;
+use crate::tcp::TcpStream;
+
+// HTTP/1.0 over `tcp`. `get` fetches a URL whole into the caller's buffer;
+// the request asks the server to close the connection afterwards, so the
+// end of the connection is the end of the body. There is no DNS yet, so
+// URLs name their host by address.
+
+const DEFAULT_PORT: u16 = 80;
+/// Give up on a server that sends nothing for this long
+const IDLE_TIMEOUT_MS: u64 = 10_000;
+const MAX_REQUEST_LEN: usize = 256;
+
+pub struct Url<'a> {
+    /// `host[:port]` as written, for the Host header
+    authority: &'a [u8],
+    pub address: Ipv4Addr,
+    pub port: u16,
+    pub path: &'a [u8],
+}
+
+impl<'a> Url<'a> {
+    /// `http://host[:port][/path]`, the scheme optional
+    pub fn parse(text: &'a [u8]) -> Result<Url<'a>, &'static str> {
+        let rest = if text.starts_with(b"http://") {
+            &text[7..]
+        } else if text.starts_with(b"https://") {
+            return Err("HTTPS isn't supported");
+        } else {
+            text
+        };
+        let split = rest.iter().position(|&b| b == b'/').unwrap_or(rest.len());
+        let (authority, path) = rest.split_at(split);
+        let (host, port) = match authority.iter().position(|&b| b == b':') {
+            Some(colon) => {
+                let port = core::str::from_utf8(&authority[colon + 1..]).ok()
+                    .and_then(|port| port.parse::<u16>().ok())
+                    .filter(|&port| port != 0)
+                    .ok_or("Invalid port")?;
+                (&authority[..colon], port)
+            }
+            None => (authority, DEFAULT_PORT),
+        };
+        if host.is_empty() {
+            return Err("No host in URL");
+        }
+        let address = Ipv4Addr::parse(host).ok_or("Host names need DNS, which isn't supported yet; use an address")?;
+        Ok(Url { authority, address, port, path: if path.is_empty() { b"/" } else { path } })
+    }
+
+    /// The last part of the path, or `index.html` for a directory
+    pub fn file_name(&self) -> &'a [u8] {
+        let path = &self.path[..self.path.iter().position(|&b| b == b'?').unwrap_or(self.path.len())];
+        match path.rsplit(|&b| b == b'/').next() {
+            Some(name) if !name.is_empty() => name,
+            _ => b"index.html",
+        }
+    }
+}
+
+pub struct Response<'a> {
+    pub status: u16,
+    pub reason: &'a [u8],
+    pub body: &'a [u8],
+}
+
+/// Fetch `url` into `buf` and split the response up. `cancel` is asked
+/// while waiting and stops the transfer when it says so.
+pub fn get<'b>(url: &Url, buf: &'b mut [u8], cancel: &mut dyn FnMut() -> bool) -> Result<Response<'b>, &'static str> {
+    let mut request = [0u8; MAX_REQUEST_LEN];
+    let mut len = 0;
+    for piece in [&b"GET "[..], url.path, b" HTTP/1.0\r\nHost: ", url.authority, b"\r\nUser-Agent: HyzeOS\r\nConnection: close\r\n\r\n"].iter() {
+        request.get_mut(len..len + piece.len()).ok_or("URL too long")?.copy_from_slice(piece);
+        len += piece.len();
+    }
+
+    let stream = TcpStream::connect(url.address, url.port)?;
+    stream.write_all(&request[..len])?;
+
+    let mut received = 0;
+    let mut last_data = clock::uptime_ms();
+    loop {
+        if received == buf.len() {
+            return Err("Response too large");
+        }
+        match stream.read(&mut buf[received..])? {
+            Some(0) => break,
+            Some(n) => {
+                received += n;
+                last_data = clock::uptime_ms();
+            }
+            None => {
+                if cancel() {
+                    return Err("Cancelled");
+                }
+                if clock::uptime_ms() - last_data > IDLE_TIMEOUT_MS {
+                    return Err("Server stopped sending");
+                }
+                idt::wait_for_interrupt();
+            }
+        }
+    }
+    drop(stream);
+    parse_response(&buf[..received])
+}
+
+/// Split a whole response into its status and body
+fn parse_response(data: &[u8]) -> Result<Response, &'static str> {
+    let head_len = data.windows(4).position(|w| w == b"\r\n\r\n").ok_or("Malformed response")?;
+    let head = &data[..head_len];
+    let body = &data[head_len + 4..];
+
+    let status_line = head.split(|&b| b == b'\n').next().unwrap_or(head);
+    let status_line = status_line.strip_suffix(b"\r").unwrap_or(status_line);
+    let mut parts = status_line.splitn(3, |&b| b == b' ');
+    if !parts.next().map_or(false, |version| version.starts_with(b"HTTP/")) {
+        return Err("Malformed response");
+    }
+    let status = parts.next()
+        .and_then(|code| core::str::from_utf8(code).ok())
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or("Malformed response")?;
+    let reason = parts.next().unwrap_or(b"");
+
+    if let Some(length) = header(head, b"content-length") {
+        let length = core::str::from_utf8(length).ok().and_then(|n| n.trim().parse::<usize>().ok()).ok_or("Malformed response")?;
+        if body.len() < length {
+            return Err("Response cut short");
+        }
+        return Ok(Response { status, reason, body: &body[..length] });
+    }
+    Ok(Response { status, reason, body })
+}
+
+/// The value of the header `name`, which is lowercase
+fn header<'a>(head: &'a [u8], name: &[u8]) -> Option<&'a [u8]> {
+    head.split(|&b| b == b'\n').skip(1).find_map(|line| {
+        let colon = line.iter().position(|&b| b == b':')?;
+        if !line[..colon].eq_ignore_ascii_case(name) {
+            return None;
+        }
+        let value = &line[colon + 1..];
+        let value = value.strip_suffix(b"\r").unwrap_or(value);
+        let start = value.iter().position(|&b| b != b' ').unwrap_or(value.len());
+        Some(&value[start..])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn urls_and_responses_parse() {
+        let url = Url::parse(b"http://10.0.2.2:8000/logs/today.txt").unwrap();
+        assert_eq!((url.address, url.port, url.path), (Ipv4Addr([10, 0, 2, 2]), 8000, &b"/logs/today.txt"[..]));
+        assert_eq!(url.file_name(), b"today.txt");
+        let url = Url::parse(b"10.0.2.2").unwrap();
+        assert_eq!((url.port, url.path, url.file_name()), (80, &b"/"[..], &b"index.html"[..]));
+        assert!(Url::parse(b"http://example.com/").is_err());
+        assert!(Url::parse(b"https://10.0.2.2/").is_err());
+        assert!(Url::parse(b"http://10.0.2.2:0/").is_err());
+
+        let response = parse_response(b"HTTP/1.0 404 Not Found\r\nContent-Length: 4\r\n\r\ngone, and more").unwrap();
+        assert_eq!((response.status, response.reason, response.body), (404, &b"Not Found"[..], &b"gone"[..]));
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\nshort").is_err());
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\n").is_err());
+    }
+}
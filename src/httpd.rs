@@ -0,0 +1,235 @@
+use crate::at;
+use crate::clock;
+use crate::dhcp;
+use crate::filesystem::{get_filesystem, MAX_FILE_SIZE};
+use crate::fmt::{Duration, Size};
+use crate::hex_fetch;
+use crate::idt;
+use crate::multiboot;
+use crate::report::{JsonReport, Report, TextReport};
+use crate::sink::{Capture, Sink};
+use crate::tcp::{TcpListener, TcpStream};
+
+// The status server: `httpd start` listens on a TCP port and `poll`, called
+// from the shell's idle loop, answers one request at a time with pages on
+// uptime, memory, background jobs and the hexfetch fields, as plain text or,
+// with `.json` on the path, as JSON. Under QEMU's user network the host
+// reaches it through a forwarded port (`make run` forwards 8080 to 80).
+
+pub const DEFAULT_PORT: u16 = 80;
+const MAX_REQUEST_LEN: usize = 512;
+/// Give up on a client that doesn't finish its request in this long; the
+/// shell waits meanwhile, so it is short
+const REQUEST_TIMEOUT_MS: u64 = 250;
+/// How long closing waits for the client to acknowledge the end of a page
+const CLOSE_TIMEOUT_MS: u64 = 250;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Page {
+    Index,
+    Uptime,
+    Memory,
+    Tasks,
+    HexFetch,
+    Status,
+}
+
+const PAGES: [(&[u8], Page); 5] = [
+    (b"/uptime", Page::Uptime),
+    (b"/memory", Page::Memory),
+    (b"/tasks", Page::Tasks),
+    (b"/hexfetch", Page::HexFetch),
+    (b"/status", Page::Status),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Text,
+    Json,
+}
+
+struct Server {
+    listener: TcpListener,
+    served: u32,
+}
+
+static mut SERVER: Option<Server> = None;
+/// Pages are written here before they go out, as a response has to say
+/// how long it is
+static mut PAGE: [u8; MAX_FILE_SIZE] = [0; MAX_FILE_SIZE];
+
+pub fn start(port: u16) -> Result<(), &'static str> {
+    if running().is_some() {
+        return Err("Already running");
+    }
+    let listener = TcpListener::bind(port)?;
+    unsafe {
+        SERVER = Some(Server { listener, served: 0 });
+    }
+    Ok(())
+}
+
+pub fn stop() -> Result<(), &'static str> {
+    unsafe { SERVER.take() }.map(|_| ()).ok_or("Not running")
+}
+
+/// The port it listens on and how many requests it has answered
+pub fn running() -> Option<(u16, u32)> {
+    unsafe { SERVER.as_ref() }.map(|server| (server.listener.port(), server.served))
+}
+
+/// Answer a request if one has come in
+pub fn poll() {
+    let server = match unsafe { SERVER.as_mut() } {
+        Some(server) => server,
+        None => return,
+    };
+    if let Some(mut stream) = server.listener.accept() {
+        server.served += 1;
+        stream.set_close_timeout(CLOSE_TIMEOUT_MS);
+        // A client that goes away halfway is its own problem
+        let _ = serve(&stream);
+    }
+}
+
+fn serve(stream: &TcpStream) -> Result<(), &'static str> {
+    let mut request = [0u8; MAX_REQUEST_LEN];
+    let mut len = 0;
+    let deadline = clock::uptime_ms() + REQUEST_TIMEOUT_MS;
+    // Only the request line matters, but the headers are read too so the
+    // client doesn't see its request cut off
+    while !request[..len].windows(4).any(|w| w == b"\r\n\r\n") && len < request.len() {
+        match stream.read(&mut request[len..])? {
+            Some(0) => break,
+            Some(n) => len += n,
+            None if clock::uptime_ms() > deadline => return Err("Request timed out"),
+            None => idt::wait_for_interrupt(),
+        }
+    }
+
+    let (status, page) = match parse_request(&request[..len]) {
+        Err(status) => (status, None),
+        Ok(path) => match route(path) {
+            Some(page) => ("200 OK", Some(page)),
+            None => ("404 Not Found", None),
+        },
+    };
+    let body = unsafe { &mut PAGE };
+    let mut out = Capture::new(body);
+    let content_type = match page {
+        Some((page, format)) => {
+            write_page(page, format, &mut out);
+            if format == Format::Json { "application/json" } else { "text/plain" }
+        }
+        None => {
+            out.write_str(status);
+            out.write_byte(b'\n');
+            "text/plain"
+        }
+    };
+
+    let mut head = [0u8; 128];
+    let mut header = Capture::new(&mut head);
+    write!(header, "HTTP/1.0 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status, content_type, out.as_bytes().len());
+    stream.write_all(header.as_bytes())?;
+    stream.write_all(out.as_bytes())
+}
+
+/// The path a GET request line asks for, or the status to refuse it with
+fn parse_request(request: &[u8]) -> Result<&[u8], &'static str> {
+    let line = request.split(|&b| b == b'\n').next().unwrap_or(request);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let mut parts = line.split(|&b| b == b' ');
+    let method = parts.next().unwrap_or(b"");
+    let path = parts.next().ok_or("400 Bad Request")?;
+    if !parts.next().map_or(false, |version| version.starts_with(b"HTTP/")) || !path.starts_with(b"/") {
+        return Err("400 Bad Request");
+    }
+    if method != b"GET" {
+        return Err("405 Method Not Allowed");
+    }
+    // Queries mean nothing here
+    Ok(&path[..path.iter().position(|&b| b == b'?').unwrap_or(path.len())])
+}
+
+/// Which page a path names and in which format
+fn route(path: &[u8]) -> Option<(Page, Format)> {
+    if path == b"/" {
+        return Some((Page::Index, Format::Text));
+    }
+    let (name, format) = match path.strip_suffix(b".json") {
+        Some(name) => (name, Format::Json),
+        None => (path, Format::Text),
+    };
+    PAGES.iter().find(|&&(page_path, _)| page_path == name).map(|&(_, page)| (page, format))
+}
+
+fn write_page(page: Page, format: Format, out: &mut dyn Sink) {
+    if page == Page::Index {
+        out.write_str("HyzeOS status pages; add .json for JSON\n");
+        for &(path, _) in PAGES.iter() {
+            out.write_bytes(path);
+            out.write_byte(b'\n');
+        }
+        return;
+    }
+    match format {
+        Format::Text => write_sections(page, &mut TextReport::new(out)),
+        Format::Json => write_sections(page, &mut JsonReport::new(out)),
+    }
+}
+
+fn write_sections(page: Page, report: &mut dyn Report) {
+    if page == Page::Uptime || page == Page::Status {
+        let seconds = clock::uptime_seconds() as u64;
+        report.section("uptime");
+        report.value("seconds", format_args!("{}", seconds));
+        report.value("text", format_args!("{}", Duration(seconds)));
+    }
+    if page == Page::Memory || page == Page::Status {
+        let usage = get_filesystem().usage();
+        report.section("memory");
+        report.value("total", format_args!("{}", Size(multiboot::total_memory_kb().unwrap_or(0) as u64 * 1024)));
+        report.value("kernel", format_args!("{}", Size(crate::kernel_image_size() as u64)));
+        report.value("files", format_args!("{} of {}", usage.files, usage.max_files));
+        report.value("storage", format_args!("{} of {}", Size(usage.bytes as u64), Size(usage.capacity as u64)));
+    }
+    if page == Page::Tasks || page == Page::Status {
+        // No scheduler yet: what runs besides the shell is the work its
+        // idle loop does
+        report.section("tasks");
+        report.value("dhcp", format_args!("{}", dhcp::state().name()));
+        if let Some((port, served)) = running() {
+            report.value("httpd", format_args!("port {}, {} requests", port, served));
+        }
+        let now = clock::uptime_ms();
+        report.begin_list("at jobs");
+        for job in at::jobs() {
+            let command = core::str::from_utf8(job.command()).unwrap_or("?");
+            report.item(format_args!("{} in {}: {}", job.id, Duration(job.due_ms.saturating_sub(now) / 1000), command));
+        }
+        report.end_list();
+    }
+    if page == Page::HexFetch || page == Page::Status {
+        hex_fetch::report(report);
+    }
+    report.finish();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn requests_route_to_pages() {
+        assert_eq!(parse_request(b"GET /memory.json?x=1 HTTP/1.1\r\nHost: a\r\n\r\n"), Ok(&b"/memory.json"[..]));
+        assert_eq!(parse_request(b"POST / HTTP/1.1\r\n\r\n"), Err("405 Method Not Allowed"));
+        assert_eq!(parse_request(b"GET\r\n\r\n"), Err("400 Bad Request"));
+
+        assert_eq!(route(b"/"), Some((Page::Index, Format::Text)));
+        assert_eq!(route(b"/memory.json"), Some((Page::Memory, Format::Json)));
+        assert_eq!(route(b"/tasks"), Some((Page::Tasks, Format::Text)));
+        assert_eq!(route(b"/nope"), None);
+    }
+}
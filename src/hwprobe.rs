@@ -0,0 +1,181 @@
+use crate::acpi;
+use crate::apic;
+use crate::cpu::cpuid;
+use crate::framebuffer;
+use crate::io::inb;
+use crate::multiboot;
+use crate::pci;
+use crate::report::{JsonReport, Report, TextReport};
+use crate::serial::{self, SerialPort, COM1};
+use crate::sink::Sink;
+use crate::smp;
+use crate::tsc;
+
+// `hwprobe`: everything the kernel can find out about the machine in one
+// report, as text on screen or as JSON on COM1 for collecting compatibility
+// reports. Probing only reads, apart from the UART scratch registers.
+
+/// CPUID leaf 1 EDX bits
+const FEATURES_EDX: [(u32, &str); 17] = [
+    (0, "fpu"), (3, "pse"), (4, "tsc"), (5, "msr"), (6, "pae"), (8, "cx8"), (9, "apic"),
+    (11, "sep"), (12, "mtrr"), (13, "pge"), (15, "cmov"), (16, "pat"), (23, "mmx"),
+    (24, "fxsr"), (25, "sse"), (26, "sse2"), (28, "htt"),
+];
+/// CPUID leaf 1 ECX bits
+const FEATURES_ECX: [(u32, &str); 12] = [
+    (0, "sse3"), (3, "monitor"), (9, "ssse3"), (19, "sse4.1"), (20, "sse4.2"), (21, "x2apic"),
+    (23, "popcnt"), (25, "aes"), (26, "xsave"), (28, "avx"), (30, "rdrand"), (31, "hypervisor"),
+];
+/// CPUID leaf 0x80000001 EDX bits
+const FEATURES_EXT_EDX: [(u32, &str); 3] = [(20, "nx"), (27, "rdtscp"), (29, "lm")];
+
+/// COM1 as a sink, counting what went out
+struct SerialSink {
+    port: SerialPort,
+    bytes: usize,
+}
+
+impl Sink for SerialSink {
+    fn write_byte(&mut self, byte: u8) {
+        if byte == b'\n' {
+            self.port.write_byte(b'\r');
+        }
+        self.port.write_byte(byte);
+        self.bytes += 1;
+    }
+
+    fn column(&self) -> usize {
+        0
+    }
+}
+
+/// The human-readable report
+pub fn write_text(out: &mut dyn Sink) {
+    probe(&mut TextReport::new(out));
+}
+
+/// Send the JSON report to COM1 and return its size in bytes
+pub fn send_json() -> usize {
+    let mut serial = SerialSink { port: SerialPort::new(COM1), bytes: 0 };
+    probe(&mut JsonReport::new(&mut serial));
+    serial.bytes
+}
+
+fn probe(report: &mut dyn Report) {
+    probe_cpu(report);
+    probe_memory(report);
+    probe_acpi(report);
+    probe_pci(report);
+    probe_legacy(report);
+    report.finish();
+}
+
+fn cpuid_string(leaves: &[u32], out: &mut [u8]) {
+    for (i, &leaf) in leaves.iter().enumerate() {
+        let (eax, ebx, ecx, edx) = cpuid(leaf);
+        for (j, reg) in [eax, ebx, ecx, edx].iter().enumerate() {
+            out[i * 16 + j * 4..i * 16 + j * 4 + 4].copy_from_slice(&reg.to_le_bytes());
+        }
+    }
+}
+
+fn probe_cpu(report: &mut dyn Report) {
+    report.section("cpu");
+    let (max_leaf, ebx, ecx, edx) = cpuid(0);
+    let mut vendor = [0u8; 12];
+    vendor[..4].copy_from_slice(&ebx.to_le_bytes());
+    vendor[4..8].copy_from_slice(&edx.to_le_bytes());
+    vendor[8..].copy_from_slice(&ecx.to_le_bytes());
+    report.value("vendor", format_args!("{}", core::str::from_utf8(&vendor).unwrap_or("?")));
+
+    let max_ext = cpuid(0x8000_0000).0;
+    if max_ext >= 0x8000_0004 {
+        let mut brand = [0u8; 48];
+        cpuid_string(&[0x8000_0002, 0x8000_0003, 0x8000_0004], &mut brand);
+        let brand = core::str::from_utf8(&brand).unwrap_or("?").trim_matches(|c: char| c == '\0' || c == ' ');
+        report.value("brand", format_args!("{}", brand));
+    }
+
+    let (signature, _, ecx, edx) = if max_leaf >= 1 { cpuid(1) } else { (0, 0, 0, 0) };
+    let family = (signature >> 8) & 0xF;
+    let family = if family == 0xF { family + ((signature >> 20) & 0xFF) } else { family };
+    let model = (signature >> 4) & 0xF | ((signature >> 16) & 0xF) << 4;
+    report.value("signature", format_args!("family {} model {} stepping {}", family, model, signature & 0xF));
+
+    let ext_edx = if max_ext >= 0x8000_0001 { cpuid(0x8000_0001).3 } else { 0 };
+    report.begin_list("features");
+    let tables: [(&[(u32, &str)], u32); 3] = [(&FEATURES_EDX, edx), (&FEATURES_ECX, ecx), (&FEATURES_EXT_EDX, ext_edx)];
+    for &(table, register) in tables.iter() {
+        for &(_, name) in table.iter().filter(|&&(bit, _)| register & (1 << bit) != 0) {
+            report.item(format_args!("{}", name));
+        }
+    }
+    report.end_list();
+
+    if tsc::frequency_hz() != 0 {
+        report.value("tsc", format_args!("{} MHz", tsc::frequency_hz() / 1_000_000));
+    }
+    report.flag("invariant tsc", tsc::is_invariant());
+    if ecx & (1 << 31) != 0 {
+        let mut hypervisor = [0u8; 16];
+        cpuid_string(&[0x4000_0000], &mut hypervisor);
+        let name = core::str::from_utf8(&hypervisor[4..]).unwrap_or("?").trim_end_matches('\0');
+        report.value("hypervisor", format_args!("{}", name));
+    }
+    report.value("cpus online", format_args!("{}", smp::online_count()));
+}
+
+fn probe_memory(report: &mut dyn Report) {
+    report.section("memory");
+    match multiboot::total_memory_kb() {
+        Some(kb) => report.value("total", format_args!("{} KB", kb)),
+        None => report.value("total", format_args!("unknown")),
+    }
+}
+
+fn probe_acpi(report: &mut dyn Report) {
+    report.section("acpi");
+    let info = acpi::get_info();
+    report.flag("present", info.present);
+    if !info.present {
+        return;
+    }
+    report.value("revision", format_args!("{}", info.revision));
+    report.value("oem", format_args!("{}", core::str::from_utf8(&info.oem_id).unwrap_or("?").trim_end()));
+    report.begin_list("tables");
+    for signature in acpi::table_signatures() {
+        report.item(format_args!("{}", core::str::from_utf8(&signature).unwrap_or("????")));
+    }
+    report.end_list();
+    report.value("processors", format_args!("{}", info.cpu_count()));
+    report.value("io apics", format_args!("{}", info.io_apic_count));
+    report.flag("apic in use", apic::is_enabled());
+}
+
+fn probe_pci(report: &mut dyn Report) {
+    report.section("pci");
+    report.begin_list("devices");
+    for device in pci::devices() {
+        report.item(format_args!(
+            "{:02x}:{:02x}.{} {:04x}:{:04x} class {:02x}.{:02x}",
+            device.bus, device.device, device.function, device.vendor_id, device.device_id, device.class, device.subclass
+        ));
+    }
+    report.end_list();
+}
+
+fn probe_legacy(report: &mut dyn Report) {
+    report.section("legacy");
+    report.begin_list("serial ports");
+    for &(base, name, _) in serial::PORTS.iter().filter(|&&(base, _, _)| SerialPort::new(base).is_present()) {
+        report.item(format_args!("{} ({:#x})", name, base));
+    }
+    report.end_list();
+    // A missing controller floats the status port high
+    report.flag("ps/2 controller", unsafe { inb(0x64) } != 0xFF);
+    match framebuffer::active() {
+        Some(fb) => report.value("display", format_args!("framebuffer {}x{}", fb.width(), fb.height())),
+        None if framebuffer::is_available() => report.value("display", format_args!("vga text 80x25, vbe adapter")),
+        None => report.value("display", format_args!("vga text 80x25")),
+    }
+}
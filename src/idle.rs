@@ -0,0 +1,245 @@
+use core::arch::asm;
+
+use crate::cpu::{self, cpuid, cpuid_count};
+
+// Idle state selection. `HLT` is always available as C1; CPUs with
+// MONITOR/MWAIT also offer deeper C-states, entered with an MWAIT hint.
+// The choice is made from how long recent idle periods lasted, so short
+// waits between keystrokes don't pay the exit latency of a deep state.
+
+const MAX_STATES: usize = 8;
+
+const CPUID_MONITOR: u32 = 1 << 3;
+/// Leaf 5 ECX: the sub-state counts in EDX are valid
+const MWAIT_EXTENSIONS: u32 = 1 << 0;
+
+// Intel hardware P-states (HWP)
+const CPUID_HWP: u32 = 1 << 7;
+const CPUID_HWP_EPP: u32 = 1 << 10;
+const IA32_PM_ENABLE: u32 = 0x770;
+const IA32_HWP_REQUEST: u32 = 0x774;
+
+// AMD hardware P-states
+const CPUID_AMD_HW_PSTATE: u32 = 1 << 7;
+const AMD_PSTATE_LIMIT: u32 = 0xC001_0061;
+const AMD_PSTATE_CONTROL: u32 = 0xC001_0062;
+
+#[derive(Clone, Copy)]
+pub struct State {
+    pub name: &'static str,
+    /// MWAIT hint, or `None` for HLT
+    pub hint: Option<u32>,
+    /// Predicted idle ticks needed before this state is worth entering
+    pub min_ticks: u32,
+    pub entries: u32,
+    /// Timer ticks that arrived while in this state
+    pub ticks: u32,
+}
+
+const HALT: State = State { name: "C1 (hlt)", hint: None, min_ticks: 0, entries: 0, ticks: 0 };
+
+static mut STATES: [State; MAX_STATES] = [HALT; MAX_STATES];
+static mut STATE_COUNT: usize = 1;
+static mut CURRENT: Option<usize> = None;
+/// Recent idle length in ticks, scaled by 8 for a fractional average
+static mut PREDICTED: u32 = 0;
+/// MWAIT watches this line; nothing writes it, interrupts end the wait
+static mut MONITOR_LINE: u32 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PerfHint {
+    Performance,
+    Balanced,
+    PowerSave,
+}
+
+impl PerfHint {
+    pub fn from_name(name: &[u8]) -> Option<PerfHint> {
+        match name {
+            b"performance" => Some(PerfHint::Performance),
+            b"balanced" => Some(PerfHint::Balanced),
+            b"powersave" => Some(PerfHint::PowerSave),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PerfHint::Performance => "performance",
+            PerfHint::Balanced => "balanced",
+            PerfHint::PowerSave => "powersave",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PerfControl {
+    /// Intel HWP with an energy/performance preference
+    Hwp,
+    /// AMD P-state control MSRs
+    AmdPstate,
+}
+
+static mut PERF_CONTROL: Option<PerfControl> = None;
+static mut PERF_HINT: Option<PerfHint> = None;
+
+/// Find the idle states and P-state control this CPU offers. Returns the
+/// number of idle states.
+pub fn init() -> usize {
+    let (max_func, _, _, _) = cpuid(0);
+    let (_, _, ecx, _) = cpuid(1);
+    unsafe {
+        if ecx & CPUID_MONITOR != 0 && max_func >= 5 {
+            let (_, _, ecx, edx) = cpuid(5);
+            if ecx & MWAIT_EXTENSIONS != 0 {
+                add_mwait_states(edx);
+            }
+        }
+
+        if max_func >= 6 {
+            let (eax, _, _, _) = cpuid_count(6, 0);
+            if eax & CPUID_HWP != 0 && eax & CPUID_HWP_EPP != 0 {
+                PERF_CONTROL = Some(PerfControl::Hwp);
+            }
+        }
+        let (max_ext, _, _, _) = cpuid(0x8000_0000);
+        if PERF_CONTROL.is_none() && max_ext >= 0x8000_0007 {
+            let (_, _, _, edx) = cpuid(0x8000_0007);
+            if edx & CPUID_AMD_HW_PSTATE != 0 {
+                PERF_CONTROL = Some(PerfControl::AmdPstate);
+            }
+        }
+        STATE_COUNT
+    }
+}
+
+/// Leaf 5 EDX holds four bits per C-state (C0 upwards) giving how many
+/// MWAIT sub-states it has. C1 keeps using HLT; each deeper C-state with
+/// sub-states gets an entry using its first sub-state.
+unsafe fn add_mwait_states(substates: u32) {
+    const NAMES: [&str; 7] = ["C2 (mwait)", "C3 (mwait)", "C4 (mwait)", "C5 (mwait)", "C6 (mwait)", "C7 (mwait)", "C8 (mwait)"];
+    for cstate in 2..8 {
+        if STATE_COUNT == MAX_STATES {
+            break;
+        }
+        if (substates >> (cstate * 4)) & 0xF == 0 {
+            continue;
+        }
+        STATES[STATE_COUNT] = State {
+            name: NAMES[cstate as usize - 2],
+            // The hint encodes C-state minus one in bits 7:4
+            hint: Some((cstate - 1) << 4),
+            min_ticks: STATE_COUNT as u32,
+            entries: 0,
+            ticks: 0,
+        };
+        STATE_COUNT += 1;
+    }
+}
+
+pub fn states() -> &'static [State] {
+    unsafe { &STATES[..STATE_COUNT] }
+}
+
+/// Deepest state whose break-even point is within the predicted idle time
+fn select() -> usize {
+    let predicted = unsafe { PREDICTED / 8 };
+    states().iter().rposition(|state| state.min_ticks <= predicted).unwrap_or(0)
+}
+
+/// Sleep until the next interrupt, in the best idle state for the wait
+pub fn enter() {
+    let index = select();
+    unsafe {
+        let state = &mut STATES[index];
+        state.entries = state.entries.wrapping_add(1);
+        CURRENT = Some(index);
+        match state.hint {
+            None => asm!("hlt", options(nostack)),
+            Some(hint) => {
+                asm!(
+                    "monitor",
+                    in("eax") &MONITOR_LINE,
+                    in("ecx") 0,
+                    in("edx") 0,
+                    options(nostack),
+                );
+                asm!("mwait", in("eax") hint, in("ecx") 0, options(nostack));
+            }
+        }
+        CURRENT = None;
+    }
+}
+
+/// Called with how many ticks the last wait took, to update the prediction
+pub fn record(ticks: u32) {
+    unsafe {
+        // Exponential average with weight 1/4 on the newest sample
+        PREDICTED = PREDICTED - PREDICTED / 4 + ticks.min(1000) * 2;
+    }
+}
+
+/// Account a timer tick to the idle state the CPU is in, if any
+pub fn tick() {
+    unsafe {
+        if let Some(index) = CURRENT {
+            STATES[index].ticks = STATES[index].ticks.wrapping_add(1);
+        }
+    }
+}
+
+pub fn perf_control() -> Option<PerfControl> {
+    unsafe { PERF_CONTROL }
+}
+
+pub fn perf_hint() -> Option<PerfHint> {
+    unsafe { PERF_HINT }
+}
+
+/// Ask the CPU to favour speed or power
+pub fn set_perf_hint(hint: PerfHint) -> Result<(), &'static str> {
+    match perf_control() {
+        None => return Err("CPU has no P-state control HyzeOS knows"),
+        Some(PerfControl::Hwp) => {
+            cpu::write_msr(IA32_PM_ENABLE, 1);
+            let preference: u64 = match hint {
+                PerfHint::Performance => 0x00,
+                PerfHint::Balanced => 0x80,
+                PerfHint::PowerSave => 0xFF,
+            };
+            let request = cpu::read_msr(IA32_HWP_REQUEST) & !(0xFF << 24);
+            cpu::write_msr(IA32_HWP_REQUEST, request | preference << 24);
+        }
+        Some(PerfControl::AmdPstate) => {
+            // P0 is the fastest; the limit register gives the slowest
+            let slowest = (cpu::read_msr(AMD_PSTATE_LIMIT) >> 4) & 0x7;
+            let pstate = match hint {
+                PerfHint::Performance => 0,
+                PerfHint::Balanced => slowest / 2,
+                PerfHint::PowerSave => slowest,
+            };
+            cpu::write_msr(AMD_PSTATE_CONTROL, pstate);
+        }
+    }
+    unsafe {
+        PERF_HINT = Some(hint);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn long_waits_pick_deeper_states() {
+        unsafe {
+            PREDICTED = 0;
+        }
+        assert_eq!(select(), 0);
+        for _ in 0..32 {
+            record(100);
+        }
+        assert_eq!(select(), states().len() - 1);
+    }
+}
@@ -1,6 +1,20 @@
 use core::arch::asm;
 use core::arch::naked_asm;
 
+use crate::apic;
+use crate::clock;
+use crate::gdt;
+use crate::hotkey;
+use crate::idle;
+use crate::keyboard;
+use crate::latency;
+use crate::metrics;
+use crate::rng;
+use crate::signal;
+use crate::watchdog;
+use crate::stackguard;
+use crate::sync::Mutex;
+
 #[derive(Copy, Clone)]
 #[repr(C, packed)]
 pub struct IdtEntry {
@@ -29,6 +43,21 @@ impl IdtEntry {
         self.zero = 0;
         self.type_attr = 0x8E;
     }
+
+    /// Switch to the task in `tss_selector` instead of calling a handler
+    pub fn set_task_gate(&mut self, tss_selector: u16) {
+        self.offset_low = 0;
+        self.offset_high = 0;
+        self.selector = tss_selector;
+        self.zero = 0;
+        self.type_attr = 0x85;
+    }
+
+    /// Like `set_handler`, but ring 3 code may also reach the gate with `int`
+    pub fn set_user_handler(&mut self, handler: u32) {
+        self.set_handler(handler);
+        self.type_attr = 0xEE;
+    }
 }
 
 #[repr(C, packed)]
@@ -42,16 +71,24 @@ static mut IDT: [IdtEntry; IDT_SIZE] = [IdtEntry::empty(); IDT_SIZE];
 static mut IDT_PTR: IdtPointer = IdtPointer { limit: 0, base: 0 };
 static mut TICK_COUNT: u32 = 0;
 
+/// Timer ticks that found the CPU halted
+static mut IDLE: bool = false;
+static mut IDLE_TICKS: u32 = 0;
+
+const MAX_TIMER_CALLBACKS: usize = 4;
+static mut TIMER_CALLBACKS: [Option<fn(u32)>; MAX_TIMER_CALLBACKS] = [None; MAX_TIMER_CALLBACKS];
+
 pub fn init() {
     unsafe {
+        IDT[0x08].set_task_gate(gdt::DOUBLE_FAULT_TSS_SELECTOR);
         IDT[0x20].set_handler(timer_interrupt_handler as u32);
         IDT[0x21].set_handler(keyboard_interrupt_handler as u32);
+        IDT[0x2C].set_handler(mouse_interrupt_handler as u32);
 
         IDT_PTR.limit = (core::mem::size_of::<[IdtEntry; IDT_SIZE]>() - 1) as u16;
         IDT_PTR.base = IDT.as_ptr() as u32;
-
-        asm!("lidt [{}]", in(reg) &IDT_PTR, options(nostack));
     }
+    load();
 
     init_pics();
     init_pit();
@@ -61,19 +98,154 @@ pub fn init() {
     }
 }
 
+/// Load the shared IDT on the calling CPU
+pub fn load() {
+    unsafe {
+        asm!("lidt [{}]", in(reg) &IDT_PTR, options(nostack));
+    }
+}
+
+/// Point `vector` at `handler`; `user` gates can be invoked from ring 3
+pub fn set_gate(vector: u8, handler: u32, user: bool) {
+    unsafe {
+        if user {
+            IDT[vector as usize].set_user_handler(handler);
+        } else {
+            IDT[vector as usize].set_handler(handler);
+        }
+    }
+}
+
+/// PIT input clock in Hz
+pub const PIT_FREQUENCY_HZ: u32 = 1_193_182;
+/// Timer interrupts per second at boot
+pub const TIMER_HZ: u32 = 100;
+/// PIT input clock period in picoseconds (1.193182 MHz)
+pub const PIT_PERIOD_PS: u32 = 838_096;
+
+static mut PIT_DIVISOR: u16 = 0;
+
 fn init_pit() {
-    let divisor: u16 = 11932;
+    set_timer_frequency(TIMER_HZ);
+}
+
+/// Reprogram channel 0 to interrupt `hz` times a second, as a rate
+/// generator (mode 2), so the count read back by `pit_count` falls steadily
+/// from the divisor to the next interrupt. The clock is told the new tick
+/// length.
+pub fn set_timer_frequency(hz: u32) {
+    let divisor = (PIT_FREQUENCY_HZ / hz.max(1)).clamp(1, u16::MAX as u32) as u16;
+    program_pit(divisor);
+    clock::set_tick_period(clock::pit_tick_ns(divisor as u32));
+}
+
+fn program_pit(divisor: u16) {
     unsafe {
-        outb(0x43, 0x36);
+        PIT_DIVISOR = divisor;
+        outb(0x43, 0x34);
         outb(0x40, (divisor & 0xFF) as u8);
         outb(0x40, ((divisor >> 8) & 0xFF) as u8);
     }
 }
 
+pub fn pit_divisor() -> u16 {
+    unsafe { PIT_DIVISOR }
+}
+
+/// Latch and read the channel 0 count
+pub fn pit_count() -> u16 {
+    unsafe {
+        outb(0x43, 0x00);
+        let low = inb(0x40) as u16;
+        let high = inb(0x40) as u16;
+        high << 8 | low
+    }
+}
+
 pub fn get_ticks() -> u32 {
     unsafe { TICK_COUNT }
 }
 
+/// Ticks that arrived while the CPU was waiting in `wait_for_interrupt`
+pub fn idle_ticks() -> u32 {
+    unsafe { IDLE_TICKS }
+}
+
+/// Legacy IRQs sit at these vectors behind either interrupt controller
+const IRQ_BASE_VECTOR: u8 = 0x20;
+
+pub fn count_irq(irq: usize) {
+    metrics::count_interrupt(IRQ_BASE_VECTOR + irq as u8);
+}
+
+/// Interrupts seen on an IRQ line
+pub fn irq_count(irq: usize) -> u32 {
+    metrics::interrupts(IRQ_BASE_VECTOR + irq as u8)
+}
+
+/// Acknowledge `irq` to whichever interrupt controller delivered it
+pub fn end_of_interrupt(irq: usize) {
+    if apic::is_enabled() {
+        apic::end_of_interrupt();
+        return;
+    }
+    unsafe {
+        if irq >= 8 {
+            outb(0xA0, 0x20);
+        }
+        outb(0x20, 0x20);
+    }
+}
+
+/// Unmask `irq` on the 8259 PICs, for lines `init_pics` leaves masked
+pub fn unmask_pic_irq(irq: u8) {
+    unsafe {
+        let port = if irq < 8 { 0x21 } else { 0xA1 };
+        outb(port, inb(port) & !(1 << (irq % 8)));
+    }
+}
+
+/// Current PIC interrupt masks, master then slave
+pub fn pic_masks() -> [u8; 2] {
+    unsafe { [inb(0x21), inb(0xA1)] }
+}
+
+/// Reload the IDT and reprogram the PICs and PIT after waking from S3,
+/// which resets them. `pic_masks` come from `pic_masks` before the sleep.
+pub fn resume(pic_masks: [u8; 2]) {
+    load();
+    init_pics();
+    unsafe {
+        outb(0x21, pic_masks[0]);
+        outb(0xA1, pic_masks[1]);
+    }
+    program_pit(pit_divisor());
+}
+
+/// Run `callback` with the tick count on every timer interrupt. It executes
+/// in interrupt context, so it must be short and must not block.
+pub fn add_timer_callback(callback: fn(u32)) -> Result<usize, &'static str> {
+    unsafe {
+        asm!("cli", options(nostack));
+        let slot = TIMER_CALLBACKS.iter().position(|c| c.is_none());
+        if let Some(id) = slot {
+            TIMER_CALLBACKS[id] = Some(callback);
+        }
+        asm!("sti", options(nostack));
+        slot.ok_or("No free timer callback slots")
+    }
+}
+
+pub fn remove_timer_callback(id: usize) {
+    unsafe {
+        asm!("cli", options(nostack));
+        if id < MAX_TIMER_CALLBACKS {
+            TIMER_CALLBACKS[id] = None;
+        }
+        asm!("sti", options(nostack));
+    }
+}
+
 fn init_pics() {
     unsafe {
         outb(0x20, 0x11);
@@ -88,8 +260,9 @@ fn init_pics() {
         outb(0x21, 0x01);
         outb(0xA1, 0x01);
 
-        outb(0x21, 0xFC);
-        outb(0xA1, 0xFF);
+        // Unmask timer, keyboard and the cascade; IRQ12 (mouse) on the slave
+        outb(0x21, 0xF8);
+        outb(0xA1, 0xEF);
     }
 }
 
@@ -114,66 +287,100 @@ pub unsafe fn inb(port: u16) -> u8 {
 }
 
 const BUFFER_SIZE: usize = 32;
-static mut KEY_BUFFER: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
-static mut BUFFER_HEAD: usize = 0;
-static mut BUFFER_TAIL: usize = 0;
 
-pub fn get_scancode() -> Option<u8> {
-    unsafe {
-        asm!("cli", options(nostack));
-        let result = if BUFFER_HEAD == BUFFER_TAIL {
-            None
-        } else {
-            let scancode = KEY_BUFFER[BUFFER_TAIL];
-            BUFFER_TAIL = (BUFFER_TAIL + 1) % BUFFER_SIZE;
-            Some(scancode)
-        };
-        asm!("sti", options(nostack));
-        result
-    }
+/// Scancodes from the keyboard interrupt, waiting to be read
+struct ScancodeQueue {
+    data: [u8; BUFFER_SIZE],
+    head: usize,
+    tail: usize,
 }
 
-pub fn wait_for_interrupt() {
-    unsafe {
-        asm!("hlt", options(nostack));
+impl ScancodeQueue {
+    const fn new() -> Self {
+        Self { data: [0; BUFFER_SIZE], head: 0, tail: 0 }
     }
-}
 
-pub fn flush_buffer() {
-    unsafe {
-        asm!("cli", options(nostack));
-        BUFFER_HEAD = 0;
-        BUFFER_TAIL = 0;
-        asm!("sti", options(nostack));
+    /// Drops the scancode when full
+    fn push(&mut self, scancode: u8) {
+        let next_head = (self.head + 1) % BUFFER_SIZE;
+        if next_head != self.tail {
+            self.data[self.head] = scancode;
+            self.head = next_head;
+        }
     }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.head == self.tail {
+            return None;
+        }
+        let scancode = self.data[self.tail];
+        self.tail = (self.tail + 1) % BUFFER_SIZE;
+        Some(scancode)
+    }
+
+    fn clear(&mut self) {
+        self.head = 0;
+        self.tail = 0;
+    }
+}
+
+static KEY_BUFFER: Mutex<ScancodeQueue> = Mutex::new(ScancodeQueue::new());
+
+pub fn get_scancode() -> Option<u8> {
+    hotkey::run_pending();
+    let scancode = KEY_BUFFER.lock().pop()?;
+    keyboard::observe(scancode);
+    Some(scancode)
 }
 
-fn buffer_push(scancode: u8) {
+pub fn wait_for_interrupt() {
+    watchdog::feed();
     unsafe {
-        let next_head = (BUFFER_HEAD + 1) % BUFFER_SIZE;
-        if next_head != BUFFER_TAIL {
-            KEY_BUFFER[BUFFER_HEAD] = scancode;
-            BUFFER_HEAD = next_head;
-        }
+        let start = TICK_COUNT;
+        IDLE = true;
+        idle::enter();
+        IDLE = false;
+        idle::record(TICK_COUNT.wrapping_sub(start));
     }
 }
 
+pub fn flush_buffer() {
+    KEY_BUFFER.lock().clear();
+}
+
 #[no_mangle]
 pub extern "C" fn keyboard_handler_inner() {
+    let _canary = stackguard::Canary::new();
     unsafe {
         let scancode = inb(0x60);
-        
-        buffer_push(scancode);
+        count_irq(1);
+        rng::add_entropy();
 
-        outb(0x20, 0x20);
+        if !hotkey::filter(scancode) && !signal::filter(scancode) {
+            KEY_BUFFER.lock().push(scancode);
+        }
+
+        end_of_interrupt(1);
     }
 }
 
 #[no_mangle]
 pub extern "C" fn timer_handler_inner() {
+    let _canary = stackguard::Canary::new();
+    stackguard::check_stacks();
+    latency::sample();
     unsafe {
         TICK_COUNT = TICK_COUNT.wrapping_add(1);
-        outb(0x20, 0x20);
+        clock::tick();
+        count_irq(0);
+        if IDLE {
+            IDLE_TICKS = IDLE_TICKS.wrapping_add(1);
+            idle::tick();
+        }
+        for callback in TIMER_CALLBACKS.iter().flatten() {
+            callback(TICK_COUNT);
+        }
+        end_of_interrupt(0);
     }
 }
 
@@ -182,9 +389,18 @@ pub extern "C" fn timer_handler_inner() {
 pub unsafe extern "C" fn keyboard_interrupt_handler() {
     naked_asm!(
         "pusha",
+        // A program may have been running with its own data segments
+        "push ds",
+        "push es",
+        "mov ax, {kernel_data}",
+        "mov ds, ax",
+        "mov es, ax",
         "call keyboard_handler_inner",
+        "pop es",
+        "pop ds",
         "popa",
         "iretd",
+        kernel_data = const gdt::KERNEL_DATA as u32,
     );
 }
 
@@ -193,8 +409,35 @@ pub unsafe extern "C" fn keyboard_interrupt_handler() {
 pub unsafe extern "C" fn timer_interrupt_handler() {
     naked_asm!(
         "pusha",
+        "push ds",
+        "push es",
+        "mov ax, {kernel_data}",
+        "mov ds, ax",
+        "mov es, ax",
         "call timer_handler_inner",
+        "pop es",
+        "pop ds",
+        "popa",
+        "iretd",
+        kernel_data = const gdt::KERNEL_DATA as u32,
+    );
+}
+
+#[unsafe(naked)]
+#[no_mangle]
+pub unsafe extern "C" fn mouse_interrupt_handler() {
+    naked_asm!(
+        "pusha",
+        "push ds",
+        "push es",
+        "mov ax, {kernel_data}",
+        "mov ds, ax",
+        "mov es, ax",
+        "call mouse_handler_inner",
+        "pop es",
+        "pop ds",
         "popa",
         "iretd",
+        kernel_data = const gdt::KERNEL_DATA as u32,
     );
 }
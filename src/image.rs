@@ -0,0 +1,212 @@
+use crate::framebuffer::Framebuffer;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Format {
+    /// Uncompressed BMP: 8bpp paletted, 24bpp BGR or 32bpp BGRX
+    Bmp { bits: u16, palette: usize, bottom_up: bool },
+    /// Binary PPM (P6) with 8-bit samples
+    Ppm,
+}
+
+/// A decoded view of an image file; pixels are read straight out of `data`
+pub struct Image<'a> {
+    data: &'a [u8],
+    format: Format,
+    width: usize,
+    height: usize,
+    pixels: usize,
+    stride: usize,
+}
+
+impl<'a> Image<'a> {
+    pub fn decode(data: &'a [u8]) -> Result<Self, &'static str> {
+        if data.starts_with(b"BM") {
+            Self::decode_bmp(data)
+        } else if data.starts_with(b"P6") {
+            Self::decode_ppm(data)
+        } else {
+            Err("Unknown image format (need BMP or binary PPM)")
+        }
+    }
+
+    fn decode_bmp(data: &'a [u8]) -> Result<Self, &'static str> {
+        if data.len() < 54 {
+            return Err("Truncated BMP header");
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+
+        let pixels = u32_at(10) as usize;
+        let header_size = u32_at(14) as usize;
+        let width = u32_at(18) as i32;
+        let height = u32_at(22) as i32;
+        let bits = u16_at(28);
+        let compression = u32_at(30);
+
+        // BI_BITFIELDS is accepted for 32bpp, assuming the usual BGRX masks
+        if compression != 0 && !(compression == 3 && bits == 32) {
+            return Err("Compressed BMPs are not supported");
+        }
+        if bits != 8 && bits != 24 && bits != 32 {
+            return Err("Unsupported BMP bit depth");
+        }
+        if width <= 0 || height == 0 {
+            return Err("Invalid BMP dimensions");
+        }
+
+        let width = width as usize;
+        let stride = (width * bits as usize / 8 + 3) & !3;
+        let image = Self {
+            data,
+            format: Format::Bmp { bits, palette: 14 + header_size, bottom_up: height > 0 },
+            width,
+            height: height.unsigned_abs() as usize,
+            pixels,
+            stride,
+        };
+        image.check_size()
+    }
+
+    fn decode_ppm(data: &'a [u8]) -> Result<Self, &'static str> {
+        // Header: "P6" width height maxval, whitespace separated, with # comments
+        let mut fields = [0usize; 3];
+        let mut pos = 2;
+        for field in fields.iter_mut() {
+            loop {
+                match data.get(pos) {
+                    Some(b'#') => {
+                        while data.get(pos).map_or(false, |&b| b != b'\n') {
+                            pos += 1;
+                        }
+                    }
+                    Some(b) if b.is_ascii_whitespace() => pos += 1,
+                    _ => break,
+                }
+            }
+            let start = pos;
+            while data.get(pos).map_or(false, |b| b.is_ascii_digit()) {
+                *field = *field * 10 + (data[pos] - b'0') as usize;
+                pos += 1;
+            }
+            if pos == start {
+                return Err("Invalid PPM header");
+            }
+        }
+        if fields[2] != 255 {
+            return Err("Only 8-bit PPMs are supported");
+        }
+
+        let image = Self {
+            data,
+            format: Format::Ppm,
+            width: fields[0],
+            height: fields[1],
+            // Exactly one whitespace byte follows maxval
+            pixels: pos + 1,
+            stride: fields[0] * 3,
+        };
+        image.check_size()
+    }
+
+    fn check_size(self) -> Result<Self, &'static str> {
+        if self.width == 0 || self.height == 0 {
+            return Err("Empty image");
+        }
+        if self.pixels + self.stride * self.height > self.data.len() {
+            return Err("Image data is truncated");
+        }
+        Ok(self)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Pixel at (x, y) as 0xRRGGBB, with y = 0 at the top
+    pub fn pixel(&self, x: usize, y: usize) -> u32 {
+        let rgb = |r: u8, g: u8, b: u8| (r as u32) << 16 | (g as u32) << 8 | b as u32;
+        match self.format {
+            Format::Ppm => {
+                let p = self.pixels + y * self.stride + x * 3;
+                rgb(self.data[p], self.data[p + 1], self.data[p + 2])
+            }
+            Format::Bmp { bits, palette, bottom_up } => {
+                let row = if bottom_up { self.height - 1 - y } else { y };
+                let line = self.pixels + row * self.stride;
+                match bits {
+                    8 => {
+                        // Palette entries are BGRX
+                        let entry = palette + self.data[line + x] as usize * 4;
+                        match self.data.get(entry..entry + 3) {
+                            Some(c) => rgb(c[2], c[1], c[0]),
+                            None => 0,
+                        }
+                    }
+                    _ => {
+                        let p = line + x * (bits as usize / 8);
+                        rgb(self.data[p + 2], self.data[p + 1], self.data[p])
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draw the image centered on the screen, scaled (nearest neighbour) to
+    /// the largest size that fits while keeping its aspect ratio
+    pub fn draw_fitted(&self, fb: &Framebuffer) {
+        let (screen_w, screen_h) = (fb.width(), fb.height());
+        let (mut w, mut h) = (screen_w, self.height * screen_w / self.width);
+        if h > screen_h {
+            h = screen_h;
+            w = (self.width * screen_h / self.height).max(1);
+        }
+        let h = h.max(1);
+        let (left, top) = ((screen_w - w) / 2, (screen_h - h) / 2);
+
+        let mut line = [0u32; 1280];
+        let w = w.min(line.len());
+        for dy in 0..h {
+            let sy = dy * self.height / h;
+            for dx in 0..w {
+                line[dx] = self.pixel(dx * self.width / w, sy);
+            }
+            fb.blit(left, top + dy, w, 1, &line[..w]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn decodes_ppm_with_comment() {
+        let data = b"P6\n# test\n2 1\n255\n\xff\x00\x00\x00\x00\xff";
+        let image = Image::decode(data).unwrap();
+        assert_eq!((image.width(), image.height()), (2, 1));
+        assert_eq!(image.pixel(0, 0), 0xFF0000);
+        assert_eq!(image.pixel(1, 0), 0x0000FF);
+    }
+
+    #[test_case]
+    fn decodes_bottom_up_24bpp_bmp() {
+        let mut data = [0u8; 54 + 8];
+        data[..2].copy_from_slice(b"BM");
+        data[10] = 54;
+        data[14] = 40;
+        data[18] = 1; // 1x2
+        data[22] = 2;
+        data[28] = 24;
+        // Bottom row first, each row padded to 4 bytes, BGR order
+        data[54..57].copy_from_slice(&[0x00, 0xFF, 0x00]);
+        data[58..61].copy_from_slice(&[0x00, 0x00, 0xFF]);
+        let image = Image::decode(&data).unwrap();
+        assert_eq!(image.pixel(0, 0), 0xFF0000);
+        assert_eq!(image.pixel(0, 1), 0x00FF00);
+        assert!(Image::decode(&data[..60]).is_err());
+    }
+}
@@ -0,0 +1,177 @@
+use crate::filesystem::get_filesystem;
+use crate::multiboot;
+
+const BLOCK_SIZE: usize = 512;
+
+/// One entry of a ustar archive
+pub struct TarEntry<'a> {
+    /// Path with any leading "./" or "/" removed
+    pub path: &'a [u8],
+    pub is_dir: bool,
+    pub data: &'a [u8],
+}
+
+/// Iterates over the entries of an in-memory ustar archive
+pub struct TarReader<'a> {
+    archive: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> TarReader<'a> {
+    pub fn new(archive: &'a [u8]) -> Self {
+        Self { archive, offset: 0 }
+    }
+
+    pub fn is_ustar(archive: &[u8]) -> bool {
+        archive.len() >= BLOCK_SIZE && &archive[257..262] == b"ustar"
+    }
+}
+
+impl<'a> Iterator for TarReader<'a> {
+    type Item = TarEntry<'a>;
+
+    fn next(&mut self) -> Option<TarEntry<'a>> {
+        loop {
+            let header = self.archive.get(self.offset..self.offset + BLOCK_SIZE)?;
+            // The archive ends with zero blocks
+            if header[0] == 0 || !Self::is_ustar(header) {
+                return None;
+            }
+
+            let size = parse_octal(&header[124..136])?;
+            let data_start = self.offset + BLOCK_SIZE;
+            let data = self.archive.get(data_start..data_start + size)?;
+            self.offset = data_start + (size + BLOCK_SIZE - 1) / BLOCK_SIZE * BLOCK_SIZE;
+
+            let kind = header[156];
+            // Skip links, devices and extended headers
+            if kind != b'0' && kind != 0 && kind != b'5' {
+                continue;
+            }
+
+            let path = trim_path(nul_terminated(&header[..100]));
+            if path.is_empty() {
+                continue;
+            }
+            // Long names keep their leading directories in the prefix field,
+            // which this simple reader does not join
+            if header[345] != 0 {
+                continue;
+            }
+            return Some(TarEntry { path, is_dir: kind == b'5', data });
+        }
+    }
+}
+
+fn nul_terminated(field: &[u8]) -> &[u8] {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    &field[..len]
+}
+
+fn trim_path(mut path: &[u8]) -> &[u8] {
+    while path.starts_with(b"./") {
+        path = &path[2..];
+    }
+    while path.first() == Some(&b'/') {
+        path = &path[1..];
+    }
+    while path.last() == Some(&b'/') {
+        path = &path[..path.len() - 1];
+    }
+    path
+}
+
+fn parse_octal(field: &[u8]) -> Option<usize> {
+    let mut value = 0usize;
+    for &b in field {
+        match b {
+            b'0'..=b'7' => value = value * 8 + (b - b'0') as usize,
+            b' ' | 0 => {
+                if value > 0 {
+                    break;
+                }
+            }
+            _ => return None,
+        }
+    }
+    Some(value)
+}
+
+/// Copy every ustar Multiboot module into the ramfs. Returns the number of
+/// files created; entries that don't fit are logged and skipped.
+pub fn load() -> usize {
+    let fs = get_filesystem();
+    let saved = fs.current_dir();
+    let mut loaded = 0;
+
+    for module in multiboot::modules() {
+        let archive = module.data();
+        if !TarReader::is_ustar(archive) {
+            log_warn!("initrd: module {} is not a ustar archive", core::str::from_utf8(module.cmdline).unwrap_or("?"));
+            continue;
+        }
+
+        for entry in TarReader::new(archive) {
+            match populate(&entry) {
+                Ok(()) if !entry.is_dir => loaded += 1,
+                Ok(()) => {}
+                Err(e) => log_warn!("initrd: {}: {}", core::str::from_utf8(entry.path).unwrap_or("?"), e),
+            }
+        }
+    }
+
+    fs.set_current_dir(saved);
+    loaded
+}
+
+/// Create `entry` relative to the root, making parent directories as needed
+fn populate(entry: &TarEntry) -> Result<(), &'static str> {
+    let fs = get_filesystem();
+    fs.set_current_dir(0);
+
+    let mut components = entry.path.split(|&b| b == b'/').filter(|c| !c.is_empty()).peekable();
+    while let Some(name) = components.next() {
+        let is_last = components.peek().is_none();
+        if is_last && !entry.is_dir {
+            return fs.write_file(name, entry.data);
+        }
+        if fs.change_directory(name).is_err() {
+            fs.create_directory(name)?;
+            fs.change_directory(name)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(block: &mut [u8], name: &[u8], size: usize, kind: u8) {
+        block[..name.len()].copy_from_slice(name);
+        let mut octal = [b'0'; 11];
+        let mut n = size;
+        for digit in octal.iter_mut().rev() {
+            *digit = b'0' + (n % 8) as u8;
+            n /= 8;
+        }
+        block[124..135].copy_from_slice(&octal);
+        block[156] = kind;
+        block[257..263].copy_from_slice(b"ustar\0");
+    }
+
+    #[test_case]
+    fn reads_directories_and_files() {
+        let mut archive = [0u8; BLOCK_SIZE * 5];
+        header(&mut archive[..BLOCK_SIZE], b"./etc/", 0, b'5');
+        header(&mut archive[BLOCK_SIZE..], b"./etc/motd", 5, b'0');
+        archive[BLOCK_SIZE * 2..BLOCK_SIZE * 2 + 5].copy_from_slice(b"hello");
+
+        let mut reader = TarReader::new(&archive);
+        let dir = reader.next().unwrap();
+        assert!(dir.is_dir && dir.path == b"etc");
+        let file = reader.next().unwrap();
+        assert!(!file.is_dir && file.path == b"etc/motd" && file.data == b"hello");
+        assert!(reader.next().is_none());
+    }
+}
@@ -0,0 +1,99 @@
+// Integrity checks for long-lived kernel structures, in debug builds. A
+// `Seal` holds a magic number for the kind of structure and a checksum of
+// the fields that matter. The owner updates it after every change and
+// verifies it before use, so memory scribbled over by something else
+// panics with the structure's name where it is next touched, instead of
+// being trusted. In release builds a seal is empty and checks do nothing.
+
+#[derive(Clone, Copy)]
+pub struct Seal {
+    #[cfg(debug_assertions)]
+    magic: u32,
+    #[cfg(debug_assertions)]
+    checksum: u32,
+}
+
+impl Seal {
+    #[cfg(debug_assertions)]
+    pub const fn new(magic: u32, checksum: u32) -> Self {
+        Self { magic, checksum }
+    }
+
+    #[cfg(not(debug_assertions))]
+    pub const fn new(_magic: u32, _checksum: u32) -> Self {
+        Self {}
+    }
+
+    pub const fn update(&mut self, _checksum: u32) {
+        #[cfg(debug_assertions)]
+        {
+            self.checksum = _checksum;
+        }
+    }
+
+    pub fn is_intact(&self, _magic: u32, _checksum: u32) -> bool {
+        #[cfg(debug_assertions)]
+        {
+            self.magic == _magic && self.checksum == _checksum
+        }
+        #[cfg(not(debug_assertions))]
+        {
+            true
+        }
+    }
+
+    /// Panic naming the structure, e.g. "file 3", if the seal doesn't match
+    pub fn verify(&self, magic: u32, checksum: u32, what: &str, index: usize) {
+        if !self.is_intact(magic, checksum) {
+            panic!("Integrity: {} {} was corrupted (magic or checksum mismatch)", what, index);
+        }
+    }
+}
+
+/// FNV-1a, enough to notice stray writes
+#[derive(Clone, Copy)]
+pub struct Checksum(u32);
+
+impl Checksum {
+    pub const fn new() -> Self {
+        Checksum(0x811C_9DC5)
+    }
+
+    pub const fn bytes(self, bytes: &[u8]) -> Self {
+        let mut hash = self.0;
+        let mut i = 0;
+        while i < bytes.len() {
+            hash = (hash ^ bytes[i] as u32).wrapping_mul(0x0100_0193);
+            i += 1;
+        }
+        Checksum(hash)
+    }
+
+    pub const fn word(self, value: usize) -> Self {
+        self.bytes(&(value as u32).to_le_bytes())
+    }
+
+    pub const fn finish(self) -> u32 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn seal_notices_changed_fields() {
+        const MAGIC: u32 = 0x7E57_5EA1;
+        let sum = |len: usize| Checksum::new().bytes(b"name").word(len).finish();
+        let mut seal = Seal::new(MAGIC, sum(4));
+        assert!(seal.is_intact(MAGIC, sum(4)));
+        assert_ne!(sum(4), sum(5));
+        if cfg!(debug_assertions) {
+            assert!(!seal.is_intact(MAGIC, sum(5)));
+            assert!(!seal.is_intact(MAGIC ^ 1, sum(4)));
+        }
+        seal.update(sum(5));
+        assert!(seal.is_intact(MAGIC, sum(5)));
+    }
+}
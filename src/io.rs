@@ -21,3 +21,47 @@ pub unsafe fn inb(port: u16) -> u8 {
     );
     value
 }
+
+/// Writes a word to the specified hardware port
+pub unsafe fn outw(port: u16, value: u16) {
+    asm!(
+        "out dx, ax",
+        in("dx") port,
+        in("ax") value,
+        options(nomem, nostack, preserves_flags)
+    );
+}
+
+/// Reads a word from the specified hardware port
+pub unsafe fn inw(port: u16) -> u16 {
+    let value: u16;
+    asm!(
+        "in ax, dx",
+        out("ax") value,
+        in("dx") port,
+        options(nomem, nostack, preserves_flags)
+    );
+    value
+}
+
+/// Writes a doubleword to the specified hardware port
+pub unsafe fn outl(port: u16, value: u32) {
+    asm!(
+        "out dx, eax",
+        in("dx") port,
+        in("eax") value,
+        options(nomem, nostack, preserves_flags)
+    );
+}
+
+/// Reads a doubleword from the specified hardware port
+pub unsafe fn inl(port: u16) -> u32 {
+    let value: u32;
+    asm!(
+        "in eax, dx",
+        out("eax") value,
+        in("dx") port,
+        options(nomem, nostack, preserves_flags)
+    );
+    value
+}
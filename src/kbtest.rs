@@ -0,0 +1,293 @@
+use crate::hotkey;
+use crate::idt;
+use crate::keyboard::{self, Keyboard};
+use crate::signal;
+use crate::sink::{Capture, Sink};
+use crate::theme;
+use crate::vga_colors::{color_code, Color};
+use crate::writer::Writer;
+
+// `kbtest`: every key press and release as it comes out of the keyboard
+// buffer, for checking layouts. Each line has the raw scancode bytes, the
+// key code hotkeys use (extended keys carry 0xE000), the key's name, the
+// modifiers held and the character the current layout gives, dead keys
+// included. Escape pressed twice in a row or Ctrl+C leaves; Ctrl+C and
+// registered hotkeys are taken in the interrupt handler and never show.
+
+const WIDTH: usize = 80;
+const HEIGHT: usize = 25;
+const HEADING_ROW: usize = 2;
+const LOG_TOP: usize = 3;
+const LOG_ROWS: usize = HEIGHT - LOG_TOP - 2;
+const STATUS_ROW: usize = HEIGHT - 1;
+
+/// Columns of the log
+const KEY_COL: usize = 10;
+const NAME_COL: usize = 16;
+const EVENT_COL: usize = 29;
+const MODS_COL: usize = 38;
+const CHAR_COL: usize = 55;
+
+const EXTENDED_PREFIX: u8 = 0xE0;
+/// Starts the Pause key's sequence, E1 1D 45 then E1 9D C5, with no break
+const PAUSE_PREFIX: u8 = 0xE1;
+const PAUSE: u16 = 0xE11D;
+const ESCAPE: u16 = 0x01;
+
+/// Names of keys without a character of their own
+const KEY_NAMES: [(u16, &str); 56] = [
+    (0x01, "Esc"), (0x0E, "Backspace"), (0x0F, "Tab"), (0x1C, "Enter"), (0x1D, "Left Ctrl"),
+    (0x2A, "Left Shift"), (0x36, "Right Shift"), (0x37, "Keypad *"), (0x38, "Left Alt"),
+    (0x39, "Space"), (0x3A, "Caps Lock"), (0x3B, "F1"), (0x3C, "F2"), (0x3D, "F3"), (0x3E, "F4"),
+    (0x3F, "F5"), (0x40, "F6"), (0x41, "F7"), (0x42, "F8"), (0x43, "F9"), (0x44, "F10"),
+    (0x45, "Num Lock"), (0x46, "Scroll Lock"), (0x47, "Keypad 7"), (0x48, "Keypad 8"),
+    (0x49, "Keypad 9"), (0x4A, "Keypad -"), (0x4B, "Keypad 4"), (0x4C, "Keypad 5"),
+    (0x4D, "Keypad 6"), (0x4E, "Keypad +"), (0x4F, "Keypad 1"), (0x50, "Keypad 2"),
+    (0x51, "Keypad 3"), (0x52, "Keypad 0"), (0x53, "Keypad ."), (0x57, "F11"), (0x58, "F12"),
+    (0xE01C, "Keypad Enter"), (0xE01D, "Right Ctrl"), (0xE02A, "Fake Shift"), (0xE035, "Keypad /"),
+    (0xE036, "Fake Shift"), (0xE037, "Print Screen"), (0xE038, "AltGr"), (0xE047, "Home"),
+    (0xE048, "Up"), (0xE049, "Page Up"), (0xE04B, "Left"), (0xE04D, "Right"), (0xE04F, "End"),
+    (0xE050, "Down"), (0xE051, "Page Down"), (0xE052, "Insert"), (0xE053, "Delete"), (PAUSE, "Pause"),
+];
+
+fn key_name(key: u16) -> Option<&'static str> {
+    KEY_NAMES.iter().find(|&&(code, _)| code == key).map(|&(_, name)| name)
+}
+
+/// One press or release, with the bytes it came in
+#[derive(Debug, Clone, Copy, Default)]
+struct Event {
+    raw: [u8; 3],
+    raw_len: usize,
+    key: u16,
+    released: bool,
+    modifiers: u8,
+    character: Option<u8>,
+}
+
+/// Puts scancode bytes back together into keys
+#[derive(Default)]
+struct Decoder {
+    raw: [u8; 3],
+    len: usize,
+}
+
+impl Decoder {
+    /// Take one byte; returns the event once its last byte is in
+    fn feed(&mut self, scancode: u8) -> Option<Event> {
+        self.raw[self.len] = scancode;
+        self.len += 1;
+
+        let key = match self.raw[0] {
+            EXTENDED_PREFIX if self.len < 2 => return None,
+            EXTENDED_PREFIX => hotkey::EXTENDED | (scancode & 0x7F) as u16,
+            PAUSE_PREFIX if self.len < 3 => return None,
+            PAUSE_PREFIX => PAUSE,
+            _ => (scancode & 0x7F) as u16,
+        };
+        let event = Event {
+            raw: self.raw,
+            raw_len: self.len,
+            key,
+            released: scancode & 0x80 != 0,
+            modifiers: 0,
+            character: None,
+        };
+        self.len = 0;
+        Some(event)
+    }
+}
+
+/// A log line for `event` into `out`, in code page 437; returns its length
+fn format_event(event: &Event, out: &mut [u8]) -> usize {
+    let mut line = Capture::new(out);
+    for byte in &event.raw[..event.raw_len] {
+        write!(line, "{:02X} ", byte);
+    }
+    line.pad_to(KEY_COL);
+    write!(line, "{:04X}", event.key);
+    line.pad_to(NAME_COL);
+    match (key_name(event.key), event.character) {
+        (Some(name), _) => line.write_str(name),
+        (None, Some(_)) => line.write_str("Character"),
+        (None, None) => line.write_str("?"),
+    }
+    line.pad_to(EVENT_COL);
+    line.write_str(if event.released { "release" } else { "press" });
+    line.pad_to(MODS_COL);
+    for &(bit, name) in &[(hotkey::CTRL, "Ctrl "), (hotkey::ALT, "Alt "), (hotkey::SHIFT, "Shift")] {
+        if event.modifiers & bit != 0 {
+            line.write_str(name);
+        }
+    }
+    if let Some(byte) = event.character {
+        line.pad_to(CHAR_COL);
+        line.write_byte(b'\'');
+        line.write_byte(byte);
+        write!(line, "' {:02X}", byte);
+    }
+    line.as_bytes().len()
+}
+
+pub struct KeyboardTest {
+    lines: [[u8; WIDTH]; LOG_ROWS],
+    lengths: [usize; LOG_ROWS],
+    released: [bool; LOG_ROWS],
+    /// Lines logged so far; the newest is at `count % LOG_ROWS`
+    count: usize,
+    heading: u8,
+}
+
+impl KeyboardTest {
+    pub fn new() -> Self {
+        Self {
+            lines: [[0; WIDTH]; LOG_ROWS],
+            lengths: [0; LOG_ROWS],
+            released: [false; LOG_ROWS],
+            count: 0,
+            heading: color_code(theme::palette().heading, Color::Black),
+        }
+    }
+
+    /// Log keys until Escape is pressed twice or Ctrl+C
+    pub fn run(&mut self, writer: &mut Writer) {
+        idt::flush_buffer();
+        signal::take_interrupt();
+        writer.set_color(Color::White, Color::Black);
+        writer.clear();
+        writer.disable_cursor();
+        self.draw_frame();
+
+        let mut decoder = Decoder::default();
+        let mut last_press = 0;
+        let mut shown_modifiers = None;
+        loop {
+            if signal::take_interrupt() {
+                break;
+            }
+            if let Some(scancode) = idt::get_scancode() {
+                if let Some(mut event) = decoder.feed(scancode) {
+                    event.modifiers = hotkey::modifiers();
+                    if !event.released && event.key & hotkey::EXTENDED == 0 {
+                        let shift = event.modifiers & hotkey::SHIFT != 0;
+                        event.character = Keyboard::scancode_to_byte(event.key as u8, shift);
+                    }
+                    self.log(&event);
+                    if !event.released {
+                        if event.key == ESCAPE && last_press == ESCAPE {
+                            break;
+                        }
+                        last_press = event.key;
+                    }
+                }
+                continue;
+            }
+            let modifiers = hotkey::modifiers();
+            if shown_modifiers != Some(modifiers) {
+                self.draw_status(modifiers);
+                shown_modifiers = Some(modifiers);
+            }
+            idt::wait_for_interrupt();
+        }
+
+        writer.enable_cursor();
+        writer.set_color(Color::White, Color::Black);
+        writer.clear();
+    }
+
+    fn draw_frame(&self) {
+        let mut title = [0; WIDTH];
+        let len = {
+            let mut text = Capture::new(&mut title);
+            write!(text, "Keyboard test - layout {} - Esc twice or Ctrl+C exits", keyboard::layout().name);
+            text.as_bytes().len()
+        };
+        draw_text(0, &title[..len], self.heading);
+
+        let mut heading = [0; WIDTH];
+        let len = {
+            let mut text = Capture::new(&mut heading);
+            text.write_str("Raw");
+            text.pad_to(KEY_COL);
+            text.write_str("Key");
+            text.pad_to(NAME_COL);
+            text.write_str("Name");
+            text.pad_to(EVENT_COL);
+            text.write_str("Event");
+            text.pad_to(MODS_COL);
+            text.write_str("Modifiers");
+            text.pad_to(CHAR_COL);
+            text.write_str("Character");
+            text.as_bytes().len()
+        };
+        draw_text(HEADING_ROW, &heading[..len], self.heading);
+    }
+
+    fn draw_status(&self, modifiers: u8) {
+        let mut status = [0; WIDTH];
+        let len = {
+            let mut text = Capture::new(&mut status);
+            text.write_str("Held:");
+            for &(bit, name) in &[(hotkey::CTRL, " Ctrl"), (hotkey::ALT, " Alt"), (hotkey::SHIFT, " Shift")] {
+                if modifiers & bit != 0 {
+                    text.write_str(name);
+                }
+            }
+            if modifiers == 0 {
+                text.write_str(" nothing");
+            }
+            text.as_bytes().len()
+        };
+        draw_text(STATUS_ROW, &status[..len], color_code(Color::LightGray, Color::Black));
+    }
+
+    /// Add `event` at the bottom of the log, scrolling the rest up
+    fn log(&mut self, event: &Event) {
+        let slot = self.count % LOG_ROWS;
+        self.lengths[slot] = format_event(event, &mut self.lines[slot]);
+        self.released[slot] = event.released;
+        self.count += 1;
+
+        let shown = self.count.min(LOG_ROWS);
+        for row in LOG_ROWS - shown..LOG_ROWS {
+            let slot = (self.count + row) % LOG_ROWS;
+            let text = &self.lines[slot][..self.lengths[slot]];
+            let color = if self.released[slot] { Color::DarkGray } else { Color::White };
+            draw_text(LOG_TOP + row, text, color_code(color, Color::Black));
+        }
+    }
+}
+
+/// Fill screen row `row` with `text` and blanks after it
+fn draw_text(row: usize, text: &[u8], attribute: u8) {
+    for col in 0..WIDTH {
+        let byte = text.get(col).copied().unwrap_or(b' ');
+        Writer::set_cell(col, row, (attribute as u16) << 8 | byte as u16);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn decodes_extended_and_pause_sequences() {
+        let mut decoder = Decoder::default();
+        let press = decoder.feed(0x1E).unwrap();
+        assert_eq!((press.key, press.released, press.raw_len), (0x1E, false, 1));
+
+        assert!(decoder.feed(0xE0).is_none());
+        let release = decoder.feed(0xC8).unwrap();
+        assert_eq!((release.key, release.released), (0xE048, true));
+        assert_eq!(key_name(release.key), Some("Up"));
+
+        assert!(decoder.feed(0xE1).is_none());
+        assert!(decoder.feed(0x1D).is_none());
+        assert_eq!(decoder.feed(0x45).unwrap().key, PAUSE);
+
+        let mut out = [0; WIDTH];
+        let event = Event { modifiers: hotkey::SHIFT, character: Some(b'A'), ..press };
+        let len = format_event(&event, &mut out);
+        assert_eq!(&out[..len], &b"1E        001E  Character    press    Shift            'A' 41"[..]);
+    }
+}
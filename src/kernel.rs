@@ -1,44 +1,234 @@
 #![no_std]
 #![no_main]
 #![feature(lang_items)]
+#![cfg_attr(test, feature(custom_test_frameworks))]
+#![cfg_attr(test, test_runner(crate::testing::test_runner))]
+#![cfg_attr(test, reexport_test_harness_main = "test_main")]
+
+extern crate alloc;
 
 use core::panic::PanicInfo;
 
+#[macro_use]
+mod serial;
+mod serial_terminal;
+#[macro_use]
+mod log;
+#[macro_use]
+mod console;
+mod config;
+mod sha256;
+mod rng;
+mod fmt;
+mod buildinfo;
+mod theme;
+mod multiboot;
+mod initrd;
 mod vga_colors;
 mod writer;
+mod ansi;
+mod sink;
 mod keyboard;
+mod hotkey;
+mod signal;
+mod screenshot;
+mod recorder;
 mod cli;
+mod script;
+mod env;
+mod glob;
+mod text;
+mod prompt;
+mod history;
+mod users;
+mod chart;
 mod intrinsics;
+mod clock;
+mod rtc;
+mod at;
+mod watchdog;
+mod tsc;
 mod idt;
+mod gdt;
+mod usermode;
+mod syscall;
+mod handle;
+mod caps;
+mod elf;
+mod faulttest;
 mod snake;
+mod tetris;
+mod life;
+mod matrix;
+mod digital_clock;
+mod kbtest;
+mod lock;
+mod monitor;
+mod mandelbrot;
 mod video_player;
 mod bad_apple_data;
 mod RAHH_data;
 mod filesystem;
+mod integrity;
 mod editor;
+mod pager;
+mod file_manager;
 
 mod hex_fetch;
+mod acpi;
+mod aml;
+mod apic;
+mod driver;
+mod sci;
+mod suspend;
+mod smp;
+mod latency;
+mod metrics;
+mod heap;
+mod sync;
+#[cfg(debug_assertions)]
+mod lockdep;
+mod cpu;
+mod idle;
+mod power;
+mod crash;
+mod stackguard;
+mod qemu;
+mod speaker;
+mod mouse;
+mod pci;
+mod netdev;
+mod e1000;
+mod net;
+mod tcp;
+mod http;
+mod httpd;
+mod dhcp;
+mod report;
+mod hwprobe;
+mod framebuffer;
+mod font;
+mod image;
+#[cfg(test)]
+mod testing;
 
 mod graphics;
 
 pub mod io;
 
-use vga_colors::{Color, color_code};
-use writer::Writer;
+use vga_colors::Color;
 use cli::CLI;
 
+#[cfg(not(test))]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    crash::panic(info)
+}
+
+#[cfg(test)]
 #[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
-    loop {}
+fn panic(info: &PanicInfo) -> ! {
+    testing::test_panic(info)
+}
+
+extern "C" {
+    static __kernel_start: u8;
+    static __kernel_end: u8;
+}
+
+/// Bytes spanned by the loaded kernel image, including .bss
+pub fn kernel_image_size() -> usize {
+    unsafe { &__kernel_end as *const u8 as usize - &__kernel_start as *const u8 as usize }
 }
 
 #[no_mangle]
-pub extern "C" fn kernel_main() -> ! {
-    let mut writer = Writer::new(color_code(Color::White, Color::Black));
+pub extern "C" fn kernel_main(magic: u32, multiboot_info: u32) -> ! {
+    stackguard::init();
+    let boot_ok = multiboot::init(magic, multiboot_info);
+    config::init(multiboot::command_line().unwrap_or(b""));
+
+    let mut writer = console::lock();
     writer.clear();
     writer.enable_cursor();
 
+    // Mouse setup polls for ACKs, so it runs before interrupts are enabled
+    mouse::init();
+    gdt::init();
     idt::init();
+    usermode::init();
+    hotkey::init();
+    screenshot::init();
+    serial::init();
+    if let Err(e) = boot_ok {
+        log_warn!("{}", e);
+    }
+    log_debug!("Command line: {}", core::str::from_utf8(config::get().command_line()).unwrap_or("?"));
+    if let Err(e) = watchdog::init() {
+        log_warn!("Watchdog: {}", e);
+    }
+    match tsc::init() {
+        Ok(()) => log_info!("TSC: {} MHz{}", tsc::frequency_hz() / 1_000_000, if tsc::is_invariant() { ", invariant" } else { "" }),
+        Err(e) => log_info!("TSC: {}, timing with the PIT", e),
+    }
+    log_info!("RNG: {}", if rng::init() { "RDRAND" } else { "no RDRAND, SHA-256 seeded from TSC and keyboard timing" });
+    match acpi::init() {
+        Ok(()) => log_info!("ACPI: {} CPU(s)", acpi::get_info().cpu_count()),
+        Err(e) => log_warn!("ACPI: {}", e),
+    }
+    match aml::init() {
+        Ok(count) => {
+            log_info!("AML: {} device(s)", count);
+            if let Some(kbc) = aml::ps2_controller() {
+                log_info!("AML: PS/2 controller at {:#x}", kbc.io_base().unwrap_or(0));
+            }
+            for port in aml::serial_ports() {
+                log_info!("AML: serial port at {:#x}, IRQ {}", port.io_base().unwrap_or(0), port.irq().unwrap_or(0));
+            }
+        }
+        Err(e) => log_warn!("AML: {}", e),
+    }
+    if config::get().apic {
+        let source = if config::get().apic_timer {
+            apic::TickSource::ApicTimer
+        } else {
+            apic::TickSource::Pit
+        };
+        match apic::init(source) {
+            Ok(()) => log_info!("APIC: enabled, local APIC id {}", apic::local_id()),
+            Err(e) => log_warn!("APIC: {}, staying on the 8259 PIC", e),
+        }
+    }
+    if let Err(e) = driver::init() {
+        log_warn!("Drivers: {}", e);
+    }
+    match sci::init() {
+        Ok(irq) => log_info!("ACPI: power button events on IRQ {}", irq),
+        Err(e) => log_warn!("ACPI events: {}", e),
+    }
+    match e1000::init() {
+        Ok(irq) => {
+            log_info!("Network: e1000 on IRQ {}", irq);
+            if config::get().dhcp {
+                match dhcp::acquire() {
+                    Ok(lease) => log_info!("DHCP: {} via {}, lease {}s", lease.address, lease.gateway, lease.duration_secs),
+                    Err(e) => log_warn!("DHCP: {}, using {}", e, net::config().address),
+                }
+            }
+        }
+        Err(e) => log_info!("Network: {}", e),
+    }
+    match smp::init() {
+        Ok(online) => log_info!("SMP: {} of {} CPU(s) online", online, smp::cpus().len()),
+        Err(e) => log_info!("SMP: {}, running on one CPU", e),
+    }
+    log_info!("Idle: {} state(s)", idle::init());
+    match suspend::init() {
+        Ok(()) => log_info!("Suspend: S3 available"),
+        Err(e) => log_info!("Suspend: {}, no S3", e),
+    }
+
+    #[cfg(test)]
+    test_main();
 
     writer.set_color(Color::LightCyan, Color::Black);
     writer.write_str("██╗  ██╗██╗   ██╗███████╗███████╗ ██████╗ ███████╗\n");
@@ -55,8 +245,27 @@ pub extern "C" fn kernel_main() -> ! {
     writer.set_color(Color::White, Color::Black);
     writer.write_str("Type 'help' for available commands.\n\n");
 
-    filesystem::get_filesystem().init();
+    let fs = filesystem::get_filesystem();
+    fs.init();
+    let initrd_files = initrd::load();
+    if initrd_files > 0 {
+        log_info!("initrd: loaded {} file(s)", initrd_files);
+    }
+    if let Some(motd) = fs.read_file(b"motd") {
+        writer.write_bytes(motd);
+        writer.write_byte(b'\n');
+    }
+
+    if config::get().chime {
+        speaker::startup_chime();
+    }
 
+    env::init();
+    users::init();
+    log_debug!("History: {} command(s) loaded", history::load());
     let mut cli = CLI::new();
+    cli.run_boot_script(&mut writer);
+    users::login(&mut writer);
+    cli.run_profile(&mut writer);
     cli.run(&mut writer);
 }
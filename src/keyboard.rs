@@ -1,8 +1,192 @@
+use crate::hotkey;
+
 const KEYBOARD_DATA_PORT: u16 = 0x60;
 const KEYBOARD_STATUS_PORT: u16 = 0x64;
 
 pub struct Keyboard;
 
+/// Scancodes (set 1) covered by the layout tables; 0x56 is the extra key
+/// next to left shift on ISO keyboards
+const KEYS: usize = 0x57;
+const SPACE: usize = 0x39;
+
+const EXTENDED_PREFIX: u8 = 0xE0;
+const ALT: u8 = 0x38;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Accent {
+    Acute,
+    Grave,
+    Circumflex,
+    Diaeresis,
+}
+
+impl Accent {
+    /// The accent typed on its own, by pressing space after the dead key
+    fn spacing(self) -> u8 {
+        match self {
+            Accent::Acute => b'\'',
+            Accent::Grave => b'`',
+            Accent::Circumflex => b'^',
+            Accent::Diaeresis => b'"',
+        }
+    }
+
+    /// Accented form of `base` in code page 437, if it has one
+    fn compose(self, base: u8) -> Option<u8> {
+        let table: &[(u8, u8)] = match self {
+            Accent::Acute => &[(b'a', 0xA0), (b'e', 0x82), (b'i', 0xA1), (b'o', 0xA2), (b'u', 0xA3), (b'E', 0x90)],
+            Accent::Grave => &[(b'a', 0x85), (b'e', 0x8A), (b'i', 0x8D), (b'o', 0x95), (b'u', 0x97)],
+            Accent::Circumflex => &[(b'a', 0x83), (b'e', 0x88), (b'i', 0x8C), (b'o', 0x93), (b'u', 0x96)],
+            Accent::Diaeresis => &[
+                (b'a', 0x84), (b'e', 0x89), (b'i', 0x8B), (b'o', 0x94), (b'u', 0x81), (b'y', 0x98),
+                (b'A', 0x8E), (b'O', 0x99), (b'U', 0x9A),
+            ],
+        };
+        if base == b' ' {
+            return Some(self.spacing());
+        }
+        table.iter().find(|&&(from, _)| from == base).map(|&(_, to)| to)
+    }
+}
+
+/// What one key press types: nothing, a character, or the accent of a dead
+/// key that doesn't combine with the character followed by the character
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Typed {
+    accent: Option<u8>,
+    byte: Option<u8>,
+}
+
+impl Iterator for Typed {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        self.accent.take().or_else(|| self.byte.take())
+    }
+}
+
+/// Characters produced by each key, as code page 437 bytes to match the
+/// VGA font. Zero means the key produces nothing.
+pub struct Layout {
+    pub name: &'static str,
+    normal: [u8; KEYS],
+    shift: [u8; KEYS],
+    /// Right Alt combinations: scancode, character
+    altgr: &'static [(u8, u8)],
+    /// Dead keys: scancode, with shift, accent
+    dead: &'static [(u8, bool, Accent)],
+}
+
+/// Build a table from runs of consecutive keys starting at a scancode
+const fn keymap(runs: &[(usize, &[u8])]) -> [u8; KEYS] {
+    let mut map = [0u8; KEYS];
+    let mut r = 0;
+    while r < runs.len() {
+        let (start, keys) = runs[r];
+        let mut i = 0;
+        while i < keys.len() {
+            map[start + i] = keys[i];
+            i += 1;
+        }
+        r += 1;
+    }
+    map[SPACE] = b' ';
+    map
+}
+
+pub const US: Layout = Layout {
+    name: "us",
+    normal: keymap(&[(0x02, b"1234567890-="), (0x10, b"qwertyuiop[]"), (0x1E, b"asdfghjkl;'`"), (0x2B, b"\\zxcvbnm,./"), (0x56, b"\\")]),
+    shift: keymap(&[(0x02, b"!@#$%^&*()_+"), (0x10, b"QWERTYUIOP{}"), (0x1E, b"ASDFGHJKL:\"~"), (0x2B, b"|ZXCVBNM<>?"), (0x56, b"|")]),
+    altgr: &[],
+    dead: &[],
+};
+
+pub const UK: Layout = Layout {
+    name: "uk",
+    normal: keymap(&[(0x02, b"1234567890-="), (0x10, b"qwertyuiop[]"), (0x1E, b"asdfghjkl;'`"), (0x2B, b"#zxcvbnm,./"), (0x56, b"\\")]),
+    // \x9C is the pound sign, \xAA the not sign
+    shift: keymap(&[(0x02, b"!\"\x9C$%^&*()_+"), (0x10, b"QWERTYUIOP{}"), (0x1E, b"ASDFGHJKL:@\xAA"), (0x2B, b"~ZXCVBNM<>?"), (0x56, b"|")]),
+    altgr: &[],
+    dead: &[],
+};
+
+pub const DE: Layout = Layout {
+    name: "de",
+    normal: keymap(&[(0x02, b"1234567890\xE1"), (0x10, b"qwertzuiop\x81+"), (0x1E, b"asdfghjkl\x94\x84"), (0x2B, b"#yxcvbnm,.-"), (0x56, b"<")]),
+    shift: keymap(&[(0x02, b"!\"\x15$%&/()=?"), (0x10, b"QWERTZUIOP\x9A*"), (0x1E, b"ASDFGHJKL\x99\x8E\xF8"), (0x2B, b"'YXCVBNM;:_"), (0x56, b">")]),
+    altgr: &[
+        (0x03, 0xFD), (0x08, b'{'), (0x09, b'['), (0x0A, b']'), (0x0B, b'}'), (0x0C, b'\\'),
+        (0x10, b'@'), (0x1B, b'~'), (0x32, 0xE6), (0x56, b'|'),
+    ],
+    dead: &[(0x0D, false, Accent::Acute), (0x0D, true, Accent::Grave), (0x29, false, Accent::Circumflex)],
+};
+
+pub const FR: Layout = Layout {
+    name: "fr",
+    normal: keymap(&[(0x02, b"&\x82\"'(-\x8A_\x87\x85)="), (0x10, b"azertyuiop"), (0x1B, b"$"), (0x1E, b"qsdfghjklm\x97\xFD"), (0x2B, b"*wxcvbn,;:!"), (0x56, b"<")]),
+    shift: keymap(&[(0x02, b"1234567890\xF8+"), (0x10, b"AZERTYUIOP"), (0x1B, b"\x9C"), (0x1E, b"QSDFGHJKLM%"), (0x2B, b"\xE6WXCVBN?./\x15"), (0x56, b">")]),
+    altgr: &[
+        (0x03, b'~'), (0x04, b'#'), (0x05, b'{'), (0x06, b'['), (0x07, b'|'), (0x08, b'`'),
+        (0x09, b'\\'), (0x0A, b'^'), (0x0B, b'@'), (0x0C, b']'), (0x0D, b'}'),
+    ],
+    dead: &[(0x1A, false, Accent::Circumflex), (0x1A, true, Accent::Diaeresis)],
+};
+
+pub const LAYOUTS: [&Layout; 4] = [&US, &UK, &DE, &FR];
+
+static mut LAYOUT: &Layout = &US;
+static mut PENDING_ACCENT: Option<Accent> = None;
+static mut EXTENDED: bool = false;
+static mut ALTGR: bool = false;
+
+pub fn find_layout(name: &[u8]) -> Option<&'static Layout> {
+    LAYOUTS.iter().copied().find(|layout| layout.name.as_bytes() == name)
+}
+
+pub fn layout() -> &'static Layout {
+    unsafe { LAYOUT }
+}
+
+pub fn set_layout(layout: &'static Layout) {
+    unsafe {
+        LAYOUT = layout;
+        PENDING_ACCENT = None;
+    }
+}
+
+/// Track modifiers the table lookup can't see from a lone scancode: right
+/// Alt (AltGr) only differs from left Alt by its 0xE0 prefix, which
+/// consumers skip. Called for every scancode taken from the buffer.
+pub fn observe(scancode: u8) {
+    unsafe {
+        if scancode == EXTENDED_PREFIX {
+            EXTENDED = true;
+            return;
+        }
+        if EXTENDED {
+            match scancode {
+                ALT => ALTGR = true,
+                s if s == ALT | 0x80 => ALTGR = false,
+                _ => {}
+            }
+        }
+        EXTENDED = false;
+    }
+}
+
+/// The character a key press gives in the current layout, with Shift as
+/// held now; for answering prompts whichever layout is loaded
+pub fn answer(scancode: u8) -> Option<u8> {
+    Keyboard::scancode_to_byte(scancode, hotkey::modifiers() & hotkey::SHIFT != 0)
+}
+
+/// Whether a key press is Y in the current layout, for y/n prompts
+pub fn is_yes(scancode: u8) -> bool {
+    matches!(answer(scancode), Some(b'y') | Some(b'Y'))
+}
+
 impl Keyboard {
     pub fn wait_and_read_scancode() -> u8 {
         unsafe {
@@ -30,57 +214,76 @@ impl Keyboard {
         }
     }
 
-    pub fn scancode_to_char(scancode: u8, shift: bool) -> Option<char> {
-        match scancode {
-            0x1E => Some(if shift { 'A' } else { 'a' }),
-            0x30 => Some(if shift { 'B' } else { 'b' }),
-            0x2E => Some(if shift { 'C' } else { 'c' }),
-            0x20 => Some(if shift { 'D' } else { 'd' }),
-            0x12 => Some(if shift { 'E' } else { 'e' }),
-            0x21 => Some(if shift { 'F' } else { 'f' }),
-            0x22 => Some(if shift { 'G' } else { 'g' }),
-            0x23 => Some(if shift { 'H' } else { 'h' }),
-            0x17 => Some(if shift { 'I' } else { 'i' }),
-            0x24 => Some(if shift { 'J' } else { 'j' }),
-            0x25 => Some(if shift { 'K' } else { 'k' }),
-            0x26 => Some(if shift { 'L' } else { 'l' }),
-            0x32 => Some(if shift { 'M' } else { 'm' }),
-            0x31 => Some(if shift { 'N' } else { 'n' }),
-            0x18 => Some(if shift { 'O' } else { 'o' }),
-            0x19 => Some(if shift { 'P' } else { 'p' }),
-            0x10 => Some(if shift { 'Q' } else { 'q' }),
-            0x13 => Some(if shift { 'R' } else { 'r' }),
-            0x1F => Some(if shift { 'S' } else { 's' }),
-            0x14 => Some(if shift { 'T' } else { 't' }),
-            0x16 => Some(if shift { 'U' } else { 'u' }),
-            0x2F => Some(if shift { 'V' } else { 'v' }),
-            0x11 => Some(if shift { 'W' } else { 'w' }),
-            0x2D => Some(if shift { 'X' } else { 'x' }),
-            0x15 => Some(if shift { 'Y' } else { 'y' }),
-            0x2C => Some(if shift { 'Z' } else { 'z' }),
-            0x02 => Some(if shift { '!' } else { '1' }),
-            0x03 => Some(if shift { '@' } else { '2' }),
-            0x04 => Some(if shift { '#' } else { '3' }),
-            0x05 => Some(if shift { '$' } else { '4' }),
-            0x06 => Some(if shift { '%' } else { '5' }),
-            0x07 => Some(if shift { '^' } else { '6' }),
-            0x08 => Some(if shift { '&' } else { '7' }),
-            0x09 => Some(if shift { '*' } else { '8' }),
-            0x0A => Some(if shift { '(' } else { '9' }),
-            0x0B => Some(if shift { ')' } else { '0' }),
-            0x39 => Some(' '),
-            0x0C => Some(if shift { '_' } else { '-' }),
-            0x0D => Some(if shift { '+' } else { '=' }),
-            0x1A => Some(if shift { '{' } else { '[' }),
-            0x1B => Some(if shift { '}' } else { ']' }),
-            0x27 => Some(if shift { ':' } else { ';' }),
-            0x28 => Some(if shift { '"' } else { '\'' }),
-            0x33 => Some(if shift { '<' } else { ',' }),
-            0x34 => Some(if shift { '>' } else { '.' }),
-            0x35 => Some(if shift { '?' } else { '/' }),
-            0x29 => Some(if shift { '~' } else { '`' }),
-            0x2B => Some(if shift { '|' } else { '\\' }),
-            _ => None,
+    /// Characters for a key press in the current layout, as code page 437
+    /// bytes. A dead key produces nothing; it changes the next character,
+    /// or comes out before it when the two don't combine.
+    pub fn scancode_to_bytes(scancode: u8, shift: bool) -> Typed {
+        let index = scancode as usize;
+        if index >= KEYS {
+            return Typed::default();
         }
+        let layout = layout();
+        unsafe {
+            if ALTGR {
+                let byte = layout.altgr.iter().find(|&&(key, _)| key == scancode).map(|&(_, byte)| byte);
+                return Typed { accent: None, byte };
+            }
+
+            let dead = layout.dead.iter().find(|&&(key, shifted, _)| key == scancode && shifted == shift);
+            if let Some(&(_, _, accent)) = dead {
+                // Pressing the dead key twice types the accent itself
+                if PENDING_ACCENT.take() == Some(accent) {
+                    return Typed { accent: None, byte: Some(accent.spacing()) };
+                }
+                PENDING_ACCENT = Some(accent);
+                return Typed::default();
+            }
+
+            let byte = if shift { layout.shift[index] } else { layout.normal[index] };
+            if byte == 0 {
+                return Typed::default();
+            }
+            match PENDING_ACCENT.take() {
+                Some(accent) => match accent.compose(byte) {
+                    Some(composed) => Typed { accent: None, byte: Some(composed) },
+                    None => Typed { accent: Some(accent.spacing()), byte: Some(byte) },
+                },
+                None => Typed { accent: None, byte: Some(byte) },
+            }
+        }
+    }
+
+    /// The character a key press gives, for keys used as commands: an
+    /// accent that doesn't combine with it is left out. Text input wants
+    /// `scancode_to_bytes`.
+    pub fn scancode_to_byte(scancode: u8, shift: bool) -> Option<u8> {
+        Self::scancode_to_bytes(scancode, shift).last()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn dead_keys_compose_accents() {
+        set_layout(&DE);
+        // ^ then e
+        assert_eq!(Keyboard::scancode_to_byte(0x29, false), None);
+        assert_eq!(Keyboard::scancode_to_byte(0x12, false), Some(0x88));
+        // z and y are swapped on QWERTZ
+        assert_eq!(Keyboard::scancode_to_byte(0x15, false), Some(b'z'));
+        // ^ then space
+        assert_eq!(Keyboard::scancode_to_byte(0x29, false), None);
+        assert_eq!(Keyboard::scancode_to_byte(0x39, false), Some(b'^'));
+        // ^ then q, which takes no accent, types both
+        assert_eq!(Keyboard::scancode_to_byte(0x29, false), None);
+        let mut typed = Keyboard::scancode_to_bytes(0x10, false);
+        assert_eq!((typed.next(), typed.next(), typed.next()), (Some(b'^'), Some(b'q'), None));
+        // y/n prompts follow the layout
+        assert!(is_yes(0x2C) && !is_yes(0x15));
+        set_layout(&US);
+        assert_eq!(Keyboard::scancode_to_byte(0x15, false), Some(b'y'));
+        assert!(is_yes(0x15) && !is_yes(0x2C));
     }
 }
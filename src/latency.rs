@@ -0,0 +1,95 @@
+use crate::apic::{self, TickSource};
+use crate::idt;
+
+/// Upper bounds of the histogram buckets in microseconds; the last bucket
+/// takes everything slower
+pub const BUCKET_LIMITS_US: [u32; 7] = [5, 10, 20, 50, 100, 200, 500];
+pub const BUCKETS: usize = BUCKET_LIMITS_US.len() + 1;
+
+/// Timer interrupt latencies, measured from the PIT deadline to the handler
+#[derive(Clone, Copy)]
+pub struct Stats {
+    pub samples: u32,
+    pub min_ns: u32,
+    pub max_ns: u32,
+    pub total_ns: u64,
+    pub buckets: [u32; BUCKETS],
+}
+
+impl Stats {
+    const fn new() -> Self {
+        Self { samples: 0, min_ns: u32::MAX, max_ns: 0, total_ns: 0, buckets: [0; BUCKETS] }
+    }
+
+    pub fn add(&mut self, ns: u32) {
+        self.samples += 1;
+        self.min_ns = self.min_ns.min(ns);
+        self.max_ns = self.max_ns.max(ns);
+        self.total_ns += ns as u64;
+        let us = ns / 1000;
+        let bucket = BUCKET_LIMITS_US.iter().position(|&limit| us < limit).unwrap_or(BUCKETS - 1);
+        self.buckets[bucket] += 1;
+    }
+
+    pub fn average_ns(&self) -> u32 {
+        if self.samples == 0 {
+            0
+        } else {
+            (self.total_ns / self.samples as u64) as u32
+        }
+    }
+}
+
+static mut ENABLED: bool = false;
+static mut STATS: Stats = Stats::new();
+
+/// Start recording latencies. Only works while the PIT is the tick source.
+pub fn start() -> Result<(), &'static str> {
+    if apic::is_enabled() && apic::tick_source() == TickSource::ApicTimer {
+        return Err("Latency is measured against the PIT; boot with timer=pit");
+    }
+    unsafe {
+        STATS = Stats::new();
+        ENABLED = true;
+    }
+    Ok(())
+}
+
+pub fn stop() -> Stats {
+    unsafe {
+        ENABLED = false;
+        STATS
+    }
+}
+
+/// Called first thing in the timer handler. The PIT reloads its count at
+/// the deadline, so what it has counted down since is the latency.
+pub fn sample() {
+    unsafe {
+        if !ENABLED {
+            return;
+        }
+        let elapsed = idt::pit_divisor().saturating_sub(idt::pit_count()) as u64;
+        STATS.add((elapsed * idt::PIT_PERIOD_PS as u64 / 1000) as u32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn samples_fall_into_buckets() {
+        let mut stats = Stats::new();
+        stats.add(3_000);
+        stats.add(12_000);
+        stats.add(900_000);
+        assert_eq!(stats.samples, 3);
+        assert_eq!(stats.min_ns, 3_000);
+        assert_eq!(stats.max_ns, 900_000);
+        assert_eq!(stats.average_ns(), 305_000);
+        assert_eq!(stats.buckets[0], 1);
+        assert_eq!(stats.buckets[2], 1);
+        assert_eq!(stats.buckets[BUCKETS - 1], 1);
+    }
+}
@@ -0,0 +1,204 @@
+use crate::writer::Writer;
+use crate::vga_colors::{Color, color_code};
+use crate::idt;
+use crate::filesystem::get_filesystem;
+
+const WIDTH: usize = 80;
+const HEIGHT: usize = 25;
+
+const MIN_DELAY_TICKS: u32 = 1;
+const MAX_DELAY_TICKS: u32 = 100;
+
+const CELL: u8 = 0xDB;
+
+/// The rows of a `.cells` pattern: its lines bar comments, not counting
+/// the empty one after a final newline
+fn pattern_rows(content: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let content = content.strip_suffix(b"\n").unwrap_or(content);
+    content.split(|&b| b == b'\n').filter(|line| line.first() != Some(&b'!'))
+}
+
+/// Conway's Game of Life on a torus the size of the text screen
+pub struct LifeGame {
+    cells: [[bool; WIDTH]; HEIGHT],
+    delay_ticks: u32,
+    paused: bool,
+    seed: u32,
+}
+
+impl LifeGame {
+    pub fn new() -> Self {
+        Self {
+            cells: [[false; WIDTH]; HEIGHT],
+            delay_ticks: 10,
+            paused: false,
+            seed: idt::get_ticks().wrapping_add(0xC0FFEE),
+        }
+    }
+
+    fn random(&mut self) -> u32 {
+        self.seed = self.seed.wrapping_mul(1103515245).wrapping_add(12345);
+        self.seed >> 16
+    }
+
+    pub fn randomize(&mut self) {
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                self.cells[y][x] = self.random() % 4 == 0;
+            }
+        }
+    }
+
+    /// Load a pattern in plaintext `.cells` format (`O`/`*` alive, `!` comments),
+    /// centered on the screen
+    pub fn load_pattern(&mut self, filename: &[u8]) -> Result<(), &'static str> {
+        let content = get_filesystem().read_file(filename).ok_or("Pattern file not found")?;
+        self.place_pattern(content)
+    }
+
+    fn place_pattern(&mut self, content: &[u8]) -> Result<(), &'static str> {
+        let mut rows = 0;
+        let mut cols = 0;
+        for line in pattern_rows(content) {
+            rows += 1;
+            cols = cols.max(line.len());
+        }
+        if rows > HEIGHT || cols > WIDTH {
+            return Err("Pattern larger than the screen");
+        }
+
+        self.cells = [[false; WIDTH]; HEIGHT];
+        let top = (HEIGHT - rows) / 2;
+        let left = (WIDTH - cols) / 2;
+        for (y, line) in pattern_rows(content).enumerate() {
+            for (x, &byte) in line.iter().enumerate() {
+                self.cells[top + y][left + x] = byte == b'O' || byte == b'*';
+            }
+        }
+        Ok(())
+    }
+
+    fn live_neighbors(&self, x: usize, y: usize) -> u8 {
+        let mut count = 0;
+        for &dy in [HEIGHT - 1, 0, 1].iter() {
+            for &dx in [WIDTH - 1, 0, 1].iter() {
+                if dx == 0 && dy == 0 {
+                    continue;
+                }
+                if self.cells[(y + dy) % HEIGHT][(x + dx) % WIDTH] {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+    pub fn step(&mut self) {
+        let mut next = [[false; WIDTH]; HEIGHT];
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let n = self.live_neighbors(x, y);
+                next[y][x] = n == 3 || (n == 2 && self.cells[y][x]);
+            }
+        }
+        self.cells = next;
+    }
+
+    pub fn draw(&self) {
+        let alive = (color_code(Color::LightGreen, Color::Black) as u16) << 8 | CELL as u16;
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                Writer::set_cell(x, y, if self.cells[y][x] { alive } else { b' ' as u16 });
+            }
+        }
+    }
+
+    /// Interactive mode: Space pauses, +/- change speed, R reseeds, Q quits
+    pub fn run(&mut self, writer: &mut Writer) {
+        idt::flush_buffer();
+        writer.clear();
+        self.draw();
+
+        let mut last_step = idt::get_ticks();
+        loop {
+            idt::wait_for_interrupt();
+
+            while let Some(scancode) = idt::get_scancode() {
+                match scancode {
+                    0x10 | 0x01 => return,
+                    0x39 => self.paused = !self.paused,
+                    0x0D | 0x4E => self.delay_ticks = (self.delay_ticks / 2).max(MIN_DELAY_TICKS),
+                    0x0C | 0x4A => self.delay_ticks = (self.delay_ticks * 2).min(MAX_DELAY_TICKS),
+                    0x13 => {
+                        self.randomize();
+                        self.draw();
+                    }
+                    _ => {}
+                }
+            }
+
+            let now = idt::get_ticks();
+            if !self.paused && now.wrapping_sub(last_step) >= self.delay_ticks {
+                last_step = now;
+                self.step();
+                self.draw();
+            }
+        }
+    }
+
+    /// Screensaver mode for `lock`: random soup, reseeded every 500
+    /// generations, until any key
+    pub fn run_screensaver(&mut self) {
+        idt::flush_buffer();
+        self.randomize();
+        self.draw();
+
+        let mut last_step = idt::get_ticks();
+        let mut generations = 0;
+        loop {
+            idt::wait_for_interrupt();
+            if let Some(scancode) = idt::get_scancode() {
+                if scancode & 0x80 == 0 {
+                    return;
+                }
+            }
+
+            let now = idt::get_ticks();
+            if now.wrapping_sub(last_step) >= self.delay_ticks {
+                last_step = now;
+                self.step();
+                generations += 1;
+                if generations >= 500 {
+                    generations = 0;
+                    self.randomize();
+                }
+                self.draw();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn a_final_newline_is_not_a_row() {
+        let mut pattern = [b'.'; HEIGHT * 2 + 8];
+        pattern[..7].copy_from_slice(b"!Glider");
+        for row in 0..=HEIGHT {
+            pattern[7 + row * 2] = b'\n';
+        }
+        pattern[8] = b'O';
+        // 25 rows, each ending in a newline
+        let mut game = LifeGame::new();
+        assert_eq!(game.place_pattern(&pattern), Ok(()));
+        assert!(game.cells[0][(WIDTH - 1) / 2]);
+        assert_eq!(pattern_rows(b"O\n\nO\n").count(), 3);
+
+        // `O` and 25 blank rows, then the final newline
+        let mut too_tall = [b'\n'; HEIGHT + 2];
+        too_tall[0] = b'O';
+        assert!(game.place_pattern(&too_tall).is_err());
+    }
+}
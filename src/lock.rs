@@ -0,0 +1,180 @@
+use crate::clock;
+use crate::config::{self, Saver};
+use crate::idt;
+use crate::keyboard::Keyboard;
+use crate::life::LifeGame;
+use crate::sha256::{self, DIGEST_LEN};
+use crate::tsc;
+use crate::vga_colors::{color_code, Color};
+use crate::writer::Writer;
+
+// The lock screen, shown by `lock` and by the shell after `screensaver=`
+// seconds without a key at the prompt. The screen is saved and blanked but
+// for a line that moves somewhere else every few seconds, or with `saver=life`
+// filled with the Game of Life, and comes back when a key is pressed. With `lockpass=` on the boot command line (a
+// SHA-256 digest in hex, as `lock --hash` prints it) the key has to be
+// followed by the password.
+
+const WIDTH: usize = 80;
+const HEIGHT: usize = 25;
+const MOVE_INTERVAL_MS: u64 = 3000;
+/// Back to the blank screen if the password isn't typed in this long
+const PASSWORD_TIMEOUT_MS: u64 = 15_000;
+const MAX_PASSWORD_LEN: usize = 64;
+
+static mut SAVED: [u16; WIDTH * HEIGHT] = [0; WIDTH * HEIGHT];
+
+fn attribute(fg: Color, bg: Color) -> u16 {
+    (color_code(fg, bg) as u16) << 8
+}
+
+fn blank() {
+    for row in 0..HEIGHT {
+        for col in 0..WIDTH {
+            Writer::set_cell(col, row, attribute(Color::Black, Color::Black) | b' ' as u16);
+        }
+    }
+}
+
+fn draw(col: usize, row: usize, text: &[u8], fg: Color) {
+    for (i, &b) in text.iter().enumerate() {
+        Writer::set_cell(col + i, row, attribute(fg, Color::Black) | b as u16);
+    }
+}
+
+/// Lock the screen with `saver` until a key, and the password if there is
+/// one, is given; then put back what was on it
+pub fn lock(writer: &mut Writer, saver: Saver) {
+    let saved = unsafe { &mut SAVED };
+    for row in 0..HEIGHT {
+        for col in 0..WIDTH {
+            saved[row * WIDTH + col] = Writer::cell(col, row);
+        }
+    }
+    writer.disable_cursor();
+    idt::flush_buffer();
+
+    let hash = config::get().lock_hash;
+    loop {
+        match saver {
+            Saver::Uptime => wait_for_key(hash.is_some()),
+            Saver::Life => LifeGame::new().run_screensaver(),
+        }
+        match hash {
+            Some(hash) if !ask_password(&hash) => continue,
+            _ => break,
+        }
+    }
+
+    for row in 0..HEIGHT {
+        for col in 0..WIDTH {
+            Writer::set_cell(col, row, saved[row * WIDTH + col]);
+        }
+    }
+    // Puts the hardware cursor back where it was
+    let (col, row) = (writer.get_col(), writer.get_row());
+    writer.set_position(col, row);
+    writer.enable_cursor();
+    idt::flush_buffer();
+}
+
+/// Blank the screen with a wandering uptime line until a key goes down
+fn wait_for_key(locked: bool) {
+    let mut seed = tsc::read() as u32 | 1;
+    let mut next_move = 0;
+    loop {
+        let now = clock::uptime_ms();
+        if now >= next_move {
+            let seconds = clock::uptime_seconds();
+            let mut line = *b"HyzeOS  up 00:00:00";
+            for (i, value) in [seconds / 3600 % 100, seconds / 60 % 60, seconds % 60].iter().enumerate() {
+                line[11 + i * 3] = b'0' + (value / 10) as u8;
+                line[12 + i * 3] = b'0' + (value % 10) as u8;
+            }
+            let hint: &[u8] = if locked { b"locked, press a key" } else { b"press a key" };
+            // xorshift: the place only has to look random
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let col = seed as usize % (WIDTH - line.len());
+            let row = (seed >> 16) as usize % (HEIGHT - 1);
+            blank();
+            draw(col, row, &line, Color::LightCyan);
+            draw(col, row + 1, hint, Color::DarkGray);
+            next_move = now + MOVE_INTERVAL_MS;
+        }
+        match idt::get_scancode() {
+            Some(scancode) if scancode & 0x80 == 0 => return,
+            Some(_) => {}
+            None => idt::wait_for_interrupt(),
+        }
+    }
+}
+
+/// Read a password on a blank screen and check it against `hash`. Esc or
+/// waiting too long gives up.
+fn ask_password(hash: &[u8; DIGEST_LEN]) -> bool {
+    const PROMPT: &[u8] = b"Password: ";
+    let (col, row) = ((WIDTH - PROMPT.len() - 20) / 2, HEIGHT / 2);
+    blank();
+    draw(col, row, PROMPT, Color::White);
+
+    let mut password = [0u8; MAX_PASSWORD_LEN];
+    let mut len = 0;
+    let mut shift = false;
+    let mut last_key = clock::uptime_ms();
+    loop {
+        let scancode = match idt::get_scancode() {
+            Some(scancode) => scancode,
+            None => {
+                if clock::uptime_ms() - last_key > PASSWORD_TIMEOUT_MS {
+                    return false;
+                }
+                idt::wait_for_interrupt();
+                continue;
+            }
+        };
+        last_key = clock::uptime_ms();
+        match scancode {
+            0x2A | 0x36 => shift = true,
+            0xAA | 0xB6 => shift = false,
+            // Esc
+            0x01 => return false,
+            // Enter
+            0x1C => break,
+            // Backspace
+            0x0E if len > 0 => {
+                len -= 1;
+                if len < 20 {
+                    draw(col + PROMPT.len() + len, row, b" ", Color::White);
+                }
+            }
+            _ => {
+                for c in Keyboard::scancode_to_bytes(scancode, shift) {
+                    if len < MAX_PASSWORD_LEN {
+                        password[len] = c;
+                        len += 1;
+                        // Stars stop at 20 but the password goes on
+                        if len <= 20 {
+                            draw(col + PROMPT.len() + len - 1, row, b"*", Color::White);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if sha256::digest(&password[..len]) == *hash {
+        return true;
+    }
+    draw(col, row + 2, b"Wrong password", Color::LightRed);
+    clock::sleep_ms(1000);
+    false
+}
+
+/// The lowercase hex digest `lockpass=` takes for `password`
+pub fn hash_hex(password: &[u8]) -> [u8; DIGEST_LEN * 2] {
+    let mut hex = [0u8; DIGEST_LEN * 2];
+    sha256::write_hex(&sha256::digest(password), &mut hex);
+    hex
+}
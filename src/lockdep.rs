@@ -0,0 +1,141 @@
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+// Lock dependency checking for debug builds (`make DEBUG=1`).
+//
+// Every `SpinLock` acquisition is recorded against the processor taking
+// it. Taking a lock the same processor already holds, or taking locks in
+// an order that contradicts one seen before, would eventually hang; both
+// stop the kernel with the current stack and the stack recorded for the
+// conflicting acquisition.
+
+use crate::acpi::MAX_CPUS;
+use crate::crash::{self, Registers};
+use crate::smp;
+
+/// Deepest lock nesting tracked per processor
+const MAX_HELD: usize = 8;
+/// Distinct "held A while taking B" orderings remembered
+const MAX_EDGES: usize = 64;
+const TRACE_LEN: usize = 6;
+
+#[derive(Clone, Copy)]
+struct Held {
+    lock: usize,
+    trace: [u32; TRACE_LEN],
+    trace_len: usize,
+}
+
+/// Lock `to` was taken while `from` was held, first seen with `trace`
+#[derive(Clone, Copy)]
+struct Edge {
+    from: usize,
+    to: usize,
+    trace: [u32; TRACE_LEN],
+    trace_len: usize,
+}
+
+const NO_HELD: Held = Held { lock: 0, trace: [0; TRACE_LEN], trace_len: 0 };
+const NO_EDGE: Edge = Edge { from: 0, to: 0, trace: [0; TRACE_LEN], trace_len: 0 };
+
+/// Guards the tables below; a plain flag, since a `SpinLock` would recurse
+static BUSY: AtomicBool = AtomicBool::new(false);
+static mut HELD: [[Held; MAX_HELD]; MAX_CPUS] = [[NO_HELD; MAX_HELD]; MAX_CPUS];
+static mut HELD_COUNT: [usize; MAX_CPUS] = [0; MAX_CPUS];
+static mut EDGES: [Edge; MAX_EDGES] = [NO_EDGE; MAX_EDGES];
+static mut EDGE_COUNT: usize = 0;
+
+fn with_tables<R>(f: impl FnOnce() -> R) -> R {
+    let flags: usize;
+    unsafe {
+        asm!("pushfd", "pop {}", "cli", out(reg) flags);
+    }
+    while BUSY.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+        core::hint::spin_loop();
+    }
+    let result = f();
+    BUSY.store(false, Ordering::Release);
+    if flags & (1 << 9) != 0 {
+        unsafe {
+            asm!("sti", options(nomem, nostack));
+        }
+    }
+    result
+}
+
+/// Whether `to` can be reached from `from` through recorded orderings.
+/// Returns the edge leaving `from` on the way there.
+unsafe fn path(from: usize, to: usize) -> Option<Edge> {
+    let mut stack = [(0usize, 0usize); MAX_EDGES];
+    let mut visited = [false; MAX_EDGES];
+    stack[0] = (from, MAX_EDGES);
+    let mut depth = 1;
+
+    while depth > 0 {
+        depth -= 1;
+        let (lock, first) = stack[depth];
+        for (i, edge) in EDGES[..EDGE_COUNT].iter().enumerate() {
+            if edge.from != lock || visited[i] {
+                continue;
+            }
+            visited[i] = true;
+            let first = if first == MAX_EDGES { i } else { first };
+            if edge.to == to {
+                return Some(EDGES[first]);
+            }
+            if depth < MAX_EDGES {
+                stack[depth] = (edge.to, first);
+                depth += 1;
+            }
+        }
+    }
+    None
+}
+
+/// Check and record an acquisition of `lock` by this processor
+#[inline(always)]
+pub fn acquire(lock: usize) {
+    let mut trace = [0u32; TRACE_LEN];
+    let trace_len = crash::stack_trace(Registers::capture().ebp, &mut trace);
+    let cpu = smp::current_index();
+
+    let conflict = with_tables(|| unsafe {
+        let held = &HELD[cpu][..HELD_COUNT[cpu]];
+        if let Some(h) = held.iter().find(|h| h.lock == lock) {
+            return Some(("Lock taken twice by the same CPU", h.trace, h.trace_len));
+        }
+        for h in held {
+            // Someone took `h` after `lock` before; taking `lock` after `h`
+            // now closes the cycle
+            if let Some(edge) = path(lock, h.lock) {
+                return Some(("Lock order inversion", edge.trace, edge.trace_len));
+            }
+            let known = EDGES[..EDGE_COUNT].iter().any(|e| e.from == h.lock && e.to == lock);
+            if !known && EDGE_COUNT < MAX_EDGES {
+                EDGES[EDGE_COUNT] = Edge { from: h.lock, to: lock, trace, trace_len };
+                EDGE_COUNT += 1;
+            }
+        }
+        if HELD_COUNT[cpu] < MAX_HELD {
+            HELD[cpu][HELD_COUNT[cpu]] = Held { lock, trace, trace_len };
+            HELD_COUNT[cpu] += 1;
+        }
+        None
+    });
+
+    if let Some((message, trace, len)) = conflict {
+        crash::deadlock(message, lock, &trace[..len]);
+    }
+}
+
+pub fn release(lock: usize) {
+    let cpu = smp::current_index();
+    with_tables(|| unsafe {
+        let count = HELD_COUNT[cpu];
+        // Usually the innermost lock, but releases may come out of order
+        if let Some(i) = HELD[cpu][..count].iter().rposition(|h| h.lock == lock) {
+            HELD[cpu].copy_within(i + 1..count, i);
+            HELD_COUNT[cpu] -= 1;
+        }
+    });
+}
@@ -0,0 +1,38 @@
+use core::fmt;
+
+use crate::config::{self, LogLevel};
+
+#[doc(hidden)]
+pub fn _log(level: LogLevel, args: fmt::Arguments) {
+    if level > config::get().log_level {
+        return;
+    }
+    let tag = match level {
+        LogLevel::Error => "ERROR",
+        LogLevel::Warn => "WARN",
+        LogLevel::Info => "INFO",
+        LogLevel::Debug => "DEBUG",
+    };
+    crate::serial::_print(format_args!("[{:5}] {}\n", tag, args));
+}
+
+/// Log to COM1 if the boot `loglevel` allows it
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)*) => ($crate::log::_log($crate::config::LogLevel::Error, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)*) => ($crate::log::_log($crate::config::LogLevel::Warn, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => ($crate::log::_log($crate::config::LogLevel::Info, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)*) => ($crate::log::_log($crate::config::LogLevel::Debug, format_args!($($arg)*)));
+}
@@ -0,0 +1,115 @@
+use crate::framebuffer::{self, Framebuffer};
+use crate::idt;
+
+const WIDTH: usize = 640;
+const HEIGHT: usize = 480;
+
+/// Fixed-point fraction bits; the kernel is built without SSE, so no floats
+const FRAC_BITS: u32 = 26;
+const ONE: i64 = 1 << FRAC_BITS;
+/// |z|^2 beyond this escapes
+const ESCAPE: i64 = 4 * ONE;
+
+const MIN_ITERATIONS: u32 = 16;
+const MAX_ITERATIONS: u32 = 1024;
+
+/// Mandelbrot set explorer with keyboard pan and zoom
+pub struct Mandelbrot {
+    center_x: i64,
+    center_y: i64,
+    /// Width of one pixel in the complex plane
+    step: i64,
+    iterations: u32,
+}
+
+impl Mandelbrot {
+    pub fn new() -> Self {
+        Self {
+            center_x: -ONE / 2,
+            center_y: 0,
+            step: 7 * ONE / 2 / WIDTH as i64,
+            iterations: 64,
+        }
+    }
+
+    fn escape_time(&self, cx: i64, cy: i64) -> u32 {
+        let (mut x, mut y) = (0i64, 0i64);
+        for i in 0..self.iterations {
+            let x2 = (x * x) >> FRAC_BITS;
+            let y2 = (y * y) >> FRAC_BITS;
+            if x2 + y2 > ESCAPE {
+                return i;
+            }
+            y = ((2 * x * y) >> FRAC_BITS) + cy;
+            x = x2 - y2 + cx;
+        }
+        self.iterations
+    }
+
+    fn color(&self, n: u32) -> u32 {
+        if n >= self.iterations {
+            return 0x000000;
+        }
+        // Cycle through a blue-gold gradient
+        let t = (n * 8) % 256;
+        let (r, g, b) = match (n * 8 / 256) % 3 {
+            0 => (t / 4, t / 2, 128 + t / 2),
+            1 => (64 + t * 3 / 4, 128 + t / 2, 255 - t),
+            _ => (255 - t, 255 - t * 3 / 4, t / 2),
+        };
+        (r << 16) | (g << 8) | b
+    }
+
+    fn render(&self, fb: &Framebuffer) {
+        let mut line = [0u32; WIDTH];
+        let left = self.center_x - self.step * (WIDTH as i64 / 2);
+        let top = self.center_y - self.step * (HEIGHT as i64 / 2);
+        for py in 0..HEIGHT {
+            let cy = top + self.step * py as i64;
+            for px in 0..WIDTH {
+                let cx = left + self.step * px as i64;
+                line[px] = self.color(self.escape_time(cx, cy));
+            }
+            fb.blit(0, py, WIDTH, 1, &line);
+        }
+    }
+
+    /// Arrows pan, Z/X (or PgUp/PgDn) zoom, +/- change the iteration
+    /// limit, R resets and Q or ESC exits
+    pub fn run(&mut self) -> Result<(), &'static str> {
+        let (fb, saved) = framebuffer::begin_fullscreen(WIDTH, HEIGHT)?;
+
+        idt::flush_buffer();
+        self.render(&fb);
+
+        loop {
+            let scancode = match idt::get_scancode() {
+                Some(sc) if sc & 0x80 == 0 => sc,
+                _ => {
+                    idt::wait_for_interrupt();
+                    continue;
+                }
+            };
+
+            let pan = self.step * (WIDTH as i64 / 4);
+            match scancode {
+                0x01 | 0x10 => break,
+                0x48 => self.center_y -= pan,
+                0x50 => self.center_y += pan,
+                0x4B => self.center_x -= pan,
+                0x4D => self.center_x += pan,
+                // Stop zooming in once pixels hit the fixed-point resolution
+                0x2C | 0x49 if self.step > 1 => self.step /= 2,
+                0x2D | 0x51 if self.step < ONE / 64 => self.step *= 2,
+                0x0D | 0x4E => self.iterations = (self.iterations * 2).min(MAX_ITERATIONS),
+                0x0C | 0x4A => self.iterations = (self.iterations / 2).max(MIN_ITERATIONS),
+                0x13 => *self = Self::new(),
+                _ => continue,
+            }
+            self.render(&fb);
+        }
+
+        framebuffer::end_fullscreen(saved);
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+use crate::writer::Writer;
+use crate::vga_colors::Color;
+use crate::idt;
+
+const WIDTH: usize = 80;
+const HEIGHT: usize = 25;
+
+/// Timer ticks per animation frame (100 Hz PIT, so ~33 fps)
+const FRAME_TICKS: u32 = 3;
+/// Frames to catch up on at most before dropping the rest
+const MAX_CATCH_UP: u32 = 4;
+
+const MIN_TRAIL: i32 = 6;
+const MAX_TRAIL: i32 = 20;
+
+static mut FRAMES_DUE: u32 = 0;
+
+fn on_tick(ticks: u32) {
+    if ticks % FRAME_TICKS == 0 {
+        unsafe {
+            FRAMES_DUE += 1;
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Stream {
+    head: i32,
+    trail: i32,
+    /// Frames between steps, so columns fall at different speeds
+    speed: u32,
+    countdown: u32,
+}
+
+/// Falling green glyph columns drawn through the Writer
+pub struct MatrixRain {
+    streams: [Stream; WIDTH],
+    glyphs: [[u8; WIDTH]; HEIGHT],
+    seed: u32,
+    frames: u32,
+    dropped: u32,
+}
+
+impl MatrixRain {
+    pub fn new() -> Self {
+        let mut rain = Self {
+            streams: [Stream { head: 0, trail: 0, speed: 1, countdown: 0 }; WIDTH],
+            glyphs: [[b' '; WIDTH]; HEIGHT],
+            seed: idt::get_ticks().wrapping_add(0x5EED),
+            frames: 0,
+            dropped: 0,
+        };
+        for col in 0..WIDTH {
+            rain.reset_stream(col);
+            // Stagger the first wave over twice the screen height
+            rain.streams[col].head = -((rain.random() % (HEIGHT as u32 * 2)) as i32);
+        }
+        rain
+    }
+
+    fn random(&mut self) -> u32 {
+        self.seed = self.seed.wrapping_mul(1103515245).wrapping_add(12345);
+        self.seed >> 16
+    }
+
+    fn random_glyph(&mut self) -> u8 {
+        // Mostly ASCII, with some CP437 symbols mixed in
+        match self.random() % 8 {
+            0 => 0xE0 + (self.random() % 16) as u8,
+            _ => 0x21 + (self.random() % 94) as u8,
+        }
+    }
+
+    fn reset_stream(&mut self, col: usize) {
+        let span = (MAX_TRAIL - MIN_TRAIL) as u32;
+        self.streams[col] = Stream {
+            head: -((self.random() % HEIGHT as u32) as i32),
+            trail: MIN_TRAIL + (self.random() % span) as i32,
+            speed: 1 + self.random() % 3,
+            countdown: 0,
+        };
+    }
+
+    fn put(&self, writer: &mut Writer, col: usize, row: i32, color: Color) {
+        if row < 0 || row >= HEIGHT as i32 {
+            return;
+        }
+        writer.set_position(col, row as usize);
+        writer.set_color(color, Color::Black);
+        writer.write_byte(self.glyphs[row as usize][col]);
+    }
+
+    fn step(&mut self, writer: &mut Writer) {
+        for col in 0..WIDTH {
+            let mut stream = self.streams[col];
+            if stream.countdown > 0 {
+                self.streams[col].countdown -= 1;
+                continue;
+            }
+            stream.countdown = stream.speed - 1;
+            stream.head += 1;
+            self.streams[col] = stream;
+
+            if stream.head >= 0 && stream.head < HEIGHT as i32 {
+                self.glyphs[stream.head as usize][col] = self.random_glyph();
+            }
+            let tail = stream.head - stream.trail;
+            if tail >= 0 && tail < HEIGHT as i32 {
+                self.glyphs[tail as usize][col] = b' ';
+            }
+
+            self.put(writer, col, stream.head, Color::White);
+            self.put(writer, col, stream.head - 1, Color::LightGreen);
+            self.put(writer, col, stream.head - 2, Color::Green);
+            self.put(writer, col, tail + 1, Color::DarkGray);
+            self.put(writer, col, tail, Color::Black);
+
+            if tail >= HEIGHT as i32 {
+                self.reset_stream(col);
+            }
+        }
+
+        // Flicker a few glyphs inside the trails, keeping their colors
+        for _ in 0..8 {
+            let col = (self.random() % WIDTH as u32) as usize;
+            let row = (self.random() % HEIGHT as u32) as usize;
+            if self.glyphs[row][col] != b' ' {
+                self.glyphs[row][col] = self.random_glyph();
+                let attribute = Writer::cell_attribute(col, row);
+                writer.set_position(col, row);
+                writer.write_byte(self.glyphs[row][col]);
+                Writer::set_cell_attribute(col, row, attribute);
+            }
+        }
+
+        self.frames += 1;
+    }
+
+    /// Animate until a key is pressed
+    pub fn run(&mut self, writer: &mut Writer) -> Result<(), &'static str> {
+        let callback = idt::add_timer_callback(on_tick)?;
+        unsafe {
+            FRAMES_DUE = 0;
+        }
+
+        idt::flush_buffer();
+        writer.set_color(Color::Green, Color::Black);
+        writer.clear();
+        writer.disable_cursor();
+
+        let start = idt::get_ticks();
+        loop {
+            idt::wait_for_interrupt();
+            if let Some(scancode) = idt::get_scancode() {
+                if scancode & 0x80 == 0 {
+                    break;
+                }
+            }
+
+            let due = unsafe { core::mem::replace(&mut FRAMES_DUE, 0) };
+            if due > MAX_CATCH_UP {
+                self.dropped += due - MAX_CATCH_UP;
+            }
+            for _ in 0..due.min(MAX_CATCH_UP) {
+                self.step(writer);
+            }
+        }
+
+        idt::remove_timer_callback(callback);
+        writer.enable_cursor();
+        writer.set_color(Color::White, Color::Black);
+        writer.clear();
+        self.report(writer, idt::get_ticks().wrapping_sub(start));
+        Ok(())
+    }
+
+    fn report(&self, writer: &mut Writer, ticks: u32) {
+        let seconds = (ticks / 100).max(1);
+        writer.write_str("Rendered ");
+        write_number(writer, self.frames);
+        writer.write_str(" frames in ");
+        write_number(writer, seconds);
+        writer.write_str(" s (");
+        write_number(writer, self.frames / seconds);
+        writer.write_str(" fps, ");
+        write_number(writer, self.dropped);
+        writer.write_str(" dropped)\n");
+    }
+}
+
+fn write_number(writer: &mut Writer, mut n: u32) {
+    let mut buf = [0u8; 10];
+    let mut i = buf.len();
+    loop {
+        i -= 1;
+        buf[i] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    writer.write_bytes(&buf[i..]);
+}
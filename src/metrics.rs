@@ -0,0 +1,65 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+// Counters for `stats`: interrupts taken per vector, switches between the
+// kernel and user programs, and bytes sent to the screen and to COM1. They
+// are bumped from interrupt handlers as well as the shell, so they are
+// atomics, and they wrap rather than saturate.
+
+const VECTORS: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Counter {
+    /// Into a user program and back out of it
+    ContextSwitches,
+    /// Through the console `Writer`, so text and framebuffer alike
+    ConsoleBytes,
+    SerialBytes,
+}
+
+const COUNTERS: usize = 3;
+
+const ZERO: AtomicU32 = AtomicU32::new(0);
+static INTERRUPTS: [AtomicU32; VECTORS] = [ZERO; VECTORS];
+static COUNTS: [AtomicU32; COUNTERS] = [ZERO; COUNTERS];
+
+pub fn count_interrupt(vector: u8) {
+    INTERRUPTS[vector as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn interrupts(vector: u8) -> u32 {
+    INTERRUPTS[vector as usize].load(Ordering::Relaxed)
+}
+
+/// Every interrupt counted so far, all vectors together
+pub fn total_interrupts() -> u64 {
+    INTERRUPTS.iter().map(|count| count.load(Ordering::Relaxed) as u64).sum()
+}
+
+pub fn add(counter: Counter, n: u32) {
+    COUNTS[counter as usize].fetch_add(n, Ordering::Relaxed);
+}
+
+pub fn get(counter: Counter) -> u32 {
+    COUNTS[counter as usize].load(Ordering::Relaxed)
+}
+
+/// Vectors that have been taken at least once, lowest first
+pub fn active_vectors() -> impl Iterator<Item = (u8, u32)> {
+    (0..VECTORS).map(|vector| (vector as u8, interrupts(vector as u8))).filter(|&(_, count)| count > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn counts_add_up_per_vector_and_overall() {
+        // Vector 0xF0 is never taken otherwise
+        let (before, total_before) = (interrupts(0xF0), total_interrupts());
+        count_interrupt(0xF0);
+        count_interrupt(0xF0);
+        assert_eq!(interrupts(0xF0), before + 2);
+        assert!(total_interrupts() >= total_before + 2);
+        assert!(active_vectors().any(|(vector, count)| vector == 0xF0 && count == before + 2));
+    }
+}
@@ -0,0 +1,212 @@
+use crate::chart;
+use crate::clock;
+use crate::filesystem::get_filesystem;
+use crate::idt;
+use crate::multiboot;
+use crate::vga_colors::Color;
+use crate::writer::Writer;
+
+const WIDTH: usize = 80;
+/// Samples kept per graph, one per refresh
+const HISTORY: usize = 60;
+/// Timer ticks between refreshes (100 Hz PIT)
+const REFRESH_TICKS: u32 = 100;
+const GRAPH_COL: usize = 11;
+const CPU_GRAPH_HEIGHT: usize = 5;
+
+/// IRQ lines shown in the interrupt graphs
+const IRQS: [(usize, &str); 3] = [(0, "Timer"), (1, "Keyboard"), (12, "Mouse")];
+
+/// Fixed-size history of samples, oldest first once full
+struct Series {
+    values: [u32; HISTORY],
+    len: usize,
+}
+
+impl Series {
+    const fn new() -> Self {
+        Self { values: [0; HISTORY], len: 0 }
+    }
+
+    fn push(&mut self, value: u32) {
+        if self.len == HISTORY {
+            self.values.copy_within(1.., 0);
+            self.len -= 1;
+        }
+        self.values[self.len] = value;
+        self.len += 1;
+    }
+
+    fn samples(&self) -> &[u32] {
+        &self.values[..self.len]
+    }
+
+    fn last(&self) -> u32 {
+        self.samples().last().copied().unwrap_or(0)
+    }
+}
+
+/// Full-screen live view of CPU load, memory and interrupt rates
+pub struct Monitor {
+    cpu: Series,
+    irqs: [Series; 3],
+    last_ticks: u32,
+    last_idle: u32,
+    last_irq_counts: [u32; 3],
+}
+
+impl Monitor {
+    pub fn new() -> Self {
+        Self {
+            cpu: Series::new(),
+            irqs: [Series::new(), Series::new(), Series::new()],
+            last_ticks: idt::get_ticks(),
+            last_idle: idt::idle_ticks(),
+            last_irq_counts: IRQS.map(|(irq, _)| idt::irq_count(irq)),
+        }
+    }
+
+    /// Take one sample of every counter since the previous call
+    fn sample(&mut self) {
+        let ticks = idt::get_ticks();
+        let idle = idt::idle_ticks();
+        let elapsed = ticks.wrapping_sub(self.last_ticks).max(1);
+        let idle_elapsed = idle.wrapping_sub(self.last_idle).min(elapsed);
+        self.cpu.push(100 - idle_elapsed * 100 / elapsed);
+
+        for (i, &(irq, _)) in IRQS.iter().enumerate() {
+            let count = idt::irq_count(irq);
+            let delta = count.wrapping_sub(self.last_irq_counts[i]);
+            self.irqs[i].push(delta * 100 / elapsed);
+            self.last_irq_counts[i] = count;
+        }
+
+        self.last_ticks = ticks;
+        self.last_idle = idle;
+    }
+
+    fn heading(&self, writer: &mut Writer, row: usize, text: &str) {
+        writer.set_position(0, row);
+        writer.set_color(Color::LightCyan, Color::Black);
+        writer.write_str(text);
+        writer.set_color(Color::White, Color::Black);
+    }
+
+    fn label(&self, writer: &mut Writer, row: usize, text: &str) {
+        writer.set_position(0, row);
+        writer.set_color(Color::Yellow, Color::Black);
+        writer.write_str(text);
+        for _ in text.len()..GRAPH_COL {
+            writer.write_byte(b' ');
+        }
+        writer.set_color(Color::White, Color::Black);
+    }
+
+    fn draw(&self, writer: &mut Writer) {
+        writer.set_position(0, 0);
+        writer.set_color(Color::Black, Color::LightGray);
+        let uptime = clock::uptime_seconds();
+        let mut title = [b' '; WIDTH];
+        let text = b" HyzeOS System Monitor";
+        title[..text.len()].copy_from_slice(text);
+        let hint = b"Q: quit ";
+        title[WIDTH - hint.len()..].copy_from_slice(hint);
+        let clock = [
+            b'0' + (uptime / 36000 % 10) as u8, b'0' + (uptime / 3600 % 10) as u8, b':',
+            b'0' + (uptime / 600 % 6) as u8, b'0' + (uptime / 60 % 10) as u8, b':',
+            b'0' + (uptime / 10 % 6) as u8, b'0' + (uptime % 10) as u8,
+        ];
+        title[34..41].copy_from_slice(b"uptime ");
+        title[41..49].copy_from_slice(&clock);
+        writer.write_bytes(&title);
+
+        let cpu = self.cpu.last();
+        self.heading(writer, 2, "CPU");
+        self.label(writer, 3, "Load");
+        chart::bar(writer, cpu as u64, 100, 40, chart::level_color(cpu));
+        writer.write_str("  ");
+        write_number(writer, cpu);
+        writer.write_str("%  ");
+        chart::column_chart(writer, GRAPH_COL, 4, CPU_GRAPH_HEIGHT, self.cpu.samples(), 100, Color::LightGreen);
+
+        let usage = get_filesystem().usage();
+        let total_kb = multiboot::total_memory_kb().unwrap_or(0);
+        let kernel_kb = (crate::kernel_image_size() / 1024) as u32;
+        self.heading(writer, 10, "Memory");
+        self.memory_row(writer, 11, "Kernel", kernel_kb, total_kb, " KB");
+        self.memory_row(writer, 12, "Ramfs", (usage.bytes / 1024) as u32, (usage.capacity / 1024) as u32, " KB");
+        self.memory_row(writer, 13, "Files", usage.files as u32, usage.max_files as u32, "");
+
+        self.heading(writer, 15, "Interrupts/s");
+        for (i, &(_, name)) in IRQS.iter().enumerate() {
+            self.label(writer, 16 + i, name);
+            chart::sparkline(writer, self.irqs[i].samples(), 0, Color::Pink);
+            writer.set_position(GRAPH_COL + HISTORY + 1, 16 + i);
+            write_number(writer, self.irqs[i].last());
+            writer.write_str("    ");
+        }
+
+        self.heading(writer, 20, "I/O");
+        self.label(writer, 21, "Disk");
+        writer.set_color(Color::DarkGray, Color::Black);
+        writer.write_str("no disk driver");
+        self.label(writer, 22, "Network");
+        writer.set_color(Color::DarkGray, Color::Black);
+        writer.write_str("no network device");
+        writer.set_color(Color::White, Color::Black);
+    }
+
+    fn memory_row(&self, writer: &mut Writer, row: usize, name: &str, used: u32, total: u32, unit: &str) {
+        self.label(writer, row, name);
+        let percent = chart::percent(used as u64, total as u64);
+        chart::bar(writer, used as u64, total as u64, 40, chart::level_color(percent));
+        writer.write_str("  ");
+        write_number(writer, used);
+        writer.write_str(" / ");
+        write_number(writer, total);
+        writer.write_str(unit);
+        writer.write_str("    ");
+    }
+
+    /// Refresh once a second until Q or ESC
+    pub fn run(&mut self, writer: &mut Writer) {
+        idt::flush_buffer();
+        writer.set_color(Color::White, Color::Black);
+        writer.clear();
+        writer.disable_cursor();
+
+        self.draw(writer);
+        let mut next_refresh = idt::get_ticks().wrapping_add(REFRESH_TICKS);
+        loop {
+            if let Some(scancode) = idt::get_scancode() {
+                if scancode == 0x01 || scancode == 0x10 {
+                    break;
+                }
+            }
+            if idt::get_ticks().wrapping_sub(next_refresh) as i32 >= 0 {
+                self.sample();
+                self.draw(writer);
+                next_refresh = idt::get_ticks().wrapping_add(REFRESH_TICKS);
+            }
+            idt::wait_for_interrupt();
+        }
+
+        writer.enable_cursor();
+        writer.set_color(Color::White, Color::Black);
+        writer.clear();
+    }
+}
+
+fn write_number(writer: &mut Writer, mut n: u32) {
+    let mut buf = [0u8; 10];
+    let mut i = buf.len();
+    loop {
+        i -= 1;
+        buf[i] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    writer.write_bytes(&buf[i..]);
+}
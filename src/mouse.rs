@@ -0,0 +1,257 @@
+use crate::io::{inb, outb};
+use crate::idt;
+use crate::writer::{invert_attribute, Writer};
+use crate::stackguard;
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+const COMMAND_PORT: u16 = 0x64;
+const ACK: u8 = 0xFA;
+
+const SCREEN_COLS: i32 = 80;
+const SCREEN_ROWS: i32 = 25;
+/// Mouse counts per text cell, so the cursor doesn't race across the screen
+const COUNTS_PER_COL: i32 = 8;
+const COUNTS_PER_ROW: i32 = 16;
+
+const EVENT_QUEUE_SIZE: usize = 32;
+
+pub const BUTTON_LEFT: u8 = 1 << 0;
+pub const BUTTON_RIGHT: u8 = 1 << 1;
+pub const BUTTON_MIDDLE: u8 = 1 << 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseEventKind {
+    Move,
+    Press(u8),
+    Release(u8),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MouseEvent {
+    pub kind: MouseEventKind,
+    pub col: usize,
+    pub row: usize,
+    pub buttons: u8,
+}
+
+struct MouseState {
+    packet: [u8; 3],
+    packet_len: usize,
+    x: i32,
+    y: i32,
+    buttons: u8,
+    cursor_visible: bool,
+    cursor_col: usize,
+    cursor_row: usize,
+    saved_attribute: u8,
+}
+
+static mut STATE: MouseState = MouseState {
+    packet: [0; 3],
+    packet_len: 0,
+    x: SCREEN_COLS * COUNTS_PER_COL / 2,
+    y: SCREEN_ROWS * COUNTS_PER_ROW / 2,
+    buttons: 0,
+    cursor_visible: false,
+    cursor_col: 0,
+    cursor_row: 0,
+    saved_attribute: 0,
+};
+
+static mut EVENTS: [MouseEvent; EVENT_QUEUE_SIZE] = [MouseEvent { kind: MouseEventKind::Move, col: 0, row: 0, buttons: 0 }; EVENT_QUEUE_SIZE];
+static mut EVENT_HEAD: usize = 0;
+static mut EVENT_TAIL: usize = 0;
+
+/// Enable the auxiliary PS/2 port and put the mouse in streaming mode
+pub fn init() {
+    unsafe {
+        wait_write();
+        outb(COMMAND_PORT, 0xA8); // Enable auxiliary device
+
+        wait_write();
+        outb(COMMAND_PORT, 0x20); // Read controller configuration
+        wait_read();
+        let config = (inb(DATA_PORT) | 0x02) & !0x20; // IRQ12 on, mouse clock on
+        wait_write();
+        outb(COMMAND_PORT, 0x60);
+        wait_write();
+        outb(DATA_PORT, config);
+
+        write_mouse(0xF6); // Defaults
+        write_mouse(0xF4); // Enable data reporting
+
+        STATE.cursor_visible = true;
+        show_cursor();
+    }
+}
+
+pub fn suspend() -> Result<(), &'static str> {
+    set_cursor_visible(false);
+    Ok(())
+}
+
+/// Set the mouse up again and check it still acknowledges commands
+pub fn resume() -> Result<(), &'static str> {
+    init();
+    if unsafe { write_mouse(0xF4) } != ACK {
+        return Err("Mouse did not acknowledge");
+    }
+    Ok(())
+}
+
+/// Pop the oldest pending event
+pub fn poll_event() -> Option<MouseEvent> {
+    unsafe {
+        core::arch::asm!("cli", options(nostack));
+        let result = if EVENT_HEAD == EVENT_TAIL {
+            None
+        } else {
+            let event = EVENTS[EVENT_TAIL];
+            EVENT_TAIL = (EVENT_TAIL + 1) % EVENT_QUEUE_SIZE;
+            Some(event)
+        };
+        core::arch::asm!("sti", options(nostack));
+        result
+    }
+}
+
+pub fn position() -> (usize, usize) {
+    unsafe { (STATE.cursor_col, STATE.cursor_row) }
+}
+
+pub fn set_cursor_visible(visible: bool) {
+    unsafe {
+        core::arch::asm!("cli", options(nostack));
+        if STATE.cursor_visible && !visible {
+            hide_cursor();
+        } else if !STATE.cursor_visible && visible {
+            show_cursor();
+        }
+        STATE.cursor_visible = visible;
+        core::arch::asm!("sti", options(nostack));
+    }
+}
+
+unsafe fn wait_write() {
+    for _ in 0..100000 {
+        if inb(STATUS_PORT) & 0x02 == 0 {
+            return;
+        }
+    }
+}
+
+unsafe fn wait_read() {
+    for _ in 0..100000 {
+        if inb(STATUS_PORT) & 0x01 != 0 {
+            return;
+        }
+    }
+}
+
+/// Send a byte to the mouse and consume its reply, normally `ACK`
+unsafe fn write_mouse(byte: u8) -> u8 {
+    wait_write();
+    outb(COMMAND_PORT, 0xD4);
+    wait_write();
+    outb(DATA_PORT, byte);
+    wait_read();
+    inb(DATA_PORT)
+}
+
+unsafe fn show_cursor() {
+    let col = (STATE.x / COUNTS_PER_COL) as usize;
+    let row = (STATE.y / COUNTS_PER_ROW) as usize;
+    STATE.cursor_col = col;
+    STATE.cursor_row = row;
+    STATE.saved_attribute = Writer::cell_attribute(col, row);
+    Writer::set_cell_attribute(col, row, invert_attribute(STATE.saved_attribute));
+}
+
+unsafe fn hide_cursor() {
+    let (col, row) = (STATE.cursor_col, STATE.cursor_row);
+    // Only restore if nothing has redrawn the cell underneath us
+    if Writer::cell_attribute(col, row) == invert_attribute(STATE.saved_attribute) {
+        Writer::set_cell_attribute(col, row, STATE.saved_attribute);
+    }
+}
+
+unsafe fn push_event(kind: MouseEventKind) {
+    let next_head = (EVENT_HEAD + 1) % EVENT_QUEUE_SIZE;
+    if next_head != EVENT_TAIL {
+        EVENTS[EVENT_HEAD] = MouseEvent {
+            kind,
+            col: STATE.cursor_col,
+            row: STATE.cursor_row,
+            buttons: STATE.buttons,
+        };
+        EVENT_HEAD = next_head;
+    }
+}
+
+unsafe fn handle_packet() {
+    let [flags, raw_x, raw_y] = STATE.packet;
+    // Drop packets with overflowed movement
+    if flags & 0xC0 != 0 {
+        return;
+    }
+
+    let dx = raw_x as i32 - (((flags as i32) << 4) & 0x100);
+    let dy = raw_y as i32 - (((flags as i32) << 3) & 0x100);
+
+    if dx != 0 || dy != 0 {
+        STATE.x = (STATE.x + dx).max(0).min(SCREEN_COLS * COUNTS_PER_COL - 1);
+        // PS/2 reports up as positive
+        STATE.y = (STATE.y - dy).max(0).min(SCREEN_ROWS * COUNTS_PER_ROW - 1);
+
+        let col = (STATE.x / COUNTS_PER_COL) as usize;
+        let row = (STATE.y / COUNTS_PER_ROW) as usize;
+        if col != STATE.cursor_col || row != STATE.cursor_row {
+            if STATE.cursor_visible {
+                hide_cursor();
+                show_cursor();
+            } else {
+                STATE.cursor_col = col;
+                STATE.cursor_row = row;
+            }
+            push_event(MouseEventKind::Move);
+        }
+    }
+
+    let buttons = flags & 0x07;
+    let changed = buttons ^ STATE.buttons;
+    STATE.buttons = buttons;
+    for &button in [BUTTON_LEFT, BUTTON_RIGHT, BUTTON_MIDDLE].iter() {
+        if changed & button != 0 {
+            if buttons & button != 0 {
+                push_event(MouseEventKind::Press(button));
+            } else {
+                push_event(MouseEventKind::Release(button));
+            }
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn mouse_handler_inner() {
+    let _canary = stackguard::Canary::new();
+    unsafe {
+        let byte = inb(DATA_PORT);
+        idt::count_irq(12);
+
+        // Resynchronise on the always-set bit 3 of the first byte
+        if STATE.packet_len == 0 && byte & 0x08 == 0 {
+            idt::end_of_interrupt(12);
+            return;
+        }
+
+        STATE.packet[STATE.packet_len] = byte;
+        STATE.packet_len += 1;
+        if STATE.packet_len == 3 {
+            STATE.packet_len = 0;
+            handle_packet();
+        }
+
+        idt::end_of_interrupt(12);
+    }
+}
@@ -0,0 +1,225 @@
+/// Value the bootloader leaves in EAX
+pub const BOOTLOADER_MAGIC: u32 = 0x2BADB002;
+
+const FLAG_MEMORY: u32 = 1 << 0;
+const FLAG_BOOT_DEVICE: u32 = 1 << 1;
+const FLAG_CMDLINE: u32 = 1 << 2;
+const FLAG_MODULES: u32 = 1 << 3;
+const FLAG_MEMORY_MAP: u32 = 1 << 6;
+const FLAG_BOOTLOADER_NAME: u32 = 1 << 9;
+const FLAG_FRAMEBUFFER: u32 = 1 << 12;
+
+const MAX_STRING_LEN: usize = 256;
+
+/// Memory map entry type for usable RAM
+pub const MEMORY_AVAILABLE: u32 = 1;
+
+static mut INFO: usize = 0;
+
+#[derive(Clone, Copy)]
+pub struct MemoryRegion {
+    pub base: u64,
+    pub length: u64,
+    /// 1 available, 3 ACPI reclaimable, 4 ACPI NVS, 5 bad, others reserved
+    pub kind: u32,
+}
+
+#[derive(Clone, Copy)]
+pub struct Module {
+    pub start: u32,
+    pub end: u32,
+    pub cmdline: &'static [u8],
+}
+
+impl Module {
+    pub fn data(&self) -> &'static [u8] {
+        unsafe { core::slice::from_raw_parts(self.start as *const u8, (self.end - self.start) as usize) }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct FramebufferInfo {
+    pub address: u64,
+    pub pitch: u32,
+    pub width: u32,
+    pub height: u32,
+    pub bpp: u8,
+    /// 0 indexed color, 1 direct RGB, 2 EGA text
+    pub kind: u8,
+}
+
+#[derive(Clone, Copy)]
+pub struct BootDevice {
+    /// BIOS drive number (0x80 is the first hard disk)
+    pub drive: u8,
+    pub partition: u8,
+    pub sub_partition: u8,
+}
+
+/// Remember the Multiboot information structure passed in EBX
+pub fn init(magic: u32, info: u32) -> Result<(), &'static str> {
+    if magic != BOOTLOADER_MAGIC {
+        return Err("Not booted by a Multiboot loader");
+    }
+    if info == 0 {
+        return Err("Multiboot information pointer is null");
+    }
+    unsafe {
+        INFO = info as usize;
+    }
+    Ok(())
+}
+
+fn read<T: Copy>(offset: usize) -> T {
+    unsafe { core::ptr::read_unaligned((INFO + offset) as *const T) }
+}
+
+fn has_flag(flag: u32) -> bool {
+    unsafe { INFO != 0 && read::<u32>(0) & flag != 0 }
+}
+
+/// NUL-terminated string at a physical address
+fn c_string(address: u32) -> &'static [u8] {
+    if address == 0 {
+        return b"";
+    }
+    let ptr = address as *const u8;
+    let mut len = 0;
+    unsafe {
+        while len < MAX_STRING_LEN && *ptr.add(len) != 0 {
+            len += 1;
+        }
+        core::slice::from_raw_parts(ptr, len)
+    }
+}
+
+/// The kernel command line, without its NUL terminator
+pub fn command_line() -> Option<&'static [u8]> {
+    if !has_flag(FLAG_CMDLINE) {
+        return None;
+    }
+    Some(c_string(read(16)))
+}
+
+pub fn bootloader_name() -> Option<&'static [u8]> {
+    if !has_flag(FLAG_BOOTLOADER_NAME) {
+        return None;
+    }
+    Some(c_string(read(64)))
+}
+
+/// Conventional memory below 1 MB and extended memory above it, in KB
+pub fn basic_memory_kb() -> Option<(u32, u32)> {
+    if !has_flag(FLAG_MEMORY) {
+        return None;
+    }
+    Some((read(4), read(8)))
+}
+
+/// Usable RAM according to the memory map, falling back to the basic
+/// lower/upper figures when the bootloader gave no map
+pub fn total_memory_kb() -> Option<u32> {
+    if has_flag(FLAG_MEMORY_MAP) {
+        let bytes: u64 = memory_map()
+            .filter(|region| region.kind == MEMORY_AVAILABLE)
+            .map(|region| region.length)
+            .sum();
+        return Some((bytes / 1024) as u32);
+    }
+    basic_memory_kb().map(|(lower, upper)| lower + upper)
+}
+
+pub fn boot_device() -> Option<BootDevice> {
+    if !has_flag(FLAG_BOOT_DEVICE) {
+        return None;
+    }
+    let device: u32 = read(12);
+    Some(BootDevice {
+        drive: (device >> 24) as u8,
+        partition: (device >> 16) as u8,
+        sub_partition: (device >> 8) as u8,
+    })
+}
+
+pub fn framebuffer() -> Option<FramebufferInfo> {
+    if !has_flag(FLAG_FRAMEBUFFER) {
+        return None;
+    }
+    Some(FramebufferInfo {
+        address: read(88),
+        pitch: read(96),
+        width: read(100),
+        height: read(104),
+        bpp: read(108),
+        kind: read(109),
+    })
+}
+
+pub fn memory_map() -> MemoryMapIter {
+    if !has_flag(FLAG_MEMORY_MAP) {
+        return MemoryMapIter { next: 0, end: 0 };
+    }
+    let length: u32 = read(44);
+    let address: u32 = read(48);
+    MemoryMapIter { next: address as usize, end: address as usize + length as usize }
+}
+
+pub fn modules() -> ModuleIter {
+    if !has_flag(FLAG_MODULES) {
+        return ModuleIter { next: 0, remaining: 0 };
+    }
+    ModuleIter { next: read::<u32>(24) as usize, remaining: read(20) }
+}
+
+pub struct MemoryMapIter {
+    next: usize,
+    end: usize,
+}
+
+impl Iterator for MemoryMapIter {
+    type Item = MemoryRegion;
+
+    fn next(&mut self) -> Option<MemoryRegion> {
+        if self.next + 24 > self.end {
+            return None;
+        }
+        // Each entry is prefixed by its size, which does not count itself
+        let entry = self.next;
+        let (size, base, length, kind) = unsafe {
+            (
+                core::ptr::read_unaligned(entry as *const u32),
+                core::ptr::read_unaligned((entry + 4) as *const u64),
+                core::ptr::read_unaligned((entry + 12) as *const u64),
+                core::ptr::read_unaligned((entry + 20) as *const u32),
+            )
+        };
+        self.next += size as usize + 4;
+        Some(MemoryRegion { base, length, kind })
+    }
+}
+
+pub struct ModuleIter {
+    next: usize,
+    remaining: u32,
+}
+
+impl Iterator for ModuleIter {
+    type Item = Module;
+
+    fn next(&mut self) -> Option<Module> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let entry = self.next as *const u32;
+        let module = unsafe {
+            Module {
+                start: *entry,
+                end: *entry.add(1),
+                cmdline: c_string(*entry.add(2)),
+            }
+        };
+        self.next += 16;
+        self.remaining -= 1;
+        Some(module)
+    }
+}
@@ -0,0 +1,641 @@
+use core::fmt;
+
+use crate::clock;
+use crate::idt;
+use crate::netdev::{self, MacAddress, NetDevice, MAX_FRAME_LEN};
+use crate::tcp;
+
+// A small IPv4 stack on the first network card: ARP with a cache, IPv4
+// without fragments or options, ICMP echo and UDP sockets; TCP is in
+// `tcp`. Frames are
+// handled by `poll`, which the shell calls whenever it wakes up and the
+// blocking calls here call while they wait; nothing runs in interrupt
+// context. Replies go straight back to the MAC address a packet came
+// from, so answering one never waits on ARP.
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_ARP: u16 = 0x0806;
+const ETH_HEADER_LEN: usize = 14;
+
+const ARP_LEN: usize = 28;
+const ARP_REQUEST: u16 = 1;
+const ARP_REPLY: u16 = 2;
+const ARP_CACHE_SIZE: usize = 8;
+/// Forget an entry after five minutes
+const ARP_MAX_AGE_MS: u64 = 300_000;
+const ARP_TRIES: u32 = 3;
+const ARP_TIMEOUT_MS: u64 = 1000;
+
+const IP_HEADER_LEN: usize = 20;
+pub const MAX_IP_PAYLOAD: usize = MAX_FRAME_LEN - ETH_HEADER_LEN - IP_HEADER_LEN;
+const DEFAULT_TTL: u8 = 64;
+/// Don't fragment
+const IP_FLAG_DF: u16 = 0x4000;
+const PROTOCOL_ICMP: u8 = 1;
+pub const PROTOCOL_TCP: u8 = 6;
+const PROTOCOL_UDP: u8 = 17;
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_UNREACHABLE: u8 = 3;
+const ICMP_PORT_UNREACHABLE: u8 = 3;
+const ICMP_ECHO_REQUEST: u8 = 8;
+
+const UDP_HEADER_LEN: usize = 8;
+pub const MAX_UDP_PAYLOAD: usize = MAX_IP_PAYLOAD - UDP_HEADER_LEN;
+const MAX_SOCKETS: usize = 8;
+/// Datagrams kept per socket until read
+const SOCKET_QUEUE_LEN: usize = 4;
+const EPHEMERAL_PORTS: u16 = 49152;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Ipv4Addr(pub [u8; 4]);
+
+impl Ipv4Addr {
+    pub const UNSPECIFIED: Ipv4Addr = Ipv4Addr([0; 4]);
+    pub const BROADCAST: Ipv4Addr = Ipv4Addr([255; 4]);
+
+    /// Dotted decimal such as `10.0.2.2`
+    pub fn parse(text: &[u8]) -> Option<Ipv4Addr> {
+        let mut octets = [0u8; 4];
+        let mut parts = text.split(|&b| b == b'.');
+        for octet in octets.iter_mut() {
+            let part = parts.next()?;
+            if part.is_empty() || part.len() > 3 || !part.iter().all(u8::is_ascii_digit) {
+                return None;
+            }
+            let value = part.iter().fold(0u32, |n, &d| n * 10 + (d - b'0') as u32);
+            if value > 255 {
+                return None;
+            }
+            *octet = value as u8;
+        }
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Ipv4Addr(octets))
+    }
+
+    fn to_u32(self) -> u32 {
+        u32::from_be_bytes(self.0)
+    }
+
+    fn from_u32(value: u32) -> Ipv4Addr {
+        Ipv4Addr(value.to_be_bytes())
+    }
+}
+
+impl fmt::Display for Ipv4Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}.{}", self.0[0], self.0[1], self.0[2], self.0[3])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    pub address: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    /// Where anything outside the subnet goes, unspecified for nowhere
+    pub gateway: Ipv4Addr,
+    pub dns: Ipv4Addr,
+}
+
+impl Config {
+    pub fn same_subnet(&self, ip: Ipv4Addr) -> bool {
+        let mask = self.netmask.to_u32();
+        ip.to_u32() & mask == self.address.to_u32() & mask
+    }
+
+    /// The subnet's broadcast address
+    pub fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from_u32(self.address.to_u32() | !self.netmask.to_u32())
+    }
+}
+
+/// QEMU's user-mode network hands out 10.0.2.15 behind a router at
+/// 10.0.2.2, with DNS at 10.0.2.3; DHCP replaces this
+static mut CONFIG: Config = Config {
+    address: Ipv4Addr([10, 0, 2, 15]),
+    netmask: Ipv4Addr([255, 255, 255, 0]),
+    gateway: Ipv4Addr([10, 0, 2, 2]),
+    dns: Ipv4Addr([10, 0, 2, 3]),
+};
+
+pub fn config() -> Config {
+    unsafe { CONFIG }
+}
+
+pub fn configure(config: Config) {
+    unsafe {
+        CONFIG = config;
+    }
+}
+
+fn device() -> Result<&'static dyn NetDevice, &'static str> {
+    netdev::devices().next().ok_or("No network interface")
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn write_u16(bytes: &mut [u8], offset: usize, value: u16) {
+    bytes[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+fn ip_at(bytes: &[u8], offset: usize) -> Ipv4Addr {
+    Ipv4Addr([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+/// Ones' complement sum of 16-bit words, an odd last byte padded with zero
+fn sum(data: &[u8], mut acc: u32) -> u32 {
+    for pair in data.chunks(2) {
+        acc += (pair[0] as u32) << 8 | *pair.get(1).unwrap_or(&0) as u32;
+    }
+    acc
+}
+
+fn fold(mut acc: u32) -> u16 {
+    while acc >> 16 != 0 {
+        acc = (acc & 0xFFFF) + (acc >> 16);
+    }
+    !(acc as u16)
+}
+
+/// The internet checksum; zero over data that carries a correct one
+pub fn checksum(data: &[u8]) -> u16 {
+    fold(sum(data, 0))
+}
+
+/// UDP's and TCP's checksum, which also covers the addresses
+pub fn transport_checksum(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, segment: &[u8]) -> u16 {
+    let pseudo = sum(&src.0, 0) + sum(&dst.0, 0) + protocol as u32 + segment.len() as u32;
+    fold(sum(segment, pseudo))
+}
+
+/// Fill in the Ethernet header ahead of `len` payload bytes and send
+fn send_frame(frame: &mut [u8], dst: MacAddress, ethertype: u16, len: usize) -> Result<(), &'static str> {
+    let device = device()?;
+    frame[0..6].copy_from_slice(&dst.0);
+    frame[6..12].copy_from_slice(&device.mac().0);
+    write_u16(frame, 12, ethertype);
+    device.send(&frame[..ETH_HEADER_LEN + len])
+}
+
+// ARP
+
+#[derive(Debug, Clone, Copy)]
+struct ArpEntry {
+    ip: Ipv4Addr,
+    mac: MacAddress,
+    updated_ms: u64,
+}
+
+static mut ARP_CACHE: [Option<ArpEntry>; ARP_CACHE_SIZE] = [None; ARP_CACHE_SIZE];
+
+fn arp_lookup(ip: Ipv4Addr) -> Option<MacAddress> {
+    let now = clock::uptime_ms();
+    unsafe {
+        ARP_CACHE.iter().flatten()
+            .find(|entry| entry.ip == ip && now - entry.updated_ms < ARP_MAX_AGE_MS)
+            .map(|entry| entry.mac)
+    }
+}
+
+/// Remember `ip` at `mac`, in its old slot, a free one or the oldest
+fn arp_insert(ip: Ipv4Addr, mac: MacAddress) {
+    let entry = ArpEntry { ip, mac, updated_ms: clock::uptime_ms() };
+    unsafe {
+        let slot = match ARP_CACHE.iter().position(|e| e.map_or(false, |e| e.ip == ip)) {
+            Some(i) => i,
+            None => match ARP_CACHE.iter().position(Option::is_none) {
+                Some(i) => i,
+                None => (0..ARP_CACHE_SIZE).min_by_key(|&i| ARP_CACHE[i].map_or(0, |e| e.updated_ms)).unwrap_or(0),
+            },
+        };
+        ARP_CACHE[slot] = Some(entry);
+    }
+}
+
+/// Cached addresses and how many milliseconds ago each was confirmed
+pub fn arp_entries() -> impl Iterator<Item = (Ipv4Addr, MacAddress, u64)> {
+    let now = clock::uptime_ms();
+    unsafe {
+        ARP_CACHE.iter().flatten()
+            .filter(move |entry| now - entry.updated_ms < ARP_MAX_AGE_MS)
+            .map(move |entry| (entry.ip, entry.mac, now - entry.updated_ms))
+    }
+}
+
+fn send_arp(operation: u16, dst_mac: MacAddress, target_mac: MacAddress, target_ip: Ipv4Addr) -> Result<(), &'static str> {
+    let mut frame = [0u8; ETH_HEADER_LEN + ARP_LEN];
+    let mac = device()?.mac();
+    {
+        let arp = &mut frame[ETH_HEADER_LEN..];
+        write_u16(arp, 0, 1);
+        write_u16(arp, 2, ETHERTYPE_IPV4);
+        arp[4] = 6;
+        arp[5] = 4;
+        write_u16(arp, 6, operation);
+        arp[8..14].copy_from_slice(&mac.0);
+        arp[14..18].copy_from_slice(&config().address.0);
+        arp[18..24].copy_from_slice(&target_mac.0);
+        arp[24..28].copy_from_slice(&target_ip.0);
+    }
+    send_frame(&mut frame, dst_mac, ETHERTYPE_ARP, ARP_LEN)
+}
+
+fn handle_arp(arp: &[u8]) {
+    if arp.len() < ARP_LEN || read_u16(arp, 2) != ETHERTYPE_IPV4 || arp[4] != 6 || arp[5] != 4 {
+        return;
+    }
+    let mut sender_mac = [0u8; 6];
+    sender_mac.copy_from_slice(&arp[8..14]);
+    let sender = (ip_at(arp, 14), MacAddress(sender_mac));
+    let for_us = ip_at(arp, 24) == config().address;
+
+    // Learn from anything addressed to us, and keep known entries fresh
+    if sender.0 != Ipv4Addr::UNSPECIFIED && (for_us || arp_lookup(sender.0).is_some()) {
+        arp_insert(sender.0, sender.1);
+    }
+    if for_us && read_u16(arp, 6) == ARP_REQUEST {
+        let _ = send_arp(ARP_REPLY, sender.1, sender.1, sender.0);
+    }
+}
+
+/// The MAC address to send to for `ip`: its own on the subnet, else the
+/// gateway's. Asks with ARP and waits for the answer if it isn't cached.
+pub fn resolve(ip: Ipv4Addr) -> Result<MacAddress, &'static str> {
+    let config = config();
+    if ip == Ipv4Addr::BROADCAST || ip == config.broadcast() {
+        return Ok(MacAddress::BROADCAST);
+    }
+    let hop = if config.same_subnet(ip) { ip } else { config.gateway };
+    if hop == Ipv4Addr::UNSPECIFIED {
+        return Err("No route to host");
+    }
+    for _ in 0..ARP_TRIES {
+        if let Some(mac) = arp_lookup(hop) {
+            return Ok(mac);
+        }
+        send_arp(ARP_REQUEST, MacAddress::BROADCAST, MacAddress([0; 6]), hop)?;
+        let deadline = clock::uptime_ms() + ARP_TIMEOUT_MS;
+        while clock::uptime_ms() < deadline {
+            poll();
+            if let Some(mac) = arp_lookup(hop) {
+                return Ok(mac);
+            }
+            idt::wait_for_interrupt();
+        }
+    }
+    Err("Host unreachable")
+}
+
+// IPv4
+
+static mut NEXT_ID: u16 = 1;
+/// Outgoing packets are built here rather than on the 16 KB stack. Only one
+/// is built at a time: `send_ipv4` resolves the next hop before starting.
+static mut TX_FRAME: [u8; MAX_FRAME_LEN] = [0; MAX_FRAME_LEN];
+
+/// Send `payload` to `dst`, resolving its next hop first
+pub fn send_ipv4(dst: Ipv4Addr, protocol: u8, payload: &[u8]) -> Result<(), &'static str> {
+    let mac = resolve(dst)?;
+    send_ipv4_via(mac, dst, protocol, payload)
+}
+
+/// Send `payload` to `dst` through `mac`, as when answering a packet
+pub fn send_ipv4_via(mac: MacAddress, dst: Ipv4Addr, protocol: u8, payload: &[u8]) -> Result<(), &'static str> {
+    if payload.len() > MAX_IP_PAYLOAD {
+        return Err("Packet too long");
+    }
+    let len = IP_HEADER_LEN + payload.len();
+    let frame = unsafe { &mut TX_FRAME };
+    {
+        let ip = &mut frame[ETH_HEADER_LEN..ETH_HEADER_LEN + len];
+        ip[0] = 0x45;
+        write_u16(ip, 2, len as u16);
+        unsafe {
+            write_u16(ip, 4, NEXT_ID);
+            NEXT_ID = NEXT_ID.wrapping_add(1);
+        }
+        write_u16(ip, 6, IP_FLAG_DF);
+        ip[8] = DEFAULT_TTL;
+        ip[9] = protocol;
+        ip[12..16].copy_from_slice(&config().address.0);
+        ip[16..20].copy_from_slice(&dst.0);
+        let sum = checksum(&ip[..IP_HEADER_LEN]);
+        write_u16(ip, 10, sum);
+        ip[IP_HEADER_LEN..].copy_from_slice(payload);
+    }
+    send_frame(frame, mac, ETHERTYPE_IPV4, len)
+}
+
+/// What `handle_ipv4` needs to know about a packet to answer it
+#[derive(Clone, Copy)]
+struct Received {
+    mac: MacAddress,
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    ttl: u8,
+}
+
+fn handle_ipv4(mac: MacAddress, ip: &[u8]) {
+    if ip.len() < IP_HEADER_LEN || ip[0] >> 4 != 4 {
+        return;
+    }
+    let header_len = (ip[0] & 0xF) as usize * 4;
+    let total_len = read_u16(ip, 2) as usize;
+    if header_len < IP_HEADER_LEN || total_len < header_len || total_len > ip.len() || checksum(&ip[..header_len]) != 0 {
+        return;
+    }
+    // Fragments are never put back together
+    if read_u16(ip, 6) & 0x3FFF != 0 {
+        return;
+    }
+    let config = config();
+    let packet = Received { mac, src: ip_at(ip, 12), dst: ip_at(ip, 16), ttl: ip[8] };
+    let broadcast = packet.dst == Ipv4Addr::BROADCAST || packet.dst == config.broadcast();
+    if packet.dst != config.address && !broadcast {
+        return;
+    }
+
+    let payload = &ip[header_len..total_len];
+    match ip[9] {
+        PROTOCOL_ICMP => handle_icmp(packet, payload),
+        PROTOCOL_TCP if !broadcast => tcp::handle(packet.mac, packet.src, packet.dst, payload),
+        PROTOCOL_UDP => handle_udp(packet, payload, &ip[..total_len], broadcast),
+        _ => {}
+    }
+}
+
+// ICMP
+
+#[derive(Debug, Clone, Copy)]
+pub struct EchoReply {
+    pub from: Ipv4Addr,
+    pub id: u16,
+    pub sequence: u16,
+    pub ttl: u8,
+    /// Bytes of data after the ICMP header
+    pub len: usize,
+}
+
+/// The newest echo reply not yet taken
+static mut ECHO_REPLY: Option<EchoReply> = None;
+
+fn handle_icmp(packet: Received, icmp: &[u8]) {
+    if icmp.len() < 8 || checksum(icmp) != 0 {
+        return;
+    }
+    match icmp[0] {
+        ICMP_ECHO_REQUEST if packet.dst == config().address => {
+            let mut reply = [0u8; MAX_IP_PAYLOAD];
+            let reply = &mut reply[..icmp.len()];
+            reply.copy_from_slice(icmp);
+            reply[0] = ICMP_ECHO_REPLY;
+            write_u16(reply, 2, 0);
+            let sum = checksum(reply);
+            write_u16(reply, 2, sum);
+            let _ = send_ipv4_via(packet.mac, packet.src, PROTOCOL_ICMP, reply);
+        }
+        ICMP_ECHO_REPLY => unsafe {
+            ECHO_REPLY = Some(EchoReply {
+                from: packet.src,
+                id: read_u16(icmp, 4),
+                sequence: read_u16(icmp, 6),
+                ttl: packet.ttl,
+                len: icmp.len() - 8,
+            });
+        },
+        _ => {}
+    }
+}
+
+/// Send an echo request carrying `data`; its reply turns up in
+/// `take_echo_reply`
+pub fn send_echo_request(dst: Ipv4Addr, id: u16, sequence: u16, data: &[u8]) -> Result<(), &'static str> {
+    let len = 8 + data.len();
+    if len > MAX_IP_PAYLOAD {
+        return Err("Packet too long");
+    }
+    let mut icmp = [0u8; MAX_IP_PAYLOAD];
+    icmp[0] = ICMP_ECHO_REQUEST;
+    write_u16(&mut icmp, 4, id);
+    write_u16(&mut icmp, 6, sequence);
+    icmp[8..len].copy_from_slice(data);
+    let sum = checksum(&icmp[..len]);
+    write_u16(&mut icmp, 2, sum);
+    send_ipv4(dst, PROTOCOL_ICMP, &icmp[..len])
+}
+
+pub fn take_echo_reply() -> Option<EchoReply> {
+    unsafe { ECHO_REPLY.take() }
+}
+
+/// Tell `packet`'s sender nothing listens on the port, quoting the start
+/// of what it sent
+fn send_port_unreachable(packet: Received, ip: &[u8]) {
+    let quoted = ip.len().min(IP_HEADER_LEN + 8);
+    let mut icmp = [0u8; 8 + IP_HEADER_LEN + 8];
+    icmp[0] = ICMP_UNREACHABLE;
+    icmp[1] = ICMP_PORT_UNREACHABLE;
+    icmp[8..8 + quoted].copy_from_slice(&ip[..quoted]);
+    let icmp = &mut icmp[..8 + quoted];
+    let sum = checksum(icmp);
+    write_u16(icmp, 2, sum);
+    let _ = send_ipv4_via(packet.mac, packet.src, PROTOCOL_ICMP, icmp);
+}
+
+// UDP
+
+#[derive(Clone, Copy)]
+struct Datagram {
+    from: Ipv4Addr,
+    port: u16,
+    len: usize,
+    data: [u8; MAX_UDP_PAYLOAD],
+}
+
+struct Socket {
+    /// Zero while the slot is free
+    port: u16,
+    queue: [Datagram; SOCKET_QUEUE_LEN],
+    head: usize,
+    len: usize,
+}
+
+const EMPTY_DATAGRAM: Datagram = Datagram { from: Ipv4Addr::UNSPECIFIED, port: 0, len: 0, data: [0; MAX_UDP_PAYLOAD] };
+const FREE_SOCKET: Socket = Socket { port: 0, queue: [EMPTY_DATAGRAM; SOCKET_QUEUE_LEN], head: 0, len: 0 };
+
+static mut SOCKETS: [Socket; MAX_SOCKETS] = [FREE_SOCKET; MAX_SOCKETS];
+static mut NEXT_EPHEMERAL: u16 = EPHEMERAL_PORTS;
+
+fn socket_on(port: u16) -> Option<&'static mut Socket> {
+    unsafe { SOCKETS.iter_mut().find(|socket| socket.port == port && port != 0) }
+}
+
+fn handle_udp(packet: Received, udp: &[u8], ip: &[u8], broadcast: bool) {
+    if udp.len() < UDP_HEADER_LEN {
+        return;
+    }
+    let len = read_u16(udp, 4) as usize;
+    if len < UDP_HEADER_LEN || len > udp.len() {
+        return;
+    }
+    let udp = &udp[..len];
+    if read_u16(udp, 6) != 0 && transport_checksum(packet.src, packet.dst, PROTOCOL_UDP, udp) != 0 {
+        return;
+    }
+
+    let socket = match socket_on(read_u16(udp, 2)) {
+        Some(socket) => socket,
+        None => {
+            if !broadcast {
+                send_port_unreachable(packet, ip);
+            }
+            return;
+        }
+    };
+    // A full queue drops the newest, as the card does
+    if socket.len == SOCKET_QUEUE_LEN {
+        return;
+    }
+    let datagram = &mut socket.queue[(socket.head + socket.len) % SOCKET_QUEUE_LEN];
+    datagram.from = packet.src;
+    datagram.port = read_u16(udp, 0);
+    datagram.len = len - UDP_HEADER_LEN;
+    datagram.data[..datagram.len].copy_from_slice(&udp[UDP_HEADER_LEN..]);
+    socket.len += 1;
+}
+
+/// A bound UDP port; dropping it closes the port
+pub struct UdpSocket {
+    port: u16,
+}
+
+impl UdpSocket {
+    /// Listen on `port`, or on a free port from 49152 up if it is 0
+    pub fn bind(port: u16) -> Result<UdpSocket, &'static str> {
+        let port = if port == 0 { ephemeral_port()? } else { port };
+        if socket_on(port).is_some() {
+            return Err("Port already in use");
+        }
+        let socket = unsafe { SOCKETS.iter_mut().find(|socket| socket.port == 0) }.ok_or("No free sockets")?;
+        socket.port = port;
+        socket.head = 0;
+        socket.len = 0;
+        Ok(UdpSocket { port })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn send_to(&self, data: &[u8], dst: Ipv4Addr, port: u16) -> Result<(), &'static str> {
+        let len = UDP_HEADER_LEN + data.len();
+        if len > MAX_IP_PAYLOAD {
+            return Err("Datagram too long");
+        }
+        let mut udp = [0u8; MAX_IP_PAYLOAD];
+        write_u16(&mut udp, 0, self.port);
+        write_u16(&mut udp, 2, port);
+        write_u16(&mut udp, 4, len as u16);
+        udp[UDP_HEADER_LEN..len].copy_from_slice(data);
+        let sum = match transport_checksum(config().address, dst, PROTOCOL_UDP, &udp[..len]) {
+            // Zero means no checksum, so a real zero is sent as all ones
+            0 => 0xFFFF,
+            sum => sum,
+        };
+        write_u16(&mut udp, 6, sum);
+        send_ipv4(dst, PROTOCOL_UDP, &udp[..len])
+    }
+
+    /// The oldest datagram waiting, copied into `buf` (cut short if it is
+    /// smaller), with its sender's address and port
+    pub fn recv_from(&self, buf: &mut [u8]) -> Option<(usize, Ipv4Addr, u16)> {
+        poll();
+        let socket = socket_on(self.port)?;
+        if socket.len == 0 {
+            return None;
+        }
+        let datagram = &socket.queue[socket.head];
+        let len = datagram.len.min(buf.len());
+        buf[..len].copy_from_slice(&datagram.data[..len]);
+        let from = (datagram.from, datagram.port);
+        socket.head = (socket.head + 1) % SOCKET_QUEUE_LEN;
+        socket.len -= 1;
+        Some((len, from.0, from.1))
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        if let Some(socket) = socket_on(self.port) {
+            socket.port = 0;
+        }
+    }
+}
+
+fn ephemeral_port() -> Result<u16, &'static str> {
+    for _ in EPHEMERAL_PORTS..=u16::MAX {
+        let port = unsafe {
+            let port = NEXT_EPHEMERAL;
+            NEXT_EPHEMERAL = if port == u16::MAX { EPHEMERAL_PORTS } else { port + 1 };
+            port
+        };
+        if socket_on(port).is_none() {
+            return Ok(port);
+        }
+    }
+    Err("No free ports")
+}
+
+static mut RX_FRAME: [u8; MAX_FRAME_LEN] = [0; MAX_FRAME_LEN];
+
+/// Handle every frame the card has received. Called from the shell's idle
+/// loop and while waiting here.
+pub fn poll() {
+    let device = match device() {
+        Ok(device) => device,
+        Err(_) => return,
+    };
+    let frame = unsafe { &mut RX_FRAME };
+    while let Some(len) = device.receive(frame) {
+        if len < ETH_HEADER_LEN {
+            continue;
+        }
+        let mut src = [0u8; 6];
+        src.copy_from_slice(&frame[6..12]);
+        let payload = &frame[ETH_HEADER_LEN..len];
+        match read_u16(frame, 12) {
+            ETHERTYPE_ARP => handle_arp(payload),
+            ETHERTYPE_IPV4 => handle_ipv4(MacAddress(src), payload),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn checksums_addresses_and_subnets() {
+        // The worked example from RFC 1071's reference header
+        let header = [0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 0xc0, 0xa8, 0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7];
+        assert_eq!(checksum(&header), 0xb861);
+        let mut with_sum = header;
+        with_sum[10..12].copy_from_slice(&0xb861u16.to_be_bytes());
+        assert_eq!(checksum(&with_sum), 0);
+
+        assert_eq!(Ipv4Addr::parse(b"10.0.2.2"), Some(Ipv4Addr([10, 0, 2, 2])));
+        assert_eq!(Ipv4Addr::parse(b"10.0.2"), None);
+        assert_eq!(Ipv4Addr::parse(b"10.0.2.256"), None);
+        assert_eq!(Ipv4Addr::parse(b"1.2.3.4.5"), None);
+
+        let config = Config { address: Ipv4Addr([10, 0, 2, 15]), netmask: Ipv4Addr([255, 255, 255, 0]), gateway: Ipv4Addr([10, 0, 2, 2]), dns: Ipv4Addr([10, 0, 2, 3]) };
+        assert!(config.same_subnet(Ipv4Addr([10, 0, 2, 3])));
+        assert!(!config.same_subnet(Ipv4Addr([10, 0, 3, 3])));
+        assert_eq!(config.broadcast(), Ipv4Addr([10, 0, 2, 255]));
+    }
+}
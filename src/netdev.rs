@@ -0,0 +1,138 @@
+use core::fmt;
+
+// Network interfaces: drivers register a `NetDevice` that sends and
+// receives raw Ethernet frames, and whatever speaks a protocol on top
+// finds it here by name.
+
+const MAX_DEVICES: usize = 4;
+/// Largest frame without the FCS: 1500 bytes of payload and the header
+pub const MAX_FRAME_LEN: usize = 1514;
+/// Received frames waiting to be read, per device
+const QUEUE_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl MacAddress {
+    pub const BROADCAST: MacAddress = MacAddress([0xFF; 6]);
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = self.0;
+        write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", b[0], b[1], b[2], b[3], b[4], b[5])
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    pub rx_packets: u32,
+    pub rx_bytes: u64,
+    /// Received but thrown away because the queue was full
+    pub rx_dropped: u32,
+    /// Frames the card flagged as bad
+    pub rx_errors: u32,
+    pub tx_packets: u32,
+    pub tx_bytes: u64,
+    /// Frames not sent, e.g. because every transmit slot was busy
+    pub tx_errors: u32,
+}
+
+/// A network card. Methods take `&self` because the driver's interrupt
+/// handler shares the device; implementations lock their own state.
+pub trait NetDevice: Sync {
+    /// Interface name such as `eth0`
+    fn name(&self) -> &'static str;
+    /// Driver name such as `e1000`
+    fn driver(&self) -> &'static str;
+    fn mac(&self) -> MacAddress;
+    fn link_up(&self) -> bool;
+    /// Queue one frame, headers included and without the FCS
+    fn send(&self, frame: &[u8]) -> Result<(), &'static str>;
+    /// Copy the oldest received frame into `buf` and return its length,
+    /// cut short if `buf` is smaller
+    fn receive(&self, buf: &mut [u8]) -> Option<usize>;
+    fn stats(&self) -> Stats;
+}
+
+static mut DEVICES: [Option<&'static dyn NetDevice>; MAX_DEVICES] = [None; MAX_DEVICES];
+
+pub fn register(device: &'static dyn NetDevice) -> Result<(), &'static str> {
+    unsafe {
+        let slot = DEVICES.iter_mut().find(|slot| slot.is_none()).ok_or("No free network device slots")?;
+        *slot = Some(device);
+    }
+    Ok(())
+}
+
+pub fn devices() -> impl Iterator<Item = &'static dyn NetDevice> {
+    unsafe { DEVICES.iter().flatten().copied() }
+}
+
+pub fn find(name: &[u8]) -> Option<&'static dyn NetDevice> {
+    devices().find(|device| device.name().as_bytes() == name)
+}
+
+/// Received frames between a driver's interrupt handler and `receive`
+pub struct FrameQueue {
+    frames: [[u8; MAX_FRAME_LEN]; QUEUE_LEN],
+    lens: [usize; QUEUE_LEN],
+    head: usize,
+    len: usize,
+}
+
+impl FrameQueue {
+    pub const fn new() -> Self {
+        Self { frames: [[0; MAX_FRAME_LEN]; QUEUE_LEN], lens: [0; QUEUE_LEN], head: 0, len: 0 }
+    }
+
+    /// Keep a copy of `frame`; false if the queue is full
+    pub fn push(&mut self, frame: &[u8]) -> bool {
+        if self.len == QUEUE_LEN {
+            return false;
+        }
+        let slot = (self.head + self.len) % QUEUE_LEN;
+        let len = frame.len().min(MAX_FRAME_LEN);
+        self.frames[slot][..len].copy_from_slice(&frame[..len]);
+        self.lens[slot] = len;
+        self.len += 1;
+        true
+    }
+
+    pub fn pop(&mut self, buf: &mut [u8]) -> Option<usize> {
+        if self.len == 0 {
+            return None;
+        }
+        let len = self.lens[self.head].min(buf.len());
+        buf[..len].copy_from_slice(&self.frames[self.head][..len]);
+        self.head = (self.head + 1) % QUEUE_LEN;
+        self.len -= 1;
+        Some(len)
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn queue_keeps_order_and_refuses_when_full() {
+        let mut queue = FrameQueue::new();
+        for i in 0..QUEUE_LEN {
+            assert!(queue.push(&[i as u8; 60]));
+        }
+        assert!(!queue.push(&[0xEE; 60]));
+
+        let mut buf = [0u8; 8];
+        assert_eq!(queue.pop(&mut buf), Some(8));
+        assert_eq!(buf, [0; 8]);
+        let mut buf = [0u8; MAX_FRAME_LEN];
+        assert_eq!(queue.pop(&mut buf), Some(60));
+        assert_eq!(buf[59], 1);
+        assert_eq!(MacAddress([0x52, 0x54, 0, 0x12, 0x34, 0x56]), MacAddress([0x52, 0x54, 0, 0x12, 0x34, 0x56]));
+    }
+}
@@ -0,0 +1,226 @@
+use crate::filesystem::MAX_FILE_SIZE;
+use crate::idt;
+use crate::keyboard::Keyboard;
+use crate::vga_colors::Color;
+use crate::writer::Writer;
+
+// `less` and `more`: text a screen at a time, long lines wrapped, with a
+// status line showing the position. The text is a file or the output piped
+// in, so at most a file's size.
+
+const WIDTH: usize = 80;
+const PAGE_ROWS: usize = 24;
+const STATUS_ROW: usize = 24;
+const MAX_QUERY_LEN: usize = 40;
+/// Each row starts with at least one byte of the text
+const MAX_ROWS: usize = MAX_FILE_SIZE;
+
+static mut ROW_STARTS: [u16; MAX_ROWS] = [0; MAX_ROWS];
+
+pub struct Pager<'a> {
+    text: &'a [u8],
+    /// File name, or what the text came from
+    name: &'a [u8],
+    rows: usize,
+    /// First row on screen
+    top: usize,
+    /// Last search, repeated by `n`
+    query: [u8; MAX_QUERY_LEN],
+    query_len: usize,
+}
+
+impl<'a> Pager<'a> {
+    pub fn new(text: &'a [u8], name: &'a [u8]) -> Result<Self, &'static str> {
+        if text.len() > MAX_FILE_SIZE {
+            return Err("Text too long to page");
+        }
+        let rows = wrap(text, WIDTH, unsafe { &mut ROW_STARTS });
+        Ok(Self { text, name, rows, top: 0, query: [0; MAX_QUERY_LEN], query_len: 0 })
+    }
+
+    fn row(&self, index: usize) -> &'a [u8] {
+        row_at(self.text, unsafe { ROW_STARTS[index] } as usize, WIDTH)
+    }
+
+    fn last_top(&self) -> usize {
+        self.rows.saturating_sub(PAGE_ROWS)
+    }
+
+    fn scroll_by(&mut self, rows: isize) {
+        self.top = (self.top as isize + rows).clamp(0, self.last_top() as isize) as usize;
+    }
+
+    pub fn run(&mut self, writer: &mut Writer) {
+        let mut shift_pressed = false;
+        let mut message = None;
+        writer.clear();
+
+        loop {
+            self.draw(writer, message.take());
+            let scancode = next_key(&mut shift_pressed);
+            match scancode {
+                0x01 => break,
+                0x48 => self.scroll_by(-1),
+                0x50 | 0x1C => self.scroll_by(1),
+                0x49 => self.scroll_by(-(PAGE_ROWS as isize)),
+                0x51 => self.scroll_by(PAGE_ROWS as isize),
+                0x47 => self.top = 0,
+                0x4F => self.top = self.last_top(),
+                _ => match Keyboard::scancode_to_byte(scancode, shift_pressed) {
+                    Some(b'q') => break,
+                    Some(b' ') | Some(b'f') => self.scroll_by(PAGE_ROWS as isize),
+                    Some(b'b') => self.scroll_by(-(PAGE_ROWS as isize)),
+                    Some(b'j') => self.scroll_by(1),
+                    Some(b'k') => self.scroll_by(-1),
+                    Some(b'g') => self.top = 0,
+                    Some(b'G') => self.top = self.last_top(),
+                    Some(b'/') => {
+                        if self.read_query(writer, &mut shift_pressed) {
+                            message = self.find_next();
+                        }
+                    }
+                    Some(b'n') => message = self.find_next(),
+                    _ => {}
+                },
+            }
+        }
+    }
+
+    fn draw(&self, writer: &mut Writer, message: Option<&str>) {
+        writer.set_color(Color::White, Color::Black);
+        for screen_row in 0..PAGE_ROWS {
+            writer.set_position(0, screen_row);
+            let row = if self.top + screen_row < self.rows { self.row(self.top + screen_row) } else { &b""[..] };
+            for &b in row {
+                writer.write_byte(if b < b' ' { b' ' } else { b });
+            }
+            writer.pad_to(WIDTH);
+        }
+
+        writer.set_position(0, STATUS_ROW);
+        writer.set_color(Color::Black, Color::LightGray);
+        let bottom = (self.top + PAGE_ROWS).min(self.rows);
+        match message {
+            Some(message) => {
+                writer.write_byte(b' ');
+                writer.write_str(message);
+            }
+            None => {
+                writer.write_byte(b' ');
+                writer.write_bytes(self.name);
+                write!(writer, "  rows {}-{}/{}", (self.top + 1).min(bottom), bottom, self.rows);
+                if bottom == self.rows {
+                    writer.write_str("  (END)");
+                } else {
+                    write!(writer, "  {}%", bottom * 100 / self.rows);
+                }
+            }
+        }
+        // Stop short of the last column so the screen doesn't scroll
+        let help = "Space b arrows / n q ";
+        writer.pad_to(WIDTH - 1 - help.len());
+        writer.write_str(help);
+        writer.set_color(Color::White, Color::Black);
+    }
+
+    /// Edit the search text on the status line. Returns false if ESC
+    /// cancelled it or it was left empty.
+    fn read_query(&mut self, writer: &mut Writer, shift_pressed: &mut bool) -> bool {
+        loop {
+            writer.set_position(0, STATUS_ROW);
+            writer.set_color(Color::White, Color::Black);
+            writer.write_byte(b'/');
+            writer.write_bytes(&self.query[..self.query_len]);
+            writer.pad_to(WIDTH - 1);
+            writer.set_position(1 + self.query_len, STATUS_ROW);
+
+            match next_key(shift_pressed) {
+                0x01 => return false,
+                0x1C => return self.query_len > 0,
+                0x0E => self.query_len = self.query_len.saturating_sub(1),
+                scancode => {
+                    for c in Keyboard::scancode_to_bytes(scancode, *shift_pressed) {
+                        if self.query_len < MAX_QUERY_LEN {
+                            self.query[self.query_len] = c;
+                            self.query_len += 1;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Bring the next row below the top one that contains the search to the
+    /// top of the screen, as far as it scrolls. Returns a message if there
+    /// is none.
+    fn find_next(&mut self) -> Option<&'static str> {
+        let query = &self.query[..self.query_len];
+        if query.is_empty() {
+            return Some("No search yet, type / first");
+        }
+        match (self.top + 1..self.rows).find(|&i| self.row(i).windows(query.len()).any(|window| window == query)) {
+            Some(row) => {
+                self.top = row.min(self.last_top());
+                None
+            }
+            None => Some("Pattern not found"),
+        }
+    }
+}
+
+/// Wait for a key press, keeping track of Shift
+fn next_key(shift_pressed: &mut bool) -> u8 {
+    loop {
+        match idt::get_scancode() {
+            Some(0x2A) | Some(0x36) => *shift_pressed = true,
+            Some(0xAA) | Some(0xB6) => *shift_pressed = false,
+            Some(scancode) if scancode & 0x80 == 0 => return scancode,
+            Some(_) => {}
+            None => idt::wait_for_interrupt(),
+        }
+    }
+}
+
+/// Split `text` into screen rows at newlines and every `width` columns,
+/// storing where each starts. Returns the number of rows.
+fn wrap(text: &[u8], width: usize, starts: &mut [u16]) -> usize {
+    let mut rows = 0;
+    let mut start = 0;
+    while start < text.len() && rows < starts.len() {
+        starts[rows] = start as u16;
+        rows += 1;
+        start += row_at(text, start, width).len();
+        if text.get(start) == Some(&b'\n') {
+            start += 1;
+        }
+    }
+    rows
+}
+
+/// The row starting at `start`: up to the newline or `width` bytes
+fn row_at(text: &[u8], start: usize, width: usize) -> &[u8] {
+    let rest = &text[start..];
+    let len = rest.iter().take(width).position(|&b| b == b'\n').unwrap_or(rest.len().min(width));
+    &rest[..len]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn wraps_long_lines_and_keeps_empty_ones() {
+        let mut starts = [0u16; 8];
+        let text = b"abcdef\n\nxy\n";
+        let rows = wrap(text, 4, &mut starts);
+        assert_eq!(rows, 4);
+        assert_eq!(&starts[..rows], &[0, 4, 7, 8]);
+        assert_eq!(row_at(text, 0, 4), b"abcd");
+        assert_eq!(row_at(text, 4, 4), b"ef");
+        assert_eq!(row_at(text, 7, 4), b"");
+        assert_eq!(row_at(text, 8, 4), b"xy");
+
+        assert_eq!(wrap(b"abcd\nz", 4, &mut starts), 2);
+        assert_eq!(wrap(b"", 4, &mut starts), 0);
+    }
+}
@@ -0,0 +1,135 @@
+use crate::io::{inl, outl};
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciDevice {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+}
+
+impl PciDevice {
+    pub fn read(&self, offset: u8) -> u32 {
+        config_read(self.bus, self.device, self.function, offset)
+    }
+
+    pub fn write(&self, offset: u8, value: u32) {
+        config_write(self.bus, self.device, self.function, offset, value);
+    }
+
+    /// Base address of a memory or I/O BAR with the flag bits masked off
+    pub fn bar(&self, index: u8) -> u32 {
+        let raw = self.read(0x10 + index * 4);
+        if raw & 1 != 0 { raw & !0x3 } else { raw & !0xF }
+    }
+
+    pub fn interrupt_line(&self) -> u8 {
+        (self.read(0x3C) & 0xFF) as u8
+    }
+
+    /// Turn on I/O space, memory space and bus mastering
+    pub fn enable_bus_master(&self) {
+        let command = self.read(0x04);
+        self.write(0x04, command | 0x7);
+    }
+}
+
+pub fn config_read(bus: u8, device: u8, function: u8, offset: u8) -> u32 {
+    unsafe {
+        outl(CONFIG_ADDRESS, config_address(bus, device, function, offset));
+        inl(CONFIG_DATA)
+    }
+}
+
+pub fn config_write(bus: u8, device: u8, function: u8, offset: u8, value: u32) {
+    unsafe {
+        outl(CONFIG_ADDRESS, config_address(bus, device, function, offset));
+        outl(CONFIG_DATA, value);
+    }
+}
+
+fn config_address(bus: u8, device: u8, function: u8, offset: u8) -> u32 {
+    0x8000_0000
+        | (bus as u32) << 16
+        | (device as u32) << 11
+        | (function as u32) << 8
+        | (offset as u32 & 0xFC)
+}
+
+fn probe(bus: u8, device: u8, function: u8) -> Option<PciDevice> {
+    let id = config_read(bus, device, function, 0x00);
+    if id & 0xFFFF == 0xFFFF {
+        return None;
+    }
+    let class = config_read(bus, device, function, 0x08);
+    Some(PciDevice {
+        bus,
+        device,
+        function,
+        vendor_id: (id & 0xFFFF) as u16,
+        device_id: (id >> 16) as u16,
+        class: (class >> 24) as u8,
+        subclass: (class >> 16) as u8,
+    })
+}
+
+/// Brute-force scan of every bus/device/function
+pub fn devices() -> PciIterator {
+    PciIterator { bus: 0, device: 0, function: 0, done: false }
+}
+
+pub fn find_device(vendor_id: u16, device_id: u16) -> Option<PciDevice> {
+    devices().find(|d| d.vendor_id == vendor_id && d.device_id == device_id)
+}
+
+pub struct PciIterator {
+    bus: u16,
+    device: u8,
+    function: u8,
+    done: bool,
+}
+
+impl PciIterator {
+    fn advance(&mut self, skip_functions: bool) {
+        self.function += 1;
+        if skip_functions || self.function == 8 {
+            self.function = 0;
+            self.device += 1;
+            if self.device == 32 {
+                self.device = 0;
+                self.bus += 1;
+                if self.bus == 256 {
+                    self.done = true;
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for PciIterator {
+    type Item = PciDevice;
+
+    fn next(&mut self) -> Option<PciDevice> {
+        while !self.done {
+            let (bus, device, function) = (self.bus as u8, self.device, self.function);
+            let found = probe(bus, device, function);
+
+            // Only probe functions 1-7 on multi-function devices
+            let multifunction = function == 0
+                && found.is_some()
+                && config_read(bus, device, 0, 0x0C) & (0x80 << 16) != 0;
+            self.advance(function == 0 && !multifunction);
+
+            if found.is_some() {
+                return found;
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,106 @@
+use core::arch::asm;
+
+use crate::acpi;
+use crate::io::{inb, inw, outb, outw};
+
+const KBC_STATUS_PORT: u16 = 0x64;
+const KBC_RESET_CMD: u8 = 0xFE;
+
+const SLP_EN: u16 = 1 << 13;
+const SCI_EN: u16 = 1;
+
+/// Reboot the machine: ACPI reset register, then the 8042 reset line,
+/// then a triple fault as the last resort
+pub fn reboot() -> ! {
+    unsafe {
+        asm!("cli", options(nostack));
+
+        if let Some(fadt) = acpi::get_info().fadt {
+            if fadt.reset_port != 0 {
+                outb(fadt.reset_port, fadt.reset_value);
+            }
+        }
+
+        // Wait for the controller input buffer to drain, then pulse reset
+        for _ in 0..0x10000 {
+            if inb(KBC_STATUS_PORT) & 0x02 == 0 {
+                break;
+            }
+        }
+        outb(KBC_STATUS_PORT, KBC_RESET_CMD);
+
+        triple_fault();
+    }
+}
+
+/// Power off through the ACPI PM1 control blocks, falling back to the
+/// emulator-specific shutdown ports
+pub fn shutdown() -> ! {
+    unsafe {
+        asm!("cli", options(nostack));
+    }
+
+    if let (Some(fadt), Some((typ_a, typ_b))) = (acpi::get_info().fadt, acpi::s5_sleep_type()) {
+        unsafe {
+            enable_acpi_mode(&fadt);
+            enter_sleep_state(&fadt, typ_a, typ_b);
+        }
+    }
+
+    unsafe {
+        // QEMU (newer machine types)
+        outw(0x604, 0x2000);
+        // Bochs and older QEMU
+        outw(0xB004, 0x2000);
+        // VirtualBox
+        outw(0x4004, 0x3400);
+    }
+
+    // Nothing worked; park the CPU
+    loop {
+        unsafe {
+            asm!("cli; hlt", options(nostack));
+        }
+    }
+}
+
+/// Write SLP_TYPa/SLP_TYPb to the PM1 control blocks and set SLP_EN. For
+/// S5 this does not return; for S3 execution resumes at the waking vector.
+pub unsafe fn enter_sleep_state(fadt: &acpi::Fadt, typ_a: u16, typ_b: u16) {
+    if fadt.pm1a_cnt_blk != 0 {
+        let port = fadt.pm1a_cnt_blk as u16;
+        outw(port, (inw(port) & !(0x7 << 10)) | (typ_a << 10) | SLP_EN);
+    }
+    if fadt.pm1b_cnt_blk != 0 {
+        let port = fadt.pm1b_cnt_blk as u16;
+        outw(port, (inw(port) & !(0x7 << 10)) | (typ_b << 10) | SLP_EN);
+    }
+}
+
+/// Hand the PM registers from SMM to the OS if the firmware hasn't already
+pub unsafe fn enable_acpi_mode(fadt: &acpi::Fadt) {
+    if fadt.pm1a_cnt_blk == 0 || inw(fadt.pm1a_cnt_blk as u16) & SCI_EN != 0 {
+        return;
+    }
+    if fadt.smi_cmd == 0 || fadt.acpi_enable == 0 {
+        return;
+    }
+
+    outb(fadt.smi_cmd as u16, fadt.acpi_enable);
+    for _ in 0..0x100000 {
+        if inw(fadt.pm1a_cnt_blk as u16) & SCI_EN != 0 {
+            break;
+        }
+    }
+}
+
+unsafe fn triple_fault() -> ! {
+    #[repr(C, packed)]
+    struct NullIdt {
+        limit: u16,
+        base: u32,
+    }
+    static NULL_IDT: NullIdt = NullIdt { limit: 0, base: 0 };
+
+    asm!("lidt [{}]", "int3", in(reg) &NULL_IDT, options(noreturn));
+}
@@ -0,0 +1,69 @@
+use crate::clock;
+use crate::env;
+use crate::filesystem::{get_filesystem, MAX_PATH_LEN};
+use crate::sink::Sink;
+use crate::theme;
+use crate::vga_colors::Color;
+
+// Renders the `PROMPT` variable before each command is read. Escapes:
+//   \u  user name (`USER`)      \h  host name
+//   \w  current directory       \t  time since boot as HH:MM:SS
+//   \cN foreground color N, a VGA color 0-f; \c- goes back to the theme's
+//   \n  new line                \\  a backslash
+// Anything else is printed as it is. After a command fails (`$?` isn't 0)
+// the prompt is drawn in light red instead of the theme's color.
+
+const HOST_NAME: &str = "hyzeos";
+const DEFAULT_USER: &[u8] = b"root";
+
+pub fn render(template: &[u8], out: &mut dyn Sink) {
+    let palette = theme::palette();
+    let prompt = if env::get().status() != 0 { Color::LightRed } else { palette.prompt };
+    out.set_color(prompt, palette.background);
+    let mut bytes = template.iter();
+    while let Some(&b) = bytes.next() {
+        if b != b'\\' {
+            out.write_byte(b);
+            continue;
+        }
+        match bytes.next() {
+            Some(b'u') => out.write_bytes(env::get().get(b"USER").unwrap_or(DEFAULT_USER)),
+            Some(b'h') => out.write_str(HOST_NAME),
+            Some(b'w') => {
+                let mut path = [0u8; MAX_PATH_LEN];
+                let len = get_filesystem().get_current_path(&mut path);
+                out.write_bytes(&path[..len]);
+            }
+            Some(b't') => {
+                let seconds = clock::uptime_seconds();
+                write!(out, "{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60);
+            }
+            Some(b'n') => out.write_byte(b'\n'),
+            Some(b'c') => match bytes.next() {
+                Some(b'-') => out.set_color(prompt, palette.background),
+                Some(&digit) => match (digit as char).to_digit(16).and_then(|i| Color::from_index(i as u8)) {
+                    Some(color) => out.set_color(color, palette.background),
+                    None => out.write_bytes(&[b'\\', b'c', digit]),
+                },
+                None => out.write_str("\\c"),
+            },
+            Some(&other) => out.write_bytes(&[b'\\', other]),
+            None => out.write_byte(b'\\'),
+        }
+    }
+    out.set_color(palette.text, palette.background);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::Capture;
+
+    #[test_case]
+    fn escapes_expand_and_unknown_ones_stay() {
+        let mut buffer = [0u8; 64];
+        let mut out = Capture::new(&mut buffer);
+        render(b"\\u@\\h:\\w\\ca\\c-\\\\ \\q\\cz>", &mut out);
+        assert_eq!(out.as_bytes(), b"root@hyzeos:/\\ \\q\\cz>");
+    }
+}
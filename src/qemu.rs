@@ -0,0 +1,19 @@
+use crate::io::outb;
+
+/// I/O port of QEMU's `isa-debug-exit` device
+const DEBUG_EXIT_PORT: u16 = 0xF4;
+
+/// QEMU exits with status `(code << 1) | 1`, so success becomes 33 and
+/// failure 35; anything else means the kernel never reached the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+pub fn exit_qemu(code: ExitCode) {
+    unsafe {
+        outb(DEBUG_EXIT_PORT, code as u8);
+    }
+}
@@ -0,0 +1,233 @@
+use crate::filesystem::{get_filesystem, MAX_FILE_SIZE};
+use crate::idt;
+use crate::writer::Writer;
+
+/// A recording is this magic followed by events, each a tag byte and its
+/// operands. Delays are in 10 ms timer ticks.
+const MAGIC: &[u8] = b"REC1";
+
+const TAG_BYTE: u8 = 0x01;
+const TAG_COLOR: u8 = 0x02;
+const TAG_CLEAR: u8 = 0x03;
+const TAG_POSITION: u8 = 0x04;
+const TAG_DELAY: u8 = 0x05;
+
+/// Largest operand list, the two-byte delay or position
+const MAX_EVENT_LEN: usize = 3;
+
+const ESC_SCANCODE: u8 = 0x01;
+const MAX_NAME_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Byte(u8),
+    Color(u8),
+    Clear,
+    Position(u8, u8),
+    Delay(u16),
+}
+
+struct Recording {
+    active: bool,
+    /// Set once an event didn't fit and recording stopped early
+    truncated: bool,
+    last_tick: u32,
+    /// File the recording is saved to on `stop`
+    name: [u8; MAX_NAME_LEN],
+    name_len: usize,
+    len: usize,
+    data: [u8; MAX_FILE_SIZE],
+}
+
+static mut RECORDING: Recording = Recording {
+    active: false,
+    truncated: false,
+    last_tick: 0,
+    name: [0; MAX_NAME_LEN],
+    name_len: 0,
+    len: 0,
+    data: [0; MAX_FILE_SIZE],
+};
+
+pub fn is_recording() -> bool {
+    unsafe { RECORDING.active }
+}
+
+/// Start capturing console output into `name`, starting from `color`
+pub fn start(name: &[u8], color: u8) -> Result<(), &'static str> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err("File name must be 1-32 characters");
+    }
+    unsafe {
+        if RECORDING.active {
+            return Err("Already recording");
+        }
+        RECORDING.name[..name.len()].copy_from_slice(name);
+        RECORDING.name_len = name.len();
+        RECORDING.data[..MAGIC.len()].copy_from_slice(MAGIC);
+        RECORDING.len = MAGIC.len();
+        RECORDING.truncated = false;
+        RECORDING.last_tick = idt::get_ticks();
+        RECORDING.active = true;
+    }
+    record(Event::Color(color));
+    Ok(())
+}
+
+/// Stop capturing and save the recording. Returns its file name and
+/// whether it was cut short by the file size limit.
+pub fn stop() -> Result<(&'static [u8], bool), &'static str> {
+    unsafe {
+        if !RECORDING.active && RECORDING.len == 0 {
+            return Err("Not recording");
+        }
+        RECORDING.active = false;
+        let name = &RECORDING.name[..RECORDING.name_len];
+        let result = get_filesystem().write_file(name, &RECORDING.data[..RECORDING.len]);
+        RECORDING.len = 0;
+        result.map(|()| (name, RECORDING.truncated))
+    }
+}
+
+/// Append an event, preceded by the time since the previous one. Called by
+/// the `Writer`, so it must stay cheap while nothing is being recorded.
+pub fn record(event: Event) {
+    unsafe {
+        if !RECORDING.active {
+            return;
+        }
+        let now = idt::get_ticks();
+        let elapsed = now.wrapping_sub(RECORDING.last_tick);
+        RECORDING.last_tick = now;
+        if elapsed > 0 {
+            push(Event::Delay(elapsed.min(u16::MAX as u32) as u16));
+        }
+        push(event);
+    }
+}
+
+unsafe fn push(event: Event) {
+    let mut bytes = [0u8; 1 + MAX_EVENT_LEN];
+    let len = encode(event, &mut bytes);
+    if RECORDING.len + len > MAX_FILE_SIZE {
+        // Keep what fits; `stop` still saves it
+        RECORDING.active = false;
+        RECORDING.truncated = true;
+        return;
+    }
+    RECORDING.data[RECORDING.len..RECORDING.len + len].copy_from_slice(&bytes[..len]);
+    RECORDING.len += len;
+}
+
+fn encode(event: Event, out: &mut [u8; 1 + MAX_EVENT_LEN]) -> usize {
+    match event {
+        Event::Byte(b) => {
+            out[..2].copy_from_slice(&[TAG_BYTE, b]);
+            2
+        }
+        Event::Color(c) => {
+            out[..2].copy_from_slice(&[TAG_COLOR, c]);
+            2
+        }
+        Event::Clear => {
+            out[0] = TAG_CLEAR;
+            1
+        }
+        Event::Position(col, row) => {
+            out[..3].copy_from_slice(&[TAG_POSITION, col, row]);
+            3
+        }
+        Event::Delay(ticks) => {
+            out[0] = TAG_DELAY;
+            out[1..3].copy_from_slice(&ticks.to_le_bytes());
+            3
+        }
+    }
+}
+
+/// Events of a recording, stopping at the first malformed one
+pub struct Events<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Iterator for Events<'a> {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        let data = &self.data[self.pos..];
+        let (event, len) = match *data {
+            [TAG_BYTE, b, ..] => (Event::Byte(b), 2),
+            [TAG_COLOR, c, ..] => (Event::Color(c), 2),
+            [TAG_CLEAR, ..] => (Event::Clear, 1),
+            [TAG_POSITION, col, row, ..] => (Event::Position(col, row), 3),
+            [TAG_DELAY, lo, hi, ..] => (Event::Delay(u16::from_le_bytes([lo, hi])), 3),
+            _ => return None,
+        };
+        self.pos += len;
+        Some(event)
+    }
+}
+
+pub fn events(data: &[u8]) -> Result<Events<'_>, &'static str> {
+    if !data.starts_with(MAGIC) {
+        return Err("Not a recording");
+    }
+    Ok(Events { data, pos: MAGIC.len() })
+}
+
+/// Play a recording back onto `writer`, `speed` times faster than it was
+/// captured. ESC stops playback early. Returns false if it was stopped.
+pub fn replay(data: &[u8], speed: u32, writer: &mut Writer) -> Result<bool, &'static str> {
+    let speed = speed.max(1);
+    idt::flush_buffer();
+
+    for event in events(data)? {
+        match event {
+            Event::Byte(b) => writer.write_byte(b),
+            Event::Color(c) => writer.set_color_code(c),
+            Event::Clear => writer.clear(),
+            Event::Position(col, row) => writer.set_position(col as usize, row as usize),
+            Event::Delay(ticks) => {
+                let ticks = ticks as u32 / speed;
+                let start = idt::get_ticks();
+                while idt::get_ticks().wrapping_sub(start) < ticks {
+                    if idt::get_scancode() == Some(ESC_SCANCODE) {
+                        return Ok(false);
+                    }
+                    idt::wait_for_interrupt();
+                }
+            }
+        }
+        if idt::get_scancode() == Some(ESC_SCANCODE) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn events_round_trip() {
+        let mut data = [0u8; 32];
+        data[..MAGIC.len()].copy_from_slice(MAGIC);
+        let mut len = MAGIC.len();
+        let recorded = [Event::Clear, Event::Color(0x1F), Event::Delay(300), Event::Byte(b'A'), Event::Position(79, 24)];
+        for &event in recorded.iter() {
+            let mut bytes = [0u8; 1 + MAX_EVENT_LEN];
+            let n = encode(event, &mut bytes);
+            data[len..len + n].copy_from_slice(&bytes[..n]);
+            len += n;
+        }
+
+        let mut parsed = events(&data[..len]).unwrap();
+        for &event in recorded.iter() {
+            assert_eq!(parsed.next(), Some(event));
+        }
+        assert_eq!(parsed.next(), None);
+        assert!(events(b"SCR1").is_err());
+    }
+}
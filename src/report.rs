@@ -0,0 +1,209 @@
+use core::fmt;
+
+use crate::sink::Sink;
+use crate::vga_colors::Color;
+
+// Key/value reports in sections, written once and output as aligned text
+// for the screen or as JSON for machines: `hwprobe` and the HTTP status
+// pages use them.
+
+/// What a report is written through; one implementation per format
+pub trait Report {
+    fn section(&mut self, name: &str);
+    fn value(&mut self, key: &str, value: fmt::Arguments);
+    fn flag(&mut self, key: &str, on: bool);
+    fn begin_list(&mut self, key: &str);
+    fn item(&mut self, value: fmt::Arguments);
+    fn end_list(&mut self);
+    fn finish(&mut self);
+}
+
+/// Labels in a column, lists wrapped under the value column
+pub struct TextReport<'a> {
+    out: &'a mut dyn Sink,
+    /// Whether the open list puts each item on its own line
+    item_per_line: bool,
+}
+
+const TEXT_VALUE_COLUMN: usize = 16;
+const TEXT_WIDTH: usize = 78;
+
+impl<'a> TextReport<'a> {
+    pub fn new(out: &'a mut dyn Sink) -> Self {
+        Self { out, item_per_line: false }
+    }
+
+    fn label(&mut self, key: &str) {
+        self.out.write_str("  ");
+        self.out.write_str(key);
+        self.out.pad_to(TEXT_VALUE_COLUMN);
+    }
+}
+
+impl<'a> Report for TextReport<'a> {
+    fn section(&mut self, name: &str) {
+        self.out.set_color(Color::Yellow, Color::Black);
+        self.out.write_str(name);
+        self.out.write_byte(b'\n');
+        self.out.set_color(Color::White, Color::Black);
+    }
+
+    fn value(&mut self, key: &str, value: fmt::Arguments) {
+        self.label(key);
+        self.out.write_fmt(value);
+        self.out.write_byte(b'\n');
+    }
+
+    fn flag(&mut self, key: &str, on: bool) {
+        self.value(key, format_args!("{}", if on { "yes" } else { "no" }));
+    }
+
+    fn begin_list(&mut self, key: &str) {
+        self.label(key);
+        // Long items such as PCI devices read better one per line
+        self.item_per_line = key == "devices";
+    }
+
+    fn item(&mut self, value: fmt::Arguments) {
+        let column = self.out.column();
+        if (self.item_per_line && column > TEXT_VALUE_COLUMN) || column > TEXT_WIDTH - 12 {
+            self.out.write_byte(b'\n');
+        } else if column > TEXT_VALUE_COLUMN {
+            self.out.write_byte(b' ');
+        }
+        self.out.pad_to(TEXT_VALUE_COLUMN);
+        self.out.write_fmt(value);
+    }
+
+    fn end_list(&mut self) {
+        if self.out.column() == TEXT_VALUE_COLUMN {
+            self.out.write_str("none");
+        }
+        self.out.write_byte(b'\n');
+    }
+
+    fn finish(&mut self) {}
+}
+
+/// One JSON object: a member object per section, lists as string arrays
+pub struct JsonReport<'a> {
+    out: &'a mut dyn Sink,
+    in_section: bool,
+    /// Whether the next member or item needs a comma before it
+    needs_comma: bool,
+}
+
+impl<'a> JsonReport<'a> {
+    pub fn new(out: &'a mut dyn Sink) -> Self {
+        out.write_byte(b'{');
+        Self { out, in_section: false, needs_comma: false }
+    }
+
+    fn separator(&mut self) {
+        if self.needs_comma {
+            self.out.write_byte(b',');
+        }
+        self.needs_comma = true;
+    }
+
+    fn string(&mut self, value: fmt::Arguments) {
+        struct Escaper<'b>(&'b mut dyn Sink);
+
+        impl<'b> fmt::Write for Escaper<'b> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                for b in s.bytes() {
+                    match b {
+                        b'"' | b'\\' => {
+                            self.0.write_byte(b'\\');
+                            self.0.write_byte(b);
+                        }
+                        b if b < b' ' => write!(self.0, "\\u{:04x}", b),
+                        b => self.0.write_byte(b),
+                    }
+                }
+                Ok(())
+            }
+        }
+
+        self.out.write_byte(b'"');
+        let _ = fmt::write(&mut Escaper(&mut *self.out), value);
+        self.out.write_byte(b'"');
+    }
+
+    fn key(&mut self, key: &str) {
+        self.separator();
+        self.string(format_args!("{}", key));
+        self.out.write_byte(b':');
+    }
+}
+
+impl<'a> Report for JsonReport<'a> {
+    fn section(&mut self, name: &str) {
+        if self.in_section {
+            self.out.write_byte(b'}');
+            self.needs_comma = true;
+        }
+        self.key(name);
+        self.out.write_byte(b'{');
+        self.in_section = true;
+        self.needs_comma = false;
+    }
+
+    fn value(&mut self, key: &str, value: fmt::Arguments) {
+        self.key(key);
+        self.string(value);
+    }
+
+    fn flag(&mut self, key: &str, on: bool) {
+        self.key(key);
+        self.out.write_str(if on { "true" } else { "false" });
+    }
+
+    fn begin_list(&mut self, key: &str) {
+        self.key(key);
+        self.out.write_byte(b'[');
+        self.needs_comma = false;
+    }
+
+    fn item(&mut self, value: fmt::Arguments) {
+        self.separator();
+        self.string(value);
+    }
+
+    fn end_list(&mut self) {
+        self.out.write_byte(b']');
+        self.needs_comma = true;
+    }
+
+    fn finish(&mut self) {
+        if self.in_section {
+            self.out.write_byte(b'}');
+        }
+        self.out.write_str("}\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::Capture;
+
+    #[test_case]
+    fn json_nests_sections_and_escapes() {
+        let mut buffer = [0u8; 128];
+        let mut capture = Capture::new(&mut buffer);
+        {
+            let mut report = JsonReport::new(&mut capture);
+            report.section("a");
+            report.value("name", format_args!("say \"hi\""));
+            report.flag("ok", true);
+            report.section("b");
+            report.begin_list("list");
+            report.item(format_args!("x"));
+            report.item(format_args!("y"));
+            report.end_list();
+            report.finish();
+        }
+        assert_eq!(capture.as_bytes(), &b"{\"a\":{\"name\":\"say \\\"hi\\\"\",\"ok\":true},\"b\":{\"list\":[\"x\",\"y\"]}}\n"[..]);
+    }
+}
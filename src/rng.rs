@@ -0,0 +1,210 @@
+use core::arch::asm;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::clock;
+use crate::cpu::cpuid;
+use crate::sha256::{Sha256, DIGEST_LEN};
+use crate::tsc;
+
+// Random numbers for salts, TCP sequence numbers, `random` and `uuid`.
+// With RDRAND (CPUID leaf 1, ECX bit 30; QEMU needs `-cpu max` or `host`)
+// numbers come straight from the CPU. Without it, or when RDRAND keeps
+// failing, they come from a SHA-256 generator: a key hashed with a counter
+// for each block of output. Its key is reseeded before every request from
+// the TSC and from the timing of keyboard interrupts, which `add_entropy`
+// folds together, and from RDRAND when there is one.
+
+/// RDRAND can come back empty-handed when its buffer runs dry; Intel
+/// suggests ten tries before giving up
+const RDRAND_RETRIES: usize = 10;
+
+/// SHA-256 in counter mode. Not a vetted DRBG, but nothing it has put out
+/// tells anything about its key, and each request leaves a new key behind.
+struct HashGenerator {
+    key: [u8; DIGEST_LEN],
+    counter: u64,
+}
+
+impl HashGenerator {
+    const fn new() -> Self {
+        Self { key: [0; DIGEST_LEN], counter: 0 }
+    }
+
+    /// Hash `input` into the key
+    fn mix(&mut self, input: &[u8]) {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.key);
+        hasher.update(input);
+        self.key = hasher.finish();
+    }
+
+    fn fill(&mut self, out: &mut [u8]) {
+        for chunk in out.chunks_mut(DIGEST_LEN) {
+            let block = self.block();
+            chunk.copy_from_slice(&block[..chunk.len()]);
+        }
+        // So what was handed out can't be worked back to from the key
+        self.key = self.block();
+    }
+
+    fn block(&mut self) -> [u8; DIGEST_LEN] {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.key);
+        hasher.update(&self.counter.to_le_bytes());
+        self.counter += 1;
+        hasher.finish()
+    }
+}
+
+static mut GENERATOR: HashGenerator = HashGenerator::new();
+static mut HAS_RDRAND: bool = false;
+/// TSC readings from interrupts, folded together until the next request
+static ENTROPY: AtomicU32 = AtomicU32::new(0);
+static EVENTS: AtomicU32 = AtomicU32::new(0);
+
+/// Look for RDRAND and seed the generator. Returns whether RDRAND is there.
+pub fn init() -> bool {
+    let rdrand = cpuid(1).2 & (1 << 30) != 0 && rdrand32().is_some();
+    unsafe {
+        HAS_RDRAND = rdrand;
+    }
+    reseed();
+    rdrand
+}
+
+pub fn has_rdrand() -> bool {
+    unsafe { HAS_RDRAND }
+}
+
+/// Keyboard interrupts seen so far, each a little timing entropy
+pub fn events() -> u32 {
+    EVENTS.load(Ordering::Relaxed)
+}
+
+/// Fold the time of an interrupt into the pool. Called from the keyboard
+/// handler, so it only reads the TSC.
+pub fn add_entropy() {
+    let sample = tsc::read() as u32;
+    let pool = ENTROPY.load(Ordering::Relaxed);
+    ENTROPY.store(pool.rotate_left(7) ^ sample, Ordering::Relaxed);
+    EVENTS.fetch_add(1, Ordering::Relaxed);
+}
+
+fn reseed() {
+    let mut seed = [0u8; 24];
+    seed[..8].copy_from_slice(&tsc::read().to_le_bytes());
+    seed[8..12].copy_from_slice(&ENTROPY.swap(0, Ordering::Relaxed).to_le_bytes());
+    seed[12..20].copy_from_slice(&clock::now_ns().to_le_bytes());
+    if has_rdrand() {
+        seed[20..24].copy_from_slice(&rdrand32().unwrap_or(0).to_le_bytes());
+    }
+    unsafe { GENERATOR.mix(&seed) };
+}
+
+fn rdrand32() -> Option<u32> {
+    for _ in 0..RDRAND_RETRIES {
+        let value: u32;
+        let ok: u8;
+        unsafe {
+            asm!("rdrand {0:e}", "setc {1}", out(reg) value, out(reg_byte) ok, options(nomem, nostack));
+        }
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+pub fn random_u32() -> u32 {
+    if has_rdrand() {
+        if let Some(value) = rdrand32() {
+            return value;
+        }
+    }
+    let mut bytes = [0u8; 4];
+    fill_bytes(&mut bytes);
+    u32::from_le_bytes(bytes)
+}
+
+pub fn fill_bytes(out: &mut [u8]) {
+    if has_rdrand() {
+        let mut filled = 0;
+        while filled < out.len() {
+            let value = match rdrand32() {
+                Some(value) => value.to_le_bytes(),
+                None => break,
+            };
+            let take = (out.len() - filled).min(4);
+            out[filled..filled + take].copy_from_slice(&value[..take]);
+            filled += take;
+        }
+        if filled == out.len() {
+            return;
+        }
+    }
+    reseed();
+    unsafe { GENERATOR.fill(out) };
+}
+
+/// A number below `bound`, every one as likely as the others
+pub fn below(bound: u32) -> u32 {
+    // Values past the last whole multiple of `bound` would favour the
+    // small results
+    let limit = u32::MAX - u32::MAX % bound;
+    loop {
+        let value = random_u32();
+        if value < limit {
+            return value % bound;
+        }
+    }
+}
+
+/// A random (version 4) UUID, e.g. `0f8e1c4a-5b7d-4e2f-9a3c-6d1b8e4f2a70`
+pub fn uuid() -> [u8; 36] {
+    let mut bytes = [0u8; 16];
+    fill_bytes(&mut bytes);
+    format_uuid(bytes)
+}
+
+fn format_uuid(mut bytes: [u8; 16]) -> [u8; 36] {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    bytes[6] = bytes[6] & 0x0F | 0x40;
+    bytes[8] = bytes[8] & 0x3F | 0x80;
+    let mut text = [b'-'; 36];
+    let mut at = 0;
+    for (i, &byte) in bytes.iter().enumerate() {
+        if i == 4 || i == 6 || i == 8 || i == 10 {
+            at += 1;
+        }
+        text[at] = DIGITS[(byte >> 4) as usize];
+        text[at + 1] = DIGITS[(byte & 0xF) as usize];
+        at += 2;
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn generator_and_uuids() {
+        let (mut a, mut b) = (HashGenerator::new(), HashGenerator::new());
+        a.mix(b"seed");
+        b.mix(b"seed");
+        let (mut x, mut y) = ([0u8; 40], [0u8; 40]);
+        a.fill(&mut x);
+        b.fill(&mut y);
+        assert_eq!(&x[..], &y[..]);
+        a.fill(&mut x);
+        assert!(x[..] != y[..]);
+        b.mix(b"more");
+        b.fill(&mut y);
+        assert!(x[..] != y[..]);
+
+        let uuid = format_uuid([0xFF; 16]);
+        assert_eq!(&uuid[..], &b"ffffffff-ffff-4fff-bfff-ffffffffffff"[..]);
+        let uuid = format_uuid([0; 16]);
+        assert_eq!(&uuid[..], &b"00000000-0000-4000-8000-000000000000"[..]);
+    }
+}
@@ -0,0 +1,127 @@
+use crate::io::{inb, outb};
+
+// The CMOS real-time clock: the date and time of day kept by the battery
+// backed clock, as the firmware set it (usually UTC under QEMU). Registers
+// may hold BCD or binary and 12 or 24-hour time, as status register B says.
+
+const CMOS_INDEX: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+
+/// Status A: the clock is updating and its registers may be half written
+const UPDATE_IN_PROGRESS: u8 = 0x80;
+/// Status B: hours run 0-23 rather than 1-12 with a PM bit
+const HOURS_24: u8 = 0x02;
+/// Status B: registers hold binary rather than BCD
+const BINARY: u8 = 0x04;
+const HOUR_PM: u8 = 0x80;
+
+const MONTH_NAMES: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June",
+    "July", "August", "September", "October", "November", "December",
+];
+const DAY_NAMES: [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1-12
+    pub month: u8,
+    /// 1-31
+    pub day: u8,
+    /// 0-23
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Decode raw clock registers (seconds, minutes, hours, day, month,
+    /// year) in the format status register B gives
+    fn from_registers(raw: [u8; 6], status_b: u8) -> Self {
+        let decode = |value: u8| if status_b & BINARY != 0 { value } else { (value >> 4) * 10 + (value & 0x0F) };
+        let mut hour = decode(raw[2] & !HOUR_PM);
+        if status_b & HOURS_24 == 0 {
+            // 12 AM is midnight and 12 PM noon
+            hour %= 12;
+            if raw[2] & HOUR_PM != 0 {
+                hour += 12;
+            }
+        }
+        // Two-digit years from 1970 on
+        let year = decode(raw[5]) as u16;
+        Self {
+            year: if year < 70 { 2000 + year } else { 1900 + year },
+            month: decode(raw[4]),
+            day: decode(raw[3]),
+            hour,
+            minute: decode(raw[1]),
+            second: decode(raw[0]),
+        }
+    }
+
+    pub fn month_name(&self) -> &'static str {
+        MONTH_NAMES[(self.month.clamp(1, 12) - 1) as usize]
+    }
+
+    /// Sunday through Saturday, worked out from the date
+    pub fn weekday_name(&self) -> &'static str {
+        // Sakamoto's method
+        const OFFSETS: [u16; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+        let month = self.month.clamp(1, 12) as usize;
+        let year = if month < 3 { self.year - 1 } else { self.year };
+        let day = year + year / 4 - year / 100 + year / 400 + OFFSETS[month - 1] + self.day as u16;
+        DAY_NAMES[(day % 7) as usize]
+    }
+}
+
+fn read_register(reg: u8) -> u8 {
+    unsafe {
+        outb(CMOS_INDEX, reg);
+        inb(CMOS_DATA)
+    }
+}
+
+fn read_raw() -> [u8; 6] {
+    while read_register(REG_STATUS_A) & UPDATE_IN_PROGRESS != 0 {}
+    [REG_SECONDS, REG_MINUTES, REG_HOURS, REG_DAY, REG_MONTH, REG_YEAR].map(read_register)
+}
+
+/// The current date and time
+pub fn now() -> DateTime {
+    // An update can still start partway through; read until two agree
+    let mut raw = read_raw();
+    loop {
+        let again = read_raw();
+        if again == raw {
+            break;
+        }
+        raw = again;
+    }
+    DateTime::from_registers(raw, read_register(REG_STATUS_B))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn decodes_bcd_and_12_hour_registers() {
+        let time = DateTime::from_registers([0x59, 0x07, 0x12 | HOUR_PM, 0x16, 0x10, 0x26], 0);
+        assert_eq!(time, DateTime { year: 2026, month: 10, day: 16, hour: 12, minute: 7, second: 59 });
+        assert_eq!(DateTime::from_registers([0, 0, 0x12, 1, 1, 0x99], 0).hour, 0);
+
+        let time = DateTime::from_registers([5, 30, 23, 29, 2, 24], BINARY | HOURS_24);
+        assert_eq!(time, DateTime { year: 2024, month: 2, day: 29, hour: 23, minute: 30, second: 5 });
+        assert_eq!(time.weekday_name(), "Thursday");
+        assert_eq!(time.month_name(), "February");
+    }
+}
@@ -0,0 +1,173 @@
+use core::arch::naked_asm;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::acpi::{self, Fadt};
+use crate::apic;
+use crate::gdt;
+use crate::idt;
+use crate::io::{inw, outw};
+use crate::power;
+use crate::driver::{self, Driver};
+use crate::stackguard;
+
+// ACPI fixed events: the power and sleep buttons raise the SCI and set a
+// status bit in the PM1 event block. The handler only records the press;
+// the shell acts on it outside interrupt context.
+
+/// PM1 status and enable bits, identical in both registers
+const PWRBTN: u16 = 1 << 8;
+const SLPBTN: u16 = 1 << 9;
+
+/// FADT flags: set when the button is a control method device instead of
+/// a fixed feature
+const FADT_PWR_BUTTON: u32 = 1 << 4;
+const FADT_SLP_BUTTON: u32 = 1 << 5;
+
+/// The SCI is level triggered and active low unless the MADT overrides it
+const SCI_DEFAULT_FLAGS: u16 = 0x0F;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    PowerButton,
+    SleepButton,
+}
+
+static POWER_PRESSED: AtomicBool = AtomicBool::new(false);
+static SLEEP_PRESSED: AtomicBool = AtomicBool::new(false);
+static mut SCI_IRQ: u8 = 0;
+static mut ENABLED: u16 = 0;
+
+/// PM1a and PM1b status register ports; PM1b is optional
+fn pm1_blocks(fadt: &Fadt) -> [u16; 2] {
+    [fadt.pm1a_evt_blk as u16, fadt.pm1b_evt_blk as u16]
+}
+
+/// Switch to ACPI mode and enable the fixed-feature buttons. Runs after
+/// `apic::init`, so the SCI is routed to whichever controller is in use.
+/// Returns the SCI's IRQ.
+pub fn init() -> Result<u8, &'static str> {
+    let fadt = acpi::get_info().fadt.ok_or("No FADT")?;
+    if fadt.pm1a_evt_blk == 0 {
+        return Err("No PM1 event block");
+    }
+    if fadt.sci_int >= 16 {
+        return Err("SCI is not an ISA IRQ");
+    }
+
+    let mut enable = 0;
+    if fadt.flags & FADT_PWR_BUTTON == 0 {
+        enable |= PWRBTN;
+    }
+    if fadt.flags & FADT_SLP_BUTTON == 0 {
+        enable |= SLPBTN;
+    }
+    if enable == 0 {
+        return Err("No fixed-feature buttons");
+    }
+
+    let irq = fadt.sci_int as u8;
+    unsafe {
+        SCI_IRQ = irq;
+        ENABLED = enable;
+        power::enable_acpi_mode(&fadt);
+        enable_buttons(&fadt, enable);
+    }
+    driver::register(Driver { name: "acpi-buttons", suspend: no_op, resume })?;
+
+    idt::set_gate(0x20 + irq, sci_interrupt_handler as u32, false);
+    if apic::is_enabled() {
+        apic::enable_irq(irq, SCI_DEFAULT_FLAGS);
+    } else {
+        idt::unmask_pic_irq(irq);
+    }
+    Ok(irq)
+}
+
+unsafe fn enable_buttons(fadt: &Fadt, enable: u16) {
+    for &status in pm1_blocks(fadt).iter().filter(|&&port| port != 0) {
+        // Drop earlier presses, then enable
+        outw(status, PWRBTN | SLPBTN);
+        outw(status + fadt.pm1_evt_len as u16 / 2, enable);
+    }
+}
+
+fn no_op() -> Result<(), &'static str> {
+    Ok(())
+}
+
+/// After waking from S3: the enable bits are lost, and the press that woke
+/// the machine must not shut it down
+fn resume() -> Result<(), &'static str> {
+    let fadt = acpi::get_info().fadt.ok_or("No FADT")?;
+    let enable = unsafe { ENABLED };
+    unsafe {
+        power::enable_acpi_mode(&fadt);
+        enable_buttons(&fadt, enable);
+    }
+    POWER_PRESSED.store(false, Ordering::Release);
+    SLEEP_PRESSED.store(false, Ordering::Release);
+
+    let port = fadt.pm1a_evt_blk as u16 + fadt.pm1_evt_len as u16 / 2;
+    if unsafe { inw(port) } & enable != enable {
+        return Err("Button enable bits did not stick");
+    }
+    Ok(())
+}
+
+/// The oldest button press not yet handled, if any
+pub fn take_event() -> Option<Event> {
+    if POWER_PRESSED.swap(false, Ordering::Acquire) {
+        Some(Event::PowerButton)
+    } else if SLEEP_PRESSED.swap(false, Ordering::Acquire) {
+        Some(Event::SleepButton)
+    } else {
+        None
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn sci_handler_inner() {
+    let _canary = stackguard::Canary::new();
+    let irq = unsafe { SCI_IRQ };
+    idt::count_irq(irq as usize);
+
+    if let Some(fadt) = acpi::get_info().fadt {
+        for &port in pm1_blocks(&fadt).iter().filter(|&&port| port != 0) {
+            unsafe {
+                let status = inw(port) & (PWRBTN | SLPBTN);
+                if status == 0 {
+                    continue;
+                }
+                // Status bits are cleared by writing them back
+                outw(port, status);
+                if status & PWRBTN != 0 {
+                    POWER_PRESSED.store(true, Ordering::Release);
+                }
+                if status & SLPBTN != 0 {
+                    SLEEP_PRESSED.store(true, Ordering::Release);
+                }
+            }
+        }
+    }
+
+    idt::end_of_interrupt(irq as usize);
+}
+
+#[unsafe(naked)]
+#[no_mangle]
+pub unsafe extern "C" fn sci_interrupt_handler() {
+    naked_asm!(
+        "pusha",
+        "push ds",
+        "push es",
+        "mov ax, {kernel_data}",
+        "mov ds, ax",
+        "mov es, ax",
+        "call sci_handler_inner",
+        "pop es",
+        "pop ds",
+        "popa",
+        "iretd",
+        kernel_data = const gdt::KERNEL_DATA as u32,
+    );
+}
@@ -0,0 +1,153 @@
+use crate::filesystem::get_filesystem;
+use crate::hotkey;
+use crate::speaker;
+use crate::writer::Writer;
+
+const WIDTH: usize = 80;
+const HEIGHT: usize = 25;
+
+/// The color-preserving format is this magic, a width byte, a height byte,
+/// then the raw character/attribute pairs row by row
+const MAGIC: &[u8] = b"SCR1";
+const HEADER_LEN: usize = MAGIC.len() + 2;
+const CELLS_LEN: usize = HEADER_LEN + WIDTH * HEIGHT * 2;
+/// Each row trimmed, plus its newline
+const TEXT_LEN: usize = (WIDTH + 1) * HEIGHT;
+
+/// Longest base name that still leaves room for ".txt"/".scr"
+pub const MAX_BASE_LEN: usize = 27;
+
+pub fn init() {
+    let _ = hotkey::register(0, hotkey::KEY_PRINT_SCREEN, on_hotkey);
+}
+
+/// PrtSc: save under the next free "shotN" name, with a beep to confirm
+fn on_hotkey() {
+    let mut name = [0u8; MAX_BASE_LEN];
+    let len = next_free_name(&mut name);
+    match save(&name[..len]) {
+        Ok(()) => {
+            log_info!("screenshot: saved {}.txt", core::str::from_utf8(&name[..len]).unwrap_or("?"));
+            speaker::beep(1760, 30);
+        }
+        Err(e) => {
+            log_warn!("screenshot: {}", e);
+            speaker::error_beep();
+        }
+    }
+}
+
+/// Plain-text stand-in for a CP437 glyph
+fn ascii_for(ch: u8) -> u8 {
+    match ch {
+        0x20..=0x7E => ch,
+        0xC4 | 0xCD => b'-',
+        0xB3 | 0xBA => b'|',
+        0xB4..=0xDA => b'+',
+        0xB0..=0xB2 | 0xDB..=0xDF => b'#',
+        0 => b' ',
+        _ => b'.',
+    }
+}
+
+/// Write "<base>.txt" and "<base>.scr" in the current directory
+pub fn save(base: &[u8]) -> Result<(), &'static str> {
+    if base.is_empty() || base.len() > MAX_BASE_LEN {
+        return Err("Screenshot name must be 1-27 characters");
+    }
+
+    let mut text = [0u8; TEXT_LEN];
+    let mut text_len = 0;
+    let mut cells = [0u8; CELLS_LEN];
+    cells[..MAGIC.len()].copy_from_slice(MAGIC);
+    cells[MAGIC.len()] = WIDTH as u8;
+    cells[MAGIC.len() + 1] = HEIGHT as u8;
+
+    for row in 0..HEIGHT {
+        let line_start = text_len;
+        for col in 0..WIDTH {
+            let cell = Writer::cell(col, row);
+            let offset = HEADER_LEN + (row * WIDTH + col) * 2;
+            cells[offset] = cell as u8;
+            cells[offset + 1] = (cell >> 8) as u8;
+            text[text_len] = ascii_for(cell as u8);
+            text_len += 1;
+        }
+        while text_len > line_start && text[text_len - 1] == b' ' {
+            text_len -= 1;
+        }
+        text[text_len] = b'\n';
+        text_len += 1;
+    }
+
+    let fs = get_filesystem();
+    let mut name = [0u8; MAX_BASE_LEN + 4];
+    name[..base.len()].copy_from_slice(base);
+    name[base.len()..base.len() + 4].copy_from_slice(b".txt");
+    fs.write_file(&name[..base.len() + 4], &text[..text_len])?;
+    name[base.len()..base.len() + 4].copy_from_slice(b".scr");
+    fs.write_file(&name[..base.len() + 4], &cells)
+}
+
+/// Fill `name` with the first "shotN" whose text file doesn't exist yet
+pub fn next_free_name(name: &mut [u8; MAX_BASE_LEN]) -> usize {
+    let fs = get_filesystem();
+    let mut file = [0u8; MAX_BASE_LEN + 4];
+    for n in 1..1000u32 {
+        let mut len = 0;
+        for &b in b"shot" {
+            name[len] = b;
+            len += 1;
+        }
+        let mut digits = [0u8; 3];
+        let mut count = 0;
+        let mut value = n;
+        while value > 0 {
+            digits[count] = b'0' + (value % 10) as u8;
+            value /= 10;
+            count += 1;
+        }
+        for i in (0..count).rev() {
+            name[len] = digits[i];
+            len += 1;
+        }
+
+        file[..len].copy_from_slice(&name[..len]);
+        file[len..len + 4].copy_from_slice(b".txt");
+        if !fs.file_exists(&file[..len + 4]) {
+            return len;
+        }
+    }
+    // Give up and overwrite the last one
+    4 + 3
+}
+
+/// Paint a saved .scr file back onto the screen
+pub fn show(data: &[u8]) -> Result<(), &'static str> {
+    if data.len() != CELLS_LEN || !data.starts_with(MAGIC) {
+        return Err("Not a screenshot (.scr) file");
+    }
+    if data[MAGIC.len()] as usize != WIDTH || data[MAGIC.len() + 1] as usize != HEIGHT {
+        return Err("Screenshot size does not match the screen");
+    }
+    for row in 0..HEIGHT {
+        for col in 0..WIDTH {
+            let offset = HEADER_LEN + (row * WIDTH + col) * 2;
+            Writer::set_cell(col, row, data[offset] as u16 | (data[offset + 1] as u16) << 8);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn box_drawing_maps_to_ascii() {
+        assert_eq!(ascii_for(b'A'), b'A');
+        assert_eq!(ascii_for(0xC4), b'-');
+        assert_eq!(ascii_for(0xDA), b'+');
+        assert_eq!(ascii_for(0xDB), b'#');
+    }
+}
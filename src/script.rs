@@ -0,0 +1,126 @@
+// Shell scripts: one command per line, `#` comments, and `if <command>` /
+// `else` / `fi` blocks that test whether the command succeeded. Running the
+// commands, `$NAME` expansion included, is up to the shell; this module
+// only parses and keeps the state.
+
+const MAX_DEPTH: usize = 8;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Line<'a> {
+    /// Blank or a comment
+    Empty,
+    If(&'a [u8]),
+    Else,
+    Fi,
+    Command(&'a [u8]),
+}
+
+impl<'a> Line<'a> {
+    pub fn parse(line: &'a [u8]) -> Line<'a> {
+        let line = trim(line);
+        let split = line.iter().position(|&b| b == b' ').unwrap_or(line.len());
+        let (word, rest) = (&line[..split], trim(&line[split..]));
+        match word {
+            b"" => Line::Empty,
+            _ if word[0] == b'#' => Line::Empty,
+            b"if" => Line::If(rest),
+            b"else" if rest.is_empty() => Line::Else,
+            b"fi" if rest.is_empty() => Line::Fi,
+            _ => Line::Command(line),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Block {
+    /// Whether the lines around the block run
+    outer: bool,
+    condition: bool,
+    in_else: bool,
+}
+
+/// Nested `if` blocks and which of their branches run
+pub struct Blocks {
+    stack: [Block; MAX_DEPTH],
+    depth: usize,
+}
+
+impl Blocks {
+    pub const fn new() -> Self {
+        Self { stack: [Block { outer: true, condition: true, in_else: false }; MAX_DEPTH], depth: 0 }
+    }
+
+    /// Whether the current line runs
+    pub fn active(&self) -> bool {
+        match self.depth {
+            0 => true,
+            depth => {
+                let block = self.stack[depth - 1];
+                block.outer && block.condition != block.in_else
+            }
+        }
+    }
+
+    /// Open an `if` whose command succeeded or not. Inside a skipped block
+    /// neither branch runs, whatever `condition` is.
+    pub fn begin_if(&mut self, condition: bool) -> Result<(), &'static str> {
+        if self.depth == MAX_DEPTH {
+            return Err("if nested too deeply");
+        }
+        self.stack[self.depth] = Block { outer: self.active(), condition, in_else: false };
+        self.depth += 1;
+        Ok(())
+    }
+
+    pub fn begin_else(&mut self) -> Result<(), &'static str> {
+        match self.depth.checked_sub(1).map(|top| &mut self.stack[top]) {
+            Some(block) if !block.in_else => {
+                block.in_else = true;
+                Ok(())
+            }
+            Some(_) => Err("Second else in one if"),
+            None => Err("else without if"),
+        }
+    }
+
+    pub fn end(&mut self) -> Result<(), &'static str> {
+        self.depth = self.depth.checked_sub(1).ok_or("fi without if")?;
+        Ok(())
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.depth > 0
+    }
+}
+
+fn trim(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|&b| b != b' ' && b != b'\t' && b != b'\r').unwrap_or(bytes.len());
+    let end = bytes.iter().rposition(|&b| b != b' ' && b != b'\t' && b != b'\r').map_or(start, |i| i + 1);
+    &bytes[start..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn parses_lines_and_skips_branches() {
+        assert_eq!(Line::parse(b"  if cat  notes "), Line::If(b"cat  notes"));
+        assert_eq!(Line::parse(b"set X a b"), Line::Command(b"set X a b"));
+        assert_eq!(Line::parse(b"# note"), Line::Empty);
+
+        let mut blocks = Blocks::new();
+        blocks.begin_if(false).unwrap();
+        assert!(!blocks.active());
+        blocks.begin_if(true).unwrap();
+        assert!(!blocks.active());
+        blocks.begin_else().unwrap();
+        assert!(!blocks.active());
+        blocks.end().unwrap();
+        blocks.begin_else().unwrap();
+        assert!(blocks.active());
+        blocks.end().unwrap();
+        assert!(!blocks.is_open());
+        assert!(blocks.end().is_err());
+    }
+}
@@ -0,0 +1,323 @@
+use core::arch::naked_asm;
+use core::fmt;
+
+use crate::apic;
+use crate::gdt;
+use crate::idt;
+use crate::io::{inb, outb};
+use crate::metrics::{self, Counter};
+use crate::stackguard;
+use crate::sync::Mutex;
+
+pub const COM1: u16 = 0x3F8;
+
+/// The standard PC serial ports: base, name and IRQ
+pub const PORTS: [(u16, &str, u8); 4] = [(0x3F8, "com1", 4), (0x2F8, "com2", 3), (0x3E8, "com3", 4), (0x2E8, "com4", 3)];
+
+/// The UART's clock divided by 16; every rate is this over a divisor
+const MAX_BAUD: u32 = 115200;
+pub const DEFAULT_BAUD: u32 = 38400;
+/// Interrupt enable register: data received
+const IER_RECEIVED: u8 = 0x01;
+/// Line status register bits
+const LSR_DATA_READY: u8 = 0x01;
+const LSR_TRANSMIT_EMPTY: u8 = 0x20;
+/// ISA IRQs are edge triggered and active high
+const ISA_IRQ_FLAGS: u16 = 0;
+const RECEIVE_BUFFER_SIZE: usize = 1024;
+
+/// Minimal 16550 UART driver used for debug and test output
+pub struct SerialPort {
+    base: u16,
+}
+
+impl SerialPort {
+    pub const fn new(base: u16) -> Self {
+        Self { base }
+    }
+
+    pub fn init(&self) {
+        let _ = self.init_with_baud(DEFAULT_BAUD);
+    }
+
+    /// Set the port up for `baud`, which has to divide 115200
+    pub fn init_with_baud(&self, baud: u32) -> Result<(), &'static str> {
+        let divisor = divisor(baud)?;
+        unsafe {
+            outb(self.base + 1, 0x00); // Disable interrupts
+            outb(self.base + 3, 0x80); // Enable DLAB to set the divisor
+            outb(self.base, divisor as u8);
+            outb(self.base + 1, (divisor >> 8) as u8);
+            outb(self.base + 3, 0x03); // 8 bits, no parity, one stop bit
+            outb(self.base + 2, 0xC7); // Enable and clear FIFOs, 14-byte threshold
+            outb(self.base + 4, 0x0B); // DTR, RTS, OUT2
+        }
+        Ok(())
+    }
+
+    /// Whether a UART answers here: its scratch register keeps what is
+    /// written to it, while an empty port reads back 0xFF
+    pub fn is_present(&self) -> bool {
+        unsafe {
+            outb(self.base + 7, 0x5A);
+            inb(self.base + 7) == 0x5A
+        }
+    }
+
+    /// Echo a byte through the UART's loopback mode
+    pub fn self_test(&self) -> Result<(), &'static str> {
+        const PATTERN: u8 = 0xAE;
+        unsafe {
+            outb(self.base + 4, 0x1E); // Loopback, with OUT1/OUT2/RTS
+            outb(self.base, PATTERN);
+            let mut received = None;
+            for _ in 0..10000 {
+                if inb(self.base + 5) & 0x01 != 0 {
+                    received = Some(inb(self.base));
+                    break;
+                }
+            }
+            outb(self.base + 4, 0x0B);
+            match received {
+                Some(PATTERN) => Ok(()),
+                Some(_) => Err("Loopback byte came back changed"),
+                None => Err("Loopback byte never arrived"),
+            }
+        }
+    }
+
+    fn transmit_empty(&self) -> bool {
+        unsafe { inb(self.base + 5) & LSR_TRANSMIT_EMPTY != 0 }
+    }
+
+    /// A byte the UART has received, if there is one
+    pub fn read_byte(&self) -> Option<u8> {
+        unsafe {
+            if inb(self.base + 5) & LSR_DATA_READY == 0 {
+                return None;
+            }
+            Some(inb(self.base))
+        }
+    }
+
+    pub fn write_byte(&self, byte: u8) {
+        while !self.transmit_empty() {}
+        unsafe {
+            outb(self.base, byte);
+        }
+        metrics::add(Counter::SerialBytes, 1);
+    }
+
+    pub fn write_str(&self, s: &str) {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(byte);
+        }
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        SerialPort::write_str(self, s);
+        Ok(())
+    }
+}
+
+pub fn init() {
+    SerialPort::new(COM1).init();
+}
+
+/// Base and IRQ of the port called `name`, e.g. `com2`
+pub fn find_port(name: &[u8]) -> Option<(u16, u8)> {
+    PORTS.iter().find(|&&(_, port, _)| port.as_bytes() == name).map(|&(base, _, irq)| (base, irq))
+}
+
+/// Divisor latch value for `baud`
+fn divisor(baud: u32) -> Result<u16, &'static str> {
+    if baud == 0 || baud > MAX_BAUD || MAX_BAUD % baud != 0 {
+        return Err("Baud rate must divide 115200, e.g. 9600 or 115200");
+    }
+    Ok((MAX_BAUD / baud) as u16)
+}
+
+/// Bytes received on the port being listened to, waiting to be read
+struct ReceiveQueue {
+    data: [u8; RECEIVE_BUFFER_SIZE],
+    head: usize,
+    tail: usize,
+    /// Bytes dropped because the queue was full
+    overruns: u32,
+}
+
+impl ReceiveQueue {
+    const fn new() -> Self {
+        Self { data: [0; RECEIVE_BUFFER_SIZE], head: 0, tail: 0, overruns: 0 }
+    }
+
+    fn push(&mut self, byte: u8) {
+        let next_head = (self.head + 1) % RECEIVE_BUFFER_SIZE;
+        if next_head == self.tail {
+            self.overruns += 1;
+            return;
+        }
+        self.data[self.head] = byte;
+        self.head = next_head;
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.head == self.tail {
+            return None;
+        }
+        let byte = self.data[self.tail];
+        self.tail = (self.tail + 1) % RECEIVE_BUFFER_SIZE;
+        Some(byte)
+    }
+}
+
+static RECEIVED: Mutex<ReceiveQueue> = Mutex::new(ReceiveQueue::new());
+/// Base of the port whose receive interrupt is on
+static mut LISTENING: Option<u16> = None;
+/// The IRQ last listened on, kept for interrupts raced by `stop_listening`
+static mut LISTEN_IRQ: u8 = 0;
+
+/// Queue what arrives on the port at `base` from its receive interrupt, for
+/// `take_received`. One port at a time; COM1 stays output only otherwise.
+pub fn listen(base: u16, irq: u8) {
+    stop_listening();
+    unsafe {
+        LISTENING = Some(base);
+        LISTEN_IRQ = irq;
+    }
+    *RECEIVED.lock() = ReceiveQueue::new();
+    idt::set_gate(0x20 + irq, serial_interrupt_handler as u32, false);
+    if apic::is_enabled() {
+        apic::enable_irq(irq, ISA_IRQ_FLAGS);
+    } else {
+        idt::unmask_pic_irq(irq);
+    }
+    unsafe {
+        outb(base + 1, IER_RECEIVED);
+    }
+}
+
+/// Turn the receive interrupt back off. The IRQ line stays unmasked, but a
+/// UART with interrupts disabled never raises it.
+pub fn stop_listening() {
+    unsafe {
+        if let Some(base) = LISTENING.take() {
+            outb(base + 1, 0x00);
+        }
+    }
+}
+
+/// Move received bytes into `out`; returns how many
+pub fn take_received(out: &mut [u8]) -> usize {
+    let mut queue = RECEIVED.lock();
+    let mut count = 0;
+    while count < out.len() {
+        match queue.pop() {
+            Some(byte) => out[count] = byte,
+            None => break,
+        }
+        count += 1;
+    }
+    count
+}
+
+/// Bytes dropped since `listen` because they weren't taken in time
+pub fn overruns() -> u32 {
+    RECEIVED.lock().overruns
+}
+
+#[no_mangle]
+pub extern "C" fn serial_handler_inner() {
+    let _canary = stackguard::Canary::new();
+    let irq = unsafe { LISTEN_IRQ };
+    idt::count_irq(irq as usize);
+    // Nothing to do for one that arrives just after `stop_listening`
+    if let Some(base) = unsafe { LISTENING } {
+        // Emptying the FIFO also acknowledges the interrupt
+        let port = SerialPort::new(base);
+        let mut queue = RECEIVED.lock();
+        while let Some(byte) = port.read_byte() {
+            queue.push(byte);
+        }
+    }
+    idt::end_of_interrupt(irq as usize);
+}
+
+#[unsafe(naked)]
+#[no_mangle]
+pub unsafe extern "C" fn serial_interrupt_handler() {
+    naked_asm!(
+        "pusha",
+        "push ds",
+        "push es",
+        "mov ax, {kernel_data}",
+        "mov ds, ax",
+        "mov es, ax",
+        "call serial_handler_inner",
+        "pop es",
+        "pop ds",
+        "popa",
+        "iretd",
+        kernel_data = const gdt::KERNEL_DATA as u32,
+    );
+}
+
+pub fn suspend() -> Result<(), &'static str> {
+    // Let the FIFO drain so nothing is cut off mid-line
+    let port = SerialPort::new(COM1);
+    for _ in 0..100000 {
+        if port.transmit_empty() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+pub fn resume() -> Result<(), &'static str> {
+    let port = SerialPort::new(COM1);
+    port.init();
+    port.self_test()
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    let mut port = SerialPort::new(COM1);
+    let _ = fmt::write(&mut port, args);
+}
+
+/// Print to COM1
+#[macro_export]
+macro_rules! serial_print {
+    ($($arg:tt)*) => ($crate::serial::_print(format_args!($($arg)*)));
+}
+
+/// Print to COM1, appending a newline
+#[macro_export]
+macro_rules! serial_println {
+    () => ($crate::serial_print!("\n"));
+    ($($arg:tt)*) => ($crate::serial_print!("{}\n", format_args!($($arg)*)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn baud_rates_map_to_divisors() {
+        assert_eq!(divisor(115200), Ok(1));
+        assert_eq!(divisor(38400), Ok(3));
+        assert_eq!(divisor(9600), Ok(12));
+        assert_eq!(divisor(300), Ok(384));
+        assert!(divisor(0).is_err());
+        assert!(divisor(14400).is_ok());
+        assert!(divisor(250000).is_err());
+        assert!(divisor(7000).is_err());
+        assert_eq!(find_port(b"com2"), Some((0x2F8, 3)));
+        assert_eq!(find_port(b"com5"), None);
+    }
+}
@@ -0,0 +1,157 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::ansi::Parser;
+use crate::hotkey;
+use crate::idt;
+use crate::keyboard::Keyboard;
+use crate::serial::{self, SerialPort};
+use crate::signal;
+use crate::vga_colors::Color;
+use crate::writer::Writer;
+
+// `term`: the screen and keyboard as a terminal on a serial port, for
+// talking to another board or a shell on the host. What arrives goes
+// through the ANSI parser onto the screen, picked up by the port's receive
+// interrupt so nothing is lost while the CPU halts. Keys go out as a VT100
+// sends them: Enter as CR, Backspace as DEL, arrows and the editing keys as
+// escape sequences and Ctrl with a letter as its control code, Ctrl+C and
+// Ctrl+Z included. Ctrl+] is a hotkey that hangs up.
+
+/// Scancode of `]` on a US keyboard, Ctrl+] being telnet's escape too
+const KEY_RIGHT_BRACKET: u16 = 0x1B;
+const EXTENDED_PREFIX: u8 = 0xE0;
+const CTRL_C: u8 = 0x03;
+const CTRL_Z: u8 = 0x1A;
+
+/// What keys without a character send
+const KEY_SEQUENCES: [(u16, &[u8]); 16] = [
+    (0x01, b"\x1b"), (0x0E, b"\x7f"), (0x0F, b"\t"), (0x1C, b"\r"), (0xE01C, b"\r"),
+    (0xE048, b"\x1b[A"), (0xE050, b"\x1b[B"), (0xE04D, b"\x1b[C"), (0xE04B, b"\x1b[D"),
+    (0xE047, b"\x1b[H"), (0xE04F, b"\x1b[F"), (0xE052, b"\x1b[2~"), (0xE053, b"\x1b[3~"),
+    (0xE049, b"\x1b[5~"), (0xE051, b"\x1b[6~"), (0xE035, b"/"),
+];
+
+static HANG_UP: AtomicBool = AtomicBool::new(false);
+
+fn hang_up() {
+    HANG_UP.store(true, Ordering::Relaxed);
+}
+
+/// Control code for Ctrl and `byte`, for letters and `@[\]^_`
+fn control(byte: u8) -> Option<u8> {
+    match byte {
+        b'a'..=b'z' => Some(byte - b'a' + 1),
+        b'@'..=b'_' => Some(byte & 0x1F),
+        _ => None,
+    }
+}
+
+pub struct SerialTerminal {
+    port: SerialPort,
+    base: u16,
+    irq: u8,
+    baud: u32,
+}
+
+impl SerialTerminal {
+    /// The port called `name`, e.g. `com2`, at `baud`
+    pub fn open(name: &[u8], baud: u32) -> Result<Self, &'static str> {
+        let (base, irq) = serial::find_port(name).ok_or("No such port; try com1 to com4")?;
+        let port = SerialPort::new(base);
+        if !port.is_present() {
+            return Err("No UART at that port");
+        }
+        port.init_with_baud(baud)?;
+        Ok(Self { port, base, irq, baud })
+    }
+
+    /// Pass bytes both ways until Ctrl+] is pressed
+    pub fn run(&mut self, writer: &mut Writer) -> Result<(), &'static str> {
+        HANG_UP.store(false, Ordering::Relaxed);
+        let hotkey = hotkey::register(hotkey::CTRL, KEY_RIGHT_BRACKET, hang_up)?;
+        serial::listen(self.base, self.irq);
+        idt::flush_buffer();
+        signal::take_interrupt();
+        signal::take_suspend();
+
+        writer.set_color(Color::LightGray, Color::Black);
+        writer.clear();
+        let name = serial::PORTS.iter().find(|&&(base, _, _)| base == self.base).map_or("?", |&(_, name, _)| name);
+        write!(writer, "Connected to {} at {} baud; Ctrl+] hangs up\n", name, self.baud);
+
+        let mut parser = Parser::new();
+        let mut extended = false;
+        let mut received = [0; 256];
+        loop {
+            if HANG_UP.load(Ordering::Relaxed) {
+                break;
+            }
+            let count = serial::take_received(&mut received);
+            if count > 0 {
+                writer.write_ansi(&mut parser, &received[..count]);
+                continue;
+            }
+            // Taken by the keyboard interrupt before the buffer, so they
+            // come back here
+            if signal::take_interrupt() {
+                self.port.write_byte(CTRL_C);
+            }
+            if signal::take_suspend() {
+                self.port.write_byte(CTRL_Z);
+            }
+            match idt::get_scancode() {
+                Some(EXTENDED_PREFIX) => extended = true,
+                Some(scancode) => {
+                    let key = if extended { hotkey::EXTENDED } else { 0 } | scancode as u16;
+                    extended = false;
+                    if scancode & 0x80 == 0 {
+                        self.send_key(key);
+                    }
+                }
+                None => idt::wait_for_interrupt(),
+            }
+        }
+
+        serial::stop_listening();
+        hotkey::unregister(hotkey);
+        let overruns = serial::overruns();
+        writer.set_color(Color::White, Color::Black);
+        writer.write_str("\nDisconnected\n");
+        if overruns > 0 {
+            write!(writer, "{} received bytes were dropped\n", overruns);
+        }
+        Ok(())
+    }
+
+    fn send_key(&self, key: u16) {
+        if let Some(&(_, bytes)) = KEY_SEQUENCES.iter().find(|&&(code, _)| code == key) {
+            for &byte in bytes {
+                self.port.write_byte(byte);
+            }
+            return;
+        }
+        if key & hotkey::EXTENDED != 0 {
+            return;
+        }
+        let modifiers = hotkey::modifiers();
+        for byte in Keyboard::scancode_to_bytes(key as u8, modifiers & hotkey::SHIFT != 0) {
+            let byte = if modifiers & hotkey::CTRL != 0 { control(byte).unwrap_or(byte) } else { byte };
+            self.port.write_byte(byte);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn ctrl_with_a_key_sends_its_control_code() {
+        assert_eq!(control(b'c'), Some(CTRL_C));
+        assert_eq!(control(b'Z'), Some(CTRL_Z));
+        assert_eq!(control(b'['), Some(0x1B));
+        assert_eq!(control(b'1'), None);
+        let up = KEY_SEQUENCES.iter().find(|&&(key, _)| key == 0xE048).unwrap();
+        assert_eq!(up.1, b"\x1b[A");
+    }
+}
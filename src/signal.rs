@@ -0,0 +1,51 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::hotkey;
+
+// Ctrl+C and Ctrl+Z, caught in the keyboard interrupt so they get through
+// whatever is running. Ctrl+C sets a flag that long-running commands poll
+// with `interrupted` and stop on; the shell clears it before each command
+// and treats a command that ends with it set as interrupted. Ctrl+Z is
+// noted for when there are background jobs to suspend into; for now the
+// shell only says there aren't. Neither key reaches the keyboard buffer.
+
+const KEY_C: u8 = 0x2E;
+const KEY_Z: u8 = 0x2C;
+
+static INTERRUPT: AtomicBool = AtomicBool::new(false);
+static SUSPEND: AtomicBool = AtomicBool::new(false);
+
+/// Feed one scancode from the keyboard interrupt, after `hotkey::filter`.
+/// Returns true when it was Ctrl+C or Ctrl+Z and should be dropped.
+pub fn filter(scancode: u8) -> bool {
+    if hotkey::modifiers() != hotkey::CTRL {
+        return false;
+    }
+    match scancode {
+        KEY_C => INTERRUPT.store(true, Ordering::Relaxed),
+        KEY_Z => SUSPEND.store(true, Ordering::Relaxed),
+        _ => return false,
+    }
+    true
+}
+
+/// Whether Ctrl+C was pressed since the last `clear`
+pub fn interrupted() -> bool {
+    INTERRUPT.load(Ordering::Relaxed)
+}
+
+/// Whether Ctrl+C was pressed, clearing it
+pub fn take_interrupt() -> bool {
+    INTERRUPT.swap(false, Ordering::Relaxed)
+}
+
+/// Whether Ctrl+Z was pressed, clearing it
+pub fn take_suspend() -> bool {
+    SUSPEND.swap(false, Ordering::Relaxed)
+}
+
+/// Forget keys pressed before a command starts
+pub fn clear() {
+    INTERRUPT.store(false, Ordering::Relaxed);
+    SUSPEND.store(false, Ordering::Relaxed);
+}
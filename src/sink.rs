@@ -0,0 +1,176 @@
+use core::fmt;
+
+use crate::vga_colors::Color;
+use crate::writer::Writer;
+
+// Where shell commands send their output: the screen, or a buffer that
+// feeds the next command in a pipeline or gets saved to a file.
+
+pub trait Sink {
+    fn write_byte(&mut self, byte: u8);
+
+    /// Column the next byte lands in, for lining up tables
+    fn column(&self) -> usize;
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_byte(byte);
+        }
+    }
+
+    fn write_str(&mut self, s: &str) {
+        self.write_bytes(s.as_bytes());
+    }
+
+    /// Colors only mean something on screen; other sinks ignore them
+    fn set_color(&mut self, _fg: Color, _bg: Color) {}
+
+    /// The screen behind this sink, for commands that draw on it directly
+    fn screen(&mut self) -> Option<&mut Writer> {
+        None
+    }
+
+    fn write_fmt(&mut self, args: fmt::Arguments) {
+        struct Adapter<'a, S: Sink + ?Sized>(&'a mut S);
+
+        impl<S: Sink + ?Sized> fmt::Write for Adapter<'_, S> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.0.write_str(s);
+                Ok(())
+            }
+        }
+
+        let _ = fmt::write(&mut Adapter(self), args);
+    }
+
+    /// Write spaces up to column `col`; nothing if already past it
+    fn pad_to(&mut self, col: usize) {
+        while self.column() < col {
+            self.write_byte(b' ');
+        }
+    }
+
+    /// Write `s` left-aligned in a field of `width` columns, cutting it short
+    /// if it doesn't fit
+    fn write_padded(&mut self, s: &str, width: usize) {
+        let end = self.column() + width;
+        self.write_str(truncate(s, width));
+        self.pad_to(end);
+    }
+}
+
+/// The longest start of `s` no more than `len` bytes long, cut at a
+/// character boundary so a multibyte character isn't split
+pub fn truncate(s: &str, len: usize) -> &str {
+    if len >= s.len() {
+        return s;
+    }
+    let mut end = len;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+impl Sink for Writer {
+    fn write_byte(&mut self, byte: u8) {
+        Writer::write_byte(self, byte);
+    }
+
+    fn column(&self) -> usize {
+        self.get_col()
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        Writer::write_bytes(self, bytes);
+    }
+
+    fn write_str(&mut self, s: &str) {
+        Writer::write_str(self, s);
+    }
+
+    fn set_color(&mut self, fg: Color, bg: Color) {
+        Writer::set_color(self, fg, bg);
+    }
+
+    fn screen(&mut self) -> Option<&mut Writer> {
+        Some(self)
+    }
+
+    fn pad_to(&mut self, col: usize) {
+        Writer::pad_to(self, col);
+    }
+}
+
+/// Collects output in memory. Bytes past the end of the buffer are dropped
+/// and `overflowed` is set.
+pub struct Capture<'a> {
+    data: &'a mut [u8],
+    len: usize,
+    column: usize,
+    overflowed: bool,
+}
+
+impl<'a> Capture<'a> {
+    pub fn new(data: &'a mut [u8]) -> Self {
+        Self { data, len: 0, column: 0, overflowed: false }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+
+    pub fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+}
+
+impl Sink for Capture<'_> {
+    fn write_byte(&mut self, byte: u8) {
+        if self.len == self.data.len() {
+            self.overflowed = true;
+            return;
+        }
+        self.data[self.len] = byte;
+        self.len += 1;
+        self.column = if byte == b'\n' { 0 } else { self.column + 1 };
+    }
+
+    fn column(&self) -> usize {
+        self.column
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn capture_formats_pads_and_truncates() {
+        let mut buffer = [0u8; 16];
+        let mut capture = Capture::new(&mut buffer);
+        capture.set_color(Color::Red, Color::Black);
+        write!(capture, "{}:", 42);
+        capture.pad_to(5);
+        capture.write_str("ok\n");
+        assert_eq!(capture.as_bytes(), b"42:  ok\n");
+        assert!(!capture.overflowed());
+
+        capture.write_str("0123456789");
+        assert_eq!(capture.as_bytes().len(), 16);
+        assert!(capture.overflowed());
+    }
+
+    #[test_case]
+    fn truncating_stops_before_a_split_character() {
+        assert_eq!(truncate("Grüße", 3), "Gr");
+        assert_eq!(truncate("Grüße", 4), "Grü");
+        assert_eq!(truncate("abc", 10), "abc");
+
+        let mut buffer = [0u8; 16];
+        let mut capture = Capture::new(&mut buffer);
+        capture.write_padded("ü", 1);
+        capture.write_byte(b'|');
+        assert_eq!(capture.as_bytes(), b" |");
+    }
+}
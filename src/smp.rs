@@ -0,0 +1,202 @@
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::acpi::{self, MAX_CPUS};
+use crate::apic;
+use crate::gdt;
+use crate::idt;
+use crate::multiboot;
+
+/// Physical address the startup code is copied to. The startup IPI can only
+/// name a 4 KB page below 1 MB.
+pub const TRAMPOLINE: usize = 0x8000;
+const TRAMPOLINE_PAGE: u8 = (TRAMPOLINE >> 12) as u8;
+
+const AP_STACK_SIZE: usize = 8 * 1024;
+
+/// Ticks to wait after INIT, and for a started processor to check in
+const INIT_DELAY_TICKS: u32 = 2;
+const STARTUP_TIMEOUT_TICKS: u32 = 20;
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_data: u8;
+    static ap_trampoline_end: u8;
+}
+
+/// Parameters at the end of the trampoline, laid out as in boot.asm
+#[repr(C)]
+struct TrampolineData {
+    _pad: u16,
+    gdt_limit: u16,
+    gdt_base: u32,
+    stack: u32,
+    entry: u32,
+    cpu: u32,
+}
+
+/// State owned by one processor. Index 0 is the bootstrap processor, the
+/// rest follow the MADT order.
+pub struct PerCpu {
+    pub apic_id: u8,
+    online: AtomicBool,
+}
+
+impl PerCpu {
+    const fn new() -> Self {
+        Self { apic_id: 0, online: AtomicBool::new(false) }
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::Acquire)
+    }
+}
+
+#[repr(C, align(16))]
+struct Stack([u8; AP_STACK_SIZE]);
+
+static mut CPUS: [PerCpu; MAX_CPUS] = [const { PerCpu::new() }; MAX_CPUS];
+static mut CPU_COUNT: usize = 1;
+static ONLINE: AtomicUsize = AtomicUsize::new(1);
+static mut AP_STACKS: [Stack; MAX_CPUS] = [const { Stack([0; AP_STACK_SIZE]) }; MAX_CPUS];
+
+/// Processors that have checked in, the bootstrap processor included
+pub fn online_count() -> usize {
+    ONLINE.load(Ordering::Acquire)
+}
+
+/// Per-CPU areas of every processor found, online or not
+pub fn cpus() -> &'static [PerCpu] {
+    unsafe { &CPUS[..CPU_COUNT] }
+}
+
+/// Index of the calling processor in `cpus()`
+pub fn current_index() -> usize {
+    if !apic::is_enabled() {
+        return 0;
+    }
+    let id = apic::local_id();
+    cpus().iter().position(|cpu| cpu.apic_id == id).unwrap_or(0)
+}
+
+/// Start every enabled processor in the MADT. Needs the local APIC, so runs
+/// after `apic::init`. Returns how many processors are online.
+pub fn init() -> Result<usize, &'static str> {
+    if !apic::is_enabled() {
+        return Err("APIC is not enabled");
+    }
+    install_trampoline()?;
+
+    let bsp = apic::local_id();
+    unsafe {
+        CPUS[0].apic_id = bsp;
+        CPUS[0].online.store(true, Ordering::Release);
+    }
+
+    let info = acpi::get_info();
+    for processor in info.processors[..info.processor_count].iter() {
+        if !processor.enabled || processor.apic_id == bsp {
+            continue;
+        }
+        let index = unsafe { CPU_COUNT };
+        if index == MAX_CPUS {
+            break;
+        }
+        unsafe {
+            CPUS[index].apic_id = processor.apic_id;
+            CPU_COUNT += 1;
+        }
+        if !start(index) {
+            log_warn!("SMP: CPU {} (APIC id {}) did not start", index, processor.apic_id);
+        }
+    }
+    Ok(online_count())
+}
+
+/// Copy the startup code to `TRAMPOLINE`. It enters protected mode with
+/// the kernel GDT and calls the entry point set by `set_trampoline_entry`.
+pub fn install_trampoline() -> Result<(), &'static str> {
+    let size = trampoline().len();
+    let free = multiboot::memory_map().any(|region| {
+        region.kind == multiboot::MEMORY_AVAILABLE
+            && region.base <= TRAMPOLINE as u64
+            && region.base + region.length >= (TRAMPOLINE + size) as u64
+    });
+    if !free {
+        return Err("Trampoline page is not available RAM");
+    }
+    unsafe {
+        core::ptr::copy_nonoverlapping(trampoline().as_ptr(), TRAMPOLINE as *mut u8, size);
+    }
+    Ok(())
+}
+
+/// Make the installed trampoline call `entry(arg)` on `stack`
+pub fn set_trampoline_entry(entry: u32, stack: u32, arg: u32) {
+    unsafe {
+        let offset = &ap_trampoline_data as *const u8 as usize - &ap_trampoline_start as *const u8 as usize;
+        let data = &mut *((TRAMPOLINE + offset) as *mut TrampolineData);
+        let (limit, base) = gdt::pointer();
+        data.gdt_limit = limit;
+        data.gdt_base = base;
+        data.stack = stack;
+        data.entry = entry;
+        data.cpu = arg;
+    }
+}
+
+fn trampoline() -> &'static [u8] {
+    unsafe {
+        let start = &ap_trampoline_start as *const u8;
+        let len = &ap_trampoline_end as *const u8 as usize - start as usize;
+        core::slice::from_raw_parts(start, len)
+    }
+}
+
+/// INIT-SIPI-SIPI one processor and wait for it to check in
+fn start(index: usize) -> bool {
+    let cpu = unsafe { &CPUS[index] };
+    let stack = unsafe { AP_STACKS[index].0.as_ptr() as u32 + AP_STACK_SIZE as u32 };
+    set_trampoline_entry(ap_main as u32, stack, index as u32);
+
+    apic::send_init(cpu.apic_id);
+    wait_ticks(INIT_DELAY_TICKS);
+    // The second startup IPI is only needed if the first was missed
+    for _ in 0..2 {
+        apic::send_startup(cpu.apic_id, TRAMPOLINE_PAGE);
+        let start = idt::get_ticks();
+        while idt::get_ticks().wrapping_sub(start) < STARTUP_TIMEOUT_TICKS {
+            if cpu.is_online() {
+                return true;
+            }
+            idt::wait_for_interrupt();
+        }
+    }
+    false
+}
+
+fn wait_ticks(ticks: u32) {
+    let start = idt::get_ticks();
+    while idt::get_ticks().wrapping_sub(start) < ticks {
+        idt::wait_for_interrupt();
+    }
+}
+
+/// First Rust code an application processor runs, on its own stack with the
+/// kernel GDT loaded. Processors have nothing to schedule yet, so they
+/// check in and halt.
+#[no_mangle]
+pub extern "C" fn ap_main(index: u32) -> ! {
+    idt::load();
+    apic::enable_local();
+    unsafe {
+        CPUS[index as usize].online.store(true, Ordering::Release);
+    }
+    ONLINE.fetch_add(1, Ordering::AcqRel);
+
+    loop {
+        unsafe {
+            asm!("sti", "hlt", options(nomem, nostack));
+        }
+    }
+}
@@ -2,13 +2,23 @@ use crate::writer::Writer;
 use crate::vga_colors::Color;
 use crate::idt;
 
-const VGA_BUFFER: usize = 0xb8000;
 const GAME_WIDTH: usize = 40;
 const GAME_HEIGHT: usize = 20;
 const GAME_OFFSET_X: usize = 20;
 const GAME_OFFSET_Y: usize = 2;
 const MAX_SNAKE_LEN: usize = 100;
 
+/// Put `byte` in `color` on the screen
+fn put(col: usize, row: usize, byte: u8, color: u8) {
+    Writer::set_cell(col, row, (color as u16) << 8 | byte as u16);
+}
+
+fn put_str(col: usize, row: usize, text: &[u8], color: u8) {
+    for (i, &byte) in text.iter().enumerate() {
+        put(col + i, row, byte, color);
+    }
+}
+
 #[derive(Copy, Clone, PartialEq)]
 pub enum Direction {
     Up,
@@ -79,38 +89,22 @@ impl SnakeGame {
     }
 
     fn draw_box(&self) {
-        unsafe {
-            let vga = VGA_BUFFER as *mut u8;
-            let border_color = 0x0F;
-            
-            for x in 0..=GAME_WIDTH + 1 {
-                let top_offset = ((GAME_OFFSET_Y - 1) * 80 + GAME_OFFSET_X - 1 + x) * 2;
-                let bottom_offset = ((GAME_OFFSET_Y + GAME_HEIGHT) * 80 + GAME_OFFSET_X - 1 + x) * 2;
-                *vga.add(top_offset) = b'#';
-                *vga.add(top_offset + 1) = border_color;
-                *vga.add(bottom_offset) = b'#';
-                *vga.add(bottom_offset + 1) = border_color;
-            }
-            
-            for y in 0..GAME_HEIGHT {
-                let left_offset = ((GAME_OFFSET_Y + y) * 80 + GAME_OFFSET_X - 1) * 2;
-                let right_offset = ((GAME_OFFSET_Y + y) * 80 + GAME_OFFSET_X + GAME_WIDTH) * 2;
-                *vga.add(left_offset) = b'#';
-                *vga.add(left_offset + 1) = border_color;
-                *vga.add(right_offset) = b'#';
-                *vga.add(right_offset + 1) = border_color;
-            }
+        let border_color = 0x0F;
+
+        for x in 0..=GAME_WIDTH + 1 {
+            put(GAME_OFFSET_X - 1 + x, GAME_OFFSET_Y - 1, b'#', border_color);
+            put(GAME_OFFSET_X - 1 + x, GAME_OFFSET_Y + GAME_HEIGHT, b'#', border_color);
+        }
+
+        for y in 0..GAME_HEIGHT {
+            put(GAME_OFFSET_X - 1, GAME_OFFSET_Y + y, b'#', border_color);
+            put(GAME_OFFSET_X + GAME_WIDTH, GAME_OFFSET_Y + y, b'#', border_color);
         }
     }
 
     fn draw_cell(&self, x: usize, y: usize, ch: u8, color: u8) {
         if x < GAME_WIDTH && y < GAME_HEIGHT {
-            unsafe {
-                let vga = VGA_BUFFER as *mut u8;
-                let offset = ((GAME_OFFSET_Y + y) * 80 + GAME_OFFSET_X + x) * 2;
-                *vga.add(offset) = ch;
-                *vga.add(offset + 1) = color;
-            }
+            put(GAME_OFFSET_X + x, GAME_OFFSET_Y + y, ch, color);
         }
     }
 
@@ -131,34 +125,24 @@ impl SnakeGame {
         
         self.draw_cell(self.food.x, self.food.y, b'*', 0x0C);
         
-        unsafe {
-            let vga = VGA_BUFFER as *mut u8;
-            let score_str = b"Score: ";
-            for (i, &byte) in score_str.iter().enumerate() {
-                let offset = i * 2;
-                *vga.add(offset) = byte;
-                *vga.add(offset + 1) = 0x0E;
-            }
+        put_str(0, 0, b"Score: ", 0x0E);
 
-            let mut score = self.score;
-            let mut digits = [0u8; 10];
-            let mut digit_count = 0;
-            if score == 0 {
-                digits[0] = b'0';
-                digit_count = 1;
-            } else {
-                while score > 0 {
-                    digits[digit_count] = b'0' + (score % 10) as u8;
-                    score /= 10;
-                    digit_count += 1;
-                }
-            }
-            for i in 0..digit_count {
-                let offset = (7 + i) * 2;
-                *vga.add(offset) = digits[digit_count - 1 - i];
-                *vga.add(offset + 1) = 0x0E;
+        let mut score = self.score;
+        let mut digits = [0u8; 10];
+        let mut digit_count = 0;
+        if score == 0 {
+            digits[0] = b'0';
+            digit_count = 1;
+        } else {
+            while score > 0 {
+                digits[digit_count] = b'0' + (score % 10) as u8;
+                score /= 10;
+                digit_count += 1;
             }
         }
+        for i in 0..digit_count {
+            put(7 + i, 0, digits[digit_count - 1 - i], 0x0E);
+        }
     }
 
     fn update(&mut self) -> bool {
@@ -228,29 +212,12 @@ impl SnakeGame {
         
         writer.clear();
         
-        unsafe {
-            let vga = VGA_BUFFER as *mut u8;
-            let instructions = b"WASD to move, Q to quit";
-            let start_x = 50;
-            for (i, &byte) in instructions.iter().enumerate() {
-                let offset = (start_x + i) * 2;
-                *vga.add(offset) = byte;
-                *vga.add(offset + 1) = 0x07;
-            }
-        }
+        put_str(50, 0, b"WASD to move, Q to quit", 0x07);
         
         self.draw_box();
         self.draw();
         
-        unsafe {
-            let vga = VGA_BUFFER as *mut u8;
-            let start_str = b"Press WASD to start!";
-            let start_offset = ((GAME_OFFSET_Y + GAME_HEIGHT / 2) * 80 + GAME_OFFSET_X + 10) * 2;
-            for (i, &byte) in start_str.iter().enumerate() {
-                *vga.add(start_offset + i * 2) = byte;
-                *vga.add(start_offset + i * 2 + 1) = 0x0E;
-            }
-        }
+        put_str(GAME_OFFSET_X + 10, GAME_OFFSET_Y + GAME_HEIGHT / 2, b"Press WASD to start!", 0x0E);
         
         let mut last_tick: u32 = idt::get_ticks();
         let game_speed: u32 = 10;
@@ -307,15 +274,7 @@ impl SnakeGame {
                 
                 if !self.update() {
                     self.draw();
-                    unsafe {
-                        let vga = VGA_BUFFER as *mut u8;
-                        let game_over_str = b"GAME OVER! Press Q to exit";
-                        let start_offset = ((GAME_OFFSET_Y + GAME_HEIGHT / 2) * 80 + GAME_OFFSET_X + 5) * 2;
-                        for (i, &byte) in game_over_str.iter().enumerate() {
-                            *vga.add(start_offset + i * 2) = byte;
-                            *vga.add(start_offset + i * 2 + 1) = 0x4F;
-                        }
-                    }
+                    put_str(GAME_OFFSET_X + 5, GAME_OFFSET_Y + GAME_HEIGHT / 2, b"GAME OVER! Press Q to exit", 0x4F);
                     
                     loop {
                         idt::wait_for_interrupt();
@@ -0,0 +1,79 @@
+use crate::clock;
+use crate::io::{inb, outb};
+
+const PIT_CONTROL_PORT: u16 = 0x43;
+const PIT_CHANNEL_2: u16 = 0x42;
+const SPEAKER_PORT: u16 = 0x61;
+const PIT_FREQUENCY: u32 = 1193180;  // Base frequency for PIT
+
+/// Start a continuous tone at the given frequency
+pub fn tone_on(frequency_hz: u32) {
+    if frequency_hz == 0 {
+        return;
+    }
+
+    let divisor = (PIT_FREQUENCY / frequency_hz).min(0xFFFF) as u16;
+
+    unsafe {
+        // Configure PIT channel 2 for mode 3 (square wave)
+        outb(PIT_CONTROL_PORT, 0b10110110);  // 0xB6
+
+        // Set frequency divisor (low byte, then high byte)
+        outb(PIT_CHANNEL_2, (divisor & 0xFF) as u8);
+        outb(PIT_CHANNEL_2, ((divisor >> 8) & 0xFF) as u8);
+
+        // Connect channel 2 to the speaker (set bits 0 and 1)
+        let current = inb(SPEAKER_PORT);
+        outb(SPEAKER_PORT, current | 0x03);
+    }
+}
+
+/// Silence the speaker
+pub fn tone_off() {
+    unsafe {
+        let current = inb(SPEAKER_PORT);
+        outb(SPEAKER_PORT, current & 0xFC);
+    }
+}
+
+/// Play a beep at a specific frequency for a duration
+pub fn beep(frequency_hz: u32, duration_ms: u32) {
+    tone_on(frequency_hz);
+    clock::sleep_ms(duration_ms);
+    tone_off();
+}
+
+/// Play a simple melody (array of notes, frequency 0 is a rest)
+pub fn play_melody(notes: &[(u32, u32)]) {
+    for &(frequency, duration) in notes {
+        if frequency == 0 {
+            clock::sleep_ms(duration);
+        } else {
+            beep(frequency, duration);
+        }
+    }
+}
+
+/// Short rising arpeggio played at boot
+pub fn startup_chime() {
+    play_melody(&[(Notes::C4, 80), (Notes::E4, 80), (Notes::G4, 80), (Notes::C5, 160)]);
+}
+
+/// Low blip used by the shell to flag an error
+pub fn error_beep() {
+    beep(220, 60);
+}
+
+// Some common frequencies for musical notes
+pub struct Notes;
+
+impl Notes {
+    pub const C4: u32 = 262;   // Middle C
+    pub const D4: u32 = 294;
+    pub const E4: u32 = 330;
+    pub const F4: u32 = 349;
+    pub const G4: u32 = 392;
+    pub const A4: u32 = 440;
+    pub const B4: u32 = 494;
+    pub const C5: u32 = 523;
+}
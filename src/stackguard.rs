@@ -0,0 +1,157 @@
+use core::ptr;
+
+use crate::crash;
+use crate::tsc;
+
+// Stack canaries for the kernel's entry points. A `Canary` placed first in a
+// function holds a copy of a guard value chosen at boot and compares it on
+// the way out, so a buffer overrun that runs over it is caught before the
+// function returns through a clobbered frame. `__stack_chk_fail` then names
+// the function from the symbol table.
+//
+// This is done by hand in the system call and interrupt handlers rather than
+// with `-Z stack-protector`: on this target the compiler reads the guard from
+// `gs:0x14`, which would need a per-CPU segment kept loaded across ring
+// changes. The failure handler has the name the compiler expects, so such a
+// build would share it.
+//
+// Stacks themselves get a floor: the bottom words of the boot stack, the
+// ring 0 stack and the double fault stack are filled with a pattern that
+// every timer tick checks. Without paging there are no guard pages to fault
+// on, so an overflow still writes over whatever lies below the stack, but
+// it is reported naming the stack at the next tick rather than going
+// unnoticed.
+
+extern "C" {
+    static stack_bottom: u8;
+    static stack_top: u8;
+}
+
+/// Words at the bottom of each watched stack that must never be written
+const FLOOR_WORDS: usize = 16;
+const FLOOR: u32 = 0xF100_F100;
+const MAX_STACKS: usize = 4;
+
+#[derive(Clone, Copy)]
+struct Watched {
+    name: &'static str,
+    bottom: u32,
+    top: u32,
+}
+
+static mut STACKS: [Option<Watched>; MAX_STACKS] = [None; MAX_STACKS];
+
+/// Used until `init` picks one; the zero low byte stops string copies
+static mut GUARD: u32 = 0xE2A5_1700;
+
+/// Pick this boot's guard. Must run before any `Canary` exists.
+pub fn init() {
+    let tsc = tsc::read();
+    unsafe {
+        GUARD = guard_from(tsc as u32 ^ ((tsc >> 32) as u32).rotate_left(16));
+        watch_stack("boot", &stack_bottom as *const u8 as u32, &stack_top as *const u8 as u32);
+    }
+}
+
+/// Lay a floor at the bottom of the stack from `bottom` to `top` and check
+/// it from now on. The stack must not have grown that deep yet.
+pub fn watch_stack(name: &'static str, bottom: u32, top: u32) {
+    unsafe {
+        lay_floor(bottom);
+        if let Some(slot) = STACKS.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(Watched { name, bottom, top });
+        }
+    }
+}
+
+unsafe fn lay_floor(bottom: u32) {
+    for i in 0..FLOOR_WORDS {
+        ptr::write_volatile((bottom as *mut u32).add(i), FLOOR);
+    }
+}
+
+fn floor_intact(bottom: u32) -> bool {
+    (0..FLOOR_WORDS).all(|i| unsafe { ptr::read_volatile((bottom as *const u32).add(i)) } == FLOOR)
+}
+
+/// Name of the first watched stack that has run down through its floor
+pub fn overflowed_stack() -> Option<&'static str> {
+    unsafe { STACKS.iter().flatten().find(|stack| !floor_intact(stack.bottom)).map(|stack| stack.name) }
+}
+
+/// Name of the watched stack `esp` points into, if any
+pub fn stack_containing(esp: u32) -> Option<&'static str> {
+    unsafe { STACKS.iter().flatten().find(|stack| stack.bottom <= esp && esp <= stack.top).map(|stack| stack.name) }
+}
+
+/// Stop with a report if any stack has overflowed; called every timer tick
+pub fn check_stacks() {
+    if let Some(name) = overflowed_stack() {
+        crash::stack_overflow(name);
+    }
+}
+
+/// A guard from a seed: never zero, and with a zero low byte so a runaway
+/// string copy stops at it
+fn guard_from(seed: u32) -> u32 {
+    let guard = seed.wrapping_mul(0x9E37_79B9) & !0xFF;
+    if guard == 0 { 0xE2A5_1700 } else { guard }
+}
+
+pub struct Canary(u32);
+
+impl Canary {
+    #[inline(always)]
+    pub fn new() -> Self {
+        Canary(unsafe { ptr::read_volatile(&GUARD) })
+    }
+}
+
+impl Drop for Canary {
+    /// Inlined so the failure is reported from the protected function
+    #[inline(always)]
+    fn drop(&mut self) {
+        if unsafe { ptr::read_volatile(&self.0) != GUARD } {
+            __stack_chk_fail();
+        }
+    }
+}
+
+#[no_mangle]
+#[inline(never)]
+pub extern "C" fn __stack_chk_fail() -> ! {
+    // Frame pointers are always on, so our frame holds the caller's EBP and
+    // the address in the function whose canary failed
+    let frame = crash::Registers::capture().ebp as *const u32;
+    unsafe { crash::stack_smashed(*frame.add(1), *frame) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn guards_end_in_zero_and_intact_canaries_pass() {
+        for &seed in [0u32, 1, 0xFFFF_FFFF, 0x1234_5678].iter() {
+            let guard = guard_from(seed);
+            assert_ne!(guard, 0);
+            assert_eq!(guard & 0xFF, 0);
+        }
+        drop(Canary::new());
+    }
+
+    #[test_case]
+    fn floors_notice_being_written_over() {
+        let mut stack = [0u32; FLOOR_WORDS + 4];
+        let bottom = stack.as_mut_ptr() as u32;
+        unsafe {
+            lay_floor(bottom);
+        }
+        assert!(floor_intact(bottom));
+        unsafe {
+            ptr::write_volatile((bottom as *mut u32).add(FLOOR_WORDS - 1), 0);
+        }
+        assert!(!floor_intact(bottom));
+        assert_eq!(overflowed_stack(), None);
+    }
+}
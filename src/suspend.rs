@@ -0,0 +1,171 @@
+use core::arch::{asm, naked_asm};
+
+use crate::acpi::{self, Fadt};
+use crate::apic;
+use crate::driver;
+use crate::gdt;
+use crate::idt;
+use crate::io::{inw, outw};
+use crate::power;
+use crate::smp;
+
+// Suspend to RAM (S3). Drivers quiesce their devices, the callee-saved
+// registers go on the stack, and the ACPI sleep registers power down
+// everything but memory. Waking, the firmware jumps to the FACS
+// waking vector in real mode. That is the SMP trampoline, which gets back
+// to protected mode and calls `resume_entry`, which returns from the sleep
+// on the saved stack. The APIC is not restored yet, so this needs the PIC.
+
+/// PM1 status: set by hardware once the machine has woken
+const WAK_STS: u16 = 1 << 15;
+
+/// FACS layout
+const FACS_WAKING_VECTOR: usize = 12;
+const FACS_X_WAKING_VECTOR: usize = 24;
+
+/// Spins to wait for the machine to power down before giving up
+const SLEEP_TIMEOUT_SPINS: u32 = 50_000_000;
+
+static mut SAVED_ESP: u32 = 0;
+/// Only holds the trampoline's call into `resume_entry`
+static mut RESUME_STACK: [u32; 64] = [0; 64];
+
+/// Check the firmware can do S3
+pub fn init() -> Result<(), &'static str> {
+    let fadt = acpi::get_info().fadt.ok_or("No FADT")?;
+    facs(&fadt)?;
+    acpi::s3_sleep_type().ok_or("Firmware has no S3 sleep state")?;
+    Ok(())
+}
+
+fn facs(fadt: &Fadt) -> Result<usize, &'static str> {
+    let facs = fadt.firmware_ctrl as usize;
+    if facs == 0 || unsafe { *(facs as *const [u8; 4]) } != *b"FACS" {
+        return Err("No FACS");
+    }
+    Ok(facs)
+}
+
+/// Put the machine in S3 and return once it has woken and the devices are
+/// back. Fails without sleeping if anything is missing or a device refuses.
+pub fn suspend() -> Result<(), &'static str> {
+    let fadt = acpi::get_info().fadt.ok_or("No FADT")?;
+    let facs = facs(&fadt)?;
+    let (typ_a, typ_b) = acpi::s3_sleep_type().ok_or("Firmware has no S3 sleep state")?;
+    if apic::is_enabled() {
+        return Err("Resume can't restore the APIC yet, boot with apic=off");
+    }
+    smp::install_trampoline()?;
+    let stack = unsafe { RESUME_STACK.as_ptr().add(RESUME_STACK.len()) as u32 };
+    smp::set_trampoline_entry(resume_entry as u32, stack, 0);
+
+    unsafe {
+        asm!("cli", options(nostack));
+    }
+    if let Err((name, e)) = driver::suspend_all() {
+        log_warn!("Suspend: {} refused: {}", name, e);
+        unsafe {
+            asm!("sti", options(nostack));
+        }
+        return Err("A driver refused to suspend");
+    }
+
+    let pic_masks = idt::pic_masks();
+    let woke = unsafe {
+        let cr0: usize;
+        asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack));
+        *((facs + FACS_WAKING_VECTOR) as *mut u32) = smp::TRAMPOLINE as u32;
+        *((facs + FACS_X_WAKING_VECTOR) as *mut u64) = 0;
+        for &port in [fadt.pm1a_evt_blk, fadt.pm1b_evt_blk].iter().filter(|&&port| port != 0) {
+            outw(port as u16, WAK_STS);
+        }
+
+        let woke = save_and_sleep(typ_a as u32, typ_b as u32) != 0;
+        if woke {
+            // The trampoline only set PE; the FPU needs the boot settings
+            asm!("mov cr0, {}", "fninit", in(reg) cr0, options(nostack));
+        }
+        woke
+    };
+    if woke {
+        gdt::resume();
+        idt::resume(pic_masks);
+    }
+
+    let failed = driver::resume_all();
+    unsafe {
+        asm!("sti", options(nostack));
+    }
+    if !woke {
+        Err("Machine did not go to sleep")
+    } else if failed > 0 {
+        Err("Some drivers failed to resume")
+    } else {
+        Ok(())
+    }
+}
+
+/// Called from `save_and_sleep` with the registers saved. On real hardware
+/// power goes away during the wait.
+#[no_mangle]
+extern "C" fn enter_s3(typ_a: u32, typ_b: u32) {
+    let fadt = match acpi::get_info().fadt {
+        Some(fadt) => fadt,
+        None => return,
+    };
+    unsafe {
+        // Dirty cache lines are lost with the CPU's power
+        asm!("wbinvd", options(nostack));
+        power::enter_sleep_state(&fadt, typ_a as u16, typ_b as u16);
+        for _ in 0..SLEEP_TIMEOUT_SPINS {
+            if inw(fadt.pm1a_evt_blk as u16) & WAK_STS != 0 {
+                break;
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Save the callee-saved registers and stack pointer, then sleep. Returns
+/// 0 if the machine stayed awake; after waking, `resume_entry` returns 1
+/// from here instead.
+#[unsafe(naked)]
+unsafe extern "C" fn save_and_sleep(typ_a: u32, typ_b: u32) -> u32 {
+    naked_asm!(
+        "push ebp",
+        "push ebx",
+        "push esi",
+        "push edi",
+        "mov [{saved_esp}], esp",
+        // typ_b then typ_a, each 24 bytes up once the previous push lands
+        "push dword ptr [esp + 24]",
+        "push dword ptr [esp + 24]",
+        "call {enter}",
+        "add esp, 8",
+        "xor eax, eax",
+        "pop edi",
+        "pop esi",
+        "pop ebx",
+        "pop ebp",
+        "ret",
+        saved_esp = sym SAVED_ESP,
+        enter = sym enter_s3,
+    );
+}
+
+/// Entered from the trampoline after waking, with the kernel GDT and data
+/// segments loaded
+#[unsafe(naked)]
+#[no_mangle]
+pub unsafe extern "C" fn resume_entry() {
+    naked_asm!(
+        "mov esp, [{saved_esp}]",
+        "mov eax, 1",
+        "pop edi",
+        "pop esi",
+        "pop ebx",
+        "pop ebp",
+        "ret",
+        saved_esp = sym SAVED_ESP,
+    );
+}
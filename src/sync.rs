@@ -0,0 +1,197 @@
+use core::arch::asm;
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+
+#[cfg(debug_assertions)]
+use crate::lockdep;
+
+const EFLAGS_IF: usize = 1 << 9;
+
+/// Fair spinlock: each `lock` takes a ticket and waits for it to be served,
+/// so processors get the lock in the order they asked for it
+pub struct SpinLock {
+    next: AtomicU32,
+    serving: AtomicU32,
+}
+
+impl SpinLock {
+    pub const fn new() -> Self {
+        Self { next: AtomicU32::new(0), serving: AtomicU32::new(0) }
+    }
+
+    pub fn lock(&self) {
+        #[cfg(debug_assertions)]
+        lockdep::acquire(self as *const SpinLock as usize);
+        let ticket = self.next.fetch_add(1, Ordering::Relaxed);
+        while self.serving.load(Ordering::Acquire) != ticket {
+            core::hint::spin_loop();
+        }
+    }
+
+    pub fn unlock(&self) {
+        #[cfg(debug_assertions)]
+        lockdep::release(self as *const SpinLock as usize);
+        self.serving.fetch_add(1, Ordering::Release);
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.next.load(Ordering::Relaxed) != self.serving.load(Ordering::Relaxed)
+    }
+}
+
+/// Disable interrupts, returning whether they were enabled before
+pub fn disable_interrupts() -> bool {
+    let flags: usize;
+    unsafe {
+        asm!("pushfd", "pop {}", "cli", out(reg) flags);
+    }
+    flags & EFLAGS_IF != 0
+}
+
+/// Enable interrupts again if `disable_interrupts` found them enabled
+pub fn restore_interrupts(enabled: bool) {
+    if enabled {
+        unsafe {
+            asm!("sti", options(nomem, nostack));
+        }
+    }
+}
+
+/// Data behind a spinlock that also keeps interrupts off while held, so an
+/// interrupt handler taking the same lock can't deadlock against the code
+/// it interrupted
+pub struct Mutex<T> {
+    lock: SpinLock,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Self { lock: SpinLock::new(), data: UnsafeCell::new(data) }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        let interrupts = disable_interrupts();
+        self.lock.lock();
+        MutexGuard { mutex: self, interrupts }
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+    /// Whether to re-enable interrupts on release
+    interrupts: bool,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.lock.unlock();
+        restore_interrupts(self.interrupts);
+    }
+}
+
+const UNINIT: u8 = 0;
+const RUNNING: u8 = 1;
+const DONE: u8 = 2;
+
+/// A value set once, by whichever caller gets there first
+pub struct Once<T> {
+    state: AtomicU8,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send + Sync> Sync for Once<T> {}
+
+impl<T> Once<T> {
+    pub const fn new() -> Self {
+        Self { state: AtomicU8::new(UNINIT), value: UnsafeCell::new(MaybeUninit::uninit()) }
+    }
+
+    /// Run `init` if nothing has yet, otherwise wait for the value
+    pub fn call_once<F: FnOnce() -> T>(&self, init: F) -> &T {
+        if self.state.compare_exchange(UNINIT, RUNNING, Ordering::Acquire, Ordering::Acquire).is_ok() {
+            unsafe {
+                (*self.value.get()).write(init());
+            }
+            self.state.store(DONE, Ordering::Release);
+        }
+        while self.state.load(Ordering::Acquire) != DONE {
+            core::hint::spin_loop();
+        }
+        unsafe { (*self.value.get()).assume_init_ref() }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        if self.state.load(Ordering::Acquire) == DONE {
+            Some(unsafe { (*self.value.get()).assume_init_ref() })
+        } else {
+            None
+        }
+    }
+}
+
+/// A static initialized on first use
+pub struct Lazy<T, F = fn() -> T> {
+    once: Once<T>,
+    init: F,
+}
+
+unsafe impl<T: Send + Sync, F: Sync> Sync for Lazy<T, F> {}
+
+impl<T, F: Fn() -> T> Lazy<T, F> {
+    pub const fn new(init: F) -> Self {
+        Self { once: Once::new(), init }
+    }
+}
+
+impl<T, F: Fn() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.once.call_once(|| (self.init)())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn mutex_releases_lock_on_drop() {
+        let mutex = Mutex::new(1);
+        {
+            let mut value = mutex.lock();
+            *value += 1;
+            assert!(mutex.lock.is_locked());
+        }
+        assert!(!mutex.lock.is_locked());
+        assert_eq!(*mutex.lock(), 2);
+    }
+
+    #[test_case]
+    fn once_keeps_the_first_value() {
+        let once = Once::new();
+        assert!(once.get().is_none());
+        assert_eq!(*once.call_once(|| 7), 7);
+        assert_eq!(*once.call_once(|| 8), 7);
+        assert_eq!(once.get(), Some(&7));
+    }
+}
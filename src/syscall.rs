@@ -0,0 +1,305 @@
+use core::arch::naked_asm;
+
+use crate::caps::Caps;
+use crate::clock;
+use crate::filesystem::{get_filesystem, FileSystem, MAX_PATH_LEN};
+use crate::gdt;
+use crate::handle::{self, Handle, Object};
+use crate::idt;
+use crate::keyboard::Keyboard;
+use crate::metrics;
+use crate::signal;
+use crate::stackguard;
+use crate::usermode;
+use crate::writer::Writer;
+
+// System calls are made with `int 0x80`: the number in EAX, arguments in
+// EBX, ECX and EDX, and the result back in EAX, negative on failure.
+// Numbers follow Linux i386 where the call exists there. File descriptors
+// are handles into `handle`'s table. Calls that need a capability the
+// program wasn't given fail with EPERM.
+
+const SYSCALL_VECTOR: u8 = 0x80;
+
+/// What a program stopped by Ctrl+C exits with, 128 plus SIGINT
+const INTERRUPTED_EXIT: i32 = 130;
+
+/// exit(code): ends the program, does not return
+pub const SYS_EXIT: u32 = 1;
+/// read(fd, buf, len): reads one line from the keyboard (fd 0), with echo,
+/// or from an open file
+pub const SYS_READ: u32 = 3;
+/// write(fd, buf, len): writes to the console (fd 1 or 2)
+pub const SYS_WRITE: u32 = 4;
+/// open(path, flags): opens a file for reading; `path` ends with a NUL and
+/// `flags` must be O_RDONLY
+pub const SYS_OPEN: u32 = 5;
+/// close(fd)
+pub const SYS_CLOSE: u32 = 6;
+/// getpid(): id of the running program, counting up from 1 per `run`
+pub const SYS_GETPID: u32 = 20;
+/// dup(fd): another descriptor for the same open file
+pub const SYS_DUP: u32 = 41;
+/// sleep(ms): blocks for at least `ms` milliseconds, returns 0
+pub const SYS_SLEEP: u32 = 162;
+
+pub const EPERM: i32 = 1;
+pub const ENOENT: i32 = 2;
+pub const EBADF: i32 = 9;
+pub const EFAULT: i32 = 14;
+pub const EINVAL: i32 = 22;
+pub const ENFILE: i32 = 23;
+pub const EMFILE: i32 = 24;
+pub const ENAMETOOLONG: i32 = 36;
+pub const ENOSYS: i32 = 38;
+
+const O_RDONLY: u32 = 0;
+
+/// Console of the running program, valid between `begin_process` and
+/// `end_process`
+static mut CONSOLE: *mut Writer = core::ptr::null_mut();
+static mut PID: u32 = 0;
+static mut CAPS: Caps = Caps::NONE;
+static mut NEXT_PID: u32 = 1;
+
+/// Registers saved by `syscall_interrupt_handler`, in push order
+#[repr(C)]
+pub struct SyscallFrame {
+    eax: u32,
+    ebx: u32,
+    ecx: u32,
+    edx: u32,
+    esi: u32,
+    edi: u32,
+    ebp: u32,
+}
+
+pub fn init() {
+    idt::set_gate(SYSCALL_VECTOR, syscall_interrupt_handler as u32, true);
+}
+
+pub fn begin_process(writer: &mut Writer, caps: Caps) {
+    unsafe {
+        CONSOLE = writer;
+        CAPS = caps;
+        PID = NEXT_PID;
+        NEXT_PID += 1;
+    }
+    if let Err(e) = handle::begin_process() {
+        log_warn!("Handles: no console for the program ({})", e);
+    }
+}
+
+pub fn end_process() {
+    handle::end_process();
+    unsafe {
+        CONSOLE = core::ptr::null_mut();
+        PID = 0;
+        CAPS = Caps::NONE;
+    }
+}
+
+#[unsafe(naked)]
+#[no_mangle]
+pub unsafe extern "C" fn syscall_interrupt_handler() {
+    naked_asm!(
+        // Kernel code has no process to serve
+        "test dword ptr [esp + 4], 3",
+        "jnz 2f",
+        "mov eax, {enosys}",
+        "iretd",
+        "2:",
+        "push ds",
+        "push es",
+        "push ebp",
+        "push edi",
+        "push esi",
+        "push edx",
+        "push ecx",
+        "push ebx",
+        "push eax",
+        // DS and ES still hold the program's segment
+        "mov ax, {kernel_data}",
+        "mov ds, ax",
+        "mov es, ax",
+        // Calls like read and sleep wait for interrupts
+        "sti",
+        "push esp",
+        "call syscall_dispatch",
+        "add esp, 8",
+        "pop ebx",
+        "pop ecx",
+        "pop edx",
+        "pop esi",
+        "pop edi",
+        "pop ebp",
+        "pop es",
+        "pop ds",
+        "iretd",
+        enosys = const -ENOSYS,
+        kernel_data = const gdt::KERNEL_DATA as u32,
+    );
+}
+
+#[no_mangle]
+pub extern "C" fn syscall_dispatch(frame: &SyscallFrame) -> i32 {
+    let _canary = stackguard::Canary::new();
+    metrics::count_interrupt(SYSCALL_VECTOR);
+    exit_if_interrupted();
+    if !unsafe { CAPS }.contains(required_caps(frame.eax)) {
+        return -EPERM;
+    }
+    match frame.eax {
+        SYS_EXIT => unsafe { usermode::exit_to_kernel(frame.ebx as i32) },
+        SYS_READ => read(frame.ebx, frame.ecx, frame.edx),
+        SYS_WRITE => write(frame.ebx, frame.ecx, frame.edx),
+        SYS_OPEN => open(frame.ebx, frame.ecx),
+        SYS_CLOSE => handle::table().close(Handle::from_raw(frame.ebx)).map_or_else(|e| e, |()| 0),
+        SYS_DUP => handle::table().dup(Handle::from_raw(frame.ebx)).map_or_else(|e| e, |h| h.raw() as i32),
+        SYS_GETPID => unsafe { PID as i32 },
+        SYS_SLEEP => sleep(frame.ebx),
+        _ => -ENOSYS,
+    }
+}
+
+/// Ctrl+C ends a program at its next call, or while one waits for a key.
+/// One that never makes a call runs on.
+fn exit_if_interrupted() {
+    if signal::interrupted() {
+        unsafe { usermode::exit_to_kernel(INTERRUPTED_EXIT) }
+    }
+}
+
+/// What a call needs beyond running at all. Nothing needs `NET` or
+/// `RAW_IO` yet.
+fn required_caps(number: u32) -> Caps {
+    match number {
+        SYS_OPEN => Caps::FILES,
+        _ => Caps::NONE,
+    }
+}
+
+/// Borrow the user buffer at program address `address` after checking it
+/// lies in program memory
+fn user_buffer(address: u32, len: u32) -> Result<&'static mut [u8], i32> {
+    if !usermode::is_user_range(address, len) {
+        return Err(-EFAULT);
+    }
+    Ok(unsafe { core::slice::from_raw_parts_mut(usermode::physical(address), len as usize) })
+}
+
+/// Borrow a NUL-terminated user string, without the NUL
+fn user_string(address: u32, max_len: usize) -> Result<&'static [u8], i32> {
+    let mut len = 0;
+    loop {
+        let byte = user_buffer(address.wrapping_add(len as u32), 1)?;
+        if byte[0] == 0 {
+            return user_buffer(address, len as u32).map(|s| &*s);
+        }
+        len += 1;
+        if len > max_len {
+            return Err(-ENAMETOOLONG);
+        }
+    }
+}
+
+fn console() -> &'static mut Writer {
+    unsafe { &mut *CONSOLE }
+}
+
+fn open(path: u32, flags: u32) -> i32 {
+    if flags != O_RDONLY {
+        return -EINVAL;
+    }
+    let path = match user_string(path, MAX_PATH_LEN) {
+        Ok(path) => path,
+        Err(e) => return e,
+    };
+    let fs: &'static FileSystem = get_filesystem();
+    match fs.read_path(path) {
+        Some(data) => handle::table().open(Object::File { data, offset: 0 }).map_or_else(|e| e, |h| h.raw() as i32),
+        None => -ENOENT,
+    }
+}
+
+fn write(fd: u32, address: u32, len: u32) -> i32 {
+    let buf = match user_buffer(address, len) {
+        Ok(buf) => buf,
+        Err(e) => return e,
+    };
+    match handle::table().get(Handle::from_raw(fd)) {
+        Ok(Object::ConsoleOut) => {
+            console().write_bytes(buf);
+            len as i32
+        }
+        Ok(_) => -EBADF,
+        Err(e) => e,
+    }
+}
+
+fn read(fd: u32, address: u32, len: u32) -> i32 {
+    let buf = match user_buffer(address, len) {
+        Ok(buf) => buf,
+        Err(e) => return e,
+    };
+    match handle::table().get(Handle::from_raw(fd)) {
+        Ok(Object::ConsoleIn) => read_line(buf),
+        Ok(Object::File { data, offset }) => {
+            let count = buf.len().min(data.len() - *offset);
+            buf[..count].copy_from_slice(&data[*offset..*offset + count]);
+            *offset += count;
+            count as i32
+        }
+        Ok(Object::ConsoleOut) => -EBADF,
+        Err(e) => e,
+    }
+}
+
+/// Read one line from the keyboard with echo, including its newline if it
+/// fits
+fn read_line(buf: &mut [u8]) -> i32 {
+    let writer = console();
+    let mut count = 0;
+    let mut shift = false;
+
+    while count < buf.len() {
+        let scancode = match idt::get_scancode() {
+            Some(sc) => sc,
+            None => {
+                exit_if_interrupted();
+                idt::wait_for_interrupt();
+                continue;
+            }
+        };
+        match scancode {
+            0x2A | 0x36 => shift = true,
+            0xAA | 0xB6 => shift = false,
+            0x1C => {
+                writer.write_byte(b'\n');
+                buf[count] = b'\n';
+                count += 1;
+                break;
+            }
+            0x0E if count > 0 && writer.get_col() > 0 => {
+                count -= 1;
+                let (col, row) = (writer.get_col() - 1, writer.get_row());
+                writer.set_position(col, row);
+                writer.write_byte(b' ');
+                writer.set_position(col, row);
+            }
+            _ => {
+                for c in Keyboard::scancode_to_bytes(scancode, shift).take(buf.len() - count) {
+                    writer.write_byte(c);
+                    buf[count] = c;
+                    count += 1;
+                }
+            }
+        }
+    }
+    count as i32
+}
+
+fn sleep(ms: u32) -> i32 {
+    clock::sleep_ms(ms);
+    0
+}
@@ -0,0 +1,715 @@
+use crate::clock;
+use crate::idt;
+use crate::net::{self, Ipv4Addr, MAX_IP_PAYLOAD, PROTOCOL_TCP};
+use crate::netdev::MacAddress;
+use crate::rng;
+
+// TCP connections, opened by the kernel or accepted on a listening port,
+// enough for small transfers. Segments come in through `net::poll` and are
+// acknowledged at once; data that turns up out of order is dropped for the
+// peer to send again. What we send stays buffered until acknowledged, and
+// the oldest unacknowledged segment goes again whenever the timeout runs
+// out, the timeout doubling each time. We advertise the free space of the
+// receive buffer as the window and send no more than the peer's. There is
+// no TIME_WAIT: a connection's slot is freed once it is closed.
+
+const HEADER_LEN: usize = 20;
+const FIN: u8 = 0x01;
+const SYN: u8 = 0x02;
+const RST: u8 = 0x04;
+const PSH: u8 = 0x08;
+const ACK: u8 = 0x10;
+const OPTION_END: u8 = 0;
+const OPTION_NOP: u8 = 1;
+const OPTION_MSS: u8 = 2;
+
+/// Largest segment that fits one Ethernet frame
+const OUR_MSS: usize = MAX_IP_PAYLOAD - HEADER_LEN;
+/// What to assume when the peer doesn't say
+const DEFAULT_MSS: usize = 536;
+const MAX_CONNECTIONS: usize = 8;
+const MAX_LISTENERS: usize = 4;
+const RX_BUFFER_LEN: usize = 4096;
+const TX_BUFFER_LEN: usize = 1024;
+const INITIAL_RTO_MS: u64 = 1000;
+const MAX_RTO_MS: u64 = 8000;
+const MAX_RETRIES: u32 = 5;
+/// How long closing waits by default for the peer to acknowledge our FIN
+const CLOSE_TIMEOUT_MS: u64 = 2000;
+const EPHEMERAL_PORTS: u16 = 49152;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Header {
+    src_port: u16,
+    dst_port: u16,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    /// Only sent with SYN
+    mss: Option<u16>,
+}
+
+impl Header {
+    /// The header at the start of `segment` and where its data begins
+    fn parse(segment: &[u8]) -> Option<(Header, usize)> {
+        if segment.len() < HEADER_LEN {
+            return None;
+        }
+        let offset = (segment[12] >> 4) as usize * 4;
+        if offset < HEADER_LEN || offset > segment.len() {
+            return None;
+        }
+        let mut header = Header {
+            src_port: read_u16(segment, 0),
+            dst_port: read_u16(segment, 2),
+            seq: read_u32(segment, 4),
+            ack: read_u32(segment, 8),
+            flags: segment[13],
+            window: read_u16(segment, 14),
+            mss: None,
+        };
+        let mut options = &segment[HEADER_LEN..offset];
+        while let Some(&kind) = options.first() {
+            match kind {
+                OPTION_END => break,
+                OPTION_NOP => options = &options[1..],
+                _ => {
+                    let len = *options.get(1)? as usize;
+                    if len < 2 || len > options.len() {
+                        return None;
+                    }
+                    if kind == OPTION_MSS && len == 4 {
+                        header.mss = Some(read_u16(options, 2));
+                    }
+                    options = &options[len..];
+                }
+            }
+        }
+        Some((header, offset))
+    }
+
+    /// Write the header at the start of `out`, without its checksum;
+    /// returns its length
+    fn write(&self, out: &mut [u8]) -> usize {
+        let len = if self.mss.is_some() { HEADER_LEN + 4 } else { HEADER_LEN };
+        write_u16(out, 0, self.src_port);
+        write_u16(out, 2, self.dst_port);
+        out[4..8].copy_from_slice(&self.seq.to_be_bytes());
+        out[8..12].copy_from_slice(&self.ack.to_be_bytes());
+        out[12] = ((len / 4) as u8) << 4;
+        out[13] = self.flags;
+        write_u16(out, 14, self.window);
+        write_u16(out, 16, 0);
+        write_u16(out, 18, 0);
+        if let Some(mss) = self.mss {
+            out[20] = OPTION_MSS;
+            out[21] = 4;
+            write_u16(out, 22, mss);
+        }
+        len
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+fn write_u16(bytes: &mut [u8], offset: usize, value: u16) {
+    bytes[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+/// Send one segment with `header` and `data` to `dst` through `mac`
+fn send_segment(mac: MacAddress, dst: Ipv4Addr, header: &Header, data: &[u8]) -> Result<(), &'static str> {
+    let mut segment = [0u8; MAX_IP_PAYLOAD];
+    let header_len = header.write(&mut segment);
+    let len = header_len + data.len();
+    if len > segment.len() {
+        return Err("Segment too long");
+    }
+    segment[header_len..len].copy_from_slice(data);
+    let sum = net::transport_checksum(net::config().address, dst, PROTOCOL_TCP, &segment[..len]);
+    write_u16(&mut segment, 16, sum);
+    net::send_ipv4_via(mac, dst, PROTOCOL_TCP, &segment[..len])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Free,
+    SynSent,
+    /// Answered a SYN on a listening port, waiting for the ACK
+    SynReceived,
+    Established,
+    /// Refused, reset or timed out; the message says which
+    Failed(&'static str),
+}
+
+struct Connection {
+    state: State,
+    local_port: u16,
+    remote: Ipv4Addr,
+    remote_port: u16,
+    /// Next hop to the peer, looked up once when connecting
+    mac: MacAddress,
+    /// The listening port it came in on, zero if we connected
+    listener: u16,
+    /// Handed out by `TcpListener::accept`
+    accepted: bool,
+
+    /// Oldest sequence number not yet acknowledged
+    snd_una: u32,
+    snd_nxt: u32,
+    /// The peer's window and largest segment
+    snd_wnd: usize,
+    mss: usize,
+    /// Data from `snd_una` on, sent or not
+    tx: [u8; TX_BUFFER_LEN],
+    tx_len: usize,
+    /// Close asked for; FIN goes once all data is sent
+    fin_queued: bool,
+    fin_sent: bool,
+    fin_acked: bool,
+
+    rcv_nxt: u32,
+    rx: [u8; RX_BUFFER_LEN],
+    rx_head: usize,
+    rx_len: usize,
+    /// The peer sent FIN
+    peer_closed: bool,
+
+    /// When the oldest unacknowledged segment was last sent
+    sent_ms: u64,
+    rto_ms: u64,
+    retries: u32,
+}
+
+const FREE_CONNECTION: Connection = Connection {
+    state: State::Free,
+    local_port: 0,
+    remote: Ipv4Addr::UNSPECIFIED,
+    remote_port: 0,
+    mac: MacAddress([0; 6]),
+    listener: 0,
+    accepted: false,
+    snd_una: 0,
+    snd_nxt: 0,
+    snd_wnd: 0,
+    mss: DEFAULT_MSS,
+    tx: [0; TX_BUFFER_LEN],
+    tx_len: 0,
+    fin_queued: false,
+    fin_sent: false,
+    fin_acked: false,
+    rcv_nxt: 0,
+    rx: [0; RX_BUFFER_LEN],
+    rx_head: 0,
+    rx_len: 0,
+    peer_closed: false,
+    sent_ms: 0,
+    rto_ms: INITIAL_RTO_MS,
+    retries: 0,
+};
+
+static mut CONNECTIONS: [Connection; MAX_CONNECTIONS] = [FREE_CONNECTION; MAX_CONNECTIONS];
+static mut LISTENERS: [u16; MAX_LISTENERS] = [0; MAX_LISTENERS];
+static mut NEXT_EPHEMERAL: u16 = EPHEMERAL_PORTS;
+
+impl Connection {
+    fn header(&self, flags: u8, seq: u32) -> Header {
+        Header {
+            src_port: self.local_port,
+            dst_port: self.remote_port,
+            seq,
+            ack: if flags & ACK != 0 { self.rcv_nxt } else { 0 },
+            flags,
+            window: self.window(),
+            mss: if flags & SYN != 0 { Some(OUR_MSS as u16) } else { None },
+        }
+    }
+
+    fn window(&self) -> u16 {
+        (RX_BUFFER_LEN - self.rx_len) as u16
+    }
+
+    fn send(&self, flags: u8, seq: u32, data: &[u8]) -> Result<(), &'static str> {
+        send_segment(self.mac, self.remote, &self.header(flags, seq), data)
+    }
+
+    fn send_ack(&self) {
+        let _ = self.send(ACK, self.snd_nxt, &[]);
+    }
+
+    /// Sequence numbers sent but not yet acknowledged, SYN and FIN included
+    fn in_flight(&self) -> usize {
+        self.snd_nxt.wrapping_sub(self.snd_una) as usize
+    }
+
+    /// Bytes of data sent but not yet acknowledged
+    fn data_in_flight(&self) -> usize {
+        match self.state {
+            State::SynSent | State::SynReceived => 0,
+            _ => self.in_flight() - (self.fin_sent && !self.fin_acked) as usize,
+        }
+    }
+
+    /// Send the oldest unacknowledged segment again if it has waited too
+    /// long, then whatever new data the peer's window allows, then FIN
+    fn transmit(&mut self) -> Result<(), &'static str> {
+        let now = clock::uptime_ms();
+        if self.in_flight() > 0 && now - self.sent_ms >= self.rto_ms {
+            if self.retries == MAX_RETRIES {
+                self.state = State::Failed("Connection timed out");
+                return Err("Connection timed out");
+            }
+            self.retries += 1;
+            self.rto_ms = (self.rto_ms * 2).min(MAX_RTO_MS);
+            self.sent_ms = now;
+            let data_len = self.data_in_flight().min(self.mss);
+            if self.state == State::SynSent {
+                self.send(SYN, self.snd_una, &[])?;
+            } else if self.state == State::SynReceived {
+                self.send(SYN | ACK, self.snd_una, &[])?;
+            } else if data_len > 0 {
+                self.send(ACK | PSH, self.snd_una, &self.tx[..data_len])?;
+            } else {
+                self.send(FIN | ACK, self.snd_una, &[])?;
+            }
+        }
+        if self.state != State::Established {
+            return Ok(());
+        }
+
+        loop {
+            let sent = self.data_in_flight();
+            let len = (self.tx_len - sent).min(self.mss).min(self.snd_wnd.saturating_sub(sent));
+            if len == 0 {
+                break;
+            }
+            if self.in_flight() == 0 {
+                self.sent_ms = now;
+            }
+            self.send(ACK | PSH, self.snd_nxt, &self.tx[sent..sent + len])?;
+            self.snd_nxt = self.snd_nxt.wrapping_add(len as u32);
+        }
+        if self.fin_queued && !self.fin_sent && self.data_in_flight() == self.tx_len {
+            if self.in_flight() == 0 {
+                self.sent_ms = now;
+            }
+            self.send(FIN | ACK, self.snd_nxt, &[])?;
+            self.snd_nxt = self.snd_nxt.wrapping_add(1);
+            self.fin_sent = true;
+        }
+        Ok(())
+    }
+
+    /// Take an acknowledgement up to `ack` and the window that came with it
+    fn acknowledge(&mut self, ack: u32, window: u16) {
+        let acked = ack.wrapping_sub(self.snd_una) as usize;
+        // Something we never sent
+        if acked > self.in_flight() {
+            return;
+        }
+        self.snd_wnd = window as usize;
+        if acked == 0 {
+            return;
+        }
+        let data_acked = acked.min(self.data_in_flight());
+        self.tx.copy_within(data_acked..self.tx_len, 0);
+        self.tx_len -= data_acked;
+        if acked > data_acked {
+            self.fin_acked = true;
+        }
+        self.snd_una = ack;
+        self.retries = 0;
+        self.rto_ms = INITIAL_RTO_MS;
+        self.sent_ms = clock::uptime_ms();
+    }
+
+    /// Keep what fits of in-order `data`, returning how much
+    fn store(&mut self, data: &[u8]) -> usize {
+        let len = data.len().min(RX_BUFFER_LEN - self.rx_len);
+        for (i, &b) in data[..len].iter().enumerate() {
+            self.rx[(self.rx_head + self.rx_len + i) % RX_BUFFER_LEN] = b;
+        }
+        self.rx_len += len;
+        self.rcv_nxt = self.rcv_nxt.wrapping_add(len as u32);
+        len
+    }
+}
+
+fn find(local_port: u16, remote: Ipv4Addr, remote_port: u16) -> Option<&'static mut Connection> {
+    unsafe {
+        CONNECTIONS.iter_mut().find(|conn| {
+            conn.state != State::Free && conn.local_port == local_port && conn.remote == remote && conn.remote_port == remote_port
+        })
+    }
+}
+
+/// Answer a segment no connection wants with RST
+fn reset(mac: MacAddress, src: Ipv4Addr, header: &Header, data_len: usize) {
+    let mut reply = Header { src_port: header.dst_port, dst_port: header.src_port, seq: 0, ack: 0, flags: RST, window: 0, mss: None };
+    if header.flags & ACK != 0 {
+        reply.seq = header.ack;
+    } else {
+        let len = data_len + (header.flags & SYN != 0) as usize + (header.flags & FIN != 0) as usize;
+        reply.ack = header.seq.wrapping_add(len as u32);
+        reply.flags |= ACK;
+    }
+    let _ = send_segment(mac, src, &reply, &[]);
+}
+
+fn is_listening(port: u16) -> bool {
+    unsafe { LISTENERS.iter().any(|&listener| listener == port && port != 0) }
+}
+
+/// Answer a SYN on a listening port with SYN-ACK in a new connection. With
+/// no slot free the SYN goes unanswered and the peer tries again later.
+fn accept_syn(mac: MacAddress, src: Ipv4Addr, header: &Header) {
+    let conn = match unsafe { CONNECTIONS.iter_mut().find(|conn| conn.state == State::Free) } {
+        Some(conn) => conn,
+        None => return,
+    };
+    let iss = rng::random_u32();
+    *conn = FREE_CONNECTION;
+    conn.state = State::SynReceived;
+    conn.local_port = header.dst_port;
+    conn.remote = src;
+    conn.remote_port = header.src_port;
+    conn.mac = mac;
+    conn.listener = header.dst_port;
+    conn.snd_una = iss;
+    conn.snd_nxt = iss.wrapping_add(1);
+    conn.snd_wnd = header.window as usize;
+    conn.mss = header.mss.map_or(DEFAULT_MSS, |mss| (mss as usize).min(OUR_MSS));
+    conn.rcv_nxt = header.seq.wrapping_add(1);
+    conn.sent_ms = clock::uptime_ms();
+    let _ = conn.send(SYN | ACK, iss, &[]);
+}
+
+/// Handle a TCP segment `src` sent us. Called from `net::poll`.
+pub fn handle(mac: MacAddress, src: Ipv4Addr, dst: Ipv4Addr, segment: &[u8]) {
+    if net::transport_checksum(src, dst, PROTOCOL_TCP, segment) != 0 {
+        return;
+    }
+    let (header, offset) = match Header::parse(segment) {
+        Some(parsed) => parsed,
+        None => return,
+    };
+    let data = &segment[offset..];
+    let conn = match find(header.dst_port, src, header.src_port) {
+        Some(conn) => conn,
+        None => {
+            if header.flags & (SYN | ACK | RST) == SYN && is_listening(header.dst_port) {
+                accept_syn(mac, src, &header);
+            } else if header.flags & RST == 0 {
+                reset(mac, src, &header, data.len());
+            }
+            return;
+        }
+    };
+
+    match conn.state {
+        State::SynSent => {
+            if header.flags & ACK != 0 && header.ack != conn.snd_nxt {
+                if header.flags & RST == 0 {
+                    reset(mac, src, &header, data.len());
+                }
+                return;
+            }
+            if header.flags & RST != 0 {
+                if header.flags & ACK != 0 {
+                    conn.state = State::Failed("Connection refused");
+                }
+                return;
+            }
+            if header.flags & (SYN | ACK) == SYN | ACK {
+                conn.rcv_nxt = header.seq.wrapping_add(1);
+                conn.snd_una = header.ack;
+                conn.snd_wnd = header.window as usize;
+                conn.mss = header.mss.map_or(DEFAULT_MSS, |mss| (mss as usize).min(OUR_MSS));
+                conn.state = State::Established;
+                conn.retries = 0;
+                conn.rto_ms = INITIAL_RTO_MS;
+                conn.send_ack();
+            }
+            return;
+        }
+        State::SynReceived => {
+            if header.flags & RST != 0 {
+                conn.state = State::Free;
+                return;
+            }
+            if header.flags & ACK == 0 {
+                return;
+            }
+            if header.ack != conn.snd_nxt {
+                reset(mac, src, &header, data.len());
+                return;
+            }
+            // The handshake is done; the ACK may bring data along
+            conn.snd_una = header.ack;
+            conn.state = State::Established;
+            conn.retries = 0;
+            conn.rto_ms = INITIAL_RTO_MS;
+        }
+        State::Established => {}
+        _ => return,
+    }
+
+    if header.flags & RST != 0 {
+        conn.state = State::Failed("Connection reset");
+        return;
+    }
+    if header.flags & ACK != 0 {
+        conn.acknowledge(header.ack, header.window);
+    }
+    let fin = header.flags & FIN != 0;
+    // Only what comes next in order is kept; anything else gets the ACK
+    // again so the peer knows where we are
+    if header.seq == conn.rcv_nxt && !conn.peer_closed {
+        let stored = conn.store(data);
+        if fin && stored == data.len() {
+            conn.rcv_nxt = conn.rcv_nxt.wrapping_add(1);
+            conn.peer_closed = true;
+        }
+    }
+    if !data.is_empty() || fin {
+        conn.send_ack();
+    }
+}
+
+fn ephemeral_port() -> u16 {
+    loop {
+        let port = unsafe {
+            let port = NEXT_EPHEMERAL;
+            NEXT_EPHEMERAL = if port == u16::MAX { EPHEMERAL_PORTS } else { port + 1 };
+            port
+        };
+        if unsafe { CONNECTIONS.iter() }.all(|conn| conn.state == State::Free || conn.local_port != port) {
+            return port;
+        }
+    }
+}
+
+/// An open connection; dropping it closes it
+pub struct TcpStream {
+    slot: usize,
+    close_timeout_ms: u64,
+}
+
+impl TcpStream {
+    /// Connect to `port` on `ip`, waiting for the handshake
+    pub fn connect(ip: Ipv4Addr, port: u16) -> Result<TcpStream, &'static str> {
+        let mac = net::resolve(ip)?;
+        let slot = unsafe { CONNECTIONS.iter().position(|conn| conn.state == State::Free) }.ok_or("No free connections")?;
+        let conn = unsafe { &mut CONNECTIONS[slot] };
+        let iss = rng::random_u32();
+        *conn = FREE_CONNECTION;
+        conn.state = State::SynSent;
+        conn.local_port = ephemeral_port();
+        conn.remote = ip;
+        conn.remote_port = port;
+        conn.mac = mac;
+        conn.snd_una = iss;
+        conn.snd_nxt = iss.wrapping_add(1);
+        conn.sent_ms = clock::uptime_ms();
+        // From here on dropping the stream frees the slot
+        let stream = TcpStream { slot, close_timeout_ms: CLOSE_TIMEOUT_MS };
+        stream.conn().send(SYN, iss, &[])?;
+
+        loop {
+            net::poll();
+            let conn = stream.conn();
+            match conn.state {
+                State::Established => return Ok(stream),
+                State::Failed(e) => return Err(e),
+                _ => conn.transmit()?,
+            }
+            idt::wait_for_interrupt();
+        }
+    }
+
+    fn conn(&self) -> &'static mut Connection {
+        unsafe { &mut CONNECTIONS[self.slot] }
+    }
+
+    /// Wait at most `ms` for the peer to acknowledge our FIN when dropped
+    pub fn set_close_timeout(&mut self, ms: u64) {
+        self.close_timeout_ms = ms;
+    }
+
+    pub fn remote(&self) -> (Ipv4Addr, u16) {
+        let conn = self.conn();
+        (conn.remote, conn.remote_port)
+    }
+
+    /// Queue as much of `data` as there is room for and send what the
+    /// peer's window allows; returns how much was taken
+    pub fn write(&self, data: &[u8]) -> Result<usize, &'static str> {
+        net::poll();
+        let conn = self.conn();
+        if let State::Failed(e) = conn.state {
+            return Err(e);
+        }
+        if conn.fin_queued {
+            return Err("Connection closed");
+        }
+        let len = data.len().min(TX_BUFFER_LEN - conn.tx_len);
+        conn.tx[conn.tx_len..conn.tx_len + len].copy_from_slice(&data[..len]);
+        conn.tx_len += len;
+        conn.transmit()?;
+        Ok(len)
+    }
+
+    /// Queue all of `data`, waiting for room as the peer acknowledges
+    pub fn write_all(&self, mut data: &[u8]) -> Result<(), &'static str> {
+        while !data.is_empty() {
+            let len = self.write(data)?;
+            data = &data[len..];
+            if !data.is_empty() {
+                idt::wait_for_interrupt();
+            }
+        }
+        Ok(())
+    }
+
+    /// Received data copied into `buf`: None if there is none yet, Some(0)
+    /// once the peer has closed and everything has been read
+    pub fn read(&self, buf: &mut [u8]) -> Result<Option<usize>, &'static str> {
+        net::poll();
+        let conn = self.conn();
+        if conn.rx_len == 0 {
+            if let State::Failed(e) = conn.state {
+                return Err(e);
+            }
+            conn.transmit()?;
+            return Ok(if conn.peer_closed { Some(0) } else { None });
+        }
+        let was_full = (conn.window() as usize) < conn.mss;
+        let len = buf.len().min(conn.rx_len);
+        for (i, b) in buf[..len].iter_mut().enumerate() {
+            *b = conn.rx[(conn.rx_head + i) % RX_BUFFER_LEN];
+        }
+        conn.rx_head = (conn.rx_head + len) % RX_BUFFER_LEN;
+        conn.rx_len -= len;
+        // Tell a peer that stopped for a full window that there is room again
+        if was_full && conn.window() as usize >= conn.mss && conn.state == State::Established {
+            conn.send_ack();
+        }
+        Ok(Some(len))
+    }
+}
+
+impl Drop for TcpStream {
+    /// Send FIN after any data still queued and wait a little for the peer
+    /// to acknowledge it
+    fn drop(&mut self) {
+        let conn = self.conn();
+        if conn.state == State::Established {
+            conn.fin_queued = true;
+            let deadline = clock::uptime_ms() + self.close_timeout_ms;
+            while clock::uptime_ms() < deadline {
+                net::poll();
+                if conn.fin_acked || conn.transmit().is_err() || conn.state != State::Established {
+                    break;
+                }
+                idt::wait_for_interrupt();
+            }
+        }
+        conn.state = State::Free;
+    }
+}
+
+/// A listening port; dropping it stops listening
+pub struct TcpListener {
+    port: u16,
+}
+
+impl TcpListener {
+    pub fn bind(port: u16) -> Result<TcpListener, &'static str> {
+        if port == 0 {
+            return Err("Invalid port");
+        }
+        if is_listening(port) {
+            return Err("Port already in use");
+        }
+        let slot = unsafe { LISTENERS.iter_mut().find(|listener| **listener == 0) }.ok_or("Too many listening ports")?;
+        *slot = port;
+        Ok(TcpListener { port })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// A connection that has finished its handshake, if one is waiting.
+    /// Handshakes still going are kept moving here, and dropped once the
+    /// peer stops answering.
+    pub fn accept(&self) -> Option<TcpStream> {
+        net::poll();
+        for slot in 0..MAX_CONNECTIONS {
+            let conn = unsafe { &mut CONNECTIONS[slot] };
+            if conn.state == State::Free || conn.listener != self.port || conn.accepted {
+                continue;
+            }
+            match conn.state {
+                State::Established => {
+                    conn.accepted = true;
+                    return Some(TcpStream { slot, close_timeout_ms: CLOSE_TIMEOUT_MS });
+                }
+                State::SynReceived => {
+                    if conn.transmit().is_err() {
+                        conn.state = State::Free;
+                    }
+                }
+                // Reset before it was accepted
+                _ => conn.state = State::Free,
+            }
+        }
+        None
+    }
+}
+
+impl Drop for TcpListener {
+    /// Connections already accepted carry on
+    fn drop(&mut self) {
+        unsafe {
+            for listener in LISTENERS.iter_mut().filter(|listener| **listener == self.port) {
+                *listener = 0;
+            }
+            for conn in CONNECTIONS.iter_mut().filter(|conn| conn.listener == self.port && !conn.accepted) {
+                conn.state = State::Free;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn headers_round_trip_and_acks_free_the_buffer() {
+        let header = Header { src_port: 49152, dst_port: 80, seq: 0xFFFF_FFF0, ack: 7, flags: SYN | ACK, window: 4096, mss: Some(1460) };
+        let mut segment = [0u8; HEADER_LEN + 4];
+        assert_eq!(header.write(&mut segment), HEADER_LEN + 4);
+        assert_eq!(Header::parse(&segment), Some((header, HEADER_LEN + 4)));
+        segment[12] = 4 << 4;
+        assert_eq!(Header::parse(&segment), None);
+
+        // Sequence numbers wrap past zero
+        let mut conn = FREE_CONNECTION;
+        conn.state = State::Established;
+        conn.snd_una = 0xFFFF_FFF0;
+        conn.snd_nxt = 0xFFFF_FFF0u32.wrapping_add(32);
+        conn.tx_len = 40;
+        conn.acknowledge(0x0000_0008, 1000);
+        assert_eq!((conn.tx_len, conn.in_flight(), conn.snd_wnd), (16, 8, 1000));
+        conn.acknowledge(0x0000_0100, 2000);
+        assert_eq!((conn.tx_len, conn.snd_wnd), (16, 1000));
+    }
+}
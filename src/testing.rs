@@ -0,0 +1,32 @@
+use core::panic::PanicInfo;
+
+use crate::crash;
+use crate::qemu::{exit_qemu, ExitCode};
+
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        serial_print!("{}...\t", core::any::type_name::<T>());
+        self();
+        serial_println!("[ok]");
+    }
+}
+
+/// Runner for `#[test_case]` functions, reporting over COM1
+pub fn test_runner(tests: &[&dyn Testable]) {
+    serial_println!("Running {} tests", tests.len());
+    for test in tests {
+        test.run();
+    }
+    exit_qemu(ExitCode::Success);
+}
+
+pub fn test_panic(info: &PanicInfo) -> ! {
+    serial_println!("[failed]\n");
+    serial_println!("Error: {}", info);
+    exit_qemu(ExitCode::Failed);
+    crash::halt();
+}
@@ -0,0 +1,436 @@
+use crate::writer::Writer;
+use crate::vga_colors::{Color, color_code};
+use crate::idt;
+use crate::filesystem::get_filesystem;
+
+const BOARD_WIDTH: usize = 10;
+const BOARD_HEIGHT: usize = 20;
+const BOARD_OFFSET_X: usize = 30;
+const BOARD_OFFSET_Y: usize = 2;
+const INFO_OFFSET_X: usize = 56;
+const SCORES_OFFSET_X: usize = 4;
+
+const MAX_HIGH_SCORES: usize = 5;
+const HIGH_SCORE_FILE: &[u8] = b"tetris.hs";
+
+const BLOCK: u8 = 0xDB;
+
+/// Each piece has four rotations encoded as 4x4 bitmasks, row-major from the top-left
+const PIECES: [[u16; 4]; 7] = [
+    [0x0F00, 0x2222, 0x00F0, 0x4444], // I
+    [0x44C0, 0x8E00, 0x6440, 0x0E20], // J
+    [0x4460, 0x0E80, 0xC440, 0x2E00], // L
+    [0xCC00, 0xCC00, 0xCC00, 0xCC00], // O
+    [0x06C0, 0x8C40, 0x6C00, 0x4620], // S
+    [0x0E40, 0x4C40, 0x4E00, 0x4640], // T
+    [0x0C60, 0x4C80, 0xC600, 0x2640], // Z
+];
+
+const PIECE_COLORS: [Color; 7] = [
+    Color::LightCyan,
+    Color::LightBlue,
+    Color::Brown,
+    Color::Yellow,
+    Color::LightGreen,
+    Color::Pink,
+    Color::LightRed,
+];
+
+#[derive(Copy, Clone)]
+struct Piece {
+    kind: usize,
+    rotation: usize,
+    x: i32,
+    y: i32,
+}
+
+impl Piece {
+    /// Board coordinates of the four blocks
+    fn blocks(&self) -> [(i32, i32); 4] {
+        let mask = PIECES[self.kind][self.rotation];
+        let mut blocks = [(0, 0); 4];
+        let mut n = 0;
+        for i in 0..16 {
+            if mask & (0x8000 >> i) != 0 {
+                blocks[n] = (self.x + (i % 4) as i32, self.y + (i / 4) as i32);
+                n += 1;
+            }
+        }
+        blocks
+    }
+}
+
+pub struct TetrisGame {
+    /// 0 for empty, otherwise piece kind + 1
+    board: [[u8; BOARD_WIDTH]; BOARD_HEIGHT],
+    current: Piece,
+    next_kind: usize,
+    score: u32,
+    lines: u32,
+    level: u32,
+    game_over: bool,
+    paused: bool,
+    seed: u32,
+    high_scores: [u32; MAX_HIGH_SCORES],
+}
+
+impl TetrisGame {
+    pub fn new() -> Self {
+        let mut game = Self {
+            board: [[0; BOARD_WIDTH]; BOARD_HEIGHT],
+            current: Piece { kind: 0, rotation: 0, x: 3, y: 0 },
+            next_kind: 0,
+            score: 0,
+            lines: 0,
+            level: 0,
+            game_over: false,
+            paused: false,
+            seed: idt::get_ticks().wrapping_add(12345),
+            high_scores: [0; MAX_HIGH_SCORES],
+        };
+        game.current.kind = game.random_kind();
+        game.next_kind = game.random_kind();
+        game.load_high_scores();
+        game
+    }
+
+    fn random_kind(&mut self) -> usize {
+        self.seed = self.seed.wrapping_mul(1103515245).wrapping_add(12345);
+        ((self.seed >> 16) % 7) as usize
+    }
+
+    fn fits(&self, piece: &Piece) -> bool {
+        for &(x, y) in piece.blocks().iter() {
+            if x < 0 || x >= BOARD_WIDTH as i32 || y >= BOARD_HEIGHT as i32 {
+                return false;
+            }
+            if y >= 0 && self.board[y as usize][x as usize] != 0 {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn try_move(&mut self, dx: i32, dy: i32, rotate: bool) -> bool {
+        let mut moved = self.current;
+        moved.x += dx;
+        moved.y += dy;
+        if rotate {
+            moved.rotation = (moved.rotation + 1) % 4;
+        }
+
+        if self.fits(&moved) {
+            self.current = moved;
+            return true;
+        }
+
+        // Simple wall kick: nudge a rotated piece away from the edge
+        if rotate {
+            for &kick in [-1, 1, -2, 2].iter() {
+                let mut kicked = moved;
+                kicked.x += kick;
+                if self.fits(&kicked) {
+                    self.current = kicked;
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Lock the current piece, clear lines and spawn the next one
+    fn lock_piece(&mut self) {
+        for &(x, y) in self.current.blocks().iter() {
+            if y < 0 {
+                self.game_over = true;
+                return;
+            }
+            self.board[y as usize][x as usize] = self.current.kind as u8 + 1;
+        }
+
+        let mut cleared = 0;
+        let mut y = BOARD_HEIGHT;
+        while y > 0 {
+            y -= 1;
+            if self.board[y].iter().all(|&cell| cell != 0) {
+                for row in (1..=y).rev() {
+                    self.board[row] = self.board[row - 1];
+                }
+                self.board[0] = [0; BOARD_WIDTH];
+                cleared += 1;
+                // Re-check the same row, it now holds the one above
+                y += 1;
+            }
+        }
+
+        if cleared > 0 {
+            let points = match cleared {
+                1 => 40,
+                2 => 100,
+                3 => 300,
+                _ => 1200,
+            };
+            self.score += points * (self.level + 1);
+            self.lines += cleared;
+            self.level = self.lines / 10;
+        }
+
+        self.current = Piece { kind: self.next_kind, rotation: 0, x: 3, y: -1 };
+        self.next_kind = self.random_kind();
+        if !self.fits(&self.current) {
+            self.game_over = true;
+        }
+    }
+
+    fn gravity_ticks(&self) -> u32 {
+        50u32.saturating_sub(self.level * 5).max(5)
+    }
+
+    fn draw_cell(&self, x: usize, y: usize, ch: u8, color: u8) {
+        Writer::set_cell(x, y, (color as u16) << 8 | ch as u16);
+    }
+
+    fn draw_text(&self, x: usize, y: usize, text: &[u8], color: u8) {
+        for (i, &byte) in text.iter().enumerate() {
+            self.draw_cell(x + i, y, byte, color);
+        }
+    }
+
+    fn draw_number(&self, x: usize, y: usize, mut n: u32, color: u8) {
+        let mut digits = [b' '; 8];
+        let mut i = digits.len();
+        loop {
+            i -= 1;
+            digits[i] = b'0' + (n % 10) as u8;
+            n /= 10;
+            if n == 0 || i == 0 {
+                break;
+            }
+        }
+        self.draw_text(x, y, &digits, color);
+    }
+
+    /// Draw one board cell, two characters wide to look square
+    fn draw_block(&self, x: usize, y: usize, kind: Option<usize>) {
+        let (ch, color) = match kind {
+            Some(kind) => (BLOCK, color_code(PIECE_COLORS[kind], Color::Black)),
+            None => (b' ', 0x00),
+        };
+        let screen_x = BOARD_OFFSET_X + x * 2;
+        let screen_y = BOARD_OFFSET_Y + y;
+        self.draw_cell(screen_x, screen_y, ch, color);
+        self.draw_cell(screen_x + 1, screen_y, ch, color);
+    }
+
+    fn draw_frame(&self) {
+        let border = color_code(Color::LightGray, Color::Black);
+        for y in 0..BOARD_HEIGHT {
+            self.draw_cell(BOARD_OFFSET_X - 1, BOARD_OFFSET_Y + y, b'|', border);
+            self.draw_cell(BOARD_OFFSET_X + BOARD_WIDTH * 2, BOARD_OFFSET_Y + y, b'|', border);
+        }
+        for x in 0..BOARD_WIDTH * 2 + 2 {
+            self.draw_cell(BOARD_OFFSET_X - 1 + x, BOARD_OFFSET_Y + BOARD_HEIGHT, b'-', border);
+        }
+
+        let title = color_code(Color::Yellow, Color::Black);
+        let text = color_code(Color::LightGray, Color::Black);
+        self.draw_text(BOARD_OFFSET_X + 6, 0, b"T E T R I S", title);
+        self.draw_text(INFO_OFFSET_X, 2, b"Next:", title);
+        self.draw_text(INFO_OFFSET_X, 9, b"Score:", title);
+        self.draw_text(INFO_OFFSET_X, 11, b"Lines:", title);
+        self.draw_text(INFO_OFFSET_X, 13, b"Level:", title);
+        self.draw_text(INFO_OFFSET_X, 16, b"Arrows: move/rotate", text);
+        self.draw_text(INFO_OFFSET_X, 17, b"Space:  drop", text);
+        self.draw_text(INFO_OFFSET_X, 18, b"P:      pause", text);
+        self.draw_text(INFO_OFFSET_X, 19, b"Q:      quit", text);
+
+        self.draw_text(SCORES_OFFSET_X, 2, b"High scores:", title);
+        for (i, &score) in self.high_scores.iter().enumerate() {
+            self.draw_cell(SCORES_OFFSET_X, 4 + i, b'1' + i as u8, text);
+            self.draw_cell(SCORES_OFFSET_X + 1, 4 + i, b'.', text);
+            self.draw_number(SCORES_OFFSET_X + 3, 4 + i, score, text);
+        }
+    }
+
+    fn draw(&self) {
+        let current = self.current.blocks();
+        for y in 0..BOARD_HEIGHT {
+            for x in 0..BOARD_WIDTH {
+                let cell = self.board[y][x];
+                let kind = if current.iter().any(|&(px, py)| px == x as i32 && py == y as i32) {
+                    Some(self.current.kind)
+                } else if cell != 0 {
+                    Some(cell as usize - 1)
+                } else {
+                    None
+                };
+                self.draw_block(x, y, kind);
+            }
+        }
+
+        // Next piece preview in a 4x4 box
+        let preview = Piece { kind: self.next_kind, rotation: 0, x: 0, y: 0 };
+        let blocks = preview.blocks();
+        for y in 0..4 {
+            for x in 0..4 {
+                let filled = blocks.iter().any(|&(px, py)| px == x && py == y);
+                let (ch, color) = if filled {
+                    (BLOCK, color_code(PIECE_COLORS[self.next_kind], Color::Black))
+                } else {
+                    (b' ', 0x00)
+                };
+                let sx = INFO_OFFSET_X + 2 + x as usize * 2;
+                let sy = 4 + y as usize;
+                self.draw_cell(sx, sy, ch, color);
+                self.draw_cell(sx + 1, sy, ch, color);
+            }
+        }
+
+        let value = color_code(Color::White, Color::Black);
+        self.draw_number(INFO_OFFSET_X + 7, 9, self.score, value);
+        self.draw_number(INFO_OFFSET_X + 7, 11, self.lines, value);
+        self.draw_number(INFO_OFFSET_X + 7, 13, self.level, value);
+    }
+
+    fn load_high_scores(&mut self) {
+        let fs = get_filesystem();
+        let saved_dir = fs.current_dir();
+        fs.set_current_dir(0);
+        if let Some(content) = fs.read_file(HIGH_SCORE_FILE) {
+            let mut count = 0;
+            for line in content.split(|&b| b == b'\n') {
+                if count >= MAX_HIGH_SCORES {
+                    break;
+                }
+                let mut value: u32 = 0;
+                for &d in line {
+                    if d.is_ascii_digit() {
+                        value = value.saturating_mul(10).saturating_add((d - b'0') as u32);
+                    }
+                }
+                if !line.is_empty() {
+                    self.high_scores[count] = value;
+                    count += 1;
+                }
+            }
+        }
+        fs.set_current_dir(saved_dir);
+    }
+
+    /// Insert the final score into the table and write it back to `/tetris.hs`
+    fn record_high_score(&mut self) -> bool {
+        let position = match self.high_scores.iter().position(|&s| self.score > s) {
+            Some(position) => position,
+            None => return false,
+        };
+        for i in (position + 1..MAX_HIGH_SCORES).rev() {
+            self.high_scores[i] = self.high_scores[i - 1];
+        }
+        self.high_scores[position] = self.score;
+
+        let mut content = [0u8; MAX_HIGH_SCORES * 11];
+        let mut len = 0;
+        for &score in self.high_scores.iter() {
+            let mut digits = [0u8; 10];
+            let mut n = score;
+            let mut count = 0;
+            loop {
+                digits[count] = b'0' + (n % 10) as u8;
+                n /= 10;
+                count += 1;
+                if n == 0 {
+                    break;
+                }
+            }
+            for i in (0..count).rev() {
+                content[len] = digits[i];
+                len += 1;
+            }
+            content[len] = b'\n';
+            len += 1;
+        }
+
+        let fs = get_filesystem();
+        let saved_dir = fs.current_dir();
+        fs.set_current_dir(0);
+        let _ = fs.write_file(HIGH_SCORE_FILE, &content[..len]);
+        fs.set_current_dir(saved_dir);
+        true
+    }
+
+    pub fn run(&mut self, writer: &mut Writer) {
+        idt::flush_buffer();
+        writer.clear();
+        self.draw_frame();
+        self.draw();
+
+        let mut last_drop = idt::get_ticks();
+
+        loop {
+            idt::wait_for_interrupt();
+
+            while let Some(scancode) = idt::get_scancode() {
+                if scancode & 0x80 != 0 || scancode == 0xE0 {
+                    continue;
+                }
+                match scancode {
+                    0x10 | 0x01 => return, // Q / ESC
+                    0x19 => self.paused = !self.paused, // P
+                    _ if self.paused => {}
+                    0x4B => { self.try_move(-1, 0, false); }
+                    0x4D => { self.try_move(1, 0, false); }
+                    0x48 => { self.try_move(0, 0, true); }
+                    0x50 => {
+                        if self.try_move(0, 1, false) {
+                            self.score += 1;
+                        }
+                        last_drop = idt::get_ticks();
+                    }
+                    0x39 => {
+                        while self.try_move(0, 1, false) {
+                            self.score += 2;
+                        }
+                        self.lock_piece();
+                        last_drop = idt::get_ticks();
+                    }
+                    _ => {}
+                }
+                self.draw();
+            }
+
+            if self.paused {
+                continue;
+            }
+
+            let now = idt::get_ticks();
+            if now.wrapping_sub(last_drop) >= self.gravity_ticks() {
+                last_drop = now;
+                if !self.try_move(0, 1, false) {
+                    self.lock_piece();
+                }
+                self.draw();
+            }
+
+            if self.game_over {
+                break;
+            }
+        }
+
+        let message: &[u8] = if self.record_high_score() {
+            b" NEW HIGH SCORE! Q to exit "
+        } else {
+            b"  GAME OVER! Press Q to exit "
+        };
+        self.draw_frame();
+        self.draw_text(BOARD_OFFSET_X - 4, BOARD_OFFSET_Y + BOARD_HEIGHT / 2, message, 0x4F);
+
+        loop {
+            idt::wait_for_interrupt();
+            if let Some(scancode) = idt::get_scancode() {
+                if scancode == 0x10 || scancode == 0x01 {
+                    return;
+                }
+            }
+        }
+    }
+}
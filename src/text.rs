@@ -0,0 +1,492 @@
+use core::cmp::Ordering;
+
+use crate::filesystem::MAX_FILE_SIZE;
+use crate::sink::Sink;
+
+// The line handling behind `wc`, `head`, `tail`, `sort`, `uniq`, `diff`
+// and `sed`. Text is a file or a pipe's worth, so never more than a file
+// holds. A last line without a newline still counts as a line, except to
+// `wc -l`, which counts newlines like everyone else's does.
+
+/// A line per byte is the most a file can have
+const MAX_LINES: usize = MAX_FILE_SIZE;
+
+/// Lines that differ, in each text, that `diff` can match up. Its table
+/// is this squared, and a line count this small fits in a byte.
+const MAX_DIFF_LINES: usize = 250;
+/// Unchanged lines shown around each change
+const DIFF_CONTEXT: usize = 3;
+
+/// Start and length of each line being sorted, or of the old text being
+/// compared
+static mut LINES: [(u16, u16); MAX_LINES] = [(0, 0); MAX_LINES];
+/// Start and length of each line of the new text being compared
+static mut NEW_LINES: [(u16, u16); MAX_LINES] = [(0, 0); MAX_LINES];
+/// Longest common subsequence of the lines from `[i]` and `[j]` on
+static mut TABLE: [[u8; MAX_DIFF_LINES + 1]; MAX_DIFF_LINES + 1] = [[0; MAX_DIFF_LINES + 1]; MAX_DIFF_LINES + 1];
+static mut EDITS: [Edit; 2 * MAX_LINES] = [Edit::Same; 2 * MAX_LINES];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Counts {
+    pub lines: usize,
+    pub words: usize,
+    pub bytes: usize,
+}
+
+impl Counts {
+    pub fn add(&mut self, other: Counts) {
+        self.lines += other.lines;
+        self.words += other.words;
+        self.bytes += other.bytes;
+    }
+}
+
+pub fn count(text: &[u8]) -> Counts {
+    Counts {
+        lines: text.iter().filter(|&&b| b == b'\n').count(),
+        words: text.split(|b| b.is_ascii_whitespace()).filter(|word| !word.is_empty()).count(),
+        bytes: text.len(),
+    }
+}
+
+fn lines(text: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let empty = text.is_empty();
+    let text = text.strip_suffix(b"\n").unwrap_or(text);
+    text.split(|&b| b == b'\n').filter(move |_| !empty)
+}
+
+/// Record where each line of `text` starts and how long it is
+fn index_lines(text: &[u8], slots: &mut [(u16, u16)]) -> Result<usize, &'static str> {
+    let mut count = 0;
+    let mut start = 0;
+    for line in lines(text) {
+        *slots.get_mut(count).ok_or("Too many lines")? = (start as u16, line.len() as u16);
+        count += 1;
+        start += line.len() + 1;
+    }
+    Ok(count)
+}
+
+fn line_at(text: &[u8], (start, len): (u16, u16)) -> &[u8] {
+    &text[start as usize..start as usize + len as usize]
+}
+
+/// The first `n` lines
+pub fn head(text: &[u8], n: usize) -> &[u8] {
+    if n == 0 {
+        return &text[..0];
+    }
+    let end = text.iter().enumerate().filter(|&(_, &b)| b == b'\n').nth(n - 1).map_or(text.len(), |(i, _)| i + 1);
+    &text[..end]
+}
+
+/// The last `n` lines
+pub fn tail(text: &[u8], n: usize) -> &[u8] {
+    if n == 0 {
+        return &text[text.len()..];
+    }
+    let body = text.strip_suffix(b"\n").unwrap_or(text);
+    let start = body.iter().enumerate().rev().filter(|&(_, &b)| b == b'\n').nth(n - 1).map_or(0, |(i, _)| i + 1);
+    &text[start..]
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SortOptions {
+    pub reverse: bool,
+    /// By the number each line starts with
+    pub numeric: bool,
+    /// Drop lines equal to the one before, after sorting
+    pub unique: bool,
+}
+
+/// Write the lines of `text` sorted. Equal lines keep their order.
+pub fn sort(text: &[u8], options: SortOptions, out: &mut dyn Sink) -> Result<(), &'static str> {
+    let slots = unsafe { &mut LINES };
+    let count = index_lines(text, slots)?;
+    let line = |&slot: &(u16, u16)| line_at(text, slot);
+    let compare = |a: &(u16, u16), b: &(u16, u16)| {
+        let order = if options.numeric {
+            leading_number(line(a)).cmp(&leading_number(line(b))).then_with(|| line(a).cmp(line(b)))
+        } else {
+            line(a).cmp(line(b))
+        };
+        if options.reverse { order.reverse() } else { order }
+    };
+    let lines = &mut slots[..count];
+    lines.sort_unstable_by(|a, b| compare(a, b).then(a.0.cmp(&b.0)));
+
+    let mut previous: Option<&(u16, u16)> = None;
+    for slot in lines.iter() {
+        if options.unique && previous.map_or(false, |previous| compare(previous, slot) == Ordering::Equal) {
+            continue;
+        }
+        out.write_bytes(line(slot));
+        out.write_byte(b'\n');
+        previous = Some(slot);
+    }
+    Ok(())
+}
+
+/// The number a line starts with after any spaces, zero if none does
+fn leading_number(line: &[u8]) -> i64 {
+    let line = &line[line.iter().position(|&b| b != b' ' && b != b'\t').unwrap_or(line.len())..];
+    let (negative, digits) = match line.first() {
+        Some(b'-') => (true, &line[1..]),
+        _ => (false, line),
+    };
+    let value = digits
+        .iter()
+        .take_while(|b| b.is_ascii_digit())
+        .fold(0i64, |value, &digit| value.saturating_mul(10).saturating_add((digit - b'0') as i64));
+    if negative { -value } else { value }
+}
+
+/// Write each run of equal adjacent lines once, with `count` prefixed by
+/// its length, and with `repeated` only the runs of more than one
+pub fn uniq(text: &[u8], count: bool, repeated: bool, out: &mut dyn Sink) {
+    let mut lines = lines(text).peekable();
+    while let Some(line) = lines.next() {
+        let mut run = 1;
+        while lines.peek() == Some(&line) {
+            lines.next();
+            run += 1;
+        }
+        if repeated && run == 1 {
+            continue;
+        }
+        if count {
+            write!(out, "{:>7} ", run);
+        }
+        out.write_bytes(line);
+        out.write_byte(b'\n');
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edit {
+    Same,
+    Removed,
+    Added,
+}
+
+/// Write a unified diff that turns `old` into `new`, under `---` and `+++`
+/// lines naming them. Returns whether they differ. Lines the two start and
+/// end with are set aside before the rest is matched up, so only that rest
+/// has to fit in `MAX_DIFF_LINES`.
+pub fn diff(old_name: &[u8], old: &[u8], new_name: &[u8], new: &[u8], out: &mut dyn Sink) -> Result<bool, &'static str> {
+    let (old_slots, new_slots) = unsafe { (&mut LINES, &mut NEW_LINES) };
+    let old_count = index_lines(old, old_slots)?;
+    let new_count = index_lines(new, new_slots)?;
+    let (a, b) = (&old_slots[..old_count], &new_slots[..new_count]);
+    let same = |i: usize, j: usize| line_at(old, a[i]) == line_at(new, b[j]);
+
+    let shorter = a.len().min(b.len());
+    let prefix = (0..shorter).take_while(|&i| same(i, i)).count();
+    let suffix = (0..shorter - prefix).take_while(|&k| same(a.len() - 1 - k, b.len() - 1 - k)).count();
+    let (n, m) = (a.len() - prefix - suffix, b.len() - prefix - suffix);
+    if n == 0 && m == 0 {
+        return Ok(false);
+    }
+    if n > MAX_DIFF_LINES || m > MAX_DIFF_LINES {
+        return Err("Too many changed lines to compare");
+    }
+
+    let table = unsafe { &mut TABLE };
+    for i in (0..=n).rev() {
+        for j in (0..=m).rev() {
+            table[i][j] = if i == n || j == m {
+                0
+            } else if same(prefix + i, prefix + j) {
+                table[i + 1][j + 1] + 1
+            } else {
+                table[i + 1][j].max(table[i][j + 1])
+            };
+        }
+    }
+
+    let edits = unsafe { &mut EDITS };
+    let mut len = 0;
+    for _ in 0..prefix {
+        edits[len] = Edit::Same;
+        len += 1;
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        let edit = if i < n && j < m && same(prefix + i, prefix + j) {
+            Edit::Same
+        } else if j == m || (i < n && table[i + 1][j] >= table[i][j + 1]) {
+            Edit::Removed
+        } else {
+            Edit::Added
+        };
+        if edit != Edit::Added {
+            i += 1;
+        }
+        if edit != Edit::Removed {
+            j += 1;
+        }
+        edits[len] = edit;
+        len += 1;
+    }
+    for _ in 0..suffix {
+        edits[len] = Edit::Same;
+        len += 1;
+    }
+
+    out.write_str("--- ");
+    out.write_bytes(old_name);
+    out.write_str("\n+++ ");
+    out.write_bytes(new_name);
+    out.write_byte(b'\n');
+    write_hunks(&edits[..len], old, a, new, b, out);
+    Ok(true)
+}
+
+/// Write each run of changes less than twice the context apart as one
+/// `@@` hunk, with the unchanged lines around it
+fn write_hunks(edits: &[Edit], old: &[u8], a: &[(u16, u16)], new: &[u8], b: &[(u16, u16)], out: &mut dyn Sink) {
+    let is_change = |edit: &Edit| *edit != Edit::Same;
+    // Lines of each text before `edits[done]`
+    let (mut done, mut i, mut j) = (0, 0, 0);
+    while let Some(first) = edits[done..].iter().position(is_change).map(|at| done + at) {
+        let mut last = first;
+        while let Some(gap) = edits[last + 1..].iter().position(is_change).filter(|&gap| gap <= 2 * DIFF_CONTEXT) {
+            last += gap + 1;
+        }
+        let start = first.saturating_sub(DIFF_CONTEXT).max(done);
+        let end = (last + 1 + DIFF_CONTEXT).min(edits.len());
+        i += start - done;
+        j += start - done;
+
+        let hunk = &edits[start..end];
+        out.write_str("@@ -");
+        write_range(out, i, hunk.iter().filter(|&&edit| edit != Edit::Added).count());
+        out.write_str(" +");
+        write_range(out, j, hunk.iter().filter(|&&edit| edit != Edit::Removed).count());
+        out.write_str(" @@\n");
+        for &edit in hunk {
+            let (mark, line) = match edit {
+                Edit::Same | Edit::Removed => (if edit == Edit::Same { b' ' } else { b'-' }, line_at(old, a[i])),
+                Edit::Added => (b'+', line_at(new, b[j])),
+            };
+            out.write_byte(mark);
+            out.write_bytes(line);
+            out.write_byte(b'\n');
+            if edit != Edit::Added {
+                i += 1;
+            }
+            if edit != Edit::Removed {
+                j += 1;
+            }
+        }
+        done = end;
+    }
+}
+
+/// `start,count` of a hunk counted from line 1; an empty range names the
+/// line before it, as other diffs do
+fn write_range(out: &mut dyn Sink, start: usize, count: usize) {
+    match count {
+        0 => write!(out, "{},0", start),
+        1 => write!(out, "{}", start + 1),
+        _ => write!(out, "{},{}", start + 1, count),
+    }
+}
+
+/// `$` in a `sed` address: the last line, whatever its number
+const LAST_LINE: usize = usize::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SedAction<'a> {
+    /// `d`
+    Delete,
+    /// `s/old/new/`, every match in a line with `g` or else the first.
+    /// The strings are plain text, not patterns.
+    Substitute { old: &'a [u8], new: &'a [u8], global: bool },
+}
+
+/// One `sed` command: an action on the lines `first` to `last`, counted
+/// from 1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SedScript<'a> {
+    first: usize,
+    last: usize,
+    action: SedAction<'a>,
+}
+
+impl<'a> SedScript<'a> {
+    /// Parse `[address]d` or `[address]s/old/new/[g]`, where the address is
+    /// `n`, `n,m` or either with `$` for the last line. Any byte but a
+    /// letter, digit or space can stand in for `/`, e.g. `s|/bin|/usr/bin|`.
+    pub fn parse(script: &'a [u8]) -> Result<SedScript<'a>, &'static str> {
+        let (first, rest) = match sed_line_number(script)? {
+            Some((first, rest)) => (first, rest),
+            None => (1, script),
+        };
+        let (last, rest) = match rest.strip_prefix(b",") {
+            Some(rest) => sed_line_number(rest)?.ok_or("Range needs a last line")?,
+            None if rest.len() == script.len() => (LAST_LINE, rest),
+            None => (first, rest),
+        };
+        let rest = &rest[rest.iter().position(|&b| b != b' ').unwrap_or(rest.len())..];
+
+        let action = match rest.split_first() {
+            Some((b'd', b"")) => SedAction::Delete,
+            Some((b's', body)) => {
+                let (&delimiter, body) = body.split_first().ok_or("Substitution is s/old/new/")?;
+                if delimiter.is_ascii_alphanumeric() || delimiter == b' ' {
+                    return Err("Substitution is s/old/new/");
+                }
+                let mut parts = body.splitn(3, |&b| b == delimiter);
+                let (old, new, flags) = match (parts.next(), parts.next(), parts.next()) {
+                    (Some(old), Some(new), Some(flags)) => (old, new, flags),
+                    _ => return Err("Substitution is s/old/new/"),
+                };
+                if old.is_empty() {
+                    return Err("Nothing to replace");
+                }
+                let global = match flags {
+                    b"" => false,
+                    b"g" => true,
+                    _ => return Err("The only flag is g"),
+                };
+                SedAction::Substitute { old, new, global }
+            }
+            _ => return Err("Commands are d and s/old/new/"),
+        };
+        Ok(SedScript { first, last, action })
+    }
+}
+
+/// A line number or `$` at the start of `text`, and what follows it
+fn sed_line_number(text: &[u8]) -> Result<Option<(usize, &[u8])>, &'static str> {
+    if let Some(rest) = text.strip_prefix(b"$") {
+        return Ok(Some((LAST_LINE, rest)));
+    }
+    let digits = text.iter().take_while(|b| b.is_ascii_digit()).count();
+    if digits == 0 {
+        return Ok(None);
+    }
+    let number = text[..digits]
+        .iter()
+        .try_fold(0usize, |n, &digit| n.checked_mul(10)?.checked_add((digit - b'0') as usize))
+        .ok_or("Line number too big")?;
+    if number == 0 {
+        return Err("Lines are counted from 1");
+    }
+    Ok(Some((number, &text[digits..])))
+}
+
+/// Write `text` with `script` applied to the lines it addresses. A range
+/// that ends before it starts covers its first line only, as in sed.
+pub fn sed(text: &[u8], script: &SedScript, out: &mut dyn Sink) {
+    let total = lines(text).count();
+    let resolve = |number: usize| if number == LAST_LINE { total } else { number };
+    let first = resolve(script.first);
+    let last = resolve(script.last).max(first);
+    // So a file without a newline at the end keeps it that way
+    let final_newline = text.last() == Some(&b'\n');
+
+    for (i, line) in lines(text).enumerate() {
+        let number = i + 1;
+        let line_end = if number < total || final_newline { &b"\n"[..] } else { b"" };
+        if number < first || number > last {
+            out.write_bytes(line);
+            out.write_bytes(line_end);
+            continue;
+        }
+        match script.action {
+            SedAction::Delete => {}
+            SedAction::Substitute { old, new, global } => {
+                let mut rest = line;
+                while let Some(at) = rest.windows(old.len()).position(|window| window == old) {
+                    out.write_bytes(&rest[..at]);
+                    out.write_bytes(new);
+                    rest = &rest[at + old.len()..];
+                    if !global {
+                        break;
+                    }
+                }
+                out.write_bytes(rest);
+                out.write_bytes(line_end);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::Capture;
+
+    #[test_case]
+    fn lines_count_cut_sort_and_merge() {
+        let text = b"b 2\na one\n10\nb 2\n";
+        assert_eq!(count(text), Counts { lines: 4, words: 7, bytes: 17 });
+        assert_eq!(count(b"no newline").lines, 0);
+        assert_eq!(head(text, 2), b"b 2\na one\n");
+        assert_eq!(head(text, 9), text);
+        assert_eq!(tail(text, 2), b"10\nb 2\n");
+        assert_eq!(tail(b"x\ny", 1), b"y");
+        assert_eq!(tail(text, 0), b"");
+
+        let mut buffer = [0u8; 64];
+        let mut out = Capture::new(&mut buffer);
+        sort(text, SortOptions::default(), &mut out).unwrap();
+        assert_eq!(out.as_bytes(), b"10\na one\nb 2\nb 2\n");
+        let mut out = Capture::new(&mut buffer);
+        sort(b"10\n-3\n9\n", SortOptions { numeric: true, reverse: true, unique: false }, &mut out).unwrap();
+        assert_eq!(out.as_bytes(), b"10\n9\n-3\n");
+        let mut out = Capture::new(&mut buffer);
+        sort(text, SortOptions { unique: true, ..SortOptions::default() }, &mut out).unwrap();
+        assert_eq!(out.as_bytes(), b"10\na one\nb 2\n");
+
+        let mut out = Capture::new(&mut buffer);
+        uniq(b"a\na\nb\na", true, false, &mut out);
+        assert_eq!(out.as_bytes(), b"      2 a\n      1 b\n      1 a\n");
+        let mut out = Capture::new(&mut buffer);
+        uniq(b"a\na\nb\n", false, true, &mut out);
+        assert_eq!(out.as_bytes(), b"a\n");
+    }
+
+    #[test_case]
+    fn diff_shows_changes_with_context() {
+        let old = b"1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n14\n";
+        let new = b"1\n2\n3\n4\n5\nsix\n7\n8\n9\n10\n11\n12\n13\n14\n15\n";
+        let mut buffer = [0u8; 128];
+        let mut out = Capture::new(&mut buffer);
+        assert_eq!(diff(b"a", old, b"b", new, &mut out), Ok(true));
+        assert_eq!(
+            out.as_bytes(),
+            &b"--- a\n+++ b\n@@ -3,7 +3,7 @@\n 3\n 4\n 5\n-6\n+six\n 7\n 8\n 9\n@@ -12,3 +12,4 @@\n 12\n 13\n 14\n+15\n"[..]
+        );
+        let mut out = Capture::new(&mut buffer);
+        assert_eq!(diff(b"a", b"x\ny\n", b"b", b"x\ny\n", &mut out), Ok(false));
+        assert_eq!(out.as_bytes(), b"");
+        assert_eq!(diff(b"a", b"b\n", b"b", b"a\nb\nc\n", &mut out), Ok(true));
+        assert_eq!(out.as_bytes(), b"--- a\n+++ b\n@@ -1 +1,3 @@\n+a\n b\n+c\n");
+    }
+
+    #[test_case]
+    fn sed_substitutes_and_deletes_lines() {
+        let text = b"port=80\nhost=a\nport=8080";
+        let mut buffer = [0u8; 64];
+        let mut out = Capture::new(&mut buffer);
+        sed(text, &SedScript::parse(b"s/80/90/").unwrap(), &mut out);
+        assert_eq!(out.as_bytes(), b"port=90\nhost=a\nport=9080");
+        let mut out = Capture::new(&mut buffer);
+        sed(text, &SedScript::parse(b"3s|80|9|g").unwrap(), &mut out);
+        assert_eq!(out.as_bytes(), b"port=80\nhost=a\nport=99");
+        let mut out = Capture::new(&mut buffer);
+        sed(b"a\nb\nc\nd\n", &SedScript::parse(b"2,$ d").unwrap(), &mut out);
+        assert_eq!(out.as_bytes(), b"a\n");
+        let mut out = Capture::new(&mut buffer);
+        sed(b"a\nb\nc\n", &SedScript::parse(b"$d").unwrap(), &mut out);
+        assert_eq!(out.as_bytes(), b"a\nb\n");
+
+        assert!(SedScript::parse(b"0d").is_err());
+        assert!(SedScript::parse(b"s/a/b").is_err());
+        assert!(SedScript::parse(b"s//b/").is_err());
+        assert!(SedScript::parse(b"2,p").is_err());
+    }
+}
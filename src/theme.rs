@@ -0,0 +1,136 @@
+use crate::vga_colors::Color;
+
+/// Colors by role, so screens can be restyled as a whole
+pub struct Palette {
+    pub background: Color,
+    pub text: Color,
+    pub prompt: Color,
+    pub heading: Color,
+    /// Field names, e.g. "CPU:" in hexfetch
+    pub label: Color,
+    pub value: Color,
+    pub error: Color,
+    pub logo: Color,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    Default,
+    Dark,
+    Light,
+    Solarized,
+    Matrix,
+}
+
+pub const THEMES: [Theme; 5] = [Theme::Default, Theme::Dark, Theme::Light, Theme::Solarized, Theme::Matrix];
+
+const DEFAULT: Palette = Palette {
+    background: Color::Black,
+    text: Color::White,
+    prompt: Color::LightGreen,
+    heading: Color::LightCyan,
+    label: Color::Yellow,
+    value: Color::White,
+    error: Color::Red,
+    logo: Color::LightCyan,
+};
+
+const DARK: Palette = Palette {
+    background: Color::Black,
+    text: Color::LightGray,
+    prompt: Color::Cyan,
+    heading: Color::LightBlue,
+    label: Color::Brown,
+    value: Color::LightGray,
+    error: Color::LightRed,
+    logo: Color::Blue,
+};
+
+const LIGHT: Palette = Palette {
+    background: Color::LightGray,
+    text: Color::Black,
+    prompt: Color::Blue,
+    heading: Color::Blue,
+    label: Color::Magenta,
+    value: Color::Black,
+    error: Color::Red,
+    logo: Color::Blue,
+};
+
+const SOLARIZED: Palette = Palette {
+    background: Color::Black,
+    text: Color::LightGray,
+    prompt: Color::Cyan,
+    heading: Color::Brown,
+    label: Color::Green,
+    value: Color::LightCyan,
+    error: Color::Red,
+    logo: Color::Magenta,
+};
+
+const MATRIX: Palette = Palette {
+    background: Color::Black,
+    text: Color::Green,
+    prompt: Color::LightGreen,
+    heading: Color::LightGreen,
+    label: Color::Green,
+    value: Color::LightGreen,
+    error: Color::LightRed,
+    logo: Color::Green,
+};
+
+impl Theme {
+    pub fn from_name(name: &[u8]) -> Option<Theme> {
+        THEMES.iter().copied().find(|theme| theme.name().as_bytes() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Theme::Default => "default",
+            Theme::Dark => "dark",
+            Theme::Light => "light",
+            Theme::Solarized => "solarized",
+            Theme::Matrix => "matrix",
+        }
+    }
+
+    pub fn palette(self) -> &'static Palette {
+        match self {
+            Theme::Default => &DEFAULT,
+            Theme::Dark => &DARK,
+            Theme::Light => &LIGHT,
+            Theme::Solarized => &SOLARIZED,
+            Theme::Matrix => &MATRIX,
+        }
+    }
+}
+
+/// Starts as the boot `theme=` option
+static mut CURRENT: Option<Theme> = None;
+
+pub fn current() -> Theme {
+    unsafe { CURRENT.unwrap_or(crate::config::get().theme) }
+}
+
+pub fn set(theme: Theme) {
+    unsafe {
+        CURRENT = Some(theme);
+    }
+}
+
+pub fn palette() -> &'static Palette {
+    current().palette()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn names_round_trip() {
+        for &theme in THEMES.iter() {
+            assert_eq!(Theme::from_name(theme.name().as_bytes()), Some(theme));
+        }
+        assert_eq!(Theme::from_name(b"neon"), None);
+    }
+}
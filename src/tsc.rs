@@ -0,0 +1,115 @@
+use core::arch::asm;
+use core::time::Duration;
+
+use crate::clock;
+use crate::cpu::cpuid;
+use crate::idt;
+
+// The time stamp counter, calibrated against the PIT at boot, for measuring
+// short intervals far more finely than the timer tick. An invariant TSC runs
+// at the same rate in every power state; an older one may slow down with
+// the CPU, which `is_invariant` tells. Without a TSC, `Instant` falls back
+// to the clock.
+
+/// Timer ticks to count TSC cycles over
+const CALIBRATION_TICKS: u32 = 5;
+const NS_PER_SEC: u64 = 1_000_000_000;
+
+/// Cycles per second; 0 until calibrated or without a TSC
+static mut FREQUENCY_HZ: u64 = 0;
+static mut INVARIANT: bool = false;
+
+pub fn is_present() -> bool {
+    cpuid(1).3 & (1 << 4) != 0
+}
+
+pub fn read() -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+    }
+    (high as u64) << 32 | low as u64
+}
+
+/// Measure the TSC rate over a few PIT ticks. Runs with interrupts on and
+/// before anything replaces the PIT as the tick source.
+pub fn init() -> Result<(), &'static str> {
+    if !is_present() {
+        return Err("no TSC");
+    }
+    let invariant = cpuid(0x8000_0000).0 >= 0x8000_0007 && cpuid(0x8000_0007).3 & (1 << 8) != 0;
+
+    // Start on a tick boundary
+    let start = idt::get_ticks();
+    while idt::get_ticks() == start {
+        idt::wait_for_interrupt();
+    }
+    let start = idt::get_ticks();
+    let start_tsc = read();
+    while idt::get_ticks().wrapping_sub(start) < CALIBRATION_TICKS {
+        idt::wait_for_interrupt();
+    }
+    let cycles = read() - start_tsc;
+    let elapsed_ns = CALIBRATION_TICKS as u64 * clock::tick_period_ns() as u64;
+
+    unsafe {
+        FREQUENCY_HZ = cycles * (NS_PER_SEC / 1000) / (elapsed_ns / 1000);
+        INVARIANT = invariant;
+    }
+    Ok(())
+}
+
+pub fn frequency_hz() -> u64 {
+    unsafe { FREQUENCY_HZ }
+}
+
+pub fn is_invariant() -> bool {
+    unsafe { INVARIANT }
+}
+
+/// `cycles` at `hz` in nanoseconds, without overflowing for long spans
+fn cycles_to_ns(cycles: u64, hz: u64) -> u64 {
+    cycles / hz * NS_PER_SEC + cycles % hz * NS_PER_SEC / hz
+}
+
+/// A point in time, for measuring how long something took
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant {
+    /// TSC cycles when calibrated, otherwise clock nanoseconds
+    value: u64,
+}
+
+impl Instant {
+    pub fn now() -> Instant {
+        let value = if frequency_hz() != 0 { read() } else { clock::now_ns() };
+        Instant { value }
+    }
+
+    /// Time from `earlier` to this instant, zero if `earlier` is later
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        let delta = self.value.saturating_sub(earlier.value);
+        let hz = frequency_hz();
+        Duration::from_nanos(if hz != 0 { cycles_to_ns(delta, hz) } else { delta })
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn conversions_and_instants() {
+        assert_eq!(cycles_to_ns(3_000_000_000, 3_000_000_000), NS_PER_SEC);
+        assert_eq!(cycles_to_ns(1_500, 3_000_000_000), 500);
+        // An hour at 4 GHz would overflow a plain multiply
+        assert_eq!(cycles_to_ns(14_400_000_000_000, 4_000_000_000), 3600 * NS_PER_SEC);
+
+        let start = Instant::now();
+        assert!(Instant::now() >= start);
+        assert_eq!(start.duration_since(Instant::now()), Duration::ZERO);
+    }
+}
@@ -0,0 +1,265 @@
+use core::arch::naked_asm;
+
+use crate::caps::Caps;
+use crate::crash;
+use crate::gdt;
+use crate::idt;
+use crate::metrics::{self, Counter};
+use crate::multiboot;
+use crate::syscall;
+use crate::watchdog;
+use crate::writer::Writer;
+
+/// Programs run inside this window. There is no paging, but the user
+/// segments start at `USER_BASE` and end at `USER_LIMIT`, so a program
+/// sees the window at addresses 0 to `USER_SIZE` and faults outside it.
+pub const USER_BASE: u32 = 0x0200_0000;
+pub const USER_LIMIT: u32 = 0x0300_0000;
+pub const USER_SIZE: u32 = USER_LIMIT - USER_BASE;
+
+const STACK_SIZE: u32 = 64 * 1024;
+/// The user stack takes the top of the window; program addresses from
+/// here on
+pub const STACK_TOP: u32 = USER_SIZE;
+pub const STACK_BOTTOM: u32 = USER_SIZE - STACK_SIZE;
+
+const NO_FAULT: u32 = 0xFFFF_FFFF;
+
+extern "C" {
+    static __kernel_end: u8;
+}
+
+/// Kernel stack pointer saved by `enter_user`, restored on exit
+static mut KERNEL_ESP: u32 = 0;
+static mut FAULT_VECTOR: u32 = NO_FAULT;
+static mut FAULT_EIP: u32 = 0;
+
+/// How a user program ended
+#[derive(Clone, Copy)]
+pub enum Exit {
+    Code(i32),
+    /// Killed by a CPU exception raised in ring 3
+    Fault { vector: u32, eip: u32 },
+}
+
+/// Exception frame as laid out by `fault_common`
+#[repr(C)]
+pub struct FaultFrame {
+    ebp: u32,
+    vector: u32,
+    error_code: u32,
+    eip: u32,
+    cs: u32,
+    eflags: u32,
+}
+
+/// Install the exception handlers that turn ring 3 faults into program
+/// exits, and the system call gate
+pub fn init() {
+    idt::set_gate(0x00, divide_error_handler as u32, false);
+    idt::set_gate(0x06, invalid_opcode_handler as u32, false);
+    idt::set_gate(0x0C, stack_fault_handler as u32, false);
+    idt::set_gate(0x0D, general_protection_handler as u32, false);
+    syscall::init();
+}
+
+pub fn exception_name(vector: u32) -> &'static str {
+    match vector {
+        0x00 => "divide error",
+        0x06 => "invalid opcode",
+        0x0C => "stack fault",
+        0x0D => "general protection fault",
+        _ => "exception",
+    }
+}
+
+/// Whether program addresses `start..start + len` lie anywhere in the
+/// window, stack included
+pub fn is_user_range(start: u32, len: u32) -> bool {
+    start <= USER_SIZE && len <= USER_SIZE - start
+}
+
+/// Whether program addresses `start..start + len` lie in the program area
+/// below the stack
+pub fn contains(start: u32, len: u32) -> bool {
+    start <= STACK_BOTTOM && len <= STACK_BOTTOM - start
+}
+
+/// Where the kernel finds a program address
+pub fn physical(address: u32) -> *mut u8 {
+    (USER_BASE + address) as *mut u8
+}
+
+/// Make sure the window is RAM that the kernel image and boot modules
+/// don't already occupy
+pub fn check_window() -> Result<(), &'static str> {
+    let in_ram = multiboot::memory_map().any(|region| {
+        region.kind == multiboot::MEMORY_AVAILABLE
+            && region.base <= USER_BASE as u64
+            && region.base + region.length >= USER_LIMIT as u64
+    });
+    if !in_ram {
+        return Err("Program memory is not available RAM");
+    }
+    if unsafe { &__kernel_end as *const u8 as u32 } > USER_BASE {
+        return Err("Kernel image overlaps program memory");
+    }
+    if multiboot::modules().any(|module| module.start < USER_LIMIT && module.end > USER_BASE) {
+        return Err("A boot module overlaps program memory");
+    }
+    Ok(())
+}
+
+/// Jump to program address `entry` in ring 3 with an empty stack and return once the
+/// program exits or faults. The program's console I/O goes to `writer`, and
+/// it may only make the system calls `caps` allows.
+pub fn run(entry: u32, caps: Caps, writer: &mut Writer) -> Exit {
+    syscall::begin_process(writer, caps);
+    unsafe {
+        FAULT_VECTOR = NO_FAULT;
+        watchdog::set_paused(true);
+        metrics::add(Counter::ContextSwitches, 1);
+        let code = enter_user(entry, STACK_TOP);
+        metrics::add(Counter::ContextSwitches, 1);
+        watchdog::set_paused(false);
+        syscall::end_process();
+        if FAULT_VECTOR != NO_FAULT {
+            metrics::count_interrupt(FAULT_VECTOR as u8);
+            Exit::Fault { vector: FAULT_VECTOR, eip: FAULT_EIP }
+        } else {
+            Exit::Code(code)
+        }
+    }
+}
+
+#[unsafe(naked)]
+unsafe extern "C" fn enter_user(entry: u32, stack: u32) -> i32 {
+    naked_asm!(
+        "push ebp",
+        "push ebx",
+        "push esi",
+        "push edi",
+        "mov [{kernel_esp}], esp",
+        "mov eax, [esp + 20]",
+        "mov ecx, [esp + 24]",
+        "mov dx, {user_data}",
+        "mov ds, dx",
+        "mov es, dx",
+        "mov fs, dx",
+        "mov gs, dx",
+        "push {user_data}",
+        "push ecx",
+        "pushfd",
+        "or dword ptr [esp], 0x200",
+        "push {user_code}",
+        "push eax",
+        "iretd",
+        kernel_esp = sym KERNEL_ESP,
+        user_data = const gdt::USER_DATA as u32,
+        user_code = const gdt::USER_CODE as u32,
+    );
+}
+
+/// Unwind to the `enter_user` caller with EAX as its return value
+#[unsafe(naked)]
+#[no_mangle]
+pub unsafe extern "C" fn leave_user() {
+    naked_asm!(
+        "mov dx, {kernel_data}",
+        "mov ds, dx",
+        "mov es, dx",
+        "mov fs, dx",
+        "mov gs, dx",
+        "mov esp, [{kernel_esp}]",
+        "pop edi",
+        "pop esi",
+        "pop ebx",
+        "pop ebp",
+        "sti",
+        "ret",
+        kernel_data = const gdt::KERNEL_DATA as u32,
+        kernel_esp = sym KERNEL_ESP,
+    );
+}
+
+/// End the running program with `code`, discarding the kernel stack built
+/// up since it entered ring 3
+#[unsafe(naked)]
+pub unsafe extern "C" fn exit_to_kernel(code: i32) -> ! {
+    naked_asm!(
+        "mov eax, [esp + 4]",
+        "jmp leave_user",
+    );
+}
+
+#[unsafe(naked)]
+#[no_mangle]
+pub unsafe extern "C" fn divide_error_handler() {
+    naked_asm!(
+        "push 0",
+        "push 0x00",
+        "jmp fault_common",
+    );
+}
+
+#[unsafe(naked)]
+#[no_mangle]
+pub unsafe extern "C" fn invalid_opcode_handler() {
+    naked_asm!(
+        "push 0",
+        "push 0x06",
+        "jmp fault_common",
+    );
+}
+
+#[unsafe(naked)]
+#[no_mangle]
+pub unsafe extern "C" fn stack_fault_handler() {
+    // The CPU already pushed an error code
+    naked_asm!(
+        "push 0x0C",
+        "jmp fault_common",
+    );
+}
+
+#[unsafe(naked)]
+#[no_mangle]
+pub unsafe extern "C" fn general_protection_handler() {
+    // The CPU already pushed an error code
+    naked_asm!(
+        "push 0x0D",
+        "jmp fault_common",
+    );
+}
+
+#[unsafe(naked)]
+#[no_mangle]
+pub unsafe extern "C" fn fault_common() {
+    naked_asm!(
+        // Stack: vector, error code, eip, cs, eflags
+        "test dword ptr [esp + 12], 3",
+        "jz 2f",
+        // DS still holds the program's segment
+        "mov ax, {kernel_data}",
+        "mov ds, ax",
+        "mov es, ax",
+        "mov eax, [esp]",
+        "mov [{vector}], eax",
+        "mov eax, [esp + 8]",
+        "mov [{eip}], eax",
+        "xor eax, eax",
+        "jmp leave_user",
+        "2:",
+        "push ebp",
+        "push esp",
+        "call kernel_fault",
+        kernel_data = const gdt::KERNEL_DATA as u32,
+        vector = sym FAULT_VECTOR,
+        eip = sym FAULT_EIP,
+    );
+}
+
+#[no_mangle]
+pub extern "C" fn kernel_fault(frame: &FaultFrame) -> ! {
+    crash::exception(exception_name(frame.vector), frame.error_code, frame.eip, frame.ebp)
+}
@@ -0,0 +1,363 @@
+use crate::env;
+use crate::filesystem::{get_filesystem, MAX_FILE_SIZE};
+use crate::idt;
+use crate::keyboard::Keyboard;
+use crate::rng;
+use crate::sha256::{self, Sha256, DIGEST_LEN};
+use crate::writer::Writer;
+
+// User accounts, one per line of `/etc/passwd`:
+//
+//   name:salt:hash:home:prompt
+//
+// `hash` is the SHA-256 of the salt followed by the password, in hex; an
+// account with neither salt nor hash has no password. `prompt` is a
+// `PROMPT` template and, as templates have colons of their own, takes the
+// rest of the line; empty means the default. Lines starting with `#` are
+// comments. The shell asks for a name and password before its first prompt
+// and `login` switches to another account.
+
+pub const PASSWD_FILE: &[u8] = b"/etc/passwd";
+const PASSWD_DIR: &[u8] = b"/etc";
+/// Written when the initrd brings no accounts along
+const DEFAULT_PASSWD: &[u8] = b"# name:salt:hash:home:prompt, see `useradd` and `passwd`\nroot:::/root:\n";
+pub const MAX_NAME_LEN: usize = 16;
+pub const MAX_PASSWORD_LEN: usize = 64;
+/// Hex digits of salt
+const SALT_LEN: usize = 16;
+
+static mut CURRENT: [u8; MAX_NAME_LEN] = [0; MAX_NAME_LEN];
+static mut CURRENT_LEN: usize = 0;
+static mut FILE_BUFFER: [u8; MAX_FILE_SIZE] = [0; MAX_FILE_SIZE];
+
+pub struct Account<'a> {
+    pub name: &'a [u8],
+    salt: &'a [u8],
+    hash: &'a [u8],
+    pub home: &'a [u8],
+    pub prompt: &'a [u8],
+}
+
+impl<'a> Account<'a> {
+    /// One line of the file; `None` for comments and lines that don't parse
+    fn parse(line: &'a [u8]) -> Option<Account<'a>> {
+        if line.is_empty() || line[0] == b'#' {
+            return None;
+        }
+        let mut fields = line.splitn(5, |&b| b == b':');
+        let account = Account {
+            name: fields.next()?,
+            salt: fields.next()?,
+            hash: fields.next()?,
+            home: fields.next()?,
+            prompt: fields.next().unwrap_or(b""),
+        };
+        let hashed = account.hash.len() == DIGEST_LEN * 2 || account.salt.is_empty() && account.hash.is_empty();
+        if !valid_name(account.name) || !account.home.starts_with(b"/") || !hashed {
+            return None;
+        }
+        Some(account)
+    }
+
+    pub fn has_password(&self) -> bool {
+        !self.hash.is_empty()
+    }
+
+    pub fn check_password(&self, password: &[u8]) -> bool {
+        if !self.has_password() {
+            return true;
+        }
+        sha256::parse_hex(self.hash) == Some(hash(self.salt, password))
+    }
+}
+
+fn hash(salt: &[u8], password: &[u8]) -> [u8; DIGEST_LEN] {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(password);
+    hasher.finish()
+}
+
+/// Letters, digits, `_` and `-`, which keeps names clear of the separators
+pub fn valid_name(name: &[u8]) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name.iter().all(|&b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
+fn accounts() -> impl Iterator<Item = Account<'static>> {
+    get_filesystem().read_path(PASSWD_FILE).unwrap_or(b"").split(|&b| b == b'\n').filter_map(Account::parse)
+}
+
+pub fn find(name: &[u8]) -> Option<Account<'static>> {
+    accounts().find(|account| account.name == name)
+}
+
+/// The user logged in
+pub fn current() -> &'static [u8] {
+    unsafe { &CURRENT[..CURRENT_LEN] }
+}
+
+/// Make `/etc/passwd` with a root account, no password, if there isn't one
+pub fn init() {
+    if get_filesystem().read_path(PASSWD_FILE).is_none() {
+        if let Err(e) = write_passwd(DEFAULT_PASSWD) {
+            log_warn!("Users: can't write {}: {}", core::str::from_utf8(PASSWD_FILE).unwrap_or("?"), e);
+        }
+    }
+}
+
+/// Add an account with its home under `/home` and make the home directory
+pub fn add(name: &[u8], password: &[u8]) -> Result<(), &'static str> {
+    if !valid_name(name) {
+        return Err("Names are letters, digits, _ and -, up to 16 of them");
+    }
+    if find(name).is_some() {
+        return Err("User already exists");
+    }
+    let text = get_filesystem().read_path(PASSWD_FILE).unwrap_or(b"");
+    let buffer = unsafe { &mut FILE_BUFFER };
+    let mut len = 0;
+    append(buffer, &mut len, text)?;
+    if len > 0 && buffer[len - 1] != b'\n' {
+        append(buffer, &mut len, b"\n")?;
+    }
+
+    let mut home = [0u8; 6 + MAX_NAME_LEN];
+    home[..6].copy_from_slice(b"/home/");
+    home[6..6 + name.len()].copy_from_slice(name);
+    let home = &home[..6 + name.len()];
+    write_entry(buffer, &mut len, name, password, home, b"")?;
+    write_passwd(&buffer[..len])?;
+
+    let fs = get_filesystem();
+    let saved = fs.current_dir();
+    let made = enter_dirs(home);
+    fs.set_current_dir(saved);
+    made
+}
+
+/// Give `name` a new password, or none if it's empty
+pub fn set_password(name: &[u8], password: &[u8]) -> Result<(), &'static str> {
+    let text = get_filesystem().read_path(PASSWD_FILE).ok_or("No /etc/passwd")?;
+    let buffer = unsafe { &mut FILE_BUFFER };
+    let mut len = 0;
+    let mut found = false;
+    for line in text.split(|&b| b == b'\n').filter(|line| !line.is_empty()) {
+        match Account::parse(line) {
+            Some(account) if account.name == name => {
+                write_entry(buffer, &mut len, name, password, account.home, account.prompt)?;
+                found = true;
+            }
+            _ => {
+                append(buffer, &mut len, line)?;
+                append(buffer, &mut len, b"\n")?;
+            }
+        }
+    }
+    if !found {
+        return Err("No such user");
+    }
+    write_passwd(&buffer[..len])
+}
+
+fn append(buffer: &mut [u8], len: &mut usize, piece: &[u8]) -> Result<(), &'static str> {
+    buffer.get_mut(*len..*len + piece.len()).ok_or("Too many users")?.copy_from_slice(piece);
+    *len += piece.len();
+    Ok(())
+}
+
+/// One line of the file, salting and hashing the password
+fn write_entry(buffer: &mut [u8], len: &mut usize, name: &[u8], password: &[u8], home: &[u8], prompt: &[u8]) -> Result<(), &'static str> {
+    append(buffer, len, name)?;
+    append(buffer, len, b":")?;
+    if !password.is_empty() {
+        let salt = new_salt();
+        let mut hex = [0u8; DIGEST_LEN * 2];
+        sha256::write_hex(&hash(&salt, password), &mut hex);
+        append(buffer, len, &salt)?;
+        append(buffer, len, b":")?;
+        append(buffer, len, &hex)?;
+    } else {
+        append(buffer, len, b":")?;
+    }
+    append(buffer, len, b":")?;
+    append(buffer, len, home)?;
+    append(buffer, len, b":")?;
+    append(buffer, len, prompt)?;
+    append(buffer, len, b"\n")
+}
+
+fn new_salt() -> [u8; SALT_LEN] {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut bytes = [0u8; SALT_LEN / 2];
+    rng::fill_bytes(&mut bytes);
+    let mut salt = [0u8; SALT_LEN];
+    for (i, &byte) in bytes.iter().enumerate() {
+        salt[i * 2] = DIGITS[(byte >> 4) as usize];
+        salt[i * 2 + 1] = DIGITS[(byte & 0xF) as usize];
+    }
+    salt
+}
+
+fn write_passwd(text: &[u8]) -> Result<(), &'static str> {
+    let fs = get_filesystem();
+    let saved = fs.current_dir();
+    let made = enter_dirs(PASSWD_DIR);
+    fs.set_current_dir(saved);
+    made?;
+    fs.write_path(PASSWD_FILE, text)
+}
+
+/// Go to `path` from the root, making the directories on the way that
+/// don't exist yet. It stays the current directory.
+fn enter_dirs(path: &[u8]) -> Result<(), &'static str> {
+    let fs = get_filesystem();
+    fs.set_current_dir(0);
+    for name in path.split(|&b| b == b'/').filter(|name| !name.is_empty()) {
+        if fs.change_directory(name).is_err() {
+            fs.create_directory(name)?;
+            fs.change_directory(name)?;
+        }
+    }
+    Ok(())
+}
+
+/// Become `account`: set `USER`, `HOME` and `PROMPT` and go home, making
+/// the directory if it's gone
+pub fn start_session(account: &Account) {
+    unsafe {
+        CURRENT[..account.name.len()].copy_from_slice(account.name);
+        CURRENT_LEN = account.name.len();
+    }
+    let env = env::get();
+    let _ = env.set(b"USER", account.name);
+    let _ = env.set(b"HOME", account.home);
+    let _ = env.set(b"PROMPT", if account.prompt.is_empty() { env::DEFAULT_PROMPT } else { account.prompt });
+    if let Err(e) = enter_dirs(account.home) {
+        log_warn!("Users: can't make {}: {}", core::str::from_utf8(account.home).unwrap_or("?"), e);
+        get_filesystem().set_current_dir(0);
+    }
+}
+
+/// Ask for a name and password until they are right, then start a session.
+/// A name without an account still gets asked for a password, so the
+/// prompt doesn't tell which names exist.
+pub fn login(writer: &mut Writer) {
+    loop {
+        let mut name = [0u8; MAX_NAME_LEN];
+        writer.write_str("login: ");
+        let name_len = read_line(writer, false, &mut name);
+        writer.write_byte(b'\n');
+        let name = match name_len {
+            Some(len) if len > 0 => &name[..len],
+            _ => continue,
+        };
+
+        let account = find(name);
+        let allowed = match account {
+            Some(ref account) if !account.has_password() => true,
+            _ => {
+                let mut password = [0u8; MAX_PASSWORD_LEN];
+                writer.write_str("Password: ");
+                let len = read_line(writer, true, &mut password);
+                writer.write_byte(b'\n');
+                match (&account, len) {
+                    (Some(account), Some(len)) => account.check_password(&password[..len]),
+                    _ => false,
+                }
+            }
+        };
+        if let (Some(account), true) = (account, allowed) {
+            start_session(&account);
+            return;
+        }
+        writer.write_str("Login incorrect\n\n");
+    }
+}
+
+/// Ask for a password twice, for `useradd` and `passwd`
+pub fn ask_new_password(writer: &mut Writer, password: &mut [u8; MAX_PASSWORD_LEN]) -> Result<usize, &'static str> {
+    let mut again = [0u8; MAX_PASSWORD_LEN];
+    writer.write_str("New password: ");
+    let len = read_line(writer, true, password);
+    writer.write_byte(b'\n');
+    let len = len.ok_or("Cancelled")?;
+    writer.write_str("Retype password: ");
+    let again_len = read_line(writer, true, &mut again);
+    writer.write_byte(b'\n');
+    if again_len.ok_or("Cancelled")? != len || again[..len] != password[..len] {
+        return Err("Passwords don't match");
+    }
+    Ok(len)
+}
+
+/// Read a line typed at the cursor, shown as it is or, `hidden`, as stars.
+/// `None` if Esc gives up on it.
+pub fn read_line(writer: &mut Writer, hidden: bool, buf: &mut [u8]) -> Option<usize> {
+    let mut len = 0;
+    let mut shift = false;
+    idt::flush_buffer();
+    loop {
+        let scancode = match idt::get_scancode() {
+            Some(scancode) => scancode,
+            None => {
+                idt::wait_for_interrupt();
+                continue;
+            }
+        };
+        match scancode {
+            0x2A | 0x36 => shift = true,
+            0xAA | 0xB6 => shift = false,
+            // Esc
+            0x01 => return None,
+            // Enter
+            0x1C => return Some(len),
+            // Backspace
+            0x0E if len > 0 => {
+                len -= 1;
+                let (col, row) = (writer.get_col(), writer.get_row());
+                if col > 0 {
+                    writer.set_position(col - 1, row);
+                    writer.write_byte(b' ');
+                    writer.set_position(col - 1, row);
+                }
+            }
+            _ => {
+                for c in Keyboard::scancode_to_bytes(scancode, shift) {
+                    if len < buf.len() {
+                        buf[len] = c;
+                        len += 1;
+                        writer.write_byte(if hidden { b'*' } else { c });
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn accounts_parse_and_check_passwords() {
+        let line = b"alice:0011223344556677:d200d375205f8194627df80c58be19c136c5daa50a79221605ee61f4bd618f79:/home/alice:\\u:\\w$ ";
+        let account = Account::parse(line).unwrap();
+        assert_eq!((account.name, account.home, account.prompt), (&b"alice"[..], &b"/home/alice"[..], &b"\\u:\\w$ "[..]));
+        assert!(account.check_password(b"secret"));
+        assert!(!account.check_password(b"Secret"));
+
+        let root = Account::parse(b"root:::/root:").unwrap();
+        assert!(!root.has_password() && root.check_password(b""));
+        assert!(Account::parse(b"# root:::/root:").is_none());
+        assert!(Account::parse(b"bob:salt:short:/home/bob:").is_none());
+        assert!(Account::parse(b"b b:::/home:").is_none());
+
+        let mut buffer = [0u8; 128];
+        let mut len = 0;
+        write_entry(&mut buffer, &mut len, b"carol", b"pw", b"/home/carol", b"").unwrap();
+        let carol = Account::parse(&buffer[..len - 1]).unwrap();
+        assert!(carol.check_password(b"pw") && !carol.check_password(b""));
+    }
+}
@@ -20,6 +20,20 @@ pub enum Color {
     White = 0xF,
 }
 
+impl Color {
+    pub const ALL: [Color; 16] = [
+        Color::Black, Color::Blue, Color::Green, Color::Cyan,
+        Color::Red, Color::Magenta, Color::Brown, Color::LightGray,
+        Color::DarkGray, Color::LightBlue, Color::LightGreen, Color::LightCyan,
+        Color::LightRed, Color::Pink, Color::Yellow, Color::White,
+    ];
+
+    /// The color with VGA attribute value `index`, 0 to 15
+    pub fn from_index(index: u8) -> Option<Color> {
+        Color::ALL.get(index as usize).copied()
+    }
+}
+
 #[allow(dead_code)]
 pub const fn color_code(foreground: Color, background: Color) -> u8 {
     (background as u8) << 4 | (foreground as u8)
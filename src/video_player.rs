@@ -2,7 +2,6 @@ use crate::writer::Writer;
 use crate::vga_colors::Color;
 use crate::idt;
 
-const VGA_BUFFER: usize = 0xb8000;
 const VGA_WIDTH: usize = 80;
 const VGA_HEIGHT: usize = 25;
 
@@ -51,70 +50,48 @@ impl VideoPlayer {
     }
 
     fn draw_frame(&self, frame_data: &[u8]) {
-        unsafe {
-            let vga = VGA_BUFFER as *mut u8;
-            
-            let offset_x = if self.frame_width < VGA_WIDTH { (VGA_WIDTH - self.frame_width) / 2 } else { 0 };
-            let offset_y = if self.frame_height < VGA_HEIGHT { (VGA_HEIGHT - self.frame_height) / 2 } else { 0 };
-            
-            for y in 0..self.frame_height.min(VGA_HEIGHT) {
-                for x in 0..self.frame_width.min(VGA_WIDTH) {
-                    let src_idx = y * self.frame_width + x;
-                    
-                    if src_idx >= frame_data.len() {
-                        continue;
-                    }
-                    
-                    let ch = frame_data[src_idx];
-                    
-                    let color = match ch {
-                        b' ' | b'.' => 0x08,  // Dark gray
-                        b':' | b'-' => 0x07,  // Light gray
-                        b'=' | b'+' => 0x0F,  // White
-                        b'*' | b'#' => 0x0F,  // White
-                        b'%' | b'@' => 0x0F,  // White
-                        _ => 0x07,
-                    };
-                    
-                    let vga_x = offset_x + x;
-                    let vga_y = offset_y + y;
-                    
-                    let vga_offset = (vga_y * VGA_WIDTH + vga_x) * 2;
-                    
-                    *vga.add(vga_offset) = ch;
-                    *vga.add(vga_offset + 1) = color;
+        let offset_x = if self.frame_width < VGA_WIDTH { (VGA_WIDTH - self.frame_width) / 2 } else { 0 };
+        let offset_y = if self.frame_height < VGA_HEIGHT { (VGA_HEIGHT - self.frame_height) / 2 } else { 0 };
+
+        for y in 0..self.frame_height.min(VGA_HEIGHT) {
+            for x in 0..self.frame_width.min(VGA_WIDTH) {
+                let src_idx = y * self.frame_width + x;
+
+                if src_idx >= frame_data.len() {
+                    continue;
                 }
+
+                let ch = frame_data[src_idx];
+
+                let color: u8 = match ch {
+                    b' ' | b'.' => 0x08,  // Dark gray
+                    b':' | b'-' => 0x07,  // Light gray
+                    b'=' | b'+' => 0x0F,  // White
+                    b'*' | b'#' => 0x0F,  // White
+                    b'%' | b'@' => 0x0F,  // White
+                    _ => 0x07,
+                };
+
+                Writer::set_cell(offset_x + x, offset_y + y, (color as u16) << 8 | ch as u16);
             }
         }
     }
 
     fn clear_screen(&self) {
-        unsafe {
-            let vga = VGA_BUFFER as *mut u8;
-            for i in 0..(VGA_WIDTH * VGA_HEIGHT) {
-                *vga.add(i * 2) = b' ';
-                *vga.add(i * 2 + 1) = 0x00;
+        for y in 0..VGA_HEIGHT {
+            for x in 0..VGA_WIDTH {
+                Writer::set_cell(x, y, b' ' as u16);
             }
         }
     }
 
     fn draw_progress(&self) {
-        unsafe {
-            let vga = VGA_BUFFER as *mut u8;
-            
-            let progress = (self.current_frame * VGA_WIDTH) / self.frame_count;
-            let y = VGA_HEIGHT - 1;
-            
-            for x in 0..VGA_WIDTH {
-                let offset = (y * VGA_WIDTH + x) * 2;
-                if x < progress {
-                    *vga.add(offset) = b'=';
-                    *vga.add(offset + 1) = 0x0A;
-                } else {
-                    *vga.add(offset) = b'-';
-                    *vga.add(offset + 1) = 0x08; 
-                }
-            }
+        let progress = (self.current_frame * VGA_WIDTH) / self.frame_count;
+        let y = VGA_HEIGHT - 1;
+
+        for x in 0..VGA_WIDTH {
+            let cell = if x < progress { 0x0A00 | b'=' as u16 } else { 0x0800 | b'-' as u16 };
+            Writer::set_cell(x, y, cell);
         }
     }
 
@@ -0,0 +1,102 @@
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use crate::clock;
+use crate::config;
+use crate::idt;
+
+// A check for kernel hangs, for catching infinite loops in driver code
+// while developing. The shell feeds it on every pass of its main loop, and
+// so does every wait for an interrupt, since code that halts isn't stuck. A
+// timer callback panics if neither happens for `watchdog=` seconds; it runs
+// on top of whatever was interrupted, so the panic's call stack shows where
+// the kernel was spinning. User programs may spin as long as they like, so
+// it doesn't count the time one runs.
+
+const NS_PER_MS: u64 = 1_000_000;
+
+/// Zero when off
+static TIMEOUT_MS: AtomicU32 = AtomicU32::new(0);
+/// Tick count at the last `feed`
+static LAST_FED: AtomicU32 = AtomicU32::new(0);
+static PAUSED: AtomicBool = AtomicBool::new(false);
+static mut CALLBACK: Option<usize> = None;
+
+/// Start watching if the boot command line asks for it
+pub fn init() -> Result<(), &'static str> {
+    match config::get().watchdog_secs {
+        0 => Ok(()),
+        secs => set_timeout_ms(secs.checked_mul(1000).ok_or("watchdog= too large")?),
+    }
+}
+
+/// Panic if the shell goes `ms` without feeding; zero stops watching
+pub fn set_timeout_ms(ms: u32) -> Result<(), &'static str> {
+    feed();
+    unsafe {
+        match (CALLBACK, ms) {
+            (Some(id), 0) => {
+                idt::remove_timer_callback(id);
+                CALLBACK = None;
+            }
+            (None, ms) if ms > 0 => CALLBACK = Some(idt::add_timer_callback(on_tick)?),
+            _ => {}
+        }
+    }
+    TIMEOUT_MS.store(ms, Ordering::Relaxed);
+    Ok(())
+}
+
+/// The limit in milliseconds, zero when off
+pub fn timeout_ms() -> u32 {
+    TIMEOUT_MS.load(Ordering::Relaxed)
+}
+
+/// Milliseconds since the last feed
+pub fn since_fed_ms() -> u64 {
+    ticks_to_ms(idt::get_ticks().wrapping_sub(LAST_FED.load(Ordering::Relaxed)))
+}
+
+/// Tell the watchdog the kernel is still making progress
+pub fn feed() {
+    LAST_FED.store(idt::get_ticks(), Ordering::Relaxed);
+}
+
+/// Stop counting while a user program runs, or start again from now
+pub fn set_paused(paused: bool) {
+    feed();
+    PAUSED.store(paused, Ordering::Relaxed);
+}
+
+fn ticks_to_ms(ticks: u32) -> u64 {
+    ticks as u64 * clock::tick_period_ns() as u64 / NS_PER_MS
+}
+
+/// Whether `starved_ms` went past a limit of `timeout_ms`, zero meaning none
+fn expired(starved_ms: u64, timeout_ms: u32) -> bool {
+    timeout_ms > 0 && starved_ms > timeout_ms as u64
+}
+
+/// Timer callback, in interrupt context
+fn on_tick(ticks: u32) {
+    if PAUSED.load(Ordering::Relaxed) {
+        return;
+    }
+    let starved_ms = ticks_to_ms(ticks.wrapping_sub(LAST_FED.load(Ordering::Relaxed)));
+    let timeout_ms = TIMEOUT_MS.load(Ordering::Relaxed);
+    if expired(starved_ms, timeout_ms) {
+        panic!("Watchdog: kernel hung for {} ms (limit {} ms)", starved_ms, timeout_ms);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn expires_only_past_a_set_limit() {
+        assert!(!expired(5000, 0));
+        assert!(!expired(999, 1000));
+        assert!(!expired(1000, 1000));
+        assert!(expired(1001, 1000));
+    }
+}
@@ -1,7 +1,13 @@
+use crate::ansi::{Action, Parser};
 use crate::vga_colors::{Color, color_code};
+use crate::framebuffer;
+use crate::config;
+use crate::metrics::{self, Counter};
+use crate::recorder::{self, Event};
+use crate::serial::{SerialPort, COM1};
 use core::arch::asm;
+use core::fmt;
 
-const VGA_BUFFER: usize = 0xb8000;
 const VGA_WIDTH: usize = 80;
 const VGA_HEIGHT: usize = 25;
 
@@ -21,6 +27,10 @@ impl Writer {
 
     fn update_cursor(&self) {
         let pos = self.row * VGA_WIDTH + self.col;
+        if framebuffer::console_enabled() {
+            framebuffer::move_cursor(self.col, self.row);
+            return;
+        }
         unsafe {
             outb(VGA_CTRL_PORT, 0x0F);
             outb(VGA_DATA_PORT, (pos & 0xFF) as u8);
@@ -41,7 +51,19 @@ impl Writer {
         self.update_cursor();
     }
 
+    pub fn disable_cursor(&self) {
+        unsafe {
+            outb(VGA_CTRL_PORT, 0x0A);
+            outb(VGA_DATA_PORT, 0x20);
+        }
+    }
+
     pub fn write_byte(&mut self, byte: u8) {
+        if config::get().serial_console() {
+            mirror_to_serial(byte);
+        }
+        recorder::record(Event::Byte(byte));
+        metrics::add(Counter::ConsoleBytes, 1);
         match byte {
             b'\n' => self.newline(),
             byte => {
@@ -51,10 +73,11 @@ impl Writer {
 
                 let offset = (self.row * VGA_WIDTH + self.col) * 2;
                 unsafe {
-                    let vga = VGA_BUFFER as *mut u8;
+                    let vga = framebuffer::text_buffer();
                     *vga.add(offset) = byte;
                     *vga.add(offset + 1) = self.color;
                 }
+                framebuffer::text_cell_changed(self.col, self.row);
                 self.col += 1;
             }
         }
@@ -79,6 +102,70 @@ impl Writer {
         }
     }
 
+    /// Write `bytes` holding ANSI escape codes and CP437 characters, as
+    /// ANSI art and serial terminals send. `parser` keeps the colors and any
+    /// sequence cut off at the end for the next call.
+    pub fn write_ansi(&mut self, parser: &mut Parser, bytes: &[u8]) {
+        for &byte in bytes {
+            let action = match parser.feed(byte) {
+                Some(action) => action,
+                None => continue,
+            };
+            // A full line leaves the cursor past the last column until the
+            // next character wraps it
+            let (col, row) = (self.col.min(VGA_WIDTH - 1), self.row);
+            match action {
+                Action::Print(byte) => self.write_byte(byte),
+                Action::Newline => self.write_byte(b'\n'),
+                Action::CarriageReturn => self.set_position(0, row),
+                Action::Backspace => self.set_position(col.saturating_sub(1), row),
+                Action::Tab => self.set_position(((col / 8 + 1) * 8).min(VGA_WIDTH - 1), row),
+                Action::Up(n) => self.set_position(col, row.saturating_sub(n)),
+                Action::Down(n) => self.set_position(col, (row + n).min(VGA_HEIGHT - 1)),
+                Action::Forward(n) => self.set_position((col + n).min(VGA_WIDTH - 1), row),
+                Action::Back(n) => self.set_position(col.saturating_sub(n), row),
+                Action::MoveTo(row, col) => self.set_position(col.min(VGA_WIDTH - 1), row.min(VGA_HEIGHT - 1)),
+                Action::EraseDisplay(mode) => self.erase(mode, 0, VGA_WIDTH * VGA_HEIGHT),
+                Action::EraseLine(mode) => self.erase(mode, row * VGA_WIDTH, (row + 1) * VGA_WIDTH),
+                Action::Color(attribute) => self.set_color_code(attribute),
+                Action::SaveCursor => parser.save_cursor(col, row),
+                Action::RestoreCursor => {
+                    let (col, row) = parser.saved_cursor();
+                    self.set_position(col, row);
+                }
+            }
+        }
+    }
+
+    /// Blank cells between `start` and `end`, counted across the screen
+    /// from the top left: mode 0 from the cursor on, 1 up to and including
+    /// it, 2 all of them
+    fn erase(&self, mode: u8, start: usize, end: usize) {
+        let cursor = self.row * VGA_WIDTH + self.col.min(VGA_WIDTH - 1);
+        let (from, to) = match mode {
+            0 => (cursor, end),
+            1 => (start, cursor + 1),
+            _ => (start, end),
+        };
+        let blank = b' ' as u16 | (self.color as u16) << 8;
+        for cell in from..to {
+            Self::set_cell(cell % VGA_WIDTH, cell / VGA_WIDTH, blank);
+        }
+    }
+
+    /// Target of `write!`. Writing to the screen can't fail, so unlike
+    /// `fmt::Write::write_fmt` this returns nothing to be checked.
+    pub fn write_fmt(&mut self, args: fmt::Arguments) {
+        let _ = fmt::Write::write_fmt(self, args);
+    }
+
+    /// Write spaces up to column `col`; nothing if already past it
+    pub fn pad_to(&mut self, col: usize) {
+        while self.col < col.min(VGA_WIDTH) {
+            self.write_byte(b' ');
+        }
+    }
+
     fn newline(&mut self) {
         self.col = 0;
         if self.row < VGA_HEIGHT - 1 {
@@ -90,7 +177,7 @@ impl Writer {
 
     fn scroll(&mut self) {
         unsafe {
-            let vga = VGA_BUFFER as *mut u8;
+            let vga = framebuffer::text_buffer();
             for row in 1..VGA_HEIGHT {
                 for col in 0..VGA_WIDTH {
                     let src = (row * VGA_WIDTH + col) * 2;
@@ -105,22 +192,35 @@ impl Writer {
                 *vga.add(offset + 1) = self.color;
             }
         }
+        framebuffer::redraw_text();
     }
 
     pub fn clear(&mut self) {
+        recorder::record(Event::Clear);
         unsafe {
-            let vga = VGA_BUFFER as *mut u8;
+            let vga = framebuffer::text_buffer();
             for i in 0..(VGA_WIDTH * VGA_HEIGHT) {
                 *vga.add(i * 2) = b' ';
                 *vga.add(i * 2 + 1) = self.color;
             }
         }
+        framebuffer::redraw_text();
         self.col = 0;
         self.row = 0;
     }
 
     pub fn set_color(&mut self, fg: Color, bg: Color) {
-        self.color = color_code(fg, bg);
+        self.set_color_code(color_code(fg, bg));
+    }
+
+    /// Set the raw attribute byte, background in the high nibble
+    pub fn set_color_code(&mut self, color: u8) {
+        self.color = color;
+        recorder::record(Event::Color(color));
+    }
+
+    pub fn get_color(&self) -> u8 {
+        self.color
     }
 
     pub fn get_col(&self) -> usize {
@@ -132,10 +232,66 @@ impl Writer {
     }
 
     pub fn set_position(&mut self, col: usize, row: usize) {
+        recorder::record(Event::Position(col as u8, row as u8));
         self.col = col;
         self.row = row;
         self.update_cursor();
     }
+
+    /// Attribute byte of a screen cell, independent of any writer state
+    pub fn cell_attribute(col: usize, row: usize) -> u8 {
+        if col >= VGA_WIDTH || row >= VGA_HEIGHT {
+            return 0;
+        }
+        unsafe { *framebuffer::text_buffer().add((row * VGA_WIDTH + col) * 2 + 1) }
+    }
+
+    pub fn set_cell_attribute(col: usize, row: usize, attribute: u8) {
+        if col >= VGA_WIDTH || row >= VGA_HEIGHT {
+            return;
+        }
+        unsafe {
+            *framebuffer::text_buffer().add((row * VGA_WIDTH + col) * 2 + 1) = attribute;
+        }
+        framebuffer::text_cell_changed(col, row);
+    }
+
+    /// Character and attribute of a screen cell as stored in VGA memory
+    pub fn cell(col: usize, row: usize) -> u16 {
+        if col >= VGA_WIDTH || row >= VGA_HEIGHT {
+            return 0;
+        }
+        unsafe {
+            let cell = framebuffer::text_buffer().add((row * VGA_WIDTH + col) * 2);
+            *cell as u16 | (*cell.add(1) as u16) << 8
+        }
+    }
+
+    pub fn set_cell(col: usize, row: usize, cell: u16) {
+        if col >= VGA_WIDTH || row >= VGA_HEIGHT {
+            return;
+        }
+        unsafe {
+            let ptr = framebuffer::text_buffer().add((row * VGA_WIDTH + col) * 2);
+            *ptr = cell as u8;
+            *ptr.add(1) = (cell >> 8) as u8;
+        }
+        framebuffer::text_cell_changed(col, row);
+    }
+}
+
+fn mirror_to_serial(byte: u8) {
+    let port = SerialPort::new(COM1);
+    match byte {
+        b'\n' => port.write_str("\n"),
+        0x20..=0x7e => port.write_byte(byte),
+        _ => port.write_byte(b'?'),
+    }
+}
+
+/// Swap foreground and background nibbles, as used for the mouse cursor
+pub const fn invert_attribute(attribute: u8) -> u8 {
+    (attribute << 4) | (attribute >> 4)
 }
 
 unsafe fn outb(port: u16, value: u8) {
@@ -146,3 +302,92 @@ unsafe fn outb(port: u16, value: u8) {
         options(nostack)
     );
 }
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        Writer::write_str(self, s);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_cell(col: usize, row: usize) -> (u8, u8) {
+        let offset = (row * VGA_WIDTH + col) * 2;
+        unsafe {
+            let vga = framebuffer::text_buffer() as *const u8;
+            (*vga.add(offset), *vga.add(offset + 1))
+        }
+    }
+
+    #[test_case]
+    fn write_byte_stores_char_and_color() {
+        let mut writer = Writer::new(color_code(Color::Yellow, Color::Blue));
+        writer.clear();
+        writer.write_byte(b'H');
+        assert_eq!(read_cell(0, 0), (b'H', color_code(Color::Yellow, Color::Blue)));
+        assert_eq!(writer.get_col(), 1);
+    }
+
+    #[test_case]
+    fn non_printable_bytes_are_replaced() {
+        let mut writer = Writer::new(color_code(Color::White, Color::Black));
+        writer.clear();
+        writer.write_bytes(&[b'a', 0x01, b'b']);
+        assert_eq!(read_cell(1, 0).0, 0xfe);
+        assert_eq!(read_cell(2, 0).0, b'b');
+    }
+
+    #[test_case]
+    fn write_macro_formats_and_pads() {
+        let mut writer = Writer::new(color_code(Color::White, Color::Black));
+        writer.clear();
+        write!(writer, "{:>3}|{:02}", 7, 5);
+        writer.pad_to(10);
+        assert_eq!(read_cell(2, 0).0, b'7');
+        assert_eq!(read_cell(4, 0).0, b'0');
+        assert_eq!(writer.get_col(), 10);
+    }
+
+    #[test_case]
+    fn long_lines_wrap() {
+        let mut writer = Writer::new(color_code(Color::White, Color::Black));
+        writer.clear();
+        for _ in 0..VGA_WIDTH + 1 {
+            writer.write_byte(b'x');
+        }
+        assert_eq!(writer.get_row(), 1);
+        assert_eq!(writer.get_col(), 1);
+    }
+
+    #[test_case]
+    fn ansi_codes_move_erase_and_color() {
+        let mut writer = Writer::new(color_code(Color::White, Color::Black));
+        writer.clear();
+        let mut parser = Parser::new();
+        writer.write_ansi(&mut parser, b"abc\x1b[2;5H\x1b[1;33mX\x1b[0m\x1b[1;2H\x1b[K\r\xdb");
+        assert_eq!(read_cell(4, 1), (b'X', color_code(Color::Yellow, Color::Black)));
+        assert_eq!(read_cell(0, 0), (0xdb, color_code(Color::LightGray, Color::Black)));
+        assert_eq!(read_cell(1, 0).0, b' ');
+        assert_eq!(read_cell(2, 0).0, b' ');
+
+        // A sequence split between writes
+        writer.write_ansi(&mut parser, b"\x1b[");
+        writer.write_ansi(&mut parser, b"3Bz");
+        assert_eq!(read_cell(1, 3).0, b'z');
+    }
+
+    #[test_case]
+    fn writing_past_bottom_scrolls() {
+        let mut writer = Writer::new(color_code(Color::White, Color::Black));
+        writer.clear();
+        writer.write_str("first\n");
+        for _ in 0..VGA_HEIGHT - 1 {
+            writer.write_byte(b'\n');
+        }
+        assert_eq!(writer.get_row(), VGA_HEIGHT - 1);
+        assert_ne!(read_cell(0, 0).0, b'f');
+    }
+}
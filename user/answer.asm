@@ -0,0 +1,16 @@
+# Smallest useful user program: computes a value in ring 3 and exits
+# with it, so `run answer` should report exit code 42.
+
+.section .text
+.global _start
+_start:
+	mov $6, %eax
+	mov $7, %ecx
+	mul %ecx
+
+	mov %eax, %ebx
+	mov $1, %eax		# SYS_EXIT
+	int $0x80
+
+	# exit does not return
+1:	jmp 1b
@@ -0,0 +1,47 @@
+# Console I/O from ring 3 through the int 0x80 system calls: asks for a
+# name, greets it and exits with code 0.
+
+.set SYS_EXIT,  1
+.set SYS_READ,  3
+.set SYS_WRITE, 4
+
+.section .text
+.global _start
+_start:
+	mov $SYS_WRITE, %eax
+	mov $1, %ebx
+	mov $question, %ecx
+	mov $question_len, %edx
+	int $0x80
+
+	mov $SYS_READ, %eax
+	mov $0, %ebx
+	mov $name, %ecx
+	mov $32, %edx
+	int $0x80
+	mov %eax, %esi		# bytes read, newline included
+
+	mov $SYS_WRITE, %eax
+	mov $1, %ebx
+	mov $greeting, %ecx
+	mov $greeting_len, %edx
+	int $0x80
+
+	mov $SYS_WRITE, %eax
+	mov $1, %ebx
+	mov $name, %ecx
+	mov %esi, %edx
+	int $0x80
+
+	mov $SYS_EXIT, %eax
+	mov $0, %ebx
+	int $0x80
+
+.section .data
+question:	.ascii "What's your name? "
+.set question_len, . - question
+greeting:	.ascii "Hello from ring 3, "
+.set greeting_len, . - greeting
+
+.section .bss
+name:	.skip 32
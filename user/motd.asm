@@ -0,0 +1,52 @@
+# File I/O from ring 3: opens /motd, copies it to the console in small
+# reads and closes it. Exits with the negative error code if the open fails.
+
+.set SYS_EXIT,  1
+.set SYS_READ,  3
+.set SYS_WRITE, 4
+.set SYS_OPEN,  5
+.set SYS_CLOSE, 6
+
+.section .text
+.global _start
+_start:
+	mov $SYS_OPEN, %eax
+	mov $path, %ebx
+	mov $0, %ecx		# O_RDONLY
+	int $0x80
+	test %eax, %eax
+	js failed
+	mov %eax, %esi		# handle
+
+copy:
+	mov $SYS_READ, %eax
+	mov %esi, %ebx
+	mov $buffer, %ecx
+	mov $64, %edx
+	int $0x80
+	test %eax, %eax
+	jle done		# 0 at the end of the file
+
+	mov %eax, %edx
+	mov $SYS_WRITE, %eax
+	mov $1, %ebx
+	mov $buffer, %ecx
+	int $0x80
+	jmp copy
+
+done:
+	mov $SYS_CLOSE, %eax
+	mov %esi, %ebx
+	int $0x80
+
+	mov $0, %eax
+failed:
+	mov %eax, %ebx
+	mov $SYS_EXIT, %eax
+	int $0x80
+
+.section .data
+path:	.asciz "/motd"
+
+.section .bss
+buffer:	.skip 64